@@ -48,5 +48,5 @@ fn main() {
     let ref mut fout = File::create(&Path::new("fractal.png")).unwrap();
 
     // We must indicate the image’s color type and what format to save as
-    let _    = image::ImageLuma8(imgbuf).save(fout, image::PNG);
+    let _    = image::ImageLuma8(imgbuf.into()).save(fout, image::PNG);
 }
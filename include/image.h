@@ -0,0 +1,77 @@
+/*
+ * C interface of the Rust image library.
+ *
+ * Available when the crate is built with the `capi` feature, for example
+ * as a shared library with
+ *
+ *     cargo rustc --release --features capi --crate-type cdylib
+ *
+ * All functions returning `int` return IMAGE_OK on success and one of the
+ * IMAGE_ERROR_* status codes otherwise.
+ */
+
+#ifndef RUST_IMAGE_H
+#define RUST_IMAGE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Status codes */
+#define IMAGE_OK                      0
+#define IMAGE_ERROR_FORMAT            1
+#define IMAGE_ERROR_DIMENSION         2
+#define IMAGE_ERROR_UNSUPPORTED       3
+#define IMAGE_ERROR_UNSUPPORTED_COLOR 4
+#define IMAGE_ERROR_NOT_ENOUGH_DATA   5
+#define IMAGE_ERROR_IO                6
+#define IMAGE_ERROR_IMAGE_END         7
+#define IMAGE_ERROR_INVALID_ARGUMENT  8
+#define IMAGE_ERROR_PANIC             9
+
+/* Image formats accepted by image_encode */
+#define IMAGE_FORMAT_PNG  0
+#define IMAGE_FORMAT_JPEG 1
+#define IMAGE_FORMAT_GIF  2
+#define IMAGE_FORMAT_WEBP 3
+#define IMAGE_FORMAT_PPM  4
+#define IMAGE_FORMAT_TIFF 5
+#define IMAGE_FORMAT_TGA  6
+#define IMAGE_FORMAT_BMP  7
+
+/*
+ * A buffer owned by the library, released with image_buffer_free.
+ *
+ * For decoded images `data` holds `height` rows of `width * channels` bytes.
+ * For encoded images `data` holds the encoded file.
+ * `channels` is 1 (gray), 2 (gray and alpha), 3 (RGB) or 4 (RGBA).
+ */
+typedef struct image_buffer {
+    uint8_t *data;
+    size_t len;
+    uint32_t width;
+    uint32_t height;
+    uint32_t channels;
+} image_buffer;
+
+/* Decodes the image file in data[0..len], detecting its format. */
+int image_decode(const uint8_t *data, size_t len, image_buffer *out);
+
+/* Encodes height rows of width * channels bytes in the given IMAGE_FORMAT_*. */
+int image_encode(const uint8_t *pixels, uint32_t width, uint32_t height,
+                 uint32_t channels, uint32_t format, image_buffer *out);
+
+/* Releases a buffer returned by the library and resets it. */
+void image_buffer_free(image_buffer *buffer);
+
+/* Returns a static description of a status code. */
+const char *image_status_message(int status);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUST_IMAGE_H */
@@ -1,6 +1,12 @@
+use std::borrow::Borrow;
+use std::cmp;
+use std::time::{Duration, Instant};
+
 use num::rational::Ratio;
 
-use buffer::RgbaImage;
+use buffer::{ImageBuffer, RgbaImage};
+use image::ImageResult;
+use imageops;
 
 /// Hold the frames of the animated image
 pub struct Frames {
@@ -16,6 +22,121 @@ impl Frames {
             current_frame: 0
         }
     }
+
+    /// Returns up to ```n``` evenly spaced frames of the animation side by
+    /// side, starting with the first frame, as used for hover previews.
+    ///
+    /// Frames are composited onto a canvas that covers all of them, later
+    /// frames over earlier ones. Compositing stops once ```max_ms```
+    /// milliseconds have passed, the strip then holds the frames completed
+    /// so far, but always at least the first one.
+    ///
+    /// The frames are already decoded, use ```decode_thumbnail_strip``` to
+    /// count the decoding against the time budget.
+    pub fn thumbnail_strip(&self, n: usize, max_ms: u32) -> RgbaImage {
+        let start = Instant::now();
+        let (width, height) = self.frames.iter().fold((0, 0), |(w, h), f| {
+            let (fw, fh) = f.buffer.dimensions();
+            (cmp::max(w, f.left + fw), cmp::max(h, f.top + fh))
+        });
+
+        let frames = self.frames.iter().map(|frame| Ok(frame));
+        strip(frames, n, Some(self.frames.len()), ImageBuffer::new(width, height), start, max_ms).unwrap()
+    }
+
+    /// Like ```thumbnail_strip```, but takes the frames as they are decoded,
+    /// so that ```max_ms``` also bounds the time spent decoding.
+    ///
+    /// As the number of frames is not known in advance, the strip holds
+    /// frames spaced roughly evenly over those decoded within the budget.
+    /// Decoding errors are returned.
+    pub fn decode_thumbnail_strip<I>(frames: I, n: usize, max_ms: u32) -> ImageResult<RgbaImage>
+        where I: IntoIterator<Item = ImageResult<Frame>> {
+        let start = Instant::now();
+        strip(frames.into_iter(), n, None, ImageBuffer::new(0, 0), start, max_ms)
+    }
+}
+
+// Composites the frames onto `canvas`, which grows to cover each of them,
+// and keeps snapshots of the canvas for the strip. If the number of frames
+// is `known`, the snapshots are those of the evenly spaced frames. Otherwise
+// a snapshot is kept every `stride` frames, and once there are twice as many
+// as needed every other one is dropped and the stride doubled, which bounds
+// the memory.
+fn strip<F, I>(frames: I,
+               n: usize,
+               known: Option<usize>,
+               mut canvas: RgbaImage,
+               start: Instant,
+               max_ms: u32) -> ImageResult<RgbaImage>
+    where F: Borrow<Frame>, I: Iterator<Item = ImageResult<F>> {
+    let budget = Duration::from_millis(max_ms as u64);
+
+    if n == 0 {
+        return Ok(ImageBuffer::new(0, 0))
+    }
+
+    let mut snapshots = Vec::new();
+    let mut stride = 1;
+    let mut total = 0;
+
+    for frame in frames {
+        let frame = try!(frame);
+        let frame = frame.borrow();
+        let (fw, fh) = frame.buffer.dimensions();
+
+        // Grow the canvas to cover the frame
+        let (width, height) = (cmp::max(canvas.width(), frame.left + fw),
+                               cmp::max(canvas.height(), frame.top + fh));
+        if (width, height) != canvas.dimensions() {
+            let mut grown = ImageBuffer::new(width, height);
+            imageops::replace(&mut grown, &canvas, 0, 0);
+            canvas = grown;
+        }
+
+        imageops::overlay(&mut canvas, &frame.buffer, frame.left, frame.top);
+
+        if let Some(len) = known {
+            if total == snapshots.len() * len / cmp::min(n, len) {
+                snapshots.push(canvas.clone());
+
+                if snapshots.len() == n {
+                    break
+                }
+            }
+        } else if total % stride == 0 {
+            snapshots.push(canvas.clone());
+
+            if snapshots.len() == 2 * n {
+                snapshots = snapshots.into_iter().step_by(2).collect();
+                stride *= 2;
+            }
+        }
+        total += 1;
+
+        if start.elapsed() >= budget {
+            break
+        }
+    }
+
+    if snapshots.is_empty() {
+        return Ok(ImageBuffer::new(0, 0))
+    }
+
+    // The frames `i * total / count`, from the nearest earlier snapshot
+    if known.is_none() {
+        let count = cmp::min(n, total);
+        snapshots = (0..count).map(|i| snapshots[i * total / count / stride].clone()).collect();
+    }
+
+    let (width, height) = canvas.dimensions();
+    let mut strip = ImageBuffer::new(width * snapshots.len() as u32, height);
+
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        imageops::replace(&mut strip, snapshot, i as u32 * width, 0);
+    }
+
+    Ok(strip)
 }
 
 /// A single animation frame
@@ -85,3 +206,57 @@ impl<'a> Iterator for Frames {
         self.frames.get(frame).map(|v| v.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use num::rational::Ratio;
+
+    use buffer::{ImageBuffer, RgbaImage};
+    use color::Rgba;
+    use image::ImageError;
+    use super::{Frame, Frames};
+
+    fn frames(count: u8) -> Frames {
+        Frames::new((0..count).map(|i| {
+            let buffer: RgbaImage = ImageBuffer::from_pixel(2, 2, Rgba([i, 0, 0, 255]));
+            Frame::from_parts(buffer, i as u32 % 2, 0, Ratio::from_integer(1))
+        }).collect())
+    }
+
+    #[test]
+    fn test_thumbnail_strip() {
+        let strip = frames(8).thumbnail_strip(4, 10000);
+        assert_eq!(strip.dimensions(), (4 * 3, 2));
+
+        // Frames 0, 2, 4 and 6, each over the previous frames
+        for (i, &value) in [0u8, 2, 4, 6].iter().enumerate() {
+            let x = 3 * i as u32;
+            assert_eq!(strip.get_pixel(x, 0)[0], value);
+            assert_eq!(strip.get_pixel(x + 2, 1)[0], if value == 0 { 0 } else { value - 1 });
+        }
+        assert_eq!(strip.get_pixel(2, 0)[3], 0);
+
+        assert_eq!(frames(3).thumbnail_strip(5, 10000).width(), 3 * 3);
+        assert_eq!(frames(8).thumbnail_strip(4, 0).width(), 3);
+        assert_eq!(frames(0).thumbnail_strip(4, 10000).dimensions(), (0, 0));
+    }
+
+    #[test]
+    fn test_decode_thumbnail_strip() {
+        // Frames 0, 4, 8 and 12, the nearest snapshots of every 4th frame
+        // to frames 0, 5, 10 and 15
+        let strip = Frames::decode_thumbnail_strip(frames(20).map(Ok), 4, 10000).unwrap();
+        assert_eq!(strip.dimensions(), (4 * 3, 2));
+        for (i, &value) in [0u8, 4, 8, 12].iter().enumerate() {
+            assert_eq!(strip.get_pixel(3 * i as u32, 0)[0], value);
+        }
+
+        let decoded = frames(8).map(Ok).collect::<Vec<_>>();
+        assert!(Frames::decode_thumbnail_strip(decoded, 4, 10000).unwrap().into_raw() ==
+                frames(8).thumbnail_strip(4, 10000).into_raw());
+
+        // Decoding stops at the first error
+        let failing = frames(2).map(Ok).chain(Some(Err(ImageError::NotEnoughData)));
+        assert!(Frames::decode_thumbnail_strip(failing, 4, 10000).is_err());
+    }
+}
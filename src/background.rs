@@ -0,0 +1,358 @@
+//! Decoding on a background thread
+//!
+//! `spawn_decode` starts decoding an image on a thread of its own and
+//! returns at once with a `DecodeHandle`, such that GUI applications can
+//! keep their event loop running, show the progress and drop images that
+//! are no longer needed before they are decoded.
+
+#[cfg(feature = "jpeg")]
+use std::io;
+use std::io::{Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use dynimage::{self, DynamicImage};
+#[cfg(feature = "jpeg")]
+use image::{DecodingResult, ImageDecoder};
+use image::{ImageError, ImageFormat, ImageResult};
+
+// The number of rows decoded between checks for cancellation
+const STEP_ROWS: usize = 16;
+
+/// The options of `spawn_decode`
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    /// The format of the image, guessed from its first bytes if `None`
+    pub format: Option<ImageFormat>,
+
+    /// The name of the decoding thread, as shown by debuggers and in panic
+    /// messages
+    pub thread_name: Option<String>,
+
+    /// The stack size of the decoding thread in bytes, the default of the
+    /// standard library if `None`
+    pub stack_size: Option<usize>,
+}
+
+/// A decode running on a background thread, see `spawn_decode`.
+///
+/// Dropping the handle cancels the decode without waiting for the thread.
+pub struct DecodeHandle {
+    shared: Arc<Shared>,
+    taken: bool,
+}
+
+// The state the handle shares with the decoding thread
+struct Shared {
+    cancelled: AtomicBool,
+    // The rows decoded so far and the height of the image, 0 while unknown
+    rows: AtomicUsize,
+    height: AtomicUsize,
+    result: Mutex<Option<ImageResult<DynamicImage>>>,
+    finished: Condvar,
+}
+
+/// Reads an image from `reader` and decodes it on a new thread.
+///
+/// JPEG images are decoded in steps of a few rows, which report their
+/// progress and check for cancellation. They are decoded with the default
+/// `Limits` of the JPEG decoder, which also bound the size of the decoded
+/// image by `max_alloc`. Other formats are decoded at once
+/// when the data has been read, thus their progress jumps from 0 to 1 and
+/// cancelling only takes effect before decoding starts.
+///
+/// A panic of the reader or the decoder ends the decode with a
+/// `FormatError` instead of leaving `DecodeHandle::wait` blocked.
+///
+/// Returns an `IoError` if the thread can not be created.
+pub fn spawn_decode<R>(reader: R, options: DecodeOptions) -> ImageResult<DecodeHandle>
+    where R: Read + Send + 'static {
+
+    let shared = Arc::new(Shared {
+        cancelled: AtomicBool::new(false),
+        rows: AtomicUsize::new(0),
+        height: AtomicUsize::new(0),
+        result: Mutex::new(None),
+        finished: Condvar::new(),
+    });
+
+    let mut builder = thread::Builder::new();
+    if let Some(name) = options.thread_name {
+        builder = builder.name(name);
+    }
+    if let Some(size) = options.stack_size {
+        builder = builder.stack_size(size);
+    }
+
+    let thread_shared = shared.clone();
+    let format = options.format;
+
+    try!(builder.spawn(move || {
+        let shared = thread_shared;
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| decode(reader, format, &shared))) {
+            Ok(result) => result,
+            Err(_) => Err(ImageError::FormatError("The decoding thread panicked".to_string())),
+        };
+
+        *shared.result.lock().unwrap() = Some(result);
+        shared.finished.notify_all();
+    }));
+
+    Ok(DecodeHandle { shared: shared, taken: false })
+}
+
+impl DecodeHandle {
+    /// Asks the decoding thread to stop. Unless decoding has finished, the
+    /// result is a `Cancelled` error.
+    pub fn cancel(&self) {
+        self.shared.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// The fraction of the image decoded so far, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        if self.is_finished() {
+            return 1.0
+        }
+
+        match self.shared.height.load(Ordering::SeqCst) {
+            0 => 0.0,
+            height => self.shared.rows.load(Ordering::SeqCst) as f32 / height as f32
+        }
+    }
+
+    /// Whether the result is available, or has been taken
+    pub fn is_finished(&self) -> bool {
+        self.taken || self.shared.result.lock().unwrap().is_some()
+    }
+
+    /// Returns the result if decoding has finished, without blocking.
+    /// The result is returned once, later calls return `None`.
+    pub fn poll(&mut self) -> Option<ImageResult<DynamicImage>> {
+        let result = self.shared.result.lock().unwrap().take();
+        self.taken |= result.is_some();
+        result
+    }
+
+    /// Waits up to `timeout` for decoding to finish and returns the result
+    /// like `poll`
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<ImageResult<DynamicImage>> {
+        let result = {
+            let guard = self.shared.result.lock().unwrap();
+            let mut guard = if guard.is_none() && !self.taken {
+                self.shared.finished.wait_timeout(guard, timeout).unwrap().0
+            } else {
+                guard
+            };
+            guard.take()
+        };
+
+        self.taken |= result.is_some();
+        result
+    }
+
+    /// Waits for decoding to finish and returns the result.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result has already been taken by `poll` or
+    /// `wait_timeout`.
+    pub fn wait(mut self) -> ImageResult<DynamicImage> {
+        assert!(!self.taken, "the result has already been taken");
+
+        let mut guard = self.shared.result.lock().unwrap();
+        loop {
+            if let Some(result) = guard.take() {
+                self.taken = true;
+                return result
+            }
+
+            guard = self.shared.finished.wait(guard).unwrap();
+        }
+    }
+}
+
+impl Drop for DecodeHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+fn decode<R: Read>(mut reader: R, format: Option<ImageFormat>, shared: &Shared) -> ImageResult<DynamicImage> {
+    let mut data = Vec::new();
+    try!(reader.read_to_end(&mut data));
+
+    if shared.cancelled.load(Ordering::SeqCst) {
+        return Err(ImageError::Cancelled)
+    }
+
+    let format = match format {
+        Some(format) => format,
+        None => try!(dynimage::guess_format(&data))
+    };
+
+    match format {
+        #[cfg(feature = "jpeg")]
+        ImageFormat::JPEG => {
+            let decoder = ::jpeg::JPEGDecoder::new(Cursor::new(&data[..]));
+            decode_rows(decoder, ::jpeg::Limits::default().max_alloc, shared)
+        }
+        _ => dynimage::load(Cursor::new(&data[..]), format)
+    }
+}
+
+// Decodes `STEP_ROWS` rows at a time, updating the progress. The decoded
+// image may take at most `max_size` bytes, which is checked before it is
+// allocated.
+#[cfg(feature = "jpeg")]
+fn decode_rows<D: ImageDecoder>(mut decoder: D, max_size: u64, shared: &Shared) -> ImageResult<DynamicImage> {
+    let (width, height) = try!(decoder.dimensions());
+    let color = try!(decoder.colortype());
+    let row = try!(decoder.row_len());
+
+    let size = row as u64 * height as u64;
+    if size > max_size {
+        return Err(ImageError::LimitsExceeded(format!(
+            "The decoded image takes {} bytes, more than the maximum of {}", size, max_size
+        )))
+    }
+
+    shared.height.store(height as usize, Ordering::SeqCst);
+
+    let mut buf = vec![0u8; size as usize];
+    let mut rows = 0;
+
+    while rows < height as usize {
+        if shared.cancelled.load(Ordering::SeqCst) {
+            return Err(ImageError::Cancelled)
+        }
+
+        let n = try!(decoder.read_scanlines(STEP_ROWS, &mut buf[rows * row..]));
+        if n == 0 {
+            return Err(ImageError::IoError(io::Error::new(io::ErrorKind::UnexpectedEof, "image data ended")))
+        }
+
+        rows += n;
+        shared.rows.store(rows, Ordering::SeqCst);
+    }
+
+    dynimage::decoded_to_image(color, width, height, DecodingResult::U8(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::Duration;
+
+    use image::{GenericImage, ImageError};
+    use super::{spawn_decode, DecodeOptions};
+
+    // Reads `data` once the test sends a message
+    struct Gated {
+        data: io::Cursor<Vec<u8>>,
+        gate: Option<Receiver<()>>,
+    }
+
+    impl Read for Gated {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if let Some(gate) = self.gate.take() {
+                let _ = gate.recv();
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[cfg(feature = "jpeg")]
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        use color::ColorType;
+        use jpeg::JPEGEncoder;
+
+        let image = (0..width * height).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        let mut encoded = Vec::new();
+        JPEGEncoder::new(&mut encoded).encode(&image, width, height, ColorType::Gray(8)).unwrap();
+        encoded
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_spawn_decode() {
+        let (send, receive) = channel();
+        let reader = Gated { data: io::Cursor::new(jpeg(40, 70)), gate: Some(receive) };
+        let options = DecodeOptions { thread_name: Some("decoder".to_string()), ..DecodeOptions::default() };
+
+        let mut handle = spawn_decode(reader, options).unwrap();
+        assert!(handle.poll().is_none());
+        assert!(handle.wait_timeout(Duration::from_millis(10)).is_none());
+        assert_eq!(handle.progress(), 0.0);
+
+        send.send(()).unwrap();
+        let image = handle.wait().unwrap();
+        assert_eq!(image.dimensions(), (40, 70));
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_cancel() {
+        use color::ColorType;
+        use jpeg::JPEGEncoder;
+
+        let (send, receive) = channel();
+        let reader = Gated { data: io::Cursor::new(jpeg(16, 16)), gate: Some(receive) };
+
+        let mut handle = spawn_decode(reader, DecodeOptions::default()).unwrap();
+        handle.cancel();
+        send.send(()).unwrap();
+
+        match handle.wait_timeout(Duration::from_secs(10)) {
+            Some(Err(ImageError::Cancelled)) => (),
+            _ => panic!("decoding was not cancelled")
+        }
+        assert!(handle.is_finished() && handle.poll().is_none());
+        assert_eq!(handle.progress(), 1.0);
+
+        // A header that claims more than the limits is rejected before the
+        // image is allocated
+        let mut data = Vec::new();
+        JPEGEncoder::new(&mut data).encode(&[0; 16 * 16 * 3], 16, 16, ColorType::RGB(8)).unwrap();
+        let sof = data.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        for byte in data[sof + 5..sof + 9].iter_mut() {
+            *byte = 0xEA;
+        }
+        match spawn_decode(io::Cursor::new(data), DecodeOptions::default()).unwrap().wait() {
+            Err(ImageError::LimitsExceeded(..)) => (),
+            _ => panic!("the image exceeds the limits")
+        }
+
+        // Unknown formats fail on the thread
+        let handle = spawn_decode(io::Cursor::new(b"not an image".to_vec()), DecodeOptions::default()).unwrap();
+        match handle.wait() {
+            Err(ImageError::UnsupportedError(..)) => (),
+            _ => panic!("the format is not known")
+        }
+    }
+
+    // Panics on the first read
+    struct Panicking;
+
+    impl Read for Panicking {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            panic!("the reader failed")
+        }
+    }
+
+    #[test]
+    fn test_panic() {
+        // A panic on the thread ends up in the handle
+        let mut handle = spawn_decode(Panicking, DecodeOptions::default()).unwrap();
+        match handle.wait_timeout(Duration::from_secs(10)) {
+            Some(Err(ImageError::FormatError(..))) => (),
+            _ => panic!("the panic was not reported")
+        }
+
+        let handle = spawn_decode(Panicking, DecodeOptions::default()).unwrap();
+        assert!(handle.wait().is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Command line tool for converting and inspecting images
+//!
+//! Built with the `cli` feature. Only the public API of the crate is used.
+extern crate image;
+
+use std::cmp;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+use image::{DynamicImage, FilterType, GenericImage, ImageError, ImageFormat, ImageResult};
+use image::delta;
+
+const USAGE: &'static str = "\
+Usage:
+    img convert <input> <output>
+    img resize <input> <output> <width>x<height> [--exact] [--filter <filter>]
+    img info <input>...
+    img strip-metadata <input> <output>
+    img diff <a> <b> [--delta <output>]
+
+The formats are derived from the file extensions. Filters are nearest,
+triangle, catmullrom, gaussian, lanczos3 (the default) and detail, which
+keeps fine texture when shrinking to thumbnails.
+`strip-metadata` copies JPEG and PNG files without their EXIF data,
+comments and text, keeping the image data and color profile as they are.
+`diff` exits with status 1 if the images differ.";
+
+// An error of the command line tool
+enum Error {
+    Usage(String),
+    Image(ImageError),
+}
+
+impl From<ImageError> for Error {
+    fn from(err: ImageError) -> Error {
+        Error::Image(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Image(ImageError::IoError(err))
+    }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+
+    let result = match args.first().map(|s| &s[..]) {
+        Some("convert") => convert(&args[1..]),
+        Some("resize") => resize(&args[1..]),
+        Some("info") => info(&args[1..]),
+        Some("strip-metadata") => strip_metadata(&args[1..]),
+        Some("diff") => diff(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        Some(command) => Err(Error::Usage(format!("unknown command `{}`", command))),
+        None => Err(Error::Usage("missing command".to_string())),
+    };
+
+    let status = match result {
+        Ok(status) => status,
+        Err(Error::Usage(msg)) => {
+            let _ = writeln!(io::stderr(), "img: {}\n\n{}", msg, USAGE);
+            2
+        }
+        Err(Error::Image(err)) => {
+            let _ = writeln!(io::stderr(), "img: {}", err);
+            2
+        }
+    };
+
+    process::exit(status);
+}
+
+// Splits `args` into positional arguments and the values of `options`.
+// Flags in `flags` take no value and are returned as "true".
+fn parse<'a>(args: &'a [String], options: &[&str], flags: &[&str])
+             -> Result<(Vec<&'a str>, Vec<(&'a str, &'a str)>), Error> {
+    let mut positional = Vec::new();
+    let mut values = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if flags.contains(&&arg[..]) {
+            values.push((&arg[..], "true"));
+        } else if options.contains(&&arg[..]) {
+            match iter.next() {
+                Some(value) => values.push((&arg[..], &value[..])),
+                None => return Err(Error::Usage(format!("missing value for `{}`", arg)))
+            }
+        } else if arg.starts_with("--") {
+            return Err(Error::Usage(format!("unknown option `{}`", arg)))
+        } else {
+            positional.push(&arg[..]);
+        }
+    }
+
+    Ok((positional, values))
+}
+
+fn expect_args(positional: &[&str], n: usize) -> Result<(), Error> {
+    if positional.len() != n {
+        return Err(Error::Usage(format!("expected {} arguments, found {}", n, positional.len())))
+    }
+
+    Ok(())
+}
+
+fn output_format(path: &str) -> ImageResult<ImageFormat> {
+    let ext = Path::new(path).extension().and_then(|s| s.to_str())
+                             .map_or("".to_string(), |s| s.to_lowercase());
+
+    match &ext[..] {
+        "jpg" | "jpeg" => Ok(ImageFormat::JPEG),
+        "png" => Ok(ImageFormat::PNG),
+        "gif" => Ok(ImageFormat::GIF),
+        "ppm" => Ok(ImageFormat::PPM),
+        "bmp" => Ok(ImageFormat::BMP),
+        "tga" => Ok(ImageFormat::TGA),
+        "tif" | "tiff" => Ok(ImageFormat::TIFF),
+        "webp" => Ok(ImageFormat::WEBP),
+        ext => Err(ImageError::UnsupportedError(format!("Unknown file extension {:?}", ext)))
+    }
+}
+
+fn save(image: &DynamicImage, path: &str) -> ImageResult<()> {
+    let format = try!(output_format(path));
+    let mut file = try!(File::create(path));
+    image.save(&mut file, format)
+}
+
+fn convert(args: &[String]) -> Result<i32, Error> {
+    let (positional, _) = try!(parse(args, &[], &[]));
+    try!(expect_args(&positional, 2));
+
+    let image = try!(image::open(positional[0]));
+    try!(save(&image, positional[1]));
+    Ok(0)
+}
+
+fn resize(args: &[String]) -> Result<i32, Error> {
+    let (positional, options) = try!(parse(args, &["--filter"], &["--exact"]));
+    try!(expect_args(&positional, 3));
+
+    let size = positional[2].split('x').map(|s| s.parse::<u32>()).collect::<Vec<_>>();
+    let (width, height) = match &size[..] {
+        [Ok(w), Ok(h)] => (*w, *h),
+        _ => return Err(Error::Usage(format!("invalid size `{}`", positional[2])))
+    };
+
+    let mut filter = FilterType::Lanczos3;
+    let mut exact = false;
+
+    for &(option, value) in options.iter() {
+        match option {
+            "--exact" => exact = true,
+            _ => filter = match value {
+                "nearest" => FilterType::Nearest,
+                "triangle" => FilterType::Triangle,
+                "catmullrom" => FilterType::CatmullRom,
+                "gaussian" => FilterType::Gaussian,
+                "lanczos3" => FilterType::Lanczos3,
+                "detail" => FilterType::DetailPreserving,
+                _ => return Err(Error::Usage(format!("unknown filter `{}`", value)))
+            }
+        }
+    }
+
+    let image = try!(image::open(positional[0]));
+    let resized = if exact {
+        image.resize_exact(width, height, filter)
+    } else {
+        image.resize(width, height, filter)
+    };
+
+    try!(save(&resized, positional[1]));
+    Ok(0)
+}
+
+fn info(args: &[String]) -> Result<i32, Error> {
+    let (positional, _) = try!(parse(args, &[], &[]));
+    if positional.is_empty() {
+        return Err(Error::Usage("expected at least one input".to_string()))
+    }
+
+    for path in positional {
+        let image = try!(image::open(path));
+        let (width, height) = image.dimensions();
+        println!("{}: {}x{} {:?}", path, width, height, image.color());
+    }
+
+    Ok(0)
+}
+
+fn strip_metadata(args: &[String]) -> Result<i32, Error> {
+    let (positional, _) = try!(parse(args, &[], &[]));
+    try!(expect_args(&positional, 2));
+
+    let mut data = Vec::new();
+    try!(try!(File::open(positional[0])).read_to_end(&mut data));
+
+    let stripped = if data.starts_with(&[0xFF, 0xD8]) {
+        try!(strip_jpeg(&data))
+    } else if data.starts_with(PNG_SIGNATURE) {
+        try!(strip_png(&data))
+    } else {
+        return Err(Error::Image(ImageError::UnsupportedError(
+            "only JPEG and PNG files are supported".to_string()
+        )))
+    };
+
+    try!(try!(File::create(positional[1])).write_all(&stripped));
+    Ok(0)
+}
+
+const PNG_SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+
+fn truncated() -> ImageError {
+    ImageError::FormatError("the file is truncated".to_string())
+}
+
+// Copies the segments of the JPEG file `data` except comments and the APPn
+// segments other than the JFIF header, ICC profile and Adobe color
+// transform. The entropy-coded data is copied unchanged, and anything after
+// the EOI marker is dropped.
+fn strip_jpeg(data: &[u8]) -> ImageResult<Vec<u8>> {
+    let mut out = data[..2].to_vec();
+    let mut pos = 2;
+
+    loop {
+        if pos + 1 >= data.len() {
+            return Err(truncated())
+        }
+        if data[pos] != 0xFF {
+            return Err(ImageError::FormatError(format!("expected a marker at offset {}", pos)))
+        }
+
+        let marker = data[pos + 1];
+        match marker {
+            // A fill byte
+            0xFF => {
+                pos += 1;
+                continue
+            }
+            // End of image
+            0xD9 => {
+                out.extend(data[pos..pos + 2].iter().cloned());
+                return Ok(out)
+            }
+            // Restart markers and TEM have no length
+            0xD0 ... 0xD7 | 0x01 => {
+                out.extend(data[pos..pos + 2].iter().cloned());
+                pos += 2;
+                continue
+            }
+            _ => ()
+        }
+
+        if pos + 4 > data.len() {
+            return Err(truncated())
+        }
+        // The length counts its own two bytes
+        let len = (data[pos + 2] as usize) << 8 | data[pos + 3] as usize;
+        if len < 2 {
+            return Err(ImageError::FormatError(format!("invalid segment length {} at offset {}", len, pos)))
+        }
+        let end = pos + 2 + len;
+        if end > data.len() {
+            return Err(truncated())
+        }
+
+        let content = &data[pos + 4..end];
+        let keep = match marker {
+            0xE0 => content.starts_with(b"JFIF\0"),
+            0xE2 => content.starts_with(b"ICC_PROFILE\0"),
+            0xEE => content.starts_with(b"Adobe"),
+            0xE1 ... 0xEF | 0xFE => false,
+            _ => true
+        };
+        if keep {
+            out.extend(data[pos..end].iter().cloned());
+        }
+        pos = end;
+
+        // The entropy-coded data of a scan ends at the first marker that is
+        // neither a stuffed zero byte nor a restart marker
+        if marker == 0xDA {
+            let start = pos;
+            while pos + 1 < data.len() && !(data[pos] == 0xFF && match data[pos + 1] {
+                0x00 | 0xD0 ... 0xD7 => false,
+                _ => true
+            }) {
+                pos += 1;
+            }
+            out.extend(data[start..pos].iter().cloned());
+        }
+    }
+}
+
+// Copies the chunks of the PNG file `data` except text, EXIF data and the
+// modification time
+fn strip_png(data: &[u8]) -> ImageResult<Vec<u8>> {
+    let mut out = PNG_SIGNATURE.to_vec();
+    let mut pos = PNG_SIGNATURE.len();
+
+    loop {
+        if pos + 12 > data.len() {
+            return Err(truncated())
+        }
+
+        let len = (data[pos] as usize) << 24 | (data[pos + 1] as usize) << 16
+                | (data[pos + 2] as usize) << 8 | data[pos + 3] as usize;
+        let name = &data[pos + 4..pos + 8];
+        let end = pos + 12 + len;
+        if end > data.len() {
+            return Err(truncated())
+        }
+
+        if ![&b"tEXt"[..], b"zTXt", b"iTXt", b"eXIf", b"tIME"].contains(&name) {
+            out.extend(data[pos..end].iter().cloned());
+        }
+        if name == b"IEND" {
+            return Ok(out)
+        }
+        pos = end;
+    }
+}
+
+fn diff(args: &[String]) -> Result<i32, Error> {
+    let (positional, options) = try!(parse(args, &["--delta"], &[]));
+    try!(expect_args(&positional, 2));
+
+    let a = try!(image::open(positional[0])).to_rgba();
+    let b = try!(image::open(positional[1])).to_rgba();
+
+    if a.dimensions() != b.dimensions() {
+        println!("dimensions differ: {:?} and {:?}", a.dimensions(), b.dimensions());
+        return Ok(1)
+    }
+
+    let mut pixels = 0;
+    let mut max = 0;
+
+    for (p, q) in a.pixels().zip(b.pixels()) {
+        let d = p.data.iter().zip(q.data.iter())
+                     .map(|(&x, &y)| if x > y { x - y } else { y - x })
+                     .max().unwrap_or(0);
+
+        if d > 0 {
+            pixels += 1;
+            max = cmp::max(max, d);
+        }
+    }
+
+    for &(_, path) in options.iter() {
+        let delta = try!(delta::diff(&a, &b));
+        try!(delta.write_to(&mut try!(File::create(path))));
+    }
+
+    if pixels == 0 {
+        println!("identical");
+        Ok(0)
+    } else {
+        println!("{} of {} pixels differ, maximum difference {}",
+                 pixels, a.width() as u64 * a.height() as u64, max);
+        Ok(1)
+    }
+}
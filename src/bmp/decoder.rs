@@ -5,9 +5,14 @@ use image::{
     DecodingResult,
     ImageResult,
     ImageDecoder,
-    ImageError
+    ImageError,
+    RowOrder
 };
 use color::ColorType;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+#[cfg(feature = "mmap")]
+use mmap::{self, MappedFile};
 
 const BITMAPCOREHEADER_SIZE: u32 = 12;
 const BITMAPINFOHEADER_SIZE: u32 = 40;
@@ -65,6 +70,8 @@ pub struct BMPDecoder<R> {
     height: i32,
     data_offset: u64,
     top_down: bool,
+    // Whether the rows are stored from the bottom up in the output
+    bottom_up_output: bool,
     has_loaded_metadata: bool,
     image_type: ImageType,
 
@@ -86,6 +93,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
             height: 0,
             data_offset: 0,
             top_down: false,
+            bottom_up_output: false,
             has_loaded_metadata: false,
             image_type: ImageType::RGB,
 
@@ -276,6 +284,11 @@ impl<R: Read + Seek> BMPDecoder<R> {
         Ok(())
     }
 
+    // The output row of the `h`th row stored in the file
+    fn output_row(&self, h: i32) -> i32 {
+        if self.top_down != self.bottom_up_output { h } else { self.height - h - 1 }
+    }
+
     fn read_color_index_data(&mut self) -> ImageResult<Vec<u8>> {
         let row_byte_length = ((self.bit_count as u32 * self.width as u32 + 31) / 32 * 4) as usize;
         let indexes_per_byte = 8 / self.bit_count;
@@ -287,7 +300,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
             let mut line = Vec::with_capacity(row_byte_length);
             try!(self.r.by_ref().take(row_byte_length as u64).read_to_end(&mut line));
 
-            let x = if self.top_down { h } else { self.height - h - 1 };
+            let x = self.output_row(h);
             let mut y = 0;
             
             for i in 0..line.len() {
@@ -326,7 +339,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
 
         try!(self.r.seek(SeekFrom::Start(self.data_offset)));
         for h in 0..self.height {
-            let x = if self.top_down { h } else { self.height - h - 1 };
+            let x = self.output_row(h);
             for y in 0..self.width {
                 let data = try!(self.r.read_u16::<LittleEndian>());
 
@@ -368,7 +381,7 @@ impl<R: Read + Seek> BMPDecoder<R> {
 
         try!(self.r.seek(SeekFrom::Start(self.data_offset)));
         for h in 0..self.height {
-            let x = if self.top_down { h } else { self.height - h - 1 };
+            let x = self.output_row(h);
             for y in 0..self.width {
 
                 if format == FormatFullBytes::Format888 {
@@ -438,6 +451,20 @@ impl<R: Read + Seek> BMPDecoder<R> {
     }
 }
 
+#[cfg(feature = "mmap")]
+impl BMPDecoder<MappedFile> {
+    /// Create a new decoder that decodes the file at ```path```, mapped
+    /// into memory
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to or truncated, by this or any other
+    /// process, while the decoder exists. See the ```mmap``` module.
+    pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> ImageResult<BMPDecoder<MappedFile>> {
+        Ok(BMPDecoder::new(try!(mmap::map_file(path))))
+    }
+}
+
 impl<R: Read + Seek> ImageDecoder for BMPDecoder<R> {
     fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
         try!(self.read_metadata());
@@ -461,4 +488,60 @@ impl<R: Read + Seek> ImageDecoder for BMPDecoder<R> {
         try!(self.read_metadata());
         self.read_image_data().map(|v| DecodingResult::U8(v) )
     }
+
+    // Places the rows in `order` while decoding them
+    fn read_image_into_ordered(&mut self, buf: &mut [u8], order: RowOrder) -> ImageResult<()> {
+        self.bottom_up_output = order == RowOrder::BottomUp;
+        let result = self.read_image_into(buf);
+        self.bottom_up_output = false;
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageDecoder, RowOrder};
+    use super::BMPDecoder;
+
+    // A 24 bit BMP of 2x3 pixels whose row `y` from the top is gray `y`,
+    // with the rows stored from the bottom up unless `top_down`
+    fn bmp(top_down: bool) -> Vec<u8> {
+        let height: i32 = if top_down { -3 } else { 3 };
+        let mut data = b"BM".to_vec();
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&[54, 0, 0, 0, 40, 0, 0, 0, 2, 0, 0, 0]);
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&[1, 0, 24, 0]);
+        data.extend_from_slice(&[0; 24]);
+
+        for i in (0..3) {
+            let y = if top_down { i } else { 2 - i };
+            data.extend_from_slice(&[y; 6]);
+            data.extend_from_slice(&[0; 2]);
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_read_image_into_ordered() {
+        for &top_down in [false, true].iter() {
+            let mut buf = vec![9u8; 18];
+            BMPDecoder::new(Cursor::new(bmp(top_down)))
+                .read_image_into_ordered(&mut buf, RowOrder::TopDown).unwrap();
+            assert_eq!(buf, [[0u8; 6], [1; 6], [2; 6]].concat());
+
+            let mut decoder = BMPDecoder::new(Cursor::new(bmp(top_down)));
+            decoder.read_image_into_ordered(&mut buf, RowOrder::BottomUp).unwrap();
+            assert_eq!(buf, [[2u8; 6], [1; 6], [0; 6]].concat());
+
+            // The order only applies to that call
+            let mut buf = vec![9u8; 18];
+            decoder.read_image_into(&mut buf).unwrap();
+            assert_eq!(buf, [[0u8; 6], [1; 6], [2; 6]].concat());
+        }
+    }
 }
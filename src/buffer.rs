@@ -2,6 +2,7 @@ use std::slice::{ Chunks, ChunksMut };
 use std::ops::{ Deref, DerefMut, Index, IndexMut };
 use std::marker::PhantomData;
 use std::iter::repeat;
+use std::sync::Arc;
 use std::path::Path;
 use std::io;
 use num::Zero;
@@ -11,6 +12,7 @@ use color::{ Rgb, Rgba, Luma, LumaA, FromColor, ColorType };
 use image::GenericImage;
 use dynimage::save_buffer;
 use utils::expand_packed;
+use math::Rect;
 
 /// A generalized pixel.
 ///
@@ -206,6 +208,12 @@ impl<'a, P: Pixel + 'a> Iterator for EnumeratePixelsMut<'a, P> where P::Subpixel
 }
 
 /// Generic image buffer
+///
+/// The pixel data is stored in `Container`, which can be any type that
+/// dereferences to a slice of subpixels. Besides the default `Vec`, this
+/// includes `Box<[T]>`, `&[T]` and `&mut [T]` to borrow external memory, or
+/// `Arc<[T]>` to share an immutable buffer cheaply between several threads.
+/// Mutating methods are only available if the container implements `DerefMut`.
 pub struct ImageBuffer<P: Pixel, Container> {
     width: u32,
     height: u32,
@@ -244,6 +252,11 @@ where P: Pixel + 'static,
         self.data
     }
 
+    /// Returns a reference to the underlying raw buffer
+    pub fn as_raw(&self) -> &Container {
+        &self.data
+    }
+
     /// The width and height of this image.
     pub fn dimensions(&self) -> (u32, u32) {
         (self.width, self.height)
@@ -292,6 +305,55 @@ where P: Pixel + 'static,
             &self.data[index .. index + no_channels]
         )
     }
+
+    /// Copies the pixels within `rect` into a patch that can later be
+    /// restored with `apply_patch`, for example to undo an edit of that region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rect` does not fit into this image.
+    pub fn snapshot_region(&self, rect: Rect) -> RegionPatch<P> {
+        assert!(rect.fits_within(self.width, self.height),
+                "region {:?} is out of bounds", rect);
+
+        let channels = <P as Pixel>::channel_count() as usize;
+        let len = rect.width as usize * channels;
+        let mut data = Vec::with_capacity(len * rect.height as usize);
+
+        for y in (rect.y..rect.y + rect.height) {
+            let start = (y as usize * self.width as usize + rect.x as usize) * channels;
+            data.extend(self.data[start..start + len].iter().cloned());
+        }
+
+        RegionPatch {
+            rect: rect,
+            data: data,
+            _phantom: PhantomData
+        }
+    }
+}
+
+/// A copy of the pixels of a rectangular region of an image
+///
+/// Created by `ImageBuffer::snapshot_region` and written back with
+/// `ImageBuffer::apply_patch`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegionPatch<P: Pixel> {
+    rect: Rect,
+    data: Vec<P::Subpixel>,
+    _phantom: PhantomData<P>,
+}
+
+impl<P: Pixel + 'static> RegionPatch<P> {
+    /// The region of the image this patch was taken from
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// The pixel data of the patch, in row-major order
+    pub fn as_raw(&self) -> &[P::Subpixel] {
+        &self.data
+    }
 }
 
 impl<P, Container> ImageBuffer<P, Container>
@@ -342,6 +404,33 @@ where P: Pixel + 'static,
     pub fn put_pixel(&mut self, x: u32, y: u32, pixel: P) {
         *self.get_pixel_mut(x, y) = pixel
     }
+
+    /// Restores the pixels saved in `patch` to the region they were taken from
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region of the patch does not fit into this image.
+    pub fn apply_patch(&mut self, patch: &RegionPatch<P>) {
+        let rect = patch.rect;
+        assert!(rect.fits_within(self.width, self.height),
+                "patch region {:?} is out of bounds", rect);
+
+        let channels = <P as Pixel>::channel_count() as usize;
+        let len = rect.width as usize * channels;
+
+        // An empty region has no rows to copy
+        if len == 0 {
+            return
+        }
+
+        for (y, src) in (rect.y..rect.y + rect.height).zip(patch.data.chunks(len)) {
+            let start = (y as usize * self.width as usize + rect.x as usize) * channels;
+
+            for (d, s) in self.data[start..start + len].iter_mut().zip(src.iter()) {
+                *d = *s
+            }
+        }
+    }
 }
 
 impl<P, Container> ImageBuffer<P, Container>
@@ -510,6 +599,92 @@ where P::Subpixel: 'static {
     }
 }
 
+/// A reference counted, copy-on-write container for image data.
+///
+/// Cloning a `SharedBuffer` only increments a reference count. The data
+/// is copied the first time one of several clones is mutated, such that
+/// keeping many snapshots of a large image is cheap as long as they do
+/// not diverge.
+pub struct SharedBuffer<T> {
+    data: Arc<Vec<T>>
+}
+
+impl<T: Clone> SharedBuffer<T> {
+    /// Wraps `data` in a new shared buffer
+    pub fn new(data: Vec<T>) -> SharedBuffer<T> {
+        SharedBuffer {
+            data: Arc::new(data)
+        }
+    }
+
+    /// Returns true if the data is currently shared with another buffer
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.data) > 1
+    }
+
+    /// Returns the data as a `Vec`. Only copies the data if it is shared.
+    pub fn into_vec(self) -> Vec<T> {
+        match Arc::try_unwrap(self.data) {
+            Ok(data) => data,
+            Err(data) => (*data).clone()
+        }
+    }
+}
+
+impl<T> Clone for SharedBuffer<T> {
+    fn clone(&self) -> SharedBuffer<T> {
+        SharedBuffer {
+            data: self.data.clone()
+        }
+    }
+}
+
+impl<T> Deref for SharedBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T: Clone> DerefMut for SharedBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // Copies the data if it is shared
+        let data: &mut Vec<T> = Arc::make_mut(&mut self.data);
+        &mut data[..]
+    }
+}
+
+impl<T: Clone> From<Vec<T>> for SharedBuffer<T> {
+    fn from(data: Vec<T>) -> SharedBuffer<T> {
+        SharedBuffer::new(data)
+    }
+}
+
+impl<P: Pixel + 'static> From<ImageBuffer<P, Vec<P::Subpixel>>> for ImageBuffer<P, SharedBuffer<P::Subpixel>>
+where P::Subpixel: 'static {
+    fn from(buffer: ImageBuffer<P, Vec<P::Subpixel>>) -> ImageBuffer<P, SharedBuffer<P::Subpixel>> {
+        ImageBuffer {
+            width: buffer.width,
+            height: buffer.height,
+            _phantom: PhantomData,
+            data: SharedBuffer::new(buffer.data),
+        }
+    }
+}
+
+impl<P: Pixel + 'static> From<ImageBuffer<P, SharedBuffer<P::Subpixel>>> for ImageBuffer<P, Vec<P::Subpixel>>
+where P::Subpixel: 'static {
+    fn from(buffer: ImageBuffer<P, SharedBuffer<P::Subpixel>>) -> ImageBuffer<P, Vec<P::Subpixel>> {
+        ImageBuffer {
+            width: buffer.width,
+            height: buffer.height,
+            _phantom: PhantomData,
+            data: buffer.data.into_vec(),
+        }
+    }
+}
+
 /// Provides color conversions for whole image buffers.
 pub trait ConvertBuffer<T> {
     /// Converts `self` to a buffer of type T
@@ -583,11 +758,24 @@ pub type RgbaImage = ImageBuffer<Rgba<u8>, Vec<u8>>;
 pub type GrayImage = ImageBuffer<Luma<u8>, Vec<u8>>;
 /// Sendable grayscale + alpha channel image buffer
 pub type GrayAlphaImage = ImageBuffer<LumaA<u8>, Vec<u8>>;
+/// Sendable 16 bit grayscale image buffer
+pub type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+/// Sendable 16 bit grayscale + alpha channel image buffer
+pub type GrayAlpha16Image = ImageBuffer<LumaA<u16>, Vec<u16>>;
+
+/// Copy-on-write Rgb image buffer
+pub type SharedRgbImage = ImageBuffer<Rgb<u8>, SharedBuffer<u8>>;
+/// Copy-on-write Rgb + alpha channel image buffer
+pub type SharedRgbaImage = ImageBuffer<Rgba<u8>, SharedBuffer<u8>>;
+/// Copy-on-write grayscale image buffer
+pub type SharedGrayImage = ImageBuffer<Luma<u8>, SharedBuffer<u8>>;
+/// Copy-on-write grayscale + alpha channel image buffer
+pub type SharedGrayAlphaImage = ImageBuffer<LumaA<u8>, SharedBuffer<u8>>;
 
 #[cfg(test)]
 mod test {
 
-    use super::{ImageBuffer, RgbImage, GrayImage, ConvertBuffer, Pixel};
+    use super::{ImageBuffer, RgbImage, GrayImage, SharedGrayImage, ConvertBuffer, Pixel};
     use color;
     use test;
 
@@ -599,6 +787,76 @@ mod test {
         assert_eq!(&*buf, &data[..])
     }
 
+    #[test]
+    /// Tests that buffers backed by other containers than `Vec` work
+    fn container_buffers() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let mut data = vec![0u8; 2 * 2 * 3];
+        {
+            let mut buf: ImageBuffer<color::Rgb<u8>, _> =
+                ImageBuffer::from_raw(2, 2, &mut data[..]).unwrap();
+            buf.put_pixel(1, 1, color::Rgb([1, 2, 3]));
+        }
+        assert_eq!(&data[9..], &[1, 2, 3]);
+
+        let boxed: ImageBuffer<color::Rgb<u8>, Box<[u8]>> =
+            ImageBuffer::from_raw(2, 2, data.into_boxed_slice()).unwrap();
+        assert_eq!(boxed.get_pixel(1, 1).data, [1, 2, 3]);
+
+        let shared: ImageBuffer<color::Rgb<u8>, Arc<[u8]>> =
+            ImageBuffer::from_raw(2, 2, Arc::from(boxed.into_raw())).unwrap();
+        let workers: Vec<_> = (0..2).map(|_| {
+            let image = shared.clone();
+            thread::spawn(move || image.get_pixel(1, 1).data)
+        }).collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), [1, 2, 3]);
+        }
+    }
+
+    #[test]
+    /// Tests that shared buffers are only copied when mutated
+    fn copy_on_write() {
+        let a: SharedGrayImage = ImageBuffer::from_pixel(4, 4, color::Luma([1u8])).into();
+        let mut b = a.clone();
+        assert!(a.as_raw().is_shared());
+
+        b.put_pixel(0, 0, color::Luma([2]));
+        assert!(!a.as_raw().is_shared());
+        assert_eq!(a.get_pixel(0, 0).data, [1]);
+        assert_eq!(b.get_pixel(0, 0).data, [2]);
+    }
+
+    #[test]
+    /// Tests that a snapshot restores the edited region
+    fn region_patch() {
+        use math::Rect;
+
+        let mut image: GrayImage = ImageBuffer::from_fn(4, 4, |x, y| color::Luma([(y * 4 + x) as u8]));
+        let original = image.clone();
+
+        let patch = image.snapshot_region(Rect::new(1, 2, 2, 2));
+        assert_eq!(patch.as_raw(), &[9, 10, 13, 14]);
+
+        for y in 2..4 {
+            for x in 1..3 {
+                image.put_pixel(x, y, color::Luma([0]));
+            }
+        }
+        image.apply_patch(&patch);
+        assert_eq!(&*image, &*original);
+
+        // Empty regions change nothing
+        for &rect in [Rect::new(1, 2, 0, 2), Rect::new(1, 2, 2, 0)].iter() {
+            let empty = image.snapshot_region(rect);
+            assert!(empty.as_raw().is_empty());
+            image.apply_patch(&empty);
+            assert_eq!(&*image, &*original);
+        }
+    }
+
     #[test]
     fn test_get_pixel() {
         let mut a: RgbImage = ImageBuffer::new(10, 10);
@@ -0,0 +1,259 @@
+//! C interface to the decoders and encoders
+//!
+//! Enabled by the `capi` feature. The declarations for C and C++ are found
+//! in `include/image.h`. A shared library can be built with
+//! `cargo rustc --release --features capi --crate-type cdylib`.
+//!
+//! All functions return `IMAGE_OK` (0) on success and one of the other
+//! status codes otherwise. Buffers returned by the library have to be
+//! released with `image_buffer_free`.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use color::ColorType;
+use dynimage::{self, DynamicImage};
+use image::{GenericImage, ImageError, ImageFormat, ImageResult};
+
+/// The operation succeeded
+pub const IMAGE_OK: i32 = 0;
+/// The image is not formatted properly
+pub const IMAGE_ERROR_FORMAT: i32 = 1;
+/// The image dimensions are invalid or do not match the buffer
+pub const IMAGE_ERROR_DIMENSION: i32 = 2;
+/// The image format or a feature of it is not supported
+pub const IMAGE_ERROR_UNSUPPORTED: i32 = 3;
+/// The color type is not supported
+pub const IMAGE_ERROR_UNSUPPORTED_COLOR: i32 = 4;
+/// The image data ended prematurely
+pub const IMAGE_ERROR_NOT_ENOUGH_DATA: i32 = 5;
+/// An I/O error occurred
+pub const IMAGE_ERROR_IO: i32 = 6;
+/// The end of the image has been reached
+pub const IMAGE_ERROR_IMAGE_END: i32 = 7;
+/// A null pointer or invalid argument was passed
+pub const IMAGE_ERROR_INVALID_ARGUMENT: i32 = 8;
+/// The library panicked, this is a bug
+pub const IMAGE_ERROR_PANIC: i32 = 9;
+
+/// A buffer owned by the library
+///
+/// For decoded images `data` holds `height` rows of `width * channels`
+/// bytes. For encoded images `data` holds the encoded file and `channels`
+/// is the number of channels of the source image.
+#[repr(C)]
+pub struct image_buffer {
+    /// The bytes of the buffer
+    pub data: *mut u8,
+    /// The number of bytes in `data`
+    pub len: usize,
+    /// The width of the image
+    pub width: u32,
+    /// The height of the image
+    pub height: u32,
+    /// The number of 8 bit channels per pixel: 1 (gray), 2 (gray and alpha),
+    /// 3 (RGB) or 4 (RGBA)
+    pub channels: u32,
+}
+
+impl image_buffer {
+    fn empty() -> image_buffer {
+        image_buffer {
+            data: ptr::null_mut(),
+            len: 0,
+            width: 0,
+            height: 0,
+            channels: 0
+        }
+    }
+
+    fn from_vec(data: Vec<u8>, width: u32, height: u32, channels: u32) -> image_buffer {
+        let data = data.into_boxed_slice();
+        let len  = data.len();
+
+        image_buffer {
+            data: Box::into_raw(data) as *mut u8,
+            len: len,
+            width: width,
+            height: height,
+            channels: channels
+        }
+    }
+}
+
+fn error_code(error: &ImageError) -> i32 {
+    match *error {
+        ImageError::FormatError(..) => IMAGE_ERROR_FORMAT,
+        ImageError::DimensionError => IMAGE_ERROR_DIMENSION,
+        ImageError::UnsupportedError(..) => IMAGE_ERROR_UNSUPPORTED,
+        ImageError::UnsupportedColor(..) => IMAGE_ERROR_UNSUPPORTED_COLOR,
+        ImageError::NotEnoughData => IMAGE_ERROR_NOT_ENOUGH_DATA,
+        ImageError::IoError(..) => IMAGE_ERROR_IO,
+        ImageError::ImageEnd => IMAGE_ERROR_IMAGE_END,
+        ImageError::LimitsExceeded(..) => IMAGE_ERROR_DIMENSION,
+        // Decoding through the C API can not be cancelled
+        ImageError::Cancelled => IMAGE_ERROR_IO,
+    }
+}
+
+// Runs `f`, converting errors and panics into status codes
+fn guard<F>(f: F) -> i32 where F: FnOnce() -> ImageResult<()> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => IMAGE_OK,
+        Ok(Err(e)) => error_code(&e),
+        Err(_) => IMAGE_ERROR_PANIC,
+    }
+}
+
+fn image_format(format: u32) -> Option<ImageFormat> {
+    match format {
+        0 => Some(ImageFormat::PNG),
+        1 => Some(ImageFormat::JPEG),
+        2 => Some(ImageFormat::GIF),
+        3 => Some(ImageFormat::WEBP),
+        4 => Some(ImageFormat::PPM),
+        5 => Some(ImageFormat::TIFF),
+        6 => Some(ImageFormat::TGA),
+        7 => Some(ImageFormat::BMP),
+        _ => None,
+    }
+}
+
+/// Decodes the image file in `data[..len]`, detecting its format, into `out`
+#[no_mangle]
+pub unsafe extern "C" fn image_decode(data: *const u8, len: usize, out: *mut image_buffer) -> i32 {
+    if data.is_null() || out.is_null() {
+        return IMAGE_ERROR_INVALID_ARGUMENT
+    }
+
+    *out = image_buffer::empty();
+    let input = slice::from_raw_parts(data, len);
+
+    guard(|| {
+        let image = try!(dynimage::load_from_memory(input));
+        let (width, height) = (image.width(), image.height());
+
+        let (pixels, channels) = match image {
+            DynamicImage::ImageLuma8(ref i) => (i.to_vec(), 1),
+            DynamicImage::ImageLumaA8(ref i) => (i.to_vec(), 2),
+            DynamicImage::ImageRgb8(ref i) => (i.to_vec(), 3),
+            DynamicImage::ImageRgba8(ref i) => (i.to_vec(), 4),
+        };
+
+        *out = image_buffer::from_vec(pixels, width, height, channels);
+        Ok(())
+    })
+}
+
+/// Encodes `height` rows of `width * channels` bytes from `pixels` in
+/// `format` into `out`. The formats are numbered as `IMAGE_FORMAT_*`.
+#[no_mangle]
+pub unsafe extern "C" fn image_encode(pixels: *const u8,
+                                      width: u32,
+                                      height: u32,
+                                      channels: u32,
+                                      format: u32,
+                                      out: *mut image_buffer) -> i32 {
+    if pixels.is_null() || out.is_null() {
+        return IMAGE_ERROR_INVALID_ARGUMENT
+    }
+
+    *out = image_buffer::empty();
+
+    let format = match image_format(format) {
+        Some(format) => format,
+        None => return IMAGE_ERROR_INVALID_ARGUMENT
+    };
+
+    if channels < 1 || channels > 4 {
+        return IMAGE_ERROR_UNSUPPORTED_COLOR
+    }
+
+    let len = match (width as usize).checked_mul(height as usize)
+                                    .and_then(|n| n.checked_mul(channels as usize)) {
+        Some(len) => len,
+        None => return IMAGE_ERROR_DIMENSION
+    };
+
+    let color = match channels {
+        1 => ColorType::Gray(8),
+        2 => ColorType::GrayA(8),
+        3 => ColorType::RGB(8),
+        _ => ColorType::RGBA(8),
+    };
+
+    guard(|| {
+        let data = slice::from_raw_parts(pixels, len);
+
+        let mut encoded = Vec::new();
+        try!(dynimage::write_buffer(&mut encoded, data, width, height, color, format));
+
+        *out = image_buffer::from_vec(encoded, width, height, channels);
+        Ok(())
+    })
+}
+
+/// Releases the memory of a buffer returned by the library and resets it.
+/// Passing an empty buffer is allowed.
+#[no_mangle]
+pub unsafe extern "C" fn image_buffer_free(buffer: *mut image_buffer) {
+    if buffer.is_null() || (*buffer).data.is_null() {
+        return
+    }
+
+    let data = slice::from_raw_parts_mut((*buffer).data, (*buffer).len);
+    drop(Box::from_raw(data as *mut [u8]));
+
+    *buffer = image_buffer::empty();
+}
+
+/// Returns a static, null terminated description of a status code
+#[no_mangle]
+pub extern "C" fn image_status_message(status: i32) -> *const u8 {
+    let message: &'static [u8] = match status {
+        IMAGE_OK => b"Success\0",
+        IMAGE_ERROR_FORMAT => b"The image is not formatted properly\0",
+        IMAGE_ERROR_DIMENSION => b"Invalid image dimensions\0",
+        IMAGE_ERROR_UNSUPPORTED => b"Unsupported image format\0",
+        IMAGE_ERROR_UNSUPPORTED_COLOR => b"Unsupported color type\0",
+        IMAGE_ERROR_NOT_ENOUGH_DATA => b"Not enough data\0",
+        IMAGE_ERROR_IO => b"I/O error\0",
+        IMAGE_ERROR_IMAGE_END => b"End of image reached\0",
+        IMAGE_ERROR_INVALID_ARGUMENT => b"Invalid argument\0",
+        IMAGE_ERROR_PANIC => b"Internal error\0",
+        _ => b"Unknown status\0",
+    };
+
+    message.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let pixels = (0..16 * 8 * 3).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut encoded = super::image_buffer::empty();
+        let mut decoded = super::image_buffer::empty();
+        let mut failed  = super::image_buffer::empty();
+
+        unsafe {
+            assert_eq!(image_encode(pixels.as_ptr(), 16, 8, 3, 1, &mut encoded), IMAGE_OK);
+            assert_eq!(image_decode(encoded.data, encoded.len, &mut decoded), IMAGE_OK);
+            assert_eq!((decoded.width, decoded.height, decoded.channels), (16, 8, 3));
+            assert_eq!(decoded.len, pixels.len());
+
+            assert_eq!(image_encode(pixels.as_ptr(), 16, 8, 5, 4, &mut failed), IMAGE_ERROR_UNSUPPORTED_COLOR);
+            assert_eq!(image_encode(pixels.as_ptr(), !0, !0, 4, 1, &mut failed), IMAGE_ERROR_DIMENSION);
+            assert_eq!(image_decode(ptr::null(), 0, &mut failed), IMAGE_ERROR_INVALID_ARGUMENT);
+            assert!(failed.data.is_null());
+
+            image_buffer_free(&mut encoded);
+            image_buffer_free(&mut decoded);
+            assert!(decoded.data.is_null());
+        }
+    }
+}
@@ -24,6 +24,63 @@ pub enum ColorType {
     RGBA(u8)
 }
 
+/// The colorimetry of the samples of an RGB or gray image, which encoders
+/// record so that viewers interpret the samples as intended
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorSpace {
+    /// Whether the samples are sRGB encoded. The other fields then hold the
+    /// sRGB values, for formats that cannot mark an image as sRGB.
+    pub srgb: bool,
+
+    /// The exponent of the red, green and blue channels that converts the
+    /// samples, scaled to 0 to 1, to linear intensity. Gray images use the
+    /// first one.
+    pub gamma: [f32; 3],
+
+    /// The CIE xy chromaticity of the white point
+    pub white_point: (f32, f32),
+
+    /// The CIE xy chromaticities of the red, green and blue primaries
+    pub primaries: [(f32, f32); 3],
+
+    /// The samples of black and white of the red, green and blue channels
+    pub black_white: [(f32, f32); 3],
+}
+
+impl ColorSpace {
+    /// The sRGB color space, with the D65 white point
+    pub fn srgb() -> ColorSpace {
+        ColorSpace {
+            srgb: true,
+            ..ColorSpace::with_gamma(2.2)
+        }
+    }
+
+    /// A color space with the sRGB primaries and white point whose samples
+    /// follow a power law with exponent ```gamma```
+    pub fn with_gamma(gamma: f32) -> ColorSpace {
+        ColorSpace {
+            srgb: false,
+            gamma: [gamma; 3],
+            white_point: (0.3127, 0.3290),
+            primaries: [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06)],
+            black_white: [(0.0, 255.0); 3],
+        }
+    }
+
+    /// Converts the sample ```v```, scaled to 0 to 1, of channel
+    /// ```channel``` to linear intensity
+    pub fn to_linear(&self, channel: usize, v: f32) -> f32 {
+        if !self.srgb {
+            v.max(0.0).powf(self.gamma[channel])
+        } else if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    }
+}
+
 /// Returns the number of bits contained in a pixel of ColorType c
 pub fn bits_per_pixel(c: ColorType) -> usize {
     match c {
@@ -178,7 +235,7 @@ impl<T: Primitive + 'static> Pixel for $ident<T> {
             *v = f(*v)
         }
         if $alphas as usize != 0 {
-            let v = &mut self.data[$channels as usize-$alphas as usize-1];
+            let v = &mut self.data[$channels as usize-$alphas as usize];
             *v = g(*v)
         }
     }
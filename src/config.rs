@@ -0,0 +1,140 @@
+//! Global configuration of the codecs
+//!
+//! The SIMD level selects which vectorized code paths the codecs may use.
+//! By default the best level supported by the running CPU is used. Lowering
+//! it helps to debug vector code or to compare implementations in benchmarks.
+//!
+//! The vectorized code paths are only compiled with the ```simd``` cargo
+//! feature. Without it every level but ```Scalar``` is unsupported and the
+//! codecs use their portable scalar implementations.
+//!
+//! ```
+//! use image::config::{self, SimdLevel};
+//!
+//! config::set_simd(SimdLevel::Scalar);
+//! assert_eq!(config::simd(), SimdLevel::Scalar);
+//! config::reset_simd();
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The instruction set extensions the codecs may use
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SimdLevel {
+    /// Only scalar code
+    Scalar,
+    /// SSE2 on x86 and x86-64
+    Sse2,
+    /// AVX2 on x86 and x86-64
+    Avx2,
+    /// NEON on ARM
+    Neon,
+    /// SIMD128 on WebAssembly
+    Simd128,
+}
+
+impl SimdLevel {
+    /// Returns true if the running CPU supports this level
+    pub fn is_supported(self) -> bool {
+        match self {
+            SimdLevel::Scalar => true,
+            _ if !cfg!(feature = "simd") => false,
+            SimdLevel::Sse2 => x86_feature_sse2(),
+            SimdLevel::Avx2 => x86_feature_avx2(),
+            SimdLevel::Neon => cfg!(target_arch = "aarch64"),
+            SimdLevel::Simd128 => cfg!(all(target_arch = "wasm32", target_feature = "simd128")),
+        }
+    }
+
+    // The next lower level on the same architecture
+    fn fallback(self) -> SimdLevel {
+        match self {
+            SimdLevel::Avx2 => SimdLevel::Sse2,
+            _ => SimdLevel::Scalar,
+        }
+    }
+
+    /// Returns this level if it is supported, otherwise the best supported
+    /// level below it
+    pub fn effective(self) -> SimdLevel {
+        let mut level = self;
+
+        while !level.is_supported() {
+            level = level.fallback();
+        }
+
+        level
+    }
+
+    fn to_index(self) -> usize {
+        match self {
+            SimdLevel::Scalar => 1,
+            SimdLevel::Sse2 => 2,
+            SimdLevel::Avx2 => 3,
+            SimdLevel::Neon => 4,
+            SimdLevel::Simd128 => 5,
+        }
+    }
+
+    fn from_index(index: usize) -> Option<SimdLevel> {
+        match index {
+            1 => Some(SimdLevel::Scalar),
+            2 => Some(SimdLevel::Sse2),
+            3 => Some(SimdLevel::Avx2),
+            4 => Some(SimdLevel::Neon),
+            5 => Some(SimdLevel::Simd128),
+            _ => None,
+        }
+    }
+}
+
+// The level set by `set_simd`, 0 if none was set
+static SIMD_LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the best level supported by the running CPU
+pub fn detect_simd() -> SimdLevel {
+    [SimdLevel::Avx2, SimdLevel::Neon, SimdLevel::Simd128].iter()
+        .map(|level| level.effective())
+        .find(|&level| level != SimdLevel::Scalar)
+        .unwrap_or(SimdLevel::Scalar)
+}
+
+/// Sets the SIMD level used by all codecs that are not configured otherwise.
+/// Levels not supported by the running CPU fall back to the best supported
+/// level below them.
+pub fn set_simd(level: SimdLevel) {
+    SIMD_LEVEL.store(level.to_index(), Ordering::SeqCst);
+}
+
+/// Restores the default of using the best level supported by the CPU
+pub fn reset_simd() {
+    SIMD_LEVEL.store(0, Ordering::SeqCst);
+}
+
+/// Returns the SIMD level the codecs currently use
+pub fn simd() -> SimdLevel {
+    match SimdLevel::from_index(SIMD_LEVEL.load(Ordering::SeqCst)) {
+        Some(level) => level.effective(),
+        None => detect_simd(),
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn x86_feature_sse2() -> bool {
+    is_x86_feature_detected!("sse2")
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn x86_feature_avx2() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn x86_feature_sse2() -> bool {
+    false
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn x86_feature_avx2() -> bool {
+    false
+}
@@ -0,0 +1,294 @@
+//! Differences between images of the same size
+//!
+//! A `Delta` stores the runs of bytes that differ between two images, which is
+//! much smaller than the image itself if only small parts of it changed, as is
+//! common for consecutive frames of screen recordings or remote desktop streams.
+//!
+//! ```
+//! use image::{GrayImage, ImageBuffer, Luma};
+//! use image::delta;
+//!
+//! let a: GrayImage = ImageBuffer::new(16, 16);
+//! let mut b = a.clone();
+//! b.put_pixel(3, 4, Luma([255]));
+//!
+//! let d = delta::diff(&a, &b).unwrap();
+//! let mut c = a.clone();
+//! delta::apply(&mut c, &d).unwrap();
+//! assert_eq!(&*b, &*c);
+//! ```
+use std::io::{self, Read, Write};
+use std::ops::{Deref, DerefMut};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+
+use buffer::{ImageBuffer, Pixel};
+use image::{ImageError, ImageResult};
+
+// Gaps of up to this many unchanged bytes are stored as part of a run, as the
+// overhead of starting a new run would exceed the cost of storing the gap.
+const MIN_GAP: usize = 4;
+
+// Identifies the serialized form
+const MAGIC: &'static [u8; 4] = b"IDLT";
+
+/// A run of changed bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Run {
+    /// Unchanged bytes since the end of the previous run
+    skip: usize,
+    /// The new bytes
+    data: Vec<u8>,
+}
+
+/// The difference between two images of the same dimensions and pixel type
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delta {
+    width: u32,
+    height: u32,
+    channels: u8,
+    runs: Vec<Run>,
+}
+
+impl Delta {
+    /// The dimensions of the images this delta applies to
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Returns true if both images were identical
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// The number of changed bytes stored in this delta
+    pub fn changed_bytes(&self) -> usize {
+        self.runs.iter().fold(0, |n, r| n + r.data.len())
+    }
+
+    /// Writes the compact serialized form of this delta to `w`
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        try!(w.write_all(MAGIC));
+        try!(w.write_u32::<LittleEndian>(self.width));
+        try!(w.write_u32::<LittleEndian>(self.height));
+        try!(w.write_u8(self.channels));
+        try!(write_varint(w, self.runs.len()));
+
+        for run in self.runs.iter() {
+            try!(write_varint(w, run.skip));
+            try!(write_varint(w, run.data.len()));
+            try!(w.write_all(&run.data));
+        }
+
+        Ok(())
+    }
+
+    /// Reads a delta in the format written by `write_to`
+    pub fn read_from<R: Read>(r: &mut R) -> ImageResult<Delta> {
+        let mut magic = [0u8; 4];
+        try!(read_exact(r, &mut magic));
+
+        if &magic != MAGIC {
+            return Err(ImageError::FormatError("Invalid delta signature".to_string()))
+        }
+
+        let width    = try!(r.read_u32::<LittleEndian>());
+        let height   = try!(r.read_u32::<LittleEndian>());
+        let channels = try!(r.read_u8());
+        let count    = try!(read_varint(r));
+
+        let total = match (width as u64).checked_mul(height as u64)
+                                        .and_then(|n| n.checked_mul(channels as u64)) {
+            Some(total) => total,
+            None => return Err(ImageError::DimensionError)
+        };
+        let mut position = 0u64;
+        let mut runs = Vec::new();
+
+        for _ in (0..count) {
+            let skip = try!(read_varint(r));
+            let len  = try!(read_varint(r));
+
+            position = position.saturating_add(skip as u64 + len as u64);
+            if position > total {
+                return Err(ImageError::FormatError("Delta exceeds the image size".to_string()))
+            }
+
+            // The data is read as it arrives rather than allocated up front,
+            // as the length may be corrupt or larger than the stream
+            let mut data = Vec::new();
+            try!(r.by_ref().take(len as u64).read_to_end(&mut data));
+            if data.len() != len {
+                return Err(ImageError::NotEnoughData)
+            }
+
+            runs.push(Run { skip: skip, data: data });
+        }
+
+        Ok(Delta {
+            width: width,
+            height: height,
+            channels: channels,
+            runs: runs
+        })
+    }
+}
+
+/// Computes the delta that turns image `a` into image `b`
+///
+/// Returns a `DimensionError` if the images differ in size.
+pub fn diff<P, A, B>(a: &ImageBuffer<P, A>, b: &ImageBuffer<P, B>) -> ImageResult<Delta>
+where P: Pixel<Subpixel=u8> + 'static,
+      A: Deref<Target=[u8]>,
+      B: Deref<Target=[u8]> {
+    if a.dimensions() != b.dimensions() {
+        return Err(ImageError::DimensionError)
+    }
+
+    let (width, height) = a.dimensions();
+    let channels = <P as Pixel>::channel_count();
+    let len = width as usize * height as usize * channels as usize;
+    let (a, b) = (&a.deref()[..len], &b.deref()[..len]);
+
+    let mut runs = Vec::new();
+    // End of the previous run
+    let mut end = 0;
+    let mut i = 0;
+
+    while i < len {
+        if a[i] == b[i] {
+            i += 1;
+            continue
+        }
+
+        // Extend the run until more than MIN_GAP unchanged bytes follow
+        let start = i;
+        let mut last = i;
+        while i < len && i - last <= MIN_GAP {
+            if a[i] != b[i] {
+                last = i;
+            }
+            i += 1;
+        }
+
+        runs.push(Run {
+            skip: start - end,
+            data: b[start..last + 1].to_vec()
+        });
+
+        end = last + 1;
+        i = end;
+    }
+
+    Ok(Delta {
+        width: width,
+        height: height,
+        channels: channels,
+        runs: runs
+    })
+}
+
+/// Applies `delta` to `base`, turning it into the second image passed to `diff`
+///
+/// Returns a `DimensionError` if `base` does not match the size and pixel
+/// type of the images the delta was computed from.
+pub fn apply<P, C>(base: &mut ImageBuffer<P, C>, delta: &Delta) -> ImageResult<()>
+where P: Pixel<Subpixel=u8> + 'static,
+      C: Deref<Target=[u8]> + DerefMut {
+    if base.dimensions() != delta.dimensions() ||
+       <P as Pixel>::channel_count() != delta.channels {
+        return Err(ImageError::DimensionError)
+    }
+
+    let mut position = 0;
+
+    for run in delta.runs.iter() {
+        position += run.skip;
+        ::copy_memory(&run.data, &mut base.deref_mut()[position..position + run.data.len()]);
+        position += run.data.len();
+    }
+
+    Ok(())
+}
+
+fn write_varint<W: Write>(w: &mut W, mut v: usize) -> io::Result<()> {
+    while v >= 0x80 {
+        try!(w.write_u8((v & 0x7F) as u8 | 0x80));
+        v >>= 7;
+    }
+
+    try!(w.write_u8(v as u8));
+    Ok(())
+}
+
+fn read_varint<R: Read>(r: &mut R) -> ImageResult<usize> {
+    let mut v = 0usize;
+
+    for shift in (0..5) {
+        let b = try!(r.read_u8());
+        v |= ((b & 0x7F) as usize) << (7 * shift);
+
+        if b & 0x80 == 0 {
+            return Ok(v)
+        }
+    }
+
+    Err(ImageError::FormatError("Invalid length in delta".to_string()))
+}
+
+fn read_exact<R: Read>(r: &mut R, buf: &mut [u8]) -> ImageResult<()> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match try!(r.read(&mut buf[read..])) {
+            0 => return Err(ImageError::NotEnoughData),
+            n => read += n
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, apply, write_varint, Delta};
+    use buffer::{ImageBuffer, RgbImage};
+    use color::Rgb;
+    use image::ImageError;
+
+    #[test]
+    fn test_roundtrip() {
+        let a: RgbImage = ImageBuffer::from_fn(32, 32, |x, y| Rgb([x as u8, y as u8, 0]));
+        let mut b = a.clone();
+        b.put_pixel(1, 1, Rgb([9, 9, 9]));
+        b.put_pixel(3, 1, Rgb([9, 9, 9]));
+        b.put_pixel(30, 20, Rgb([9, 9, 9]));
+
+        let d = diff(&a, &b).unwrap();
+        assert_eq!(d.runs.len(), 2);
+        assert!(diff(&a, &a).unwrap().is_empty());
+
+        let mut buf = Vec::new();
+        d.write_to(&mut buf).unwrap();
+        let d = Delta::read_from(&mut &buf[..]).unwrap();
+
+        let mut c = a.clone();
+        apply(&mut c, &d).unwrap();
+        assert_eq!(&*b, &*c);
+    }
+
+    #[test]
+    fn test_read_corrupt_lengths() {
+        // A run claiming gigabytes of data in a stream of a few bytes
+        let mut buf = b"IDLT".to_vec();
+        buf.extend([0xFF, 0xFF, 0xFF, 0xFF, 1, 0, 0, 0, 4].iter().cloned());
+        write_varint(&mut buf, 1).unwrap();
+        write_varint(&mut buf, 0).unwrap();
+        write_varint(&mut buf, 1 << 30).unwrap();
+        buf.extend([1, 2, 3].iter().cloned());
+
+        match Delta::read_from(&mut &buf[..]) {
+            Err(ImageError::NotEnoughData) => (),
+            _ => panic!("a truncated run was accepted")
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Drawing of detection results
+//!
+//! Bounding boxes with labels and keypoints joined by a skeleton, as found
+//! by object detection and pose estimation models, drawn onto any image.
+//! Labels are rendered with a built-in 3x5 pixel font of digits, capital
+//! letters and common punctuation, scaled up by an integer factor. Lower
+//! case letters are drawn as capitals.
+//!
+//! ```
+//! use image::{Rect, Rgb, RgbImage};
+//! use image::draw::{self, LabeledBox, Style};
+//!
+//! let mut img = RgbImage::new(64, 64);
+//! let detections = [LabeledBox { rect: Rect::new(8, 20, 40, 30), label: "cat 0.93".to_string(),
+//!                                color: Rgb([255, 0, 0]) }];
+//! draw::boxes(&mut img, &detections, &Style::default());
+//! ```
+
+use std::cmp;
+
+use num::{Bounded, NumCast};
+
+use buffer::Pixel;
+use image::GenericImage;
+use math::Rect;
+
+/// The edges between the 17 keypoints of COCO, from the nose, the eyes and
+/// the ears to the shoulders, elbows, wrists, hips, knees and ankles, each
+/// left before right
+pub const COCO_SKELETON: [(usize, usize); 19] = [
+    (15, 13), (13, 11), (16, 14), (14, 12), (11, 12), (5, 11), (6, 12), (5, 6), (5, 7), (6, 8),
+    (7, 9), (8, 10), (1, 2), (0, 1), (0, 2), (1, 3), (2, 4), (3, 5), (4, 6),
+];
+
+// The rows of the glyphs, the highest of the 3 bits is the left pixel.
+// Characters without a glyph are drawn as `?`.
+static GLYPHS: [(char, [u8; 5]); 52] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]), ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]), ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]), ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]), ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]), ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]), ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]), ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]), ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]), ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]), ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]), ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]), ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]), ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]), ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]), ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]), ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]), ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]), ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]), ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]), (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]), ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]), ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('(', [0b010, 0b100, 0b100, 0b100, 0b010]), (')', [0b010, 0b001, 0b001, 0b001, 0b010]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]), ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]), ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]), ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+];
+
+/// A bounding box with a label, e.g. a class and a confidence
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabeledBox<P> {
+    /// The rectangle of the box
+    pub rect: Rect,
+    /// The label, none is drawn if it is empty
+    pub label: String,
+    /// The color of the outline and of the background of the label
+    pub color: P,
+}
+
+/// How boxes, labels and keypoints are drawn
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Style {
+    /// The width of the outlines of boxes and of the lines of skeletons in
+    /// pixels. Defaults to 2.
+    pub thickness: u32,
+    /// The size of each pixel of the font in pixels. Defaults to 2.
+    pub font_scale: u32,
+    /// The radius of keypoints in pixels. Defaults to 3.
+    pub point_radius: u32,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            thickness: 2,
+            font_scale: 2,
+            point_radius: 3,
+        }
+    }
+}
+
+/// Draws the outlines of ```boxes``` onto ```image```, inside their
+/// rectangles, and their labels in black or white on the color of the box.
+///
+/// Each label is placed above its box, or inside its top edge or below it
+/// if that is where it fits within the image without covering the labels
+/// drawn before it.
+pub fn boxes<I: GenericImage>(image: &mut I, boxes: &[LabeledBox<I::Pixel>], style: &Style) {
+    let (width, height) = image.dimensions();
+    let mut placed: Vec<Rect> = Vec::new();
+
+    for b in boxes.iter() {
+        let r = b.rect;
+        let t = cmp::min(style.thickness, cmp::min(r.width, r.height) / 2 + 1);
+
+        fill(image, r.x as i64, r.y as i64, r.width, t, b.color);
+        fill(image, r.x as i64, (r.y + r.height) as i64 - t as i64, r.width, t, b.color);
+        fill(image, r.x as i64, r.y as i64, t, r.height, b.color);
+        fill(image, (r.x + r.width) as i64 - t as i64, r.y as i64, t, r.height, b.color);
+
+        if b.label.is_empty() {
+            continue
+        }
+
+        // The text is surrounded by a margin of one font pixel
+        let scale = cmp::max(1, style.font_scale);
+        let (tw, th) = text_size(&b.label, scale);
+        let (lw, lh) = (tw + 2 * scale, th + 2 * scale);
+        let x = cmp::min(r.x, width.saturating_sub(lw));
+
+        let candidates = [r.y as i64 - lh as i64, r.y as i64, (r.y + r.height) as i64];
+        let inside = |&y: &i64| y >= 0 && y + lh as i64 <= height as i64;
+        let free = |y: i64| !placed.iter().any(|p| overlap(p, &Rect::new(x, y as u32, lw, lh)));
+
+        let y = candidates.iter().cloned().find(|y| inside(y) && free(*y))
+                          .or(candidates.iter().cloned().find(inside))
+                          .unwrap_or(0);
+
+        fill(image, x as i64, y, lw, lh, b.color);
+        draw_text(image, (x + scale) as i64, y + scale as i64, &b.label, contrasting(&b.color), scale);
+        placed.push(Rect::new(x, y as u32, lw, lh));
+    }
+}
+
+/// Draws the ```keypoints``` found by a pose estimation model onto
+/// ```image``` as discs, joined by lines for the pairs of indices of
+/// ```skeleton```, e.g. ```COCO_SKELETON```. Keypoints that were not found
+/// are ```None``` and neither they nor their lines are drawn.
+pub fn keypoints<I: GenericImage>(image: &mut I,
+                                  keypoints: &[Option<(f32, f32)>],
+                                  skeleton: &[(usize, usize)],
+                                  color: I::Pixel,
+                                  style: &Style) {
+    for &(a, b) in skeleton.iter() {
+        if let (Some(&Some(p)), Some(&Some(q))) = (keypoints.get(a), keypoints.get(b)) {
+            draw_line(image, p, q, style.thickness, color);
+        }
+    }
+
+    let r = style.point_radius as i64;
+    for &(x, y) in keypoints.iter().filter_map(|k| k.as_ref()) {
+        let (cx, cy) = (x.floor() as i64, y.floor() as i64);
+
+        for dy in (-r..r + 1) {
+            for dx in (-r..r + 1) {
+                if dx * dx + dy * dy <= r * r + r {
+                    put(image, cx + dx, cy + dy, color);
+                }
+            }
+        }
+    }
+}
+
+/// Draws ```text``` onto ```image``` with the built-in font scaled by
+/// ```scale```, its top left corner at ```x```, ```y```. Parts outside of
+/// the image are left out.
+pub fn draw_text<I: GenericImage>(image: &mut I, x: i64, y: i64, text: &str, color: I::Pixel, scale: u32) {
+    let scale = cmp::max(1, scale) as i64;
+
+    for (i, c) in text.chars().enumerate() {
+        let rows = glyph(c);
+        let x0 = x + i as i64 * 4 * scale;
+
+        for (row, &bits) in rows.iter().enumerate() {
+            for column in (0..3) {
+                if bits & (0b100 >> column) != 0 {
+                    fill(image, x0 + column * scale, y + row as i64 * scale, scale as u32, scale as u32, color);
+                }
+            }
+        }
+    }
+}
+
+/// The width and height of ```text``` drawn by ```draw_text``` with
+/// ```scale```
+pub fn text_size(text: &str, scale: u32) -> (u32, u32) {
+    let chars = text.chars().count() as u32;
+    let scale = cmp::max(1, scale);
+
+    (if chars == 0 { 0 } else { (4 * chars - 1) * scale }, 5 * scale)
+}
+
+fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+    GLYPHS.iter().find(|g| g.0 == c).unwrap_or(&GLYPHS[GLYPHS.len() - 1]).1
+}
+
+// Black or white, whichever stands out more against `color`, and opaque
+fn contrasting<P: Pixel>(color: &P) -> P {
+    let max = P::Subpixel::max_value();
+    let luma: f32 = NumCast::from(color.to_luma()[0]).unwrap();
+    let max_luma: f32 = NumCast::from(max).unwrap();
+    let zero = NumCast::from(0).unwrap();
+
+    if luma > max_luma / 2.0 {
+        color.map_with_alpha(|_| zero, |_| max)
+    } else {
+        color.map_with_alpha(|_| max, |_| max)
+    }
+}
+
+fn overlap(a: &Rect, b: &Rect) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+// Draws a line of `thickness` pixels from `p` to `q` as squares along the
+// pixels of Bresenham's line
+fn draw_line<I: GenericImage>(image: &mut I, p: (f32, f32), q: (f32, f32), thickness: u32, color: I::Pixel) {
+    let (mut x, mut y) = (p.0.floor() as i64, p.1.floor() as i64);
+    let (x1, y1) = (q.0.floor() as i64, q.1.floor() as i64);
+    let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+    let (sx, sy) = (if x < x1 { 1 } else { -1 }, if y < y1 { 1 } else { -1 });
+    let mut error = dx + dy;
+
+    let t = cmp::max(1, thickness);
+    let half = (t / 2) as i64;
+
+    loop {
+        fill(image, x - half, y - half, t, t, color);
+
+        if x == x1 && y == y1 {
+            break
+        }
+
+        let e2 = 2 * error;
+        if e2 >= dy {
+            error += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            error += dx;
+            y += sy;
+        }
+    }
+}
+
+// Fills the rectangle at `x`, `y` with `color`, clipped to the image
+fn fill<I: GenericImage>(image: &mut I, x: i64, y: i64, width: u32, height: u32, color: I::Pixel) {
+    for py in (y..y + height as i64) {
+        for px in (x..x + width as i64) {
+            put(image, px, py, color);
+        }
+    }
+}
+
+fn put<I: GenericImage>(image: &mut I, x: i64, y: i64, color: I::Pixel) {
+    let (width, height) = image.dimensions();
+
+    if x >= 0 && y >= 0 && x < width as i64 && y < height as i64 {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{GrayAlphaImage, ImageBuffer, RgbImage};
+    use color::{LumaA, Rgb};
+    use math::Rect;
+    use super::{boxes, keypoints, text_size, LabeledBox, Style};
+
+    #[test]
+    fn test_boxes() {
+        let mut image: RgbImage = ImageBuffer::new(64, 64);
+        let detections = [
+            LabeledBox { rect: Rect::new(10, 30, 20, 20), label: "a1".to_string(), color: Rgb([255, 0, 0]) },
+            LabeledBox { rect: Rect::new(12, 2, 10, 10), label: "b".to_string(), color: Rgb([255, 0, 0]) },
+            LabeledBox { rect: Rect::new(40, 40, 10, 10), label: String::new(), color: Rgb([255, 0, 0]) },
+        ];
+        boxes(&mut image, &detections, &Style::default());
+
+        // The outline is inside the rectangle
+        assert_eq!(image[(10, 45)], Rgb([255, 0, 0]));
+        assert_eq!(image[(11, 45)], Rgb([255, 0, 0]));
+        assert_eq!(image[(12, 45)], Rgb([0, 0, 0]));
+        assert_eq!(image[(29, 49)], Rgb([255, 0, 0]));
+        assert_eq!(image[(30, 49)], Rgb([0, 0, 0]));
+
+        // The first label is above its box, with the text in white as red
+        // is dark
+        assert_eq!(text_size("a1", 2), (14, 10));
+        assert_eq!(image[(10, 16)], Rgb([255, 0, 0]));
+        assert_eq!(image[(12, 18)], Rgb([255, 0, 0]));
+        assert_eq!(image[(14, 18)], Rgb([255, 255, 255]));
+        assert_eq!(image[(27, 29)], Rgb([255, 0, 0]));
+        assert_eq!(image[(28, 29)], Rgb([0, 0, 0]));
+
+        // The second does not fit above its box, so it covers its top edge
+        assert_eq!(image[(14, 4)], Rgb([255, 255, 255]));
+        assert!((2..16).all(|y| image[(21, y)] == Rgb([255, 0, 0])));
+        assert_eq!(image[(22, 15)], Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_gray_alpha_labels() {
+        let mut image: GrayAlphaImage = ImageBuffer::new(64, 64);
+        let detections = [
+            LabeledBox { rect: Rect::new(10, 30, 20, 20), label: "a1".to_string(), color: LumaA([200, 255]) },
+            LabeledBox { rect: Rect::new(40, 30, 20, 20), label: "a1".to_string(), color: LumaA([50, 255]) },
+        ];
+        boxes(&mut image, &detections, &Style::default());
+
+        // Opaque black text on the light label, opaque white on the dark one
+        assert_eq!(image[(12, 18)], LumaA([200, 255]));
+        assert_eq!(image[(14, 18)], LumaA([0, 255]));
+        assert_eq!(image[(42, 18)], LumaA([50, 255]));
+        assert_eq!(image[(44, 18)], LumaA([255, 255]));
+    }
+
+    #[test]
+    fn test_keypoints() {
+        let mut image: RgbImage = ImageBuffer::new(32, 32);
+        let points = [Some((4.0, 4.0)), Some((20.0, 4.0)), None];
+        let style = Style { thickness: 1, point_radius: 1, ..Style::default() };
+        keypoints(&mut image, &points, &[(0, 1), (1, 2)], Rgb([255, 0, 0]), &style);
+
+        assert!((4..21).all(|x| image[(x, 4)] == Rgb([255, 0, 0])));
+        assert_eq!(image[(4, 5)], Rgb([255, 0, 0]));
+        assert_eq!(image[(5, 5)], Rgb([255, 0, 0]));
+        assert_eq!(image[(6, 6)], Rgb([0, 0, 0]));
+        assert_eq!(image.pixels().filter(|&&p| p == Rgb([255, 0, 0])).count(), 17 + 2 * 8 - 2);
+    }
+}
@@ -1,3 +1,4 @@
+use std::cmp;
 use std::io;
 use std::io::{Read, Write, Seek, BufReader};
 use std::path::Path;
@@ -24,8 +25,10 @@ use tga;
 use bmp;
 
 use color;
-use buffer::{ImageBuffer, ConvertBuffer, Pixel, GrayImage, GrayAlphaImage, RgbImage, RgbaImage};
+use buffer::{ImageBuffer, ConvertBuffer, Pixel, GrayImage, GrayAlphaImage, RgbImage, RgbaImage,
+             SharedBuffer, SharedGrayImage, SharedGrayAlphaImage, SharedRgbImage, SharedRgbaImage};
 use imageops;
+use tensor::{self, Normalization};
 use image;
 use image:: {
     GenericImage,
@@ -34,40 +37,45 @@ use image:: {
     ImageFormat,
 };
 
-use image::DecodingResult::{U8};
+use image::DecodingResult::{self, U8};
 
 /// A Dynamic Image
+///
+/// The pixel data is reference counted. Cloning a dynamic image is cheap,
+/// the data is only copied once one of the clones is modified. Buffers
+/// like ```RgbImage``` are converted with ```into()``` when constructing a
+/// variant.
 #[derive(Clone)]
 pub enum DynamicImage {
     /// Each pixel in this image is 8-bit Luma
-    ImageLuma8(GrayImage),
+    ImageLuma8(SharedGrayImage),
 
     /// Each pixel in this image is 8-bit Luma with alpha
-    ImageLumaA8(GrayAlphaImage),
+    ImageLumaA8(SharedGrayAlphaImage),
 
     /// Each pixel in this image is 8-bit Rgb
-    ImageRgb8(RgbImage),
+    ImageRgb8(SharedRgbImage),
 
     /// Each pixel in this image is 8-bit Rgb with alpha
-    ImageRgba8(RgbaImage),
+    ImageRgba8(SharedRgbaImage),
 }
 
 macro_rules! dynamic_map(
         ($dynimage: expr, ref $image: ident => $action: expr) => (
                 match $dynimage {
-                        DynamicImage::ImageLuma8(ref $image) => DynamicImage::ImageLuma8($action),
-                        DynamicImage::ImageLumaA8(ref $image) => DynamicImage::ImageLumaA8($action),
-                        DynamicImage::ImageRgb8(ref $image) => DynamicImage::ImageRgb8($action),
-                        DynamicImage::ImageRgba8(ref $image) => DynamicImage::ImageRgba8($action),
+                        DynamicImage::ImageLuma8(ref $image) => DynamicImage::ImageLuma8($action.into()),
+                        DynamicImage::ImageLumaA8(ref $image) => DynamicImage::ImageLumaA8($action.into()),
+                        DynamicImage::ImageRgb8(ref $image) => DynamicImage::ImageRgb8($action.into()),
+                        DynamicImage::ImageRgba8(ref $image) => DynamicImage::ImageRgba8($action.into()),
                 }
         );
 
         ($dynimage: expr, ref mut $image: ident => $action: expr) => (
                 match $dynimage {
-                        DynamicImage::ImageLuma8(ref mut $image) => DynamicImage::ImageLuma8($action),
-                        DynamicImage::ImageLumaA8(ref mut $image) => DynamicImage::ImageLumaA8($action),
-                        DynamicImage::ImageRgb8(ref mut $image) => DynamicImage::ImageRgb8($action),
-                        DynamicImage::ImageRgba8(ref mut $image) => DynamicImage::ImageRgba8($action),
+                        DynamicImage::ImageLuma8(ref mut $image) => DynamicImage::ImageLuma8($action.into()),
+                        DynamicImage::ImageLumaA8(ref mut $image) => DynamicImage::ImageLumaA8($action.into()),
+                        DynamicImage::ImageRgb8(ref mut $image) => DynamicImage::ImageRgb8($action.into()),
+                        DynamicImage::ImageRgba8(ref mut $image) => DynamicImage::ImageRgba8($action.into()),
                 }
         );
 
@@ -90,26 +98,30 @@ macro_rules! dynamic_map(
         );
 );
 
+// The largest ratio of the sides of a pixel that
+// `resample_to_square_pixels` stretches an image by
+const MAX_PIXEL_STRETCH: u64 = 4;
+
 impl DynamicImage {
     /// Creates a dynamic image backed by a buffer of grey pixels.
     pub fn new_luma8(w: u32, h: u32) -> DynamicImage {
-        DynamicImage::ImageLuma8(ImageBuffer::new(w, h))
+        DynamicImage::ImageLuma8(GrayImage::new(w, h).into())
     }
 
     /// Creates a dynamic image backed by a buffer of grey
     /// pixels with transparency.
     pub fn new_luma_a8(w: u32, h: u32) -> DynamicImage {
-        DynamicImage::ImageLumaA8(ImageBuffer::new(w, h))
+        DynamicImage::ImageLumaA8(GrayAlphaImage::new(w, h).into())
     }
 
     /// Creates a dynamic image backed by a buffer of RGB pixels.
     pub fn new_rgb8(w: u32, h: u32) -> DynamicImage {
-        DynamicImage::ImageRgb8(ImageBuffer::new(w, h))
+        DynamicImage::ImageRgb8(RgbImage::new(w, h).into())
     }
 
     /// Creates a dynamic image backed by a buffer of RGBA pixels.
     pub fn new_rgba8(w: u32, h: u32) -> DynamicImage {
-        DynamicImage::ImageRgba8(ImageBuffer::new(w, h))
+        DynamicImage::ImageRgba8(RgbaImage::new(w, h).into())
     }
 
     /// Returns a copy of this image as an RGB image.
@@ -140,7 +152,33 @@ impl DynamicImage {
         })
     }
 
+    /// Returns this image composited over a checkerboard of ```light``` and
+    /// ```dark``` squares of ```checker_size``` pixels, the way viewers
+    /// present transparency. The top left square is light.
+    pub fn to_preview(&self, checker_size: u32, light: color::Rgb<u8>, dark: color::Rgb<u8>) -> RgbImage {
+        let size = cmp::max(checker_size, 1);
+        let (width, height) = self.dimensions();
+        let mut data = Vec::with_capacity(width as usize * height as usize * 3);
+
+        dynamic_map!(*self, ref p -> {
+            for (x, y, pixel) in p.enumerate_pixels() {
+                let rgba = pixel.to_rgba();
+                let background = if (x / size + y / size) % 2 == 0 { light } else { dark };
+                let alpha = rgba[3] as u32;
+
+                for c in (0..3) {
+                    let v = rgba[c] as u32 * alpha + background[c] as u32 * (255 - alpha);
+                    data.push(((v + 127) / 255) as u8);
+                }
+            }
+        });
+
+        ImageBuffer::from_raw(width, height, data).unwrap()
+    }
+
     /// Return a cut out of this image delimited by the bounding rectangle.
+    /// The rectangle is clamped to the image, thus the result may be smaller
+    /// than requested or even empty.
     pub fn crop(&mut self,
                 x: u32,
                 y: u32,
@@ -151,7 +189,7 @@ impl DynamicImage {
     }
 
     /// Return a reference to an 8bit RGB image
-    pub fn as_rgb8(&self) -> Option<&RgbImage> {
+    pub fn as_rgb8(&self) -> Option<&SharedRgbImage> {
         match *self {
             DynamicImage::ImageRgb8(ref p) => Some(p),
             _                              => None
@@ -159,7 +197,7 @@ impl DynamicImage {
     }
 
     /// Return a mutable reference to an 8bit RGB image
-    pub fn as_mut_rgb8(&mut self) -> Option<&mut RgbImage> {
+    pub fn as_mut_rgb8(&mut self) -> Option<&mut SharedRgbImage> {
         match *self {
             DynamicImage::ImageRgb8(ref mut p) => Some(p),
             _                                  => None
@@ -167,7 +205,7 @@ impl DynamicImage {
     }
 
     /// Return a reference to an 8bit RGBA image
-    pub fn as_rgba8(&self) -> Option<& RgbaImage> {
+    pub fn as_rgba8(&self) -> Option<&SharedRgbaImage> {
         match *self {
             DynamicImage::ImageRgba8(ref p) => Some(p),
             _                               => None
@@ -175,7 +213,7 @@ impl DynamicImage {
     }
 
     /// Return a mutable reference to an 8bit RGBA image
-    pub fn as_mut_rgba8(&mut self) -> Option<&mut RgbaImage> {
+    pub fn as_mut_rgba8(&mut self) -> Option<&mut SharedRgbaImage> {
         match *self {
             DynamicImage::ImageRgba8(ref mut p) => Some(p),
             _                                   => None
@@ -183,7 +221,7 @@ impl DynamicImage {
     }
 
     /// Return a reference to an 8bit Grayscale image
-    pub fn as_luma8(& self) -> Option<& GrayImage> {
+    pub fn as_luma8(& self) -> Option<&SharedGrayImage> {
         match *self {
             DynamicImage::ImageLuma8(ref p) => Some(p),
             _                               => None
@@ -191,7 +229,7 @@ impl DynamicImage {
     }
 
     /// Return a mutable reference to an 8bit Grayscale image
-    pub fn as_mut_luma8(&mut self) -> Option<&mut GrayImage> {
+    pub fn as_mut_luma8(&mut self) -> Option<&mut SharedGrayImage> {
         match *self {
             DynamicImage::ImageLuma8(ref mut p) => Some(p),
             _                                   => None
@@ -199,7 +237,7 @@ impl DynamicImage {
     }
 
     /// Return a reference to an 8bit Grayscale image with an alpha channel
-    pub fn as_luma_alpha8(&self) -> Option<& GrayAlphaImage> {
+    pub fn as_luma_alpha8(&self) -> Option<&SharedGrayAlphaImage> {
         match *self {
             DynamicImage::ImageLumaA8(ref p) => Some(p),
             _                                => None
@@ -207,7 +245,7 @@ impl DynamicImage {
     }
 
     /// Return a mutable reference to an 8bit Grayscale image with an alpha channel
-    pub fn as_mut_luma_alpha8(&mut self) -> Option<&mut GrayAlphaImage> {
+    pub fn as_mut_luma_alpha8(&mut self) -> Option<&mut SharedGrayAlphaImage> {
         match *self {
             DynamicImage::ImageLumaA8(ref mut p) => Some(p),
             _                                    => None
@@ -233,9 +271,9 @@ impl DynamicImage {
     pub fn grayscale(&self) -> DynamicImage {
         match *self {
             DynamicImage::ImageLuma8(ref p) => DynamicImage::ImageLuma8(p.clone()),
-            DynamicImage::ImageLumaA8(ref p) => DynamicImage::ImageLuma8(imageops::grayscale(p)),
-            DynamicImage::ImageRgb8(ref p) => DynamicImage::ImageLuma8(imageops::grayscale(p)),
-            DynamicImage::ImageRgba8(ref p) => DynamicImage::ImageLuma8(imageops::grayscale(p)),
+            DynamicImage::ImageLumaA8(ref p) => DynamicImage::ImageLuma8(imageops::grayscale(p).into()),
+            DynamicImage::ImageRgb8(ref p) => DynamicImage::ImageLuma8(imageops::grayscale(p).into()),
+            DynamicImage::ImageRgba8(ref p) => DynamicImage::ImageLuma8(imageops::grayscale(p).into()),
         }
     }
 
@@ -248,6 +286,10 @@ impl DynamicImage {
     /// Resize this image using the specified filter algorithm.
     /// Returns a new image. The image's aspect ratio is preserved.
     /// ```nwidth``` and ```nheight``` are the new image's dimensions
+    ///
+    /// Each side of the result is at least one pixel long, unless this
+    /// image or the requested size is empty, in which case the result is
+    /// empty as well.
     pub fn resize(&self,
                   nwidth: u32,
                   nheight: u32,
@@ -255,6 +297,10 @@ impl DynamicImage {
 
         let (width, height) = self.dimensions();
 
+        if width == 0 || height == 0 || nwidth == 0 || nheight == 0 {
+            return self.resize_exact(0, 0, filter)
+        }
+
         let ratio  = width as f32 / height as f32;
         let nratio = nwidth as f32 / nheight as f32;
 
@@ -264,8 +310,8 @@ impl DynamicImage {
             nwidth as f32 / width as f32
         };
 
-        let width2  = (width as f32 * scale) as u32;
-        let height2 = (height as f32 * scale) as u32;
+        let width2  = cmp::min(cmp::max(1, (width as f32 * scale) as u32), nwidth);
+        let height2 = cmp::min(cmp::max(1, (height as f32 * scale) as u32), nheight);
 
         self.resize_exact(width2, height2, filter)
     }
@@ -273,6 +319,8 @@ impl DynamicImage {
     /// Resize this image using the specified filter algorithm.
     /// Returns a new image. Does not preserve aspect ratio.
     /// ```nwidth``` and ```nheight``` are the new image's dimensions
+    ///
+    /// Resizing an empty image yields black, transparent pixels.
     pub fn resize_exact(&self,
                         nwidth: u32,
                         nheight: u32,
@@ -281,8 +329,111 @@ impl DynamicImage {
         dynamic_map!(*self, ref p => imageops::resize(p, nwidth, nheight, filter))
     }
 
+    /// Resamples this image, whose pixels have the width to height ratio
+    /// ```aspect``` as returned by ```ImageDecoder::pixel_aspect_ratio```,
+    /// to square pixels using the specified filter algorithm. The image is
+    /// stretched along the longer side of its pixels, thus no detail is
+    /// lost. Returns a copy if the pixels are square already.
+    ///
+    /// The ratio comes from the file and may be anything up to 65535:1, thus
+    /// it is limited to 4:1 and 1:4, which keeps the result within four
+    /// times the size of the image. Real pixels are rarely more than twice
+    /// as wide as high.
+    pub fn resample_to_square_pixels(&self, aspect: (u32, u32), filter: imageops::FilterType) -> DynamicImage {
+        let (width, height) = self.dimensions();
+        let (mut w, mut h) = (aspect.0 as u64, aspect.1 as u64);
+
+        if w == h || w == 0 || h == 0 {
+            return self.clone()
+        }
+
+        if w > MAX_PIXEL_STRETCH * h {
+            w = MAX_PIXEL_STRETCH;
+            h = 1;
+        } else if h > MAX_PIXEL_STRETCH * w {
+            w = 1;
+            h = MAX_PIXEL_STRETCH;
+        }
+
+        // Sizes that overflow are limited to the largest one possible
+        let stretch = |size: u32, num: u64, den: u64| cmp::min((size as u64 * num + den / 2) / den, u32::MAX as u64) as u32;
+
+        if w > h {
+            self.resize_exact(stretch(width, w, h), height, filter)
+        } else {
+            self.resize_exact(width, stretch(height, h, w), filter)
+        }
+    }
+
+    /// Returns the average color of this image, for example as the
+    /// background of a page while the image loads. The color channels are
+    /// weighted by alpha, thus transparent pixels do not tint the result.
+    /// An empty image gives a transparent black.
+    pub fn average_color(&self) -> color::Rgba<u8> {
+        let mut sums = [0u64; 4];
+
+        dynamic_map!(*self, ref p -> {
+            for pixel in p.pixels() {
+                let rgba = pixel.to_rgba();
+                let alpha = rgba[3] as u64;
+
+                for c in (0..3) {
+                    sums[c] += rgba[c] as u64 * alpha;
+                }
+                sums[3] += alpha;
+            }
+        });
+
+        let (width, height) = self.dimensions();
+        let count = width as u64 * height as u64;
+
+        if sums[3] == 0 {
+            return color::Rgba([0, 0, 0, 0])
+        }
+
+        let mean = |c: usize| ((sums[c] + sums[3] / 2) / sums[3]) as u8;
+        color::Rgba([mean(0), mean(1), mean(2), ((sums[3] + count / 2) / count) as u8])
+    }
+
+    /// Returns the samples of this image as a tensor of 32 bit floats, as
+    /// the input of a neural network. The image is converted to luma, RGB
+    /// or RGBA as ```normalization``` has 1, 3 or 4 channels, and each
+    /// sample is scaled to 0 to 1, then shifted by the mean and divided by
+    /// the standard deviation of its channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if ```normalization``` does not have 1, 3 or 4 channels, or
+    /// a different number of means and standard deviations.
+    pub fn to_normalized_f32(&self, normalization: &Normalization) -> Vec<f32> {
+        let channels = normalization.mean.len();
+        let samples = match channels {
+            1 => self.to_luma().into_raw(),
+            3 => self.to_rgb().into_raw(),
+            4 => self.to_rgba().into_raw(),
+            _ => panic!("a tensor needs 1, 3 or 4 channels, not {}", channels)
+        };
+
+        tensor::normalize(&samples, channels, normalization)
+    }
+
+    /// Returns a copy of this image that fits into ```max_dim``` by
+    /// ```max_dim``` pixels, keeping the aspect ratio, as a placeholder to
+    /// be blurred and scaled up while the image loads. Sizes up to 16
+    /// pixels suit such placeholders. Smaller images are not enlarged.
+    pub fn tiny_preview(&self, max_dim: u32) -> DynamicImage {
+        let (width, height) = self.dimensions();
+
+        if width <= max_dim && height <= max_dim {
+            return self.clone()
+        }
+
+        self.resize(max_dim, max_dim, imageops::FilterType::Triangle)
+    }
+
     /// Performs a Gaussian blur on this image.
-    /// ```sigma``` is a measure of how much to blur by.
+    /// ```sigma``` is a measure of how much to blur by, a
+    /// ```sigma``` of 0 returns an unchanged copy.
     pub fn blur(&self, sigma: f32) -> DynamicImage {
         dynamic_map!(*self, ref p => imageops::blur(p, sigma))
     }
@@ -344,51 +495,11 @@ impl DynamicImage {
     }
 
     /// Encode this image and write it to ```w```
+    /// Images without pixels can not be encoded and are rejected with a
+    /// ```DimensionError```.
     pub fn save<W: Write>(&self, w: &mut W, format: ImageFormat) -> ImageResult<()> {
-        let bytes = self.raw_pixels();
         let (width, height) = self.dimensions();
-        let color = self.color();
-
-        match format {
-            #[cfg(feature = "png_codec")]
-            image::ImageFormat::PNG  => {
-                let p = png::PNGEncoder::new(w);
-
-                try!(p.encode(&bytes, width, height, color));
-                Ok(())
-            }
-            #[cfg(feature = "ppm")]
-            image::ImageFormat::PPM  => {
-                let mut p = ppm::PPMEncoder::new(w);
-
-                try!(p.encode(&bytes, width, height, color));
-                Ok(())
-            }
-
-            #[cfg(feature = "jpeg")]
-            image::ImageFormat::JPEG => {
-                let mut j = jpeg::JPEGEncoder::new(w);
-
-                try!(j.encode(&bytes, width, height, color));
-                Ok(())
-            }
-
-            #[cfg(feature = "gif_codec")]
-            image::ImageFormat::GIF => {
-                let g = gif::Encoder::new(w);
-
-                try!(g.encode(gif::Frame::from_rgba(
-                    width as u16,
-                    height as u16,
-                    &mut *self.to_rgba().iter().cloned().collect::<Vec<u8>>()
-                )));
-                Ok(())
-            }
-
-            _ => Err(image::ImageError::UnsupportedError(
-                     format!("An encoder for {:?} is not available.", format))
-                 ),
-        }
+        write_buffer(w, &self.raw_pixels(), width, height, self.color(), format)
     }
 }
 
@@ -433,6 +544,62 @@ impl GenericImage for DynamicImage {
 }
 
 
+/// Encodes the ```width``` by ```height``` pixels ```buf``` of color type
+/// ```color``` in ```format``` and writes them to ```w```
+pub fn write_buffer<W: Write>(w: &mut W, buf: &[u8], width: u32, height: u32,
+                              color: color::ColorType, format: ImageFormat) -> ImageResult<()> {
+    if width == 0 || height == 0 {
+        return Err(image::ImageError::DimensionError)
+    }
+
+    let row_len = (color::bits_per_pixel(color) * width as usize + 7) / 8;
+    if row_len.checked_mul(height as usize).map_or(true, |len| buf.len() < len) {
+        return Err(image::ImageError::DimensionError)
+    }
+
+    match format {
+        #[cfg(feature = "png_codec")]
+        image::ImageFormat::PNG  => {
+            let p = png::PNGEncoder::new(w);
+
+            try!(p.encode(buf, width, height, color));
+            Ok(())
+        }
+        #[cfg(feature = "ppm")]
+        image::ImageFormat::PPM  => {
+            let mut p = ppm::PPMEncoder::new(w);
+
+            try!(p.encode(buf, width, height, color));
+            Ok(())
+        }
+
+        #[cfg(feature = "jpeg")]
+        image::ImageFormat::JPEG => {
+            let mut j = jpeg::JPEGEncoder::new(w);
+
+            try!(j.encode(buf, width, height, color));
+            Ok(())
+        }
+
+        #[cfg(feature = "gif_codec")]
+        image::ImageFormat::GIF => {
+            let g = gif::Encoder::new(w);
+
+            let image = try!(decoded_to_image(color, width, height, U8(buf.to_vec())));
+            try!(g.encode(gif::Frame::from_rgba(
+                width as u16,
+                height as u16,
+                &mut *image.to_rgba().into_raw()
+            )));
+            Ok(())
+        }
+
+        _ => Err(image::ImageError::UnsupportedError(
+                 format!("An encoder for {:?} is not available.", format))
+             ),
+    }
+}
+
 /// Decodes an image and stores it into a dynamic image
 pub fn decoder_to_image<I: ImageDecoder>(codec: I) -> ImageResult<DynamicImage> {
     let mut codec = codec;
@@ -441,21 +608,27 @@ pub fn decoder_to_image<I: ImageDecoder>(codec: I) -> ImageResult<DynamicImage>
     let buf    = try!(codec.read_image());
     let (w, h) = try!(codec.dimensions());
 
+    decoded_to_image(color, w, h, buf)
+}
+
+/// Stores the decoded pixels ```buf``` of a ```w``` by ```h``` image of
+/// color type ```color``` into a dynamic image
+pub fn decoded_to_image(color: color::ColorType, w: u32, h: u32, buf: DecodingResult) -> ImageResult<DynamicImage> {
     let image = match (color, buf) {
         (color::ColorType::RGB(8), U8(buf)) => {
-            ImageBuffer::from_raw(w, h, buf).map(|v| DynamicImage::ImageRgb8(v))
+            ImageBuffer::from_raw(w, h, buf.into()).map(|v| DynamicImage::ImageRgb8(v))
         }
 
         (color::ColorType::RGBA(8), U8(buf)) => {
-            ImageBuffer::from_raw(w, h, buf).map(|v| DynamicImage::ImageRgba8(v))
+            ImageBuffer::from_raw(w, h, buf.into()).map(|v| DynamicImage::ImageRgba8(v))
         }
 
         (color::ColorType::Gray(8), U8(buf)) => {
-            ImageBuffer::from_raw(w, h, buf).map(|v| DynamicImage::ImageLuma8(v))
+            ImageBuffer::from_raw(w, h, buf.into()).map(|v| DynamicImage::ImageLuma8(v))
         }
 
         (color::ColorType::GrayA(8), U8(buf)) => {
-            ImageBuffer::from_raw(w, h, buf).map(|v| DynamicImage::ImageLumaA8(v))
+            ImageBuffer::from_raw(w, h, buf.into()).map(|v| DynamicImage::ImageLumaA8(v))
         }
         (color::ColorType::Gray(bit_depth), U8(ref buf)) if bit_depth == 1 || bit_depth == 2 || bit_depth == 4 => {
             // Note: this conversion assumes that the scanlines begin on byte boundaries
@@ -477,7 +650,7 @@ pub fn decoder_to_image<I: ImageDecoder>(codec: I) -> ImageResult<DynamicImage>
                        )
                        .map(|pixel| pixel * scaling_factor)
                        .collect();
-            ImageBuffer::from_raw(w, h, p).map(|buf| DynamicImage::ImageLuma8(buf))
+            ImageBuffer::from_raw(w, h, SharedBuffer::new(p)).map(|buf| DynamicImage::ImageLuma8(buf))
         },
         _ => return Err(image::ImageError::UnsupportedColor(color))
     };
@@ -605,11 +778,16 @@ static MAGIC_BYTES: [(&'static [u8], ImageFormat); 7] = [
 /// Makes an educated guess about the image format.
 /// TGA is not supported by this function.
 pub fn load_from_memory(buffer: &[u8]) -> ImageResult<DynamicImage> {
-    let max_len = MAGIC_BYTES.iter().map(|v| v.0.len()).max().unwrap_or(0);
-    let beginning = &buffer[..max_len];
+    let format = try!(guess_format(buffer));
+    load_from_memory_with_format(buffer, format)
+}
+
+/// Guesses the format of the image file ```buffer``` from its first bytes.
+/// TGA is not supported by this function.
+pub fn guess_format(buffer: &[u8]) -> ImageResult<ImageFormat> {
     for &(signature, format) in MAGIC_BYTES.iter() {
-        if beginning.starts_with(signature) {
-            return load_from_memory_with_format(buffer, format)
+        if buffer.starts_with(signature) {
+            return Ok(format)
         }
     }
     Err(image::ImageError::UnsupportedError(
@@ -631,10 +809,123 @@ mod bench {
 
     #[bench]
     fn bench_conversion(b: &mut test::Bencher) {
-        let a = super::DynamicImage::ImageRgb8(::ImageBuffer::new(1000, 1000));
+        let a = super::DynamicImage::new_rgb8(1000, 1000);
         b.iter(|| {
             a.to_luma()
         });
         b.bytes = 1000*1000*3
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DynamicImage;
+    use color::{Rgb, Rgba};
+    use image::{GenericImage, ImageError, ImageFormat};
+    use imageops::FilterType;
+    use tensor::{Normalization, TensorLayout};
+
+    #[test]
+    fn test_tiny_images() {
+        for &(w, h) in [(0, 0), (0, 5), (1, 1), (1, 5), (5, 1)].iter() {
+            let mut img = DynamicImage::new_rgb8(w, h);
+
+            assert_eq!(img.fliph().dimensions(), (w, h));
+            assert_eq!(img.flipv().dimensions(), (w, h));
+            assert_eq!(img.rotate90().dimensions(), (h, w));
+            assert_eq!(img.crop(1, 1, 3, 3).dimensions(), (w.saturating_sub(1).min(3), h.saturating_sub(1).min(3)));
+            assert_eq!(img.unsharpen(1.0, 1).dimensions(), (w, h));
+        }
+
+        let img = DynamicImage::new_rgb8(1, 5);
+        assert_eq!(img.resize(3, 3, FilterType::Triangle).dimensions(), (1, 3));
+        assert_eq!(img.resize(0, 3, FilterType::Triangle).dimensions(), (0, 0));
+        assert_eq!(DynamicImage::new_rgb8(0, 5).resize(3, 3, FilterType::Triangle).dimensions(), (0, 0));
+    }
+
+    #[test]
+    fn test_resample_to_square_pixels() {
+        let img = DynamicImage::new_rgb8(10, 6);
+
+        assert_eq!(img.resample_to_square_pixels((2, 1), FilterType::Triangle).dimensions(), (20, 6));
+        assert_eq!(img.resample_to_square_pixels((1, 2), FilterType::Triangle).dimensions(), (10, 12));
+        assert_eq!(img.resample_to_square_pixels((4, 3), FilterType::Triangle).dimensions(), (13, 6));
+        assert_eq!(img.resample_to_square_pixels((1, 1), FilterType::Triangle).dimensions(), (10, 6));
+
+        // Ratios from untrusted headers are limited
+        assert_eq!(img.resample_to_square_pixels((65535, 1), FilterType::Triangle).dimensions(), (40, 6));
+        assert_eq!(img.resample_to_square_pixels((1, 65535), FilterType::Triangle).dimensions(), (10, 24));
+        assert_eq!(img.resample_to_square_pixels((4, 1), FilterType::Triangle).dimensions(), (40, 6));
+    }
+
+    #[test]
+    fn test_to_normalized_f32() {
+        let mut img = DynamicImage::new_rgb8(2, 1);
+        img.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+
+        let gray = Normalization { mean: vec![0.5], std: vec![0.5], layout: TensorLayout::Chw };
+        assert_eq!(img.to_normalized_f32(&gray), vec![-1.0, 1.0]);
+
+        let tensor = img.to_normalized_f32(&Normalization::imagenet(TensorLayout::Chw));
+        assert_eq!(tensor.len(), 2 * 3);
+        assert!((tensor[0] + 0.485 / 0.229).abs() < 1e-5);
+        assert!((tensor[5] - (1.0 - 0.406) / 0.225).abs() < 1e-5);
+
+        let empty = DynamicImage::new_rgb8(0, 5);
+        assert!(empty.to_normalized_f32(&Normalization::imagenet(TensorLayout::Chw)).is_empty());
+    }
+
+    #[test]
+    fn test_average_color() {
+        let mut img = DynamicImage::new_rgba8(4, 1);
+        img.put_pixel(0, 0, Rgba([200, 0, 0, 255]));
+        img.put_pixel(1, 0, Rgba([0, 100, 0, 255]));
+        img.put_pixel(2, 0, Rgba([0, 0, 255, 0]));
+        assert_eq!(img.average_color(), Rgba([100, 50, 0, 128]));
+
+        assert_eq!(DynamicImage::new_luma8(3, 3).average_color(), Rgba([0, 0, 0, 255]));
+        assert_eq!(DynamicImage::new_rgb8(0, 0).average_color(), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_tiny_preview() {
+        let img = DynamicImage::new_rgb8(400, 100);
+        assert_eq!(img.tiny_preview(16).dimensions(), (16, 4));
+        assert_eq!(DynamicImage::new_rgb8(10, 12).tiny_preview(16).dimensions(), (10, 12));
+        assert_eq!(DynamicImage::new_rgb8(1000, 10).tiny_preview(8).dimensions(), (8, 1));
+    }
+
+    #[test]
+    fn test_to_preview() {
+        let (light, dark) = (Rgb([200, 200, 200]), Rgb([100, 100, 100]));
+
+        let mut img = DynamicImage::new_rgba8(5, 3);
+        img.put_pixel(2, 0, Rgba([255, 0, 0, 255]));
+        img.put_pixel(3, 0, Rgba([255, 0, 0, 128]));
+
+        let preview = img.to_preview(2, light, dark);
+        assert_eq!(preview.dimensions(), (5, 3));
+        assert_eq!(preview.get_pixel(0, 0), &light);
+        assert_eq!(preview.get_pixel(1, 1), &light);
+        assert_eq!(preview.get_pixel(0, 2), &dark);
+        assert_eq!(preview.get_pixel(2, 2), &light);
+        assert_eq!(preview.get_pixel(4, 2), &dark);
+        assert_eq!(preview.get_pixel(2, 0), &Rgb([255, 0, 0]));
+        assert_eq!(preview.get_pixel(3, 0), &Rgb([178, 50, 50]));
+
+        // Opaque images are unchanged
+        let rgb = DynamicImage::new_rgb8(4, 4);
+        assert!(rgb.to_preview(1, light, dark).pixels().all(|p| p == &Rgb([0, 0, 0])));
+    }
+
+    #[test]
+    #[cfg(feature = "jpeg")]
+    fn test_save_empty() {
+        let img = DynamicImage::new_rgb8(0, 5);
+
+        match img.save(&mut Vec::new(), ImageFormat::JPEG) {
+            Err(ImageError::DimensionError) => (),
+            result => panic!("unexpected result {:?}", result)
+        }
+    }
+}
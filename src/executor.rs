@@ -0,0 +1,186 @@
+//! Control over the threads used by parallel codec paths
+//!
+//! Codecs that can split their work, like the JPEG encoder, hand it to an
+//! `Executor` as independent jobs. By default the jobs run on threads of the
+//! standard library, but applications can provide their own implementation
+//! to run them on an existing thread pool or async runtime.
+//!
+//! An executor only ever receives `'static` jobs, which it may queue and run
+//! whenever it likes. The codecs use `scope` to run jobs that borrow their
+//! buffers: it blocks until every job has finished, which is what allows
+//! the borrows.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use image::executor::{Executor, Job};
+//!
+//! // Runs every job on a new thread, named for debugging
+//! struct Named;
+//!
+//! impl Executor for Named {
+//!     fn spawn(&self, job: Job<'static>) {
+//!         std::thread::Builder::new().name("codec".to_string())
+//!                                    .spawn(move || job()).unwrap();
+//!     }
+//! }
+//!
+//! let mut out = Vec::new();
+//! let mut encoder = image::jpeg::JPEGEncoder::new(&mut out);
+//! encoder.set_threads(4);
+//! encoder.set_executor(Arc::new(Named));
+//! ```
+
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// A unit of work handed to an `Executor`
+pub type Job<'a> = Box<FnOnce() + Send + 'a>;
+
+/// Runs jobs on behalf of the codecs
+pub trait Executor: Send + Sync {
+    /// Runs `job`, either right away on the calling thread or later on
+    /// another thread.
+    ///
+    /// Codecs block until the jobs they spawned have finished, thus a job
+    /// must eventually run even while the thread that spawned it waits. An
+    /// executor driven only by the spawning thread, like a single-threaded
+    /// async runtime, has to run the job before returning.
+    fn spawn(&self, job: Job<'static>);
+}
+
+/// Runs every job on the calling thread, one after another
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Sequential;
+
+impl Executor for Sequential {
+    fn spawn(&self, job: Job<'static>) {
+        job();
+    }
+}
+
+/// Runs every job on its own thread of the standard library
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdThreads;
+
+impl Executor for StdThreads {
+    fn spawn(&self, job: Job<'static>) {
+        thread::spawn(move || job());
+    }
+}
+
+// The number of jobs of a `scope` that have not finished yet, and whether
+// one of them panicked or was dropped without running
+struct Pending {
+    count: Mutex<(usize, bool)>,
+    finished: Condvar,
+}
+
+// Marks a job of a `scope` as finished when dropped, which also happens if
+// the job panics or the executor drops it without running it
+struct Finish {
+    pending: Arc<Pending>,
+    completed: bool,
+}
+
+impl Finish {
+    fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        let mut count = self.pending.count.lock().unwrap_or_else(|e| e.into_inner());
+        count.0 -= 1;
+        count.1 |= !self.completed;
+        self.pending.finished.notify_all();
+    }
+}
+
+// Waits for the jobs of a `scope`, also while unwinding from a panic of the
+// executor, as the jobs may still borrow from the caller
+struct Wait(Arc<Pending>);
+
+impl Drop for Wait {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock().unwrap_or_else(|e| e.into_inner());
+        while count.0 > 0 {
+            count = self.0.finished.wait(count).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// Runs all `jobs` on `executor` and returns once every job has finished.
+/// Unlike the jobs of ```Executor::spawn```, these may borrow data from the
+/// caller.
+///
+/// # Panics
+///
+/// Panics if a job panicked, or if the executor dropped a job without
+/// running it.
+pub fn scope<'a>(executor: &Executor, jobs: Vec<Job<'a>>) {
+    let pending = Arc::new(Pending {
+        count: Mutex::new((0, false)),
+        finished: Condvar::new(),
+    });
+    let wait = Wait(pending.clone());
+
+    for job in jobs.into_iter() {
+        pending.count.lock().unwrap().0 += 1;
+
+        let finish = Finish { pending: pending.clone(), completed: false };
+        let job: Job<'a> = Box::new(move || {
+            job();
+            finish.complete();
+        });
+
+        // `wait` keeps this function from returning, or unwinding past the
+        // caller, before the job has run or been dropped
+        let job: Job<'static> = unsafe { mem::transmute(job) };
+        executor.spawn(job);
+    }
+
+    drop(wait);
+
+    if pending.count.lock().unwrap().1 {
+        panic!("a job of the executor panicked or did not run")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::{scope, Executor, Job, Sequential, StdThreads};
+
+    // Drops every job without running it
+    struct Forgetful;
+
+    impl Executor for Forgetful {
+        fn spawn(&self, _: Job<'static>) {}
+    }
+
+    #[test]
+    fn test_scope() {
+        let executors: [&Executor; 2] = [&Sequential, &StdThreads];
+
+        for executor in executors.iter() {
+            let mut sums = vec![0; 4];
+            let data = (0..100).collect::<Vec<u32>>();
+
+            scope(*executor, sums.iter_mut().zip(data.chunks(25)).map(|(sum, chunk)| {
+                let job: Job = Box::new(move || *sum = chunk.iter().sum());
+                job
+            }).collect());
+
+            assert_eq!(sums, vec![300, 925, 1550, 2175]);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_scope_dropped_job() {
+        let ran = Mutex::new(false);
+        scope(&Forgetful, vec![Box::new(|| *ran.lock().unwrap() = true)]);
+    }
+}
@@ -7,12 +7,14 @@
 //!
 extern crate gif;
 
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub use self::gif::Frame;
 use self::gif::{SetParameter, ColorOutput};
 
-use image::{ImageError, ImageResult, DecodingResult, ImageDecoder};
+use image::{self, ImageError, ImageResult, DecodingResult, ImageDecoder};
 use color;
 
 enum Either<T, U> {
@@ -20,23 +22,52 @@ enum Either<T, U> {
     Right(U)
 }
 
+// The offset of the pixel aspect ratio in the logical screen descriptor
+const ASPECT_OFFSET: u64 = 12;
+
+// Passes the stream on to the GIF decoder, which does not keep the pixel
+// aspect ratio, and records it on the way
+struct AspectReader<R> {
+    inner: R,
+    pos: u64,
+    // The aspect ratio byte plus one, 0 until it has been read
+    aspect: Arc<AtomicUsize>,
+}
+
+impl<R: Read> Read for AspectReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+
+        if self.pos <= ASPECT_OFFSET && ASPECT_OFFSET < self.pos + n as u64 {
+            let byte = buf[(ASPECT_OFFSET - self.pos) as usize];
+            self.aspect.store(byte as usize + 1, Ordering::Relaxed);
+        }
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
 /// GIF decoder
 pub struct Decoder<R: Read> {
-    inner: Option<Either<gif::Decoder<R>, gif::Reader<R>>>
+    inner: Option<Either<gif::Decoder<AspectReader<R>>, gif::Reader<AspectReader<R>>>>,
+    aspect: Arc<AtomicUsize>,
 }
 
 impl<R: Read> Decoder<R> {
     /// Creates a new decoder that decodes the input steam ```r```
     pub fn new(r: R) -> Decoder<R> {
-        let mut decoder = gif::Decoder::new(r);
+        let aspect = Arc::new(AtomicUsize::new(0));
+        let mut decoder = gif::Decoder::new(AspectReader { inner: r, pos: 0, aspect: aspect.clone() });
         decoder.set(ColorOutput::RGBA);
         Decoder {
-            inner: Some(Either::Left(decoder))
+            inner: Some(Either::Left(decoder)),
+            aspect: aspect,
         }
     }
 
     // Converts the inner decoder to a reader
-    fn get_reader(&mut self) -> Result<&mut gif::Reader<R>, gif::DecodingError> {
+    fn get_reader(&mut self) -> Result<&mut gif::Reader<AspectReader<R>>, gif::DecodingError> {
         let inner = self.inner.take().unwrap();
         self.inner = Some(match inner {
             Either::Left(decoder) => {
@@ -63,6 +94,16 @@ impl<R: Read> ImageDecoder for Decoder<R> {
         Ok(color::ColorType::RGBA(8))
     }
 
+    // A byte `b` other than 0 stands for a ratio of `(b + 15) / 64`
+    fn pixel_aspect_ratio(&mut self) -> ImageResult<(u32, u32)> {
+        let _ = try!(self.get_reader());
+
+        Ok(match self.aspect.load(Ordering::Relaxed) {
+            0 | 1 => (1, 1),
+            a => image::reduce_aspect_ratio(a as u32 - 1 + 15, 64)
+        })
+    }
+
     fn row_len(&mut self) -> ImageResult<usize> {
         let reader = try!(self.get_reader());
         Ok(reader.line_length())
@@ -117,4 +158,54 @@ impl From<gif::DecodingError> for ImageError {
             Io(io_err) => ImageError::IoError(io_err),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use image::ImageDecoder;
+    use super::Decoder;
+
+    // A GIF of one black pixel with the pixel aspect ratio byte `aspect`
+    fn gif(aspect: u8) -> Vec<u8> {
+        let mut data = b"GIF89a\x01\x00\x01\x00\x80\x00".to_vec();
+        data.push(aspect);
+        data.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        data.extend_from_slice(b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3b");
+        data
+    }
+
+    // Hands out the stream one byte at a time
+    struct Bytes<'a>(&'a [u8]);
+
+    impl<'a> Read for Bytes<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0)
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_pixel_aspect_ratio() {
+        // A byte `b` stands for (b + 15) / 64, 0 for no ratio
+        for &(byte, aspect) in [(0, (1, 1)), (49, (1, 1)), (17, (1, 2)), (113, (2, 1)), (255, (135, 32))].iter() {
+            let data = gif(byte);
+            assert_eq!(Decoder::new(&data[..]).pixel_aspect_ratio().unwrap(), aspect);
+            assert_eq!(Decoder::new(Bytes(&data)).pixel_aspect_ratio().unwrap(), aspect);
+        }
+
+        // The ratio does not disturb decoding
+        let data = gif(17);
+        let mut decoder = Decoder::new(&data[..]);
+        assert_eq!(decoder.pixel_aspect_ratio().unwrap(), (1, 2));
+        assert_eq!(decoder.dimensions().unwrap(), (1, 1));
+        match decoder.read_image().unwrap() {
+            ::image::DecodingResult::U8(pixels) => assert_eq!(pixels, vec![0, 0, 0, 255]),
+            _ => panic!("8 bit samples expected")
+        }
+    }
+}
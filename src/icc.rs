@@ -0,0 +1,790 @@
+//! ICC color profiles and soft-proofing
+//!
+//! Supports matrix/TRC profiles (RGB and gray) and ```lut8```/```lut16```
+//! based profiles, which covers most display and printer profiles.
+//! Colors are exchanged in the D50 XYZ profile connection space.
+//!
+//! See http://www.color.org/specification/ICC1v43_2010-12.pdf
+
+use buffer::{ImageBuffer, Pixel, RgbaImage};
+use color::Rgba;
+use image::{GenericImage, ImageError, ImageResult};
+use math::utils::clamp;
+
+/// The D50 white point of the profile connection space
+const D50: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+/// The rendering intent of a color transform
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Intent {
+    /// Compresses the whole source gamut into the destination gamut,
+    /// keeping the relation between colors
+    Perceptual,
+
+    /// Reproduces in-gamut colors exactly, relative to the media white,
+    /// and clips the others
+    RelativeColorimetric,
+}
+
+/// A tone reproduction curve, mapping device values in [0, 1] to linear light
+#[derive(Clone, Debug, PartialEq)]
+enum Curve {
+    Gamma(f32),
+    Table(Vec<f32>),
+    /// The parameters g, a, b, c, d, e, f of a ```para``` curve
+    Parametric([f32; 7]),
+}
+
+/// A ```lut8``` or ```lut16``` transform
+#[derive(Clone, Debug, PartialEq)]
+struct Lut {
+    inputs: usize,
+    outputs: usize,
+    grid: usize,
+    matrix: [f32; 9],
+    input_curves: Vec<Vec<f32>>,
+    clut: Vec<f32>,
+    output_curves: Vec<Vec<f32>>,
+    /// Whether Lab values use the legacy 16 bit encoding with L* = 100 at 0xFF00
+    legacy_lab: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Transform {
+    /// Columns of the red, green and blue colorants and their curves
+    Matrix([f32; 9], [Curve; 3]),
+    Gray(Curve),
+    Lut {
+        a2b: [Option<Lut>; 2],
+        b2a: [Option<Lut>; 2],
+    },
+}
+
+/// A parsed ICC profile
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+    channels: usize,
+    lab_pcs: bool,
+    transform: Transform,
+}
+
+fn u16_at(data: &[u8], pos: usize) -> ImageResult<u16> {
+    if pos + 2 > data.len() {
+        return Err(ImageError::NotEnoughData)
+    }
+    Ok((data[pos] as u16) << 8 | data[pos + 1] as u16)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> ImageResult<u32> {
+    if pos + 4 > data.len() {
+        return Err(ImageError::NotEnoughData)
+    }
+    Ok((data[pos] as u32) << 24 | (data[pos + 1] as u32) << 16
+        | (data[pos + 2] as u32) << 8 | data[pos + 3] as u32)
+}
+
+fn s15f16_at(data: &[u8], pos: usize) -> ImageResult<f32> {
+    Ok(try!(u32_at(data, pos)) as i32 as f32 / 65536.0)
+}
+
+fn sig(s: &[u8; 4]) -> u32 {
+    (s[0] as u32) << 24 | (s[1] as u32) << 16 | (s[2] as u32) << 8 | s[3] as u32
+}
+
+/// Linearly interpolates the table at ```v``` in [0, 1]
+fn lookup(table: &[f32], v: f32) -> f32 {
+    match table.len() {
+        0 => v,
+        1 => table[0],
+        n => {
+            let pos = clamp(v, 0.0, 1.0) * (n - 1) as f32;
+            let i = (pos as usize).min(n - 2);
+            let t = pos - i as f32;
+            table[i] * (1.0 - t) + table[i + 1] * t
+        }
+    }
+}
+
+/// Finds the ```v``` with ```lookup(table, v) == y``` in a monotonic table
+fn inverse_lookup(table: &[f32], y: f32) -> f32 {
+    let n = table.len();
+    if n < 2 {
+        return y
+    }
+    let ascending = table[n - 1] >= table[0];
+    let (mut lo, mut hi) = (0.0f32, 1.0f32);
+    for _ in 0..24 {
+        let mid = (lo + hi) / 2.0;
+        if (lookup(table, mid) < y) == ascending {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+impl Curve {
+    fn eval(&self, v: f32) -> f32 {
+        let v = clamp(v, 0.0, 1.0);
+        match *self {
+            Curve::Gamma(g) => v.powf(g),
+            Curve::Table(ref t) => lookup(t, v),
+            Curve::Parametric([g, a, b, c, d, e, f]) => {
+                if v >= d {
+                    (a * v + b).max(0.0).powf(g) + e
+                } else {
+                    c * v + f
+                }
+            }
+        }
+    }
+
+    fn eval_inverse(&self, y: f32) -> f32 {
+        let y = clamp(y, 0.0, 1.0);
+        match *self {
+            Curve::Gamma(g) => y.powf(1.0 / g),
+            Curve::Table(ref t) => inverse_lookup(t, y),
+            Curve::Parametric([g, a, b, c, d, e, f]) => {
+                let knee = c * d + f;
+                if y >= knee && a != 0.0 {
+                    clamp(((y - e).max(0.0).powf(1.0 / g) - b) / a, 0.0, 1.0)
+                } else if c != 0.0 {
+                    clamp((y - f) / c, 0.0, 1.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    fn parse(data: &[u8]) -> ImageResult<Curve> {
+        let kind = try!(u32_at(data, 0));
+        if kind == sig(b"curv") {
+            let count = try!(u32_at(data, 8)) as usize;
+            match count {
+                0 => Ok(Curve::Gamma(1.0)),
+                1 => Ok(Curve::Gamma(try!(u16_at(data, 12)) as f32 / 256.0)),
+                _ => {
+                    if count > data.len().saturating_sub(12) / 2 {
+                        return Err(ImageError::NotEnoughData)
+                    }
+                    let mut table = Vec::with_capacity(count);
+                    for i in 0..count {
+                        table.push(try!(u16_at(data, 12 + 2 * i)) as f32 / 65535.0);
+                    }
+                    Ok(Curve::Table(table))
+                }
+            }
+        } else if kind == sig(b"para") {
+            let function = try!(u16_at(data, 8));
+            let nparams = match function {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return Err(ImageError::UnsupportedError(
+                    format!("Parametric curve type {}", function)
+                ))
+            };
+            let mut p = [0.0f32; 7];
+            for i in 0..nparams {
+                p[i] = try!(s15f16_at(data, 12 + 4 * i));
+            }
+            let [g, a, b, c, d, e, f] = p;
+            // Bring every function into the form of type 4
+            Ok(Curve::Parametric(match function {
+                0 => [g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                1 => [g, a, b, 0.0, -b / a, 0.0, 0.0],
+                2 => [g, a, b, 0.0, -b / a, c, c],
+                3 => [g, a, b, c, d, 0.0, 0.0],
+                _ => [g, a, b, c, d, e, f],
+            }))
+        } else {
+            Err(ImageError::FormatError("Unknown curve type".to_string()))
+        }
+    }
+}
+
+impl Lut {
+    fn parse(data: &[u8], lab_pcs: bool) -> ImageResult<Lut> {
+        let kind = try!(u32_at(data, 0));
+        let wide = if kind == sig(b"mft2") {
+            true
+        } else if kind == sig(b"mft1") {
+            false
+        } else {
+            return Err(ImageError::UnsupportedError(
+                "Only lut8 and lut16 transforms are supported".to_string()
+            ))
+        };
+        if data.len() < 52 {
+            return Err(ImageError::NotEnoughData)
+        }
+        let inputs = data[8] as usize;
+        let outputs = data[9] as usize;
+        let grid = data[10] as usize;
+        if inputs == 0 || inputs > 4 || outputs == 0 || outputs > 4 || grid < 2 {
+            return Err(ImageError::FormatError("Invalid lut dimensions".to_string()))
+        }
+
+        let mut matrix = [0.0f32; 9];
+        for (i, m) in matrix.iter_mut().enumerate() {
+            *m = try!(s15f16_at(data, 12 + 4 * i));
+        }
+
+        let (in_entries, out_entries, mut pos) = if wide {
+            (try!(u16_at(data, 48)) as usize, try!(u16_at(data, 50)) as usize, 52)
+        } else {
+            (256, 256, 48)
+        };
+        let mut read = |count: usize| -> ImageResult<Vec<f32>> {
+            let width = if wide { 2 } else { 1 };
+            if count > (data.len() - pos) / width {
+                return Err(ImageError::NotEnoughData)
+            }
+            let mut values = Vec::with_capacity(count);
+            for _ in 0..count {
+                if wide {
+                    values.push(try!(u16_at(data, pos)) as f32 / 65535.0);
+                } else {
+                    values.push(data[pos] as f32 / 255.0);
+                }
+                pos += width;
+            }
+            Ok(values)
+        };
+
+        let mut input_curves = Vec::with_capacity(inputs);
+        for _ in 0..inputs {
+            input_curves.push(try!(read(in_entries)));
+        }
+        let entries = match grid.checked_pow(inputs as u32).and_then(|n| n.checked_mul(outputs)) {
+            Some(entries) => entries,
+            None => return Err(ImageError::FormatError("Lut too large".to_string())),
+        };
+        let clut = try!(read(entries));
+        let mut output_curves = Vec::with_capacity(outputs);
+        for _ in 0..outputs {
+            output_curves.push(try!(read(out_entries)));
+        }
+
+        Ok(Lut {
+            inputs: inputs,
+            outputs: outputs,
+            grid: grid,
+            matrix: matrix,
+            input_curves: input_curves,
+            clut: clut,
+            output_curves: output_curves,
+            legacy_lab: wide && lab_pcs,
+        })
+    }
+
+    /// Multilinear interpolation of the color lookup table
+    fn interpolate(&self, input: &[f32]) -> [f32; 4] {
+        let mut base = 0;
+        let mut fractions = [0.0f32; 4];
+        let mut strides = [0usize; 4];
+        let mut stride = self.outputs;
+        for i in (0..self.inputs).rev() {
+            let pos = clamp(input[i], 0.0, 1.0) * (self.grid - 1) as f32;
+            let cell = (pos as usize).min(self.grid - 2);
+            fractions[i] = pos - cell as f32;
+            base += cell * stride;
+            strides[i] = stride;
+            stride *= self.grid;
+        }
+
+        let mut out = [0.0f32; 4];
+        for corner in 0..(1usize << self.inputs) {
+            let mut weight = 1.0;
+            let mut offset = base;
+            for i in 0..self.inputs {
+                if corner & (1 << i) != 0 {
+                    weight *= fractions[i];
+                    offset += strides[i];
+                } else {
+                    weight *= 1.0 - fractions[i];
+                }
+            }
+            if weight == 0.0 {
+                continue
+            }
+            for o in 0..self.outputs {
+                out[o] += weight * self.clut[offset + o];
+            }
+        }
+        out
+    }
+
+    /// Evaluates the transform on values normalized to [0, 1]
+    fn eval(&self, input: &[f32], xyz_input: bool) -> [f32; 4] {
+        let mut v = [0.0f32; 4];
+        v[..self.inputs].copy_from_slice(&input[..self.inputs]);
+        if xyz_input && self.inputs == 3 {
+            let m = &self.matrix;
+            let x = v;
+            for r in 0..3 {
+                v[r] = m[3 * r] * x[0] + m[3 * r + 1] * x[1] + m[3 * r + 2] * x[2];
+            }
+        }
+        for i in 0..self.inputs {
+            v[i] = lookup(&self.input_curves[i], v[i]);
+        }
+        let mut out = self.interpolate(&v);
+        for o in 0..self.outputs {
+            out[o] = lookup(&self.output_curves[o], out[o]);
+        }
+        out
+    }
+}
+
+fn lab_to_xyz(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+    let f_inv = |t: f32| {
+        if t > 6.0 / 29.0 { t * t * t } else { 3.0 * (6.0f32 / 29.0).powi(2) * (t - 4.0 / 29.0) }
+    };
+    [D50[0] * f_inv(fx), D50[1] * f_inv(fy), D50[2] * f_inv(fz)]
+}
+
+fn xyz_to_lab(xyz: [f32; 3]) -> [f32; 3] {
+    let f = |t: f32| {
+        if t > (6.0f32 / 29.0).powi(3) { t.cbrt() } else { t / (3.0 * (6.0f32 / 29.0).powi(2)) + 4.0 / 29.0 }
+    };
+    let fx = f(xyz[0] / D50[0]);
+    let fy = f(xyz[1] / D50[1]);
+    let fz = f(xyz[2] / D50[2]);
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Inverts a 3x3 matrix in row major order
+fn invert(m: &[f32; 9]) -> Option<[f32; 9]> {
+    let det = m[0] * (m[4] * m[8] - m[5] * m[7])
+            - m[1] * (m[3] * m[8] - m[5] * m[6])
+            + m[2] * (m[3] * m[7] - m[4] * m[6]);
+    if det.abs() < 1e-12 {
+        return None
+    }
+    let d = 1.0 / det;
+    Some([
+        (m[4] * m[8] - m[5] * m[7]) * d, (m[2] * m[7] - m[1] * m[8]) * d, (m[1] * m[5] - m[2] * m[4]) * d,
+        (m[5] * m[6] - m[3] * m[8]) * d, (m[0] * m[8] - m[2] * m[6]) * d, (m[2] * m[3] - m[0] * m[5]) * d,
+        (m[3] * m[7] - m[4] * m[6]) * d, (m[1] * m[6] - m[0] * m[7]) * d, (m[0] * m[4] - m[1] * m[3]) * d,
+    ])
+}
+
+fn mul(m: &[f32; 9], v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0] * v[0] + m[1] * v[1] + m[2] * v[2],
+        m[3] * v[0] + m[4] * v[1] + m[5] * v[2],
+        m[6] * v[0] + m[7] * v[1] + m[8] * v[2],
+    ]
+}
+
+impl IccProfile {
+    /// Parses the profile from its binary representation, e.g. as embedded
+    /// in a PNG ```iCCP``` chunk or the JPEG ```APP2``` segments
+    pub fn from_bytes(data: &[u8]) -> ImageResult<IccProfile> {
+        if data.len() < 132 || try!(u32_at(data, 36)) != sig(b"acsp") {
+            return Err(ImageError::FormatError("Not an ICC profile".to_string()))
+        }
+
+        let space = try!(u32_at(data, 16));
+        let channels = if space == sig(b"GRAY") {
+            1
+        } else if space == sig(b"RGB ") || space == sig(b"Lab ") || space == sig(b"XYZ ") {
+            3
+        } else if space == sig(b"CMYK") {
+            4
+        } else {
+            return Err(ImageError::UnsupportedError("ICC profile color space".to_string()))
+        };
+        let pcs = try!(u32_at(data, 20));
+        let lab_pcs = pcs == sig(b"Lab ");
+        if !lab_pcs && pcs != sig(b"XYZ ") {
+            return Err(ImageError::FormatError("Invalid profile connection space".to_string()))
+        }
+
+        let count = try!(u32_at(data, 128)) as usize;
+        if count > (data.len() - 132) / 12 {
+            return Err(ImageError::NotEnoughData)
+        }
+        let mut tags = Vec::with_capacity(count);
+        for i in 0..count {
+            let entry = 132 + 12 * i;
+            let offset = try!(u32_at(data, entry + 4)) as usize;
+            let size = try!(u32_at(data, entry + 8)) as usize;
+            if offset.checked_add(size).map_or(true, |end| end > data.len()) {
+                return Err(ImageError::NotEnoughData)
+            }
+            tags.push((try!(u32_at(data, entry)), &data[offset..offset + size]));
+        }
+        let tag = |name: &[u8; 4]| tags.iter().find(|t| t.0 == sig(name)).map(|t| t.1);
+        let lut = |name: &[u8; 4], inputs: usize, outputs: usize| -> ImageResult<Option<Lut>> {
+            let lut = match tag(name) {
+                Some(data) => try!(Lut::parse(data, lab_pcs)),
+                None => return Ok(None),
+            };
+            if lut.inputs != inputs || lut.outputs != outputs {
+                return Err(ImageError::FormatError(
+                    "Lut channels do not match the color space".to_string()
+                ))
+            }
+            Ok(Some(lut))
+        };
+
+        // Device to PCS luts map the device channels to three PCS values,
+        // and the PCS to device luts the other way around
+        let a2b = [try!(lut(b"A2B0", channels, 3)), try!(lut(b"A2B1", channels, 3))];
+        let b2a = [try!(lut(b"B2A0", 3, channels)), try!(lut(b"B2A1", 3, channels))];
+        let transform = if a2b.iter().any(|l| l.is_some()) {
+            Transform::Lut { a2b: a2b, b2a: b2a }
+        } else if channels == 1 {
+            match tag(b"kTRC") {
+                Some(curve) => Transform::Gray(try!(Curve::parse(curve))),
+                None => return Err(ImageError::FormatError("Missing gray curve".to_string())),
+            }
+        } else {
+            let mut matrix = [0.0f32; 9];
+            for (column, name) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().enumerate() {
+                let xyz = match tag(name) {
+                    Some(xyz) => xyz,
+                    None => return Err(ImageError::FormatError("Missing colorant tag".to_string())),
+                };
+                for row in 0..3 {
+                    matrix[3 * row + column] = try!(s15f16_at(xyz, 8 + 4 * row));
+                }
+            }
+            let mut curves = Vec::with_capacity(3);
+            for name in [b"rTRC", b"gTRC", b"bTRC"].iter() {
+                match tag(name) {
+                    Some(curve) => curves.push(try!(Curve::parse(curve))),
+                    None => return Err(ImageError::FormatError("Missing tone curve".to_string())),
+                }
+            }
+            let b = curves.pop().unwrap();
+            let g = curves.pop().unwrap();
+            let r = curves.pop().unwrap();
+            Transform::Matrix(matrix, [r, g, b])
+        };
+
+        Ok(IccProfile {
+            channels: channels,
+            lab_pcs: lab_pcs,
+            transform: transform,
+        })
+    }
+
+    /// The sRGB profile, adapted to D50
+    pub fn srgb() -> IccProfile {
+        let curve = Curve::Parametric([2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045, 0.0, 0.0]);
+        IccProfile {
+            channels: 3,
+            lab_pcs: false,
+            transform: Transform::Matrix([
+                0.4360, 0.3851, 0.1431,
+                0.2225, 0.7169, 0.0606,
+                0.0139, 0.0971, 0.7141,
+            ], [curve.clone(), curve.clone(), curve]),
+        }
+    }
+
+    /// The number of device channels, e.g. 4 for a CMYK printer profile
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn slot(intent: Intent) -> usize {
+        match intent {
+            Intent::Perceptual => 0,
+            Intent::RelativeColorimetric => 1,
+        }
+    }
+
+    /// Converts device values in [0, 1] to XYZ
+    fn to_pcs(&self, device: &[f32], intent: Intent) -> [f32; 3] {
+        match self.transform {
+            Transform::Matrix(ref m, ref curves) => {
+                mul(m, [curves[0].eval(device[0]), curves[1].eval(device[1]), curves[2].eval(device[2])])
+            }
+            Transform::Gray(ref curve) => {
+                let y = curve.eval(device[0]);
+                [D50[0] * y, y, D50[2] * y]
+            }
+            Transform::Lut { ref a2b, .. } => {
+                let lut = a2b[IccProfile::slot(intent)].as_ref()
+                    .or(a2b[0].as_ref()).or(a2b[1].as_ref()).unwrap();
+                let out = lut.eval(device, false);
+                self.decode_pcs(lut, [out[0], out[1], out[2]])
+            }
+        }
+    }
+
+    /// Converts XYZ to device values in [0, 1]
+    fn from_pcs(&self, xyz: [f32; 3], intent: Intent) -> [f32; 4] {
+        let mut out = [0.0f32; 4];
+        match self.transform {
+            Transform::Matrix(ref m, ref curves) => {
+                let linear = mul(&invert(m).unwrap_or([0.0; 9]), xyz);
+                for c in 0..3 {
+                    out[c] = curves[c].eval_inverse(linear[c]);
+                }
+            }
+            Transform::Gray(ref curve) => out[0] = curve.eval_inverse(xyz[1]),
+            Transform::Lut { ref b2a, .. } => {
+                match b2a[IccProfile::slot(intent)].as_ref().or(b2a[0].as_ref()).or(b2a[1].as_ref()) {
+                    Some(lut) => {
+                        let pcs = self.encode_pcs(lut, xyz);
+                        out = lut.eval(&pcs, !self.lab_pcs);
+                    }
+                    // An input only profile: approximate with the PCS itself
+                    None => for c in 0..3 {
+                        out[c] = clamp(xyz[c] / D50[c], 0.0, 1.0);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Normalizes PCS values to the [0, 1] range of a lut's input
+    fn encode_pcs(&self, lut: &Lut, xyz: [f32; 3]) -> [f32; 3] {
+        if self.lab_pcs {
+            let lab = xyz_to_lab(xyz);
+            let scale = if lut.legacy_lab { 65280.0 / 65535.0 } else { 1.0 };
+            [
+                lab[0] / 100.0 * scale,
+                (lab[1] + 128.0) / 255.0 * scale,
+                (lab[2] + 128.0) / 255.0 * scale,
+            ]
+        } else {
+            // XYZ is encoded with 1.0 at 0x8000
+            [xyz[0] * 32768.0 / 65535.0, xyz[1] * 32768.0 / 65535.0, xyz[2] * 32768.0 / 65535.0]
+        }
+    }
+
+    /// Turns the [0, 1] output of a lut into XYZ
+    fn decode_pcs(&self, lut: &Lut, pcs: [f32; 3]) -> [f32; 3] {
+        if self.lab_pcs {
+            let scale = if lut.legacy_lab { 65535.0 / 65280.0 } else { 1.0 };
+            lab_to_xyz(
+                pcs[0] * scale * 100.0,
+                pcs[1] * scale * 255.0 - 128.0,
+                pcs[2] * scale * 255.0 - 128.0,
+            )
+        } else {
+            [pcs[0] * 65535.0 / 32768.0, pcs[1] * 65535.0 / 32768.0, pcs[2] * 65535.0 / 32768.0]
+        }
+    }
+
+    /// The darkest color the device reproduces
+    fn black_point(&self, intent: Intent) -> [f32; 3] {
+        let device = self.from_pcs([0.0, 0.0, 0.0], intent);
+        self.to_pcs(&device, intent)
+    }
+}
+
+/// Simulates how the sRGB image ```image``` looks when reproduced on the
+/// device described by ```target```, e.g. a CMYK printer.
+///
+/// Every color is converted to the device with ```intent``` and back with
+/// the relative colorimetric intent, so out of gamut colors show as the
+/// device would render them. With ```black_point_compensation``` the
+/// source black is mapped to the darkest color of the device, which keeps
+/// the shadow detail that would otherwise be clipped.
+/// The alpha channel is preserved.
+pub fn proof<I: GenericImage>(image: &I, target: &IccProfile, intent: Intent,
+                              black_point_compensation: bool) -> RgbaImage
+    where I::Pixel: Pixel<Subpixel=u8> {
+
+    let source = IccProfile::srgb();
+    let black = if black_point_compensation {
+        target.black_point(Intent::RelativeColorimetric)
+    } else {
+        [0.0; 3]
+    };
+
+    let (width, height) = image.dimensions();
+    let mut out = ImageBuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let p = image.get_pixel(x, y).to_rgba();
+            let rgb = [p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0];
+
+            let mut xyz = source.to_pcs(&rgb, intent);
+            // Scale towards the target black, keeping the white fixed
+            for c in 0..3 {
+                xyz[c] = black[c] + xyz[c] * (D50[c] - black[c]) / D50[c];
+            }
+
+            let device = target.from_pcs(xyz, intent);
+            let simulated = target.to_pcs(&device, Intent::RelativeColorimetric);
+            let display = source.from_pcs(simulated, Intent::RelativeColorimetric);
+
+            let channel = |v: f32| (clamp(v, 0.0, 1.0) * 255.0 + 0.5) as u8;
+            out.put_pixel(x, y, Rgba([channel(display[0]), channel(display[1]), channel(display[2]), p[3]]));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::ImageBuffer;
+    use color::Rgba;
+    use super::{D50, IccProfile, Intent, proof};
+
+    #[test]
+    fn test_srgb_round_trip() {
+        let srgb = IccProfile::srgb();
+        for &v in [0.0f32, 0.02, 0.25, 0.5, 0.75, 1.0].iter() {
+            let xyz = srgb.to_pcs(&[v, v, v], Intent::Perceptual);
+            let back = srgb.from_pcs(xyz, Intent::Perceptual);
+            for c in 0..3 {
+                assert!((back[c] - v).abs() < 1e-3, "{} became {}", v, back[c]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_proof_srgb_is_identity() {
+        let img: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, 128, 200])
+        });
+        let proofed = proof(&img, &IccProfile::srgb(), Intent::RelativeColorimetric, false);
+        for (a, b) in img.pixels().zip(proofed.pixels()) {
+            for c in 0..4 {
+                assert!((a[c] as i32 - b[c] as i32).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(IccProfile::from_bytes(&[0; 200]).is_err());
+    }
+
+    fn profile(space: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data[16..20].copy_from_slice(space);
+        data[20..24].copy_from_slice(b"XYZ ");
+        data[36..40].copy_from_slice(b"acsp");
+        data.extend_from_slice(&be(tags.len() as u32));
+
+        let mut offset = 132 + 12 * tags.len();
+        for &(name, ref tag) in tags.iter() {
+            data.extend_from_slice(name);
+            data.extend_from_slice(&be(offset as u32));
+            data.extend_from_slice(&be(tag.len() as u32));
+            offset += tag.len();
+        }
+        for &(_, ref tag) in tags.iter() {
+            data.extend_from_slice(tag);
+        }
+        data
+    }
+
+    fn be(v: u32) -> [u8; 4] {
+        [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+    }
+
+    // A ```lut8``` with identity matrix and curves, and a table of ```f```
+    // sampled at each grid point
+    fn lut8<F: Fn(&[f32]) -> [f32; 4]>(inputs: usize, outputs: usize, grid: usize, f: F) -> Vec<u8> {
+        let mut data = b"mft1\0\0\0\0".to_vec();
+        data.extend_from_slice(&[inputs as u8, outputs as u8, grid as u8, 0]);
+        for i in 0..9 {
+            data.extend_from_slice(&be(if i % 4 == 0 { 0x10000 } else { 0 }));
+        }
+        for _ in 0..inputs {
+            data.extend((0..256).map(|v| v as u8));
+        }
+        for index in 0..grid.pow(inputs as u32) {
+            let mut point = [0.0f32; 4];
+            let mut rest = index;
+            for i in (0..inputs).rev() {
+                point[i] = (rest % grid) as f32 / (grid - 1) as f32;
+                rest /= grid;
+            }
+            let out = f(&point[..inputs]);
+            data.extend(out[..outputs].iter().map(|&v| (v * 255.0 + 0.5) as u8));
+        }
+        for _ in 0..outputs {
+            data.extend((0..256).map(|v| v as u8));
+        }
+        data
+    }
+
+    // A printer that only has black ink
+    fn black_ink() -> Vec<u8> {
+        // XYZ is encoded with 1.0 at 0x8000
+        let encode = 32768.0 / 65535.0;
+        profile(b"CMYK", &[
+            (b"A2B0", lut8(4, 3, 2, |cmyk| {
+                let y = (1.0 - cmyk[3]) * encode;
+                [D50[0] * y, y, D50[2] * y, 0.0]
+            })),
+            (b"B2A0", lut8(3, 4, 3, |xyz| {
+                [0.0, 0.0, 0.0, (1.0 - xyz[1] / encode).max(0.0)]
+            })),
+        ])
+    }
+
+    #[test]
+    fn test_cmyk_lut() {
+        let printer = IccProfile::from_bytes(&black_ink()).unwrap();
+        assert_eq!(printer.channels(), 4);
+
+        let img: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_fn(16, 2, |x, y| {
+            if y == 0 { Rgba([x as u8 * 17; 4]) } else { Rgba([255, 0, 0, 255]) }
+        });
+        let proofed = proof(&img, &printer, Intent::Perceptual, true);
+
+        // Grays survive, within the precision of the 8 bit luts
+        for x in 0..16 {
+            let p = proofed.get_pixel(x, 0);
+            for c in 0..3 {
+                assert!((p[c] as i32 - x as i32 * 17).abs() <= 6, "{} became {:?}", x * 17, p);
+            }
+            assert_eq!(p[3], x as u8 * 17);
+        }
+        // Colors lose their hue, but keep their luminance
+        let red = proofed.get_pixel(0, 1);
+        for c in 0..3 {
+            assert!((red[c] as i32 - 127).abs() <= 6, "red became {:?}", red);
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_luts() {
+        use image::ImageError;
+
+        // A PCS to device lut that produces RGB for a CMYK printer
+        let data = profile(b"CMYK", &[
+            (b"A2B0", lut8(4, 3, 2, |_| [0.0; 4])),
+            (b"B2A0", lut8(3, 3, 2, |_| [0.0; 4])),
+        ]);
+        match IccProfile::from_bytes(&data) {
+            Err(ImageError::FormatError(_)) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // Counts far beyond the data must not be allocated
+        let mut data = profile(b"RGB ", &[]);
+        data[128..132].copy_from_slice(&[0xFF; 4]);
+        assert!(IccProfile::from_bytes(&data).is_err());
+
+        let mut curve = b"curv\0\0\0\0".to_vec();
+        curve.extend_from_slice(&[0xFF; 4]);
+        assert!(IccProfile::from_bytes(&profile(b"GRAY", &[(b"kTRC", curve)])).is_err());
+
+        let mut lut = lut8(4, 3, 2, |_| [0.0; 4]);
+        lut[10] = 255;
+        assert!(IccProfile::from_bytes(&profile(b"CMYK", &[(b"A2B0", lut)])).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use std::cmp;
 use std::fmt;
 use std::mem;
 use std::io;
@@ -5,6 +6,7 @@ use std::iter::repeat;
 use std::error::Error;
 
 use byteorder;
+use num::integer::gcd;
 
 use color;
 use color::ColorType;
@@ -36,7 +38,14 @@ pub enum ImageError {
     IoError(io::Error),
 
     /// The end of the image has been reached
-    ImageEnd
+    ImageEnd,
+
+    /// The image exceeds a limit set on the decoder, like its maximum
+    /// dimensions or the memory it may allocate
+    LimitsExceeded(String),
+
+    /// Decoding was cancelled before it finished
+    Cancelled
 }
 
 impl fmt::Display for ImageError {
@@ -52,7 +61,9 @@ impl fmt::Display for ImageError {
             &ImageError::NotEnoughData => write!(fmt, "Not enough data was provided to the \
                                                        Decoder to decode the image"),
             &ImageError::IoError(ref e) => e.fmt(fmt),
-            &ImageError::ImageEnd => write!(fmt, "The end of the image has been reached")
+            &ImageError::ImageEnd => write!(fmt, "The end of the image has been reached"),
+            &ImageError::LimitsExceeded(ref e) => write!(fmt, "Limits exceeded: {}", e),
+            &ImageError::Cancelled => write!(fmt, "Decoding was cancelled")
         }
     }
 }
@@ -64,9 +75,11 @@ impl Error for ImageError {
             ImageError::DimensionError => &"Dimension error",
             ImageError::UnsupportedError(..) => &"Unsupported error",
             ImageError::UnsupportedColor(..) => &"Unsupported color",
-            ImageError::NotEnoughData(..) => &"Not enough data",
+            ImageError::NotEnoughData => &"Not enough data",
             ImageError::IoError(..) => &"IO error",
-            ImageError::ImageEnd => &"Image end"
+            ImageError::ImageEnd => &"Image end",
+            ImageError::LimitsExceeded(..) => &"Limits exceeded",
+            ImageError::Cancelled => &"Cancelled"
         }
     }
 
@@ -142,6 +155,16 @@ pub enum ImageFormat {
     BMP
 }
 
+/// The order in which the rows of a decoded image are stored
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RowOrder {
+    /// The top row first, as images are shown
+    TopDown,
+
+    /// The bottom row first, as OpenGL expects the data of textures
+    BottomUp,
+}
+
 /// The trait that all decoders implement
 pub trait ImageDecoder: Sized {
     /// Returns a tuple containing the width and height of the image
@@ -150,15 +173,109 @@ pub trait ImageDecoder: Sized {
     /// Returns the color type of the image e.g RGB(8) (8bit RGB)
     fn colortype(&mut self) -> ImageResult<ColorType>;
 
+    /// Returns the ratio of the width to the height of a pixel in lowest
+    /// terms, as recorded in the image. Pixels that are not square make
+    /// the image look stretched unless it is resampled, for example with
+    /// ```DynamicImage::resample_to_square_pixels```.
+    ///
+    /// The default implementation returns ```(1, 1)```, for formats that
+    /// do not record it.
+    fn pixel_aspect_ratio(&mut self) -> ImageResult<(u32, u32)> {
+        Ok((1, 1))
+    }
+
     /// Returns the length in bytes of one decoded row of the image
     fn row_len(&mut self) -> ImageResult<usize>;
 
     /// Reads one row from the image into buf and returns the row index
     fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32>;
 
+    /// Reads up to ```n``` rows into buf, as many as fit into it, and
+    /// returns the number of rows read. Rows are stored without padding.
+    ///
+    /// The default implementation calls ```read_scanline``` for every row,
+    /// decoders override it to avoid the overhead of each call.
+    fn read_scanlines(&mut self, n: usize, buf: &mut [u8]) -> ImageResult<usize> {
+        let row = try!(self.row_len());
+        if row == 0 {
+            return Ok(0)
+        }
+
+        let rows = cmp::min(n, buf.len() / row);
+
+        for (i, chunk) in buf[..rows * row].chunks_mut(row).enumerate() {
+            match self.read_scanline(chunk) {
+                Ok(_) => (),
+                // The rows before the end of the image were read
+                Err(ImageError::ImageEnd) if i > 0 => return Ok(i),
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(rows)
+    }
+
     /// Decodes the entire image and return it as a Vector
     fn read_image(&mut self) -> ImageResult<DecodingResult>;
 
+    /// Returns the number of bytes ```read_image_into``` needs to store the
+    /// decoded image, that is the rows of all pixels without padding
+    fn required_bytes(&mut self) -> ImageResult<usize> {
+        let (width, height) = try!(self.dimensions());
+        let bits = color::bits_per_pixel(try!(self.colortype()));
+
+        Ok((width as usize * bits + 7) / 8 * height as usize)
+    }
+
+    /// Decodes the entire image into ```buf```, which has to hold at least
+    /// ```required_bytes``` bytes. Allows to reuse a single buffer for many
+    /// images of the same size. Samples of 16 bits are stored in native
+    /// byte order.
+    ///
+    /// The default implementation copies the result of ```read_image```,
+    /// decoders which can decode in place override it.
+    fn read_image_into(&mut self, buf: &mut [u8]) -> ImageResult<()> {
+        let required = try!(self.required_bytes());
+        if buf.len() < required {
+            return Err(ImageError::DimensionError)
+        }
+
+        match try!(self.read_image()) {
+            DecodingResult::U8(data) => {
+                let len = cmp::min(data.len(), required);
+                ::copy_memory(&data[..len], &mut buf[..len]);
+            }
+            DecodingResult::U16(data) => {
+                for (chunk, &sample) in buf[..required].chunks_mut(2).zip(data.iter()) {
+                    let bytes = sample.to_ne_bytes();
+                    ::copy_memory(&bytes, chunk);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the entire image into ```buf``` like ```read_image_into```,
+    /// with its rows stored in ```order``` whatever order the format stores
+    /// them in.
+    ///
+    /// The default implementation reverses the rows after decoding them
+    /// top-down, decoders which can place the rows directly override it.
+    fn read_image_into_ordered(&mut self, buf: &mut [u8], order: RowOrder) -> ImageResult<()> {
+        try!(self.read_image_into(buf));
+
+        if order == RowOrder::BottomUp {
+            let (_, height) = try!(self.dimensions());
+            let required = try!(self.required_bytes());
+            if height > 0 {
+                reverse_rows(&mut buf[..required], required / height as usize);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Returns true if the image is animated
     fn is_animated(&mut self) -> ImageResult<bool> {
         // since most image formats do not support animation
@@ -216,6 +333,28 @@ pub trait ImageDecoder: Sized {
     }
 }
 
+/// Reduces the pixel aspect ratio ```width``` to ```height``` to lowest
+/// terms, or to ```(1, 1)``` if either is 0
+pub fn reduce_aspect_ratio(width: u32, height: u32) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (1, 1)
+    }
+
+    let d = gcd(width, height);
+    (width / d, height / d)
+}
+
+// Reverses the order of the rows of `row` bytes in `buf`
+fn reverse_rows(buf: &mut [u8], row: usize) {
+    let rows = buf.len() / row;
+
+    for i in (0..rows / 2) {
+        let (top, bottom) = buf.split_at_mut((rows - 1 - i) * row);
+        for (a, b) in top[i * row..(i + 1) * row].iter_mut().zip(bottom[..row].iter_mut()) {
+            mem::swap(a, b);
+        }
+    }
+}
 
 /// Immutable pixel iterator
 pub struct Pixels<'a, I: 'a> {
@@ -526,9 +665,40 @@ impl<'a, I: GenericImage + 'static> GenericImage for SubImage<'a, I>
 #[cfg(test)]
 mod tests {
 
-    use super::GenericImage;
+    use super::{reverse_rows, DecodingResult, GenericImage, ImageDecoder, ImageResult, RowOrder};
     use buffer::ImageBuffer;
-    use color::{Rgba};
+    use color::{ColorType, Rgba};
+
+    // A decoder of 2x3 gray pixels whose row `y` holds the value `y`, which
+    // uses the default implementations of the trait
+    struct Rows {
+        bits: u8,
+    }
+
+    impl ImageDecoder for Rows {
+        fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
+            Ok((2, 3))
+        }
+
+        fn colortype(&mut self) -> ImageResult<ColorType> {
+            Ok(ColorType::Gray(self.bits))
+        }
+
+        fn row_len(&mut self) -> ImageResult<usize> {
+            Ok(2 * self.bits as usize / 8)
+        }
+
+        fn read_scanline(&mut self, _buf: &mut [u8]) -> ImageResult<u32> {
+            unimplemented!()
+        }
+
+        fn read_image(&mut self) -> ImageResult<DecodingResult> {
+            Ok(match self.bits {
+                8 => DecodingResult::U8(vec![0, 0, 1, 1, 2, 2]),
+                _ => DecodingResult::U16(vec![0, 0, 1, 1, 2, 2]),
+            })
+        }
+    }
 
     #[test]
     /// Test that alpha blending works as expected
@@ -563,4 +733,31 @@ mod tests {
         assert!(!target.in_bounds(0,2));
         assert!(!target.in_bounds(2,2));
     }
+
+    #[test]
+    fn test_reverse_rows() {
+        let mut odd = [1u8, 2, 3, 4, 5, 6];
+        reverse_rows(&mut odd, 2);
+        assert_eq!(odd, [5, 6, 3, 4, 1, 2]);
+
+        let mut even = [1u8, 2, 3, 4];
+        reverse_rows(&mut even, 1);
+        assert_eq!(even, [4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_read_image_into_ordered() {
+        let mut buf = [9u8; 7];
+        Rows { bits: 8 }.read_image_into_ordered(&mut buf, RowOrder::TopDown).unwrap();
+        assert_eq!(buf, [0, 0, 1, 1, 2, 2, 9]);
+        Rows { bits: 8 }.read_image_into_ordered(&mut buf, RowOrder::BottomUp).unwrap();
+        assert_eq!(buf, [2, 2, 1, 1, 0, 0, 9]);
+
+        let mut buf = [0u8; 12];
+        Rows { bits: 16 }.read_image_into_ordered(&mut buf, RowOrder::BottomUp).unwrap();
+        let samples: Vec<u16> = buf.chunks(2).map(|b| u16::from_ne_bytes([b[0], b[1]])).collect();
+        assert_eq!(samples, [2, 2, 1, 1, 0, 0]);
+
+        assert!(Rows { bits: 8 }.read_image_into_ordered(&mut [0; 5], RowOrder::BottomUp).is_err());
+    }
 }
@@ -1,8 +1,85 @@
 //! Functions for performing affine transformations.
 
+use std::cmp;
+
 use buffer::{ImageBuffer, Pixel};
 use image::GenericImage;
 
+use super::sample::{resize, FilterType};
+
+/// An affine transform of coordinates, which maps ```x```, ```y``` to
+/// ```a * x + b * y + c```, ```d * x + e * y + f``` for the ```matrix```
+/// ```[a, b, c, d, e, f]```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AffineTransform {
+    /// The first two rows of the 3x3 matrix of the transform
+    pub matrix: [f32; 6],
+}
+
+impl AffineTransform {
+    /// The transform that leaves coordinates unchanged
+    pub fn identity() -> AffineTransform {
+        AffineTransform { matrix: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0] }
+    }
+
+    /// Maps the point ```x```, ```y```
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let m = &self.matrix;
+        (m[0] * x + m[1] * y + m[2], m[3] * x + m[4] * y + m[5])
+    }
+
+    /// Returns the transform that undoes this one, or ```None``` if it
+    /// collapses coordinates onto a line or a point
+    pub fn inverse(&self) -> Option<AffineTransform> {
+        let m = &self.matrix;
+        let det = m[0] * m[4] - m[1] * m[3];
+
+        if det == 0.0 {
+            return None
+        }
+
+        let (a, b, d, e) = (m[4] / det, -m[1] / det, -m[3] / det, m[0] / det);
+        Some(AffineTransform { matrix: [a, b, -(a * m[2] + b * m[5]), d, e, -(d * m[2] + e * m[5])] })
+    }
+}
+
+/// Scales ```image``` to fit in ```width``` by ```height``` pixels, keeping
+/// its aspect ratio, and centers it on a background of ```fill```, as the
+/// input of models that take a fixed size.
+///
+/// Returns the padded image and the transform from its coordinates to
+/// those of ```image```, e.g. to map the boxes a detector found back onto
+/// the image. Coordinates are continuous, pixel ```x```, ```y``` covers
+/// ```x``` to ```x + 1``` and ```y``` to ```y + 1```.
+pub fn letterbox<I: GenericImage + 'static>(image: &I, width: u32, height: u32, fill: I::Pixel)
+    -> (ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>, AffineTransform)
+    where I::Pixel: 'static,
+          <I::Pixel as Pixel>::Subpixel: 'static {
+
+    let (iwidth, iheight) = image.dimensions();
+    let mut out = ImageBuffer::from_pixel(width, height, fill);
+
+    if iwidth == 0 || iheight == 0 || width == 0 || height == 0 {
+        return (out, AffineTransform::identity())
+    }
+
+    let scale = (width as f64 / iwidth as f64).min(height as f64 / iheight as f64);
+    let fit = |v: u32, max: u32| cmp::min(max, cmp::max(1, (v as f64 * scale).round() as u32));
+    let (nwidth, nheight) = (fit(iwidth, width), fit(iheight, height));
+    let (x0, y0) = ((width - nwidth) / 2, (height - nheight) / 2);
+
+    let scaled = resize(image, nwidth, nheight, FilterType::Triangle);
+    for (x, y, p) in scaled.enumerate_pixels() {
+        out.put_pixel(x0 + x, y0 + y, *p);
+    }
+
+    // The axes may be scaled slightly differently after rounding
+    let (sx, sy) = (iwidth as f32 / nwidth as f32, iheight as f32 / nheight as f32);
+    let transform = AffineTransform { matrix: [sx, 0.0, -(x0 as f32) * sx, 0.0, sy, -(y0 as f32) * sy] };
+
+    (out, transform)
+}
+
 /// Rotate an image 90 degrees clockwise.
 // TODO: Is the 'static bound on `I` really required? Can we avoid it?
 pub fn rotate90<I: GenericImage + 'static>(image:  &I)
@@ -67,7 +144,7 @@ pub fn flip_horizontal<I: GenericImage + 'static>(image:  &I)
     where I::Pixel: 'static,
           <I::Pixel as Pixel>::Subpixel: 'static {
     let (width, height) = image.dimensions();
-    let mut out = ImageBuffer::new(height, width);
+    let mut out = ImageBuffer::new(width, height);
 
     for y in (0..height) {
         for x in (0..width) {
@@ -97,3 +174,36 @@ pub fn flip_vertical<I: GenericImage + 'static>(image:  &I)
 
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use buffer::{ImageBuffer, RgbImage};
+    use color::Rgb;
+    use super::{letterbox, AffineTransform};
+
+    #[test]
+    fn test_letterbox() {
+        let image: RgbImage = ImageBuffer::from_pixel(40, 20, Rgb([200, 100, 50]));
+        let (padded, transform) = letterbox(&image, 32, 32, Rgb([0, 0, 0]));
+
+        // Scaled to 32x16 between bars of 8 pixels
+        assert_eq!(padded.dimensions(), (32, 32));
+        assert_eq!(padded[(5, 7)], Rgb([0, 0, 0]));
+        assert_eq!(padded[(5, 8)], Rgb([200, 100, 50]));
+        assert_eq!(padded[(31, 23)], Rgb([200, 100, 50]));
+        assert_eq!(padded[(31, 24)], Rgb([0, 0, 0]));
+
+        assert_eq!(transform.apply(0.0, 8.0), (0.0, 0.0));
+        assert_eq!(transform.apply(32.0, 24.0), (40.0, 20.0));
+        assert_eq!(transform.inverse().unwrap().apply(20.0, 10.0), (16.0, 16.0));
+    }
+
+    #[test]
+    fn test_inverse() {
+        let transform = AffineTransform { matrix: [0.0, -2.0, 5.0, 3.0, 0.0, -1.0] };
+        let (x, y) = transform.apply(4.0, 7.0);
+        assert_eq!(transform.inverse().unwrap().apply(x, y), (4.0, 7.0));
+
+        assert!(AffineTransform { matrix: [1.0, 2.0, 0.0, 2.0, 4.0, 0.0] }.inverse().is_none());
+    }
+}
@@ -0,0 +1,179 @@
+//! Automatic white balance, to remove the color cast of scans and webcam
+//! pictures taken under tinted light
+use num::NumCast;
+
+use buffer::{ImageBuffer, Pixel};
+use image::GenericImage;
+use traits::Primitive;
+
+// The number of bins of the histogram of each channel
+const BINS: usize = 256;
+
+/// How ```auto_white_balance``` estimates the color of the light
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WhiteBalance {
+    /// Assumes that the scene is gray on average, and scales the red, green
+    /// and blue channels to equal means
+    GrayWorld,
+
+    /// Assumes that the brightest parts of the scene are white, and scales
+    /// each channel so that the given percentile of its values, between 0
+    /// and 100, becomes the maximum. A percentile below 100, like 99, keeps
+    /// specular highlights and noise from deciding the balance.
+    WhitePatch(f32),
+}
+
+/// Returns ```image``` with its color cast removed as estimated by
+/// ```method``` from the histograms of its red, green and blue channels.
+/// Fully transparent pixels are ignored, and the alpha channel is kept.
+/// Images without color are returned unchanged.
+pub fn auto_white_balance<I, P, S>(image: &I, method: WhiteBalance)
+    -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P>,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+
+    let (width, height) = image.dimensions();
+    let mut out = ImageBuffer::new(width, height);
+    if width == 0 || height == 0 {
+        return out
+    }
+
+    let max: f32 = NumCast::from(S::max_value()).unwrap();
+
+    let gains = if P::channel_count() < 3 {
+        [1.0; 3]
+    } else {
+        gains(&histograms(image, max), method)
+    };
+
+    for (x, y, pixel) in image.pixels() {
+        let mut pixel = pixel;
+        for (c, gain) in pixel.channels_mut().iter_mut().zip(gains.iter()) {
+            let v: f32 = NumCast::from(*c).unwrap();
+            *c = NumCast::from((v * gain).round().max(0.0).min(max)).unwrap();
+        }
+        out.put_pixel(x, y, pixel);
+    }
+
+    out
+}
+
+// The histograms of the red, green and blue channels of the pixels of
+// `image` that are not fully transparent, with `BINS` bins up to `max`
+fn histograms<I: GenericImage>(image: &I, max: f32) -> [Vec<u32>; 3] {
+    let mut histograms = [vec![0u32; BINS], vec![0u32; BINS], vec![0u32; BINS]];
+    let scale = (BINS - 1) as f32 / max;
+
+    for (_, _, pixel) in image.pixels() {
+        let p = pixel.to_rgba();
+        let alpha: f32 = NumCast::from(p[3]).unwrap();
+        if alpha == 0.0 {
+            continue
+        }
+
+        for c in (0..3) {
+            let v: f32 = NumCast::from(p[c]).unwrap();
+            histograms[c][(v * scale).round() as usize] += 1;
+        }
+    }
+
+    histograms
+}
+
+// The factors that balance the channels with the histograms `histograms`,
+// where the last bin stands for the maximum value
+fn gains(histograms: &[Vec<u32>; 3], method: WhiteBalance) -> [f32; 3] {
+    let total = histograms[0].iter().map(|&n| n as u64).sum::<u64>();
+    if total == 0 {
+        return [1.0; 3]
+    }
+
+    let mut levels = [0.0f32; 3];
+    for (level, histogram) in levels.iter_mut().zip(histograms.iter()) {
+        *level = match method {
+            WhiteBalance::GrayWorld => {
+                let sum = histogram.iter().enumerate().map(|(v, &n)| v as u64 * n as u64).sum::<u64>();
+                sum as f32 / total as f32
+            }
+            WhiteBalance::WhitePatch(percentile) => {
+                let rank = (percentile.max(0.0).min(100.0) / 100.0 * total as f32).ceil().max(1.0) as u64;
+                let mut seen = 0;
+                histogram.iter().position(|&n| { seen += n as u64; seen >= rank }).unwrap_or(BINS - 1) as f32
+            }
+        };
+    }
+
+    let target = match method {
+        WhiteBalance::GrayWorld => (levels[0] + levels[1] + levels[2]) / 3.0,
+        WhiteBalance::WhitePatch(_) => (BINS - 1) as f32,
+    };
+
+    // Channels that are black throughout stay as they are
+    let mut gains = [1.0; 3];
+    for (gain, &level) in gains.iter_mut().zip(levels.iter()) {
+        if level > 0.0 {
+            *gain = target / level;
+        }
+    }
+
+    gains
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{ImageBuffer, RgbImage};
+    use color::{Luma, Rgb, Rgba};
+    use super::{auto_white_balance, WhiteBalance};
+
+    #[test]
+    fn test_gray_world() {
+        // A gray scene under yellowish light
+        let image: RgbImage = ImageBuffer::from_fn(4, 4, |x, _| {
+            if x < 2 { Rgb([120, 100, 80]) } else { Rgb([60, 50, 40]) }
+        });
+        let balanced = auto_white_balance(&image, WhiteBalance::GrayWorld);
+
+        assert_eq!(balanced[(0, 0)], Rgb([100, 100, 100]));
+        assert_eq!(balanced[(3, 3)], Rgb([50, 50, 50]));
+    }
+
+    #[test]
+    fn test_white_patch() {
+        // A tinted white row, and a highlight above the 90th percentile
+        let mut image: RgbImage = ImageBuffer::from_pixel(10, 10, Rgb([40, 40, 40]));
+        for x in (0..10) {
+            image.put_pixel(x, 0, Rgb([200, 250, 100]));
+        }
+        image.put_pixel(0, 1, Rgb([255, 255, 255]));
+
+        let balanced = auto_white_balance(&image, WhiteBalance::WhitePatch(90.0));
+        assert_eq!(balanced[(5, 0)], Rgb([255, 255, 255]));
+        assert_eq!(balanced[(5, 5)], Rgb([51, 41, 102]));
+
+        let balanced = auto_white_balance(&image, WhiteBalance::WhitePatch(100.0));
+        assert_eq!(balanced[(5, 0)], Rgb([200, 250, 100]));
+    }
+
+    #[test]
+    fn test_alpha_and_gray() {
+        // Transparent pixels do not count, and alpha is kept
+        let image = ImageBuffer::from_fn(2, 1, |x, _| {
+            if x == 0 { Rgba([100u8, 50, 100, 128]) } else { Rgba([255, 0, 0, 0]) }
+        });
+        let balanced = auto_white_balance(&image, WhiteBalance::GrayWorld);
+        assert_eq!(balanced[(0, 0)], Rgba([83, 83, 83, 128]));
+
+        let gray = ImageBuffer::from_pixel(2, 2, Luma([77u8]));
+        assert_eq!(auto_white_balance(&gray, WhiteBalance::GrayWorld).into_raw(), gray.into_raw());
+    }
+
+    #[test]
+    fn test_empty() {
+        for &(width, height) in [(0, 5), (5, 0), (0, 0)].iter() {
+            let image: RgbImage = ImageBuffer::new(width, height);
+            let balanced = auto_white_balance(&image, WhiteBalance::WhitePatch(99.0));
+            assert_eq!(balanced.dimensions(), (width, height));
+        }
+    }
+}
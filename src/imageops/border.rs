@@ -0,0 +1,220 @@
+//! Borders and mats around pictures, as for printing or presenting them.
+//! The framed picture is built in one buffer of its final size.
+
+use std::cmp;
+
+use num::NumCast;
+
+use buffer::{ImageBuffer, Pixel};
+use image::GenericImage;
+use traits::Primitive;
+
+use super::sample::rounds_samples;
+
+/// A drop shadow that a matted picture casts onto its mat
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Shadow {
+    /// How far the shadow is moved to the right and down, in pixels
+    pub offset: (i32, i32),
+
+    /// The standard deviation of the blur of its edges, 0 for hard edges
+    pub blur: f32,
+
+    /// How much it darkens the mat, from 0 to 1
+    pub opacity: f32,
+}
+
+impl Shadow {
+    /// A faint shadow close to the picture
+    pub fn subtle() -> Shadow {
+        Shadow { offset: (2, 2), blur: 2.0, opacity: 0.25 }
+    }
+
+    /// A wide soft shadow, as if the picture was lifted off the mat
+    pub fn soft() -> Shadow {
+        Shadow { offset: (6, 8), blur: 6.0, opacity: 0.4 }
+    }
+
+    /// A dark shadow with hard edges
+    pub fn hard() -> Shadow {
+        Shadow { offset: (4, 4), blur: 0.0, opacity: 0.6 }
+    }
+}
+
+/// The borders of a matted picture: an inner border right around it and
+/// the mat around that, onto which the picture may cast a shadow
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat<P> {
+    /// The width of the inner border
+    pub inner: u32,
+
+    /// The color of the inner border
+    pub inner_color: P,
+
+    /// The width of the mat
+    pub outer: u32,
+
+    /// The color of the mat
+    pub outer_color: P,
+
+    /// The shadow of the picture with its inner border on the mat
+    pub shadow: Option<Shadow>,
+}
+
+/// Returns ```image``` surrounded by a border of ```width``` pixels of
+/// ```color```
+pub fn add_border<I: GenericImage + 'static>(image: &I, width: u32, color: I::Pixel)
+    -> ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>
+    where I::Pixel: 'static,
+          <I::Pixel as Pixel>::Subpixel: 'static {
+
+    let (iwidth, iheight) = image.dimensions();
+    let mut out = ImageBuffer::from_pixel(iwidth + 2 * width, iheight + 2 * width, color);
+    out.copy_from(image, width, width);
+
+    out
+}
+
+/// Returns ```image``` in the inner border and on the mat of ```mat```.
+/// The shadow only shows on the mat, thus it is cut off where it reaches
+/// past the mat.
+pub fn mat<I: GenericImage + 'static>(image: &I, mat: &Mat<I::Pixel>)
+    -> ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>
+    where I::Pixel: 'static,
+          <I::Pixel as Pixel>::Subpixel: 'static {
+
+    let (iwidth, iheight) = image.dimensions();
+    let (fwidth, fheight) = (iwidth + 2 * mat.inner, iheight + 2 * mat.inner);
+    let (width, height) = (fwidth + 2 * mat.outer, fheight + 2 * mat.outer);
+    let mut out = ImageBuffer::from_pixel(width, height, mat.outer_color);
+
+    if let Some(shadow) = mat.shadow {
+        let start = |offset: i32| mat.outer as i64 + offset as i64;
+        let columns = coverage(width, start(shadow.offset.0), fwidth, shadow.blur);
+        let rows = coverage(height, start(shadow.offset.1), fheight, shadow.blur);
+        let opacity = shadow.opacity.max(0.0).min(1.0);
+        let round = rounds_samples::<<I::Pixel as Pixel>::Subpixel>();
+
+        for (x, y, p) in out.enumerate_pixels_mut() {
+            let alpha = opacity * columns[x as usize] * rows[y as usize];
+            if alpha > 0.0 {
+                *p = p.map_with_alpha(|c| darken(c, alpha, round), |a| a);
+            }
+        }
+    }
+
+    for y in (mat.outer..mat.outer + fheight) {
+        for x in (mat.outer..mat.outer + fwidth) {
+            out.put_pixel(x, y, mat.inner_color);
+        }
+    }
+    out.copy_from(image, mat.outer + mat.inner, mat.outer + mat.inner);
+
+    out
+}
+
+// How much each of `len` pixels is covered by `size` pixels from `start`,
+// blurred by a Gaussian with standard deviation `sigma`. The Gaussian is
+// cut off at 3 `sigma`, or at `len` as no pixel sees more of it.
+fn coverage(len: u32, start: i64, size: u32, sigma: f32) -> Vec<f32> {
+    let inside = |x: i64| x >= start && x < start + size as i64;
+
+    if !(sigma > 0.0) {
+        return (0..len as i64).map(|x| if inside(x) { 1.0 } else { 0.0 }).collect()
+    }
+
+    let radius = cmp::min((3.0 * sigma).ceil() as i64, len as i64);
+    let weights: Vec<f32> = (-radius..radius + 1)
+        .map(|d| (-(d * d) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+
+    (0..len as i64).map(|x| {
+        let sum: f32 = weights.iter().enumerate()
+            .filter(|&(i, _)| inside(x + i as i64 - radius))
+            .map(|(_, w)| w)
+            .sum();
+        sum / total
+    }).collect()
+}
+
+// Darkens `c` by `alpha`, rounded if the samples are integers
+fn darken<T: Primitive>(c: T, alpha: f32, round: bool) -> T {
+    let c: f32 = NumCast::from(c).unwrap();
+    let v = c * (1.0 - alpha);
+    NumCast::from(if round { v.round() } else { v }).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{ImageBuffer, RgbImage};
+    use color::{Luma, Rgb};
+    use super::{add_border, coverage, mat, Mat, Shadow};
+
+    #[test]
+    fn test_add_border() {
+        let image = ImageBuffer::from_pixel(3, 2, Luma([200u8]));
+        let framed = add_border(&image, 2, Luma([10]));
+
+        assert_eq!(framed.dimensions(), (7, 6));
+        assert_eq!(framed[(1, 1)], Luma([10]));
+        assert_eq!(framed[(2, 2)], Luma([200]));
+        assert_eq!(framed[(4, 3)], Luma([200]));
+        assert_eq!(framed[(5, 3)], Luma([10]));
+        assert_eq!(add_border(&image, 0, Luma([10])).into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn test_mat() {
+        let image: RgbImage = ImageBuffer::from_pixel(10, 10, Rgb([255, 0, 0]));
+        let white = Rgb([255, 255, 255]);
+        let mut options = Mat { inner: 1, inner_color: Rgb([0, 0, 0]), outer: 8, outer_color: white, shadow: None };
+
+        let framed = mat(&image, &options);
+        assert_eq!(framed.dimensions(), (28, 28));
+        assert_eq!(framed[(7, 7)], white);
+        assert_eq!(framed[(8, 8)], Rgb([0, 0, 0]));
+        assert_eq!(framed[(9, 9)], Rgb([255, 0, 0]));
+        assert_eq!(framed[(19, 19)], Rgb([0, 0, 0]));
+
+        // A hard shadow darkens the mat below and to the right
+        options.shadow = Some(Shadow::hard());
+        let framed = mat(&image, &options);
+        assert_eq!(framed[(9, 9)], Rgb([255, 0, 0]));
+        assert_eq!(framed[(22, 22)], Rgb([102, 102, 102]));
+        assert_eq!(framed[(23, 23)], Rgb([102, 102, 102]));
+        assert_eq!(framed[(24, 24)], white);
+        assert_eq!(framed[(11, 7)], white);
+        assert_eq!(framed[(13, 20)], Rgb([102, 102, 102]));
+
+        // A soft one fades out towards its edges
+        options.shadow = Some(Shadow::soft());
+        let framed = mat(&image, &options);
+        assert!(framed[(21, 16)][0] < framed[(24, 16)][0]);
+        assert!(framed[(24, 16)][0] < 255);
+        assert_eq!(framed[(0, 0)], white);
+    }
+
+    #[test]
+    fn test_float_shadow() {
+        // Float samples are darkened without rounding
+        let image = ImageBuffer::from_pixel(2, 2, Rgb([1.0f32, 0.0, 0.0]));
+        let shadow = Shadow { offset: (1, 1), blur: 0.0, opacity: 0.25 };
+        let options = Mat { inner: 0, inner_color: Rgb([0.0, 0.0, 0.0]), outer: 2,
+                            outer_color: Rgb([0.8, 0.8, 0.8]), shadow: Some(shadow) };
+
+        let framed = mat(&image, &options);
+        assert_eq!(framed[(4, 4)], Rgb([0.6, 0.6, 0.6]));
+        assert_eq!(framed[(5, 5)], Rgb([0.8, 0.8, 0.8]));
+    }
+
+    #[test]
+    fn test_coverage() {
+        // A blur much wider than the image is cut off at its size
+        let columns = coverage(10, 2, 4, 1.0e9);
+        assert_eq!(columns.len(), 10);
+        assert!(columns.iter().all(|&c| c > 0.0 && c < 1.0));
+
+        assert_eq!(coverage(4, 1, 2, 0.0), vec![0.0, 1.0, 1.0, 0.0]);
+    }
+}
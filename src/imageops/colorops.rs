@@ -1,10 +1,12 @@
 //! Functions for altering and converting the color of pixelbufs
+use std::ops::{Deref, DerefMut};
+
 use num:: {
     NumCast,
     Float,
 };
 
-use color::{Luma, Rgba};
+use color::{Luma, Rgb, Rgba};
 use buffer::{ImageBuffer, Pixel};
 use traits::Primitive;
 use image::GenericImage;
@@ -195,9 +197,10 @@ macro_rules! do_dithering(
 
 /// Reduces the colors of the image using the supplied `color_map` while applying
 /// Floyd-Steinberg dithering to improve the visual conception
-pub fn dither<Pix, Map>(image: &mut ImageBuffer<Pix, Vec<u8>>, color_map: &Map)
+pub fn dither<Pix, Map, Container>(image: &mut ImageBuffer<Pix, Container>, color_map: &Map)
 where Map: ColorMap<Color=Pix>,
       Pix: Pixel<Subpixel=u8> + 'static,
+      Container: Deref<Target=[u8]> + DerefMut,
 {
     let (width, height) = image.dimensions();
     let mut err: [i16; 3] = [0; 3];
@@ -232,10 +235,11 @@ where Map: ColorMap<Color=Pix>,
 }
 
 /// Reduces the colors using the supplied `color_map` and returns an image of the indices
-pub fn index_colors<Pix, Map>(image: &ImageBuffer<Pix, Vec<u8>>, color_map: &Map) ->
+pub fn index_colors<Pix, Map, Container>(image: &ImageBuffer<Pix, Container>, color_map: &Map) ->
 ImageBuffer<Luma<u8>, Vec<u8>>
 where Map: ColorMap<Color=Pix>,
       Pix: Pixel<Subpixel=u8> + 'static,
+      Container: Deref<Target=[u8]>,
 {
     let mut indices = ImageBuffer::new(image.width(), image.height());
     for (pixel, idx) in image.pixels().zip(indices.pixels_mut()) {
@@ -244,6 +248,97 @@ where Map: ColorMap<Color=Pix>,
     indices
 }
 
+/// A mapping of scalar values to colors, used to visualize depth maps,
+/// disparities and sensor data
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Colormap {
+    /// The perceptually uniform dark blue to yellow map of matplotlib
+    Viridis,
+    /// Google's improved rainbow map, dark blue over green to dark red
+    Turbo,
+    /// The classic rainbow map of MATLAB, blue over green to red
+    Jet,
+    /// Black to white
+    Grayscale,
+}
+
+// Viridis at nine evenly spaced points
+static VIRIDIS: [[f32; 3]; 9] = [
+    [ 68.0,   1.0,  84.0],
+    [ 71.0,  44.0, 122.0],
+    [ 59.0,  81.0, 139.0],
+    [ 44.0, 113.0, 142.0],
+    [ 33.0, 144.0, 141.0],
+    [ 39.0, 173.0, 129.0],
+    [ 92.0, 200.0,  99.0],
+    [170.0, 220.0,  50.0],
+    [253.0, 231.0,  37.0],
+];
+
+impl Colormap {
+    /// Returns the color of the value ```v``` in [0, 1]
+    pub fn color(&self, v: f32) -> Rgb<u8> {
+        let v = clamp(v, 0.0, 1.0);
+        let to_u8 = |c: f32| (clamp(c, 0.0, 1.0) * 255.0 + 0.5) as u8;
+
+        match *self {
+            Colormap::Viridis => {
+                let pos = v * (VIRIDIS.len() - 1) as f32;
+                let i = (pos as usize).min(VIRIDIS.len() - 2);
+                let t = pos - i as f32;
+                let (a, b) = (VIRIDIS[i], VIRIDIS[i + 1]);
+                let c = |k: usize| (a[k] + (b[k] - a[k]) * t + 0.5) as u8;
+
+                Rgb([c(0), c(1), c(2)])
+            }
+            Colormap::Turbo => {
+                // The polynomial approximation by Anton Mikhailov
+                let r = 0.13572138 + v * (4.61539260 + v * (-42.66032258 + v * (132.13108234
+                        + v * (-152.94239396 + v * 59.28637943))));
+                let g = 0.09140261 + v * (2.19418839 + v * (4.84296658 + v * (-14.18503333
+                        + v * (4.27729857 + v * 2.82956604))));
+                let b = 0.10667330 + v * (12.64194608 + v * (-60.58204836 + v * (110.36276771
+                        + v * (-89.90310912 + v * 27.34824973))));
+
+                Rgb([to_u8(r), to_u8(g), to_u8(b)])
+            }
+            Colormap::Jet => {
+                let channel = |center: f32| to_u8(1.5 - (4.0 * v - center).abs());
+
+                Rgb([channel(3.0), channel(2.0), channel(1.0)])
+            }
+            Colormap::Grayscale => {
+                let l = to_u8(v);
+
+                Rgb([l, l, l])
+            }
+        }
+    }
+}
+
+/// Maps the values of a grayscale image to the colors of ```colormap```.
+/// The full range of the subpixel type, e.g. 0 to 65535 for 16 bit
+/// depth maps, is spread over the colormap.
+pub fn colorize<I, S>(image: &I, colormap: Colormap) -> ImageBuffer<Rgb<u8>, Vec<u8>>
+    where I: GenericImage<Pixel=Luma<S>>,
+          S: Primitive + 'static {
+
+    let (width, height) = image.dimensions();
+    let mut out = ImageBuffer::new(width, height);
+
+    let max = S::max_value();
+    let max: f32 = NumCast::from(max).unwrap();
+
+    for y in (0..height) {
+        for x in (0..width) {
+            let v: f32 = NumCast::from(image.get_pixel(x, y)[0]).unwrap();
+            out.put_pixel(x, y, colormap.color(v / max));
+        }
+    }
+
+    out
+}
+
 #[cfg(test)]
 mod test {
 
@@ -258,4 +353,24 @@ mod test {
         assert_eq!(&*image, &[0, 0xFF, 0xFF, 0]);
         assert_eq!(index_colors(&image, &cmap).into_raw(), vec![0, 1, 1, 0])
     }
+
+    #[test]
+    fn test_colorize() {
+        let depth: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_raw(3, 1, vec![0, 32768, 65535]).unwrap();
+
+        let gray = colorize(&depth, Colormap::Grayscale);
+        assert_eq!(gray.into_raw(), vec![0, 0, 0, 128, 128, 128, 255, 255, 255]);
+
+        let viridis = colorize(&depth, Colormap::Viridis);
+        assert_eq!(viridis[(0, 0)], Rgb([68, 1, 84]));
+        assert_eq!(viridis[(2, 0)], Rgb([253, 231, 37]));
+
+        let jet = colorize(&depth, Colormap::Jet);
+        assert_eq!(jet[(0, 0)], Rgb([0, 0, 128]));
+        assert_eq!(jet[(2, 0)], Rgb([128, 0, 0]));
+
+        let turbo = colorize(&depth, Colormap::Turbo);
+        assert!(turbo[(1, 0)][1] > 200);
+        assert!(turbo[(2, 0)][0] > turbo[(2, 0)][2]);
+    }
 }
@@ -0,0 +1,237 @@
+//! Contour extraction from binary masks
+use buffer::GrayImage;
+
+// The offsets (dy, dx) of the 8 neighbours of a pixel in clockwise order,
+// starting with the right neighbour
+static NEIGHBOURS: [(isize, isize); 8] = [
+    (0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1),
+];
+
+/// Finds the borders of the objects in a binary mask, where non-zero pixels
+/// are the foreground.
+///
+/// Every border is returned as the (x, y) coordinates of its pixels in the
+/// order in which it is traced, the outer border of an object counterclockwise
+/// and the border of a hole in it clockwise, as seen with the y axis pointing
+/// down. Borders are found in raster order, thus an outer border comes
+/// before the borders of its holes.
+/// A single pixel object has a border of one point.
+///
+/// This is the border following algorithm of Suzuki and Abe, "Topological
+/// structural analysis of digitized binary images by border following".
+pub fn find_contours(image: &GrayImage) -> Vec<Vec<(u32, u32)>> {
+    let (width, height) = image.dimensions();
+
+    // The labels of the pixels with a frame of background around the image
+    let stride = width as usize + 2;
+    let mut f = vec![0i32; stride * (height as usize + 2)];
+    for (x, y, p) in image.enumerate_pixels() {
+        if p.data[0] != 0 {
+            f[(y as usize + 1) * stride + x as usize + 1] = 1;
+        }
+    }
+
+    let step = |pos: usize, dir: usize| {
+        let (dy, dx) = NEIGHBOURS[dir];
+        (pos as isize + dy * stride as isize + dx) as usize
+    };
+
+    let mut contours = Vec::new();
+    let mut nbd = 1;
+
+    for i in (1..height as usize + 1) {
+        for j in (1..width as usize + 1) {
+            let pos = i * stride + j;
+
+            // The direction of the background pixel next to a border start
+            let from = if f[pos] == 1 && f[pos - 1] == 0 {
+                4
+            } else if f[pos] >= 1 && f[pos + 1] == 0 {
+                0
+            } else {
+                continue
+            };
+
+            nbd += 1;
+            contours.push(follow_border(&mut f, stride, pos, from, nbd, &step));
+        }
+    }
+
+    contours
+}
+
+// Traces the border that starts at `start`, whose neighbour in direction
+// `from` is background, and labels its pixels with `nbd`
+fn follow_border<F>(f: &mut [i32], stride: usize, start: usize, from: usize,
+                    nbd: i32, step: &F) -> Vec<(u32, u32)>
+    where F: Fn(usize, usize) -> usize {
+
+    let point = |pos: usize| ((pos % stride - 1) as u32, (pos / stride - 1) as u32);
+
+    // Look clockwise for the first foreground neighbour
+    let first = (0..8).map(|k| (from + k) % 8).find(|&d| f[step(start, d)] != 0);
+    let first = match first {
+        Some(d) => d,
+        None => {
+            f[start] = -nbd;
+            return vec![point(start)]
+        }
+    };
+
+    let last = step(start, first);
+    let mut contour = Vec::new();
+    let mut current = start;
+    // The direction from `current` to the previous pixel of the border
+    let mut back = first;
+
+    loop {
+        contour.push(point(current));
+
+        // Look counterclockwise, starting after the previous pixel
+        let mut right_is_background = false;
+        let mut next = None;
+        for k in (1..9) {
+            let d = (back + 8 - k) % 8;
+            let pos = step(current, d);
+
+            if f[pos] != 0 {
+                next = Some((pos, d));
+                break
+            }
+            if d == 0 {
+                right_is_background = true;
+            }
+        }
+
+        if right_is_background {
+            f[current] = -nbd;
+        } else if f[current] == 1 {
+            f[current] = nbd;
+        }
+
+        // A foreground pixel is always found, the previous one at the latest
+        let (pos, d) = next.unwrap();
+        if pos == start && current == last {
+            break
+        }
+
+        current = pos;
+        back = (d + 4) % 8;
+    }
+
+    contour
+}
+
+/// Simplifies a closed contour to a polygon, keeping only the points that
+/// deviate by more than ```epsilon``` pixels from the simplified outline.
+///
+/// This is the algorithm of Ramer, Douglas and Peucker, applied to both
+/// halves of the contour between its first point and the point farthest
+/// from it.
+pub fn simplify_contour(contour: &[(u32, u32)], epsilon: f32) -> Vec<(u32, u32)> {
+    if contour.len() < 3 {
+        return contour.to_vec()
+    }
+
+    let distance = |a: (u32, u32), b: (u32, u32)| {
+        let (dx, dy) = (a.0 as f32 - b.0 as f32, a.1 as f32 - b.1 as f32);
+        (dx * dx + dy * dy).sqrt()
+    };
+
+    let far = (1..contour.len()).fold(0, |far, i| {
+        if distance(contour[0], contour[i]) > distance(contour[0], contour[far]) { i } else { far }
+    });
+
+    let mut keep = vec![false; contour.len()];
+    keep[0] = true;
+    keep[far] = true;
+
+    // The second half closes the contour back to its first point
+    let closed = contour.iter().chain(contour[..1].iter()).cloned().collect::<Vec<_>>();
+    let mut segments = vec![(0, far), (far, contour.len())];
+
+    while let Some((from, to)) = segments.pop() {
+        let (a, b) = (closed[from], closed[to]);
+        let length = distance(a, b);
+
+        let mut worst = None;
+        let mut max = epsilon;
+        for i in (from + 1..to) {
+            let p = closed[i];
+            // The distance to the line through a and b, or to a if they coincide
+            let d = if length == 0.0 {
+                distance(a, p)
+            } else {
+                ((b.0 as f32 - a.0 as f32) * (a.1 as f32 - p.1 as f32)
+                 - (a.0 as f32 - p.0 as f32) * (b.1 as f32 - a.1 as f32)).abs() / length
+            };
+
+            if d > max {
+                max = d;
+                worst = Some(i);
+            }
+        }
+
+        if let Some(i) = worst {
+            keep[i] = true;
+            segments.push((from, i));
+            segments.push((i, to));
+        }
+    }
+
+    contour.iter().zip(keep.iter()).filter(|&(_, &k)| k).map(|(&p, _)| p).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{GrayImage, ImageBuffer};
+    use color::Luma;
+    use super::{find_contours, simplify_contour};
+
+    fn mask(width: u32, height: u32, inside: &Fn(u32, u32) -> bool) -> GrayImage {
+        ImageBuffer::from_fn(width, height, |x, y| Luma([if inside(x, y) { 255u8 } else { 0 }]))
+    }
+
+    #[test]
+    fn test_rectangle() {
+        let image = mask(8, 6, &|x, y| x >= 2 && x < 6 && y >= 1 && y < 4);
+        let contours = find_contours(&image);
+
+        assert_eq!(contours.len(), 1);
+        assert_eq!(contours[0], vec![
+            (2, 1), (2, 2), (2, 3), (3, 3), (4, 3), (5, 3),
+            (5, 2), (5, 1), (4, 1), (3, 1),
+        ]);
+        assert_eq!(simplify_contour(&contours[0], 0.5), vec![(2, 1), (2, 3), (5, 3), (5, 1)]);
+    }
+
+    #[test]
+    fn test_holes_and_points() {
+        // A ring touching the image border and a separate pixel
+        let image = mask(7, 7, &|x, y| {
+            (x < 5 && y < 5 && !(x >= 1 && x < 4 && y >= 1 && y < 4)) || (x, y) == (6, 6)
+        });
+        let contours = find_contours(&image);
+
+        assert_eq!(contours.len(), 3);
+        assert_eq!(contours[0].len(), 16);
+        assert_eq!(contours[0][0], (0, 0));
+        // The hole is traced along the pixels of the ring
+        assert_eq!(contours[1].len(), 12);
+        assert!(contours[1].contains(&(4, 2)) && !contours[1].contains(&(2, 2)));
+        assert_eq!(contours[2], vec![(6, 6)]);
+
+        assert!(find_contours(&ImageBuffer::new(0, 0)).is_empty());
+        assert!(find_contours(&ImageBuffer::new(3, 3)).is_empty());
+    }
+
+    #[test]
+    fn test_thin_line() {
+        // Borders of one pixel wide objects visit their pixels twice
+        let image = mask(5, 3, &|x, y| y == 1 && x >= 1 && x < 4);
+        let contours = find_contours(&image);
+
+        assert_eq!(contours, vec![vec![(1, 1), (2, 1), (3, 1), (2, 1)]]);
+        assert_eq!(simplify_contour(&contours[0], 0.5), vec![(1, 1), (3, 1)]);
+    }
+}
@@ -0,0 +1,208 @@
+//! Distance transforms of binary masks
+use std::cmp;
+use std::f32;
+
+use buffer::{GrayImage, ImageBuffer};
+use color::Luma;
+
+/// Computes the exact Euclidean distance of every non-zero pixel of
+/// ```image``` to the nearest zero pixel. Zero pixels have a distance of 0.
+///
+/// The distances of a mask without zero pixels are infinite.
+///
+/// This is the linear time algorithm of Felzenszwalb and Huttenlocher,
+/// "Distance Transforms of Sampled Functions", applied to the columns
+/// and then to the rows.
+pub fn distance_transform(image: &GrayImage) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    let (width, height) = image.dimensions();
+    let (w, h) = (width as usize, height as usize);
+
+    // Squared distances
+    let mut d = image.pixels().map(|p| {
+        if p.data[0] == 0 { 0.0 } else { f32::INFINITY }
+    }).collect::<Vec<f32>>();
+
+    let mut f = vec![0.0f32; cmp::max(w, h)];
+    let mut out = vec![0.0f32; cmp::max(w, h)];
+    let mut v = vec![0usize; cmp::max(w, h)];
+    let mut z = vec![0.0f32; cmp::max(w, h) + 1];
+
+    for x in (0..w) {
+        for y in (0..h) {
+            f[y] = d[y * w + x];
+        }
+        distance_1d(&f[..h], &mut out[..h], &mut v, &mut z);
+        for y in (0..h) {
+            d[y * w + x] = out[y];
+        }
+    }
+
+    for y in (0..h) {
+        f[..w].copy_from_slice(&d[y * w..y * w + w]);
+        distance_1d(&f[..w], &mut out[..w], &mut v, &mut z);
+        d[y * w..y * w + w].copy_from_slice(&out[..w]);
+    }
+
+    let d = d.into_iter().map(|s| s.sqrt()).collect();
+    ImageBuffer::from_raw(width, height, d).unwrap()
+}
+
+// The squared distance transform of the sampled function `f`, that is the
+// lower envelope of the parabolas rooted at its finite samples
+fn distance_1d(f: &[f32], out: &mut [f32], v: &mut [usize], z: &mut [f32]) {
+    let n = f.len();
+
+    // The parabolas of the envelope and the boundaries between them
+    let mut k = 0;
+    let mut found = false;
+
+    for q in (0..n) {
+        if f[q] == f32::INFINITY {
+            continue
+        }
+
+        if !found {
+            v[0] = q;
+            z[0] = f32::NEG_INFINITY;
+            z[1] = f32::INFINITY;
+            found = true;
+            continue
+        }
+
+        let intersection = |p: usize| {
+            ((f[q] + (q * q) as f32) - (f[p] + (p * p) as f32)) / (2.0 * (q as f32 - p as f32))
+        };
+
+        let mut s = intersection(v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(v[k]);
+        }
+
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f32::INFINITY;
+    }
+
+    if !found {
+        for o in out.iter_mut() {
+            *o = f32::INFINITY;
+        }
+        return
+    }
+
+    k = 0;
+    for q in (0..n) {
+        while z[k + 1] < q as f32 {
+            k += 1;
+        }
+
+        let dq = q as f32 - v[k] as f32;
+        out[q] = dq * dq + f[v[k]];
+    }
+}
+
+/// Approximates the Euclidean distance of every non-zero pixel of
+/// ```image``` to the nearest zero pixel with the 3-4 chamfer metric,
+/// which is faster than ```distance_transform```. Straight steps count 1
+/// and diagonal steps 4/3, thus the error is at most about 8%.
+///
+/// The distances of a mask without zero pixels are infinite.
+pub fn chamfer_distance_transform(image: &GrayImage) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    let (width, height) = image.dimensions();
+    let (w, h) = (width as isize, height as isize);
+
+    let mut d = image.pixels().map(|p| {
+        if p.data[0] == 0 { 0.0 } else { f32::INFINITY }
+    }).collect::<Vec<f32>>();
+
+    let (straight, diagonal) = (1.0f32, 4.0f32 / 3.0);
+
+    // The neighbours visited before a pixel in a forward raster scan
+    let forward = [(-1, -1, diagonal), (0, -1, straight), (1, -1, diagonal), (-1, 0, straight)];
+
+    {
+        let mut relax = |x: isize, y: isize, sign: isize| {
+            let mut best = d[(y * w + x) as usize];
+
+            for &(dx, dy, cost) in forward.iter() {
+                let (nx, ny) = (x + sign * dx, y + sign * dy);
+
+                if nx >= 0 && nx < w && ny >= 0 && ny < h {
+                    let candidate = d[(ny * w + nx) as usize] + cost;
+                    if candidate < best {
+                        best = candidate;
+                    }
+                }
+            }
+
+            d[(y * w + x) as usize] = best;
+        };
+
+        for y in (0..h) {
+            for x in (0..w) {
+                relax(x, y, 1);
+            }
+        }
+
+        for y in (0..h).rev() {
+            for x in (0..w).rev() {
+                relax(x, y, -1);
+            }
+        }
+    }
+
+    ImageBuffer::from_raw(width, height, d).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use buffer::{GrayImage, ImageBuffer};
+    use color::Luma;
+    use super::{chamfer_distance_transform, distance_transform};
+
+    // A mask that is set everywhere but at the given points
+    fn mask(width: u32, height: u32, zeros: &[(u32, u32)]) -> GrayImage {
+        ImageBuffer::from_fn(width, height, |x, y| {
+            Luma([if zeros.contains(&(x, y)) { 0u8 } else { 255 }])
+        })
+    }
+
+    #[test]
+    fn test_distance_transform() {
+        let zeros = [(1, 1), (6, 4)];
+        let image = mask(9, 7, &zeros);
+        let d = distance_transform(&image);
+
+        for (x, y, p) in d.enumerate_pixels() {
+            let expected = zeros.iter().map(|&(zx, zy)| {
+                let (dx, dy) = (x as f32 - zx as f32, y as f32 - zy as f32);
+                (dx * dx + dy * dy).sqrt()
+            }).fold(f32::INFINITY, f32::min);
+
+            assert!((p[0] - expected).abs() < 1e-4, "({}, {}): {} != {}", x, y, p[0], expected);
+        }
+
+        let full = distance_transform(&mask(3, 2, &[]));
+        assert!(full.pixels().all(|p| p[0] == f32::INFINITY));
+        assert_eq!(distance_transform(&ImageBuffer::new(0, 0)).dimensions(), (0, 0));
+    }
+
+    #[test]
+    fn test_chamfer_distance_transform() {
+        let image = mask(9, 7, &[(4, 3)]);
+        let exact = distance_transform(&image);
+        let chamfer = chamfer_distance_transform(&image);
+
+        assert_eq!(chamfer.get_pixel(4, 3)[0], 0.0);
+        assert_eq!(chamfer.get_pixel(8, 3)[0], 4.0);
+        assert!((chamfer.get_pixel(6, 5)[0] - 8.0 / 3.0).abs() < 1e-4);
+
+        for (a, b) in exact.pixels().zip(chamfer.pixels()) {
+            assert!((a[0] - b[0]).abs() <= 0.09 * a[0]);
+        }
+    }
+}
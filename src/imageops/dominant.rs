@@ -0,0 +1,224 @@
+//! Extraction of the dominant colors of an image
+use std::f32;
+
+use buffer::Pixel;
+use color::Rgb;
+use image::GenericImage;
+
+// The maximum number of k-means iterations
+const ITERATIONS: usize = 20;
+
+/// A color that makes up part of an image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Swatch {
+    /// The mean color of the pixels the swatch stands for
+    pub color: Rgb<u8>,
+
+    /// The fraction of the pixels of the image the swatch stands for,
+    /// between 0 and 1
+    pub coverage: f32,
+}
+
+/// Finds up to ```k``` colors that represent ```image```, sorted by
+/// decreasing coverage. Fully transparent pixels are ignored.
+///
+/// The colors are clustered with k-means in the CIE L\*a\*b\* space, where
+/// distances follow the perceived color differences. The pixels are first
+/// reduced to a histogram of 15 bit colors, thus the cost hardly depends on
+/// the size of the image, and the clusters are seeded deterministically
+/// with the most frequent color and the colors farthest from the seeds so
+/// far, weighted by their frequency.
+pub fn dominant_colors<I>(image: &I, k: usize) -> Vec<Swatch>
+    where I: GenericImage, I::Pixel: Pixel<Subpixel=u8> {
+
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Vec::new()
+    }
+
+    // The pixel count and the sums of the channels of each histogram bin
+    let mut bins = vec![(0u32, [0u64; 3]); 1 << 15];
+    let mut total = 0u32;
+
+    for (_, _, pixel) in image.pixels() {
+        let p = pixel.to_rgba();
+        if p[3] == 0 {
+            continue
+        }
+
+        let index = (p[0] as usize >> 3) << 10 | (p[1] as usize >> 3) << 5 | p[2] as usize >> 3;
+        let bin = &mut bins[index];
+        bin.0 += 1;
+        for c in (0..3) {
+            bin.1[c] += p[c] as u64;
+        }
+        total += 1;
+    }
+
+    // The mean colors of the occupied bins
+    let colors = bins.iter().filter(|b| b.0 > 0).map(|&(count, sums)| {
+        let mean = [sums[0] as f32 / count as f32, sums[1] as f32 / count as f32, sums[2] as f32 / count as f32];
+        (count, sums, srgb_to_lab(mean))
+    }).collect::<Vec<_>>();
+
+    if colors.is_empty() || k == 0 {
+        return Vec::new()
+    }
+
+    let mut centers = seed(&colors, k);
+    let mut assignment = vec![0usize; colors.len()];
+
+    for iteration in (0..ITERATIONS) {
+        let mut changed = false;
+
+        for (a, &(_, _, lab)) in assignment.iter_mut().zip(colors.iter()) {
+            let nearest = nearest(&centers, lab);
+            if nearest != *a || iteration == 0 {
+                changed = true;
+            }
+            *a = nearest;
+        }
+
+        if !changed {
+            break
+        }
+
+        // Move the centers to the weighted means of their colors
+        let mut sums = vec![(0u32, [0f32; 3]); centers.len()];
+        for (&a, &(count, _, lab)) in assignment.iter().zip(colors.iter()) {
+            sums[a].0 += count;
+            for c in (0..3) {
+                sums[a].1[c] += lab[c] * count as f32;
+            }
+        }
+
+        for (center, &(count, sum)) in centers.iter_mut().zip(sums.iter()) {
+            if count > 0 {
+                *center = [sum[0] / count as f32, sum[1] / count as f32, sum[2] / count as f32];
+            }
+        }
+    }
+
+    // The swatches take the mean of their pixels in sRGB
+    let mut swatches = vec![(0u32, [0u64; 3]); centers.len()];
+    for (&a, &(count, sums, _)) in assignment.iter().zip(colors.iter()) {
+        swatches[a].0 += count;
+        for c in (0..3) {
+            swatches[a].1[c] += sums[c];
+        }
+    }
+
+    let mut swatches = swatches.into_iter().filter(|s| s.0 > 0).map(|(count, sums)| {
+        let mean = |c: usize| ((sums[c] + count as u64 / 2) / count as u64) as u8;
+
+        Swatch {
+            color: Rgb([mean(0), mean(1), mean(2)]),
+            coverage: count as f32 / total as f32,
+        }
+    }).collect::<Vec<_>>();
+
+    swatches.sort_by(|a, b| b.coverage.partial_cmp(&a.coverage).unwrap());
+    swatches
+}
+
+// Chooses up to `k` initial centers among `colors`
+fn seed(colors: &[(u32, [u64; 3], [f32; 3])], k: usize) -> Vec<[f32; 3]> {
+    let first = colors.iter().max_by_key(|c| c.0).unwrap();
+    let mut centers = vec![first.2];
+
+    // The squared distance of each color to its nearest center
+    let mut distances = colors.iter().map(|c| distance2(c.2, first.2)).collect::<Vec<f32>>();
+
+    while centers.len() < k {
+        let (index, weight) = colors.iter().zip(distances.iter()).enumerate()
+                                    .map(|(i, (c, &d))| (i, d * c.0 as f32))
+                                    .fold((0, 0.0), |best, x| if x.1 > best.1 { x } else { best });
+
+        // Every color is a center already
+        if weight == 0.0 {
+            break
+        }
+
+        let center = colors[index].2;
+        centers.push(center);
+
+        for (d, c) in distances.iter_mut().zip(colors.iter()) {
+            *d = d.min(distance2(c.2, center));
+        }
+    }
+
+    centers
+}
+
+fn nearest(centers: &[[f32; 3]], lab: [f32; 3]) -> usize {
+    centers.iter().enumerate().fold((0, f32::INFINITY), |best, (i, &center)| {
+        let d = distance2(center, lab);
+        if d < best.1 { (i, d) } else { best }
+    }).0
+}
+
+fn distance2(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (a[0] - b[0]) * (a[0] - b[0]) + (a[1] - b[1]) * (a[1] - b[1]) + (a[2] - b[2]) * (a[2] - b[2])
+}
+
+// Converts an sRGB color with channels from 0 to 255 to L*a*b* relative to
+// the D65 white point
+fn srgb_to_lab(rgb: [f32; 3]) -> [f32; 3] {
+    let linear = |v: f32| {
+        let v = v / 255.0;
+        if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(rgb[0]), linear(rgb[1]), linear(rgb[2]));
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{ImageBuffer, RgbaImage};
+    use color::{Rgb, Rgba};
+    use super::dominant_colors;
+
+    #[test]
+    fn test_dominant_colors() {
+        // Half red, 30% slightly varying blue, 20% white and a transparent row
+        let image: RgbaImage = ImageBuffer::from_fn(10, 11, |x, y| {
+            match (x, y) {
+                (_, 10) => Rgba([0, 255, 0, 0]),
+                (x, _) if x < 5 => Rgba([200, 20, 20, 255]),
+                (x, y) if x < 8 => Rgba([10, 20, 180 + (y % 2) as u8 * 2, 255]),
+                _ => Rgba([255, 255, 255, 255]),
+            }
+        });
+
+        let swatches = dominant_colors(&image, 3);
+        assert_eq!(swatches.len(), 3);
+        assert_eq!(swatches[0].color, Rgb([200, 20, 20]));
+        assert_eq!(swatches[1].color, Rgb([10, 20, 181]));
+        assert_eq!(swatches[2].color, Rgb([255, 255, 255]));
+
+        let coverage = swatches.iter().map(|s| s.coverage).collect::<Vec<_>>();
+        assert!((coverage[0] - 0.5).abs() < 1e-6 && (coverage[1] - 0.3).abs() < 1e-6);
+
+        // The shades of blue share a histogram bin
+        assert_eq!(dominant_colors(&image, 10).len(), 3);
+        assert_eq!(dominant_colors(&image, 1)[0].coverage, 1.0);
+        assert!(dominant_colors(&RgbaImage::new(3, 3), 2).is_empty());
+    }
+
+    #[test]
+    fn test_empty() {
+        for &(width, height) in [(0, 5), (5, 0), (0, 0)].iter() {
+            assert!(dominant_colors(&RgbaImage::new(width, height), 2).is_empty());
+        }
+    }
+}
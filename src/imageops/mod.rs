@@ -15,7 +15,8 @@ pub use self::sample::FilterType:: {
     Nearest,
     CatmullRom,
     Gaussian,
-    Lanczos3
+    Lanczos3,
+    DetailPreserving
 };
 
 /// Affine transformations
@@ -25,16 +26,67 @@ pub use self::affine:: {
     rotate270,
     flip_horizontal,
     flip_vertical,
+    letterbox,
+    AffineTransform,
 };
 
 /// Image sampling
 pub use self::sample:: {
     filter3x3,
     resize,
+    resize_linear,
     blur,
     unsharpen,
 };
 
+/// White balance
+pub use self::balance:: {
+    auto_white_balance,
+    WhiteBalance,
+};
+
+/// Borders and mats
+pub use self::border:: {
+    add_border,
+    mat,
+    Mat,
+    Shadow,
+};
+
+/// Orientation detection
+pub use self::orientation::detect_orientation_text;
+
+/// Contour extraction
+pub use self::contours:: {
+    find_contours,
+    simplify_contour,
+};
+
+/// Distance transforms
+pub use self::distance:: {
+    distance_transform,
+    chamfer_distance_transform,
+};
+
+/// Dominant colors
+pub use self::dominant:: {
+    dominant_colors,
+    Swatch,
+};
+
+/// Signed distance fields and feathering
+pub use self::sdf:: {
+    sdf,
+    msdf,
+    feather,
+};
+
+/// Tiling for processing large images in overlapping chunks
+pub use self::tiles:: {
+    tile_with_overlap,
+    stitch_predictions,
+};
+
 /// Color operations
 pub use self::colorops:: {
     grayscale,
@@ -45,13 +97,23 @@ pub use self::colorops:: {
     BiLevel,
     dither,
     index_colors,
+    Colormap,
+    colorize,
 };
 
 mod affine;
+mod balance;
+mod border;
+mod contours;
+mod distance;
+mod dominant;
 /// Public only because of Rust bug:
 /// https://github.com/rust-lang/rust/issues/18241
 pub mod colorops;
+mod orientation;
 mod sample;
+mod sdf;
+mod tiles;
 
 /// Return a mutable view into an image
 // TODO: Is a 'static bound on `I` really required? Acn we avoid it?
@@ -128,9 +190,10 @@ pub fn replace<I: GenericImage>(bottom: &mut I, top: &I, x: u32, y:u32) {
 mod tests {
 
     use image::GenericImage;
-    use buffer::ImageBuffer;
-    use color::{Rgb};
-    use super::overlay;
+    use buffer::{ImageBuffer, Gray16Image, GrayAlpha16Image};
+    use color::{Luma, LumaA, Rgb};
+    use super::{overlay, resize, blur, unsharpen, rotate90, flip_horizontal,
+                contrast, brighten, invert, grayscale, Triangle, Lanczos3};
 
     #[test]
     /// Test that images written into other images works
@@ -156,4 +219,53 @@ mod tests {
         assert!(*target.get_pixel(31, 31) == Rgb([255u8, 0, 0]));
     }
 
+    #[test]
+    /// Test that 16 bit grayscale images keep their full range
+    fn test_gray16() {
+        let img: Gray16Image = ImageBuffer::from_fn(8, 4, |x, _| Luma([x as u16 * 8000 + 4000]));
+
+        let resized = resize(&img, 4, 2, Triangle);
+        assert_eq!(resized.dimensions(), (4, 2));
+        assert!(resized.pixels().all(|p| p[0] > 255));
+
+        // Constant rows stay close to constant, without overflowing at the
+        // maximum. Filtered values are truncated, which loses a few levels of
+        // 16 bit samples to the limited precision of `f32`.
+        let flat: Gray16Image = ImageBuffer::from_pixel(6, 6, Luma([65535]));
+        assert!(resize(&flat, 13, 3, Lanczos3).pixels().all(|p| p[0] >= 65530));
+        assert!(blur(&flat, 1.5).pixels().all(|p| p[0] >= 65530));
+        assert!(unsharpen(&flat, 1.5, 10).pixels().all(|p| p[0] >= 65530));
+
+        let rotated = rotate90(&img);
+        assert_eq!(rotated.dimensions(), (4, 8));
+        assert_eq!(*rotated.get_pixel(3, 7), Luma([60000]));
+        assert_eq!(*flip_horizontal(&img).get_pixel(0, 0), Luma([60000]));
+
+        assert_eq!(*brighten(&img, 10000).get_pixel(7, 0), Luma([65535]));
+        assert_eq!(*contrast(&img, 0.0).get_pixel(3, 0), *img.get_pixel(3, 0));
+
+        let mut inverted = img.clone();
+        invert(&mut inverted);
+        assert_eq!(*inverted.get_pixel(0, 0), Luma([61535]));
+    }
+
+    #[test]
+    /// Test that operations on 16 bit grayscale + alpha images keep the alpha
+    fn test_gray_alpha16() {
+        let img: GrayAlpha16Image = ImageBuffer::from_fn(4, 4, |x, y| {
+            LumaA([x as u16 * 20000, y as u16 * 20000 + 1000])
+        });
+
+        let mut inverted = img.clone();
+        invert(&mut inverted);
+        assert_eq!(*inverted.get_pixel(1, 2), LumaA([45535, 41000]));
+
+        assert_eq!(*brighten(&img, 100).get_pixel(1, 2), LumaA([20100, 41000]));
+        assert_eq!(grayscale(&img).get_pixel(3, 0), &Luma([60000]));
+
+        let resized = resize(&img, 2, 2, Triangle);
+        assert!(resized.pixels().any(|p| p[1] > 255));
+        assert_eq!(blur(&img, 0.0).get_pixel(1, 2), img.get_pixel(1, 2));
+    }
+
 }
@@ -0,0 +1,211 @@
+//! Orientation detection for scanned text
+use std::cmp;
+
+use buffer::GrayImage;
+use imageops::rotate90;
+
+/// Guesses the orientation of a scanned page of horizontal text, for
+/// scanners that do not record it.
+///
+/// Returns the clockwise rotation in degrees, 0, 90, 180 or 270, that makes
+/// the text upright, or ```None``` if the image contains no text lines.
+///
+/// The ink is separated from the paper with Otsu's threshold. Text lines
+/// make the ink counts of the rows vary much more than those of the
+/// columns, which tells upright and upside down pages from rotated ones.
+/// The remaining ambiguity is resolved with the ascenders of latin scripts,
+/// which are more frequent than descenders: within a line more ink lies
+/// above the band of the lowercase letters than below it.
+pub fn detect_orientation_text(image: &GrayImage) -> Option<u32> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return None
+    }
+
+    let threshold = otsu_threshold(image);
+    let dark = image.pixels().filter(|p| p.data[0] <= threshold).count() as u64;
+
+    // The ink is the minority of the pixels, also for light text on dark paper
+    let dark_ink = 2 * dark <= width as u64 * height as u64;
+    let ink = |x: u32, y: u32| (image.get_pixel(x, y).data[0] <= threshold) == dark_ink;
+
+    let mut rows = vec![0u32; height as usize];
+    let mut columns = vec![0u32; width as usize];
+
+    for y in (0..height) {
+        for x in (0..width) {
+            if ink(x, y) {
+                rows[y as usize] += 1;
+                columns[x as usize] += 1;
+            }
+        }
+    }
+
+    let horizontal = variation(&rows, width) >= variation(&columns, height);
+
+    let upright = if horizontal {
+        is_upright(&rows)
+    } else {
+        // Rotating clockwise turns vertical lines into horizontal ones
+        let rotated = rotate90(image);
+        let rows = (0..width).map(|y| {
+            (0..height).filter(|&x| (rotated.get_pixel(x, y).data[0] <= threshold) == dark_ink)
+                       .count() as u32
+        }).collect::<Vec<u32>>();
+
+        is_upright(&rows)
+    };
+
+    let upright = match upright {
+        Some(upright) => upright,
+        None => return None
+    };
+
+    Some(match (horizontal, upright) {
+        (true, true) => 0,
+        (true, false) => 180,
+        (false, true) => 90,
+        (false, false) => 270,
+    })
+}
+
+// The threshold that best separates the two classes of the histogram
+fn otsu_threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for p in image.pixels() {
+        histogram[p.data[0] as usize] += 1;
+    }
+
+    let total = histogram.iter().fold(0, |a, &b| a + b) as f64;
+    let sum = histogram.iter().enumerate().fold(0.0, |a, (i, &n)| a + i as f64 * n as f64);
+
+    let (mut best, mut threshold) = (0.0, 0);
+    let (mut count, mut partial) = (0.0, 0.0);
+
+    for (i, &n) in histogram.iter().enumerate() {
+        count += n as f64;
+        partial += i as f64 * n as f64;
+
+        if count == 0.0 || count == total {
+            continue
+        }
+
+        let mean0 = partial / count;
+        let mean1 = (sum - partial) / (total - count);
+        let between = count * (total - count) * (mean0 - mean1) * (mean0 - mean1);
+
+        if between > best {
+            best = between;
+            threshold = i as u8;
+        }
+    }
+
+    threshold
+}
+
+// The variance of a projection profile, relative to the `length` of the
+// rows projected. Text lines alternate with empty gaps spanning the page,
+// whereas the gaps between letters of different lines do not line up.
+fn variation(profile: &[u32], length: u32) -> f64 {
+    let n = profile.len() as f64;
+    let mean = profile.iter().fold(0.0, |a, &p| a + p as f64) / n;
+
+    profile.iter().fold(0.0, |sum, &p| {
+        let d = (p as f64 - mean) / length as f64;
+        sum + d * d
+    }) / n
+}
+
+// Decides whether the horizontal text lines of the `rows` profile are
+// upright by comparing the ink above and below the core of each line.
+fn is_upright(rows: &[u32]) -> Option<bool> {
+    let peak = rows.iter().cloned().max().unwrap_or(0);
+    if peak == 0 {
+        return None
+    }
+
+    // Rows with less ink, like scanning noise, separate the lines
+    let line = cmp::max(1, peak / 20);
+
+    let (mut above, mut below) = (0u64, 0u64);
+    let mut y = 0;
+
+    while y < rows.len() {
+        if rows[y] < line {
+            y += 1;
+            continue
+        }
+
+        let start = y;
+        while y < rows.len() && rows[y] >= line {
+            y += 1;
+        }
+
+        let band = &rows[start..y];
+        let top = band.iter().cloned().max().unwrap();
+
+        // The core is where the lowercase letters are, covering most ink
+        let first = band.iter().position(|&n| 2 * n >= top).unwrap();
+        let last = band.iter().rposition(|&n| 2 * n >= top).unwrap();
+
+        above += band[..first].iter().fold(0, |a, &n| a + n as u64);
+        below += band[last + 1..].iter().fold(0, |a, &n| a + n as u64);
+    }
+
+    if above == below {
+        None
+    } else {
+        Some(above > below)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{GrayImage, ImageBuffer};
+    use color::Luma;
+    use imageops::{rotate90, rotate180, rotate270};
+    use super::detect_orientation_text;
+
+    // Lines of letters of varying widths with an x-height of 6 pixels,
+    // some of them with ascenders and fewer with descenders
+    fn page() -> GrayImage {
+        let mut image = ImageBuffer::from_pixel(120, 90, Luma([230u8]));
+
+        for line in (0..5u32) {
+            let base = 14 + line * 16;
+            let mut x = 4 + line;
+            let mut letter = line * 7;
+
+            while x < 110 {
+                let width = 2 + letter * 5 % 3;
+                let top = if letter % 3 == 0 { base - 10 } else { base - 6 };
+                let bottom = if letter % 7 == 0 { base + 3 } else { base };
+
+                for y in (top..bottom) {
+                    for dx in (0..width) {
+                        image.put_pixel(x + dx, y, Luma([20u8]));
+                    }
+                }
+
+                x += width + 1 + letter % 2;
+                letter += 1;
+            }
+        }
+
+        image
+    }
+
+    #[test]
+    fn test_detect_orientation_text() {
+        let upright = page();
+
+        assert_eq!(detect_orientation_text(&upright), Some(0));
+        assert_eq!(detect_orientation_text(&rotate90(&upright)), Some(270));
+        assert_eq!(detect_orientation_text(&rotate180(&upright)), Some(180));
+        assert_eq!(detect_orientation_text(&rotate270(&upright)), Some(90));
+
+        let blank = ImageBuffer::from_pixel(40, 30, Luma([230u8]));
+        assert_eq!(detect_orientation_text(&blank), None);
+        assert_eq!(detect_orientation_text(&ImageBuffer::new(0, 0)), None);
+    }
+}
@@ -10,7 +10,8 @@ use num:: {
     Float,
 };
 
-use buffer::{ImageBuffer, Pixel};
+use buffer::{ImageBuffer, Pixel, RgbaImage};
+use color::Rgba;
 use traits::Primitive;
 use image::GenericImage;
 use math::utils::clamp;
@@ -31,7 +32,13 @@ pub enum FilterType {
     Gaussian,
 
     /// Lanczos with window 3
-    Lanczos3
+    Lanczos3,
+
+    /// Detail-preserving downscaling, which keeps the contrast of fine
+    /// texture that the other filters average away when shrinking an
+    /// image many times, e.g. to thumbnails. Upscaling and ```resize_linear```
+    /// use Lanczos3.
+    DetailPreserving
 }
 
 /// A Representation of a separable filter.
@@ -129,6 +136,13 @@ pub fn box_kernel(x: f32) -> f32 {
     }
 }
 
+// Whether samples of type `S` are integers, which averaged values are
+// rounded to
+pub fn rounds_samples<S: Primitive>() -> bool {
+    let half: Option<S> = NumCast::from(0.5f32);
+    half.map_or(false, |h| h == S::zero())
+}
+
 // Sample the rows of the supplied image using the provided filter.
 // The height of the image remains unchanged.
 // ```new_width``` is the desired width of the new image
@@ -144,6 +158,11 @@ fn horizontal_sample<I, P, S>(image: &I, new_width: u32,
     let (width, height) = image.dimensions();
     let mut out = ImageBuffer::new(new_width, height);
 
+    // An empty image has no samples, the result is left zeroed
+    if width == 0 {
+        return out
+    }
+
     for y in (0..height) {
         let max = S::max_value();
         let max: f32 = NumCast::from(max).unwrap();
@@ -193,6 +212,14 @@ fn horizontal_sample<I, P, S>(image: &I, new_width: u32,
                 t.2 += vec.2 * w.2; t.3 += vec.3 * w.3;
             }
 
+            // None of the taps is covered by the filter when upscaling
+            // with a narrow filter, use the nearest pixel instead
+            if sum.0 == 0.0 {
+                let x0 = clamp(inputx as u32, 0, width - 1);
+                out.put_pixel(outx, y, image.get_pixel(x0, y));
+                continue
+            }
+
             let (t1, t2, t3, t4) = (t.0 / sum.0, t.1 / sum.1, t.2 / sum.2, t.3 / sum.3);
             let t = Pixel::from_channels(
                 NumCast::from(clamp(t1, 0.0, max)).unwrap(),
@@ -223,6 +250,10 @@ fn vertical_sample<I, P, S>(image: &I, new_height: u32,
     let (width, height) = image.dimensions();
     let mut out = ImageBuffer::new(width, new_height);
 
+    // An empty image has no samples, the result is left zeroed
+    if height == 0 {
+        return out
+    }
 
     for x in (0..width) {
         let max = S::max_value();
@@ -272,6 +303,14 @@ fn vertical_sample<I, P, S>(image: &I, new_height: u32,
                 t.2 += vec.2 * w.2; t.3 += vec.3 * w.3;
             }
 
+            // None of the taps is covered by the filter when upscaling
+            // with a narrow filter, use the nearest pixel instead
+            if sum.0 == 0.0 {
+                let y0 = clamp(inputy as u32, 0, height - 1);
+                out.put_pixel(x, outy, image.get_pixel(x, y0));
+                continue
+            }
+
             let (t1, t2, t3, t4) = (t.0 / sum.0, t.1 / sum.1, t.2 / sum.2, t.3 / sum.3);
             let t = Pixel::from_channels(
                 NumCast::from(clamp(t1, 0.0, max)).unwrap(),
@@ -309,15 +348,14 @@ pub fn filter3x3<I, P, S>(image: &I, kernel: &[f32])
 
     let max = S::max_value();
     let max: f32 = NumCast::from(max).unwrap();
-
     let sum = match kernel.iter().fold(0.0, |s, &item| s + item) {
         0.0 => 1.0,
         sum => sum
     };
     let sum = (sum, sum, sum, sum);
 
-    for y in (1..height - 1) {
-        for x in (1..width - 1) {
+    for y in (1..height.saturating_sub(1)) {
+        for x in (1..width.saturating_sub(1)) {
             let mut t = (0., 0., 0., 0.);
 
 
@@ -370,7 +408,223 @@ pub fn resize<I: GenericImage + 'static>(image: &I, nwidth: u32, nheight: u32,
     where I::Pixel: 'static,
           <I::Pixel as Pixel>::Subpixel: 'static {
 
-    let mut method = match filter {
+    if let FilterType::DetailPreserving = filter {
+        let (width, height) = image.dimensions();
+        if nwidth > 0 && nheight > 0 && nwidth <= width && nheight <= height {
+            return detail_preserving_resize(image, nwidth, nheight)
+        }
+    }
+
+    let mut method = sampling_filter(filter);
+
+    let tmp = vertical_sample(image, nheight, &mut method);
+    horizontal_sample(&tmp, nwidth, &mut method)
+}
+
+// Downscales `image` with the method of Weber et al., "Rapid,
+// Detail-Preserving Image Downscaling". Each output pixel averages the
+// pixels it covers, weighted by their distance from a smoothed box filtered
+// guide, thus pixels that stand out of their surroundings keep more of
+// their contrast.
+fn detail_preserving_resize<I, P, S>(image: &I, nwidth: u32, nheight: u32) -> ImageBuffer<P, Vec<S>>
+    where I: GenericImage<Pixel=P> + 'static,
+          P: Pixel<Subpixel=S> + 'static,
+          S: Primitive + 'static {
+
+    let (width, height) = image.dimensions();
+    let channels = P::channel_count() as usize;
+    let max: f32 = NumCast::from(S::max_value()).unwrap();
+    let mut samples = Vec::with_capacity(width as usize * height as usize * channels);
+    for y in (0..height) {
+        for x in (0..width) {
+            samples.extend(image.get_pixel(x, y).channels().iter().map(|&c| -> f32 { NumCast::from(c).unwrap() }));
+        }
+    }
+
+    let columns = footprints(width, nwidth);
+    let rows = footprints(height, nheight);
+    let pixel = |x: usize, y: usize| &samples[(y * width as usize + x) * channels..][..channels];
+
+    // The guide, the average of the pixels covered by each output pixel
+    let mut guide = vec![0f32; nwidth as usize * nheight as usize * channels];
+    for (oy, row) in rows.iter().enumerate() {
+        for (ox, column) in columns.iter().enumerate() {
+            let g = &mut guide[(oy * nwidth as usize + ox) * channels..][..channels];
+
+            for &(y, wy) in row.iter() {
+                for &(x, wx) in column.iter() {
+                    for (g, &v) in g.iter_mut().zip(pixel(x, y).iter()) {
+                        *g += v * wx * wy;
+                    }
+                }
+            }
+        }
+    }
+
+    let smoothed = smooth(&guide, nwidth as usize, nheight as usize, channels);
+    let distance_scale = max * (channels as f32).sqrt();
+
+    let round = rounds_samples::<S>();
+    let mut out = ImageBuffer::new(nwidth, nheight);
+    let mut sum = vec![0f32; channels];
+    let mut result = vec![S::zero(); channels];
+
+    for (oy, row) in rows.iter().enumerate() {
+        for (ox, column) in columns.iter().enumerate() {
+            let index = (oy * nwidth as usize + ox) * channels;
+            let g = &smoothed[index..index + channels];
+            let mut total = 0.0;
+
+            for v in sum.iter_mut() {
+                *v = 0.0;
+            }
+
+            for &(y, wy) in row.iter() {
+                for &(x, wx) in column.iter() {
+                    let p = pixel(x, y);
+                    let distance = p.iter().zip(g.iter()).fold(0.0, |d, (&a, &b)| d + (a - b) * (a - b)).sqrt();
+                    let w = wx * wy * (distance / distance_scale).powf(DETAIL_LAMBDA);
+
+                    for (s, &v) in sum.iter_mut().zip(p.iter()) {
+                        *s += v * w;
+                    }
+                    total += w;
+                }
+            }
+
+            // All pixels equal the guide, as in flat areas
+            for c in (0..channels) {
+                let v = if total > 0.0 { sum[c] / total } else { guide[index + c] };
+                let v = if round { v.round() } else { v };
+                result[c] = NumCast::from(clamp(v, 0.0, max)).unwrap();
+            }
+
+            out.put_pixel(ox as u32, oy as u32, *P::from_slice(&result));
+        }
+    }
+
+    out
+}
+
+// The exponent of the weights of detail-preserving downscaling, larger
+// values emphasize details more and 0 gives a box filter
+const DETAIL_LAMBDA: f32 = 0.5;
+
+// The pixels covered by each of `m` pixels downscaled from `n` pixels and
+// the fraction of the larger pixel they cover
+fn footprints(n: u32, m: u32) -> Vec<Vec<(usize, f32)>> {
+    let scale = n as f32 / m as f32;
+
+    (0..m).map(|x| {
+        let (left, right) = (x as f32 * scale, (x + 1) as f32 * scale);
+        let last = ::std::cmp::min(n as usize, right.ceil() as usize);
+
+        (left.floor() as usize..last).filter_map(|i| {
+            let covered = right.min(i as f32 + 1.0) - left.max(i as f32);
+            if covered > 0.0 { Some((i, covered / scale)) } else { None }
+        }).collect()
+    }).collect()
+}
+
+// Smooths the samples of a `width` by `height` image with the kernel
+// [1 2 1] in both directions, repeating the pixels at the edges
+fn smooth(samples: &[f32], width: usize, height: usize, channels: usize) -> Vec<f32> {
+    let mut out = vec![0f32; samples.len()];
+    let weights = [1.0 / 4.0, 2.0 / 4.0, 1.0 / 4.0];
+
+    for y in (0..height) {
+        for x in (0..width) {
+            let o = &mut out[(y * width + x) * channels..][..channels];
+
+            for (dy, wy) in weights.iter().enumerate() {
+                for (dx, wx) in weights.iter().enumerate() {
+                    let sy = clamp(y as i64 + dy as i64 - 1, 0, height as i64 - 1) as usize;
+                    let sx = clamp(x as i64 + dx as i64 - 1, 0, width as i64 - 1) as usize;
+                    let s = &samples[(sy * width + sx) * channels..][..channels];
+
+                    for (o, &v) in o.iter_mut().zip(s.iter()) {
+                        *o += v * wx * wy;
+                    }
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Resize the supplied RGBA image to the specified dimensions like
+/// ```resize```, but with the colors weighted by their alpha and
+/// interpolated in linear light instead of sRGB.
+///
+/// Fully transparent pixels have no influence on the color of their
+/// neighbours, thus downscaled sprites do not get dark halos from
+/// transparent black pixels, and fine patterns keep their brightness.
+// TODO: Do we really need the 'static bound on `I`? Can we avoid it?
+pub fn resize_linear<I>(image: &I, nwidth: u32, nheight: u32, filter: FilterType) -> RgbaImage
+    where I: GenericImage<Pixel=Rgba<u8>> + 'static {
+
+    let (width, height) = image.dimensions();
+    let to_linear = (0..256).map(|v| srgb_to_linear(v as f32 / 255.0)).collect::<Vec<f32>>();
+
+    // Premultiplied linear colors
+    let mut linear: ImageBuffer<Rgba<f32>, Vec<f32>> = ImageBuffer::new(width, height);
+    for y in (0..height) {
+        for x in (0..width) {
+            let p = image.get_pixel(x, y);
+            let a = p[3] as f32 / 255.0;
+
+            linear.put_pixel(x, y, Rgba([
+                to_linear[p[0] as usize] * a,
+                to_linear[p[1] as usize] * a,
+                to_linear[p[2] as usize] * a,
+                a
+            ]));
+        }
+    }
+
+    let mut method = sampling_filter(filter);
+    let tmp = vertical_sample(&linear, nheight, &mut method);
+    let tmp = horizontal_sample(&tmp, nwidth, &mut method);
+
+    let mut out = ImageBuffer::new(nwidth, nheight);
+    for (x, y, p) in tmp.enumerate_pixels() {
+        let a = clamp(p[3], 0.0, 1.0);
+
+        let channel = |c: f32| {
+            if a == 0.0 {
+                0
+            } else {
+                let v = linear_to_srgb(clamp(c / a, 0.0, 1.0));
+                (v * 255.0 + 0.5) as u8
+            }
+        };
+
+        out.put_pixel(x, y, Rgba([channel(p[0]), channel(p[1]), channel(p[2]), (a * 255.0 + 0.5) as u8]));
+    }
+
+    out
+}
+
+// The sRGB transfer functions for values between 0 and 1
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn sampling_filter(filter: FilterType) -> Filter<'static> {
+    match filter {
         FilterType::Nearest    =>   Filter {
             kernel: Box::new(box_kernel),
             support: 0.5
@@ -387,14 +641,11 @@ pub fn resize<I: GenericImage + 'static>(image: &I, nwidth: u32, nheight: u32,
             kernel: Box::new(gaussian_kernel),
             support: 3.0
         },
-        FilterType::Lanczos3   => Filter {
+        FilterType::Lanczos3 | FilterType::DetailPreserving => Filter {
             kernel: Box::new(lanczos3_kernel),
             support: 3.0
         },
-};
-
-    let tmp = vertical_sample(image, nheight, &mut method);
-    horizontal_sample(&tmp, nwidth, &mut method)
+    }
 }
 
 /// Performs a Gaussian blur on the supplied image.
@@ -411,13 +662,26 @@ pub fn blur<I: GenericImage + 'static>(image: &I, sigma: f32)
         sigma
     };
 
+    let (width, height) = image.dimensions();
+
+    // Without blurring the filter has no width, the image is copied
+    if sigma == 0.0 {
+        let mut out = ImageBuffer::new(width, height);
+
+        for y in (0..height) {
+            for x in (0..width) {
+                out.put_pixel(x, y, image.get_pixel(x, y));
+            }
+        }
+
+        return out
+    }
+
     let mut method = Filter {
         kernel: Box::new(|x| gaussian(x, sigma)),
         support: 2.0 * sigma
     };
 
-    let (width, height) = image.dimensions();
-
     // Keep width and height the same for horizontal and
     // vertical sampling.
     let tmp = vertical_sample(image, height, &mut method);
@@ -471,8 +735,10 @@ pub fn unsharpen<I, P, S>(image: &I, sigma: f32, threshold: i32)
 #[cfg(test)]
 mod tests {
     use test;
-    use buffer::{ImageBuffer, RgbImage};
-    use super::{resize, FilterType};
+    use buffer::{GrayImage, ImageBuffer, RgbImage};
+    use image::GenericImage;
+    use color::{Luma, Rgba};
+    use super::{blur, filter3x3, resize, resize_linear, FilterType};
     use std::path::Path;
 
     #[bench]
@@ -491,4 +757,70 @@ mod tests {
         let _ = resize(&img, 50, 50, FilterType::Lanczos3);
     }
 
+    #[test]
+    fn test_resize_linear() {
+        // Transparent black does not darken the opaque red
+        let sprite = ImageBuffer::from_fn(4, 1, |x, _| {
+            if x % 2 == 0 { Rgba([255u8, 0, 0, 255]) } else { Rgba([0u8, 0, 0, 0]) }
+        });
+        let p = *resize_linear(&sprite, 1, 1, FilterType::Triangle).get_pixel(0, 0);
+        assert_eq!(p[0], 255);
+        assert!(p[3] > 64 && p[3] < 192);
+
+        // Averaging black and white in linear light gives 188 in sRGB
+        let checker = ImageBuffer::from_fn(4, 4, |x, y| {
+            if (x + y) % 2 == 0 { Rgba([255u8, 255, 255, 255]) } else { Rgba([0u8, 0, 0, 255]) }
+        });
+        let p = *resize_linear(&checker, 1, 1, FilterType::Triangle).get_pixel(0, 0);
+        assert!(p[0] >= 186 && p[0] <= 190);
+        assert_eq!(p[3], 255);
+
+        assert_eq!(resize_linear(&checker, 9, 7, FilterType::Lanczos3).dimensions(), (9, 7));
+    }
+
+    #[test]
+    fn test_detail_preserving() {
+        // Gray with a bright dot in each block of 10x10 pixels
+        let img: GrayImage = ImageBuffer::from_fn(40, 40, |x, y| {
+            Luma([if x % 10 == 3 && y % 10 == 6 { 255 } else { 128 }])
+        });
+
+        let lanczos = resize(&img, 4, 4, FilterType::Lanczos3);
+        let detail = resize(&img, 4, 4, FilterType::DetailPreserving);
+        assert!(lanczos.pixels().all(|p| p[0] <= 131));
+        assert!(detail.pixels().all(|p| p[0] >= 136 && p[0] <= 142));
+
+        // Flat images stay flat
+        let flat: GrayImage = ImageBuffer::from_pixel(30, 20, Luma([77]));
+        assert!(resize(&flat, 7, 3, FilterType::DetailPreserving).pixels().all(|p| p[0] == 77));
+
+        // Upscaling falls back to Lanczos3
+        assert_eq!(resize(&img, 50, 20, FilterType::DetailPreserving).dimensions(), (50, 20));
+    }
+
+    #[test]
+    fn test_tiny_images() {
+        let filters = [FilterType::Nearest, FilterType::Triangle, FilterType::CatmullRom,
+                       FilterType::Gaussian, FilterType::Lanczos3];
+
+        for &(w, h) in [(0, 0), (0, 5), (5, 0), (1, 1), (1, 5), (5, 1)].iter() {
+            let img: RgbImage = ImageBuffer::new(w, h);
+
+            for &filter in filters.iter() {
+                assert_eq!(resize(&img, 3, 3, filter).dimensions(), (3, 3));
+                assert_eq!(resize(&img, 0, 0, filter).dimensions(), (0, 0));
+            }
+
+            assert_eq!(blur(&img, 0.0).dimensions(), (w, h));
+            assert_eq!(blur(&img, 1.0).dimensions(), (w, h));
+            assert_eq!(filter3x3(&img, &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]).dimensions(), (w, h));
+        }
+
+        // Upscaling a single pixel keeps its value
+        let img: RgbImage = ImageBuffer::from_pixel(1, 1, ::Rgb([10, 20, 30]));
+        for &filter in filters.iter() {
+            assert!(resize(&img, 3, 3, filter).pixels().all(|p| p.data == [10, 20, 30]));
+        }
+    }
+
 }
@@ -0,0 +1,271 @@
+//! Signed distance fields of binary masks, as used for glyph and icon
+//! atlases, and feathering of masks
+use std::f32;
+
+use buffer::{GrayImage, ImageBuffer, RgbImage};
+use color::{Luma, Rgb};
+
+use super::contours::{find_contours, simplify_contour};
+use super::distance::distance_transform;
+
+// The maximum deviation of the edges of an MSDF from the traced contours
+const MSDF_EPSILON: f32 = 0.75;
+
+// The channels of the edge colors of an MSDF. Adjacent edges of a contour
+// always share exactly one channel.
+const RED: u8 = 1;
+const GREEN: u8 = 2;
+const BLUE: u8 = 4;
+const EDGE_COLORS: [u8; 3] = [RED | GREEN, RED | BLUE, GREEN | BLUE];
+
+/// Computes the signed distance field of ```mask```, where non-zero pixels
+/// are inside.
+///
+/// The distance of every pixel to the edge of the mask, which lies halfway
+/// between inside and outside pixels, is positive inside and negative
+/// outside. It is mapped linearly to a sample, 128 at the edge, 255 at
+/// ```spread``` pixels inside and 0 at ```spread``` pixels outside.
+/// Distances beyond the spread are clamped.
+pub fn sdf(mask: &GrayImage, spread: f32) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let d = signed_distances(mask);
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        Luma([encode(d[(y * width + x) as usize], spread)])
+    })
+}
+
+/// Softens the edges of ```mask```, where non-zero pixels are inside.
+///
+/// The samples rise smoothly from 0 at ```radius``` pixels outside the
+/// edge of the mask to 255 at ```radius``` pixels inside it, following
+/// the smoothstep curve of the signed distance to the edge. A radius of 0
+/// returns the mask with all non-zero pixels set to 255.
+pub fn feather(mask: &GrayImage, radius: f32) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let d = signed_distances(mask);
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let d = d[(y * width + x) as usize];
+
+        let v = if radius <= 0.0 {
+            if d > 0.0 { 1.0 } else { 0.0 }
+        } else {
+            let t = ((d + radius) / (2.0 * radius)).max(0.0).min(1.0);
+            t * t * (3.0 - 2.0 * t)
+        };
+
+        Luma([(v * 255.0).round() as u8])
+    })
+}
+
+// The distances of the pixels of `mask` to its edge, which lies halfway
+// between inside and outside pixels, positive inside and negative outside
+fn signed_distances(mask: &GrayImage) -> Vec<f32> {
+    let (width, height) = mask.dimensions();
+    let inside = distance_transform(mask);
+    let outside = distance_transform(&ImageBuffer::from_fn(width, height, |x, y| {
+        Luma([if mask.get_pixel(x, y)[0] == 0 { 255u8 } else { 0 }])
+    }));
+
+    mask.pixels().zip(inside.pixels().zip(outside.pixels())).map(|(p, (i, o))| {
+        if p[0] != 0 { i[0] - 0.5 } else { 0.5 - o[0] }
+    }).collect()
+}
+
+/// Computes a multi-channel signed distance field of ```mask```, where
+/// non-zero pixels are inside.
+///
+/// The borders of the mask are traced and simplified to polygons, whose
+/// edges are colored with two of the three channels each such that
+/// neighbouring edges share one channel. Every channel holds the signed
+/// pseudo-distance, the distance to the line through the nearest edge of
+/// its color, encoded like ```sdf```.
+/// The median of the three channels reconstructs the shape with sharp
+/// corners even when the field is magnified.
+///
+/// This is a basic version of the generator of Chlumský, "Shape
+/// Decomposition for Multi-channel Distance Fields". It compares every
+/// pixel to every edge, thus it is meant for glyph and icon sized masks.
+pub fn msdf(mask: &GrayImage, spread: f32) -> RgbImage {
+    let (width, height) = mask.dimensions();
+    let edges = colored_edges(mask);
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let p = (x as f32, y as f32);
+        let inside = mask.get_pixel(x, y)[0] != 0;
+        let mut channels = [0u8; 3];
+
+        for (i, &channel) in [RED, GREEN, BLUE].iter().enumerate() {
+            // The distance to the nearest edge, how orthogonally p lies to
+            // it and the signed pseudo-distance to its line
+            let mut best: Option<(f32, f32, f32)> = None;
+
+            for &(a, b, color) in edges.iter() {
+                if color & channel == 0 {
+                    continue
+                }
+
+                let (distance, orthogonality, pseudo) = edge_distance(a, b, p);
+                let closer = match best {
+                    None => true,
+                    Some((d, o, _)) => distance < d - 1e-4
+                        || (distance < d + 1e-4 && orthogonality > o)
+                };
+
+                if closer {
+                    best = Some((distance, orthogonality, pseudo));
+                }
+            }
+
+            let d = match best {
+                // Points on the line of an edge take the side of the mask
+                Some((_, _, pseudo)) => {
+                    let positive = if pseudo == 0.0 { inside } else { pseudo > 0.0 };
+                    if positive { pseudo.abs() + 0.5 } else { 0.5 - pseudo.abs() }
+                }
+                None if inside => f32::INFINITY,
+                None => f32::NEG_INFINITY,
+            };
+
+            channels[i] = encode(d, spread);
+        }
+
+        Rgb(channels)
+    })
+}
+
+// Maps a signed distance to a sample
+fn encode(d: f32, spread: f32) -> u8 {
+    let v = 127.5 + d * 127.5 / spread;
+
+    if v <= 0.0 {
+        0
+    } else if v >= 255.0 {
+        255
+    } else {
+        v.round() as u8
+    }
+}
+
+// The edges of the simplified borders of `mask` with their colors
+fn colored_edges(mask: &GrayImage) -> Vec<((f32, f32), (f32, f32), u8)> {
+    let mut edges = Vec::new();
+
+    for contour in find_contours(mask) {
+        let polygon = simplify_contour(&contour, MSDF_EPSILON);
+        let n = polygon.len();
+
+        for i in (0..n) {
+            // A contour without corners is smooth and gets all channels.
+            // Otherwise the colors cycle, with the last edge of a contour
+            // of 3k + 1 edges changed to differ from the first one.
+            let color = if n == 1 {
+                RED | GREEN | BLUE
+            } else if n % 3 == 1 && i == n - 1 {
+                EDGE_COLORS[1]
+            } else {
+                EDGE_COLORS[i % 3]
+            };
+
+            let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+            edges.push(((a.0 as f32, a.1 as f32), (b.0 as f32, b.1 as f32), color));
+        }
+    }
+
+    edges
+}
+
+// The distance of `p` to the edge from `a` to `b`, the absolute sine of the
+// angle between the edge and the direction to its nearest point, and the
+// distance to the line through the edge, positive towards the inside of
+// the border
+fn edge_distance(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> (f32, f32, f32) {
+    let (ex, ey) = (b.0 - a.0, b.1 - a.1);
+    let (px, py) = (p.0 - a.0, p.1 - a.1);
+    let length2 = ex * ex + ey * ey;
+
+    let t = if length2 == 0.0 { 0.0 } else { ((px * ex + py * ey) / length2).max(0.0).min(1.0) };
+    let (dx, dy) = (px - t * ex, py - t * ey);
+    let distance = (dx * dx + dy * dy).sqrt();
+
+    // Outer borders are traced counterclockwise with the y axis pointing
+    // down, thus the inside is to the right of the edges
+    let cross = ex * py - ey * px;
+    let pseudo = if length2 == 0.0 { 0.0 } else { -cross / length2.sqrt() };
+
+    let orthogonality = if distance == 0.0 || length2 == 0.0 {
+        1.0
+    } else {
+        (ex * dy - ey * dx).abs() / (length2.sqrt() * distance)
+    };
+
+    (distance, orthogonality, pseudo)
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{GrayImage, ImageBuffer};
+    use color::Luma;
+    use super::{feather, msdf, sdf};
+
+    fn square() -> GrayImage {
+        ImageBuffer::from_fn(16, 16, |x, y| {
+            Luma([if x >= 4 && x < 12 && y >= 4 && y < 12 { 255u8 } else { 0 }])
+        })
+    }
+
+    fn median(a: u8, b: u8, c: u8) -> u8 {
+        ::std::cmp::max(::std::cmp::min(a, b), ::std::cmp::min(::std::cmp::max(a, b), c))
+    }
+
+    #[test]
+    fn test_sdf() {
+        let field = sdf(&square(), 4.0);
+
+        // Half a pixel on either side of the edge
+        assert_eq!(field.get_pixel(4, 8)[0], 143);
+        assert_eq!(field.get_pixel(3, 8)[0], 112);
+        assert_eq!(field.get_pixel(7, 8)[0], 239);
+        assert_eq!(field.get_pixel(0, 8)[0], 16);
+        assert_eq!(field.get_pixel(0, 0)[0], 0);
+
+        assert!(sdf(&ImageBuffer::new(4, 4), 2.0).pixels().all(|p| p[0] == 0));
+    }
+
+    #[test]
+    fn test_msdf() {
+        let mask = square();
+        let field = msdf(&mask, 4.0);
+        let plain = sdf(&mask, 4.0);
+
+        for (x, y, p) in field.enumerate_pixels() {
+            let m = median(p[0], p[1], p[2]);
+            assert_eq!(m >= 128, mask.get_pixel(x, y)[0] != 0, "({}, {})", x, y);
+
+            // Away from the corners the median is the plain distance
+            if (x >= 5 && x < 11) || (y >= 5 && y < 11) {
+                assert!((m as i32 - plain.get_pixel(x, y)[0] as i32).abs() <= 1, "({}, {})", x, y);
+            }
+        }
+
+        // The channels differ near a corner
+        let corner = field.get_pixel(2, 3);
+        assert!(corner[0] != corner[1] || corner[1] != corner[2]);
+    }
+
+    #[test]
+    fn test_feather() {
+        let mask = square();
+        let soft = feather(&mask, 2.0);
+
+        // Symmetric around the edge, flat beyond the radius
+        assert_eq!(soft.get_pixel(3, 8)[0] as u32 + soft.get_pixel(4, 8)[0] as u32, 255);
+        assert_eq!(soft.get_pixel(4, 8)[0], 174);
+        assert_eq!(soft.get_pixel(6, 8)[0], 255);
+        assert_eq!(soft.get_pixel(1, 8)[0], 0);
+
+        let hard = feather(&mask, 0.0);
+        assert!(hard.pixels().zip(mask.pixels()).all(|(a, b)| a[0] == b[0]));
+    }
+}
@@ -0,0 +1,182 @@
+//! Splitting large images into overlapping tiles and stitching the results
+//! of processing each tile back together, e.g. to run segmentation models
+//! that take a fixed input size. Overlapping tiles give each pixel context
+//! on all sides, and blending them hides the seams between the outputs.
+
+use num::NumCast;
+
+use buffer::{ImageBuffer, Pixel};
+use image::GenericImage;
+use math::Rect;
+
+use super::sample::rounds_samples;
+
+/// Splits ```image``` into tiles of ```size``` by ```size``` pixels, each
+/// overlapping its neighbours by at least ```overlap``` pixels, and returns
+/// the rectangle of each tile with a copy of its pixels, row by row.
+///
+/// The last tiles of each row and column are moved back to end at the edge
+/// of the image, thus all tiles are of the same size unless the image is
+/// smaller than a tile, in which case they are as wide or as high as the
+/// image.
+///
+/// # Panics
+///
+/// Panics if ```overlap``` is not less than ```size```.
+pub fn tile_with_overlap<I: GenericImage + 'static>(image: &I, size: u32, overlap: u32)
+    -> Vec<(Rect, ImageBuffer<I::Pixel, Vec<<I::Pixel as Pixel>::Subpixel>>)>
+    where I::Pixel: 'static,
+          <I::Pixel as Pixel>::Subpixel: 'static {
+
+    assert!(overlap < size, "the overlap must be less than the tile size");
+
+    let (width, height) = image.dimensions();
+    let mut tiles = Vec::new();
+
+    for &y in offsets(height, size, overlap).iter() {
+        for &x in offsets(width, size, overlap).iter() {
+            let rect = Rect::new(x, y, size.min(width), size.min(height));
+            let tile = ImageBuffer::from_fn(rect.width, rect.height, |tx, ty| {
+                image.get_pixel(x + tx, y + ty)
+            });
+
+            tiles.push((rect, tile));
+        }
+    }
+
+    tiles
+}
+
+/// Stitches the ```tiles``` of an image of ```width``` by ```height```
+/// pixels back together, e.g. the predictions of a model for the tiles of
+/// ```tile_with_overlap```, which need not have the pixel type of the image.
+///
+/// Where tiles overlap their pixels are blended, weighted by their distance
+/// to the edges of the tile that are inside the image, thus each tile fades
+/// out towards its neighbours. Pixels that no tile covers are zero.
+///
+/// # Panics
+///
+/// Panics if a tile is not as large as its rectangle or does not lie within
+/// the image.
+pub fn stitch_predictions<P>(tiles: &[(Rect, ImageBuffer<P, Vec<P::Subpixel>>)], width: u32, height: u32)
+    -> ImageBuffer<P, Vec<P::Subpixel>>
+    where P: Pixel + 'static,
+          P::Subpixel: 'static {
+
+    let channels = P::channel_count() as usize;
+    let mut sums = vec![0f32; width as usize * height as usize * channels];
+    let mut weights = vec![0f32; width as usize * height as usize];
+
+    for &(rect, ref tile) in tiles.iter() {
+        assert!(tile.dimensions() == (rect.width, rect.height), "a tile differs in size from its rectangle");
+        assert!(rect.fits_within(width, height), "a tile lies outside of the image");
+
+        // Edges at the border of the image have no neighbour to fade into
+        let distance = |t: u32, start: u32, len: u32, end: u32| {
+            let before = if start == 0 { u32::max_value() } else { t + 1 };
+            let after = if start + len == end { u32::max_value() } else { len - t };
+            before.min(after)
+        };
+
+        for (tx, ty, p) in tile.enumerate_pixels() {
+            let weight = distance(tx, rect.x, rect.width, width).min(distance(ty, rect.y, rect.height, height));
+            let weight = weight.min(rect.width.max(rect.height)) as f32;
+            let i = ((rect.y + ty) * width + rect.x + tx) as usize;
+
+            weights[i] += weight;
+            for (c, &v) in p.channels().iter().enumerate() {
+                let v: f32 = NumCast::from(v).unwrap();
+                sums[i * channels + c] += v * weight;
+            }
+        }
+    }
+
+    let round = rounds_samples::<P::Subpixel>();
+    let mut out: ImageBuffer<P, Vec<P::Subpixel>> = ImageBuffer::new(width, height);
+
+    for (x, y, p) in out.enumerate_pixels_mut() {
+        let i = (y * width + x) as usize;
+        if weights[i] == 0.0 {
+            continue
+        }
+
+        for (c, v) in p.channels_mut().iter_mut().enumerate() {
+            let blended = sums[i * channels + c] / weights[i];
+            *v = NumCast::from(if round { blended.round() } else { blended }).unwrap();
+        }
+    }
+
+    out
+}
+
+// The offsets of tiles of `size` pixels overlapping by `overlap` pixels
+// along `len` pixels, the last one ending at `len`
+fn offsets(len: u32, size: u32, overlap: u32) -> Vec<u32> {
+    if len <= size {
+        return vec![0]
+    }
+
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+
+    while offset + size < len {
+        offsets.push(offset);
+        offset += size - overlap;
+    }
+
+    offsets.push(len - size);
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{GrayImage, ImageBuffer, RgbImage};
+    use color::{Luma, Rgb};
+    use super::{offsets, stitch_predictions, tile_with_overlap};
+
+    #[test]
+    fn test_offsets() {
+        assert_eq!(offsets(10, 16, 4), vec![0]);
+        assert_eq!(offsets(16, 16, 4), vec![0]);
+        assert_eq!(offsets(40, 16, 4), vec![0, 12, 24]);
+        assert_eq!(offsets(41, 16, 4), vec![0, 12, 24, 25]);
+    }
+
+    #[test]
+    fn test_tiles() {
+        let image: RgbImage = ImageBuffer::from_fn(40, 20, |x, y| Rgb([x as u8, y as u8, 0]));
+        let tiles = tile_with_overlap(&image, 16, 4);
+
+        assert_eq!(tiles.len(), 3 * 2);
+        for &(rect, ref tile) in tiles.iter() {
+            assert_eq!(tile.dimensions(), (16, 16));
+            assert_eq!(tile[(3, 5)], Rgb([rect.x as u8 + 3, rect.y as u8 + 5, 0]));
+        }
+
+        // The identity prediction stitches back to the image
+        assert!(stitch_predictions(&tiles, 40, 20).into_raw() == image.into_raw());
+    }
+
+    #[test]
+    fn test_blend() {
+        let image = GrayImage::new(28, 16);
+        let tiles = tile_with_overlap(&image, 16, 4);
+
+        // Predictions that disagree where the tiles overlap
+        let predictions = tiles.iter().enumerate().map(|(i, &(rect, _))| {
+            (rect, ImageBuffer::from_pixel(rect.width, rect.height, Luma([if i == 0 { 0u8 } else { 200 }])))
+        }).collect::<Vec<_>>();
+        let stitched = stitch_predictions(&predictions, 28, 16);
+
+        // Only the first tile covers the left edge, only the second the right
+        assert_eq!(stitched[(0, 8)][0], 0);
+        assert_eq!(stitched[(27, 8)][0], 200);
+
+        // The overlap from 12 to 15 fades from one tile into the other
+        let row = (11..17).map(|x| stitched[(x, 8)][0]).collect::<Vec<_>>();
+        assert_eq!(row[0], 0);
+        assert!(row.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(row[5], 200);
+    }
+}
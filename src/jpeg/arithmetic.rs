@@ -0,0 +1,642 @@
+//! Arithmetic entropy coding of sequential DCT scans
+//!
+//! The QM coder of Annex D codes binary decisions with probabilities that
+//! adapt to the statistics of the image, which typically makes the scan
+//! a few percent smaller than with Huffman codes. The decisions of each
+//! block follow the model of Section F.1.4, with the default conditioning
+//! of the DC and AC coefficients, thus no DAC segment is needed.
+
+use std::io::{self, Write};
+
+use super::decoder::UNZIGZAG;
+
+// Table D.2, the probability estimate of each state with the next states
+// after a more and a less probable symbol and whether the less probable
+// symbol switches the sense of the more probable one. The last state has a
+// fixed estimate of one half and codes the signs of AC coefficients.
+static QE_TABLE: [(u32, u8, u8, u8); 114] = [
+    (0x5a1d, 1, 1, 1), (0x2586, 2, 14, 0), (0x1114, 3, 16, 0), (0x080b, 4, 18, 0),
+    (0x03d8, 5, 20, 0), (0x01da, 6, 23, 0), (0x00e5, 7, 25, 0), (0x006f, 8, 28, 0),
+    (0x0036, 9, 30, 0), (0x001a, 10, 33, 0), (0x000d, 11, 35, 0), (0x0006, 12, 9, 0),
+    (0x0003, 13, 10, 0), (0x0001, 13, 12, 0), (0x5a7f, 15, 15, 1), (0x3f25, 16, 36, 0),
+    (0x2cf2, 17, 38, 0), (0x207c, 18, 39, 0), (0x17b9, 19, 40, 0), (0x1182, 20, 42, 0),
+    (0x0cef, 21, 43, 0), (0x09a1, 22, 45, 0), (0x072f, 23, 46, 0), (0x055c, 24, 48, 0),
+    (0x0406, 25, 49, 0), (0x0303, 26, 51, 0), (0x0240, 27, 52, 0), (0x01b1, 28, 54, 0),
+    (0x0144, 29, 56, 0), (0x00f5, 30, 57, 0), (0x00b7, 31, 59, 0), (0x008a, 32, 60, 0),
+    (0x0068, 33, 62, 0), (0x004e, 34, 63, 0), (0x003b, 35, 32, 0), (0x002c, 9, 33, 0),
+    (0x5ae1, 37, 37, 1), (0x484c, 38, 64, 0), (0x3a0d, 39, 65, 0), (0x2ef1, 40, 67, 0),
+    (0x261f, 41, 68, 0), (0x1f33, 42, 69, 0), (0x19a8, 43, 70, 0), (0x1518, 44, 72, 0),
+    (0x1177, 45, 73, 0), (0x0e74, 46, 74, 0), (0x0bfb, 47, 75, 0), (0x09f8, 48, 77, 0),
+    (0x0861, 49, 78, 0), (0x0706, 50, 79, 0), (0x05cd, 51, 48, 0), (0x04de, 52, 50, 0),
+    (0x040f, 53, 50, 0), (0x0363, 54, 51, 0), (0x02d4, 55, 52, 0), (0x025c, 56, 53, 0),
+    (0x01f8, 57, 54, 0), (0x01a4, 58, 55, 0), (0x0160, 59, 56, 0), (0x0125, 60, 57, 0),
+    (0x00f6, 61, 58, 0), (0x00cb, 62, 59, 0), (0x00ab, 63, 61, 0), (0x008f, 32, 61, 0),
+    (0x5b12, 65, 65, 1), (0x4d04, 66, 80, 0), (0x412c, 67, 81, 0), (0x37d8, 68, 82, 0),
+    (0x2fe8, 69, 83, 0), (0x293c, 70, 84, 0), (0x2379, 71, 86, 0), (0x1edf, 72, 87, 0),
+    (0x1aa9, 73, 87, 0), (0x174e, 74, 72, 0), (0x1424, 75, 72, 0), (0x119c, 76, 74, 0),
+    (0x0f6b, 77, 74, 0), (0x0d51, 78, 75, 0), (0x0bb6, 79, 77, 0), (0x0a40, 48, 77, 0),
+    (0x5832, 81, 80, 1), (0x4d1c, 82, 88, 0), (0x438e, 83, 89, 0), (0x3bdd, 84, 90, 0),
+    (0x34ee, 85, 91, 0), (0x2eae, 86, 92, 0), (0x299a, 87, 93, 0), (0x2516, 71, 86, 0),
+    (0x5570, 89, 88, 1), (0x4ca9, 90, 95, 0), (0x44d9, 91, 96, 0), (0x3e22, 92, 97, 0),
+    (0x3824, 93, 99, 0), (0x32b4, 94, 99, 0), (0x2e17, 86, 93, 0), (0x56a8, 96, 95, 1),
+    (0x4f46, 97, 101, 0), (0x47e5, 98, 102, 0), (0x41cf, 99, 103, 0), (0x3c3d, 100, 104, 0),
+    (0x375e, 93, 99, 0), (0x5231, 102, 105, 0), (0x4c0f, 103, 106, 0), (0x4639, 104, 107, 0),
+    (0x415e, 99, 103, 0), (0x5627, 106, 105, 1), (0x50e7, 107, 108, 0), (0x4b85, 103, 109, 0),
+    (0x5597, 109, 110, 0), (0x504f, 107, 111, 0), (0x5a10, 111, 110, 1), (0x5522, 109, 112, 0),
+    (0x59eb, 111, 112, 1), (0x5a1d, 113, 113, 0),
+];
+
+const FIXED_STATE: u8 = 113;
+
+// The default conditioning of Section F.1.4.4: DC differences up to
+// 2^(L-1) are small, those above 2^(U-1) large, and the magnitude
+// categories of AC coefficients up to index K use their own statistics
+const DC_L: u32 = 0;
+const DC_U: u32 = 1;
+const AC_K: usize = 5;
+
+// Twice the bounds of Section F.1.4.4.1.2 on the magnitude category of a
+// DC difference, below which the next one is conditioned on a zero and
+// above which on a large difference. The bound 2^L / 2 truncates to zero
+// for L = 0, which `2 * m + 1 < DC_ZERO_BOUND` keeps exact.
+const DC_ZERO_BOUND: u32 = 1 << DC_L;
+const DC_LARGE_BOUND: u32 = 1 << DC_U;
+
+// A statistics bin, the state index in the low 7 bits and the value of the
+// more probable symbol in the high bit
+type Bin = u8;
+
+/// The QM coder with the statistics of the luma and chroma tables
+pub struct ArithmeticEncoder {
+    // The interval size and the code register, Section D.1.3
+    a: u32,
+    c: u32,
+    ct: u32,
+
+    // The last byte that may still take a carry, or -1, and the counts of
+    // stacked 0xFF and pending 0x00 bytes after it
+    buffer: i32,
+    sc: u32,
+    zc: u32,
+
+    dc_stats: [[Bin; 64]; 2],
+    ac_stats: [[Bin; 256]; 2],
+    fixed: Bin,
+
+    // The conditioning category of the next DC difference of each component
+    dc_context: [usize; 3],
+}
+
+impl ArithmeticEncoder {
+    pub fn new() -> ArithmeticEncoder {
+        ArithmeticEncoder {
+            a: 0x10000,
+            c: 0,
+            ct: 11,
+            buffer: -1,
+            sc: 0,
+            zc: 0,
+            dc_stats: [[0; 64]; 2],
+            ac_stats: [[0; 256]; 2],
+            fixed: FIXED_STATE,
+            dc_context: [0; 3],
+        }
+    }
+
+    /// Codes the quantized ```block``` in natural order of ```component```,
+    /// whose DC coefficient differs by ```diff``` from the previous one,
+    /// with the statistics of ```table```, 0 for luma and 1 for chroma
+    pub fn encode_block<W: Write>(&mut self,
+                                  w: &mut W,
+                                  block: &[i32],
+                                  diff: i32,
+                                  component: usize,
+                                  table: usize) -> io::Result<()> {
+        // Figure F.4, the DC difference
+        let context = self.dc_context[component];
+
+        if diff == 0 {
+            try!(self.encode_dc(w, table, context, 0));
+            self.dc_context[component] = 0;
+        } else {
+            try!(self.encode_dc(w, table, context, 1));
+
+            // Figure F.7, the sign selects the statistics of the magnitude
+            let (sign, s) = if diff > 0 { (0, context + 2) } else { (1, context + 3) };
+            try!(self.encode_dc(w, table, context + 1, sign));
+            self.dc_context[component] = if diff > 0 { 4 } else { 8 };
+
+            let (m, s) = try!(self.encode_magnitude(w, diff.abs() as u32 - 1, Stats::Dc(table), s, 20));
+
+            // Section F.1.4.4.1.2
+            if 2 * m + 1 < DC_ZERO_BOUND {
+                self.dc_context[component] = 0;
+            } else if 2 * m > DC_LARGE_BOUND {
+                self.dc_context[component] += 8;
+            }
+
+            try!(self.encode_bits(w, diff.abs() as u32 - 1, m, Stats::Dc(table), s + 14));
+        }
+
+        // Figure F.5, the AC coefficients up to the last one that is not zero
+        let end = (1..64).rev().find(|&k| block[UNZIGZAG[k] as usize] != 0).unwrap_or(0);
+        let mut k = 1;
+
+        while k <= end {
+            let mut s = 3 * (k - 1);
+            try!(self.encode_ac(w, table, s, 0));
+
+            while block[UNZIGZAG[k] as usize] == 0 {
+                try!(self.encode_ac(w, table, s + 1, 0));
+                s += 3;
+                k += 1;
+            }
+            try!(self.encode_ac(w, table, s + 1, 1));
+
+            let v = block[UNZIGZAG[k] as usize];
+            let mut fixed = self.fixed;
+            try!(self.encode(w, &mut fixed, if v > 0 { 0 } else { 1 }));
+            self.fixed = fixed;
+
+            // Figure F.8, the second bin of the magnitude category depends
+            // on the index of the coefficient
+            let x1 = if k <= AC_K { 189 } else { 217 };
+            let (m, s) = try!(self.encode_magnitude(w, v.abs() as u32 - 1, Stats::Ac(table), s + 2, x1));
+            try!(self.encode_bits(w, v.abs() as u32 - 1, m, Stats::Ac(table), s + 14));
+
+            k += 1;
+        }
+
+        // End of block, unless the last coefficient is not zero
+        if k <= 63 {
+            try!(self.encode_ac(w, table, 3 * (k - 1), 1));
+        }
+
+        Ok(())
+    }
+
+    // Figure F.8, codes the magnitude category of `v`, one less than the
+    // magnitude, starting at bin `s` and continuing at bin `x1`. Returns the
+    // highest bit of `v` and the bin that ended the category.
+    fn encode_magnitude<W: Write>(&mut self, w: &mut W, v: u32, stats: Stats, s: usize, x1: usize)
+                                  -> io::Result<(u32, usize)> {
+        let mut s = s;
+        let mut m = 0;
+
+        if v > 0 {
+            try!(self.encode_stat(w, stats, s, 1));
+            m = 1;
+
+            let mut v2 = v >> 1;
+            match stats {
+                Stats::Dc(..) => s = x1,
+                // The first bin of AC magnitudes is the one after the sign
+                Stats::Ac(..) if v2 > 0 => {
+                    try!(self.encode_stat(w, stats, s, 1));
+                    m <<= 1;
+                    v2 >>= 1;
+                    s = x1;
+                }
+                Stats::Ac(..) => ()
+            }
+
+            while v2 > 0 {
+                try!(self.encode_stat(w, stats, s, 1));
+                m <<= 1;
+                v2 >>= 1;
+                s += 1;
+            }
+        }
+
+        try!(self.encode_stat(w, stats, s, 0));
+        Ok((m, s))
+    }
+
+    // Figure F.9, codes the bits of `v` below its highest bit `m`
+    fn encode_bits<W: Write>(&mut self, w: &mut W, v: u32, m: u32, stats: Stats, s: usize) -> io::Result<()> {
+        let mut m = m >> 1;
+
+        while m > 0 {
+            try!(self.encode_stat(w, stats, s, if v & m != 0 { 1 } else { 0 }));
+            m >>= 1;
+        }
+
+        Ok(())
+    }
+
+    fn encode_stat<W: Write>(&mut self, w: &mut W, stats: Stats, s: usize, bit: u8) -> io::Result<()> {
+        match stats {
+            Stats::Dc(table) => self.encode_dc(w, table, s, bit),
+            Stats::Ac(table) => self.encode_ac(w, table, s, bit),
+        }
+    }
+
+    fn encode_dc<W: Write>(&mut self, w: &mut W, table: usize, s: usize, bit: u8) -> io::Result<()> {
+        let mut bin = self.dc_stats[table][s];
+        try!(self.encode(w, &mut bin, bit));
+        self.dc_stats[table][s] = bin;
+        Ok(())
+    }
+
+    fn encode_ac<W: Write>(&mut self, w: &mut W, table: usize, s: usize, bit: u8) -> io::Result<()> {
+        let mut bin = self.ac_stats[table][s];
+        try!(self.encode(w, &mut bin, bit));
+        self.ac_stats[table][s] = bin;
+        Ok(())
+    }
+
+    // Sections D.1.4 to D.1.6, codes `bit` with the estimate of `bin` and
+    // updates it
+    fn encode<W: Write>(&mut self, w: &mut W, bin: &mut Bin, bit: u8) -> io::Result<()> {
+        let (qe, next_mps, next_lps, switch) = QE_TABLE[(*bin & 0x7F) as usize];
+        let mps = *bin >> 7;
+
+        self.a -= qe;
+
+        if bit != mps {
+            // The larger subinterval is assigned to the more probable symbol
+            if self.a >= qe {
+                self.c += self.a;
+                self.a = qe;
+            }
+
+            *bin = (mps ^ switch) << 7 | next_lps;
+        } else {
+            if self.a >= 0x8000 {
+                return Ok(())
+            }
+
+            if self.a < qe {
+                self.c += self.a;
+                self.a = qe;
+            }
+
+            *bin = mps << 7 | next_mps;
+        }
+
+        // Renormalization
+        while self.a < 0x8000 {
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+
+            if self.ct == 0 {
+                try!(self.output_byte(w));
+                self.c &= 0x7FFFF;
+                self.ct = 8;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Moves the byte above the spacer bits of the code register to the
+    // output, propagating a carry into the bytes before it
+    fn output_byte<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        let byte = self.c >> 19;
+
+        if byte > 0xFF {
+            try!(self.carry(w));
+            self.buffer = (byte & 0xFF) as i32;
+        } else if byte == 0xFF {
+            self.sc += 1;
+        } else {
+            try!(self.settle(w));
+            self.buffer = byte as i32;
+        }
+
+        Ok(())
+    }
+
+    // Writes the buffered byte plus the carry, the stacked 0xFF bytes become
+    // pending zeros
+    fn carry<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        if self.buffer >= 0 {
+            try!(self.write_zeros(w));
+            try!(write_byte(w, (self.buffer + 1) as u8));
+        }
+
+        self.zc += self.sc;
+        self.sc = 0;
+        Ok(())
+    }
+
+    // Writes the buffered byte and the stacked 0xFF bytes, which can no
+    // longer take a carry. Zero bytes are held back, as trailing zeros
+    // need not be written.
+    fn settle<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        if self.buffer == 0 {
+            self.zc += 1;
+        } else if self.buffer > 0 {
+            try!(self.write_zeros(w));
+            try!(write_byte(w, self.buffer as u8));
+        }
+
+        if self.sc > 0 {
+            try!(self.write_zeros(w));
+
+            while self.sc > 0 {
+                try!(write_byte(w, 0xFF));
+                self.sc -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_zeros<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        while self.zc > 0 {
+            try!(w.write_all(&[0x00]));
+            self.zc -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Section D.1.8, flushes the code register at the end of the scan or
+    /// of a restart interval and resets the coder and the statistics
+    pub fn finish<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        // The value in the final interval with the most trailing zeros
+        let c = (self.a - 1 + self.c) & 0xFFFF0000;
+        self.c = if c < self.c { c + 0x8000 } else { c };
+        self.c <<= self.ct;
+
+        if self.c & 0xF8000000 != 0 {
+            try!(self.carry(w));
+        } else {
+            try!(self.settle(w));
+        }
+
+        // The last bytes are left out if they are zero
+        if self.c & 0x7FFF800 != 0 {
+            try!(self.write_zeros(w));
+            try!(write_byte(w, (self.c >> 19) as u8));
+
+            if self.c & 0x7F800 != 0 {
+                try!(write_byte(w, (self.c >> 11) as u8));
+            }
+        }
+
+        *self = ArithmeticEncoder::new();
+        Ok(())
+    }
+}
+
+// The statistics of a table of DC or AC decisions
+#[derive(Clone, Copy)]
+enum Stats {
+    Dc(usize),
+    Ac(usize),
+}
+
+// Writes `byte` with a stuffed zero after 0xFF
+fn write_byte<W: Write>(w: &mut W, byte: u8) -> io::Result<()> {
+    if byte == 0xFF {
+        w.write_all(&[0xFF, 0x00])
+    } else {
+        w.write_all(&[byte])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArithmeticEncoder, Bin, FIXED_STATE, QE_TABLE, AC_K, DC_LARGE_BOUND, DC_ZERO_BOUND};
+    use super::super::decoder::UNZIGZAG;
+
+    // The QM decoder of Section D.2 with the model of Section F.2.4, as
+    // implemented by libjpeg
+    struct ArithmeticDecoder<'a> {
+        data: &'a [u8],
+        pos: usize,
+        a: u32,
+        c: i64,
+        ct: i32,
+        dc_stats: [[Bin; 64]; 2],
+        ac_stats: [[Bin; 256]; 2],
+        fixed: Bin,
+        dc_context: [usize; 3],
+        dc: [i32; 3],
+    }
+
+    impl<'a> ArithmeticDecoder<'a> {
+        fn new(data: &'a [u8]) -> ArithmeticDecoder<'a> {
+            ArithmeticDecoder {
+                data: data,
+                pos: 0,
+                a: 0,
+                c: 0,
+                // Reads two bytes before the first decision
+                ct: -16,
+                dc_stats: [[0; 64]; 2],
+                ac_stats: [[0; 256]; 2],
+                fixed: FIXED_STATE,
+                dc_context: [0; 3],
+                dc: [0; 3],
+            }
+        }
+
+        // The next byte of the scan without stuffed zeros, or zeros at its end
+        fn byte(&mut self) -> i64 {
+            match self.data.get(self.pos) {
+                Some(&0xFF) => {
+                    self.pos += 2;
+                    0xFF
+                }
+                Some(&byte) => {
+                    self.pos += 1;
+                    byte as i64
+                }
+                None => 0
+            }
+        }
+
+        fn decode(&mut self, bin: &mut Bin) -> u8 {
+            while self.a < 0x8000 {
+                self.ct -= 1;
+
+                if self.ct < 0 {
+                    self.c = self.c << 8 | self.byte();
+                    self.ct += 8;
+
+                    if self.ct < 0 {
+                        self.ct += 1;
+                        if self.ct == 0 {
+                            self.a = 0x8000;
+                        }
+                    }
+                }
+
+                self.a <<= 1;
+            }
+
+            let (qe, next_mps, next_lps, switch) = QE_TABLE[(*bin & 0x7F) as usize];
+            let mps = *bin >> 7;
+            let after_mps = mps << 7 | next_mps;
+            let after_lps = (mps ^ switch) << 7 | next_lps;
+
+            self.a -= qe;
+            let temp = (self.a as i64) << self.ct;
+
+            let lps = if self.c >= temp {
+                self.c -= temp;
+                let lps = self.a >= qe;
+                self.a = qe;
+                lps
+            } else if self.a < 0x8000 {
+                self.a < qe
+            } else {
+                return mps
+            };
+
+            *bin = if lps { after_lps } else { after_mps };
+            if lps { mps ^ 1 } else { mps }
+        }
+
+        fn dc(&mut self, table: usize, s: usize) -> u8 {
+            let mut bin = self.dc_stats[table][s];
+            let bit = self.decode(&mut bin);
+            self.dc_stats[table][s] = bin;
+            bit
+        }
+
+        fn ac(&mut self, table: usize, s: usize) -> u8 {
+            let mut bin = self.ac_stats[table][s];
+            let bit = self.decode(&mut bin);
+            self.ac_stats[table][s] = bin;
+            bit
+        }
+
+        // Figures F.23 and F.24, the magnitude category starting at bin `s`
+        // and continuing at bin `x1`, followed by the lower bits. Returns the
+        // highest bit of the magnitude less one, and the magnitude.
+        fn magnitude<F>(&mut self, s: usize, x1: usize, ac: bool, decode: F) -> (u32, i32)
+            where F: Fn(&mut Self, usize) -> u8 {
+            let mut s = s;
+            let mut m = decode(self, s) as i32;
+
+            if m != 0 && (!ac || decode(self, s) != 0) {
+                if ac {
+                    m <<= 1;
+                }
+                s = x1;
+                while decode(self, s) != 0 {
+                    m <<= 1;
+                    s += 1;
+                }
+            }
+
+            let mut v = m;
+            let mut bit = m >> 1;
+            while bit > 0 {
+                if decode(self, s + 14) != 0 {
+                    v |= bit;
+                }
+                bit >>= 1;
+            }
+
+            (m as u32, v + 1)
+        }
+
+        // Section F.2.4, a block in natural order
+        fn decode_block(&mut self, component: usize, table: usize) -> [i32; 64] {
+            let mut block = [0i32; 64];
+            let context = self.dc_context[component];
+
+            if self.dc(table, context) == 0 {
+                self.dc_context[component] = 0;
+            } else {
+                let sign = self.dc(table, context + 1);
+                let s = context + 2 + sign as usize;
+
+                let (m, v) = self.magnitude(s, 20, false, |d, s| d.dc(table, s));
+
+                self.dc_context[component] = if 2 * m + 1 < DC_ZERO_BOUND {
+                    0
+                } else if 2 * m > DC_LARGE_BOUND {
+                    12 + 4 * sign as usize
+                } else {
+                    4 + 4 * sign as usize
+                };
+                self.dc[component] += if sign == 1 { -v } else { v };
+            }
+            block[0] = self.dc[component];
+
+            let mut k = 1;
+            while k < 64 {
+                let mut s = 3 * (k - 1);
+                if self.ac(table, s) == 1 {
+                    break
+                }
+
+                while self.ac(table, s + 1) == 0 {
+                    s += 3;
+                    k += 1;
+                }
+
+                let mut fixed = self.fixed;
+                let sign = self.decode(&mut fixed);
+                self.fixed = fixed;
+
+                let x1 = if k <= AC_K { 189 } else { 217 };
+                let (_, v) = self.magnitude(s + 2, x1, true, |d, s| d.ac(table, s));
+                block[UNZIGZAG[k] as usize] = if sign == 1 { -v } else { v };
+                k += 1;
+            }
+
+            block
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Blocks of three components, sparse and dense, with magnitudes of
+        // every category and the last coefficient set in some
+        let mut seed = 12345u32;
+        let mut random = move |n: u32| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 8) % n
+        };
+
+        let blocks = (0..60).map(|i| {
+            let mut block = [0i32; 64];
+            block[0] = random(4096) as i32 - 2048;
+            let density = [2, 8, 40, 64][i % 4];
+            for k in (1..64) {
+                if random(64) < density {
+                    let category = random(11);
+                    let v = (1 << category) + random(1 << category) as i32;
+                    block[UNZIGZAG[k] as usize] = if random(2) == 0 { v } else { -v };
+                }
+            }
+            if i % 5 == 0 {
+                block[UNZIGZAG[63] as usize] = -1;
+            }
+            if i % 7 == 0 {
+                block = [0i32; 64];
+            }
+            block
+        }).collect::<Vec<_>>();
+
+        // Two restart intervals
+        for interval in blocks.chunks(30) {
+            let mut encoder = ArithmeticEncoder::new();
+            let mut data = Vec::new();
+            let mut dc = [0i32; 3];
+
+            for (i, block) in interval.iter().enumerate() {
+                let component = i % 3;
+                encoder.encode_block(&mut data, block, block[0] - dc[component],
+                                     component, if component == 0 { 0 } else { 1 }).unwrap();
+                dc[component] = block[0];
+            }
+            encoder.finish(&mut data).unwrap();
+
+            let mut decoder = ArithmeticDecoder::new(&data);
+            for (i, block) in interval.iter().enumerate() {
+                let component = i % 3;
+                let decoded = decoder.decode_block(component, if component == 0 { 0 } else { 1 });
+                assert!(&decoded[..] == &block[..], "block {}", i);
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! C2PA manifest stores in APP11 segments
+//!
+//! Provenance manifests of the Coalition for Content Provenance and
+//! Authenticity are JUMBF boxes (ISO/IEC 19566-5), which JPEG files carry
+//! in APP11 segments as specified by JPEG XT. Every segment starts with the
+//! identifier ```JP```, the instance number of the box and the sequence
+//! number of the segment, followed by the box header and the next part of
+//! the box content.
+//!
+//! See https://c2pa.org/specifications/
+
+use image::{ImageError, ImageResult};
+
+use super::decoder::MarkerSegment;
+
+pub const APP11: u8 = 0xEB;
+
+// The largest part of a box a segment holds after its 16 header bytes
+const CHUNK_LEN: usize = 65533 - 16;
+
+/// Reassembles the C2PA manifest store, a JUMBF superbox labelled
+/// ```c2pa```, from the APP11 segments in ```segments```
+pub fn read_manifest(segments: &[MarkerSegment]) -> Option<Vec<u8>> {
+    // The segments of each box instance with their sequence numbers
+    let mut parts: Vec<(u16, u32, &[u8])> = segments.iter().filter_map(|s| {
+        let d = &s.data;
+        if s.marker != APP11 || d.len() < 16 || &d[..2] != b"JP" || &d[12..16] != b"jumb" {
+            return None
+        }
+
+        let instance = (d[2] as u16) << 8 | d[3] as u16;
+        let sequence = be_u32(&d[4..8]);
+        Some((instance, sequence, &d[8..]))
+    }).collect();
+
+    parts.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    let mut i = 0;
+    while i < parts.len() {
+        let instance = parts[i].0;
+        let header = header_len(parts[i].2);
+
+        // The first segment holds the box header, the others repeat it
+        let mut manifest = parts[i].2.to_vec();
+        i += 1;
+
+        while i < parts.len() && parts[i].0 == instance {
+            if parts[i].2.len() >= header {
+                manifest.extend(parts[i].2[header..].iter().cloned());
+            }
+            i += 1;
+        }
+
+        if is_c2pa(&manifest) {
+            return Some(manifest)
+        }
+    }
+
+    None
+}
+
+/// Splits the JUMBF box ```manifest``` into the contents of APP11 segments
+/// of box instance ```instance```
+pub fn split_manifest(manifest: &[u8], instance: u16) -> ImageResult<Vec<Vec<u8>>> {
+    if manifest.len() < 8 || &manifest[4..8] != b"jumb" {
+        return Err(ImageError::FormatError("The manifest is not a JUMBF box".to_string()))
+    }
+
+    let header = header_len(manifest);
+    if manifest.len() < header {
+        return Err(ImageError::FormatError("The manifest is truncated".to_string()))
+    }
+
+    let (header, content) = manifest.split_at(header);
+    let chunk_len = CHUNK_LEN - (header.len() - 8);
+
+    Ok(content.chunks(chunk_len).enumerate().map(|(i, chunk)| {
+        let sequence = i as u32 + 1;
+
+        let mut segment = b"JP".to_vec();
+        segment.extend([(instance >> 8) as u8, instance as u8].iter().cloned());
+        segment.extend([(sequence >> 24) as u8, (sequence >> 16) as u8,
+                        (sequence >> 8) as u8, sequence as u8].iter().cloned());
+        segment.extend(header.iter().cloned());
+        segment.extend(chunk.iter().cloned());
+        segment
+    }).collect())
+}
+
+// The length of the header of the box `b`, which has an extended length
+// field if its length is 1
+fn header_len(b: &[u8]) -> usize {
+    if b.len() >= 4 && be_u32(b) == 1 { 16 } else { 8 }
+}
+
+// Whether the superbox `b` is described by a `jumd` box labelled `c2pa`
+fn is_c2pa(b: &[u8]) -> bool {
+    if b.len() < header_len(b) {
+        return false
+    }
+
+    let d = &b[header_len(b)..];
+
+    // Description box header, content type UUID and toggles
+    d.len() > 25 && &d[4..8] == b"jumd" && d[25..].starts_with(b"c2pa\0")
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_manifest, split_manifest, APP11};
+    use super::super::decoder::MarkerSegment;
+
+    // A manifest store with a content box of `len` bytes
+    fn manifest(len: usize) -> Vec<u8> {
+        let mut description = vec![0, 0, 0, 0];
+        description.extend(b"jumd".iter().cloned());
+        description.extend(b"c2pa\x00\x11\x00\x10\x80\x00\x00\xAA\x00\x38\x9B\x71".iter().cloned());
+        description.push(3);
+        description.extend(b"c2pa\0".iter().cloned());
+        let n = description.len() as u32;
+        description[3] = n as u8;
+
+        let total = 8 + description.len() + len;
+        let mut b = vec![(total >> 24) as u8, (total >> 16) as u8, (total >> 8) as u8, total as u8];
+        b.extend(b"jumb".iter().cloned());
+        b.extend(description);
+        b.extend((0..len).map(|i| (i % 251) as u8));
+        b
+    }
+
+    #[test]
+    fn test_split_and_read() {
+        let original = manifest(70000);
+        let parts = split_manifest(&original, 1).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].len(), 65533);
+
+        // Out of order and among unrelated segments
+        let mut segments = parts.into_iter().rev().map(|data| {
+            MarkerSegment { marker: APP11, offset: 0, data: data }
+        }).collect::<Vec<_>>();
+        segments.insert(1, MarkerSegment { marker: APP11, offset: 0, data: b"FLIR".to_vec() });
+
+        assert!(read_manifest(&segments).unwrap() == original);
+        assert!(read_manifest(&segments[1..2]).is_none());
+        assert!(split_manifest(b"not a box", 1).is_err());
+    }
+
+    #[test]
+    fn test_short_segment() {
+        // An extended length box that ends before its 16 header bytes
+        let data = b"JP\x00\x01\x00\x00\x00\x01\x00\x00\x00\x01jumb\x00\x00".to_vec();
+        let segments = [MarkerSegment { marker: APP11, offset: 0, data: data }];
+        assert!(read_manifest(&segments).is_none());
+    }
+}
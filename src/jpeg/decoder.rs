@@ -1,13 +1,18 @@
 use std::cmp;
-use std::io::Read;
+use std::io::{self, Read};
 use std::default::Default;
-use std::collections::HashMap;
 use std::iter::repeat;
-use byteorder::{ReadBytesExt, BigEndian};
-use num::range_step;
+use std::mem;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use byteorder::{ByteOrder, ReadBytesExt, BigEndian};
 
 use color;
-use super::transform;
+use config::{self, SimdLevel};
+use executor::{self, Executor, Job, StdThreads};
+use super::c2pa;
+use super::exif::{self, Exif};
+use super::simd::{self, Idct};
 
 use super::entropy:: {
     HuffTable,
@@ -18,7 +23,12 @@ use super::entropy:: {
 use image;
 use image::ImageResult;
 use image::ImageDecoder;
+use math::Rect;
 use math::utils::clamp;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+#[cfg(feature = "mmap")]
+use mmap::{self, MappedFile};
 
 /// The permutation of dct coefficients.
 pub static UNZIGZAG: [u8; 64] = [
@@ -33,7 +43,7 @@ pub static UNZIGZAG: [u8; 64] = [
 ];
 
 /// A representation of a JPEG component
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct Component {
     /// The Component's identifier
     pub id: u8,
@@ -57,6 +67,10 @@ pub struct Component {
     pub dc_pred: i32
 }
 
+// The most blocks in an MCU: 10 in interleaved scans, Section B.2.3, and 16
+// of a single component with sampling factors of 4x4
+const MAX_MCU_BLOCKS: usize = 16;
+
 // Markers
 // Baseline DCT
 const SOF0: u8 = 0xC0;
@@ -68,11 +82,11 @@ const DHT: u8 = 0xC4;
 const RST0: u8 = 0xD0;
 const RST7: u8 = 0xD7;
 // Start of Image (standalone)
-const SOI: u8 = 0xD8;
+pub const SOI: u8 = 0xD8;
 // End of image (standalone)
-const EOI: u8 = 0xD9;
+pub const EOI: u8 = 0xD9;
 // Start of Scan
-const SOS: u8 = 0xDA;
+pub const SOS: u8 = 0xDA;
 // Quantization Tables
 const DQT: u8 = 0xDB;
 // Number of lines
@@ -80,13 +94,414 @@ const DNL: u8 = 0xDC;
 // Restart Interval
 const DRI: u8 = 0xDD;
 // Application segments start and end
-const APP0: u8 = 0xE0;
+pub const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const APP2: u8 = 0xE2;
+const APP14: u8 = 0xEE;
 const APPF: u8 = 0xEF;
 // Comment
 const COM: u8 = 0xFE;
 // Reserved
 const TEM: u8 = 0x01;
 
+/// The method used to upsample subsampled chroma components
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UpsamplingMethod {
+    /// Replicate each chroma sample (nearest neighbour)
+    Replicate,
+
+    /// Interpolate between the nearest chroma samples with a triangle filter,
+    /// similar to libjpeg's "fancy upsampling". Reduces color fringing on
+    /// subsampled images at a small cost in speed.
+    Fancy
+}
+
+/// The order of the color channels of decoded color images.
+/// Only images with 3 components are affected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorOrder {
+    /// Red, green, blue
+    RGB,
+
+    /// Blue, green, red
+    BGR,
+
+    /// Blue, green, red and an opaque alpha channel, as used by Windows
+    /// GDI bitmaps and many GPU texture formats. The color type is still
+    /// reported as `RGBA(8)`.
+    BGRA
+}
+
+/// How the decoder handles malformed or truncated images
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tolerance {
+    /// Fail on any error in the image data
+    Strict,
+
+    /// Skip unknown markers, ignore out of sequence restart markers and
+    /// fill the rest of the image with gray if the data is truncated or
+    /// corrupt after the first row of MCUs. The problems encountered are
+    /// reported by `JPEGDecoder::warnings`.
+    Lenient
+}
+
+/// Limits on the size of images the decoder accepts
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum width of an image
+    pub max_width: u32,
+
+    /// The maximum height of an image
+    pub max_height: u32,
+
+    /// The maximum number of pixels of an image
+    pub max_pixels: u64,
+
+    /// The maximum number of bytes the decoder allocates for its sample
+    /// buffers, which depends on the dimensions and sampling factors of the
+    /// image, and for the APPn and COM segments it keeps. A multi-threaded
+    /// decode of an image with restart intervals buffers the samples of the
+    /// whole image.
+    pub max_alloc: u64
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_width: 65535,
+            max_height: 65535,
+            max_pixels: 65535 * 65535,
+            max_alloc: 1 << 32
+        }
+    }
+}
+
+/// The resources used to decode an image, as reported by
+/// `JPEGDecoder::resource_usage`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// The largest number of bytes that was checked against
+    /// `Limits::max_alloc`. It is the size of the sample buffers and marker
+    /// segments the decoder needed at once, computed from their sizes
+    /// rather than measured, thus not counting smaller, short-lived
+    /// allocations.
+    pub checked_alloc: u64,
+
+    /// The number of bytes of the decoded image
+    pub output_size: u64
+}
+
+/// Options controlling how a JPEG image is decoded
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct JpegDecodeOptions {
+    /// The method used to upsample subsampled chroma components.
+    /// Defaults to `UpsamplingMethod::Replicate`.
+    pub upsampling: UpsamplingMethod,
+
+    /// The channel order of decoded color images. Defaults to `ColorOrder::RGB`.
+    /// Grayscale images are not affected.
+    pub color_order: ColorOrder,
+
+    /// How malformed images are handled. Defaults to `Tolerance::Strict`.
+    pub tolerance: Tolerance,
+
+    /// The image is scaled down by this factor while decoding, by averaging
+    /// blocks of `scale * scale` pixels. Must be 1, 2, 4 or 8. Defaults to 1.
+    pub scale: u8,
+
+    /// Images exceeding the dimension limits are rejected with a
+    /// `DimensionError`, those needing more buffers than ```max_alloc```
+    /// with a `LimitsExceeded` error, before the decoder allocates them
+    pub limits: Limits,
+
+    /// Overrides the global SIMD level of `config::set_simd` for this decoder
+    pub simd: Option<SimdLevel>
+}
+
+impl Default for JpegDecodeOptions {
+    fn default() -> JpegDecodeOptions {
+        JpegDecodeOptions {
+            upsampling: UpsamplingMethod::Replicate,
+            color_order: ColorOrder::RGB,
+            tolerance: Tolerance::Strict,
+            scale: 1,
+            limits: Default::default(),
+            simd: None
+        }
+    }
+}
+
+// The samples of one component for one MCU row. An extra row of context
+// from the neighbouring MCU rows is kept above and below the samples.
+struct Plane {
+    data: Vec<u8>,
+    stride: usize,
+    rows: usize,
+}
+
+impl Plane {
+    fn new(stride: usize, rows: usize) -> Plane {
+        Plane {
+            data: repeat(0u8).take(stride * (rows + 2)).collect(),
+            stride: stride,
+            rows: rows
+        }
+    }
+
+    // Changes the size of the plane, reusing its allocation
+    fn resize(&mut self, stride: usize, rows: usize) {
+        self.data.clear();
+        self.data.resize(stride * (rows + 2), 0);
+        self.stride = stride;
+        self.rows = rows;
+    }
+
+    // Returns sample row `y`, where -1 and `rows` denote the context rows
+    fn row(&self, y: isize) -> &[u8] {
+        let start = (y + 1) as usize * self.stride;
+        &self.data[start..start + self.stride]
+    }
+
+    fn row_mut(&mut self, y: isize) -> &mut [u8] {
+        let start = (y + 1) as usize * self.stride;
+        &mut self.data[start..start + self.stride]
+    }
+}
+
+// Decodes the quantized coefficients of one block in natural order
+// into `coefficients` and returns the DC coefficient.
+fn decode_coefficients<R: Read>(h: &mut HuffDecoder, r: &mut R,
+                                dctable: &HuffTable, actable: &HuffTable,
+                                pred: i32, coefficients: &mut [i32; 64]) -> ImageResult<i32> {
+    let t     = try!(h.decode_symbol(r, dctable));
+
+    let diff  = if t > 0 {
+        try!(h.receive(r, t))
+    } else {
+        0
+    };
+
+    // Section F.2.1.3.1
+    let diff = extend(diff, t);
+    let dc = diff + pred;
+    coefficients[0] = dc;
+
+    let mut k = 0usize;
+    while k < 63 {
+        let rs = try!(h.decode_symbol(r, actable));
+
+        let ssss = rs & 0x0F;
+        let rrrr = rs >> 4;
+
+        if ssss == 0 {
+            if rrrr != 15 {
+                break
+            }
+
+            k += 16;
+        } else {
+            k += rrrr as usize;
+
+            if k >= 63 {
+                return Err(image::ImageError::FormatError(
+                    "Coefficient index out of range.".to_string()
+                ))
+            }
+
+            // Figure F.14
+            let t = try!(h.receive(r, ssss));
+
+            coefficients[UNZIGZAG[k + 1] as usize] = extend(t, ssss);
+            k += 1;
+        }
+    }
+
+    // The block needed bits past the end of the data
+    if h.overrun {
+        return Err(image::ImageError::ImageEnd)
+    }
+
+    Ok(dc)
+}
+
+// The tables shared by the jobs decoding restart intervals in parallel
+struct Tables<'a> {
+    dctables: &'a [HuffTable],
+    actables: &'a [HuffTable],
+    qtables: &'a [u8],
+    layout: &'a [Component],
+    idct: Idct,
+}
+
+// Splits the entropy-coded data of a scan at its restart markers. Each
+// interval keeps the marker that ends it, where the Huffman decoder stops.
+fn split_intervals(data: &[u8]) -> ImageResult<Vec<&[u8]>> {
+    let mut intervals = Vec::new();
+    let mut expected = RST0;
+    let mut start = 0;
+    let mut i = 0;
+
+    while i + 1 < data.len() {
+        if data[i] != 0xFF {
+            i += 1;
+            continue
+        }
+
+        match data[i + 1] {
+            // A stuffed zero byte or a fill byte before a marker
+            0x00 => i += 2,
+            0xFF => i += 1,
+            RST0 ... RST7 => {
+                if data[i + 1] != expected {
+                    return Err(image::ImageError::FormatError(format!(
+                        "Unexpected restart maker {} found", data[i + 1]
+                    )))
+                }
+
+                intervals.push(&data[start..i + 2]);
+                expected = if expected == RST7 { RST0 } else { expected + 1 };
+                i += 2;
+                start = i;
+            }
+            _ => {
+                intervals.push(&data[start..i + 2]);
+                return Ok(intervals)
+            }
+        }
+    }
+
+    intervals.push(&data[start..]);
+
+    Ok(intervals)
+}
+
+// Decodes the MCUs of one restart interval into `out`, which receives the
+// samples of each MCU as consecutive blocks in the order of the scan.
+fn decode_interval(mut data: &[u8], tables: &Tables, out: &mut [u8]) -> ImageResult<()> {
+    let mut h = HuffDecoder::new();
+    let mut preds = [0i32; 4];
+    let per_mcu = tables.layout.iter().map(|c| c.h as usize * c.v as usize).sum::<usize>();
+    let mut blocks = [[0i32; 64]; MAX_MCU_BLOCKS];
+
+    for out in out.chunks_mut(64 * per_mcu) {
+        let mut n = 0;
+
+        for (c, pred) in tables.layout.iter().zip(preds.iter_mut()) {
+            for _ in (0..c.h as usize * c.v as usize) {
+                blocks[n] = [0; 64];
+                *pred = try!(decode_coefficients(&mut h, &mut data,
+                                                 &tables.dctables[c.dc_table as usize],
+                                                 &tables.actables[c.ac_table as usize],
+                                                 *pred, &mut blocks[n]));
+                n += 1;
+            }
+        }
+
+        transform_blocks(&mut blocks[..n], tables.qtables, tables.layout, tables.idct, out);
+    }
+
+    Ok(())
+}
+
+// Transforms the blocks of whole MCUs to samples, laid out in `out` like
+// the output of `decode_interval`
+fn transform_blocks(blocks: &mut [[i32; 64]], qtables: &[u8], layout: &[Component], idct: Idct,
+                    out: &mut [u8]) {
+    let per_mcu = layout.iter().map(|c| c.h as usize * c.v as usize).sum::<usize>();
+
+    for mcu in blocks.chunks_mut(per_mcu) {
+        let mut mcu = mcu.iter_mut();
+
+        for c in layout.iter() {
+            let q = 64 * c.tq as usize;
+            for block in mcu.by_ref().take(c.h as usize * c.v as usize) {
+                dequantize(block, &qtables[q..q + 64]);
+            }
+        }
+    }
+
+    idct.transform(blocks, out);
+}
+
+// A transformed MCU row, or `None` if its job panicked or was dropped
+// without running
+type RowResult = (u32, Option<Vec<u8>>);
+
+// The MCU rows of a scan without restart intervals that are transformed by
+// jobs of the executor, while the calling thread entropy decodes the next
+struct Pipeline {
+    qtables: Arc<[u8; 256]>,
+    layout: Arc<Vec<Component>>,
+    sender: SyncSender<RowResult>,
+    receiver: Receiver<RowResult>,
+    // The next MCU row to entropy decode
+    next: u32,
+    // Transformed rows that arrived before the row the decoder waits for
+    done: Vec<(u32, Vec<u8>)>,
+    // The error that stopped the entropy decoding at row `next`, returned
+    // once the rows before it have been
+    error: Option<image::ImageError>,
+}
+
+// Sends the result of a row job when dropped, thus also if the job panics or
+// the executor drops it
+struct SendRow {
+    sender: SyncSender<RowResult>,
+    row: u32,
+    samples: Option<Vec<u8>>,
+}
+
+impl SendRow {
+    fn complete(mut self, samples: Vec<u8>) {
+        self.samples = Some(samples);
+    }
+}
+
+impl Drop for SendRow {
+    fn drop(&mut self) {
+        // The decoder may have given up on the image already
+        let _ = self.sender.send((self.row, self.samples.take()));
+    }
+}
+
+// Dequantizes the `coefficients` of a block in natural order
+fn dequantize(coefficients: &mut [i32; 64], qtable: &[u8]) {
+    for (k, &z) in UNZIGZAG.iter().enumerate() {
+        coefficients[z as usize] *= qtable[k] as i32;
+    }
+}
+
+// Copies the 8x8 `samples` to block (`bx`, `by`) of `plane`
+fn put_block(plane: &mut Plane, bx: usize, by: usize, samples: &[u8]) {
+    for y in (0usize..8) {
+        let row = plane.row_mut((by * 8 + y) as isize);
+        ::copy_memory(&samples[y * 8..y * 8 + 8], &mut row[bx * 8..bx * 8 + 8]);
+    }
+}
+
+// Copies the samples of an MCU row, laid out like the output of
+// `decode_interval`, to the `planes` of the scan components in `layout`
+fn put_mcus(planes: &mut [Plane], layout: &[Component], mcus_per_row: usize, samples: &[u8]) {
+    let mut start = 0;
+
+    for mcu_x in (0..mcus_per_row) {
+        for (c, plane) in layout.iter().zip(planes.iter_mut()) {
+            for b in (0..c.h as usize * c.v as usize) {
+                let bx = mcu_x * c.h as usize + b % c.h as usize;
+                let by = b / c.h as usize;
+
+                put_block(plane, bx, by, &samples[start..start + 64]);
+                start += 64;
+            }
+        }
+    }
+}
+
+// Copies sample row `from` of `src` into row `to` of `dst`
+fn copy_plane_row(src: &Plane, from: isize, dst: &mut Plane, to: isize) {
+    ::copy_memory(src.row(from), dst.row_mut(to));
+}
+
 #[derive(PartialEq)]
 enum JPEGState {
     Start,
@@ -97,13 +512,125 @@ enum JPEGState {
     End
 }
 
+/// The rows of an image that could be decoded before its data ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialDecode {
+    /// The decoded rows, laid out as consecutive rows of ```row_len``` bytes
+    pub data: Vec<u8>,
+    /// The number of rows in ```data```
+    pub rows_decoded: u32,
+    /// The number of rows at the bottom of the image that are missing
+    pub rows_missing: u32,
+}
+
+impl PartialDecode {
+    /// Returns true if no rows are missing
+    pub fn is_complete(&self) -> bool {
+        self.rows_missing == 0
+    }
+}
+
+/// The quantized DCT coefficients of a JPEG image
+#[derive(Clone)]
+pub struct Coefficients {
+    /// The width of the image
+    pub width: u32,
+    /// The height of the image
+    pub height: u32,
+    /// The coefficients of each component in the order of the scan
+    pub components: Vec<ComponentCoefficients>,
+}
+
+/// The quantized DCT coefficients of one component
+#[derive(Clone)]
+pub struct ComponentCoefficients {
+    /// The component's identifier
+    pub id: u8,
+    /// Horizontal sampling factor
+    pub h: u8,
+    /// Vertical sampling factor
+    pub v: u8,
+    /// The number of blocks per row, including the padding of the last MCU
+    pub blocks_wide: u32,
+    /// The number of block rows, including the padding of the last MCU
+    pub blocks_high: u32,
+    /// The quantization table in natural (row-major) order
+    pub quantization_table: [u16; 64],
+    /// The blocks in row-major order, each holding its coefficients in
+    /// natural order
+    pub blocks: Vec<[i16; 64]>,
+}
+
+impl ComponentCoefficients {
+    /// Returns the quantized coefficients of the block at ```(bx, by)```
+    pub fn block(&self, bx: u32, by: u32) -> &[i16; 64] {
+        &self.blocks[(by * self.blocks_wide + bx) as usize]
+    }
+
+    /// Returns the dequantized coefficients of the block at ```(bx, by)```
+    pub fn dequantized(&self, bx: u32, by: u32) -> [i32; 64] {
+        let mut out = [0i32; 64];
+
+        for ((o, &c), &q) in out.iter_mut().zip(self.block(bx, by).iter())
+                                .zip(self.quantization_table.iter()) {
+            *o = c as i32 * q as i32;
+        }
+
+        out
+    }
+}
+
+/// The samples of one component, decoded without upsampling or color conversion
+#[derive(Clone)]
+pub struct ComponentPlane {
+    /// The component's identifier
+    pub id: u8,
+    /// Horizontal sampling factor
+    pub h: u8,
+    /// Vertical sampling factor
+    pub v: u8,
+    /// The width of the plane, reduced by the horizontal subsampling
+    pub width: u32,
+    /// The height of the plane, reduced by the vertical subsampling
+    pub height: u32,
+    /// The samples as ```height``` rows of ```width``` bytes
+    pub data: Vec<u8>,
+}
+
+/// An APPn or COM marker segment, as found before the first scan
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MarkerSegment {
+    /// The marker, ```0xE0``` to ```0xEF``` for APP0 to APP15 and ```0xFE``` for COM
+    pub marker: u8,
+    /// The offset of the marker in the stream, counted from where the
+    /// decoder started reading
+    pub offset: u64,
+    /// The content of the segment, without the marker and its length
+    pub data: Vec<u8>,
+}
+
+// Counts the bytes read from a reader, to locate marker segments
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
 /// The representation of a JPEG decoder
 ///
 /// Does not support decoding progressive JPEG images
 pub struct JPEGDecoder<R> {
-    r: R,
+    r: CountingReader<R>,
 
     qtables: [u8; 64 * 4],
+    qtables_defined: [bool; 4],
     dctables: [HuffTable; 2],
     actables: [HuffTable; 2],
 
@@ -113,11 +640,31 @@ pub struct JPEGDecoder<R> {
     width: u16,
 
     num_components: u8,
-    scan_components: Vec<u8>,
-    components: HashMap<usize, Component>,     // TODO: replace by `VecMap`
+    // The components of the frame, those of the current scan first and in
+    // the order of the scan
+    components: [Component; 4],
+    num_scan_components: usize,
 
     mcu_row: Vec<u8>,
-    mcu: Vec<u8>,
+    planes: Vec<Plane>,
+    current: Vec<Plane>,
+    options: JpegDecodeOptions,
+    simd: SimdLevel,
+    idct: Idct,
+    // The coefficients and samples of the blocks of one component in an MCU
+    blocks: [[i32; 64]; MAX_MCU_BLOCKS],
+    block_samples: [u8; 64 * MAX_MCU_BLOCKS],
+    mcu_rows_decoded: u32,
+    truncated: bool,
+    skip_mcus: u32,
+    damaged: Vec<Rect>,
+    warnings: Vec<String>,
+    segments: Vec<MarkerSegment>,
+    // The bytes of the retained marker segments
+    retained: u64,
+    // The color transform of the Adobe APP14 segment
+    adobe_transform: Option<u8>,
+    usage: ResourceUsage,
     hmax: u8,
     vmax: u8,
 
@@ -129,17 +676,34 @@ pub struct JPEGDecoder<R> {
     decoded_rows: u32,
     padded_width: usize,
     state: JPEGState,
+
+    threads: usize,
+    executor: Arc<Executor>,
+    intervals: Vec<u8>,
+    pipeline: Option<Pipeline>,
 }
 
 impl<R: Read>JPEGDecoder<R> {
     /// Create a new decoder that decodes from the stream ```r```
     pub fn new(r: R) -> JPEGDecoder<R> {
+        JPEGDecoder::new_with_options(r, Default::default())
+    }
+
+    /// Create a new decoder that decodes from the stream ```r``` using ```options```.
+    /// If ```options.scale``` is not 1, 2, 4 or 8, reading the image fails
+    /// with an ```UnsupportedError```.
+    pub fn new_with_options(r: R, options: JpegDecodeOptions) -> JPEGDecoder<R> {
         let h: HuffTable  = Default::default();
+        let simd = match options.simd {
+            Some(level) => level.effective(),
+            None => config::simd()
+        };
 
         JPEGDecoder {
-            r: r,
+            r: CountingReader { inner: r, count: 0 },
 
             qtables: [0u8; 64 * 4],
+            qtables_defined: [false; 4],
             dctables: [h.clone(), h.clone()],
             actables: [h.clone(), h.clone()],
 
@@ -149,11 +713,26 @@ impl<R: Read>JPEGDecoder<R> {
             width: 0,
 
             num_components: 0,
-            scan_components: Vec::new(),
-            components: HashMap::new(),
+            components: [Component::default(); 4],
+            num_scan_components: 0,
 
             mcu_row: Vec::new(),
-            mcu: Vec::new(),
+            planes: Vec::new(),
+            current: Vec::new(),
+            options: options,
+            simd: simd,
+            idct: Idct::new(simd),
+            blocks: [[0; 64]; MAX_MCU_BLOCKS],
+            block_samples: [0; 64 * MAX_MCU_BLOCKS],
+            mcu_rows_decoded: 0,
+            truncated: false,
+            skip_mcus: 0,
+            damaged: Vec::new(),
+            warnings: Vec::new(),
+            segments: Vec::new(),
+            retained: 0,
+            adobe_transform: None,
+            usage: Default::default(),
             hmax: 0,
             vmax: 0,
 
@@ -164,524 +743,2548 @@ impl<R: Read>JPEGDecoder<R> {
             row_count: 0,
             decoded_rows: 0,
             state: JPEGState::Start,
-            padded_width: 0
+            padded_width: 0,
+
+            threads: 1,
+            executor: Arc::new(StdThreads),
+            intervals: Vec::new(),
+            pipeline: None,
         }
     }
 
-    fn decode_mcu_row(&mut self) -> ImageResult<()> {
-        let bytesperpixel = self.num_components as usize;
-
-        for x0 in range_step(0, self.padded_width * bytesperpixel, bytesperpixel * 8 * self.hmax as usize) {
-
-            let _ = try!(self.decode_mcu());
+    /// Sets the number of threads used to decode images. Defaults to 1.
+    ///
+    /// The restart intervals of a scan are independent of each other, thus
+    /// they are decoded in parallel and the MCU rows stitched together.
+    /// This reads the whole scan into memory first.
+    /// Without restart intervals, the calling thread entropy decodes the MCU
+    /// rows as they are read, while up to ```threads``` of them are
+    /// transformed by other threads.
+    /// Damaged images are always decoded on the calling thread in
+    /// `Tolerance::Lenient` mode.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = if threads == 0 { 1 } else { threads };
+    }
 
-            upsample_mcu (
-                &mut self.mcu_row,
-                x0,
-                self.padded_width,
-                bytesperpixel,
-                &self.mcu,
-                self.hmax,
-                self.vmax
-            );
-        }
+    /// Sets the executor that runs the jobs of a multi-threaded decode.
+    /// Defaults to threads of the standard library.
+    ///
+    /// The decoder waits for the jobs it spawned, thus the executor must run
+    /// every job eventually, even while the calling thread is blocked, as
+    /// described for ```Executor::spawn```. Jobs never wait for each other,
+    /// so they may run in any order and on any number of threads. A job
+    /// that panics or is dropped without running fails the decode with a
+    /// `FormatError`.
+    pub fn set_executor(&mut self, executor: Arc<Executor>) {
+        self.executor = executor;
+    }
 
-        Ok(())
+    /// Prepares the decoder for the next image, read from ```r```, and
+    /// returns the reader of the previous one. After a complete image, that
+    /// reader is positioned right after its EOI marker.
+    ///
+    /// The options and the buffers of the decoder are kept, thus decoding
+    /// many images of similar size does not allocate them again. The
+    /// quantization and Huffman tables also stay defined, as required by
+    /// the abbreviated images of a stream whose tables are sent first.
+    pub fn reset(&mut self, r: R) -> R {
+        self.h = HuffDecoder::new();
+
+        self.height = 0;
+        self.width = 0;
+
+        self.num_components = 0;
+        self.num_scan_components = 0;
+
+        self.mcu_rows_decoded = 0;
+        self.truncated = false;
+        self.skip_mcus = 0;
+        self.damaged.clear();
+        self.warnings.clear();
+        self.segments.clear();
+        self.retained = 0;
+        self.adobe_transform = None;
+        self.usage = Default::default();
+        self.hmax = 0;
+        self.vmax = 0;
+
+        self.interval = 0;
+        self.mcucount = 0;
+        self.expected_rst = RST0;
+
+        self.row_count = 0;
+        self.decoded_rows = 0;
+        self.padded_width = 0;
+        self.state = JPEGState::Start;
+        self.intervals.clear();
+        self.pipeline = None;
+
+        mem::replace(&mut self.r, CountingReader { inner: r, count: 0 }).inner
     }
 
-    fn decode_mcu(&mut self) -> ImageResult<()> {
-        let mut i = 0;
-        let tmp = self.scan_components.clone();
+    /// Decodes the image and calls ```f``` with each slab of rows as soon
+    /// as it has been decoded, without materializing the full image.
+    ///
+    /// ```f``` receives the index of the first row of the slab and the rows
+    /// themselves, laid out as consecutive rows of ```row_len``` bytes.
+    /// A slab usually contains one row of MCUs, that is 8 or 16 rows.
+    /// Rows already read with ```read_scanline``` are skipped.
+    pub fn decode_rows<F>(&mut self, mut f: F) -> ImageResult<()>
+    where F: FnMut(u32, &[u8]) {
+        let row_len = try!(self.row_len());
+        let height  = self.output_dimensions().1;
+
+        let slab_height = self.vmax as usize * 8 / self.options.scale as usize;
+        let stride = self.padded_width / self.options.scale as usize * self.output_bpp();
+        let mut slab = Vec::with_capacity(row_len * slab_height);
+
+        while self.decoded_rows < height {
+            if self.row_count == 0 {
+                let _ = try!(self.next_mcu_row());
+            }
 
-        for id in tmp.iter() {
-            let mut c = self.components.get(&(*id as usize)).unwrap().clone();
+            let first = self.row_count as usize;
+            let count = cmp::min(slab_height - first, (height - self.decoded_rows) as usize);
 
-            for _ in (0..c.h * c.v) {
-                let pred  = try!(self.decode_block(i, c.dc_table, c.dc_pred, c.ac_table, c.tq));
-                c.dc_pred = pred;
-                i += 1;
+            slab.clear();
+            for row in (first..first + count) {
+                slab.extend(self.mcu_row[row * stride..row * stride + row_len].iter().cloned());
             }
 
-            self.components.insert(*id as usize, c);
+            let y = self.decoded_rows;
+            self.row_count = ((first + count) % slab_height) as u8;
+            self.decoded_rows += count as u32;
+
+            f(y, &slab);
         }
 
-        self.mcucount += 1;
-        self.read_restart()
+        Ok(())
     }
 
-    fn decode_block(&mut self, i: usize, dc: u8, pred: i32, ac: u8, q: u8) -> ImageResult<i32> {
-        let zz   = &mut self.mcu[i * 64..i * 64 + 64];
-        let mut tmp = [0i32; 64];
+    /// Decodes the image like ```read_image```, but returns the rows decoded
+    /// so far instead of an error if the data ends within the scan, as is
+    /// the case for files cut off during a download.
+    ///
+    /// Errors other than an unexpected end of the data are returned as is.
+    pub fn read_image_partial(&mut self) -> ImageResult<PartialDecode> {
+        let row_len = try!(self.row_len());
+        let height  = self.output_dimensions().1;
+        let mut data = Vec::with_capacity(row_len * height as usize);
+
+        let result = self.decode_rows(|_, slab| data.extend(slab.iter().cloned()));
+
+        match result {
+            Ok(()) => (),
+            Err(ref e) if is_unexpected_eof(e) => (),
+            Err(e) => return Err(e)
+        }
 
-        let dctable = &self.dctables[dc as usize];
-        let actable = &self.actables[ac as usize];
-        let qtable  = &self.qtables[64 * q as usize..64 * q as usize + 64];
+        let rows_decoded = (data.len() / cmp::max(row_len, 1)) as u32;
 
-        let t     = try!(self.h.decode_symbol(&mut self.r, dctable));
+        Ok(PartialDecode {
+            data: data,
+            rows_decoded: rows_decoded,
+            rows_missing: height - rows_decoded,
+        })
+    }
 
-        let diff  = if t > 0 {
-            try!(self.h.receive(&mut self.r, t))
-        } else {
-            0
-        };
+    /// Reads the quantized DCT coefficients of all blocks instead of decoding
+    /// the image to pixels. Together with the quantization tables, they allow
+    /// lossless transformations and re-encoding without generation loss.
+    ///
+    /// This has to be called before any rows are read.
+    pub fn read_coefficients(&mut self) -> ImageResult<Coefficients> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
 
-        // Section F.2.1.3.1
-        let diff = extend(diff, t);
-        let dc = diff + pred;
-        tmp[0] = dc * qtable[0] as i32;
+        if self.mcu_rows_decoded > 0 {
+            return Err(image::ImageError::FormatError(
+                "The scan was already decoded to pixels".to_string()
+            ))
+        }
 
-        let mut k = 0usize;
-        while k < 63 {
-            let rs = try!(self.h.decode_symbol(&mut self.r, actable));
+        let mcus_per_row    = self.padded_width / (8 * self.hmax as usize);
+        let mcus_per_column = self.mcus_per_column() as usize;
 
-            let ssss = rs & 0x0F;
-            let rrrr = rs >> 4;
+        let mut components = self.scan_components().iter().map(|&c| {
+            let quantization_table = self.natural_qtable(c.tq);
 
-            if ssss == 0 {
-                if rrrr != 15 {
-                    break
-                }
+            let blocks_wide = mcus_per_row * c.h as usize;
+            let blocks_high = mcus_per_column * c.v as usize;
 
-                k += 16;
-            } else {
-                k += rrrr as usize;
+            ComponentCoefficients {
+                id: c.id,
+                h: c.h,
+                v: c.v,
+                blocks_wide: blocks_wide as u32,
+                blocks_high: blocks_high as u32,
+                quantization_table: quantization_table,
+                blocks: vec![[0i16; 64]; blocks_wide * blocks_high],
+            }
+        }).collect::<Vec<ComponentCoefficients>>();
+
+        for mcu_y in (0..mcus_per_column) {
+            for mcu_x in (0..mcus_per_row) {
+                for i in (0..self.num_scan_components) {
+                    let mut c = self.components[i];
+
+                    for b in (0..c.h as usize * c.v as usize) {
+                        let bx = mcu_x * c.h as usize + b % c.h as usize;
+                        let by = mcu_y * c.v as usize + b / c.h as usize;
+
+                        let mut coefficients = [0i32; 64];
+                        c.dc_pred = try!(self.decode_coefficients(
+                            c.dc_table, c.dc_pred, c.ac_table, &mut coefficients
+                        ));
+
+                        let component = &mut components[i];
+                        let block = &mut component.blocks[by * component.blocks_wide as usize + bx];
+                        for (dst, &src) in block.iter_mut().zip(coefficients.iter()) {
+                            *dst = src as i16;
+                        }
+                    }
 
-                // Figure F.14
-                let t = try!(self.h.receive(&mut self.r, ssss));
+                    self.components[i] = c;
+                }
 
-                tmp[UNZIGZAG[k + 1] as usize] = extend(t, ssss) * qtable[k + 1] as i32;
-                k += 1;
+                self.mcucount += 1;
+                try!(self.read_restart());
             }
         }
 
-        transform::idct(&tmp, zz);
+        self.mcu_rows_decoded = mcus_per_column as u32;
 
-        Ok(dc)
+        Ok(Coefficients {
+            width: self.width as u32,
+            height: self.height as u32,
+            components: components,
+        })
     }
 
-    fn read_metadata(&mut self) -> ImageResult<()> {
-        while self.state != JPEGState::HaveFirstScan {
-            let byte = try!(self.r.read_u8());
+    /// Decodes the image into one plane per component, in the order of the
+    /// scan, without upsampling the chroma planes or converting the colors.
+    /// For color images these are the Y, Cb and Cr planes. The decode
+    /// options other than the tolerance do not apply.
+    ///
+    /// This has to be called before any rows are read.
+    pub fn read_planes(&mut self) -> ImageResult<Vec<ComponentPlane>> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
 
-            if byte != 0xFF {
-                continue;
+        if self.mcu_rows_decoded > 0 {
+            return Err(image::ImageError::FormatError(
+                "The scan was already decoded to pixels".to_string()
+            ))
+        }
+
+        let (width, height) = (self.width as u32, self.height as u32);
+        let (hmax, vmax) = (self.hmax as u32, self.vmax as u32);
+
+        let mut planes = self.scan_components().iter().map(|&c| {
+            let w = (width * c.h as u32 + hmax - 1) / hmax;
+            let h = (height * c.v as u32 + vmax - 1) / vmax;
+
+            ComponentPlane {
+                id: c.id,
+                h: c.h,
+                v: c.v,
+                width: w,
+                height: h,
+                data: Vec::with_capacity(w as usize * h as usize),
             }
+        }).collect::<Vec<ComponentPlane>>();
 
-            let marker = try!(self.r.read_u8());
+        while self.mcu_rows_decoded < self.mcus_per_column() {
+            try!(self.decode_mcu_row());
 
-            match marker {
-                SOI => self.state = JPEGState::HaveSOI,
-                DHT => try!(self.read_huffman_tables()),
-                DQT => try!(self.read_quantization_tables()),
-                SOF0 => {
-                    let _ = try!(self.read_frame_header());
-                    self.state = JPEGState::HaveFirstFrame;
-                }
-                SOS => {
-                    let _ = try!(self.read_scan_header());
-                    self.state = JPEGState::HaveFirstScan;
-                }
-                DRI => try!(self.read_restart_interval()),
-                APP0 ... APPF | COM => {
-                    let length = try!(self.r.read_u16::<BigEndian>());
-                    let mut buf = Vec::with_capacity((length - 2) as usize);
-                    try!(self.r.by_ref().take((length - 2) as u64).read_to_end(&mut buf));
+            for (plane, samples) in planes.iter_mut().zip(self.planes.iter()) {
+                let w = plane.width as usize;
+                let rows = cmp::min(samples.rows, plane.height as usize - plane.data.len() / w);
+
+                for y in (0..rows) {
+                    plane.data.extend(samples.row(y as isize)[..w].iter().cloned());
                 }
-                TEM  => continue,
-                SOF2 => return Err(image::ImageError::UnsupportedError("Marker SOF2 ist not supported.".to_string())),
-                DNL  => return Err(image::ImageError::UnsupportedError("Marker DNL ist not supported.".to_string())),
-                marker => return Err(image::ImageError::FormatError(format!("Unkown marker {} encountered.", marker))),
             }
         }
 
-        Ok(())
+        Ok(planes)
     }
 
-    fn read_frame_header(&mut self) -> ImageResult<()> {
-        let _frame_length = try!(self.r.read_u16::<BigEndian>());
-        let sample_precision = try!(self.r.read_u8());
+    /// Returns the APPn and COM segments before the first scan, in the order
+    /// of the stream. They hold metadata like EXIF, ICC profiles or vendor
+    /// specific data, which the decoder itself ignores.
+    pub fn marker_segments(&mut self) -> ImageResult<&[MarkerSegment]> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
 
-        if sample_precision != 8 {
-            return Err(image::ImageError::UnsupportedError(format!(
-                "A sample precision of {} is not supported",
-                sample_precision
-            )))
+        Ok(&self.segments)
+    }
+
+    /// Returns the ICC profile embedded in the APP2 segments, joined in the
+    /// order of their sequence numbers
+    pub fn icc_profile(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        let mut chunks = try!(self.marker_segments()).iter().filter(|s| {
+            s.marker == APP2 && s.data.len() >= 14 && s.data.starts_with(b"ICC_PROFILE\0")
+        }).map(|s| (s.data[12], &s.data[14..])).collect::<Vec<_>>();
+
+        if chunks.is_empty() {
+            return Ok(None)
         }
 
-        self.height         = try!(self.r.read_u16::<BigEndian>());
-        self.width          = try!(self.r.read_u16::<BigEndian>());
-        self.num_components = try!(self.r.read_u8());
+        chunks.sort_by_key(|c| c.0);
+        Ok(Some(chunks.iter().flat_map(|c| c.1.iter().cloned()).collect()))
+    }
 
-        if self.height == 0 || self.width == 0 {
-            return Err(image::ImageError::DimensionError)
+    /// Returns the orientation of the image in the EXIF metadata, from 1
+    /// for upright to 8 as defined by TIFF
+    pub fn exif_orientation(&mut self) -> ImageResult<Option<u16>> {
+        for segment in try!(self.marker_segments()).iter().filter(|s| s.marker == APP1) {
+            if let Some(orientation) = try!(exif::read_orientation(&segment.data)) {
+                return Ok(Some(orientation))
+            }
         }
 
-        if self.num_components != 1 && self.num_components != 3 {
-            return Err(image::ImageError::UnsupportedError(format!(
-                "Frames with {} components are not supported",
-                self.num_components
-            )))
+        Ok(None)
+    }
+
+    /// Returns the EXIF metadata of the first APP1 segment that has any
+    pub fn exif(&mut self) -> ImageResult<Option<Exif>> {
+        for segment in try!(self.marker_segments()).iter().filter(|s| s.marker == APP1) {
+            if let Some(exif) = try!(exif::read_exif(&segment.data)) {
+                return Ok(Some(exif))
+            }
         }
 
-        self.padded_width = 8 * ((self.width as usize + 7) / 8);
+        Ok(None)
+    }
 
-        let num_components = self.num_components;
-        self.read_frame_components(num_components)
+    /// Returns the C2PA manifest store embedded in the APP11 segments, the
+    /// JUMBF box with the provenance claims and signatures of the image,
+    /// without verifying it
+    pub fn c2pa_manifest(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        let segments = try!(self.marker_segments());
+        Ok(c2pa::read_manifest(segments))
     }
 
-    fn read_frame_components(&mut self, n: u8) -> ImageResult<()> {
-        let mut blocks_per_mcu = 0;
+    /// Returns the text of the COM segments before the first scan, in the
+    /// order of the stream. Bytes that are not UTF-8 are replaced with
+    /// U+FFFD.
+    pub fn comments(&mut self) -> ImageResult<Vec<String>> {
+        let segments = try!(self.marker_segments());
+        Ok(segments.iter().filter(|s| s.marker == COM).map(|s| {
+            String::from_utf8_lossy(&s.data).into_owned()
+        }).collect())
+    }
 
-        for _ in (0..n) {
-            let id = try!(self.r.read_u8());
-            let hv = try!(self.r.read_u8());
-            let tq = try!(self.r.read_u8());
+    /// Returns the quantization tables defined before the first scan in
+    /// natural (row-major) order, indexed by their table identifier.
+    /// Use ```jpeg::estimate_quality``` to infer the encoding quality.
+    pub fn quantization_tables(&mut self) -> ImageResult<[Option<[u16; 64]>; 4]> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
 
-            let c = Component {
-                id: id,
-                h: hv >> 4,
-                v: hv & 0x0F,
-                tq: tq,
-                dc_table: 0,
-                ac_table: 0,
-                dc_pred: 0
-            };
+        let mut tables = [None; 4];
 
-            blocks_per_mcu += (hv >> 4) * (hv & 0x0F);
-            self.components.insert(id as usize, c);
+        for (i, table) in tables.iter_mut().enumerate() {
+            if self.qtables_defined[i] {
+                *table = Some(self.natural_qtable(i as u8));
+            }
         }
 
-        let (hmax, vmax) = self.components.iter().fold((0, 0), | (h, v), (_, c) | {
-            (cmp::max(h, c.h), cmp::max(v, c.v))
+        Ok(tables)
+    }
+
+    // Returns quantization table `q` in natural order
+    fn natural_qtable(&self, q: u8) -> [u16; 64] {
+        let qtable = &self.qtables[64 * q as usize..64 * q as usize + 64];
+        let mut table = [0u16; 64];
+
+        for (k, &z) in UNZIGZAG.iter().enumerate() {
+            table[z as usize] = qtable[k] as u16;
+        }
+
+        table
+    }
+
+    /// Returns the SIMD level used by this decoder, which is selected when
+    /// it is created
+    pub fn simd_level(&self) -> SimdLevel {
+        self.simd
+    }
+
+    /// Returns the regions of the image that could not be decoded because
+    /// of errors in the data and were filled with gray. In `Tolerance::Lenient`
+    /// mode, the decoder skips to the next restart marker after an error
+    /// within a restart interval.
+    pub fn damaged_regions(&self) -> &[Rect] {
+        &self.damaged
+    }
+
+    /// Returns the problems that were tolerated while decoding in
+    /// `Tolerance::Lenient` mode. If the image is not empty, the
+    /// decoded image may be incomplete or damaged.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Returns the resources used to decode the image so far, to choose
+    /// the `Limits` of a service from measurements of typical images
+    pub fn resource_usage(&self) -> ResourceUsage {
+        self.usage
+    }
+
+    // The number of bytes of an output pixel
+    fn output_bpp(&self) -> usize {
+        if self.num_components == 3 && self.options.color_order == ColorOrder::BGRA {
+            4
+        } else {
+            self.num_components as usize
+        }
+    }
+
+    // Returns an error if holding `bytes` of sample buffers and marker
+    // segments exceeds the limits, otherwise accounts for them in the
+    // resource usage
+    fn check_alloc(&mut self, bytes: u64) -> ImageResult<()> {
+        let max = self.options.limits.max_alloc;
+
+        if bytes > max {
+            return Err(image::ImageError::LimitsExceeded(format!(
+                "Decoding needs {} bytes of buffers, more than the maximum of {}", bytes, max
+            )))
+        }
+
+        self.usage.checked_alloc = cmp::max(self.usage.checked_alloc, bytes);
+        Ok(())
+    }
+
+    fn mcus_per_column(&self) -> u32 {
+        let mcu_height = 8 * self.vmax as u32;
+        (self.height as u32 + mcu_height - 1) / mcu_height
+    }
+
+    // Decodes the next MCU row and converts it to interleaved
+    // output samples in `mcu_row`.
+    fn next_mcu_row(&mut self) -> ImageResult<()> {
+        if self.options.upsampling == UpsamplingMethod::Replicate || self.hmax * self.vmax == 1 {
+            try!(self.decode_mcu_row());
+            mem::swap(&mut self.current, &mut self.planes);
+        } else {
+            // Fancy upsampling needs the neighbouring sample rows, thus the
+            // decoder keeps one MCU row ahead of the output.
+            if self.mcu_rows_decoded == 0 {
+                try!(self.decode_mcu_row());
+                mem::swap(&mut self.current, &mut self.planes);
+
+                for plane in self.current.iter_mut() {
+                    let row = plane.row(0).to_vec();
+                    ::copy_memory(&row, plane.row_mut(-1));
+                }
+            } else {
+                mem::swap(&mut self.current, &mut self.planes);
+
+                for (cur, prev) in self.current.iter_mut().zip(self.planes.iter()) {
+                    copy_plane_row(prev, prev.rows as isize - 1, cur, -1);
+                }
+            }
+
+            if self.mcu_rows_decoded < self.mcus_per_column() {
+                try!(self.decode_mcu_row());
+
+                for (cur, next) in self.current.iter_mut().zip(self.planes.iter()) {
+                    copy_plane_row(next, 0, cur, cur.rows as isize);
+                }
+            } else {
+                for plane in self.current.iter_mut() {
+                    let last = plane.rows as isize - 1;
+                    let row = plane.row(last).to_vec();
+                    ::copy_memory(&row, plane.row_mut(last + 1));
+                }
+            }
+        }
+
+        let layout = self.scan_components().to_vec();
+
+        let bpp = self.output_bpp();
+
+        upsample_row(
+            &mut self.mcu_row,
+            self.padded_width,
+            bpp,
+            &self.current,
+            &layout,
+            self.hmax,
+            self.vmax,
+            self.options.upsampling,
+            self.options.color_order,
+            self.simd
+        );
+
+        if layout.len() == 4 {
+            cmyk_to_rgba(&mut self.mcu_row, self.adobe_transform);
+        }
+
+        let scale = self.options.scale as usize;
+
+        if scale > 1 {
+            downscale_rows(&mut self.mcu_row, self.padded_width, 8 * self.vmax as usize, bpp, scale);
+        }
+
+        Ok(())
+    }
+
+    // Decodes one MCU row into `planes`. In lenient mode the part of a
+    // truncated or corrupt image that can not be decoded is filled with gray.
+    fn decode_mcu_row(&mut self) -> ImageResult<()> {
+        let mcus_per_row = self.padded_width / (8 * self.hmax as usize);
+        let mut decoded  = 0;
+
+        if self.mcucount == 0 && self.intervals.is_empty() && self.decodes_in_parallel() &&
+           self.interval > 0 && self.total_mcus() > self.interval as u32 {
+            try!(self.decode_intervals());
+        }
+
+        if !self.intervals.is_empty() {
+            self.copy_interval_mcus(mcus_per_row);
+            self.mcu_rows_decoded += 1;
+
+            return Ok(())
+        }
+
+        if self.decodes_in_parallel() {
+            try!(self.decode_pipelined(mcus_per_row));
+            decoded = mcus_per_row;
+        }
+
+        while decoded < mcus_per_row && !self.truncated {
+            if self.skip_mcus > 0 {
+                // Damaged MCUs before the restart marker the decoder resynchronized on
+                self.fill_mcus(decoded, decoded + 1);
+                self.mark_damaged(decoded);
+                self.skip_mcus -= 1;
+                self.mcucount += 1;
+                decoded += 1;
+                continue
+            }
+
+            let error = match self.decode_mcu(decoded) {
+                Ok(()) => {
+                    decoded += 1;
+
+                    match self.read_restart() {
+                        Ok(()) => continue,
+                        Err(e) => e
+                    }
+                }
+                Err(e) => {
+                    if self.options.tolerance == Tolerance::Lenient && self.resync() {
+                        self.warnings.push(format!(
+                            "Skipped {} damaged MCUs after MCU {}: {}", self.skip_mcus, self.mcucount, e
+                        ));
+                        continue
+                    }
+
+                    e
+                }
+            };
+
+            if self.options.tolerance == Tolerance::Strict || self.mcu_rows_decoded == 0 {
+                return Err(error)
+            }
+
+            self.warnings.push(format!(
+                "Decoding stopped at MCU row {}: {}", self.mcu_rows_decoded, error
+            ));
+            self.truncated = true;
+        }
+
+        self.fill_mcus(decoded, mcus_per_row);
+        for mcu_x in (decoded..mcus_per_row) {
+            self.mark_damaged(mcu_x);
+        }
+
+        self.mcu_rows_decoded += 1;
+
+        // Consume the rest of the scan and the marker that ends it, unless the
+        // Huffman decoder already read the marker or the end of the data
+        if self.mcu_rows_decoded == self.mcus_per_column() && !self.truncated &&
+           self.h.marker == 0 && !self.h.eof {
+            let _ = try!(self.read_scan_data(false));
+        }
+
+        Ok(())
+    }
+
+    fn decodes_in_parallel(&self) -> bool {
+        self.threads > 1 &&
+        self.options.tolerance == Tolerance::Strict
+    }
+
+    // The number of sample bytes of one MCU of the scan
+    fn mcu_bytes(&self) -> usize {
+        self.scan_components().iter().map(|c| {
+            64 * c.h as usize * c.v as usize
+        }).fold(0, |a, b| a + b)
+    }
+
+    // Reads the entropy-coded data of the scan, up to and including the
+    // marker that ends it, and returns it if `keep` is set. This leaves the
+    // reader right after the EOI marker of a complete image, at the start of
+    // whatever follows it.
+    fn read_scan_data(&mut self, keep: bool) -> ImageResult<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut byte = [0u8];
+        let mut previous = 0;
+
+        loop {
+            match self.r.read(&mut byte) {
+                Ok(0) => return Ok(data),
+                Ok(_) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(image::ImageError::IoError(e))
+            }
+
+            if keep {
+                data.push(byte[0]);
+            }
+
+            if previous == 0xFF {
+                match byte[0] {
+                    // A stuffed zero byte, a fill byte or a restart marker
+                    0x00 | 0xFF | RST0 ... RST7 => (),
+                    _ => return Ok(data)
+                }
+            }
+            previous = byte[0];
+        }
+    }
+
+    // Reads the rest of the scan and decodes its restart intervals in
+    // parallel into `intervals`, which holds the samples of all MCUs.
+    fn decode_intervals(&mut self) -> ImageResult<()> {
+        let data = try!(self.read_scan_data(true));
+
+        let interval = self.interval as usize;
+        let total = self.total_mcus() as usize;
+        let count = (total + interval - 1) / interval;
+
+        let mut segments = try!(split_intervals(&data));
+        if segments.len() < count {
+            return Err(image::ImageError::FormatError(format!(
+                "Found {} of {} restart intervals", segments.len(), count
+            )))
+        }
+        segments.truncate(count);
+
+        let layout = self.scan_components().to_vec();
+        let mcu_bytes = self.mcu_bytes();
+
+        let retained = self.retained;
+        try!(self.check_alloc(retained + (total * mcu_bytes) as u64));
+        self.intervals.clear();
+        self.intervals.resize(total * mcu_bytes, 0);
+
+        let jobs = cmp::min(self.threads, count);
+        let per_job = (count + jobs - 1) / jobs;
+        let mut results = (0..(count + per_job - 1) / per_job).map(|_| Ok(()))
+                                                             .collect::<Vec<ImageResult<()>>>();
+
+        let tables = Tables {
+            dctables: &self.dctables,
+            actables: &self.actables,
+            qtables: &self.qtables,
+            layout: &layout,
+            idct: self.idct,
+        };
+        let tables = &tables;
+
+        executor::scope(&*self.executor, self.intervals.chunks_mut(per_job * interval * mcu_bytes)
+                                                       .zip(segments.chunks(per_job))
+                                                       .zip(results.iter_mut())
+                                                       .map(|((out, segments), result)| {
+            let job: Job = Box::new(move || {
+                *result = segments.iter()
+                                  .zip(out.chunks_mut(interval * mcu_bytes))
+                                  .map(|(&segment, out)| decode_interval(segment, tables, out))
+                                  .collect();
+            });
+
+            job
+        }).collect());
+
+        let result: ImageResult<()> = results.into_iter().collect();
+        if result.is_err() {
+            self.intervals.clear();
+        }
+
+        result
+    }
+
+    // Decodes the current MCU row of a scan with at most one restart
+    // interval into `planes`. The rows are entropy decoded on the calling
+    // thread and handed to the executor, up to `threads` rows ahead of the
+    // one returned.
+    fn decode_pipelined(&mut self, mcus_per_row: usize) -> ImageResult<()> {
+        let row_blocks = mcus_per_row * self.mcu_bytes() / 64;
+
+        if self.mcu_rows_decoded == 0 {
+            // The coefficients and samples of the rows in flight
+            let in_flight = self.threads * row_blocks * 64 * (mem::size_of::<i32>() + 1);
+            let total = self.usage.checked_alloc + in_flight as u64;
+            try!(self.check_alloc(total));
+
+            let (sender, receiver) = mpsc::sync_channel(self.threads);
+            self.pipeline = Some(Pipeline {
+                qtables: Arc::new(self.qtables),
+                layout: Arc::new(self.scan_components().to_vec()),
+                sender: sender,
+                receiver: receiver,
+                next: 0,
+                done: Vec::new(),
+                error: None,
+            });
+        }
+
+        let mut pipeline = match self.pipeline.take() {
+            Some(pipeline) => pipeline,
+            None => return Err(image::ImageError::ImageEnd),
+        };
+
+        // At most `threads` rows are in flight, which the channel can hold
+        // without blocking a job
+        let ahead = cmp::min(self.mcu_rows_decoded + self.threads as u32, self.mcus_per_column());
+        while pipeline.next < ahead && pipeline.error.is_none() {
+            let mut blocks = match self.decode_row_coefficients(mcus_per_row, row_blocks) {
+                Ok(blocks) => blocks,
+                Err(e) => {
+                    pipeline.error = Some(e);
+                    break
+                }
+            };
+
+            let result = SendRow { sender: pipeline.sender.clone(), row: pipeline.next, samples: None };
+            let (qtables, layout, idct) = (pipeline.qtables.clone(), pipeline.layout.clone(), self.idct);
+            self.executor.spawn(Box::new(move || {
+                let mut samples = vec![0u8; 64 * blocks.len()];
+                transform_blocks(&mut blocks, &*qtables, &layout, idct, &mut samples);
+                result.complete(samples);
+            }));
+
+            pipeline.next += 1;
+        }
+
+        let row = self.mcu_rows_decoded;
+        if row == pipeline.next {
+            return Err(pipeline.error.take().unwrap_or(image::ImageError::ImageEnd))
+        }
+
+        let samples = loop {
+            if let Some(i) = pipeline.done.iter().position(|done| done.0 == row) {
+                break pipeline.done.swap_remove(i).1
+            }
+
+            // The pipeline is dropped with the error, the other jobs send
+            // their rows into a closed channel
+            match pipeline.receiver.recv() {
+                Ok((row, Some(samples))) => pipeline.done.push((row, samples)),
+                _ => return Err(image::ImageError::FormatError(
+                    "A job of the executor panicked or did not run".to_string())),
+            }
+        };
+
+        put_mcus(&mut self.planes, &pipeline.layout, mcus_per_row, &samples);
+
+        if row + 1 < self.mcus_per_column() {
+            self.pipeline = Some(pipeline);
+        }
+
+        Ok(())
+    }
+
+    // Entropy decodes the next MCU row into the coefficients of its blocks,
+    // in the order of the scan
+    fn decode_row_coefficients(&mut self, mcus_per_row: usize,
+                               row_blocks: usize) -> ImageResult<Vec<[i32; 64]>> {
+        let mut blocks = Vec::with_capacity(row_blocks);
+
+        for _ in (0..mcus_per_row) {
+            for i in (0..self.num_scan_components) {
+                let c = self.components[i];
+                let mut pred = c.dc_pred;
+
+                for _ in (0..c.h as usize * c.v as usize) {
+                    blocks.push([0i32; 64]);
+                    pred = try!(self.decode_coefficients(c.dc_table, pred, c.ac_table,
+                                                         blocks.last_mut().unwrap()));
+                }
+
+                self.components[i].dc_pred = pred;
+            }
+
+            self.mcucount += 1;
+            try!(self.read_restart());
+        }
+
+        Ok(blocks)
+    }
+
+    // Copies the MCUs of the current MCU row from `intervals` to `planes`
+    fn copy_interval_mcus(&mut self, mcus_per_row: usize) {
+        let start = self.mcu_rows_decoded as usize * mcus_per_row * self.mcu_bytes();
+        let layout = &self.components[..self.num_scan_components];
+
+        put_mcus(&mut self.planes, layout, mcus_per_row, &self.intervals[start..]);
+        self.mcucount += mcus_per_row as u32;
+    }
+
+    // Skips to the next restart marker after an error within a restart
+    // interval. Sets `skip_mcus` to the number of MCUs from the damaged one
+    // up to the interval starting at the marker. Returns false if the image
+    // has no restart intervals or no further restart marker was found.
+    fn resync(&mut self) -> bool {
+        if self.interval == 0 {
+            return false
+        }
+
+        let rst = match self.find_restart_marker() {
+            Ok(rst) if rst >= RST0 && rst <= RST7 => rst,
+            _ => return false
+        };
+
+        // Corrupt data may hide further markers, in which case more than
+        // the rest of the current interval has to be skipped
+        let interval = self.interval as u32;
+        let skipped  = ((rst + 8 - self.expected_rst) % 8) as u32;
+        let resume   = (self.mcucount / interval + 1 + skipped) * interval;
+
+        self.skip_mcus = cmp::min(resume, self.total_mcus()) - self.mcucount;
+        self.expected_rst = if rst == RST7 { RST0 } else { rst + 1 };
+        self.reset_entropy_coder();
+
+        true
+    }
+
+    // Fills the MCUs `from..to` of the current MCU row with gray
+    fn fill_mcus(&mut self, from: usize, to: usize) {
+        for i in (0..self.num_scan_components) {
+            let h = self.components[i].h as usize;
+            let plane = &mut self.planes[i];
+
+            for y in (0..plane.rows) {
+                for s in plane.row_mut(y as isize)[from * 8 * h..to * 8 * h].iter_mut() {
+                    *s = 128;
+                }
+            }
+        }
+    }
+
+    // Records MCU `mcu_x` of the current MCU row as damaged
+    fn mark_damaged(&mut self, mcu_x: usize) {
+        let (mcu_width, mcu_height) = (8 * self.hmax as u32, 8 * self.vmax as u32);
+        let x = mcu_x as u32 * mcu_width;
+        let y = self.mcu_rows_decoded * mcu_height;
+
+        let width  = cmp::min(mcu_width, self.width as u32 - x);
+        let height = cmp::min(mcu_height, self.height as u32 - y);
+
+        if let Some(last) = self.damaged.last_mut() {
+            if last.y == y && last.x + last.width == x {
+                last.width += width;
+                return
+            }
+        }
+
+        self.damaged.push(Rect::new(x, y, width, height));
+    }
+
+    fn total_mcus(&self) -> u32 {
+        let mcu_width = 8 * self.hmax as u32;
+        let mcus_per_row = (self.width as u32 + mcu_width - 1) / mcu_width;
+
+        mcus_per_row * self.mcus_per_column()
+    }
+
+    // The dimensions of the decoded image after scaling
+    fn output_dimensions(&self) -> (u32, u32) {
+        let scale = self.options.scale as u32;
+
+        ((self.width as u32 + scale - 1) / scale, (self.height as u32 + scale - 1) / scale)
+    }
+
+    fn scan_components(&self) -> &[Component] {
+        &self.components[..self.num_scan_components]
+    }
+
+    fn decode_mcu(&mut self, mcu_x: usize) -> ImageResult<()> {
+        for i in (0..self.num_scan_components) {
+            let c = self.components[i];
+            self.components[i].dc_pred = try!(self.decode_blocks(i, mcu_x, &c));
+        }
+
+        self.mcucount += 1;
+
+        Ok(())
+    }
+
+    // Decodes the blocks of component `c` in the MCU at `mcu_x` into plane
+    // `i`, transforming them together. Returns the new DC prediction.
+    fn decode_blocks(&mut self, i: usize, mcu_x: usize, c: &Component) -> ImageResult<i32> {
+        let count = c.h as usize * c.v as usize;
+        let qtable = &self.qtables[64 * c.tq as usize..64 * c.tq as usize + 64];
+        let (dctable, actable) = (&self.dctables[c.dc_table as usize], &self.actables[c.ac_table as usize]);
+        let mut pred = c.dc_pred;
+
+        for block in self.blocks[..count].iter_mut() {
+            *block = [0; 64];
+            pred = try!(decode_coefficients(&mut self.h, &mut self.r, dctable, actable, pred, block));
+            dequantize(block, qtable);
+        }
+
+        let samples = &mut self.block_samples[..64 * count];
+        self.idct.transform(&self.blocks[..count], samples);
+
+        for (b, samples) in samples.chunks(64).enumerate() {
+            let bx = mcu_x * c.h as usize + b % c.h as usize;
+            put_block(&mut self.planes[i], bx, b / c.h as usize, samples);
+        }
+
+        Ok(pred)
+    }
+
+    fn decode_coefficients(&mut self, dc: u8, pred: i32, ac: u8,
+                           coefficients: &mut [i32; 64]) -> ImageResult<i32> {
+        decode_coefficients(&mut self.h, &mut self.r, &self.dctables[dc as usize],
+                            &self.actables[ac as usize], pred, coefficients)
+    }
+
+    fn read_metadata(&mut self) -> ImageResult<()> {
+        match self.options.scale {
+            1 | 2 | 4 | 8 => (),
+            scale => return Err(image::ImageError::UnsupportedError(format!(
+                "A scale factor of {} is not supported, only 1, 2, 4 and 8 are", scale
+            )))
+        }
+
+        while self.state != JPEGState::HaveFirstScan {
+            let byte = try!(self.r.read_u8());
+
+            if byte != 0xFF {
+                continue;
+            }
+
+            // Any number of fill bytes may precede a marker
+            let mut marker = try!(self.r.read_u8());
+            while marker == 0xFF {
+                marker = try!(self.r.read_u8());
+            }
+
+            match marker {
+                SOI => self.state = JPEGState::HaveSOI,
+                DHT => try!(self.read_huffman_tables()),
+                DQT => try!(self.read_quantization_tables()),
+                SOF0 => {
+                    let _ = try!(self.read_frame_header());
+                    self.state = JPEGState::HaveFirstFrame;
+                }
+                SOS => {
+                    let _ = try!(self.read_scan_header());
+                    self.state = JPEGState::HaveFirstScan;
+                }
+                DRI => try!(self.read_restart_interval()),
+                APP0 ... APPF | COM => {
+                    let offset = self.r.count - 2;
+                    let length = try!(self.r.read_u16::<BigEndian>()).saturating_sub(2);
+                    let retained = self.retained + length as u64;
+                    try!(self.check_alloc(retained));
+                    self.retained = retained;
+
+                    let mut buf = Vec::with_capacity(length as usize);
+                    try!(self.r.by_ref().take(length as u64).read_to_end(&mut buf));
+
+                    if marker == APP14 && buf.len() >= 12 && buf.starts_with(b"Adobe") {
+                        self.adobe_transform = Some(buf[11]);
+                    }
+
+                    self.segments.push(MarkerSegment {
+                        marker: marker,
+                        offset: offset,
+                        data: buf,
+                    });
+                }
+                TEM  => continue,
+                SOF2 => return Err(image::ImageError::UnsupportedError("Marker SOF2 ist not supported.".to_string())),
+                DNL  => return Err(image::ImageError::UnsupportedError("Marker DNL ist not supported.".to_string())),
+                // Standalone markers have no length
+                RST0 ... RST7 | EOI if self.options.tolerance == Tolerance::Lenient => {
+                    self.warnings.push(format!("Skipped unexpected marker {}", marker));
+                }
+                marker if self.options.tolerance == Tolerance::Lenient => {
+                    self.warnings.push(format!("Skipped unknown marker {}", marker));
+                    let length = try!(self.r.read_u16::<BigEndian>());
+                    let mut buf = Vec::new();
+                    try!(self.r.by_ref().take(length.saturating_sub(2) as u64).read_to_end(&mut buf));
+                }
+                marker => return Err(image::ImageError::FormatError(format!("Unkown marker {} encountered.", marker))),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_frame_header(&mut self) -> ImageResult<()> {
+        let _frame_length = try!(self.r.read_u16::<BigEndian>());
+        let sample_precision = try!(self.r.read_u8());
+
+        if sample_precision != 8 {
+            return Err(image::ImageError::UnsupportedError(format!(
+                "A sample precision of {} is not supported",
+                sample_precision
+            )))
+        }
+
+        self.height        = try!(self.r.read_u16::<BigEndian>());
+        self.width         = try!(self.r.read_u16::<BigEndian>());
+        let num_components = try!(self.r.read_u8());
+
+        if self.height == 0 || self.width == 0 {
+            return Err(image::ImageError::DimensionError)
+        }
+
+        let limits = self.options.limits;
+        let (width, height) = (self.width as u32, self.height as u32);
+
+        if width > limits.max_width || height > limits.max_height ||
+           width as u64 * height as u64 > limits.max_pixels {
+            return Err(image::ImageError::DimensionError)
+        }
+
+        // A baseline scan interleaves at most 4 components, Section B.2.3.
+        // The count is only stored once it is accepted, since later calls
+        // index the components with it.
+        if num_components == 0 || num_components > 4 {
+            return Err(image::ImageError::UnsupportedError(format!(
+                "Frames with {} components are not supported",
+                num_components
+            )))
+        }
+
+        self.num_components = num_components;
+        self.read_frame_components(num_components)
+    }
+
+    fn read_frame_components(&mut self, n: u8) -> ImageResult<()> {
+
+        for i in (0..n as usize) {
+            let id = try!(self.r.read_u8());
+            let hv = try!(self.r.read_u8());
+            let tq = try!(self.r.read_u8());
+
+            let (h, v) = (hv >> 4, hv & 0x0F);
+
+            // Section B.2.2
+            if h == 0 || h > 4 || v == 0 || v > 4 {
+                return Err(image::ImageError::FormatError(format!(
+                    "Invalid sampling factors {}x{} for component {}", h, v, id
+                )))
+            }
+
+            if tq > 3 {
+                return Err(image::ImageError::FormatError(format!(
+                    "Invalid quantization table {} for component {}", tq, id
+                )))
+            }
+
+            let c = Component {
+                id: id,
+                h: h,
+                v: v,
+                tq: tq,
+                dc_table: 0,
+                ac_table: 0,
+                dc_pred: 0
+            };
+
+            self.components[i] = c;
+        }
+
+        let components = &mut self.components[..n as usize];
+        let (hmax, vmax) = components.iter().fold((0, 0), | (h, v), c | {
+            (cmp::max(h, c.h), cmp::max(v, c.v))
+        });
+
+        self.hmax = hmax;
+        self.vmax = vmax;
+
+        // only 1 component no interleaving
+        if n == 1 {
+            for c in components.iter_mut() {
+                c.h = 1;
+                c.v = 1;
+            }
+
+            self.hmax = 1;
+            self.vmax = 1;
+        }
+
+        // Rows are padded to a whole number of MCUs so that the
+        // rightmost MCU can be upsampled without wrapping around.
+        let mcu_width = 8 * self.hmax as usize;
+        let mcus_per_row = (self.width as usize + mcu_width - 1) / mcu_width;
+        self.padded_width = mcus_per_row * mcu_width;
+
+        let mcu_row_len = self.padded_width * self.output_bpp() * 8 * self.vmax as usize;
+
+        // The sample planes of the current and the next MCU row
+        let planes_len = 2 * (8 * self.vmax as usize + 2) * self.padded_width * n as usize;
+        let retained = self.retained;
+        try!(self.check_alloc(retained + (mcu_row_len + planes_len) as u64));
+
+        let (width, height) = self.output_dimensions();
+        self.usage.output_size = width as u64 * height as u64 * self.output_bpp() as u64;
+
+        self.mcu_row.clear();
+        self.mcu_row.resize(mcu_row_len, 0);
+
+        Ok(())
+    }
+
+    fn read_scan_header(&mut self) -> ImageResult<()> {
+        let _scan_length = try!(self.r.read_u16::<BigEndian>());
+
+        let num_scan_components = try!(self.r.read_u8());
+
+        if num_scan_components == 0 || num_scan_components > self.num_components {
+            return Err(image::ImageError::FormatError(format!(
+                "Invalid number of scan components {}", num_scan_components
+            )))
+        }
+
+        // Moves the components of the scan to the front in its order
+        for i in (0..num_scan_components as usize) {
+            let id = try!(self.r.read_u8());
+            let tables = try!(self.r.read_u8());
+
+            let found = (i..self.num_components as usize).find(|&j| self.components[j].id == id);
+            let j = match found {
+                Some(j) => j,
+                None => return Err(image::ImageError::FormatError(format!(
+                    "Scan component {} is not in the frame or appears twice", id
+                )))
+            };
+
+            // Baseline scans select one of two Huffman tables each, Section B.2.3
+            let (td, ta) = (tables >> 4, tables & 0x0F);
+            if td > 1 || ta > 1 {
+                return Err(image::ImageError::FormatError(format!(
+                    "Invalid Huffman tables {} and {} for component {}", td, ta, id
+                )))
+            }
+
+            self.components.swap(i, j);
+            self.components[i].dc_table = td;
+            self.components[i].ac_table = ta;
+        }
+
+        self.num_scan_components = num_scan_components as usize;
+
+        let blocks = self.scan_components().iter().map(|c| c.h as usize * c.v as usize).sum::<usize>();
+        if num_scan_components > 1 && blocks > 10 {
+            return Err(image::ImageError::FormatError(format!(
+                "Interleaved scans can not have {} blocks per MCU", blocks
+            )))
+        }
+
+        let _spectral_end   = try!(self.r.read_u8());
+        let _spectral_start = try!(self.r.read_u8());
+
+        let approx = try!(self.r.read_u8());
+
+        let _approx_high = approx >> 4;
+        let _approx_low  = approx & 0x0F;
+
+        self.allocate_planes();
+
+        Ok(())
+    }
+
+    // Allocates the sample planes for the components of the current scan
+    fn allocate_planes(&mut self) {
+        let mcus_per_row = self.padded_width / (8 * self.hmax as usize);
+
+        let sizes = self.scan_components().iter().map(|c| {
+            (mcus_per_row * 8 * c.h as usize, 8 * c.v as usize)
+        }).collect::<Vec<(usize, usize)>>();
+
+        // The planes of a previous image are reused after `reset`
+        for planes in [&mut self.planes, &mut self.current].iter_mut() {
+            planes.truncate(sizes.len());
+
+            for (i, &(stride, rows)) in sizes.iter().enumerate() {
+                if i < planes.len() {
+                    planes[i].resize(stride, rows);
+                } else {
+                    planes.push(Plane::new(stride, rows));
+                }
+            }
+        }
+    }
+
+    fn read_quantization_tables(&mut self) -> ImageResult<()> {
+        let mut table_length = try!(self.r.read_u16::<BigEndian>()) as i32;
+        table_length -= 2;
+
+        while table_length > 0 {
+            let pqtq = try!(self.r.read_u8());
+            let pq = pqtq >> 4;
+            let tq = pqtq & 0x0F;
+
+            if pq != 0 || tq > 3 {
+                return Err(image::ImageError::FormatError("Quantization table malformed.".to_string()))
+            }
+
+            self.qtables_defined[tq as usize] = true;
+            let slice = &mut self.qtables[64 * tq as usize..64 * tq as usize + 64];
+
+            for i in (0usize..64) {
+                slice[i] = try!(self.r.read_u8());
+            }
+
+            table_length -= 1 + 64;
+        }
+
+        Ok(())
+    }
+
+    fn read_huffman_tables(&mut self) -> ImageResult<()> {
+        let mut table_length = try!(self.r.read_u16::<BigEndian>());
+        table_length -= 2;
+
+        while table_length > 0 {
+            let tcth = try!(self.r.read_u8());
+            let tc = tcth >> 4;
+            let th = tcth & 0x0F;
+
+            if tc != 0 && tc != 1 {
+                return Err(image::ImageError::UnsupportedError(format!(
+                    "Huffman table class {} is not supported", tc
+                )))
+            }
+
+            let mut bits = Vec::with_capacity(16);
+            try!(self.r.by_ref().take(16).read_to_end(&mut bits));
+            let len = bits.len();
+
+            let mt = bits.iter().fold(0, | a, b | a + *b);
+            let mut huffval = Vec::with_capacity(mt as usize);
+            try!(self.r.by_ref().take(mt as u64).read_to_end(&mut huffval));
+
+            if tc == 0 {
+                self.dctables[th as usize] = derive_tables(bits, huffval);
+            } else {
+                self.actables[th as usize] = derive_tables(bits, huffval);
+            }
+
+            table_length -= 1 + len as u16 + mt as u16;
+        }
+
+        Ok(())
+    }
+
+
+    fn read_restart_interval(&mut self) -> ImageResult<()> {
+        let _length = try!(self.r.read_u16::<BigEndian>());
+        self.interval = try!(self.r.read_u16::<BigEndian>());
+
+        Ok(())
+    }
+
+    fn read_restart(&mut self) -> ImageResult<()> {
+        if self.interval != 0  &&
+           self.mcucount % (self.interval as u32) == 0 &&
+           self.mcucount < self.total_mcus() {
+
+            let rst = try!(self.find_restart_marker());
+
+            if rst != self.expected_rst {
+                if self.options.tolerance == Tolerance::Strict {
+                    return Err(image::ImageError::FormatError(format!(
+                        "Unexpected restart maker {} found", rst
+                    )))
+                }
+
+                self.warnings.push(format!("Unexpected restart marker {} found", rst));
+            }
+
+            self.reset_entropy_coder();
+            self.expected_rst = if rst == RST7 { RST0 } else { rst + 1 };
+        }
+
+        Ok(())
+    }
+
+    fn find_restart_marker(&mut self) -> ImageResult<u8> {
+        if self.h.marker != 0 {
+            let m = self.h.marker;
+            self.h.marker = 0;
+
+            return Ok(m);
+        }
+
+        let mut b;
+        loop {
+            b = try!(self.r.read_u8());
+
+            if b == 0xFF {
+            b = try!(self.r.read_u8());
+                match b {
+                    RST0 ... RST7 => break,
+                    EOI => return Err(image::ImageError::FormatError("Restart marker not found.".to_string())),
+                    _   => continue
+                }
+            }
+        }
+
+        Ok(b)
+    }
+
+    fn reset_entropy_coder(&mut self) {
+        self.h.bits = 0;
+        self.h.num_bits = 0;
+        self.h.end = false;
+        self.h.marker = 0;
+
+        for c in self.components.iter_mut() {
+            c.dc_pred = 0;
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl JPEGDecoder<MappedFile> {
+    /// Create a new decoder that decodes the file at ```path```, mapped
+    /// into memory
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to or truncated, by this or any other
+    /// process, while the decoder exists. See the ```mmap``` module.
+    pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> ImageResult<JPEGDecoder<MappedFile>> {
+        Ok(JPEGDecoder::new(try!(mmap::map_file(path))))
+    }
+}
+
+impl<R: Read> ImageDecoder for JPEGDecoder<R> {
+    fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
+
+        Ok(self.output_dimensions())
+    }
+
+    fn colortype(&mut self) -> ImageResult<color::ColorType> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
+
+        // 3 components are converted from YCbCr and 4 from CMYK or YCCK, the
+        // samples of 2 components are returned as gray with alpha
+        let ctype = match self.output_bpp() {
+            1 => color::ColorType::Gray(8),
+            2 => color::ColorType::GrayA(8),
+            3 => color::ColorType::RGB(8),
+            _ => color::ColorType::RGBA(8),
+        };
+
+        Ok(ctype)
+    }
+
+    // The densities of the JFIF header, a pixel is as wide as the inverse of
+    // the horizontal density
+    fn pixel_aspect_ratio(&mut self) -> ImageResult<(u32, u32)> {
+        let jfif = try!(self.marker_segments()).iter().find(|s| {
+            s.marker == APP0 && s.data.len() >= 12 && s.data.starts_with(b"JFIF\0")
         });
 
-        self.hmax = hmax;
-        self.vmax = vmax;
+        Ok(match jfif {
+            Some(s) => image::reduce_aspect_ratio(BigEndian::read_u16(&s.data[10..12]) as u32,
+                                                  BigEndian::read_u16(&s.data[8..10]) as u32),
+            None => (1, 1)
+        })
+    }
+
+    fn row_len(&mut self) -> ImageResult<usize> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
+
+        let len = self.output_dimensions().0 as usize * self.output_bpp();
+
+        Ok(len)
+    }
+
+    fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
+
+        if self.row_count == 0 {
+            let _ = try!(self.next_mcu_row());
+        }
+
+        let len   = self.padded_width / self.options.scale as usize * self.output_bpp();
+        let slice = &self.mcu_row[self.row_count as usize * len..
+        self.row_count as usize * len + buf.len()];
+
+        ::copy_memory(slice, buf);
+
+        self.row_count = (self.row_count + 1) % (self.vmax * 8 / self.options.scale);
+        self.decoded_rows += 1;
+
+        Ok(self.decoded_rows)
+    }
+
+    // Copies runs of rows out of each MCU row, stopping at the last row of
+    // the image instead of reading past it
+    fn read_scanlines(&mut self, n: usize, buf: &mut [u8]) -> ImageResult<usize> {
+        let row = try!(self.row_len());
+        let height = self.output_dimensions().1;
+
+        if self.decoded_rows >= height {
+            return Err(image::ImageError::ImageEnd)
+        }
+        if row == 0 {
+            return Ok(0)
+        }
+
+        let rows = cmp::min(cmp::min(n, buf.len() / row), (height - self.decoded_rows) as usize);
+        let stride = self.padded_width / self.options.scale as usize * self.output_bpp();
+        let mcu_rows = self.vmax * 8 / self.options.scale;
+        let mut done = 0;
+
+        while done < rows {
+            if self.row_count == 0 {
+                let _ = try!(self.next_mcu_row());
+            }
+
+            let first = self.row_count as usize;
+            let count = cmp::min(rows - done, mcu_rows as usize - first);
+
+            for i in (0..count) {
+                let start = (first + i) * stride;
+                ::copy_memory(&self.mcu_row[start..start + row],
+                              &mut buf[(done + i) * row..(done + i + 1) * row]);
+            }
+
+            done += count;
+            self.row_count = ((first + count) % mcu_rows as usize) as u8;
+            self.decoded_rows += count as u32;
+        }
+
+        Ok(rows)
+    }
+
+    fn read_image(&mut self) -> ImageResult<image::DecodingResult> {
+        if self.state != JPEGState::HaveFirstScan {
+            let _ = try!(self.read_metadata());
+        }
+
+        let row = try!(self.row_len());
+        let height = self.output_dimensions().1 as usize;
+        let mut buf = repeat(0u8).take(row * height).collect::<Vec<u8>>();
+
+        for chunk in buf.chunks_mut(row) {
+            let _len = try!(self.read_scanline(chunk));
+        }
+
+        Ok(image::DecodingResult::U8(buf))
+    }
+
+    // Decodes the rows directly into `buf`, without an intermediate buffer
+    fn read_image_into(&mut self, buf: &mut [u8]) -> ImageResult<()> {
+        let row = try!(self.row_len());
+        let height = self.output_dimensions().1 as usize;
+
+        if buf.len() < row * height {
+            return Err(image::ImageError::DimensionError)
+        }
+
+        if height > 0 && try!(self.read_scanlines(height, &mut buf[..row * height])) < height {
+            return Err(image::ImageError::NotEnoughData)
+        }
+
+        Ok(())
+    }
+
+    // Copies each row to its place from the bottom of `buf`
+    fn read_image_into_ordered(&mut self, buf: &mut [u8], order: image::RowOrder) -> ImageResult<()> {
+        if order == image::RowOrder::TopDown {
+            return self.read_image_into(buf)
+        }
+
+        let row = try!(self.row_len());
+        let height = self.output_dimensions().1 as usize;
+
+        if buf.len() < row * height {
+            return Err(image::ImageError::DimensionError)
+        }
+
+        // A row that is not read would keep the previous contents of `buf`
+        for y in (0..height).rev() {
+            if try!(self.read_scanlines(1, &mut buf[y * row..(y + 1) * row])) == 0 {
+                return Err(image::ImageError::NotEnoughData)
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Converts the sample planes of one MCU row into interleaved output rows of
+// `width` pixels of `bpp` bytes. `components` lists the components in the
+// order of `planes`. Color pixels are converted to RGB and stored in `order`.
+// Each component is upsampled according to the ratio of its sampling factors
+// to the maximum sampling factors of the frame, which also covers layouts
+// like 4:1:1 (4x1) and 4:4:0 (1x2).
+fn upsample_row(out: &mut [u8], width: usize, bpp: usize, planes: &[Plane],
+                components: &[Component], hmax: u8, vmax: u8, method: UpsamplingMethod,
+                order: ColorOrder, level: SimdLevel) {
+    let mcu_height = 8 * vmax as usize;
+    let stride     = width * bpp;
+
+    for (channel, (c, plane)) in components.iter().zip(planes.iter()).enumerate() {
+        let (h, v) = (c.h as usize, c.v as usize);
+
+        for y in (0..mcu_height) {
+            let row = &mut out[y * stride..(y + 1) * stride];
+
+            if method == UpsamplingMethod::Replicate || (h == hmax as usize && v == vmax as usize) {
+                let src = plane.row((y * v / vmax as usize) as isize);
+
+                for x in (0..width) {
+                    row[x * bpp + channel] = src[x * h / hmax as usize];
+                }
+            } else {
+                fancy_upsample_row(row, bpp, channel, plane, y, h, v, hmax as usize, vmax as usize);
+            }
+        }
+    }
+
+    if components.len() == 3 {
+        let pixels = &mut out[..mcu_height * stride];
+        let done = simd::ycbcr_to_rgb(level, pixels, bpp, order != ColorOrder::RGB);
+
+        for pixel in pixels[done * bpp..].chunks_mut(bpp) {
+            let (r, g, b) = ycbcr_to_rgb(pixel[0], pixel[1], pixel[2]);
+
+            match order {
+                ColorOrder::RGB => {
+                    pixel[0] = r;
+                    pixel[1] = g;
+                    pixel[2] = b;
+                }
+                ColorOrder::BGR => {
+                    pixel[0] = b;
+                    pixel[1] = g;
+                    pixel[2] = r;
+                }
+                ColorOrder::BGRA => {
+                    pixel[0] = b;
+                    pixel[1] = g;
+                    pixel[2] = r;
+                    pixel[3] = 255;
+                }
+            }
+        }
+    }
+}
+
+// Scales the `rows` interleaved rows of `width` pixels in `buf` down by
+// `scale`, averaging blocks of `scale * scale` pixels. The result is stored
+// at the start of `buf`, with rows of `width / scale` pixels.
+fn downscale_rows(buf: &mut [u8], width: usize, rows: usize, bpp: usize, scale: usize) {
+    let stride = width * bpp;
+    let count  = scale * scale;
+
+    for y in (0..rows / scale) {
+        for x in (0..width / scale) {
+            for c in (0..bpp) {
+                let mut sum = 0;
+
+                for dy in (0..scale) {
+                    let row = (y * scale + dy) * stride;
+
+                    for dx in (0..scale) {
+                        sum += buf[row + (x * scale + dx) * bpp + c] as usize;
+                    }
+                }
+
+                // The target always precedes the samples still to be read
+                buf[y * stride / scale + x * bpp + c] = ((sum + count / 2) / count) as u8;
+            }
+        }
+    }
+}
+
+// Returns the two nearest sample positions for output position `x` and the
+// weight of the second one. The weights sum up to `2 * max`.
+// For a ratio of 2 this is the triangle filter used by libjpeg:
+// 3/4 of the nearer and 1/4 of the farther sample.
+fn triangle_taps(x: usize, factor: usize, max: usize) -> (isize, isize, usize) {
+    // Position of the sample center in units of 1 / (2 * max)
+    let pos = (2 * x + 1) as isize * factor as isize - max as isize;
+
+    if pos < 0 {
+        (0, 0, 0)
+    } else {
+        let d = 2 * max as isize;
+        let i = pos / d;
+        (i, i + 1, (pos % d) as usize)
+    }
+}
+
+// Interpolates output row `y` of one component from its neighbouring samples
+fn fancy_upsample_row(out: &mut [u8], bpp: usize, channel: usize, plane: &Plane, y: usize,
+                      h: usize, v: usize, hmax: usize, vmax: usize) {
+    let (dx, dy) = (2 * hmax, 2 * vmax);
+
+    // The context rows allow y0 = -1 and y1 = rows
+    let pos = (2 * y + 1) as isize * v as isize - vmax as isize;
+    let y0 = if pos < 0 { -1 } else { pos / dy as isize };
+    let wy = if pos < 0 { dy as isize + pos } else { pos % dy as isize } as usize;
+
+    let row0 = plane.row(y0);
+    let row1 = plane.row(y0 + 1);
+    let last = plane.stride as isize - 1;
+
+    for x in (0..out.len() / bpp) {
+        let (x0, x1, wx) = triangle_taps(x, h, hmax);
+        let x1 = if x1 > last { last } else { x1 };
+        let (x0, x1) = (x0 as usize, x1 as usize);
+
+        let top    = row0[x0] as usize * (dx - wx) + row0[x1] as usize * wx;
+        let bottom = row1[x0] as usize * (dx - wx) + row1[x1] as usize * wx;
+        let sum    = top * (dy - wy) + bottom * wy;
+
+        out[x * bpp + channel] = ((sum + dx * dy / 2) / (dx * dy)) as u8;
+    }
+}
+
+// The factors of the conversion to RGB in fixed point with 15 fractional
+// bits, as libjpeg-turbo uses them. The factors above one are split into
+// one plus the fraction so that the vector kernels can keep them in 16 bits.
+pub const F_0_344: i32 = 11277;
+pub const F_0_402: i32 = 13173;
+pub const F_0_714: i32 = 23401;
+pub const F_0_772: i32 = 25297;
+
+fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> (u8, u8, u8) {
+    let y = y as i32;
+    let cb = cb as i32 - 128;
+    let cr = cr as i32 - 128;
+    let round = 1 << 14;
+
+    let r1 = y + cr + ((F_0_402 * cr + round) >> 15);
+    let g1 = y - ((F_0_344 * cb + F_0_714 * cr + round) >> 15);
+    let b1 = y + cb + ((F_0_772 * cb + round) >> 15);
+
+    let r = clamp(r1, 0, 255) as u8;
+    let g = clamp(g1, 0, 255) as u8;
+    let b = clamp(b1, 0, 255) as u8;
+
+    (r, g, b)
+}
+
+// Converts the CMYK or YCCK pixels of a 4 component image to RGB with an
+// opaque alpha channel. Images with an Adobe APP14 segment store YCCK if
+// its `transform` is 2, otherwise inverted CMYK, as written by Photoshop.
+fn cmyk_to_rgba(pixels: &mut [u8], transform: Option<u8>) {
+    for pixel in pixels.chunks_mut(4) {
+        // The inverse of the inks, 255 meaning none
+        let (c, m, y, k) = match transform {
+            Some(2) => {
+                let (r, g, b) = ycbcr_to_rgb(pixel[0], pixel[1], pixel[2]);
+                (255 - r, 255 - g, 255 - b, pixel[3])
+            }
+            Some(_) => (pixel[0], pixel[1], pixel[2], pixel[3]),
+            None => (255 - pixel[0], 255 - pixel[1], 255 - pixel[2], 255 - pixel[3]),
+        };
+
+        let k = k as u32;
+        pixel[0] = ((c as u32 * k + 127) / 255) as u8;
+        pixel[1] = ((m as u32 * k + 127) / 255) as u8;
+        pixel[2] = ((y as u32 * k + 127) / 255) as u8;
+        pixel[3] = 255;
+    }
+}
+
+// Returns true if `e` was caused by the data ending prematurely
+fn is_unexpected_eof(e: &image::ImageError) -> bool {
+    match *e {
+        image::ImageError::ImageEnd | image::ImageError::NotEnoughData => true,
+        image::ImageError::IoError(ref e) => e.kind() == io::ErrorKind::UnexpectedEof,
+        _ => false
+    }
+}
+
+// Section F.2.2.1
+// Figure F.12
+fn extend(v: i32, t: u8) -> i32 {
+    // FIXME check if wrapping sub is what we want
+    let mut vt: i32 = 0;
+    let shift = (t as usize).wrapping_sub(1);
+    if shift < 31 {
+        vt = 1 << shift;
+    }
+
+    if v < vt {
+    v + ((-1) << t as usize) + 1
+    }
+    else {
+        v
+    }
+}
 
-        // only 1 component no interleaving
-        if n == 1 {
-        for (_, c) in self.components.iter_mut() {
-                c.h = 1;
-                c.v = 1;
+#[cfg(test)]
+mod tests {
+    use super::{ColorOrder, Component, ComponentPlane, Coefficients, JPEGDecoder, JpegDecodeOptions, Limits, MarkerSegment, PartialDecode, Plane, Tolerance,
+                UpsamplingMethod, RST0, SOS, cmyk_to_rgba, downscale_rows, ycbcr_to_rgb, upsample_row};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use config::SimdLevel;
+    use executor::{Executor, Job, Sequential};
+    use math::Rect;
+    use super::super::JPEGEncoder;
+    use color::ColorType;
+    use image::{ImageDecoder, ImageError, ImageResult, DecodingResult, RowOrder};
+
+    fn component(id: u8, h: u8, v: u8) -> Component {
+        Component { id: id, h: h, v: v, tq: 0, dc_table: 0, ac_table: 0, dc_pred: 0 }
+    }
+
+    fn plane(stride: usize, rows: usize, f: &Fn(usize, usize) -> u8) -> Plane {
+        let mut plane = Plane::new(stride, rows);
+        for y in (0..rows) {
+            for x in (0..stride) {
+                plane.row_mut(y as isize)[x] = f(x, y);
             }
+        }
+        plane
+    }
 
-            blocks_per_mcu = 1;
-            self.hmax = 1;
-            self.vmax = 1;
+    #[test]
+    /// A 4:1:1 MCU row consists of four horizontal luma blocks per chroma block
+    fn test_upsample_411() {
+        let components = [component(1, 4, 1), component(2, 1, 1), component(3, 1, 1)];
+        let planes = [plane(32, 8, &|x, _| 10 * (x / 8) as u8),
+                      plane(8, 8, &|_, _| 128),
+                      plane(8, 8, &|_, _| 128)];
+
+        let width = 32;
+        let mut out = vec![0u8; width * 3 * 8];
+        upsample_row(&mut out, width, 3, &planes, &components, 4, 1, UpsamplingMethod::Replicate, ColorOrder::RGB,
+                     SimdLevel::Scalar);
+
+        // Neutral chroma leaves the luma value in every channel
+        for y in (0..8) {
+            for x in (0..width) {
+                let expected = 10 * (x / 8) as u8;
+                assert_eq!(&out[(y * width + x) * 3..(y * width + x) * 3 + 3],
+                           &[expected, expected, expected]);
+            }
         }
+    }
 
-        self.mcu = repeat(0u8).take(blocks_per_mcu as usize * 64).collect::<Vec<u8>>();
+    #[test]
+    /// A 4:4:0 MCU row consists of two vertical luma blocks per chroma block
+    fn test_upsample_440() {
+        let components = [component(1, 1, 2), component(2, 1, 1), component(3, 1, 1)];
+        let planes = [plane(8, 16, &|_, y| if y < 8 { 50 } else { 200 }),
+                      plane(8, 8, &|_, _| 128),
+                      plane(8, 8, &|_, _| 128)];
+
+        let width = 8;
+        let mut out = vec![0u8; width * 3 * 16];
+        upsample_row(&mut out, width, 3, &planes, &components, 1, 2, UpsamplingMethod::Replicate, ColorOrder::RGB,
+                     SimdLevel::Scalar);
+
+        assert_eq!(out[0], 50);
+        assert_eq!(out[(7 * width) * 3], 50);
+        assert_eq!(out[(8 * width) * 3], 200);
+        assert_eq!(out[(15 * width + 7) * 3], 200);
+    }
 
-        let mcus_per_row = (self.width as f32 / (8 * hmax) as f32).ceil() as usize;
-        let mcu_row_len = (hmax as usize * vmax as usize) * self.mcu.len() * mcus_per_row;
+    #[test]
+    /// Fancy upsampling weights the nearer chroma sample by 3/4
+    fn test_fancy_upsample() {
+        let components = [component(1, 2, 1), component(2, 1, 1)];
+        let planes = [plane(16, 8, &|_, _| 0),
+                      plane(8, 8, &|x, _| 20 * x as u8)];
+
+        let width = 16;
+        let mut out = vec![0u8; width * 2 * 8];
+        upsample_row(&mut out, width, 2, &planes, &components, 2, 1, UpsamplingMethod::Fancy, ColorOrder::RGB,
+                     SimdLevel::Scalar);
+
+        let chroma = out.chunks(2).take(width).map(|p| p[1]).collect::<Vec<u8>>();
+        assert_eq!(&chroma[..5], &[0, 5, 15, 25, 35]);
+        assert_eq!(chroma[15], 140);
+    }
 
-        self.mcu_row = repeat(0u8).take(mcu_row_len).collect::<Vec<u8>>();
+    #[test]
+    fn test_downscale() {
+        let mut buf = (0..32).map(|i| i as u8).collect::<Vec<u8>>();
+        downscale_rows(&mut buf, 8, 4, 1, 2);
 
-        Ok(())
+        assert_eq!(&buf[..8], &[5, 7, 9, 11, 21, 23, 25, 27]);
     }
 
-    fn read_scan_header(&mut self) -> ImageResult<()> {
-        let _scan_length = try!(self.r.read_u16::<BigEndian>());
+    fn encode(width: u32, height: u32) -> Vec<u8> {
+        encode_with_threads(width, height, 1)
+    }
 
-        let num_scan_components = try!(self.r.read_u8());
+    // More than one thread makes every MCU row a restart interval
+    fn encode_with_threads(width: u32, height: u32, threads: usize) -> Vec<u8> {
+        let image = (0..width * height * 3).map(|i| (i * 7 % 251) as u8).collect::<Vec<u8>>();
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded);
+            encoder.set_threads(threads);
+            encoder.encode(&image, width, height, ColorType::RGB(8)).unwrap();
+        }
 
-        self.scan_components = Vec::new();
+        encoded
+    }
 
-        for _ in (0..num_scan_components as usize) {
-            let id = try!(self.r.read_u8());
-            let tables = try!(self.r.read_u8());
+    fn lenient() -> JpegDecodeOptions {
+        let mut options: JpegDecodeOptions = Default::default();
+        options.tolerance = Tolerance::Lenient;
+        options
+    }
 
-            let c = self.components.get_mut(&(id as usize)).unwrap();
+    fn decode(decoder: &mut JPEGDecoder<&[u8]>) -> ImageResult<Vec<u8>> {
+        match try!(decoder.read_image()) {
+            DecodingResult::U8(data) => Ok(data),
+            _ => panic!("unexpected sample type")
+        }
+    }
 
-            c.dc_table = tables >> 4;
-            c.ac_table = tables & 0x0F;
+    #[test]
+    fn test_decode_rows() {
+        let encoded = encode(20, 21);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
 
-            self.scan_components.push(id);
-        }
+        let mut rows = Vec::new();
+        let mut slabs = Vec::new();
+        JPEGDecoder::new(&encoded[..]).decode_rows(|y, slab| {
+            slabs.push((y, slab.len() / 60));
+            rows.extend(slab.iter().cloned());
+        }).unwrap();
 
-        let _spectral_end   = try!(self.r.read_u8());
-        let _spectral_start = try!(self.r.read_u8());
+        assert_eq!(slabs, vec![(0, 8), (8, 8), (16, 5)]);
+        assert_eq!(rows, expected);
+    }
 
-        let approx = try!(self.r.read_u8());
+    #[test]
+    fn test_color_order() {
+        let encoded = encode(20, 21);
 
-        let _approx_high = approx >> 4;
-        let _approx_low  = approx & 0x0F;
+        for &scale in [1, 2].iter() {
+            let mut options: JpegDecodeOptions = Default::default();
+            options.scale = scale;
+            let rgb = decode(&mut JPEGDecoder::new_with_options(&encoded[..], options)).unwrap();
 
-        Ok(())
-    }
+            options.color_order = ColorOrder::BGR;
+            let bgr = decode(&mut JPEGDecoder::new_with_options(&encoded[..], options)).unwrap();
 
-    fn read_quantization_tables(&mut self) -> ImageResult<()> {
-        let mut table_length = try!(self.r.read_u16::<BigEndian>()) as i32;
-        table_length -= 2;
+            options.color_order = ColorOrder::BGRA;
+            let mut decoder = JPEGDecoder::new_with_options(&encoded[..], options);
+            assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+            let bgra = decode(&mut decoder).unwrap();
 
-        while table_length > 0 {
-            let pqtq = try!(self.r.read_u8());
-            let pq = pqtq >> 4;
-            let tq = pqtq & 0x0F;
+            assert_eq!(bgr.len(), rgb.len());
+            assert_eq!(bgra.len(), rgb.len() / 3 * 4);
 
-            if pq != 0 || tq > 3 {
-                return Err(image::ImageError::FormatError("Quantization table malformed.".to_string()))
+            for ((p, q), r) in rgb.chunks(3).zip(bgr.chunks(3)).zip(bgra.chunks(4)) {
+                assert_eq!([p[2], p[1], p[0]], [q[0], q[1], q[2]]);
+                assert_eq!([p[2], p[1], p[0], 255], [r[0], r[1], r[2], r[3]]);
             }
+        }
+    }
 
-            let slice = &mut self.qtables[64 * tq as usize..64 * tq as usize + 64];
+    #[test]
+    fn test_read_image_into() {
+        let encoded = encode(20, 21);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
 
-            for i in (0usize..64) {
-                slice[i] = try!(self.r.read_u8());
-            }
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        assert_eq!(decoder.required_bytes().unwrap(), 20 * 21 * 3);
 
-            table_length -= 1 + 64;
-        }
+        let mut buf = vec![0u8; 20 * 21 * 3 + 5];
+        assert!(decoder.read_image_into(&mut buf[..100]).is_err());
+        decoder.read_image_into(&mut buf).unwrap();
+        assert_eq!(&buf[..expected.len()], &expected[..]);
 
-        Ok(())
+        // The same buffer is reused for the next frame
+        JPEGDecoder::new(&encoded[..]).read_image_into(&mut buf).unwrap();
+        assert_eq!(&buf[..expected.len()], &expected[..]);
     }
 
-    fn read_huffman_tables(&mut self) -> ImageResult<()> {
-        let mut table_length = try!(self.r.read_u16::<BigEndian>());
-        table_length -= 2;
+    #[test]
+    fn test_pixel_aspect_ratio() {
+        let encoded = encode(16, 16);
+        assert_eq!(JPEGDecoder::new(&encoded[..]).pixel_aspect_ratio().unwrap(), (1, 1));
+
+        // A horizontal density of 72 and a vertical one of 48 dpi in the
+        // JFIF header, right after SOI and the APP0 marker and length
+        let mut tall = encoded.clone();
+        assert_eq!(&tall[6..11], b"JFIF\0");
+        tall[14..18].copy_from_slice(&[0, 72, 0, 48]);
+        assert_eq!(JPEGDecoder::new(&tall[..]).pixel_aspect_ratio().unwrap(), (2, 3));
+
+        // A density of 0 is invalid
+        tall[14] = 0;
+        tall[15] = 0;
+        assert_eq!(JPEGDecoder::new(&tall[..]).pixel_aspect_ratio().unwrap(), (1, 1));
+    }
 
-        while table_length > 0 {
-            let tcth = try!(self.r.read_u8());
-            let tc = tcth >> 4;
-            let th = tcth & 0x0F;
+    #[test]
+    fn test_read_image_into_ordered() {
+        let encoded = encode(20, 21);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
 
-            if tc != 0 && tc != 1 {
-                return Err(image::ImageError::UnsupportedError(format!(
-                    "Huffman table class {} is not supported", tc
-                )))
-            }
+        let mut buf = vec![0u8; 20 * 21 * 3];
+        JPEGDecoder::new(&encoded[..]).read_image_into_ordered(&mut buf, RowOrder::TopDown).unwrap();
+        assert_eq!(buf, expected);
 
-            let mut bits = Vec::with_capacity(16);
-            try!(self.r.by_ref().take(16).read_to_end(&mut bits));
-            let len = bits.len();
+        JPEGDecoder::new(&encoded[..]).read_image_into_ordered(&mut buf, RowOrder::BottomUp).unwrap();
+        for (row, expected) in buf.chunks(60).zip(expected.chunks(60).rev()) {
+            assert_eq!(row, expected);
+        }
+    }
 
-            let mt = bits.iter().fold(0, | a, b | a + *b);
-            let mut huffval = Vec::with_capacity(mt as usize);
-            try!(self.r.by_ref().take(mt as u64).read_to_end(&mut huffval));
+    #[test]
+    fn test_read_scanlines() {
+        let encoded = encode(20, 21);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        let mut buf = vec![0u8; 60 * 21];
+        let mut rows = Vec::new();
+        let mut y = 0;
+
+        // Runs of rows crossing MCU rows, limited by n or by the buffer
+        for &(n, len) in [(3, 60 * 21), (10, 60 * 21), (21, 60 * 5 + 7), (21, 60 * 21)].iter() {
+            let count = decoder.read_scanlines(n, &mut buf[..len]).unwrap();
+            rows.push(count);
+            y += count;
+            assert_eq!(&buf[..count * 60], &expected[(y - count) * 60..y * 60]);
+        }
 
-            if tc == 0 {
-                self.dctables[th as usize] = derive_tables(bits, huffval);
-            } else {
-                self.actables[th as usize] = derive_tables(bits, huffval);
-            }
+        assert_eq!(rows, vec![3, 10, 5, 3]);
+        assert!(decoder.read_scanlines(1, &mut buf).is_err());
+    }
 
-            table_length -= 1 + len as u16 + mt as u16;
-        }
+    #[test]
+    fn test_reset() {
+        let small = encode(20, 21);
+        let large = encode(40, 24);
 
-        Ok(())
+        let expected_small = decode(&mut JPEGDecoder::new(&small[..])).unwrap();
+        let expected_large = decode(&mut JPEGDecoder::new(&large[..])).unwrap();
+
+        let mut decoder = JPEGDecoder::new(&large[..]);
+        assert_eq!(decode(&mut decoder).unwrap(), expected_large);
+
+        decoder.reset(&small[..]);
+        assert_eq!(decoder.dimensions().unwrap(), (20, 21));
+        assert_eq!(decode(&mut decoder).unwrap(), expected_small);
+
+        decoder.reset(&large[..]);
+        assert_eq!(decode(&mut decoder).unwrap(), expected_large);
     }
 
+    #[test]
+    fn test_lenient_truncated() {
+        let encoded = encode(32, 32);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+        let truncated = &encoded[..encoded.len() * 3 / 4];
 
-    fn read_restart_interval(&mut self) -> ImageResult<()> {
-        let _length = try!(self.r.read_u16::<BigEndian>());
-        self.interval = try!(self.r.read_u16::<BigEndian>());
+        assert!(decode(&mut JPEGDecoder::new(truncated)).is_err());
 
-        Ok(())
+        let mut decoder = JPEGDecoder::new_with_options(truncated, lenient());
+        let partial = decode(&mut decoder).unwrap();
+
+        assert_eq!(decoder.warnings().len(), 1);
+        assert_eq!(&partial[..8 * 32 * 3], &expected[..8 * 32 * 3]);
+        assert!(partial[31 * 32 * 3..].iter().all(|&s| s == 128));
     }
 
-    fn read_restart(&mut self) -> ImageResult<()> {
-        let w = ((self.width + 7) / (self.hmax * 8) as u16) as u32;
-        let h = ((self.height + 7) / (self.vmax * 8) as u16) as u32;
+    #[test]
+    fn test_invalid_scale() {
+        let encoded = encode(16, 16);
+        let mut options: JpegDecodeOptions = Default::default();
+        options.scale = 3;
 
-        if self.interval != 0  &&
-           self.mcucount % (self.interval as u32) == 0 &&
-           self.mcucount < w * h {
+        match decode(&mut JPEGDecoder::new_with_options(&encoded[..], options)) {
+            Err(ImageError::UnsupportedError(_)) => (),
+            _ => panic!("a scale factor of 3 was accepted")
+        }
+    }
 
-            let rst = try!(self.find_restart_marker());
+    #[test]
+    fn test_lenient_standalone_markers() {
+        let encoded = encode(16, 16);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
 
-            if rst == self.expected_rst {
-                self.reset();
-                self.expected_rst += 1;
+        // A stray restart marker and fill bytes after SOI
+        let mut damaged = encoded[..2].to_vec();
+        damaged.extend([0xFF, RST0, 0xFF, 0xFF].iter().cloned());
+        damaged.extend(encoded[2..].iter().cloned());
 
-                if self.expected_rst > RST7 {
-                    self.expected_rst = RST0;
-                }
-            } else {
-                return Err(image::ImageError::FormatError(format!(
-                    "Unexpected restart maker {} found", rst
-                )))
+        assert!(decode(&mut JPEGDecoder::new(&damaged[..])).is_err());
+
+        let mut decoder = JPEGDecoder::new_with_options(&damaged[..], lenient());
+        assert_eq!(decode(&mut decoder).unwrap(), expected);
+        assert_eq!(decoder.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_missing_eoi_and_trailing_data() {
+        for &(interval_threads, threads) in [(1, 1), (1, 2), (4, 1), (4, 2)].iter() {
+            let encoded = encode_with_threads(32, 24, interval_threads);
+            let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+
+            let without_eoi = &encoded[..encoded.len() - 2];
+            let mut trailing = encoded.clone();
+            trailing.extend([0x12, 0xFF, 0xD0, 0xFF, 0x00, 0xFF].iter().cloned());
+
+            for data in [without_eoi, &trailing[..]].iter() {
+                let mut decoder = JPEGDecoder::new(*data);
+                decoder.set_threads(threads);
+                assert_eq!(decode(&mut decoder).unwrap(), expected);
+
+                // The reader is left after the EOI marker
+                let rest = decoder.reset(&[][..]);
+                assert_eq!(rest, &data[data.len().min(encoded.len())..]);
             }
+
+            // Data missing within the last MCU row is still an error
+            let mut decoder = JPEGDecoder::new(&encoded[..encoded.len() - 40]);
+            decoder.set_threads(threads);
+            assert!(decode(&mut decoder).is_err());
         }
+    }
 
-        Ok(())
+    #[test]
+    fn test_read_image_partial() {
+        let encoded = encode(32, 32);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+
+        let complete = JPEGDecoder::new(&encoded[..]).read_image_partial().unwrap();
+        assert!(complete.is_complete());
+        assert_eq!(complete.data, expected);
+
+        let truncated = &encoded[..encoded.len() * 3 / 4];
+        let PartialDecode { data, rows_decoded, rows_missing } =
+            JPEGDecoder::new(truncated).read_image_partial().unwrap();
+
+        assert!(rows_decoded >= 8);
+        assert_eq!(rows_decoded % 8, 0);
+        assert_eq!(rows_decoded + rows_missing, 32);
+        assert_eq!(&data[..], &expected[..data.len()]);
+        assert_eq!(data.len(), rows_decoded as usize * 32 * 3);
     }
 
-    fn find_restart_marker(&mut self) -> ImageResult<u8> {
-        if self.h.marker != 0 {
-            let m = self.h.marker;
-            self.h.marker = 0;
+    #[test]
+    fn test_read_coefficients() {
+        let image = [100u8; 20 * 12 * 3];
+        let mut encoded = Vec::new();
+        JPEGEncoder::new(&mut encoded).encode(&image, 20, 12, ColorType::RGB(8)).unwrap();
 
-            return Ok(m);
-        }
+        let Coefficients { width, height, components } =
+            JPEGDecoder::new(&encoded[..]).read_coefficients().unwrap();
 
-        let mut b;
-        loop {
-            b = try!(self.r.read_u8());
+        assert_eq!((width, height), (20, 12));
+        assert_eq!(components.len(), 3);
 
-            if b == 0xFF {
-            b = try!(self.r.read_u8());
-                match b {
-                    RST0 ... RST7 => break,
-                    EOI => return Err(image::ImageError::FormatError("Restart marker not found.".to_string())),
-                    _   => continue
-                }
-            }
+        for c in components.iter() {
+            assert_eq!((c.blocks_wide, c.blocks_high), (3, 2));
+            assert_eq!(c.blocks.len(), 6);
         }
 
-        Ok(b)
+        // A flat block only has a DC coefficient, eight times the level shifted sample
+        let luma = components[0].dequantized(1, 1);
+        assert!((luma[0] / 8 + 128 - 100).abs() <= 2, "dc {}", luma[0]);
+        assert!(luma[1..].iter().all(|&c| c == 0));
     }
 
-    fn reset(&mut self) {
-        self.h.bits = 0;
-        self.h.num_bits = 0;
-        self.h.end = false;
-        self.h.marker = 0;
+    #[test]
+    fn test_quantization_tables() {
+        let encoded = encode(20, 12);
+        let Coefficients { components, .. } = JPEGDecoder::new(&encoded[..]).read_coefficients().unwrap();
+        let tables = JPEGDecoder::new(&encoded[..]).quantization_tables().unwrap();
 
-        for (_, c) in self.components.iter_mut() {
-            c.dc_pred = 0;
+        // The luma and chroma tables in natural order, as used by the components
+        assert_eq!(tables[0], Some(components[0].quantization_table));
+        assert_eq!(tables[1], Some(components[1].quantization_table));
+        assert!(tables[2].is_none() && tables[3].is_none());
+    }
+
+    #[test]
+    fn test_read_planes() {
+        let encoded = encode(20, 12);
+        let rgb = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+        let planes = JPEGDecoder::new(&encoded[..]).read_planes().unwrap();
+
+        assert_eq!(planes.len(), 3);
+
+        for &ComponentPlane { width, height, ref data, .. } in planes.iter() {
+            assert_eq!((width, height), (20, 12));
+            assert_eq!(data.len(), 20 * 12);
+        }
+
+        // Without subsampling, converting the planes gives the decoded image
+        for i in (0..20 * 12) {
+            let (r, g, b) = ycbcr_to_rgb(planes[0].data[i], planes[1].data[i], planes[2].data[i]);
+            assert_eq!(&rgb[3 * i..3 * i + 3], &[r, g, b]);
         }
     }
-}
 
-impl<R: Read> ImageDecoder for JPEGDecoder<R> {
-    fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
-        if self.state == JPEGState::Start {
-            let _ = try!(self.read_metadata());
+    #[test]
+    fn test_restart_resync() {
+        let mut encoded = encode_with_threads(32, 32, 4);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+
+        // Replace the second MCU row by an invalid Huffman code
+        let marker = |m: u8| (0..encoded.len() - 1).find(|&i| encoded[i] == 0xFF && encoded[i + 1] == m).unwrap();
+        let (start, end) = (marker(RST0) + 2, marker(RST0 + 1));
+        for i in (start..end) {
+            encoded[i] = if (i - start) % 2 == 0 && i + 1 < end { 0xFF } else { 0x00 };
         }
 
-        Ok((self.width as u32, self.height as u32))
+        assert!(decode(&mut JPEGDecoder::new(&encoded[..])).is_err());
+
+        let mut decoder = JPEGDecoder::new_with_options(&encoded[..], lenient());
+        let repaired = decode(&mut decoder).unwrap();
+        let row = 32 * 3;
+
+        assert_eq!(decoder.damaged_regions(), &[Rect::new(0, 8, 32, 8)]);
+        assert_eq!(&repaired[..8 * row], &expected[..8 * row]);
+        assert!(repaired[8 * row..16 * row].iter().all(|&s| s == 128));
+        assert_eq!(&repaired[16 * row..], &expected[16 * row..]);
     }
 
-    fn colortype(&mut self) -> ImageResult<color::ColorType> {
-        if self.state == JPEGState::Start {
-            let _ = try!(self.read_metadata());
+    // An 8x8 image of `n` components with a single block each, whose
+    // samples are all 128
+    fn flat_jpeg(n: u8) -> Vec<u8> {
+        let mut file = vec![0xFF, 0xD8, 0xFF, 0xDB, 0, 67, 0x00];
+        file.extend([1u8; 64].iter().cloned());
+
+        file.extend([0xFF, 0xC0, 0, 8 + 3 * n, 8, 0, 8, 0, 8, n].iter().cloned());
+        for i in (0..n) {
+            file.extend([i + 1, 0x11, 0].iter().cloned());
         }
 
-        let ctype = if self.num_components == 1 {
-            color::ColorType::Gray(8)
-        } else {
-            color::ColorType::RGB(8)
-        };
+        // DC and AC tables with a single code for a zero difference and EOB
+        for &class in [0x00u8, 0x10].iter() {
+            file.extend([0xFF, 0xC4, 0, 20, class, 1].iter().cloned());
+            file.extend([0u8; 15].iter().cloned());
+            file.push(0);
+        }
 
-        Ok(ctype)
+        file.extend([0xFF, 0xDA, 0, 6 + 2 * n, n].iter().cloned());
+        for i in (0..n) {
+            file.extend([i + 1, 0x00].iter().cloned());
+        }
+        file.extend([0, 63, 0].iter().cloned());
+
+        // Two zero bits for each block, padded with ones
+        file.push(0xFFu8.checked_shr(2 * n as u32).unwrap_or(0));
+        file.extend([0xFF, 0xD9].iter().cloned());
+        file
     }
 
-    fn row_len(&mut self) -> ImageResult<usize> {
-        if self.state == JPEGState::Start {
-            let _ = try!(self.read_metadata());
+    #[test]
+    fn test_component_counts() {
+        let expected = [
+            (1, ColorType::Gray(8)),
+            (2, ColorType::GrayA(8)),
+        ];
+
+        for &(n, color) in expected.iter() {
+            let file = flat_jpeg(n);
+            let mut decoder = JPEGDecoder::new(&file[..]);
+            assert_eq!(decoder.colortype().unwrap(), color);
+            assert_eq!(decode(&mut decoder).unwrap(), vec![128; 64 * n as usize]);
         }
 
-        let len = self.width as usize * self.num_components as usize;
+        // CMYK without an Adobe segment, then inverted CMYK and YCCK with one
+        for &(transform, value) in [(None, 63), (Some(0), 64), (Some(2), 64)].iter() {
+            let mut file = flat_jpeg(4);
+            if let Some(transform) = transform {
+                let adobe = [0xFF, 0xEE, 0, 14, b'A', b'd', b'o', b'b', b'e', 0, 100, 0, 0, 0, 0, transform];
+                file.splice(2..2, adobe.iter().cloned());
+            }
 
-        Ok(len)
+            let mut decoder = JPEGDecoder::new(&file[..]);
+            assert_eq!(decoder.colortype().unwrap(), ColorType::RGBA(8));
+            let pixels = decode(&mut decoder).unwrap();
+            assert!(pixels.chunks(4).all(|p| p == &[value, value, value, 255]), "{:?}", &pixels[..4]);
+        }
+
+        let file = flat_jpeg(3);
+        assert_eq!(JPEGDecoder::new(&file[..]).colortype().unwrap(), ColorType::RGB(8));
+
+        // The component count of the frame header
+        let mut file = flat_jpeg(4);
+        file[80] = 5;
+        assert!(JPEGDecoder::new(&file[..]).colortype().is_err());
+
+        // Later calls read on to the scan of the rejected frame
+        let mut decoder = JPEGDecoder::new(&file[..]);
+        assert!(decoder.exif().is_err());
+        assert!(decoder.icc_profile().is_err());
+        assert!(decoder.read_image().is_err());
     }
 
-    fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
-        if self.state == JPEGState::Start {
-            let _ = try!(self.read_metadata());
+    #[test]
+    fn test_cmyk_to_rgba() {
+        let mut pixels = [0, 255, 128, 0, 0, 0, 0, 255];
+        cmyk_to_rgba(&mut pixels, None);
+        assert_eq!(pixels, [255, 0, 127, 255, 0, 0, 0, 255]);
+
+        // Inverted, as stored by Photoshop
+        let mut pixels = [255, 0, 128, 255];
+        cmyk_to_rgba(&mut pixels, Some(0));
+        assert_eq!(pixels, [255, 0, 128, 255]);
+
+        // The YCbCr of white, which YCCK uses for full ink
+        let mut pixels = [255, 128, 128, 255];
+        cmyk_to_rgba(&mut pixels, Some(2));
+        assert_eq!(pixels, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_marker_segments() {
+        let encoded = encode(16, 8);
+        let mut file = encoded[..2].to_vec();
+        file.extend([0xFF, 0xEB, 0, 6, b'F', b'L', b'I', b'R'].iter().cloned());
+        file.extend([0xFF, 0xFE, 0, 7, b'h', b'e', b'l', b'l', b'o'].iter().cloned());
+        file.extend(encoded[2..].iter().cloned());
+
+        let mut decoder = JPEGDecoder::new(&file[..]);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+        {
+            let segments = decoder.marker_segments().unwrap();
+            assert!(segments.len() >= 2);
+            assert_eq!(segments[0], MarkerSegment { marker: 0xEB, offset: 2, data: b"FLIR".to_vec() });
+            assert_eq!(segments[1], MarkerSegment { marker: 0xFE, offset: 10, data: b"hello".to_vec() });
+            for segment in segments[2..].iter() {
+                assert_eq!(&file[segment.offset as usize + 4..][..segment.data.len()], &segment.data[..]);
+            }
         }
+        assert_eq!(decode(&mut decoder).unwrap(), expected);
+    }
 
-        if self.row_count == 0 {
-            let _ = try!(self.decode_mcu_row());
+    #[test]
+    fn test_marker_segment_limits() {
+        let encoded = encode(16, 8);
+        let mut file = encoded[..2].to_vec();
+        for _ in 0..4 {
+            file.extend([0xFF, 0xFE, 0xFF, 0xFF].iter().cloned());
+            file.extend(vec![b'x'; 0xFFFD]);
         }
+        file.extend(encoded[2..].iter().cloned());
 
-        let len   = self.padded_width * self.num_components as usize;
-        let slice = &self.mcu_row[self.row_count as usize * len..
-        self.row_count as usize * len + buf.len()];
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        decode(&mut decoder).unwrap();
+        let buffers = decoder.resource_usage().checked_alloc;
 
-        ::copy_memory(slice, buf);
+        let decode_with = |max_alloc: u64| {
+            let limits = Limits { max_alloc: max_alloc, ..Default::default() };
+            decode(&mut JPEGDecoder::new_with_options(&file[..], JpegDecodeOptions { limits: limits, ..Default::default() }))
+        };
+        assert!(decode_with(buffers + 4 * 0xFFFD).is_ok());
+        match decode_with(buffers + 3 * 0xFFFD) {
+            Err(ImageError::LimitsExceeded(_)) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
 
-        self.row_count = (self.row_count + 1) % (self.vmax * 8);
-        self.decoded_rows += 1;
+    #[test]
+    fn test_limits() {
+        let encoded = encode(40, 256);
+        let decode_with = |limits: Limits| {
+            let options = JpegDecodeOptions { limits: limits, ..Default::default() };
+            let mut decoder = JPEGDecoder::new_with_options(&encoded[..], options);
+            decoder.set_threads(2);
+            decode(&mut decoder)
+        };
+        let exceeded = |result: ImageResult<Vec<u8>>| match result {
+            Err(ImageError::LimitsExceeded(_)) => true,
+            _ => false
+        };
 
-        Ok(self.decoded_rows)
+        assert!(decode_with(Default::default()).is_ok());
+        for limits in [Limits { max_width: 39, ..Default::default() },
+                       Limits { max_height: 255, ..Default::default() },
+                       Limits { max_pixels: 40 * 256 - 1, ..Default::default() }].iter() {
+            match decode_with(*limits) {
+                Err(ImageError::DimensionError) => (),
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+        }
+        // Enough for the row buffers, but not for the rows in flight
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        decode(&mut decoder).unwrap();
+        let rows = decoder.resource_usage().checked_alloc;
+        assert!(decode_with(Limits { max_alloc: rows + 20000, ..Default::default() }).is_ok());
+        assert!(exceeded(decode_with(Limits { max_alloc: rows, ..Default::default() })));
+        assert!(exceeded(decode_with(Limits { max_alloc: 1000, ..Default::default() })));
     }
 
-    fn read_image(&mut self) -> ImageResult<image::DecodingResult> {
-        if self.state == JPEGState::Start {
-            let _ = try!(self.read_metadata());
+    #[test]
+    fn test_resource_usage() {
+        let encoded = encode(40, 256);
+
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        decode(&mut decoder).unwrap();
+        let sequential = decoder.resource_usage();
+        assert_eq!(sequential.output_size, 40 * 256 * 3);
+        assert!(sequential.checked_alloc > 0);
+
+        // The rows in flight take more
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        decoder.set_threads(2);
+        decode(&mut decoder).unwrap();
+        let parallel = decoder.resource_usage();
+        assert!(parallel.checked_alloc > sequential.checked_alloc);
+
+        // The checked size is exactly what the limit bounds
+        let decode_with = |max_alloc: u64| {
+            let limits = Limits { max_alloc: max_alloc, ..Default::default() };
+            let mut decoder = JPEGDecoder::new_with_options(&encoded[..], JpegDecodeOptions { limits: limits, ..Default::default() });
+            decoder.set_threads(2);
+            decode(&mut decoder).is_ok()
+        };
+        assert!(decode_with(parallel.checked_alloc));
+        assert!(!decode_with(parallel.checked_alloc - 1));
+    }
+
+    #[test]
+    fn test_pipelined_rows() {
+        for &(width, height) in [(40, 35), (8, 8), (100, 3)].iter() {
+            let encoded = encode(width, height);
+            let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+
+            for &threads in [2, 3, 8].iter() {
+                let mut decoder = JPEGDecoder::new(&encoded[..]);
+                decoder.set_threads(threads);
+                decoder.set_executor(Arc::new(Sequential));
+                assert_eq!(decode(&mut decoder).unwrap(), expected);
+
+                let mut decoder = JPEGDecoder::new(&encoded[..]);
+                decoder.set_threads(threads);
+                assert_eq!(decode(&mut decoder).unwrap(), expected);
+            }
         }
 
-        let row = try!(self.row_len());
-        let mut buf = repeat(0u8).take(row * self.height as usize).collect::<Vec<u8>>();
+        // Truncated entropy-coded data, whose first rows are still returned
+        let encoded = encode(40, 256);
+        let mut decoder = JPEGDecoder::new(&encoded[..encoded.len() / 2]);
+        decoder.set_threads(2);
+        let mut row = vec![0; 40 * 3];
+        assert!(decoder.read_scanline(&mut row).is_ok());
+        assert!(decode(&mut decoder).is_err());
+    }
 
-        for chunk in buf.chunks_mut(row) {
-            let _len = try!(self.read_scanline(chunk));
+    // Runs the jobs one after another on a single thread
+    struct Queue(Mutex<mpsc::Sender<Job<'static>>>);
+
+    impl Executor for Queue {
+        fn spawn(&self, job: Job<'static>) {
+            self.0.lock().unwrap().send(job).unwrap();
         }
+    }
 
-        Ok(image::DecodingResult::U8(buf))
+    // Runs the jobs on the calling thread, except for job `.1`, which it
+    // drops without running
+    struct Dropping(AtomicUsize, usize);
+
+    impl Executor for Dropping {
+        fn spawn(&self, job: Job<'static>) {
+            if self.0.fetch_add(1, Ordering::SeqCst) != self.1 {
+                job();
+            }
+        }
     }
-}
 
-fn upsample_mcu(out: &mut [u8], xoffset: usize, width: usize, bpp: usize, mcu: &[u8], h: u8, v: u8) {
-    if mcu.len() == 64 {
-        for y in (0usize..8) {
-            for x in (0usize..8) {
-                out[xoffset + x + (y * width)] = mcu[x + y * 8]
+    #[test]
+    fn test_pipelined_lost_jobs() {
+        let encoded = encode(40, 256);
+
+        for &lost in [0, 3].iter() {
+            let mut decoder = JPEGDecoder::new(&encoded[..]);
+            decoder.set_threads(2);
+            decoder.set_executor(Arc::new(Dropping(AtomicUsize::new(0), lost)));
+            match decode(&mut decoder) {
+                Err(ImageError::FormatError(..)) => (),
+                other => panic!("{:?}", other.map(|_| ())),
             }
+            assert!(decode(&mut decoder).is_err());
         }
-    } else {
-        let y_blocks = h * v;
+    }
 
-        let y_blocks = &mcu[..y_blocks as usize * 64];
-        let cb = &mcu[y_blocks.len()..y_blocks.len() + 64];
-        let cr = &mcu[y_blocks.len() + cb.len()..];
+    #[test]
+    fn test_pipelined_rows_in_order() {
+        let (sender, receiver) = mpsc::channel::<Job<'static>>();
+        thread::spawn(move || for job in receiver.iter() { job() });
 
-        let mut k = 0;
+        let encoded = encode(40, 256);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
 
-        for by in (0..v as usize) {
-            let y0 = by * 8;
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        decoder.set_threads(4);
+        decoder.set_executor(Arc::new(Queue(Mutex::new(sender))));
+        assert_eq!(decode(&mut decoder).unwrap(), expected);
+    }
 
-            for bx in (0..h as usize) {
-                let x0 = xoffset + bx * 8 * bpp;
+    #[test]
+    fn test_parallel_intervals() {
+        let encoded = encode_with_threads(40, 35, 4);
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
 
-                for y in (0usize..8) {
-                    for x in (0usize..8) {
-                        let (a, b, c) = (y_blocks[k * 64 + x + y * 8], cb[x + y * 8], cr[x + y * 8]);
-                        let (r, g, b) = ycbcr_to_rgb(a , b , c );
+        for &threads in [2, 3, 8].iter() {
+            let mut decoder = JPEGDecoder::new(&encoded[..]);
+            decoder.set_threads(threads);
+            decoder.set_executor(Arc::new(Sequential));
+            assert_eq!(decode(&mut decoder).unwrap(), expected);
 
-                        let offset = (y0 + y) * (width * bpp) + x0 + x * bpp;
-                        out[offset + 0] = r;
-                        out[offset + 1] = g;
-                        out[offset + 2] = b;
-                    }
-                }
+            let mut decoder = JPEGDecoder::new(&encoded[..]);
+            decoder.set_threads(threads);
+            assert_eq!(decode(&mut decoder).unwrap(), expected);
+        }
 
-                k += 1;
-            }
+        // A missing restart interval
+        let end = (0..encoded.len() - 1).find(|&i| encoded[i] == 0xFF && encoded[i + 1] == RST0 + 2).unwrap();
+        let mut decoder = JPEGDecoder::new(&encoded[..end]);
+        decoder.set_threads(2);
+        assert!(decode(&mut decoder).is_err());
+    }
+
+    #[test]
+    fn test_scan_components() {
+        let encoded = encode(16, 16);
+        let sos = (0..encoded.len() - 1).find(|&i| encoded[i] == 0xFF && encoded[i + 1] == SOS).unwrap();
+
+        // A component that is not in the frame, or one that appears twice
+        for &(offset, id) in [(5, 9), (7, 1)].iter() {
+            let mut corrupted = encoded.clone();
+            corrupted[sos + offset] = id;
+            assert!(decode(&mut JPEGDecoder::new(&corrupted[..])).is_err());
         }
+
+        // An interleaved scan with 4x4 luma blocks has too many blocks per MCU
+        let sof = (0..encoded.len() - 1).find(|&i| encoded[i] == 0xFF && encoded[i + 1] == 0xC0).unwrap();
+        let mut corrupted = encoded.clone();
+        corrupted[sof + 11] = 0x44;
+        assert!(decode(&mut JPEGDecoder::new(&corrupted[..])).is_err());
+
+        assert!(decode(&mut JPEGDecoder::new(&encoded[..])).is_ok());
     }
-}
 
-fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> (u8, u8, u8) {
-    let y = y as f32;
-    let cr = cr as f32;
-    let cb = cb as f32;
+    #[test]
+    fn test_table_selectors() {
+        let encoded = encode(16, 16);
+        let sof = (0..encoded.len() - 1).find(|&i| encoded[i] == 0xFF && encoded[i + 1] == 0xC0).unwrap();
+        let sos = (0..encoded.len() - 1).find(|&i| encoded[i] == 0xFF && encoded[i + 1] == SOS).unwrap();
 
-    let r1 = y + 1.402f32 * (cr - 128f32) ;
-    let g1 = y - 0.34414f32 * (cb - 128f32) - 0.71414f32 * (cr - 128f32);
-    let b1 = y + 1.772f32 * (cb - 128f32);
+        // A quantization table above 3, then DC and AC Huffman tables above 1
+        for &(offset, selector) in [(sof + 12, 4), (sos + 6, 0x20), (sos + 6, 0x02)].iter() {
+            let mut corrupted = encoded.clone();
+            corrupted[offset] = selector;
 
-    let r = clamp(r1 as i32, 0, 255) as u8;
-    let g = clamp(g1 as i32, 0, 255) as u8;
-    let b = clamp(b1 as i32, 0, 255) as u8;
+            match decode(&mut JPEGDecoder::new(&corrupted[..])) {
+                Err(ImageError::FormatError(_)) => (),
+                result => panic!("{:?}", result.map(|_| ()))
+            }
 
-    (r, g, b)
-}
+            match JPEGDecoder::new(&corrupted[..]).read_coefficients() {
+                Err(ImageError::FormatError(_)) => (),
+                result => panic!("{:?}", result.map(|_| ()))
+            }
+        }
+    }
 
-// Section F.2.2.1
-// Figure F.12
-fn extend(v: i32, t: u8) -> i32 {
-    // FIXME check if wrapping sub is what we want
-    let mut vt: i32 = 0;
-    let shift = (t as usize).wrapping_sub(1);
-    if shift < 31 {
-        vt = 1 << shift;
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_from_path() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+
+        let encoded = encode(30, 20);
+        let path = env::temp_dir().join(format!("image-jpeg-mmap-{}.jpg", ::std::process::id()));
+        File::create(&path).unwrap().write_all(&encoded).unwrap();
+
+        let mapped = unsafe { JPEGDecoder::from_path(&path) }.unwrap().read_image().unwrap();
+        let expected = decode(&mut JPEGDecoder::new(&encoded[..])).unwrap();
+        match mapped {
+            DecodingResult::U8(pixels) => assert_eq!(pixels, expected),
+            _ => panic!("8 bit samples expected")
+        }
+
+        fs::remove_file(&path).unwrap();
+        assert!(unsafe { JPEGDecoder::from_path(&path) }.is_err());
     }
 
-    if v < vt {
-    v + ((-1) << t as usize) + 1
+    #[test]
+    fn test_ycbcr_to_rgb() {
+        // The fixed point conversion is off by at most one from the exact one
+        for y in (0..256).step_by(3) {
+            for cb in (0..256).step_by(5) {
+                for cr in (0..256).step_by(5) {
+                    let (y, cb, cr) = (y as f32, cb as f32 - 128.0, cr as f32 - 128.0);
+                    let expected = [y + 1.402 * cr, y - 0.344136 * cb - 0.714136 * cr, y + 1.772 * cb];
+                    let (r, g, b) = ycbcr_to_rgb(y as u8, (cb + 128.0) as u8, (cr + 128.0) as u8);
+
+                    for (&v, &e) in [r, g, b].iter().zip(expected.iter()) {
+                        assert!((v as f32 - e.max(0.0).min(255.0)).abs() <= 1.0, "{} {} {}", y, cb, cr);
+                    }
+                }
+            }
+        }
     }
-    else {
-        v
+
+    #[test]
+    fn test_simd_levels() {
+        let encoded = encode(40, 35);
+
+        for &order in [ColorOrder::RGB, ColorOrder::BGR, ColorOrder::BGRA].iter() {
+            let mut options: JpegDecodeOptions = Default::default();
+            options.color_order = order;
+            options.simd = Some(SimdLevel::Scalar);
+            let expected = decode(&mut JPEGDecoder::new_with_options(&encoded[..], options)).unwrap();
+
+            for &level in [SimdLevel::Sse2, SimdLevel::Avx2, SimdLevel::Neon, SimdLevel::Simd128].iter() {
+                options.simd = Some(level);
+                let mut decoder = JPEGDecoder::new_with_options(&encoded[..], options);
+                assert_eq!(decoder.simd_level(), level.effective());
+                assert_eq!(decode(&mut decoder).unwrap(), expected);
+
+                // WASI has no threads
+                if cfg!(not(target_os = "wasi")) {
+                    let mut decoder = JPEGDecoder::new_with_options(&encoded[..], options);
+                    decoder.set_threads(3);
+                    assert_eq!(decode(&mut decoder).unwrap(), expected);
+                }
+            }
+        }
     }
 }
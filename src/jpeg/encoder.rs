@@ -1,17 +1,34 @@
+use std::cmp;
+use std::f32;
 use std::io::{self, Write};
+use std::sync::Arc;
 use byteorder::{WriteBytesExt, BigEndian};
 use num::range_step;
 
+use buffer::GrayImage;
 use color;
+use config::{self, SimdLevel};
+use executor::{self, Executor, Job, StdThreads};
 
+use super::simd;
 use super::transform;
-use super::decoder::Component;
+use super::decoder::{Coefficients, Component, ComponentCoefficients};
 use super::decoder::UNZIGZAG;
 use super::entropy::build_huff_lut;
+use super::arithmetic::ArithmeticEncoder;
+use super::c2pa::{self, APP11};
+use super::exif::{self, Exif};
+use super::quality::quality_tables;
 
 // Markers
 // Baseline DCT
 static SOF0: u8 = 0xC0;
+
+// Start Of Frame (Extended Sequential, Huffman coding)
+static SOF1: u8 = 0xC1;
+
+// Start Of Frame (Extended Sequential, Arithmetic coding)
+static SOF9: u8 = 0xC9;
 // Huffman Tables
 static DHT: u8 = 0xC4;
 // Start of Image (standalone)
@@ -24,6 +41,19 @@ static SOS: u8 = 0xDA;
 static DQT: u8 = 0xDB;
 // Application segments start and end
 static APP0: u8 = 0xE0;
+static APP1: u8 = 0xE1;
+static APP2: u8 = 0xE2;
+// Comment
+static COM: u8 = 0xFE;
+
+// The identifier of the APP2 segments of an ICC profile and the largest
+// part of a profile a segment holds
+static ICC_PROFILE: &'static [u8] = b"ICC_PROFILE\0";
+const ICC_CHUNK_LEN: usize = 65533 - 14;
+
+// The weight of a bit against the squared error of one quantization step
+// in trellis quantization
+const TRELLIS_LAMBDA: f32 = 0.2;
 
 // section K.1
 // table K.1
@@ -122,30 +152,153 @@ static LUMAID: u8 = 1;
 static CHROMABLUEID: u8 = 2;
 static CHROMAREDID: u8 = 3;
 
+// Restart markers
+static RST0: u8 = 0xD0;
+// Define Restart Interval
+static DRI: u8 = 0xDD;
+
+/// The chroma subsampling of an encoded color image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Subsampling {
+    /// Chroma at full resolution (4:4:4)
+    Ratio444,
+
+    /// Chroma at half the horizontal resolution (4:2:2)
+    Ratio422,
+
+    /// Chroma at half the horizontal and vertical resolution (4:2:0)
+    Ratio420,
+}
+
+impl Subsampling {
+    // The sampling factors of the luma component
+    fn luma_factors(&self) -> (u8, u8) {
+        match *self {
+            Subsampling::Ratio444 => (1, 1),
+            Subsampling::Ratio422 => (2, 1),
+            Subsampling::Ratio420 => (2, 2),
+        }
+    }
+}
+
 /// The representation of a JPEG encoder
 pub struct JPEGEncoder<'a, W: 'a> {
     w: &'a mut W,
 
     components: Vec<Component>,
-    tables: Vec<u8>,
+    tables: Tables,
+    threads: usize,
+    executor: Arc<Executor>,
+    subsampling: Subsampling,
+    optimize_coding: bool,
+    trellis_quantization: bool,
+    restart_interval: u16,
+    exif: Option<Vec<u8>>,
+    icc_profile: Option<Vec<u8>>,
+    c2pa_manifest: Option<Vec<u8>>,
+    comments: Vec<String>,
+    thumbnail: Option<u32>,
+    // The encoded thumbnail of the image being encoded
+    thumbnail_data: Option<Vec<u8>>,
+    simd: Option<SimdLevel>,
+    quality_map: Option<GrayImage>,
+    target_size: Option<usize>,
+}
 
-    accumulator: u32,
-    nbits: u8,
+// The indices of the Huffman tables in `Tables::huffman`
+const LUMA_DC: usize = 0;
+const LUMA_AC: usize = 1;
+const CHROMA_DC: usize = 2;
+const CHROMA_AC: usize = 3;
+
+// The quantization and Huffman tables used to encode a scan
+#[derive(Clone)]
+struct Tables {
+    quantization: Vec<u8>,
+    huffman: Vec<HuffmanTable>,
+    // The tables whose code lengths trellis quantization minimizes, which
+    // stay the same in both passes of optimized coding
+    trellis: Option<Vec<HuffmanTable>>,
+    // Whether the scan is arithmetic coded instead of with `huffman`
+    arithmetic: bool,
+    // The tables of the regions of a quality map, if set
+    regions: Option<Regions>,
+}
+
+// The quantization tables of the regions of a quality map, which are
+// coarser than or equal to the tables of the scan
+#[derive(Clone)]
+struct Regions {
+    map: GrayImage,
+    // The dimensions of the image the map is stretched over
+    width: usize,
+    height: usize,
+    // The luma and chroma tables of each quality in the map
+    tables: Vec<Option<Vec<u8>>>,
+}
+
+impl Regions {
+    fn new(map: &GrayImage, width: u32, height: u32, quantization: &[u8]) -> Regions {
+        let mut tables = vec![None; 101];
+
+        for p in map.pixels() {
+            let quality = cmp::max(1, cmp::min(100, p[0])) as usize;
+
+            if tables[quality].is_none() {
+                let table = quality_tables(quality as u8).iter().zip(quantization.iter())
+                                                         .map(|(&a, &b)| cmp::max(a, b))
+                                                         .collect::<Vec<u8>>();
+                tables[quality] = Some(table);
+            }
+        }
+
+        Regions {
+            map: map.clone(),
+            width: width as usize,
+            height: height as usize,
+            tables: tables,
+        }
+    }
+
+    // The luma and chroma tables at pixel `x`, `y` of the image
+    fn tables_at(&self, x: usize, y: usize) -> &[u8] {
+        let (map_width, map_height) = (self.map.width() as usize, self.map.height() as usize);
+        let mx = cmp::min(map_width - 1, x * map_width / self.width);
+        let my = cmp::min(map_height - 1, y * map_height / self.height);
+        let quality = cmp::max(1, cmp::min(100, self.map.get_pixel(mx as u32, my as u32)[0]));
+
+        self.tables[quality as usize].as_ref().unwrap()
+    }
+}
 
-    luma_dctable: Vec<(u8, u16)>,
-    luma_actable: Vec<(u8, u16)>,
-    chroma_dctable: Vec<(u8, u16)>,
-    chroma_actable: Vec<(u8, u16)>,
+// A Huffman table as the number of codes of each length from 1 to 16, the
+// symbols in the order of their codes, and the code of each symbol
+#[derive(Clone)]
+struct HuffmanTable {
+    lengths: Vec<u8>,
+    values: Vec<u8>,
+    lut: Vec<(u8, u16)>,
+}
+
+impl HuffmanTable {
+    fn new(lengths: &[u8], values: &[u8]) -> HuffmanTable {
+        HuffmanTable {
+            lengths: lengths.to_vec(),
+            values: values.to_vec(),
+            lut: build_huff_lut(lengths, values),
+        }
+    }
 }
 
 impl<'a, W: Write> JPEGEncoder<'a, W> {
     /// Create a new encoder that writes its output to ```w```
     pub fn new(w: &mut W) -> JPEGEncoder<W> {
-        let ld = build_huff_lut(&STD_LUMA_DC_CODE_LENGTHS, &STD_LUMA_DC_VALUES);
-        let la = build_huff_lut(&STD_LUMA_AC_CODE_LENGTHS, &STD_LUMA_AC_VALUES);
-
-        let cd = build_huff_lut(&STD_CHROMA_DC_CODE_LENGTHS, &STD_CHROMA_DC_VALUES);
-        let ca = build_huff_lut(&STD_CHROMA_AC_CODE_LENGTHS, &STD_CHROMA_AC_VALUES);
+        let huffman = vec![
+            HuffmanTable::new(&STD_LUMA_DC_CODE_LENGTHS, &STD_LUMA_DC_VALUES),
+            HuffmanTable::new(&STD_LUMA_AC_CODE_LENGTHS, &STD_LUMA_AC_VALUES),
+            HuffmanTable::new(&STD_CHROMA_DC_CODE_LENGTHS, &STD_CHROMA_DC_VALUES),
+            HuffmanTable::new(&STD_CHROMA_AC_CODE_LENGTHS, &STD_CHROMA_AC_VALUES),
+        ];
 
         let components = vec![
             Component {id: LUMAID, h: 1, v: 1, tq: LUMADESTINATION, dc_table: LUMADESTINATION, ac_table: LUMADESTINATION, dc_pred: 0},
@@ -153,288 +306,1586 @@ impl<'a, W: Write> JPEGEncoder<'a, W> {
             Component {id: CHROMAREDID, h: 1, v: 1, tq: CHROMADESTINATION, dc_table: CHROMADESTINATION, ac_table: CHROMADESTINATION, dc_pred: 0}
         ];
 
-        let mut tables = Vec::new();
-        tables.extend(STD_LUMA_QTABLE.iter().map(|&v| v));
-        tables.extend(STD_CHROMA_QTABLE.iter().map(|&v| v));
+        let mut quantization = Vec::new();
+        quantization.extend(STD_LUMA_QTABLE.iter().map(|&v| v));
+        quantization.extend(STD_CHROMA_QTABLE.iter().map(|&v| v));
 
         JPEGEncoder {
             w: w,
 
             components: components,
-            tables: tables,
+            tables: Tables {
+                quantization: quantization,
+                huffman: huffman,
+                trellis: None,
+                arithmetic: false,
+                regions: None,
+            },
+            threads: 1,
+            executor: Arc::new(StdThreads),
+            subsampling: Subsampling::Ratio444,
+            optimize_coding: false,
+            trellis_quantization: false,
+            restart_interval: 0,
+            exif: None,
+            icc_profile: None,
+            c2pa_manifest: None,
+            comments: Vec::new(),
+            thumbnail: None,
+            thumbnail_data: None,
+            simd: None,
+            quality_map: None,
+            target_size: None,
+        }
+    }
 
-            luma_dctable: ld,
-            luma_actable: la,
-            chroma_dctable: cd,
-            chroma_actable: ca,
+    /// Create a new encoder that writes its output to ```w``` with the
+    /// quantization tables of Annex K scaled to ```quality```, which is
+    /// clamped to 1 to 100, like libjpeg does. Higher qualities give
+    /// larger files with less loss.
+    pub fn new_with_quality(w: &mut W, quality: u8) -> JPEGEncoder<W> {
+        let mut encoder = JPEGEncoder::new(w);
+        encoder.tables.quantization = quality_tables(cmp::max(1, cmp::min(100, quality)));
+        encoder
+    }
 
-            accumulator: 0,
-            nbits: 0,
+    /// Sets the number of threads used to encode the image. Defaults to 1.
+    ///
+    /// With more than one thread, each row of MCUs is transformed, quantized
+    /// and entropy coded independently and starts a new restart interval.
+    /// Unless a restart interval is set, every row forms one interval,
+    /// which makes the output slightly larger.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = if threads == 0 { 1 } else { threads };
+    }
+
+    /// Sets the executor that runs the jobs of a multi-threaded encode.
+    /// Defaults to `StdThreads`, which spawns a thread per job.
+    pub fn set_executor(&mut self, executor: Arc<Executor>) {
+        self.executor = executor;
+    }
+
+    /// Replaces the quantization tables with ```luma``` and ```chroma```,
+    /// given in natural order, e.g. tables tuned for a camera or taken from
+    /// another encoder. Larger values give smaller files with more loss.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a value is zero.
+    pub fn set_quantization_tables(&mut self, luma: &[u8; 64], chroma: &[u8; 64]) {
+        assert!(luma.iter().chain(chroma.iter()).all(|&q| q > 0), "quantization values must not be zero");
+
+        let mut quantization = luma.to_vec();
+        quantization.extend(chroma.iter().cloned());
+        self.tables.quantization = quantization;
+    }
+
+    /// Sets the chroma subsampling of color images. Defaults to
+    /// `Subsampling::Ratio444`, subsampled chroma gives smaller files.
+    /// Gray images are not affected.
+    pub fn set_subsampling(&mut self, subsampling: Subsampling) {
+        self.subsampling = subsampling;
+    }
+
+    /// Sets whether the Huffman tables are derived from the statistics of
+    /// each image instead of using the example tables of Annex K. This
+    /// takes a second pass over the image and typically makes the output
+    /// a few percent smaller without any loss. Defaults to false.
+    pub fn set_optimize_coding(&mut self, optimize_coding: bool) {
+        self.optimize_coding = optimize_coding;
+    }
+
+    /// Sets whether the AC coefficients are quantized with a trellis search
+    /// that trades the squared error of each block against the bits of its
+    /// Huffman codes, as mozjpeg does, instead of rounding each coefficient
+    /// on its own. This typically makes the output 5 to 15 percent smaller
+    /// at a similar quality, at the cost of a slower encode. Defaults to false.
+    pub fn set_trellis_quantization(&mut self, trellis_quantization: bool) {
+        self.trellis_quantization = trellis_quantization;
+    }
+
+    /// Sets whether the scan is coded with the adaptive arithmetic coder of
+    /// the JPEG standard instead of Huffman codes, which typically makes
+    /// the output about 5 percent smaller. Not all decoders support
+    /// arithmetic coding, including the decoder of this crate, thus it
+    /// defaults to false. Optimized Huffman coding has no effect if set.
+    pub fn set_arithmetic_coding(&mut self, arithmetic_coding: bool) {
+        self.tables.arithmetic = arithmetic_coding;
+    }
+
+    /// Sets the number of MCUs between restart markers, 0 writes none.
+    /// Defaults to 0.
+    ///
+    /// Restart markers let decoders recover from damaged data and decode
+    /// the intervals in parallel, at the cost of slightly larger output.
+    /// A multi-threaded encode uses the interval if it evenly divides the
+    /// MCUs of a row, otherwise the image is encoded by one thread.
+    pub fn set_restart_interval(&mut self, mcus: u16) {
+        self.restart_interval = mcus;
+    }
+
+    /// Sets a map of the quality of each region of the image, e.g. higher
+    /// for faces or the foreground than for the background. Each pixel of
+    /// ```map``` holds a quality from 1 to 100 for the part of the image it
+    /// covers when stretched over the image, and applies to whole MCUs.
+    ///
+    /// Regions are quantized with the coarser of the tables of their quality
+    /// and those of the encoder, which are the ones stored in the file. Thus
+    /// the quality of the encoder is the highest quality of any region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if ```map``` is empty.
+    pub fn set_quality_map(&mut self, map: &GrayImage) {
+        assert!(map.width() > 0 && map.height() > 0, "the quality map must not be empty");
+        self.quality_map = Some(map.clone());
+    }
+
+    /// Makes ```encode``` search for the highest quality whose output fits
+    /// in ```bytes```, including the headers and metadata. An 8 bit image is
+    /// transformed once and only quantized and entropy coded again for each
+    /// quality tried, a 12 bit image is transformed again, with the
+    /// quantization tables of Annex K, which replace those of the encoder.
+    /// The search assumes that the output grows with the quality, as it
+    /// mostly does. Where it does not, a quality that fits is found, though
+    /// not necessarily the highest.
+    ///
+    /// ```encode``` returns an error if the image does not fit even at
+    /// quality 1. Does not apply to ```encode_scanlines```.
+    pub fn set_target_size(&mut self, bytes: usize) {
+        self.target_size = Some(bytes);
+    }
+
+    /// Overrides the global SIMD level of `config::set_simd` for this encoder.
+    /// The output is the same at every level, only the speed differs.
+    pub fn set_simd_level(&mut self, level: SimdLevel) {
+        self.simd = Some(level);
+    }
+
+    /// Returns the SIMD level used by this encoder
+    pub fn simd_level(&self) -> SimdLevel {
+        match self.simd {
+            Some(level) => level.effective(),
+            None => config::simd()
         }
     }
 
+    /// Writes ```exif``` to an APP1 segment after the JFIF header
+    pub fn set_exif(&mut self, exif: &Exif) {
+        self.exif = Some(exif.to_bytes());
+    }
+
+    /// Writes the EXIF segment ```data```, which starts with
+    /// ```Exif\0\0```, unchanged, e.g. to keep the metadata of a decoded
+    /// image as found by ```JPEGDecoder::marker_segments```
+    pub fn set_exif_data(&mut self, data: &[u8]) {
+        self.exif = Some(data.to_vec());
+    }
+
+    /// Embeds the ICC profile ```profile``` in the image, split across as
+    /// many APP2 segments as needed as specified by the ICC. Profiles of
+    /// up to 255 segments, about 16 MB, are supported.
+    pub fn set_icc_profile(&mut self, profile: &[u8]) {
+        self.icc_profile = Some(profile.to_vec());
+    }
+
+    /// Embeds the C2PA manifest store ```manifest```, a JUMBF box as
+    /// returned by ```JPEGDecoder::c2pa_manifest```, in APP11 segments, so
+    /// that the provenance of a transcoded image is preserved
+    pub fn set_c2pa_manifest(&mut self, manifest: &[u8]) {
+        self.c2pa_manifest = Some(manifest.to_vec());
+    }
+
+    /// Embeds a thumbnail of the image, downscaled to fit in ```max_size```
+    /// by ```max_size``` pixels, as the JPEG of IFD1 of the EXIF metadata,
+    /// which file browsers show without decoding the image. EXIF thumbnails
+    /// are usually at most 160 pixels wide, larger ones may not fit in the
+    /// APP1 segment. Applies to ```encode``` only.
+    pub fn set_thumbnail(&mut self, max_size: u32) {
+        self.thumbnail = Some(cmp::max(1, max_size));
+    }
+
+    /// Adds the text ```comment``` in a COM segment after the other
+    /// metadata, e.g. to record the tool or pipeline that made the image.
+    /// Each comment gets its own segment, in the order they were added.
+    pub fn add_comment(&mut self, comment: &str) {
+        self.comments.push(comment.to_string());
+    }
+
     /// Encodes the image ```image```
     /// that has dimensions ```width``` and ```height```
     /// and ```ColorType``` ```c```
-    /// The Image in encoded with subsampling ratio 4:2:2
+    /// Color images are subsampled as set by ```set_subsampling```
     pub fn encode(&mut self,
                   image: &[u8],
                   width: u32,
                   height: u32,
                   c: color::ColorType) -> io::Result<()> {
 
-        let n = color::num_components(c);
-        let num_components = if n == 1 || n == 2 {1}
-                             else {3};
+        let layout = try!(self.layout(width, height, c));
+        let bpp = layout.bpp;
 
-        let _ = try!(self.write_segment(SOI, None));
+        if image.len() < width as usize * height as usize * bpp {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("{} bytes are too few for a {}x{} {:?} image", image.len(), width, height, c)[..],
+            ))
+        }
 
-        let buf = build_jfif_header();
-        let _   = try!(self.write_segment(APP0, Some(buf)));
+        let source = Source {
+            image: &image[..width as usize * height as usize * bpp],
+            top: 0,
+            transformed: None,
+            wide: None,
+            width: width as usize,
+            bpp: bpp,
+            gray: layout.components.len() == 1,
+            h: layout.h,
+            v: layout.v,
+            simd: self.simd_level(),
+        };
 
-        let buf = build_frame_header(8, width as u16, height as u16, &self.components[..num_components]);
-        let _   = try!(self.write_segment(SOF0, Some(buf)));
+        self.thumbnail_data = match self.thumbnail {
+            Some(max_size) => Some(try!(encode_thumbnail(source.image, width, height, bpp, max_size))),
+            None => None
+        };
 
-        assert!(self.tables.len() / 64 == 2);
-        let numtables = if num_components == 1 {1}
-                        else {2};
+        let result = match self.target_size {
+            Some(bytes) => self.encode_to_size(&source, width, height, &layout, bytes),
+            None => self.encode_source(&source, width, height, &layout),
+        };
 
-        let t = self.tables.clone();
+        self.thumbnail_data = None;
+        result
+    }
 
-        for (i, table) in t.chunks(64).enumerate().take(numtables) {
-            let buf = build_quantization_segment(8, i as u8, table);
-            let _   = try!(self.write_segment(DQT, Some(buf)));
-        }
+    /// Encodes the 16 bit samples ```image```, of ```width``` by ```height```
+    /// pixels and ```ColorType``` ```c```, one of ```Gray(16)```,
+    /// ```GrayA(16)```, ```RGB(16)``` or ```RGBA(16)```, as a JPEG of 12 bit
+    /// precision, keeping the 12 highest bits of each sample. Thus samples
+    /// of only 12 significant bits must be shifted left by 4 first.
+    ///
+    /// The coefficients of 12 bit images exceed the example Huffman tables,
+    /// thus they are always optimized unless arithmetic coding is set, and
+    /// trellis quantization does not apply. Few decoders support 12 bit
+    /// precision, this one does not.
+    pub fn encode_12bit(&mut self,
+                        image: &[u16],
+                        width: u32,
+                        height: u32,
+                        c: color::ColorType) -> io::Result<()> {
+        let c8 = match c {
+            color::ColorType::Gray(16)  => color::ColorType::Gray(8),
+            color::ColorType::GrayA(16) => color::ColorType::GrayA(8),
+            color::ColorType::RGB(16)   => color::ColorType::RGB(8),
+            color::ColorType::RGBA(16)  => color::ColorType::RGBA(8),
+            _  => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("Unsupported color type {:?}. Use 16 bit per channel RGB(A) or Gray(A) instead.", c)[..],
+            ))
+        };
 
-        let numcodes = STD_LUMA_DC_CODE_LENGTHS;
-        let values   = STD_LUMA_DC_VALUES;
+        let mut layout = try!(self.layout(width, height, c8));
+        layout.precision = 12;
+        let bpp = layout.bpp;
+
+        if image.len() < width as usize * height as usize * bpp {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("{} samples are too few for a {}x{} {:?} image", image.len(), width, height, c)[..],
+            ))
+        }
 
-        let buf = build_huffman_segment(DCCLASS, LUMADESTINATION, &numcodes, &values);
-        let _   = try!(self.write_segment(DHT, Some(buf)));
+        let source = Source {
+            image: &[],
+            top: 0,
+            transformed: None,
+            wide: Some(&image[..width as usize * height as usize * bpp]),
+            width: width as usize,
+            bpp: bpp,
+            gray: layout.components.len() == 1,
+            h: layout.h,
+            v: layout.v,
+            simd: self.simd_level(),
+        };
 
-        let numcodes = STD_LUMA_AC_CODE_LENGTHS;
-        let values   = STD_LUMA_AC_VALUES;
+        match self.target_size {
+            Some(bytes) => self.encode_to_size(&source, width, height, &layout, bytes),
+            None => self.encode_source(&source, width, height, &layout),
+        }
+    }
 
-        let buf = build_huffman_segment(ACCLASS, LUMADESTINATION, &numcodes, &values);
-        let _   = try!(self.write_segment(DHT, Some(buf)));
+    // Encodes the whole image of `source`, from the metadata to the end
+    fn encode_source(&mut self, source: &Source, width: u32, height: u32, layout: &Layout) -> io::Result<()> {
+        let _ = try!(self.write_frame(width, height, layout));
+        let (h, v) = (layout.h, layout.v);
+        let num_components = layout.components.len();
+
+        let mcu_height = 8 * v;
+        let mcu_rows = (height as usize + mcu_height - 1) / mcu_height;
+        let mcus_per_row = (width as usize + 8 * h - 1) / (8 * h);
+
+        // Rows of MCUs are encoded in parallel if they start new restart intervals
+        let (interval, parallel) = match self.restart_interval as usize {
+            _ if self.threads == 1 || mcu_rows == 1 => (self.restart_interval as usize, false),
+            0 => (mcus_per_row, true),
+            n => (n, mcus_per_row % n == 0),
+        };
 
-        if num_components == 3 {
-            let numcodes = STD_CHROMA_DC_CODE_LENGTHS;
-            let values   = STD_CHROMA_DC_VALUES;
+        let mut tables = self.scan_tables(width, height);
 
-            let buf = build_huffman_segment(DCCLASS, CHROMADESTINATION, &numcodes, &values);
-            let _   = try!(self.write_segment(DHT, Some(buf)));
+        // Trellis quantization minimizes the code lengths of the example
+        // tables, which lack the larger coefficients of 12 bit samples
+        if layout.precision > 8 {
+            tables.trellis = None;
+        }
 
-            let numcodes = STD_CHROMA_AC_CODE_LENGTHS;
-            let values   = STD_CHROMA_AC_VALUES;
+        if (self.optimize_coding || layout.precision > 8) && !tables.arithmetic {
+            let counts = try!(count_symbols(source, &tables, mcu_rows, interval));
 
-            let buf = build_huffman_segment(ACCLASS, CHROMADESTINATION, &numcodes, &values);
-            let _   = try!(self.write_segment(DHT, Some(buf)));
+            for (table, counts) in tables.huffman.iter_mut().zip(counts.iter()) {
+                // Tables of unused components keep the example codes
+                if counts.iter().any(|&c| c > 0) {
+                    let (lengths, values) = optimal_huffman_table(counts);
+                    *table = HuffmanTable::new(&lengths, &values);
+                }
+            }
         }
 
-        let buf = build_scan_header(&self.components[..num_components]);
+        let _ = try!(self.write_huffman_tables(&tables, num_components, interval));
+
+        let buf = build_scan_header(&layout.components);
         let _   = try!(self.write_segment(SOS, Some(buf)));
 
-        match c {
-            color::ColorType::RGB(8)   => try!(self.encode_rgb(image, width as usize, height as usize, 3)),
-            color::ColorType::RGBA(8)  => try!(self.encode_rgb(image, width as usize, height as usize, 4)),
-            color::ColorType::Gray(8)  => try!(self.encode_gray(image, width as usize, height as usize, 1)),
-            color::ColorType::GrayA(8) => try!(self.encode_gray(image, width as usize, height as usize, 2)),
-            _  => return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                &format!("Unsupported color type {:?}. Use 8 bit per channel RGB(A) or Gray(A) instead.", c)[..],
-            ))
-        };
+        if parallel {
+            let rows = try!(encode_rows_parallel(source, &tables, mcu_rows, interval,
+                                                 self.threads, &*self.executor));
+            let intervals_per_row = mcus_per_row / interval;
+
+            for (i, row) in rows.iter().enumerate() {
+                if i > 0 {
+                    let _ = try!(self.w.write_all(&[0xFF, RST0 + ((i * intervals_per_row - 1) % 8) as u8]));
+                }
+
+                let _ = try!(self.w.write_all(row));
+            }
+        } else {
+            let mut writer = BitWriter::new(&mut *self.w, tables.arithmetic);
+            writer.interval = interval;
+            let mut dcprev = [0i32; 3];
+
+            for y in (0..mcu_rows) {
+                let _ = try!(encode_mcu_row(&mut writer, source, &tables, y * mcu_height, &mut dcprev));
+            }
+
+            let _ = try!(writer.flush());
+        }
 
-        let _ = try!(self.pad_byte());
         self.write_segment(EOI, None)
     }
 
-    fn write_segment(&mut self, marker: u8, data: Option<Vec<u8>>) -> io::Result<()> {
-        let _ = try!(self.w.write_all(&[0xFF]));
-        let _ = try!(self.w.write_all(&[marker]));
+    // Encodes `source` at the highest quality whose output fits in `bytes`,
+    // bisecting the qualities from 1 to 100 with the blocks of 8 bit images
+    // transformed once. The bisection assumes that the size grows with the
+    // quality. Where it does not, the quality found fits but a higher one
+    // may fit too. The range halves with each step, thus at most 7
+    // qualities are encoded.
+    fn encode_to_size(&mut self,
+                      source: &Source,
+                      width: u32,
+                      height: u32,
+                      layout: &Layout,
+                      bytes: usize) -> io::Result<()> {
+        let mcu_rows = (height as usize + 8 * layout.v - 1) / (8 * layout.v);
+        let mut blocks = Vec::new();
+
+        if source.transformed.is_none() && source.wide.is_none() {
+            let mut writer = BitWriter::new(io::sink(), false);
+            writer.blocks = Some(Vec::new());
+            let mut dcprev = [0i32; 3];
+
+            for y in (0..mcu_rows) {
+                let _ = try!(encode_mcu_row(&mut writer, source, &self.tables, y * 8 * layout.v, &mut dcprev));
+            }
 
-        if data.is_some() {
-            let b = data.unwrap();
-            let _ = try!(self.w.write_u16::<BigEndian>(b.len() as u16 + 2));
-            let _ = try!(self.w.write_all(&b));
+            blocks = writer.blocks.unwrap();
         }
 
-        Ok(())
-    }
+        // The blocks of 12 bit images are transformed again for each quality
+        let transformed = Source {
+            transformed: if source.wide.is_some() { None } else { source.transformed.or(Some(&blocks[..])) },
+            ..*source
+        };
 
-    fn write_bits(&mut self, bits: u16, size: u8) -> io::Result<()> {
-        self.accumulator |= (bits as u32) << (32 - (self.nbits + size)) as usize;
-        self.nbits += size;
+        let (mut low, mut high) = (1u8, 100u8);
+        let mut best = None;
 
-        while self.nbits >= 8 {
-            let byte = (self.accumulator & (0xFFFFFFFFu32 << 24)) >> 24;
-            let _ = try!(self.w.write_all(&[byte as u8]));
+        while low <= high {
+            let quality = low + (high - low) / 2;
+            let mut buf = Vec::new();
+            {
+                let mut encoder = self.with_writer(&mut buf);
+                encoder.tables.quantization = quality_tables(quality);
+                let _ = try!(encoder.encode_source(&transformed, width, height, layout));
+            }
 
-            if byte == 0xFF {
-                let _ = try!(self.w.write_all(&[0x00]));
+            if buf.len() <= bytes {
+                best = Some(buf);
+                low = quality + 1;
+            } else if quality == 1 {
+                break
+            } else {
+                high = quality - 1;
             }
+        }
 
-            self.nbits -= 8;
-            self.accumulator <<= 8;
+        match best {
+            Some(buf) => self.w.write_all(&buf),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("the image does not fit in {} bytes at any quality", bytes)[..],
+            ))
         }
+    }
 
-        Ok(())
+    // An encoder with the same settings that writes to `w`
+    fn with_writer<'b, V: Write>(&self, w: &'b mut V) -> JPEGEncoder<'b, V> {
+        JPEGEncoder {
+            w: w,
+
+            components: self.components.clone(),
+            tables: self.tables.clone(),
+            threads: self.threads,
+            executor: self.executor.clone(),
+            subsampling: self.subsampling,
+            optimize_coding: self.optimize_coding,
+            trellis_quantization: self.trellis_quantization,
+            restart_interval: self.restart_interval,
+            exif: self.exif.clone(),
+            icc_profile: self.icc_profile.clone(),
+            c2pa_manifest: self.c2pa_manifest.clone(),
+            comments: self.comments.clone(),
+            thumbnail: self.thumbnail,
+            thumbnail_data: self.thumbnail_data.clone(),
+            simd: self.simd,
+            quality_map: self.quality_map.clone(),
+            target_size: None,
+        }
     }
 
-    fn pad_byte(&mut self) -> io::Result<()> {
-        self.write_bits(0x7F, 7)
+    /// Starts encoding an image of ```width``` by ```height``` pixels and
+    /// ```ColorType``` ```c```, whose scanlines are pushed to the returned
+    /// ```ScanlineEncoder``` from the top down.
+    ///
+    /// The compressed data of each row of MCUs, 8 or 16 scanlines, is
+    /// written as soon as the row is complete, thus only those scanlines
+    /// are held in memory, however large the image. The output is the same
+    /// as that of ```encode```, except that optimized Huffman coding and
+    /// multiple threads, which need the whole image, do not apply.
+    pub fn encode_scanlines<'b>(&'b mut self,
+                                width: u32,
+                                height: u32,
+                                c: color::ColorType) -> io::Result<ScanlineEncoder<'b, W>> {
+        let layout = try!(self.layout(width, height, c));
+        let _ = try!(self.write_frame(width, height, &layout));
+
+        let tables = self.scan_tables(width, height);
+        let interval = self.restart_interval as usize;
+        let _ = try!(self.write_huffman_tables(&tables, layout.components.len(), interval));
+
+        let buf = build_scan_header(&layout.components);
+        let _   = try!(self.write_segment(SOS, Some(buf)));
+
+        let simd = self.simd_level();
+        let mut writer = BitWriter::new(&mut *self.w, tables.arithmetic);
+        writer.interval = interval;
+
+        Ok(ScanlineEncoder {
+            writer: writer,
+            tables: tables,
+            width: width,
+            height: height,
+            gray: layout.components.len() == 1,
+            bpp: layout.bpp,
+            h: layout.h,
+            v: layout.v,
+            simd: simd,
+            rows: Vec::with_capacity(8 * layout.v * width as usize * layout.bpp),
+            scanlines: 0,
+            dcprev: [0i32; 3],
+        })
     }
 
-    fn huffman_encode(&mut self, val: u8, table: &[(u8, u16)]) -> io::Result<()> {
-        let (size, code) = table[val as usize];
+    // The tables of a scan of an image of `width` by `height` pixels, before
+    // its Huffman tables are optimized
+    fn scan_tables(&self, width: u32, height: u32) -> Tables {
+        let mut tables = self.tables.clone();
 
-        if size > 16 {
-            panic!("bad huffman value");
+        if self.trellis_quantization {
+            tables.trellis = Some(tables.huffman.clone());
         }
 
-        self.write_bits(code, size)
+        if let Some(ref map) = self.quality_map {
+            tables.regions = Some(Regions::new(map, width, height, &tables.quantization));
+        }
+
+        tables
     }
 
-    fn write_block(
-        &mut self,
-        block: &[i32],
-        prevdc: i32,
-        dctable: &[(u8, u16)],
-        actable: &[(u8, u16)]) -> io::Result<i32> {
+    // Validates the dimensions and color type of an image and returns the
+    // layout of its samples
+    fn layout(&self, width: u32, height: u32, c: color::ColorType) -> io::Result<Layout> {
+        let n = color::num_components(c);
+        let num_components = if n == 1 || n == 2 {1}
+                             else {3};
 
-        // Differential DC encoding
-        let dcval = block[0];
-        let diff  = dcval - prevdc;
-        let (size, value) = encode_coefficient(diff);
+        let bpp = match c {
+            color::ColorType::RGB(8)   => 3,
+            color::ColorType::RGBA(8)  => 4,
+            color::ColorType::Gray(8)  => 1,
+            color::ColorType::GrayA(8) => 2,
+            _  => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("Unsupported color type {:?}. Use 8 bit per channel RGB(A) or Gray(A) instead.", c)[..],
+            ))
+        };
 
-        let _ = try!(self.huffman_encode(size, dctable));
-        let _ = try!(self.write_bits(value, size));
+        if width == 0 || height == 0 || width > 65535 || height > 65535 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("JPEG images must be between 1 and 65535 pixels wide and high, not {}x{}", width, height)[..],
+            ))
+        }
 
-        // Figure F.2
-        let mut zero_run = 0;
-        let mut k = 0usize;
+        // Only the luma component is sampled more often than the chroma
+        let (h, v) = if num_components == 1 { (1, 1) } else { self.subsampling.luma_factors() };
+        let mut components = self.components[..num_components].to_vec();
+        components[0].h = h;
+        components[0].v = v;
 
-        loop {
-            k += 1;
+        Ok(Layout {
+            precision: 8,
+            bpp: bpp,
+            components: components,
+            h: h as usize,
+            v: v as usize,
+        })
+    }
 
-            if block[UNZIGZAG[k] as usize] == 0 {
-                if k == 63 {
+    // Writes the metadata, the frame header and the quantization tables
+    fn write_frame(&mut self, width: u32, height: u32, layout: &Layout) -> io::Result<()> {
+        let _ = try!(self.write_metadata());
 
-                let _ = try!(self.huffman_encode(0x00, actable));
-                    break
-                }
+        let buf = build_frame_header(layout.precision, width as u16, height as u16, &layout.components);
+        let sof = if self.tables.arithmetic { SOF9 }
+                  else if layout.precision > 8 { SOF1 }
+                  else { SOF0 };
+        let _   = try!(self.write_segment(sof, Some(buf)));
 
-                zero_run += 1;
-            } else {
-                while zero_run > 15 {
-                    let _ = try!(self.huffman_encode(0xF0, actable));
-                    zero_run -= 16;
-                }
+        assert!(self.tables.quantization.len() / 64 == 2);
+        let numtables = if layout.components.len() == 1 {1}
+                        else {2};
 
-                let (size, value) = encode_coefficient(block[UNZIGZAG[k] as usize]);
-                let symbol = (zero_run << 4) | size;
+        let t = self.tables.quantization.clone();
 
-                let _ = try!(self.huffman_encode(symbol, actable));
-                let _ = try!(self.write_bits(value, size));
+        for (i, table) in t.chunks(64).enumerate().take(numtables) {
+            let buf = build_quantization_segment(8, i as u8, table);
+            let _   = try!(self.write_segment(DQT, Some(buf)));
+        }
 
-                zero_run = 0;
+        Ok(())
+    }
 
-                if k == 63 {
-                    break
-                }
-            }
+    /// Encodes the quantized DCT coefficients ```coefficients```, as read
+    /// by ```JPEGDecoder::read_coefficients``` and possibly transformed
+    /// with ```Coefficients::transform```, with their own quantization
+    /// tables and sampling factors, thus without any further loss.
+    ///
+    /// The quality, quantization tables, subsampling, trellis quantization
+    /// and number of threads do not apply. Images of more than 3
+    /// components or with 16 bit quantization tables are not supported,
+    /// nor are Huffman coded coefficients outside the range of 8 bit
+    /// samples, which only corrupt files hold.
+    pub fn encode_coefficients(&mut self, coefficients: &Coefficients) -> io::Result<()> {
+        let Coefficients { width, height, ref components } = *coefficients;
+
+        if width == 0 || height == 0 || width > 65535 || height > 65535 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("JPEG images must be between 1 and 65535 pixels wide and high, not {}x{}", width, height)[..],
+            ))
         }
 
-        Ok(dcval)
-    }
+        if components.is_empty() || components.len() > 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("Unsupported number of components {}", components.len())[..],
+            ))
+        }
 
-    fn encode_gray(&mut self, image: &[u8], width: usize, height: usize, bpp: usize) -> io::Result<()> {
-        let mut yblock     = [0u8; 64];
-        let mut y_dcprev   = 0;
-        let mut dct_yblock = [0i32; 64];
+        if !self.tables.arithmetic && !huffman_range(coefficients) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput, "Coefficients exceed the range of 8 bit samples"))
+        }
 
-        for y in range_step(0, height, 8) {
-            for x in range_step(0, width, 8) {
-                // RGB -> YCbCr
-                copy_blocks_gray(image, x, y, width, bpp, &mut yblock);
+        // Components with equal quantization tables share them
+        let mut quantization: Vec<Vec<u8>> = Vec::new();
+        let mut frame = Vec::new();
 
-                // Level shift and fdct
-                // Coeffs are scaled by 8
-                transform::fdct(&yblock, &mut dct_yblock);
+        for (i, c) in components.iter().enumerate() {
+            if c.quantization_table.iter().any(|&q| q == 0 || q > 255) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Unsupported quantization table"))
+            }
 
-                // Quantization
-                for i in (0usize..64) {
-                    dct_yblock[i]   = ((dct_yblock[i] / 8)   as f32 / self.tables[i] as f32).round() as i32;
+            let table = c.quantization_table.iter().map(|&q| q as u8).collect::<Vec<u8>>();
+            let tq = match quantization.iter().position(|t| *t == table) {
+                Some(tq) => tq,
+                None => {
+                    quantization.push(table);
+                    quantization.len() - 1
                 }
+            };
 
-                let la = self.luma_actable.clone();
-                let ld = self.luma_dctable.clone();
+            // A single component is not interleaved, its MCUs are single blocks
+            let (h, v) = if components.len() == 1 { (1, 1) } else { (c.h, c.v) };
+            let destination = if i == 0 { LUMADESTINATION } else { CHROMADESTINATION };
 
-                y_dcprev  = try!(self.write_block(&dct_yblock, y_dcprev, &ld, &la));
-            }
+            frame.push(Component {
+                id: c.id, h: h, v: v, tq: tq as u8,
+                dc_table: destination, ac_table: destination, dc_pred: 0
+            });
         }
 
-        Ok(())
-    }
-
-    fn encode_rgb(&mut self, image: &[u8], width: usize, height: usize, bpp: usize) -> io::Result<()> {
-        let mut y_dcprev = 0;
-        let mut cb_dcprev = 0;
-        let mut cr_dcprev = 0;
+        let _ = try!(self.write_metadata());
 
-        let mut dct_yblock   = [0i32; 64];
-        let mut dct_cb_block = [0i32; 64];
-        let mut dct_cr_block = [0i32; 64];
+        let buf = build_frame_header(8, width as u16, height as u16, &frame);
+        let sof = if self.tables.arithmetic { SOF9 } else { SOF0 };
+        let _   = try!(self.write_segment(sof, Some(buf)));
 
-        let mut yblock   = [0u8; 64];
-        let mut cb_block = [0u8; 64];
-        let mut cr_block = [0u8; 64];
+        for (i, table) in quantization.iter().enumerate() {
+            let buf = build_quantization_segment(8, i as u8, table);
+            let _   = try!(self.write_segment(DQT, Some(buf)));
+        }
 
-        for y in range_step(0, height, 8) {
-            for x in range_step(0, width, 8) {
-                // RGB -> YCbCr
-                copy_blocks_ycbcr(image, x, y, width, bpp, &mut yblock, &mut cb_block, &mut cr_block);
+        let mut tables = self.tables.clone();
+        tables.trellis = None;
+        let interval = self.restart_interval as usize;
 
-                // Level shift and fdct
-                // Coeffs are scaled by 8
-                transform::fdct(&yblock, &mut dct_yblock);
-                transform::fdct(&cb_block, &mut dct_cb_block);
-                transform::fdct(&cr_block, &mut dct_cr_block);
+        if self.optimize_coding && !tables.arithmetic {
+            let mut writer = BitWriter::new(io::sink(), false);
+            writer.counts = Some(vec![[0u32; 256]; tables.huffman.len()]);
+            writer.interval = interval;
+            let _ = try!(write_coefficients(&mut writer, width, height, components, &frame, &tables));
 
-                // Quantization
-                for i in (0usize..64) {
-                    dct_yblock[i]   = ((dct_yblock[i] / 8)   as f32 / self.tables[i] as f32).round() as i32;
-                    dct_cb_block[i] = ((dct_cb_block[i] / 8) as f32 / self.tables[64..][i] as f32).round() as i32;
-                    dct_cr_block[i] = ((dct_cr_block[i] / 8) as f32 / self.tables[64..][i] as f32).round() as i32;
+            for (table, counts) in tables.huffman.iter_mut().zip(writer.counts.unwrap().iter()) {
+                if counts.iter().any(|&c| c > 0) {
+                    let (lengths, values) = optimal_huffman_table(counts);
+                    *table = HuffmanTable::new(&lengths, &values);
                 }
+            }
+        }
 
-                let la = self.luma_actable.clone();
-                let ld = self.luma_dctable.clone();
-                let cd = self.chroma_dctable.clone();
-                let ca = self.chroma_actable.clone();
+        let _ = try!(self.write_huffman_tables(&tables, components.len(), interval));
 
-                y_dcprev  = try!(self.write_block(&dct_yblock, y_dcprev, &ld, &la));
-                cb_dcprev = try!(self.write_block(&dct_cb_block, cb_dcprev, &cd, &ca));
-                cr_dcprev = try!(self.write_block(&dct_cr_block, cr_dcprev, &cd, &ca));
-            }
+        let buf = build_scan_header(&frame);
+        let _   = try!(self.write_segment(SOS, Some(buf)));
+
+        {
+            let mut writer = BitWriter::new(&mut *self.w, tables.arithmetic);
+            writer.interval = interval;
+            let _ = try!(write_coefficients(&mut writer, width, height, components, &frame, &tables));
+            let _ = try!(writer.flush());
         }
 
-        Ok(())
+        self.write_segment(EOI, None)
     }
-}
 
-fn build_jfif_header() -> Vec<u8> {
-    let mut m = Vec::new();
+    // Writes SOI, the JFIF header and the EXIF, ICC and C2PA segments
+    fn write_metadata(&mut self) -> io::Result<()> {
+        let exif = match self.thumbnail_data {
+            Some(ref thumbnail) => {
+                let exif = self.exif.clone().unwrap_or_else(|| Exif::default().to_bytes());
+                Some(try!(exif::add_thumbnail(&exif, thumbnail).map_err(|err| {
+                    io::Error::new(io::ErrorKind::InvalidInput, &format!("{}", err)[..])
+                })))
+            }
+            None => self.exif.clone()
+        };
 
-    let _ = write!(m, "JFIF");
-    let _ = m.write_all(&[0]);
-    let _ = m.write_all(&[0x01]);
-    let _ = m.write_all(&[0x02]);
-    let _ = m.write_all(&[0]);
-    let _ = m.write_u16::<BigEndian>(1);
-    let _ = m.write_u16::<BigEndian>(1);
-    let _ = m.write_all(&[0]);
+        if exif.as_ref().map_or(false, |exif| exif.len() > 65533) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "EXIF data exceeds 65533 bytes"))
+        }
+
+        if self.icc_profile.as_ref().map_or(false, |icc| icc.len() > 255 * ICC_CHUNK_LEN) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "ICC profile exceeds 255 segments"))
+        }
+
+        if self.comments.iter().any(|comment| comment.len() > 65533) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "comment exceeds 65533 bytes"))
+        }
+
+        let c2pa_segments = match self.c2pa_manifest {
+            Some(ref manifest) => try!(c2pa::split_manifest(manifest, 1).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidInput, &format!("{}", err)[..])
+            })),
+            None => Vec::new()
+        };
+
+        let _ = try!(self.write_segment(SOI, None));
+
+        let buf = build_jfif_header();
+        let _   = try!(self.write_segment(APP0, Some(buf)));
+
+        if let Some(exif) = exif {
+            let _ = try!(self.write_segment(APP1, Some(exif)));
+        }
+
+        if let Some(profile) = self.icc_profile.clone() {
+            let count = (profile.len() + ICC_CHUNK_LEN - 1) / ICC_CHUNK_LEN;
+
+            // Segments are numbered from 1
+            for (i, chunk) in profile.chunks(ICC_CHUNK_LEN).enumerate() {
+                let mut buf = ICC_PROFILE.to_vec();
+                buf.push(i as u8 + 1);
+                buf.push(count as u8);
+                buf.extend(chunk.iter().cloned());
+
+                let _ = try!(self.write_segment(APP2, Some(buf)));
+            }
+        }
+
+        for segment in c2pa_segments {
+            let _ = try!(self.write_segment(APP11, Some(segment)));
+        }
+
+        for comment in self.comments.clone() {
+            let _ = try!(self.write_segment(COM, Some(comment.into_bytes())));
+        }
+
+        Ok(())
+    }
+
+    // Writes the Huffman tables of `num_components` components and the
+    // restart interval
+    fn write_huffman_tables(&mut self, tables: &Tables, num_components: usize, interval: usize) -> io::Result<()> {
+        // Arithmetic coding uses the default conditioning, which needs no tables
+        let numhuffman = if tables.arithmetic {0}
+                         else if num_components == 1 {2}
+                         else {4};
+        let huffman = [
+            (LUMA_DC, DCCLASS, LUMADESTINATION),
+            (LUMA_AC, ACCLASS, LUMADESTINATION),
+            (CHROMA_DC, DCCLASS, CHROMADESTINATION),
+            (CHROMA_AC, ACCLASS, CHROMADESTINATION),
+        ];
+
+        for &(index, class, destination) in huffman.iter().take(numhuffman) {
+            let table = &tables.huffman[index];
+            let buf = build_huffman_segment(class, destination, &table.lengths, &table.values);
+            let _   = try!(self.write_segment(DHT, Some(buf)));
+        }
+
+        if interval > 0 {
+            let mut buf = Vec::new();
+            let _ = buf.write_u16::<BigEndian>(interval as u16);
+            let _ = try!(self.write_segment(DRI, Some(buf)));
+        }
+
+        Ok(())
+    }
+
+    fn write_segment(&mut self, marker: u8, data: Option<Vec<u8>>) -> io::Result<()> {
+        let _ = try!(self.w.write_all(&[0xFF]));
+        let _ = try!(self.w.write_all(&[marker]));
+
+        if data.is_some() {
+            let b = data.unwrap();
+            let _ = try!(self.w.write_u16::<BigEndian>(b.len() as u16 + 2));
+            let _ = try!(self.w.write_all(&b));
+        }
+
+        Ok(())
+    }
+}
+
+// The samples of the image being encoded
+struct Source<'a> {
+    image: &'a [u8],
+    // The line of the image that `image` starts at
+    top: usize,
+    // The DCT blocks of the whole image in the order of the scan, if it was
+    // transformed before
+    transformed: Option<&'a [[i16; 64]]>,
+    // The 16 bit samples of an image of 12 bit precision, which are
+    // transformed an MCU at a time, instead of `image`
+    wide: Option<&'a [u16]>,
+    width: usize,
+    bpp: usize,
+    gray: bool,
+    // The sampling factors of the luma component
+    h: usize,
+    v: usize,
+    simd: SimdLevel,
+}
+
+// The precision of the samples of an image, its bytes or samples per
+// pixel, the components it is encoded with and the sampling factors of the
+// luma component
+struct Layout {
+    precision: u8,
+    bpp: usize,
+    components: Vec<Component>,
+    h: usize,
+    v: usize,
+}
+
+/// Encodes an image pushed scanline by scanline, as returned by
+/// ```JPEGEncoder::encode_scanlines```
+pub struct ScanlineEncoder<'a, W: 'a> {
+    writer: BitWriter<&'a mut W>,
+    tables: Tables,
+    width: u32,
+    height: u32,
+    gray: bool,
+    bpp: usize,
+    h: usize,
+    v: usize,
+    simd: SimdLevel,
+
+    // The scanlines of the current row of MCUs, the scanlines pushed so far
+    // and the DC predictors of the components
+    rows: Vec<u8>,
+    scanlines: u32,
+    dcprev: [i32; 3],
+}
+
+impl<'a, W: Write> ScanlineEncoder<'a, W> {
+    /// Encodes the scanlines ```data```, one or more rows of pixels in the
+    /// color type the encode was started with. Each completed row of MCUs
+    /// is written immediately.
+    ///
+    /// Returns an error if ```data``` does not hold whole scanlines or
+    /// more scanlines than remain in the image.
+    pub fn write_scanlines(&mut self, data: &[u8]) -> io::Result<()> {
+        let stride = self.width as usize * self.bpp;
+        let lines = data.len() / stride;
+
+        if data.len() % stride != 0 || lines > self.remaining() as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("{} bytes are not whole scanlines of the {} remaining", data.len(), self.remaining())[..],
+            ))
+        }
+
+        let mcu_rows_len = 8 * self.v * stride;
+
+        for line in data.chunks(stride) {
+            self.rows.extend(line.iter().cloned());
+            self.scanlines += 1;
+
+            if self.rows.len() == mcu_rows_len || self.scanlines == self.height {
+                let _ = try!(self.encode_rows());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The number of scanlines still to be pushed
+    pub fn remaining(&self) -> u32 {
+        self.height - self.scanlines
+    }
+
+    /// Ends the image after its last scanline was pushed.
+    ///
+    /// Returns an error if scanlines are missing.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.remaining() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("{} scanlines of the image are missing", self.remaining())[..],
+            ))
+        }
+
+        let _ = try!(self.writer.flush());
+        self.writer.w.write_all(&[0xFF, EOI])
+    }
+
+    // Encodes the buffered row of MCUs, the last one possibly shorter
+    fn encode_rows(&mut self) -> io::Result<()> {
+        {
+            let stride = self.width as usize * self.bpp;
+            let source = Source {
+                image: &self.rows,
+                top: self.scanlines as usize - self.rows.len() / stride,
+                transformed: None,
+                wide: None,
+                width: self.width as usize,
+                bpp: self.bpp,
+                gray: self.gray,
+                h: self.h,
+                v: self.v,
+                simd: self.simd,
+            };
+
+            let _ = try!(encode_mcu_row(&mut self.writer, &source, &self.tables, 0, &mut self.dcprev));
+        }
+
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+// Writes the entropy coded segment of a scan
+struct BitWriter<W> {
+    w: W,
+
+    accumulator: u32,
+    nbits: u8,
+
+    // If set, the symbols of each Huffman table are only counted
+    counts: Option<Vec<[u32; 256]>>,
+
+    // If set, the blocks are arithmetic coded instead
+    arithmetic: Option<ArithmeticEncoder>,
+
+    // If set, the DCT blocks are only collected, unquantized
+    blocks: Option<Vec<[i16; 64]>>,
+
+    // The number of MCUs per restart interval, or 0, the MCUs written so
+    // far and the restart markers that precede them
+    interval: usize,
+    mcus: usize,
+    restarts: usize,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn new(w: W, arithmetic: bool) -> BitWriter<W> {
+        BitWriter {
+            w: w,
+            accumulator: 0,
+            nbits: 0,
+            counts: None,
+            arithmetic: if arithmetic { Some(ArithmeticEncoder::new()) } else { None },
+            blocks: None,
+            interval: 0,
+            mcus: 0,
+            restarts: 0,
+        }
+    }
+
+    // Ends the restart interval before an MCU if it is complete, which
+    // resets the DC predictions
+    fn start_mcu(&mut self, dcprev: &mut [i32; 3]) -> io::Result<()> {
+        if self.interval > 0 && self.mcus > 0 && self.mcus % self.interval == 0 {
+            let _ = try!(self.flush());
+            if self.counts.is_none() {
+                let _ = try!(self.w.write_all(&[0xFF, RST0 + (self.restarts % 8) as u8]));
+            }
+
+            self.restarts += 1;
+            *dcprev = [0i32; 3];
+        }
+
+        self.mcus += 1;
+        Ok(())
+    }
+
+    fn write_bits(&mut self, bits: u16, size: u8) -> io::Result<()> {
+        if size == 0 || self.counts.is_some() {
+            return Ok(())
+        }
+
+        self.accumulator |= (bits as u32) << (32 - (self.nbits + size)) as usize;
+        self.nbits += size;
+
+        while self.nbits >= 8 {
+            let byte = (self.accumulator & (0xFFFFFFFFu32 << 24)) >> 24;
+            let _ = try!(self.w.write_all(&[byte as u8]));
+
+            if byte == 0xFF {
+                let _ = try!(self.w.write_all(&[0x00]));
+            }
+
+            self.nbits -= 8;
+            self.accumulator <<= 8;
+        }
+
+        Ok(())
+    }
+
+    // Fills the last byte with ones. This writes the same bytes as padding
+    // with seven ones, but leaves no bits behind for the next restart
+    // interval.
+    fn pad_byte(&mut self) -> io::Result<()> {
+        let size = (8 - self.nbits % 8) % 8;
+        self.write_bits(0x7F >> (7 - size as usize), size)
+    }
+
+    // Ends the entropy coded data of the scan or of a restart interval
+    fn flush(&mut self) -> io::Result<()> {
+        match self.arithmetic {
+            Some(ref mut arithmetic) => arithmetic.finish(&mut self.w),
+            None => self.pad_byte()
+        }
+    }
+
+    fn huffman_encode(&mut self, val: u8, tables: &[HuffmanTable], index: usize) -> io::Result<()> {
+        if let Some(ref mut counts) = self.counts {
+            counts[index][val as usize] += 1;
+            return Ok(())
+        }
+
+        let (size, code) = tables[index].lut[val as usize];
+
+        if size > 16 {
+            panic!("bad huffman value");
+        }
+
+        self.write_bits(code, size)
+    }
+
+    fn write_block(
+        &mut self,
+        block: &[i32],
+        prevdc: i32,
+        component: usize,
+        tables: &[HuffmanTable],
+        dctable: usize,
+        actable: usize) -> io::Result<i32> {
+
+        // Differential DC encoding
+        let dcval = block[0];
+        let diff  = dcval - prevdc;
+
+        // The statistics of the arithmetic coder follow the Huffman tables
+        if let Some(ref mut arithmetic) = self.arithmetic {
+            let _ = try!(arithmetic.encode_block(&mut self.w, block, diff, component, dctable / 2));
+            return Ok(dcval)
+        }
+        let (size, value) = encode_coefficient(diff);
+
+        let _ = try!(self.huffman_encode(size, tables, dctable));
+        let _ = try!(self.write_bits(value, size));
+
+        // Figure F.2
+        let mut zero_run = 0;
+        let mut k = 0usize;
+
+        loop {
+            k += 1;
+
+            if block[UNZIGZAG[k] as usize] == 0 {
+                if k == 63 {
+
+                let _ = try!(self.huffman_encode(0x00, tables, actable));
+                    break
+                }
+
+                zero_run += 1;
+            } else {
+                while zero_run > 15 {
+                    let _ = try!(self.huffman_encode(0xF0, tables, actable));
+                    zero_run -= 16;
+                }
+
+                let (size, value) = encode_coefficient(block[UNZIGZAG[k] as usize]);
+                let symbol = (zero_run << 4) | size;
+
+                let _ = try!(self.huffman_encode(symbol, tables, actable));
+                let _ = try!(self.write_bits(value, size));
+
+                zero_run = 0;
+
+                if k == 63 {
+                    break
+                }
+            }
+        }
+
+        Ok(dcval)
+    }
+}
+
+// Encodes the row of MCUs starting at line `y0`. `dcprev` holds the DC
+// predictors of the components.
+fn encode_mcu_row<W: Write>(writer: &mut BitWriter<W>,
+                            source: &Source,
+                            tables: &Tables,
+                            y0: usize,
+                            dcprev: &mut [i32; 3]) -> io::Result<()> {
+    let mut dct_block = [0i32; 64];
+    let mut block     = [0u8; 64];
+
+    // The samples of one MCU
+    let (mcu_width, mcu_height) = (8 * source.h, 8 * source.v);
+    let mut ys  = vec![0u8; mcu_width * mcu_height];
+    let mut cbs = vec![0u8; mcu_width * mcu_height];
+    let mut crs = vec![0u8; mcu_width * mcu_height];
+
+    // The DCT blocks of one MCU, transformed before or of a 12 bit image
+    let mut mcu_blocks = Vec::new();
+
+    let luma   = &tables.quantization[..64];
+    let chroma = &tables.quantization[64..];
+
+    for (mx, x) in range_step(0, source.width, mcu_width).enumerate() {
+        let _ = try!(writer.start_mcu(dcprev));
+
+        // The tables of the region of a quality map at the center of the MCU
+        let region = tables.regions.as_ref().map(|r| {
+            r.tables_at(x + mcu_width / 2, source.top + y0 + mcu_height / 2)
+        });
+        let (luma_region, chroma_region) = (region.map(|t| &t[..64]), region.map(|t| &t[64..]));
+
+        // The luma blocks of transformed MCUs are followed by the chroma blocks
+        if source.transformed.is_some() || source.wide.is_some() {
+            let luma_blocks = source.h * source.v;
+            let per_mcu = if source.gray { 1 } else { luma_blocks + 2 };
+            let mcus_per_row = (source.width + mcu_width - 1) / mcu_width;
+            let first = ((source.top + y0) / mcu_height * mcus_per_row + mx) * per_mcu;
+
+            mcu_blocks.clear();
+            match source.wide {
+                Some(wide) => transform_mcu_12bit(source, wide, x, y0, &mut mcu_blocks),
+                None => for b in source.transformed.unwrap()[first..first + per_mcu].iter() {
+                    let mut coefficients = [0i32; 64];
+                    for (dst, &src) in coefficients.iter_mut().zip(b.iter()) {
+                        *dst = src as i32;
+                    }
+                    mcu_blocks.push(coefficients);
+                }
+            }
+
+            for (i, b) in mcu_blocks.iter().enumerate() {
+                dct_block = *b;
+                let c = if i < luma_blocks { 0 } else { i - luma_blocks + 1 };
+
+                dcprev[c] = try!(if c == 0 {
+                    quantize_block(writer, &mut dct_block, luma, dcprev[c], c, tables, LUMA_DC, LUMA_AC, luma_region)
+                } else {
+                    quantize_block(writer, &mut dct_block, chroma, dcprev[c], c, tables, CHROMA_DC, CHROMA_AC,
+                                   chroma_region)
+                });
+            }
+            continue
+        }
+
+        if source.gray {
+            copy_blocks_gray(source.image, x, y0, source.width, source.bpp, &mut block);
+
+            dcprev[0] = try!(encode_block(writer, &block, &mut dct_block, luma, dcprev[0], 0,
+                                          tables, LUMA_DC, LUMA_AC, luma_region, source.simd));
+            continue
+        }
+
+        // RGB -> YCbCr
+        copy_blocks_ycbcr(source.image, x, y0, source.width, source.bpp, mcu_width, mcu_height,
+                          source.simd, &mut ys, &mut cbs, &mut crs);
+
+        // The luma blocks in the order of the scan
+        for by in (0..source.v) {
+            for bx in (0..source.h) {
+                for y in (0usize..8) {
+                    let row = (by * 8 + y) * mcu_width + bx * 8;
+                    ::copy_memory(&ys[row..row + 8], &mut block[y * 8..y * 8 + 8]);
+                }
+
+                dcprev[0] = try!(encode_block(writer, &block, &mut dct_block, luma, dcprev[0], 0,
+                                              tables, LUMA_DC, LUMA_AC, luma_region, source.simd));
+            }
+        }
+
+        for (i, samples) in [&cbs, &crs].iter().enumerate() {
+            downsample(samples, mcu_width, source.h, source.v, &mut block);
+
+            dcprev[i + 1] = try!(encode_block(writer, &block, &mut dct_block, chroma, dcprev[i + 1], i + 1,
+                                              tables, CHROMA_DC, CHROMA_AC, chroma_region, source.simd));
+        }
+    }
+
+    Ok(())
+}
+
+// Whether `coefficients` fit the Huffman codes of 8 bit samples: AC
+// coefficients of at most 10 bits, and DC coefficients within -1024..1023,
+// whose differences take at most 11 bits
+pub fn huffman_range(coefficients: &Coefficients) -> bool {
+    coefficients.components.iter().all(|c| c.blocks.iter().all(|block| {
+        block[0] >= -1024 && block[0] <= 1023 && block[1..].iter().all(|&a| a >= -1023 && a <= 1023)
+    }))
+}
+
+// Writes the blocks of `components` in the MCUs of `frame`, an image of
+// `width` by `height` pixels
+fn write_coefficients<W: Write>(writer: &mut BitWriter<W>,
+                                width: u32,
+                                height: u32,
+                                components: &[ComponentCoefficients],
+                                frame: &[Component],
+                                tables: &Tables) -> io::Result<()> {
+    let hmax = frame.iter().map(|c| c.h as u32).max().unwrap();
+    let vmax = frame.iter().map(|c| c.v as u32).max().unwrap();
+    let mcus_per_row = (width + 8 * hmax - 1) / (8 * hmax);
+    let mcu_rows = (height + 8 * vmax - 1) / (8 * vmax);
+
+    let mut dcprev = [0i32; 3];
+    let mut block = [0i32; 64];
+
+    for mcu_y in (0..mcu_rows) {
+        for mcu_x in (0..mcus_per_row) {
+            let _ = try!(writer.start_mcu(&mut dcprev));
+
+            for (i, (c, f)) in components.iter().zip(frame.iter()).enumerate() {
+                let (h, v) = (f.h as u32, f.v as u32);
+
+                for b in (0..h * v) {
+                    let coefficients = c.block(mcu_x * h + b % h, mcu_y * v + b / h);
+                    for (dst, &src) in block.iter_mut().zip(coefficients.iter()) {
+                        *dst = src as i32;
+                    }
+
+                    let dctable = 2 * f.dc_table as usize;
+                    dcprev[i] = try!(writer.write_block(&block, dcprev[i], i, &tables.huffman,
+                                                        dctable, dctable + 1));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Transforms, quantizes and writes one block, returns its DC coefficient
+fn encode_block<W: Write>(writer: &mut BitWriter<W>,
+                          block: &[u8; 64],
+                          dct_block: &mut [i32; 64],
+                          quantization: &[u8],
+                          prevdc: i32,
+                          component: usize,
+                          tables: &Tables,
+                          dctable: usize,
+                          actable: usize,
+                          region: Option<&[u8]>,
+                          level: SimdLevel) -> io::Result<i32> {
+    // Level shift and fdct
+    // Coeffs are scaled by 8
+    if !simd::fdct(level, block, dct_block) {
+        transform::fdct(block, dct_block);
+    }
+
+    // The coefficients of 8 bit samples fit in 16 bits, even scaled by 8
+    if let Some(ref mut blocks) = writer.blocks {
+        let mut coefficients = [0i16; 64];
+        for (dst, &src) in coefficients.iter_mut().zip(dct_block.iter()) {
+            *dst = src as i16;
+        }
+        blocks.push(coefficients);
+        return Ok(prevdc)
+    }
+
+    quantize_block(writer, dct_block, quantization, prevdc, component, tables, dctable, actable, region)
+}
+
+// Quantizes and writes one transformed block, returns its DC coefficient
+fn quantize_block<W: Write>(writer: &mut BitWriter<W>,
+                            dct_block: &mut [i32; 64],
+                            quantization: &[u8],
+                            prevdc: i32,
+                            component: usize,
+                            tables: &Tables,
+                            dctable: usize,
+                            actable: usize,
+                            region: Option<&[u8]>) -> io::Result<i32> {
+    // Quantization, with the steps of the region if coarser
+    let steps = region.unwrap_or(quantization);
+    match tables.trellis {
+        Some(ref rates) => trellis_quantize(dct_block, steps, &rates[actable]),
+        None => for i in (0usize..64) {
+            dct_block[i] = ((dct_block[i] / 8) as f32 / steps[i] as f32).round() as i32;
+        }
+    }
+
+    // The steps of the region are stored in units of the steps of the scan
+    if let Some(region) = region {
+        for i in (0usize..64) {
+            dct_block[i] = ((dct_block[i] * region[i] as i32) as f32 / quantization[i] as f32).round() as i32;
+        }
+    }
+
+    writer.write_block(&dct_block[..], prevdc, component, &tables.huffman, dctable, actable)
+}
+
+// Quantizes the coefficients of `block`, which are scaled by 8. Each AC
+// coefficient is rounded, reduced by one step or zeroed, whichever
+// combination minimizes the squared error in quantization steps plus
+// `TRELLIS_LAMBDA` times the bits of the codes of `ac`. The coefficient
+// that ends the block is found by dynamic programming over the zigzag
+// order, where the cost of a coefficient depends on the zeros before it.
+fn trellis_quantize(block: &mut [i32; 64], quantization: &[u8], ac: &HuffmanTable) {
+    let bits = |symbol: u8| match ac.lut[symbol as usize].0 {
+        0 => f32::INFINITY,
+        n => n as f32,
+    };
+
+    // The coefficients in zigzag order in quantization steps, and the
+    // error of zeroing the coefficients up to each one
+    let mut steps = [0f32; 64];
+    let mut zeroed = [0f32; 64];
+
+    for k in (1usize..64) {
+        let z = UNZIGZAG[k] as usize;
+        steps[k] = (block[z] / 8) as f32 / quantization[z] as f32;
+        zeroed[k] = zeroed[k - 1] + steps[k] * steps[k];
+    }
+
+    // The least cost of coding the coefficients up to each one if it is
+    // the last one that is not zero, with its value and the previous one
+    let mut cost = [f32::INFINITY; 64];
+    let mut value = [0i32; 64];
+    let mut previous = [0usize; 64];
+    cost[0] = 0.0;
+
+    for i in (1usize..64) {
+        let rounded = steps[i].abs().round();
+
+        for &magnitude in [rounded, rounded - 1.0].iter().filter(|&&m| m >= 1.0) {
+            let (size, _) = encode_coefficient(magnitude as i32);
+            let error = (steps[i].abs() - magnitude) * (steps[i].abs() - magnitude);
+
+            for j in (0..i) {
+                if cost[j] == f32::INFINITY {
+                    continue
+                }
+
+                let run = i - j - 1;
+                let rate = (run / 16) as f32 * bits(0xF0) + bits(((run % 16) as u8) << 4 | size) + size as f32;
+                let c = cost[j] + zeroed[i - 1] - zeroed[j] + error + TRELLIS_LAMBDA * rate;
+
+                if c < cost[i] {
+                    cost[i] = c;
+                    value[i] = if steps[i] < 0.0 { -(magnitude as i32) } else { magnitude as i32 };
+                    previous[i] = j;
+                }
+            }
+        }
+    }
+
+    // The coefficients after the last one are zero, as coded by EOB
+    let mut last = 0;
+    let mut least = f32::INFINITY;
+
+    for j in (0usize..64).filter(|&j| cost[j] < f32::INFINITY) {
+        let eob = if j < 63 { TRELLIS_LAMBDA * bits(0x00) } else { 0.0 };
+        let c = cost[j] + zeroed[63] - zeroed[j] + eob;
+
+        if c < least {
+            least = c;
+            last = j;
+        }
+    }
+
+    block[0] = ((block[0] / 8) as f32 / quantization[0] as f32).round() as i32;
+    for k in (1usize..64) {
+        block[UNZIGZAG[k] as usize] = 0;
+    }
+
+    while last > 0 {
+        block[UNZIGZAG[last] as usize] = value[last];
+        last = previous[last];
+    }
+}
+
+// Averages the samples of an MCU `width` samples wide over `h` by `v`
+// samples into one block
+fn downsample(samples: &[u8], width: usize, h: usize, v: usize, block: &mut [u8; 64]) {
+    let count = (h * v) as u32;
+
+    for y in (0usize..8) {
+        for x in (0usize..8) {
+            let mut sum = 0u32;
+
+            for dy in (0..v) {
+                for dx in (0..h) {
+                    sum += samples[(y * v + dy) * width + x * h + dx] as u32;
+                }
+            }
+
+            block[y * 8 + x] = ((sum + count / 2) / count) as u8;
+        }
+    }
+}
+
+// Encodes each MCU row as a separate restart interval, distributing
+// contiguous ranges of rows over `jobs` jobs run by `executor`.
+fn encode_rows_parallel(source: &Source,
+                        tables: &Tables,
+                        mcu_rows: usize,
+                        interval: usize,
+                        jobs: usize,
+                        executor: &Executor) -> io::Result<Vec<Vec<u8>>> {
+    let rows_per_job = (mcu_rows + jobs - 1) / jobs;
+    let mut results = (0..mcu_rows).map(|_| Ok(Vec::new())).collect::<Vec<io::Result<Vec<u8>>>>();
+
+    executor::scope(executor, results.chunks_mut(rows_per_job).enumerate().map(|(i, chunk)| {
+        let job: Job = Box::new(move || {
+            for (j, result) in chunk.iter_mut().enumerate() {
+                let mut writer = BitWriter::new(Vec::new(), tables.arithmetic);
+                let mut dcprev = [0i32; 3];
+                let row = i * rows_per_job + j;
+                let y = row * 8 * source.v;
+
+                // Number the restart markers within the row
+                let mcus_per_row = (source.width + 8 * source.h - 1) / (8 * source.h);
+                writer.interval = interval;
+                writer.restarts = row * mcus_per_row / interval;
+
+                *result = encode_mcu_row(&mut writer, source, tables, y, &mut dcprev)
+                              .and_then(|_| writer.flush())
+                              .map(|_| writer.w);
+            }
+        });
+
+        job
+    }).collect());
+
+    results.into_iter().collect()
+}
+
+// Counts the symbols of each Huffman table that encoding the scan with
+// restart intervals of `interval` MCUs takes
+fn count_symbols(source: &Source,
+                 tables: &Tables,
+                 mcu_rows: usize,
+                 interval: usize) -> io::Result<Vec<[u32; 256]>> {
+    let mut writer = BitWriter::new(io::sink(), false);
+    writer.counts = Some(vec![[0u32; 256]; tables.huffman.len()]);
+    writer.interval = interval;
+
+    let mut dcprev = [0i32; 3];
+
+    for y in (0..mcu_rows) {
+        let _ = try!(encode_mcu_row(&mut writer, source, tables, y * 8 * source.v, &mut dcprev));
+    }
+
+    Ok(writer.counts.unwrap())
+}
+
+// Derives the code lengths and symbols of a Huffman table with codes of at
+// most 16 bits for symbols occuring `counts` times, Section K.2
+fn optimal_huffman_table(counts: &[u32; 256]) -> (Vec<u8>, Vec<u8>) {
+    // Symbol 256 reserves the code of all ones
+    let mut freq = counts.iter().map(|&c| c as u64).collect::<Vec<u64>>();
+    freq.push(1);
+
+    let mut codesize = [0usize; 257];
+    let mut others = [-1isize; 257];
+
+    // Figure K.1, repeatedly merges the two least frequent trees
+    loop {
+        let mut v1 = None;
+        let mut v2 = None;
+
+        // The least frequent symbols, preferring the larger ones on ties
+        for i in (0..257) {
+            if freq[i] == 0 {
+                continue
+            }
+
+            match v1 {
+                Some(v) if freq[i] > freq[v] => (),
+                _ => {
+                    v2 = v1;
+                    v1 = Some(i);
+                    continue
+                }
+            }
+
+            match v2 {
+                Some(v) if freq[i] > freq[v] => (),
+                _ => v2 = Some(i),
+            }
+        }
+
+        let (mut v1, mut v2) = match (v1, v2) {
+            (Some(a), Some(b)) => (a, b),
+            _ => break
+        };
+
+        freq[v1] += freq[v2];
+        freq[v2] = 0;
+
+        codesize[v1] += 1;
+        while others[v1] >= 0 {
+            v1 = others[v1] as usize;
+            codesize[v1] += 1;
+        }
+
+        others[v1] = v2 as isize;
+
+        codesize[v2] += 1;
+        while others[v2] >= 0 {
+            v2 = others[v2] as usize;
+            codesize[v2] += 1;
+        }
+    }
+
+    // Figure K.2
+    let mut bits = [0u8; 33];
+    for &size in codesize.iter() {
+        if size > 0 {
+            bits[size] += 1;
+        }
+    }
+
+    // Figure K.3, limits the code lengths to 16 bits
+    let mut i = 32;
+    while i > 16 {
+        while bits[i] > 0 {
+            let mut j = i - 2;
+            while bits[j] == 0 {
+                j -= 1;
+            }
+
+            bits[i] -= 2;
+            bits[i - 1] += 1;
+            bits[j + 1] += 2;
+            bits[j] -= 1;
+        }
+
+        i -= 1;
+    }
+
+    // Removes the reserved code from the longest codes
+    while bits[i] == 0 {
+        i -= 1;
+    }
+    bits[i] -= 1;
+
+    // Figure K.4, sorts the symbols by code length
+    let mut values = Vec::new();
+    for size in (1..33) {
+        for symbol in (0..256) {
+            if codesize[symbol] == size {
+                values.push(symbol as u8);
+            }
+        }
+    }
+
+    (bits[1..17].to_vec(), values)
+}
+
+fn build_jfif_header() -> Vec<u8> {
+    let mut m = Vec::new();
+
+    let _ = write!(m, "JFIF");
+    let _ = m.write_all(&[0]);
+    let _ = m.write_all(&[0x01]);
+    let _ = m.write_all(&[0x02]);
+    let _ = m.write_all(&[0]);
+    let _ = m.write_u16::<BigEndian>(1);
+    let _ = m.write_u16::<BigEndian>(1);
+    let _ = m.write_all(&[0]);
     let _ = m.write_all(&[0]);
 
     m
@@ -560,55 +2011,892 @@ fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
     (y as u8, cb as u8, cr as u8)
 }
 
-fn value_at(s: &[u8], index: usize) -> u8 {
-    if index < s.len() {
-        s[index]
-    } else {
-        s[s.len() - 1]
-    }
-}
-
+// Converts the MCU at `x0`, `y0` to YCbCr, repeating the last column and
+// row of the image past its edges, like `copy_blocks_gray`
 fn copy_blocks_ycbcr(source: &[u8],
                      x0: usize,
                      y0: usize,
                      width: usize,
                      bpp: usize,
-                     yb: &mut [u8; 64],
-                     cbb: &mut [u8; 64],
-                     crb: &mut [u8; 64]) {
-
-    for y in (0usize..8) {
-        let ystride = (y0 + y) * bpp * width;
-
-        for x in (0usize..8) {
-            let xstride = x0 * bpp + x * bpp;
-
-            let r = value_at(source, ystride + xstride + 0);
-            let g = value_at(source, ystride + xstride + 1);
-            let b = value_at(source, ystride + xstride + 2);
+                     mcu_width: usize,
+                     mcu_height: usize,
+                     level: SimdLevel,
+                     yb: &mut [u8],
+                     cbb: &mut [u8],
+                     crb: &mut [u8]) {
+    let height = source.len() / (bpp * width);
+
+    for y in (0usize..mcu_height) {
+        let ystride = edge_offset(0, y0 + y, width, height, bpp);
+        let row = y * mcu_width..(y + 1) * mcu_width;
+
+        // The columns within the image are converted by the SIMD kernel first
+        let start = ystride + x0 * bpp;
+        let inside = cmp::min(mcu_width, width - x0);
+        let converted = simd::rgb_to_ycbcr(level, &source[start..start + inside * bpp], bpp,
+                                           &mut yb[row.start..row.start + inside],
+                                           &mut cbb[row.clone()], &mut crb[row.clone()]);
+
+        for x in (converted..mcu_width) {
+            let offset = edge_offset(x0 + x, y0 + y, width, height, bpp);
+
+            let r = source[offset + 0];
+            let g = source[offset + 1];
+            let b = source[offset + 2];
 
             let (yc, cb, cr) = rgb_to_ycbcr(r, g, b);
 
-            yb[y * 8 + x]  = yc;
-            cbb[y * 8 + x] = cb;
-            crb[y * 8 + x] = cr;
+            yb[y * mcu_width + x]  = yc;
+            cbb[y * mcu_width + x] = cb;
+            crb[y * mcu_width + x] = cr;
         }
     }
 }
 
+// Copies the block at `x0`, `y0` of the gray channel, the first sample of
+// each pixel, repeating the last column and row of the image past its edges
 fn copy_blocks_gray(source: &[u8],
                     x0: usize,
                     y0: usize,
                     width: usize,
                     bpp: usize,
                     gb: &mut [u8; 64]) {
+    let height = source.len() / (bpp * width);
 
     for y in (0usize..8) {
-        let ystride = (y0 + y) * bpp * width;
-
         for x in (0usize..8) {
-            let xstride = x0 * bpp + x * bpp;
-            gb[y * 8 + x] = value_at(source, ystride + xstride + 1);
+            gb[y * 8 + x] = source[edge_offset(x0 + x, y0 + y, width, height, bpp)];
+        }
+    }
+}
+
+// The offset of the pixel at `x`, `y`, or past the right and bottom edges
+// of the image of the nearest pixel in the last column and row. Both the
+// gray and the YCbCr blocks are padded this way.
+fn edge_offset(x: usize, y: usize, width: usize, height: usize, bpp: usize) -> usize {
+    (cmp::min(y, height - 1) * width + cmp::min(x, width - 1)) * bpp
+}
+
+// Downscales the image of `bpp` bytes per pixel to fit in `max_size` by
+// `max_size` pixels, without its alpha, and encodes it as a thumbnail. Each
+// pixel of the thumbnail averages the pixels it covers, read directly from
+// `image`.
+fn encode_thumbnail(image: &[u8], width: u32, height: u32, bpp: usize, max_size: u32) -> io::Result<Vec<u8>> {
+    let scale = |v: u32| if width <= max_size && height <= max_size {
+        v
+    } else {
+        cmp::max(1, (v as u64 * max_size as u64 / cmp::max(width, height) as u64) as u32)
+    };
+    let (w, h) = (scale(width), scale(height));
+    let channels = if bpp < 3 { 1 } else { 3 };
+
+    let mut sums = vec![0u64; w as usize * h as usize * channels];
+    let mut counts = vec![0u64; w as usize * h as usize];
+
+    for y in (0..height as usize) {
+        let row = (y as u64 * h as u64 / height as u64) as usize * w as usize;
+
+        for x in (0..width as usize) {
+            let i = row + (x as u64 * w as u64 / width as u64) as usize;
+            let pixel = &image[(y * width as usize + x) * bpp..];
+
+            for (sum, &v) in sums[i * channels..(i + 1) * channels].iter_mut().zip(pixel.iter()) {
+                *sum += v as u64;
+            }
+            counts[i] += 1;
+        }
+    }
+
+    let samples = sums.iter().enumerate().map(|(i, &sum)| {
+        let count = counts[i / channels];
+        ((sum + count / 2) / count) as u8
+    }).collect::<Vec<u8>>();
+
+    let mut thumbnail = Vec::new();
+    {
+        let mut encoder = JPEGEncoder::new_with_quality(&mut thumbnail, 75);
+        let c = if channels == 1 { color::ColorType::Gray(8) } else { color::ColorType::RGB(8) };
+        let _ = try!(encoder.encode(&samples, w, h, c));
+    }
+
+    Ok(thumbnail)
+}
+
+// Transforms the MCU at `(x0, y0)` of the 16 bit samples `image` of
+// `source` to 12 bit DCT blocks in the order of the scan, repeating the
+// last column and row past the edges
+fn transform_mcu_12bit(source: &Source, image: &[u16], x0: usize, y0: usize, blocks: &mut Vec<[i32; 64]>) {
+    let (mcu_width, mcu_height) = (8 * source.h, 8 * source.v);
+    let (width, height) = (source.width, image.len() / (source.width * source.bpp));
+    let mut planes = vec![vec![0u16; mcu_width * mcu_height]; if source.gray { 1 } else { 3 }];
+    let mut block = [0u16; 64];
+
+    for y in (0..mcu_height) {
+        for x in (0..mcu_width) {
+            let i = (cmp::min(y0 + y, height - 1) * width + cmp::min(x0 + x, width - 1)) * source.bpp;
+            let sample = |c: usize| (image[i + c] >> 4) as f32;
+
+            if source.gray {
+                planes[0][y * mcu_width + x] = image[i] >> 4;
+                continue
+            }
+
+            let (r, g, b) = (sample(0), sample(1), sample(2));
+            let ycbcr = [
+                 0.299f32  * r + 0.587f32  * g + 0.114f32  * b,
+                -0.1687f32 * r - 0.3313f32 * g + 0.5f32    * b + 2048f32,
+                 0.5f32    * r - 0.4187f32 * g - 0.0813f32 * b + 2048f32,
+            ];
+
+            for (plane, &v) in planes.iter_mut().zip(ycbcr.iter()) {
+                plane[y * mcu_width + x] = (v + 0.5).max(0.0).min(4095.0) as u16;
+            }
+        }
+    }
+
+    // The luma blocks, then the chroma blocks averaged over the samples of
+    // each luma block
+    for (c, plane) in planes.iter().enumerate() {
+        let (h, v) = if c == 0 { (1, 1) } else { (source.h, source.v) };
+
+        for by in (0..source.v / v) {
+            for bx in (0..source.h / h) {
+                let count = (h * v) as u32;
+
+                for y in (0usize..8) {
+                    for x in (0usize..8) {
+                        let mut sum = 0u32;
+                        for dy in (0..v) {
+                            for dx in (0..h) {
+                                sum += plane[(by * 8 + y * v + dy) * mcu_width + bx * 8 + x * h + dx] as u32;
+                            }
+                        }
+                        block[y * 8 + x] = ((sum + count / 2) / count) as u16;
+                    }
+                }
+
+                let mut coefficients = [0i32; 64];
+                transform::fdct_12bit(&block, &mut coefficients);
+                blocks.push(coefficients);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::cmp;
+    use std::f32;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    use super::{copy_blocks_gray, copy_blocks_ycbcr, BitWriter, JPEGEncoder, Subsampling};
+    use super::super::{Exif, JPEGDecoder, estimate_quality, read_thumbnail};
+    use color::ColorType;
+    use buffer::{GrayImage, ImageBuffer};
+    use color::Luma;
+    use config::SimdLevel;
+    use executor::{Executor, Sequential, StdThreads};
+    use image::{GenericImage, ImageDecoder, DecodingResult};
+
+    fn decode(decoder: &mut JPEGDecoder<&[u8]>) -> Vec<u8> {
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => data,
+            _ => panic!("unexpected sample type")
+        }
+    }
+
+    fn roundtrip(image: &[u8], threads: usize, executor: Arc<Executor>) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded);
+            encoder.set_threads(threads);
+            encoder.set_executor(executor);
+            encoder.encode(image, 40, 36, ColorType::RGB(8)).unwrap();
+        }
+
+        decode(&mut JPEGDecoder::new(&encoded[..]))
+    }
+
+    #[test]
+    fn test_quality() {
+        let image = (0..40 * 36 * 3).map(|i| (i * 7 % 251) as u8).collect::<Vec<u8>>();
+        let mut sizes = Vec::new();
+        let mut errors = Vec::new();
+
+        for &quality in [10u8, 50, 90].iter() {
+            let mut encoded = Vec::new();
+            JPEGEncoder::new_with_quality(&mut encoded, quality).encode(&image, 40, 36, ColorType::RGB(8)).unwrap();
+
+            let mut decoder = JPEGDecoder::new(&encoded[..]);
+            assert_eq!(estimate_quality(&decoder.quantization_tables().unwrap()), Some(quality));
+
+            let decoded = decode(&mut decoder);
+
+            sizes.push(encoded.len());
+            errors.push(image.iter().zip(decoded.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).sum::<i32>());
+        }
+
+        assert!(sizes[0] < sizes[1] && sizes[1] < sizes[2]);
+        assert!(errors[0] > errors[1] && errors[1] > errors[2]);
+    }
+
+    #[test]
+    fn test_subsampling() {
+        // A smooth gradient with a sharp red edge
+        let (width, height) = (37u32, 29u32);
+        let image = (0..width * height).flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            let red = if x > 20 { 255 } else { (x * 6) as u8 };
+            vec![red, (y * 8) as u8, 100]
+        }).collect::<Vec<u8>>();
+
+        let mut sizes = Vec::new();
+
+        for &(subsampling, h, v) in [(Subsampling::Ratio444, 1, 1),
+                                     (Subsampling::Ratio422, 2, 1),
+                                     (Subsampling::Ratio420, 2, 2)].iter() {
+            for &threads in [1, 2].iter() {
+                let mut encoded = Vec::new();
+                {
+                    let mut encoder = JPEGEncoder::new(&mut encoded);
+                    encoder.set_subsampling(subsampling);
+                    encoder.set_threads(threads);
+                    encoder.encode(&image, width, height, ColorType::RGB(8)).unwrap();
+                }
+
+                let mut decoder = JPEGDecoder::new(&encoded[..]);
+                let coefficients = decoder.read_coefficients().unwrap();
+                assert_eq!((coefficients.components[0].h, coefficients.components[0].v), (h, v));
+                assert_eq!((coefficients.components[1].h, coefficients.components[1].v), (1, 1));
+
+                let decoded = decode(&mut JPEGDecoder::new(&encoded[..]));
+
+                assert_eq!(decoded.len(), image.len());
+                let error = image.iter().zip(decoded.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).sum::<i32>();
+                assert!(error < 12 * image.len() as i32, "{:?}: mean error {}", subsampling, error / image.len() as i32);
+
+                if threads == 1 {
+                    sizes.push(encoded.len());
+                }
+            }
+        }
+
+        assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2]);
+    }
+
+    #[test]
+    fn test_parallel_encode() {
+        let image = (0..40 * 36 * 3).map(|i| (i * 7 % 251) as u8).collect::<Vec<u8>>();
+
+        let expected = roundtrip(&image, 1, Arc::new(StdThreads));
+
+        assert_eq!(roundtrip(&image, 3, Arc::new(StdThreads)), expected);
+        assert_eq!(roundtrip(&image, 3, Arc::new(Sequential)), expected);
+    }
+
+    #[test]
+    fn test_pad_byte() {
+        let mut writer = BitWriter::new(Vec::new(), false);
+        writer.pad_byte().unwrap();
+        assert!(writer.w.is_empty());
+
+        writer.write_bits(0b101, 3).unwrap();
+        writer.pad_byte().unwrap();
+        writer.write_bits(0b0, 1).unwrap();
+        writer.pad_byte().unwrap();
+        assert_eq!(writer.w, vec![0b1011_1111, 0b0111_1111]);
+        assert_eq!(writer.nbits, 0);
+    }
+
+    #[test]
+    fn test_restart_interval() {
+        let image = (0..48 * 38 * 3).map(|i| (i * 7 % 251) as u8).collect::<Vec<u8>>();
+        let mut expected = None;
+
+        // 6 MCUs per row and 5 rows
+        for &(interval, threads, markers) in [(0, 1, 0), (1, 1, 29), (4, 1, 7), (4, 2, 7),
+                                              (3, 2, 9), (0, 2, 4)].iter() {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new(&mut encoded);
+                encoder.set_restart_interval(interval);
+                encoder.set_threads(threads);
+                encoder.encode(&image, 48, 38, ColorType::RGB(8)).unwrap();
+            }
+
+            // The markers cycle through RST0 to RST7
+            let found = encoded.windows(2).filter(|w| w[0] == 0xFF && w[1] >= 0xD0 && w[1] <= 0xD7)
+                                          .map(|w| w[1] - 0xD0).collect::<Vec<u8>>();
+            assert_eq!(found, (0..markers).map(|i| (i % 8) as u8).collect::<Vec<u8>>());
+
+            let decoded = decode(&mut JPEGDecoder::new(&encoded[..]));
+
+            match expected {
+                None => expected = Some(decoded),
+                Some(ref expected) => assert!(decoded == *expected, "interval {}", interval),
+            }
+        }
+    }
+
+    #[test]
+    fn test_exif() {
+        let image = vec![100u8; 16 * 8];
+        let exif = Exif { orientation: Some(3), ..Exif::default() };
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded);
+            encoder.set_exif(&exif);
+            encoder.encode(&image, 16, 8, ColorType::Gray(8)).unwrap();
+        }
+
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        let segments = decoder.marker_segments().unwrap().to_vec();
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[1].marker, &segments[1].data), (0xE1, &exif.to_bytes()));
+
+        // The segment is copied unchanged
+        let mut copied = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut copied);
+            encoder.set_exif_data(&segments[1].data);
+            encoder.encode(&image, 16, 8, ColorType::Gray(8)).unwrap();
+        }
+        assert_eq!(copied, encoded);
+
+        let mut encoder = JPEGEncoder::new(&mut copied);
+        encoder.set_exif_data(&vec![0; 65534]);
+        assert!(encoder.encode(&image, 16, 8, ColorType::Gray(8)).is_err());
+    }
+
+    #[test]
+    fn test_icc_profile() {
+        let image = vec![100u8; 8 * 8];
+        let profile = (0..65519 + 100).map(|i| (i % 253) as u8).collect::<Vec<u8>>();
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded);
+            encoder.set_icc_profile(&profile);
+            encoder.encode(&image, 8, 8, ColorType::Gray(8)).unwrap();
+        }
+
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        let segments = decoder.marker_segments().unwrap().iter()
+                              .filter(|s| s.marker == 0xE2)
+                              .cloned().collect::<Vec<_>>();
+        assert_eq!(segments.len(), 2);
+
+        let mut joined = Vec::new();
+        for (i, segment) in segments.iter().enumerate() {
+            assert_eq!(&segment.data[..12], b"ICC_PROFILE\0");
+            assert_eq!((segment.data[12], segment.data[13]), (i as u8 + 1, 2));
+            joined.extend(segment.data[14..].iter().cloned());
+        }
+
+        assert_eq!(segments[0].data.len(), 65533);
+        assert!(joined == profile);
+    }
+
+    #[test]
+    fn test_c2pa_manifest() {
+        let image = vec![100u8; 8 * 8];
+
+        // A superbox with a description box labelled c2pa and some content
+        let mut manifest = b"\0\0\0\x64jumb\0\0\0\x1Ejumdc2pa\0\x11\0\x10\x80\0\0\xAA\0\x38\x9B\x71\x03c2pa\0".to_vec();
+        manifest.extend((0..62).map(|i| i as u8));
+        assert_eq!(manifest.len(), 0x64);
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded);
+            encoder.set_c2pa_manifest(&manifest);
+            encoder.encode(&image, 8, 8, ColorType::Gray(8)).unwrap();
+        }
+
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        assert!(decoder.c2pa_manifest().unwrap() == Some(manifest));
+        assert!(decoder.read_image().is_ok());
+
+        let mut rejected = Vec::new();
+        let mut encoder = JPEGEncoder::new(&mut rejected);
+        encoder.set_c2pa_manifest(b"not a box");
+        assert!(encoder.encode(&image, 8, 8, ColorType::Gray(8)).is_err());
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let image = (0..64 * 32 * 4).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+
+        for exif in [None, Some(Exif { orientation: Some(6), ..Exif::default() })].iter() {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new(&mut encoded);
+                if let Some(ref exif) = *exif {
+                    encoder.set_exif(exif);
+                }
+                encoder.set_thumbnail(16);
+                encoder.encode(&image, 64, 32, ColorType::RGBA(8)).unwrap();
+            }
+
+            let thumbnail = read_thumbnail(&encoded[..]).unwrap().unwrap();
+            assert_eq!(thumbnail.dimensions(), (16, 8));
+
+            let mut decoder = JPEGDecoder::new(&encoded[..]);
+            assert_eq!(decoder.exif_orientation().unwrap(), exif.as_ref().and_then(|e| e.orientation));
+            assert!(decoder.read_image().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let image = vec![100u8; 8 * 8];
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded);
+            encoder.add_comment("built by pipeline 42");
+            encoder.add_comment("commit 0123abc");
+            encoder.encode(&image, 8, 8, ColorType::Gray(8)).unwrap();
+        }
+
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        assert_eq!(decoder.comments().unwrap(), vec!["built by pipeline 42".to_string(),
+                                                     "commit 0123abc".to_string()]);
+        assert!(decoder.read_image().is_ok());
+
+        let mut rejected = Vec::new();
+        let mut encoder = JPEGEncoder::new(&mut rejected);
+        encoder.add_comment(&"x".repeat(65534));
+        assert!(encoder.encode(&image, 8, 8, ColorType::Gray(8)).is_err());
+    }
+
+    #[test]
+    fn test_quantization_tables() {
+        let image = (0..24 * 16 * 3).map(|i| (i * 7 % 251) as u8).collect::<Vec<u8>>();
+        let luma = {
+            let mut t = [0u8; 64];
+            for (i, q) in t.iter_mut().enumerate() { *q = 2 + i as u8 }
+            t
+        };
+        let chroma = [40u8; 64];
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded);
+            encoder.set_quantization_tables(&luma, &chroma);
+            encoder.encode(&image, 24, 16, ColorType::RGB(8)).unwrap();
+        }
+
+        let mut decoder = JPEGDecoder::new(&encoded[..]);
+        let tables = decoder.quantization_tables().unwrap();
+        let widen = |t: &[u8; 64]| t.iter().map(|&q| q as u16).collect::<Vec<u16>>();
+        assert_eq!(tables[0].unwrap().to_vec(), widen(&luma));
+        assert_eq!(tables[1].unwrap().to_vec(), widen(&chroma));
+        assert!(decoder.read_image().is_ok());
+    }
+
+    #[test]
+    fn test_gray() {
+        let (width, height) = (21u32, 11u32);
+        let gray = (0..width * height).map(|i| (i % width * 10 + i / width * 3) as u8).collect::<Vec<u8>>();
+        let gray_alpha = gray.iter().flat_map(|&g| vec![g, 255 - g].into_iter()).collect::<Vec<u8>>();
+
+        let mut results = Vec::new();
+        for &(image, color) in [(&gray, ColorType::Gray(8)), (&gray_alpha, ColorType::GrayA(8))].iter() {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new(&mut encoded);
+                encoder.encode(image, width, height, color).unwrap();
+            }
+
+            let mut decoder = JPEGDecoder::new(&encoded[..]);
+            assert_eq!(decoder.colortype().unwrap(), ColorType::Gray(8));
+            let decoded = decode(&mut decoder);
+
+            let error = decoded.iter().zip(gray.iter())
+                               .map(|(&a, &b)| (a as i32 - b as i32).abs())
+                               .max().unwrap();
+            assert!(error <= 8, "{:?}: maximum error {}", color, error);
+            results.push(encoded);
+        }
+
+        // The alpha channel is ignored
+        assert!(results[0] == results[1]);
+
+        let mut encoder = JPEGEncoder::new(&mut results[0]);
+        assert!(encoder.encode(&gray[1..], width, height, ColorType::Gray(8)).is_err());
+    }
+
+    #[test]
+    fn test_gray_edges() {
+        // The last block of a 21x11 image reaches past both edges
+        let (width, height) = (21usize, 11usize);
+        let gray = (0..width * height).map(|i| (i * 37 % 256) as u8).collect::<Vec<u8>>();
+        let rgb = gray.iter().flat_map(|&g| vec![g, g, g].into_iter()).collect::<Vec<u8>>();
+
+        let mut gb = [0u8; 64];
+        copy_blocks_gray(&gray, 16, 8, width, 1, &mut gb);
+
+        let (mut yb, mut cbb, mut crb) = ([0u8; 64], [0u8; 64], [0u8; 64]);
+        copy_blocks_ycbcr(&rgb, 16, 8, width, 3, 8, 8, SimdLevel::Scalar, &mut yb, &mut cbb, &mut crb);
+
+        // Both repeat the last column and row
+        for y in (0..8) {
+            for x in (0..8) {
+                let expected = gray[cmp::min(8 + y, height - 1) * width + cmp::min(16 + x, width - 1)];
+                assert_eq!(gb[y * 8 + x], expected);
+                assert!(yb[y * 8 + x] + 1 >= expected && yb[y * 8 + x] <= expected, "{:?}", (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_quality_map() {
+        let (width, height) = (64u32, 32u32);
+        let image = (0..width * height * 3).map(|i| (i * 29 % 256 ^ i / 5) as u8).collect::<Vec<u8>>();
+
+        let encode = |map: Option<&GrayImage>| {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new_with_quality(&mut encoded, 90);
+                if let Some(map) = map {
+                    encoder.set_quality_map(map);
+                }
+                encoder.encode(&image, width, height, ColorType::RGB(8)).unwrap();
+            }
+
+            let decoded = decode(&mut JPEGDecoder::new(&encoded[..]));
+            (encoded.len(), decoded)
+        };
+
+        // The left half at the quality of the encoder, the right half lower
+        let map: GrayImage = ImageBuffer::from_fn(2, 1, |x, _| Luma([if x == 0 { 90 } else { 20 }]));
+        let (plain_len, plain) = encode(None);
+        let (mapped_len, mapped) = encode(Some(&map));
+        assert!(mapped_len < plain_len * 5 / 6);
+
+        let error = |decoded: &[u8], x0: u32| {
+            (0..height).flat_map(|y| (x0..x0 + width / 2).map(move |x| (y * width + x) as usize * 3))
+                       .map(|i| (decoded[i] as i32 - image[i] as i32).abs())
+                       .sum::<i32>()
+        };
+        assert!((0..height).all(|y| {
+            let row = (y * width * 3) as usize;
+            plain[row..row + width as usize * 3 / 2] == mapped[row..row + width as usize * 3 / 2]
+        }));
+        assert!(error(&mapped, width / 2) > 2 * error(&plain, width / 2));
+    }
+
+    #[test]
+    fn test_target_size() {
+        let image = (0..48 * 40 * 3).map(|i| (i * 13 % 256 ^ i / 7) as u8).collect::<Vec<u8>>();
+
+        let encode = |quality: u8, target: Option<usize>| {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new_with_quality(&mut encoded, quality);
+                encoder.set_subsampling(Subsampling::Ratio420);
+                if let Some(bytes) = target {
+                    encoder.set_target_size(bytes);
+                }
+                try!(encoder.encode(&image, 48, 40, ColorType::RGB(8)));
+            }
+            Ok::<_, io::Error>(encoded)
+        };
+
+        let target = encode(60, None).unwrap().len();
+        let encoded = encode(100, Some(target)).unwrap();
+        assert!(encoded.len() <= target);
+
+        // The same output as encoding at the quality found from scratch
+        let quality = estimate_quality(&JPEGDecoder::new(&encoded[..]).quantization_tables().unwrap()).unwrap();
+        assert!(quality >= 60);
+        assert!(encode(quality, None).unwrap() == encoded);
+        assert!(encode(quality + 1, None).unwrap().len() > target);
+
+        assert!(encode(100, Some(100)).is_err());
+    }
+
+    #[test]
+    fn test_12bit() {
+        let (width, height) = (40u32, 24u32);
+        let image = (0..width * height * 3).map(|i| (i * 997 % 65536) as u16).collect::<Vec<u16>>();
+
+        let encode = |arithmetic: bool| {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new_with_quality(&mut encoded, 90);
+                encoder.set_subsampling(Subsampling::Ratio420);
+                encoder.set_arithmetic_coding(arithmetic);
+                encoder.encode_12bit(&image, width, height, ColorType::RGB(16)).unwrap();
+            }
+            encoded
+        };
+
+        // Extended sequential frames of 12 bit precision
+        for &(arithmetic, sof) in [(false, 0xC1u8), (true, 0xC9)].iter() {
+            let encoded = encode(arithmetic);
+            let frame = encoded.windows(2).position(|m| m == [0xFF, sof]).unwrap();
+            assert_eq!(encoded[frame + 4], 12);
+            assert!(encoded.ends_with(&[0xFF, 0xD9]));
+        }
+
+        let mut encoded = Vec::new();
+        assert!(JPEGEncoder::new(&mut encoded).encode_12bit(&image, width, height, ColorType::RGB(8)).is_err());
+        assert!(JPEGEncoder::new(&mut encoded).encode_12bit(&image[1..], width, height, ColorType::RGB(16)).is_err());
+    }
+
+    #[test]
+    fn test_12bit_round_trip() {
+        let (width, height) = (21u32, 13u32);
+        let image = (0..width * height * 3).map(|i| {
+            let (x, y, c) = (i / 3 % width, i / 3 / width, i % 3);
+            (x * 1500 + y * 1200 + c * 5000) as u16
+        }).collect::<Vec<u16>>();
+
+        for &(c, tolerance) in [(ColorType::Gray(16), 2f32), (ColorType::RGB(16), 4f32)].iter() {
+            let bpp = if c == ColorType::Gray(16) { 1 } else { 3 };
+            let samples = image.chunks(3).flat_map(|p| p[..bpp].iter().cloned()).collect::<Vec<u16>>();
+
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new_with_quality(&mut encoded, 100);
+                encoder.set_subsampling(Subsampling::Ratio444);
+                encoder.encode_12bit(&samples, width, height, c).unwrap();
+            }
+
+            // The decoder reads the coefficients of 12 bit frames once they
+            // are marked as baseline frames of 8 bit precision
+            let frame = encoded.windows(2).position(|m| m == [0xFF, 0xC1]).unwrap();
+            encoded[frame + 1] = 0xC0;
+            encoded[frame + 4] = 8;
+            let coefficients = JPEGDecoder::new(&encoded[..]).read_coefficients().unwrap();
+
+            let planes = coefficients.components.iter().map(|component| {
+                (0..width * height).map(|i| {
+                    let (x, y) = (i % width, i / width);
+                    let block = component.dequantized(x / 8, y / 8);
+                    idct_sample(&block, (x % 8) as usize, (y % 8) as usize) + 2048.0
+                }).collect::<Vec<f32>>()
+            }).collect::<Vec<Vec<f32>>>();
+
+            for (i, &sample) in samples.iter().enumerate() {
+                let (p, channel) = (i / bpp, i % bpp);
+                let decoded = if bpp == 1 { planes[0][p] } else {
+                    let (y, cb, cr) = (planes[0][p], planes[1][p] - 2048.0, planes[2][p] - 2048.0);
+                    [y + 1.402 * cr, y - 0.344136 * cb - 0.714136 * cr, y + 1.772 * cb][channel]
+                };
+
+                assert!((decoded - (sample >> 4) as f32).abs() <= tolerance,
+                        "sample {} of {:?}: {} instead of {}", i, c, decoded, sample >> 4);
+            }
+        }
+    }
+
+    // The sample at `(x, y)` of the inverse DCT of the dequantized
+    // coefficients `block`, in natural order
+    fn idct_sample(block: &[i32; 64], x: usize, y: usize) -> f32 {
+        let scale = |u: usize| if u == 0 { f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+        let mut sum = 0f32;
+
+        for v in (0usize..8) {
+            for u in (0usize..8) {
+                sum += scale(u) * scale(v) * block[v * 8 + u] as f32
+                     * ((2 * x + 1) as f32 * u as f32 * f32::consts::PI / 16.0).cos()
+                     * ((2 * y + 1) as f32 * v as f32 * f32::consts::PI / 16.0).cos();
+            }
+        }
+
+        sum / 4.0
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let (width, height) = (45u32, 37u32);
+        let image = (0..width * height * 3).map(|i| (i * 13 % 256 ^ i / 11) as u8).collect::<Vec<u8>>();
+        let stride = width as usize * 3;
+
+        for &(subsampling, interval) in [(Subsampling::Ratio444, 0), (Subsampling::Ratio420, 2)].iter() {
+            let mut expected = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new(&mut expected);
+                encoder.set_subsampling(subsampling);
+                encoder.set_restart_interval(interval);
+                encoder.encode(&image, width, height, ColorType::RGB(8)).unwrap();
+            }
+
+            let sink = Sink(Rc::new(RefCell::new(Vec::new())));
+            let mut out = sink.clone();
+            let mut encoder = JPEGEncoder::new(&mut out);
+            encoder.set_subsampling(subsampling);
+            encoder.set_restart_interval(interval);
+
+            let mut scanlines = encoder.encode_scanlines(width, height, ColorType::RGB(8)).unwrap();
+            let mut sizes = Vec::new();
+            for rows in image.chunks(5 * stride) {
+                scanlines.write_scanlines(rows).unwrap();
+                sizes.push(sink.0.borrow().len());
+            }
+            assert!(scanlines.write_scanlines(&image[..stride]).is_err());
+            scanlines.finish().unwrap();
+
+            // The data is written row of MCUs by row
+            assert!(sizes[0] < sizes[sizes.len() / 2] && sizes[sizes.len() / 2] < sizes[sizes.len() - 1]);
+            assert!(*sink.0.borrow() == expected);
+        }
+
+        let mut encoded = Vec::new();
+        let mut encoder = JPEGEncoder::new(&mut encoded);
+        let mut scanlines = encoder.encode_scanlines(width, height, ColorType::RGB(8)).unwrap();
+        assert!(scanlines.write_scanlines(&image[1..stride]).is_err());
+        scanlines.write_scanlines(&image[..stride]).unwrap();
+        assert_eq!(scanlines.remaining(), height - 1);
+        assert!(scanlines.finish().is_err());
+    }
+
+    // A sink whose contents can be read while an encoder writes to it
+    #[derive(Clone)]
+    struct Sink(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_simd_levels() {
+        // Odd dimensions, so some rows of MCUs reach past the image
+        let (width, height) = (37u32, 19u32);
+        let image = (0..width * height * 4).map(|i| (i * 37 % 256 ^ i / 7) as u8).collect::<Vec<u8>>();
+
+        for &(subsampling, color) in [(Subsampling::Ratio444, ColorType::RGBA(8)),
+                                      (Subsampling::Ratio420, ColorType::RGB(8))].iter() {
+            let results = [SimdLevel::Scalar, SimdLevel::Sse2, SimdLevel::Avx2].iter().map(|&level| {
+                let mut encoded = Vec::new();
+                {
+                    let mut encoder = JPEGEncoder::new(&mut encoded);
+                    encoder.set_subsampling(subsampling);
+                    encoder.set_simd_level(level);
+                    encoder.encode(&image, width, height, color).unwrap();
+                }
+                encoded
+            }).collect::<Vec<_>>();
+
+            assert!(results[0] == results[1] && results[0] == results[2]);
+        }
+    }
+
+    #[test]
+    fn test_trellis_quantization() {
+        let (width, height) = (64u32, 48u32);
+        let image = (0..width * height).flat_map(|i| {
+            let (x, y) = ((i % width) as f32, (i / width) as f32);
+            let noise = (i.wrapping_mul(2654435761) >> 28) as f32;
+            let r = 128.0 + 60.0 * (x / 7.0).sin() + noise;
+            let g = 2.0 * y + 30.0 * (x * y / 200.0).cos();
+            let b = 255.0 - 2.0 * x - noise;
+            vec![r as u8, g as u8, b as u8]
+        }).collect::<Vec<u8>>();
+
+        let mut results = Vec::new();
+        for &trellis in [false, true].iter() {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new_with_quality(&mut encoded, 85);
+                encoder.set_trellis_quantization(trellis);
+                encoder.set_optimize_coding(true);
+                encoder.encode(&image, width, height, ColorType::RGB(8)).unwrap();
+            }
+
+            let decoded = decode(&mut JPEGDecoder::new(&encoded[..]));
+
+            let error = decoded.iter().zip(image.iter())
+                               .fold(0.0, |sum, (&a, &b)| sum + (a as f64 - b as f64) * (a as f64 - b as f64))
+                        / image.len() as f64;
+            results.push((encoded.len(), error));
+        }
+
+        // Smaller at almost the same error
+        assert!(results[1].0 * 100 < results[0].0 * 95, "{:?}", results);
+        assert!(results[1].1 < results[0].1 * 1.1, "{:?}", results);
+    }
+
+    #[test]
+    fn test_arithmetic_coding() {
+        let (width, height) = (45u32, 38u32);
+        let image = (0..width * height).flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            vec![(x * 5) as u8, (y * 6) as u8, ((x + y) % 7 * 30) as u8]
+        }).collect::<Vec<u8>>();
+
+        let mut results = Vec::new();
+        for &(arithmetic, threads) in [(false, 1), (true, 1), (true, 3)].iter() {
+            let mut encoded = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new(&mut encoded);
+                encoder.set_threads(threads);
+                encoder.set_arithmetic_coding(arithmetic);
+                encoder.encode(&image, width, height, ColorType::RGB(8)).unwrap();
+            }
+            results.push(encoded);
+        }
+
+        // An extended sequential frame without Huffman tables. The coding of
+        // the blocks is checked by the round trip in the arithmetic module.
+        let markers = |data: &[u8]| data.windows(2).filter(|w| w[0] == 0xFF && w[1] >= 0xC0)
+                                        .map(|w| w[1]).collect::<Vec<u8>>();
+        assert_eq!(markers(&results[1]), vec![0xD8, 0xE0, 0xC9, 0xDB, 0xDB, 0xDA, 0xD9]);
+        assert!(results[1].len() * 100 < results[0].len() * 95, "{} {}", results[1].len(), results[0].len());
+
+        // The rows are coded in separate restart intervals by several threads
+        assert!(markers(&results[2]).iter().any(|&m| m == 0xDD));
+        assert!(markers(&results[2]).iter().filter(|&&m| m & 0xF8 == 0xD0).count() == 4);
+
+        // Arithmetic decoding is not supported
+        assert!(JPEGDecoder::new(&results[1][..]).read_image().is_err());
+    }
+
+    #[test]
+    fn test_optimize_coding() {
+        let (width, height) = (45u32, 38u32);
+        let rgb = (0..width * height).flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            vec![(x * 5) as u8, (y * 6) as u8, ((x + y) % 7 * 30) as u8]
+        }).collect::<Vec<u8>>();
+        let gray = (0..width * height).map(|i| (i * 7 % 251) as u8).collect::<Vec<u8>>();
+
+        for &(image, color) in [(&rgb, ColorType::RGB(8)), (&gray, ColorType::Gray(8))].iter() {
+            for &threads in [1, 2].iter() {
+                let mut results = Vec::new();
+
+                for &optimize in [false, true].iter() {
+                    let mut encoded = Vec::new();
+                    {
+                        let mut encoder = JPEGEncoder::new(&mut encoded);
+                        encoder.set_threads(threads);
+                        encoder.set_subsampling(Subsampling::Ratio420);
+                        encoder.set_optimize_coding(optimize);
+                        encoder.encode(image, width, height, color).unwrap();
+                    }
+
+                    let decoded = decode(&mut JPEGDecoder::new(&encoded[..]));
+
+                    results.push((encoded.len(), decoded));
+                }
+
+                // The same coefficients in fewer bytes
+                assert!(results[1].0 < results[0].0, "{:?}", (results[0].0, results[1].0));
+                assert!(results[1].1 == results[0].1);
+            }
         }
     }
 }
@@ -1,6 +1,6 @@
 use std::iter::repeat;
 use std::io::Read;
-use byteorder::ReadBytesExt;
+use byteorder::{self, ReadBytesExt};
 
 use image;
 use image::ImageResult;
@@ -19,6 +19,10 @@ pub struct HuffDecoder {
     pub num_bits: u8,
     pub end: bool,
     pub marker: u8,
+    // Whether the data ended without a marker, and whether more bits were
+    // consumed than it held
+    pub eof: bool,
+    pub overrun: bool,
 }
 
 impl HuffDecoder {
@@ -27,16 +31,37 @@ impl HuffDecoder {
             bits: 0,
             num_bits: 0,
             end: false,
-            marker: 0
+            marker: 0,
+            eof: false,
+            overrun: false,
         }
     }
 
     fn guarantee<R: Read>(&mut self, r: &mut R, n: u8) -> ImageResult<()> {
         while self.num_bits < n && !self.end {
-            let byte = try!(r.read_u8());
+            // Files often end right after the last MCU without an EOI
+            // marker, the missing bits are only an error if they are used
+            let byte = match r.read_u8() {
+                Ok(byte) => byte,
+                Err(byteorder::Error::UnexpectedEOF) => {
+                    self.end = true;
+                    self.eof = true;
+                    break
+                }
+                Err(e) => return Err(e.into())
+            };
 
             if byte == 0xFF {
-                let byte2 = try!(r.read_u8());
+                let byte2 = match r.read_u8() {
+                    Ok(byte2) => byte2,
+                    // A fill byte at the end of the data
+                    Err(byteorder::Error::UnexpectedEOF) => {
+                        self.end = true;
+                        self.eof = true;
+                        break
+                    }
+                    Err(e) => return Err(e.into())
+                };
                 if byte2 != 0 {
                     self.marker = byte2;
                     self.end = true;
@@ -69,8 +94,13 @@ impl HuffDecoder {
     }
 
     fn consume(&mut self, n: u8) {
+        if self.eof && n > self.num_bits {
+            self.overrun = true;
+        }
+
         self.bits <<= n as usize;
-        self.num_bits -= n;
+        // After a marker only zero bits are left to consume
+        self.num_bits = self.num_bits.saturating_sub(n);
     }
 
     pub fn decode_symbol<R: Read>(&mut self, r: &mut R, table: &HuffTable) -> ImageResult<u8> {
@@ -0,0 +1,573 @@
+//! Reading and writing of EXIF metadata
+//!
+//! The metadata is stored as a TIFF structure in an APP1 segment after the
+//! JFIF header. IFD0 holds the orientation and the modification time and
+//! points to the EXIF IFD, with the time the picture was taken, and to the
+//! GPS IFD. IFD1, which follows IFD0, describes a JPEG thumbnail stored
+//! after it.
+//!
+//! See CIPA DC-008, "Exchangeable image file format for digital still cameras"
+
+use std::cmp;
+
+use byteorder::{WriteBytesExt, BigEndian};
+
+use image::{ImageError, ImageResult};
+
+use super::thumbnail::Tiff;
+
+// Tags of IFD0
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+
+// Tags of IFD1
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_THUMBNAIL_OFFSET: u16 = 0x0201;
+const TAG_THUMBNAIL_LENGTH: u16 = 0x0202;
+
+// The compression of JPEG thumbnails
+const COMPRESSION_JPEG: u16 = 6;
+
+// Tags of the EXIF IFD
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_INTEROPERABILITY_IFD: u16 = 0xA005;
+
+// Tags of the GPS IFD
+const TAG_GPS_VERSION: u16 = 0x0000;
+const TAG_GPS_LATITUDE_REF: u16 = 0x0001;
+const TAG_GPS_LATITUDE: u16 = 0x0002;
+const TAG_GPS_LONGITUDE_REF: u16 = 0x0003;
+const TAG_GPS_LONGITUDE: u16 = 0x0004;
+const TAG_GPS_ALTITUDE_REF: u16 = 0x0005;
+const TAG_GPS_ALTITUDE: u16 = 0x0006;
+
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+
+/// The position where a picture was taken
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GpsPosition {
+    /// The latitude in degrees, positive to the north
+    pub latitude: f64,
+
+    /// The longitude in degrees, positive to the east
+    pub longitude: f64,
+
+    /// The altitude in meters above sea level
+    pub altitude: Option<f64>,
+}
+
+/// EXIF metadata, as written by ```JPEGEncoder::set_exif```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Exif {
+    /// How the image has to be transformed to be displayed upright, from
+    /// 1 for not at all to 8 as defined by TIFF
+    pub orientation: Option<u16>,
+
+    /// When the image was last changed, as ```YYYY:MM:DD HH:MM:SS```
+    pub date_time: Option<String>,
+
+    /// When the picture was taken, as ```YYYY:MM:DD HH:MM:SS```
+    pub date_time_original: Option<String>,
+
+    /// Where the picture was taken
+    pub gps: Option<GpsPosition>,
+}
+
+impl Exif {
+    /// Serializes the metadata to the content of an APP1 segment
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ifd0 = Vec::new();
+        let mut exif = Vec::new();
+        let mut gps = Vec::new();
+
+        if let Some(orientation) = self.orientation {
+            ifd0.push(Entry::short(TAG_ORIENTATION, orientation));
+        }
+        if let Some(ref date_time) = self.date_time {
+            ifd0.push(Entry::ascii(TAG_DATE_TIME, date_time));
+        }
+        if let Some(ref date_time) = self.date_time_original {
+            exif.push(Entry::ascii(TAG_DATE_TIME_ORIGINAL, date_time));
+        }
+
+        if let Some(position) = self.gps {
+            let reference = |v: f64, positive: &str, negative: &str| {
+                if v < 0.0 { negative.to_string() } else { positive.to_string() }
+            };
+
+            gps.push(Entry { tag: TAG_GPS_VERSION, type_: BYTE, count: 4, data: vec![2, 3, 0, 0] });
+            gps.push(Entry::ascii(TAG_GPS_LATITUDE_REF, &reference(position.latitude, "N", "S")));
+            gps.push(Entry::rationals(TAG_GPS_LATITUDE, &degrees(position.latitude)));
+            gps.push(Entry::ascii(TAG_GPS_LONGITUDE_REF, &reference(position.longitude, "E", "W")));
+            gps.push(Entry::rationals(TAG_GPS_LONGITUDE, &degrees(position.longitude)));
+
+            if let Some(altitude) = position.altitude {
+                let below = if altitude < 0.0 { 1 } else { 0 };
+                gps.push(Entry { tag: TAG_GPS_ALTITUDE_REF, type_: BYTE, count: 1, data: vec![below] });
+                gps.push(Entry::rationals(TAG_GPS_ALTITUDE, &[(altitude.abs() * 100.0).round() as u32, 100]));
+            }
+        }
+
+        // The pointers to the sub IFDs follow the other entries of IFD0
+        if !exif.is_empty() {
+            ifd0.push(Entry::long(TAG_EXIF_IFD, 0));
+        }
+        if !gps.is_empty() {
+            ifd0.push(Entry::long(TAG_GPS_IFD, 0));
+        }
+
+        let exif_offset = 8 + ifd_len(&ifd0);
+        let gps_offset = exif_offset + if exif.is_empty() { 0 } else { ifd_len(&exif) };
+
+        for entry in ifd0.iter_mut() {
+            match entry.tag {
+                TAG_EXIF_IFD => *entry = Entry::long(TAG_EXIF_IFD, exif_offset),
+                TAG_GPS_IFD => *entry = Entry::long(TAG_GPS_IFD, gps_offset),
+                _ => ()
+            }
+        }
+
+        let mut buf = b"Exif\0\0MM".to_vec();
+        let _ = buf.write_u16::<BigEndian>(42);
+        let _ = buf.write_u32::<BigEndian>(8);
+
+        write_ifd(&mut buf, &ifd0, 8);
+        if !exif.is_empty() {
+            write_ifd(&mut buf, &exif, exif_offset);
+        }
+        if !gps.is_empty() {
+            write_ifd(&mut buf, &gps, gps_offset);
+        }
+
+        buf
+    }
+}
+
+/// Reads the orientation from IFD0 of the EXIF APP1 segment ```segment```
+pub fn read_orientation(segment: &[u8]) -> ImageResult<Option<u16>> {
+    if !segment.starts_with(b"Exif\0\0") {
+        return Ok(None)
+    }
+
+    let tiff = Tiff::new(&segment[6..]);
+    let ifd0 = try!(tiff.u32(4)) as usize;
+
+    for i in (0..try!(tiff.u16(ifd0)) as usize) {
+        let entry = ifd0 + 2 + 12 * i;
+
+        if try!(tiff.u16(entry)) == TAG_ORIENTATION {
+            return tiff.u16(entry + 8).map(Some)
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the metadata of ```Exif``` from the EXIF APP1 segment ```segment```.
+/// Returns ```None``` if it is not an EXIF segment.
+pub fn read_exif(segment: &[u8]) -> ImageResult<Option<Exif>> {
+    if !segment.starts_with(b"Exif\0\0") {
+        return Ok(None)
+    }
+
+    let tiff = Tiff::new(&segment[6..]);
+    let mut exif = Exif::default();
+    let mut sub_ifds = (None, None);
+
+    for entry in try!(entries(&tiff, try!(tiff.u32(4)) as usize)) {
+        match try!(tiff.u16(entry)) {
+            TAG_ORIENTATION => exif.orientation = Some(try!(tiff.u16(entry + 8))),
+            TAG_DATE_TIME => exif.date_time = Some(try!(read_ascii(&tiff, entry))),
+            TAG_EXIF_IFD => sub_ifds.0 = Some(try!(tiff.u32(entry + 8)) as usize),
+            TAG_GPS_IFD => sub_ifds.1 = Some(try!(tiff.u32(entry + 8)) as usize),
+            _ => ()
+        }
+    }
+
+    if let Some(ifd) = sub_ifds.0 {
+        for entry in try!(entries(&tiff, ifd)) {
+            if try!(tiff.u16(entry)) == TAG_DATE_TIME_ORIGINAL {
+                exif.date_time_original = Some(try!(read_ascii(&tiff, entry)));
+            }
+        }
+    }
+
+    if let Some(ifd) = sub_ifds.1 {
+        let (mut latitude, mut longitude, mut altitude) = (None, None, None);
+        let mut signs = (1.0, 1.0, 1.0);
+
+        for entry in try!(entries(&tiff, ifd)) {
+            match try!(tiff.u16(entry)) {
+                TAG_GPS_LATITUDE_REF if try!(read_ascii(&tiff, entry)) == "S" => signs.0 = -1.0,
+                TAG_GPS_LONGITUDE_REF if try!(read_ascii(&tiff, entry)) == "W" => signs.1 = -1.0,
+                TAG_GPS_ALTITUDE_REF if try!(tiff.slice(entry + 8, 1))[0] == 1 => signs.2 = -1.0,
+                TAG_GPS_LATITUDE => latitude = Some(try!(read_angle(&tiff, entry))),
+                TAG_GPS_LONGITUDE => longitude = Some(try!(read_angle(&tiff, entry))),
+                TAG_GPS_ALTITUDE => altitude = Some(try!(read_rational(&tiff, entry, 0))),
+                _ => ()
+            }
+        }
+
+        if let (Some(latitude), Some(longitude)) = (latitude, longitude) {
+            exif.gps = Some(GpsPosition {
+                latitude: signs.0 * latitude,
+                longitude: signs.1 * longitude,
+                altitude: altitude.map(|a| signs.2 * a),
+            });
+        }
+    }
+
+    Ok(Some(exif))
+}
+
+// The offsets of the entries of the IFD at `ifd`
+fn entries(tiff: &Tiff, ifd: usize) -> ImageResult<Vec<usize>> {
+    let count = try!(tiff.u16(ifd)) as usize;
+    Ok((0..count).map(|i| ifd + 2 + 12 * i).collect())
+}
+
+// The value of the ASCII entry at `entry` up to its first NUL
+fn read_ascii(tiff: &Tiff, entry: usize) -> ImageResult<String> {
+    let count = try!(tiff.u32(entry + 4)) as usize;
+    let offset = if count > 4 { try!(tiff.u32(entry + 8)) as usize } else { entry + 8 };
+    let bytes = try!(tiff.slice(offset, count));
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+// Rational `index` of the entry at `entry`
+fn read_rational(tiff: &Tiff, entry: usize, index: usize) -> ImageResult<f64> {
+    let offset = try!(tiff.u32(entry + 8)) as usize + 8 * index;
+    let (numerator, denominator) = (try!(tiff.u32(offset)), try!(tiff.u32(offset + 4)));
+
+    Ok(if denominator == 0 { 0.0 } else { numerator as f64 / denominator as f64 })
+}
+
+// The angle in degrees of the entry at `entry` with degrees, minutes and
+// seconds
+fn read_angle(tiff: &Tiff, entry: usize) -> ImageResult<f64> {
+    let mut angle = 0.0;
+    for (i, &unit) in [1.0, 60.0, 3600.0].iter().enumerate() {
+        angle += try!(read_rational(tiff, entry, i)) / unit;
+    }
+
+    Ok(angle)
+}
+
+/// Appends an IFD1 with the JPEG thumbnail ```jpeg``` to the EXIF APP1
+/// segment ```segment```, in the byte order of the segment. An IFD1 that
+/// the segment already has is removed with its thumbnail: the segment is
+/// cut before them if only they follow the other IFDs and their data,
+/// otherwise their bytes are zeroed.
+pub fn add_thumbnail(segment: &[u8], jpeg: &[u8]) -> ImageResult<Vec<u8>> {
+    if !segment.starts_with(b"Exif\0\0") {
+        return Err(ImageError::FormatError("Not an EXIF segment".to_string()))
+    }
+
+    let tiff = Tiff::new(&segment[6..]);
+    let ifd0 = try!(tiff.u32(4)) as usize;
+    let next = ifd0 + 2 + 12 * try!(tiff.u16(ifd0)) as usize;
+    let old_ifd1 = try!(tiff.u32(next)) as usize;
+
+    let mut buf = segment.to_vec();
+
+    if old_ifd1 != 0 {
+        // The entries of the old IFD1, their values and the thumbnail
+        let mut ranges = vec![(old_ifd1, try!(data_end(&tiff, old_ifd1, 0)))];
+        let (mut offset, mut length) = (None, None);
+
+        for entry in try!(entries(&tiff, old_ifd1)) {
+            match try!(tiff.u16(entry)) {
+                TAG_THUMBNAIL_OFFSET => offset = Some(try!(tiff.u32(entry + 8)) as usize),
+                TAG_THUMBNAIL_LENGTH => length = Some(try!(tiff.u32(entry + 8)) as usize),
+                _ => ()
+            }
+        }
+        if let (Some(offset), Some(length)) = (offset, length) {
+            ranges.push((offset, offset.saturating_add(length)));
+        }
+
+        let start = ranges.iter().map(|r| r.0).min().unwrap();
+        if start >= try!(data_end(&tiff, ifd0, 2)) {
+            buf.truncate(6 + start);
+        } else {
+            for &(from, to) in ranges.iter() {
+                let (from, to) = (cmp::min(6 + from, buf.len()), cmp::min(6usize.saturating_add(to), buf.len()));
+                for b in buf[from..to].iter_mut() {
+                    *b = 0;
+                }
+            }
+        }
+    }
+
+    let little_endian = segment[6..].starts_with(b"II");
+    let u16_bytes = |v: u16| if little_endian { [v as u8, (v >> 8) as u8] } else { [(v >> 8) as u8, v as u8] };
+    let u32_bytes = |v: u32| {
+        let (high, low) = (u16_bytes((v >> 16) as u16), u16_bytes(v as u16));
+        if little_endian { [low[0], low[1], high[0], high[1]] } else { [high[0], high[1], low[0], low[1]] }
+    };
+
+    // IFD1 starts at a word boundary after the data of the segment
+    if buf.len() % 2 == 1 {
+        buf.push(0);
+    }
+
+    let ifd1 = (buf.len() - 6) as u32;
+    let thumbnail = ifd1 + 2 + 3 * 12 + 4;
+    ::copy_memory(&u32_bytes(ifd1), &mut buf[6 + next..6 + next + 4]);
+
+    let entries = [
+        (TAG_COMPRESSION, SHORT, u16_bytes(COMPRESSION_JPEG).iter().chain([0, 0].iter()).cloned().collect::<Vec<u8>>()),
+        (TAG_THUMBNAIL_OFFSET, LONG, u32_bytes(thumbnail).to_vec()),
+        (TAG_THUMBNAIL_LENGTH, LONG, u32_bytes(jpeg.len() as u32).to_vec()),
+    ];
+
+    buf.extend(u16_bytes(entries.len() as u16).iter().cloned());
+    for &(tag, type_, ref value) in entries.iter() {
+        buf.extend(u16_bytes(tag).iter().cloned());
+        buf.extend(u16_bytes(type_).iter().cloned());
+        buf.extend(u32_bytes(1).iter().cloned());
+        buf.extend(value.iter().cloned());
+    }
+
+    buf.extend([0, 0, 0, 0].iter().cloned());
+    buf.extend(jpeg.iter().cloned());
+    Ok(buf)
+}
+
+// The end of the entries and values of the IFD at `ifd` and, at most `depth`
+// levels deep, of the EXIF, GPS and interoperability IFDs it points to
+fn data_end(tiff: &Tiff, ifd: usize, depth: usize) -> ImageResult<usize> {
+    let entries = try!(entries(tiff, ifd));
+    let mut end = ifd + 2 + 12 * entries.len() + 4;
+
+    for entry in entries {
+        let tag = try!(tiff.u16(entry));
+        let size = (try!(tiff.u32(entry + 4)) as usize).saturating_mul(type_size(try!(tiff.u16(entry + 2))));
+
+        if size > 4 {
+            end = cmp::max(end, (try!(tiff.u32(entry + 8)) as usize).saturating_add(size));
+        }
+
+        if depth > 0 && (tag == TAG_EXIF_IFD || tag == TAG_GPS_IFD || tag == TAG_INTEROPERABILITY_IFD) {
+            end = cmp::max(end, try!(data_end(tiff, try!(tiff.u32(entry + 8)) as usize, depth - 1)));
+        }
+    }
+
+    Ok(end)
+}
+
+// The size of one value of the TIFF field type `type_`
+fn type_size(type_: u16) -> usize {
+    match type_ {
+        SHORT | 8 => 2,
+        LONG | 9 | 11 => 4,
+        RATIONAL | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+// An IFD entry whose values are stored after the entries if they take
+// more than 4 bytes
+struct Entry {
+    tag: u16,
+    type_: u16,
+    count: u32,
+    data: Vec<u8>,
+}
+
+impl Entry {
+    fn short(tag: u16, value: u16) -> Entry {
+        let mut data = Vec::new();
+        let _ = data.write_u16::<BigEndian>(value);
+
+        Entry { tag: tag, type_: SHORT, count: 1, data: data }
+    }
+
+    fn long(tag: u16, value: u32) -> Entry {
+        let mut data = Vec::new();
+        let _ = data.write_u32::<BigEndian>(value);
+
+        Entry { tag: tag, type_: LONG, count: 1, data: data }
+    }
+
+    fn ascii(tag: u16, value: &str) -> Entry {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+
+        Entry { tag: tag, type_: ASCII, count: data.len() as u32, data: data }
+    }
+
+    // Rationals given as pairs of numerator and denominator
+    fn rationals(tag: u16, values: &[u32]) -> Entry {
+        let mut data = Vec::new();
+        for &v in values.iter() {
+            let _ = data.write_u32::<BigEndian>(v);
+        }
+
+        Entry { tag: tag, type_: RATIONAL, count: values.len() as u32 / 2, data: data }
+    }
+
+    // The space the values take after the entries, padded to a word boundary
+    fn values_len(&self) -> u32 {
+        if self.data.len() > 4 {
+            (self.data.len() as u32 + 1) / 2 * 2
+        } else {
+            0
+        }
+    }
+}
+
+fn ifd_len(entries: &[Entry]) -> u32 {
+    entries.iter().fold(2 + 12 * entries.len() as u32 + 4, |sum, e| sum + e.values_len())
+}
+
+// Writes the IFD at `offset` of the TIFF structure, which `buf` ends at,
+// without a next IFD
+fn write_ifd(buf: &mut Vec<u8>, entries: &[Entry], offset: u32) {
+    let mut values = offset + 2 + 12 * entries.len() as u32 + 4;
+    let _ = buf.write_u16::<BigEndian>(entries.len() as u16);
+
+    for e in entries.iter() {
+        let _ = buf.write_u16::<BigEndian>(e.tag);
+        let _ = buf.write_u16::<BigEndian>(e.type_);
+        let _ = buf.write_u32::<BigEndian>(e.count);
+
+        if e.data.len() > 4 {
+            let _ = buf.write_u32::<BigEndian>(values);
+            values += e.values_len();
+        } else {
+            // Values stored in the entry are left-justified
+            let mut field = [0u8; 4];
+            ::copy_memory(&e.data, &mut field);
+            buf.extend(field.iter().cloned());
+        }
+    }
+
+    let _ = buf.write_u32::<BigEndian>(0);
+
+    for e in entries.iter().filter(|e| e.data.len() > 4) {
+        buf.extend(e.data.iter().cloned());
+        if e.data.len() % 2 == 1 {
+            buf.push(0);
+        }
+    }
+}
+
+// Splits an angle into degrees, minutes and hundredths of seconds
+fn degrees(angle: f64) -> [u32; 6] {
+    let hundredths = (angle.abs() * 360000.0).round() as u32;
+
+    [hundredths / 360000, 1, hundredths / 6000 % 60, 1, hundredths % 6000, 100]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_thumbnail, read_exif, read_orientation, Exif, GpsPosition};
+    use super::super::thumbnail::Tiff;
+
+    // The value of the first entry with `tag` in the IFD at `offset`
+    fn find(tiff: &Tiff, offset: usize, tag: u16) -> Option<usize> {
+        let count = tiff.u16(offset).unwrap() as usize;
+        (0..count).map(|i| offset + 2 + 12 * i).find(|&e| tiff.u16(e).unwrap() == tag).map(|e| e + 8)
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let exif = Exif {
+            orientation: Some(6),
+            date_time: None,
+            date_time_original: Some("2015:06:01 12:30:00".to_string()),
+            gps: Some(GpsPosition { latitude: -33.8568, longitude: 151.2153, altitude: Some(-2.5) }),
+        };
+
+        let bytes = exif.to_bytes();
+        assert!(bytes.starts_with(b"Exif\0\0MM\0\x2A"));
+
+        let tiff = Tiff::new(&bytes[6..]);
+        let ifd0 = tiff.u32(4).unwrap() as usize;
+        assert_eq!(tiff.u16(find(&tiff, ifd0, 0x0112).unwrap()).unwrap(), 6);
+        assert!(find(&tiff, ifd0, 0x0132).is_none());
+
+        let exif_ifd = tiff.u32(find(&tiff, ifd0, 0x8769).unwrap()).unwrap() as usize;
+        let date = tiff.u32(find(&tiff, exif_ifd, 0x9003).unwrap()).unwrap() as usize;
+        assert_eq!(tiff.slice(date, 20).unwrap(), b"2015:06:01 12:30:00\0");
+
+        // 33 degrees, 51 minutes and 24.48 seconds south
+        let gps = tiff.u32(find(&tiff, ifd0, 0x8825).unwrap()).unwrap() as usize;
+        assert_eq!(tiff.slice(find(&tiff, gps, 0x0001).unwrap(), 2).unwrap(), b"S\0");
+        let latitude = tiff.u32(find(&tiff, gps, 0x0002).unwrap()).unwrap() as usize;
+        let values = (0..6).map(|i| tiff.u32(latitude + 4 * i).unwrap()).collect::<Vec<u32>>();
+        assert_eq!(values, vec![33, 1, 51, 1, 2448, 100]);
+        assert_eq!(tiff.slice(find(&tiff, gps, 0x0005).unwrap(), 1).unwrap(), &[1]);
+
+        assert_eq!(Exif::default().to_bytes().len(), 6 + 8 + 6);
+        assert_eq!(read_orientation(&bytes).unwrap(), Some(6));
+        assert_eq!(read_orientation(&Exif::default().to_bytes()).unwrap(), None);
+        assert_eq!(read_orientation(b"http://ns.adobe.com/xap/1.0/\0").unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_exif() {
+        let exif = Exif {
+            orientation: Some(3),
+            date_time: Some("2016:01:02 03:04:05".to_string()),
+            date_time_original: Some("2015:06:01 12:30:00".to_string()),
+            gps: Some(GpsPosition { latitude: -33.8568, longitude: 151.2153, altitude: Some(-2.5) }),
+        };
+
+        let read = read_exif(&exif.to_bytes()).unwrap().unwrap();
+        assert_eq!(read.orientation, exif.orientation);
+        assert_eq!(read.date_time, exif.date_time);
+        assert_eq!(read.date_time_original, exif.date_time_original);
+
+        let gps = read.gps.unwrap();
+        assert!((gps.latitude + 33.8568).abs() < 1e-5 && (gps.longitude - 151.2153).abs() < 1e-5);
+        assert_eq!(gps.altitude, Some(-2.5));
+
+        assert_eq!(read_exif(&Exif::default().to_bytes()).unwrap(), Some(Exif::default()));
+        assert_eq!(read_exif(b"http://ns.adobe.com/xap/1.0/\0").unwrap(), None);
+        assert!(read_exif(b"Exif\0\0MM\0\x2A\0\0\0\x40").is_err());
+    }
+
+    #[test]
+    fn test_add_thumbnail() {
+        // A little endian TIFF header and an empty IFD0
+        let segment = b"Exif\0\0II\x2A\0\x08\0\0\0\0\0\0\0\0\0";
+        let bytes = add_thumbnail(segment, b"thumbnail").unwrap();
+
+        let tiff = Tiff::new(&bytes[6..]);
+        let ifd1 = tiff.u32(10).unwrap() as usize;
+        assert_eq!(ifd1, 14);
+        assert_eq!(tiff.u16(find(&tiff, ifd1, 0x0103).unwrap()).unwrap(), 6);
+
+        let offset = tiff.u32(find(&tiff, ifd1, 0x0201).unwrap()).unwrap() as usize;
+        let length = tiff.u32(find(&tiff, ifd1, 0x0202).unwrap()).unwrap() as usize;
+        assert_eq!(tiff.slice(offset, length).unwrap(), b"thumbnail");
+
+        // The old IFD1 and thumbnail are cut off, not left behind
+        let replaced = add_thumbnail(&bytes, b"other").unwrap();
+        assert!(replaced == add_thumbnail(segment, b"other").unwrap());
+
+        // An old IFD1 followed by a value of IFD0 is zeroed instead
+        let mut segment = b"Exif\0\0II\x2A\0\x08\0\0\0".to_vec();
+        segment.extend(b"\x01\0\x0E\x01\x02\0\x08\0\0\0\x2C\0\0\0\x1A\0\0\0".iter().cloned());
+        segment.extend(b"\x01\0\x03\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0".iter().cloned());
+        segment.extend(b"example\0".iter().cloned());
+
+        let bytes = add_thumbnail(&segment, b"thumbnail").unwrap();
+        let tiff = Tiff::new(&bytes[6..]);
+        assert_eq!(tiff.u32(22).unwrap(), 52);
+        assert!(bytes[6 + 26..6 + 44].iter().all(|&b| b == 0));
+        assert_eq!(tiff.slice(44, 8).unwrap(), b"example\0");
+
+        assert!(add_thumbnail(b"JFIF\0", b"thumbnail").is_err());
+    }
+}
@@ -0,0 +1,388 @@
+//! Lossless transformations of JPEG images
+//!
+//! Like jpegtran, the transformations rearrange the quantized DCT
+//! coefficients instead of decoding and encoding the pixels. Mirroring a
+//! block negates its odd horizontal or vertical frequencies and transposing
+//! it transposes its coefficients, thus the image is transformed without
+//! any loss.
+//!
+//! Blocks can only be moved as a whole, which is why a flip drops the
+//! partial MCU at the right or bottom edge, which would otherwise end up
+//! at the left or top one, and crops start at the MCU boundary at or before
+//! the requested corner.
+
+use std::cmp;
+use std::io::{Read, Write};
+
+use image::{ImageError, ImageResult};
+use math::Rect;
+
+use super::decoder::{Coefficients, ComponentCoefficients, JPEGDecoder};
+use super::encoder::{self, JPEGEncoder};
+
+const APP1: u8 = 0xE1;
+
+/// A lossless transformation of a JPEG image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    /// Rotates by 90 degrees clockwise
+    Rotate90,
+    /// Rotates by 180 degrees
+    Rotate180,
+    /// Rotates by 90 degrees counterclockwise
+    Rotate270,
+    /// Mirrors the left and the right side
+    FlipHorizontal,
+    /// Mirrors the top and the bottom
+    FlipVertical,
+    /// Cuts out a rectangle, extended to start at an MCU boundary
+    Crop(Rect),
+}
+
+impl Coefficients {
+    /// Applies ```transform``` to the coefficients.
+    ///
+    /// Returns an error if a flip or rotation leaves no complete MCU or the
+    /// rectangle of a crop does not lie within the image.
+    pub fn transform(&self, transform: Transform) -> ImageResult<Coefficients> {
+        match transform {
+            // A transposition followed by a horizontal flip
+            Transform::Rotate90 => self.remap(true, true, false),
+            Transform::Rotate180 => self.remap(false, true, true),
+            Transform::Rotate270 => self.remap(true, false, true),
+            Transform::FlipHorizontal => self.remap(false, true, false),
+            Transform::FlipVertical => self.remap(false, false, true),
+            Transform::Crop(rect) => self.crop(rect),
+        }
+    }
+
+    // The size of an MCU in pixels
+    fn mcu_size(&self) -> (u32, u32) {
+        if self.components.len() == 1 {
+            return (8, 8)
+        }
+
+        let h = self.components.iter().map(|c| c.h as u32).max().unwrap_or(1);
+        let v = self.components.iter().map(|c| c.v as u32).max().unwrap_or(1);
+        (8 * h, 8 * v)
+    }
+
+    // Transposes the image if `transpose` is set and then mirrors it
+    fn remap(&self, transpose: bool, flip_x: bool, flip_y: bool) -> ImageResult<Coefficients> {
+        let (mcu_width, mcu_height) = self.mcu_size();
+        let (mcu_width, mcu_height) = if transpose { (mcu_height, mcu_width) } else { (mcu_width, mcu_height) };
+        let (width, height) = if transpose { (self.height, self.width) } else { (self.width, self.height) };
+
+        // Mirrored sides only keep complete MCUs
+        let width = if flip_x { width / mcu_width * mcu_width } else { width };
+        let height = if flip_y { height / mcu_height * mcu_height } else { height };
+
+        if width == 0 || height == 0 {
+            return Err(ImageError::DimensionError)
+        }
+
+        let mcus_per_row = (width + mcu_width - 1) / mcu_width;
+        let mcu_rows = (height + mcu_height - 1) / mcu_height;
+
+        let components = self.components.iter().map(|c| {
+            let (h, v) = if transpose { (c.v, c.h) } else { (c.h, c.v) };
+            let (h, v) = if self.components.len() == 1 { (1, 1) } else { (h, v) };
+            let blocks_wide = mcus_per_row * h as u32;
+            let blocks_high = mcu_rows * v as u32;
+
+            let mut blocks = Vec::with_capacity((blocks_wide * blocks_high) as usize);
+            for by in (0..blocks_high) {
+                for bx in (0..blocks_wide) {
+                    let x = if flip_x { blocks_wide - 1 - bx } else { bx };
+                    let y = if flip_y { blocks_high - 1 - by } else { by };
+                    let source = if transpose { c.block(y, x) } else { c.block(x, y) };
+
+                    blocks.push(transform_block(source, transpose, flip_x, flip_y));
+                }
+            }
+
+            let quantization_table = if transpose {
+                let mut table = [0u16; 64];
+                for i in (0..64) {
+                    table[i % 8 * 8 + i / 8] = c.quantization_table[i];
+                }
+                table
+            } else {
+                c.quantization_table
+            };
+
+            ComponentCoefficients {
+                id: c.id,
+                h: h,
+                v: v,
+                blocks_wide: blocks_wide,
+                blocks_high: blocks_high,
+                quantization_table: quantization_table,
+                blocks: blocks,
+            }
+        }).collect();
+
+        Ok(Coefficients {
+            width: width,
+            height: height,
+            components: components,
+        })
+    }
+
+    fn crop(&self, rect: Rect) -> ImageResult<Coefficients> {
+        if rect.width == 0 || rect.height == 0 || !rect.fits_within(self.width, self.height) {
+            return Err(ImageError::DimensionError)
+        }
+
+        let (mcu_width, mcu_height) = self.mcu_size();
+        let (x, y) = (rect.x / mcu_width, rect.y / mcu_height);
+        let width = rect.x + rect.width - x * mcu_width;
+        let height = rect.y + rect.height - y * mcu_height;
+
+        let mcus_per_row = (width + mcu_width - 1) / mcu_width;
+        let mcu_rows = (height + mcu_height - 1) / mcu_height;
+
+        let components = self.components.iter().map(|c| {
+            let (h, v) = if self.components.len() == 1 { (1, 1) } else { (c.h as u32, c.v as u32) };
+            let blocks_wide = mcus_per_row * h;
+            let blocks_high = mcu_rows * v;
+
+            let mut blocks = Vec::with_capacity((blocks_wide * blocks_high) as usize);
+            for by in (0..blocks_high) {
+                for bx in (0..blocks_wide) {
+                    // Blocks past the source, which pad the last MCU, are repeated
+                    let sx = cmp::min(x * h + bx, c.blocks_wide - 1);
+                    let sy = cmp::min(y * v + by, c.blocks_high - 1);
+                    blocks.push(*c.block(sx, sy));
+                }
+            }
+
+            ComponentCoefficients {
+                blocks_wide: blocks_wide,
+                blocks_high: blocks_high,
+                blocks: blocks,
+                ..c.clone()
+            }
+        }).collect();
+
+        Ok(Coefficients {
+            width: width,
+            height: height,
+            components: components,
+        })
+    }
+}
+
+// Transposes and mirrors the coefficients of one block, in natural order
+fn transform_block(block: &[i16; 64], transpose: bool, flip_x: bool, flip_y: bool) -> [i16; 64] {
+    let mut out = [0i16; 64];
+
+    for v in (0..8) {
+        for u in (0..8) {
+            let mut c = if transpose { block[u * 8 + v] } else { block[v * 8 + u] };
+
+            // Odd frequencies change their sign when mirrored
+            if (flip_x && u % 2 == 1) != (flip_y && v % 2 == 1) {
+                c = -c;
+            }
+
+            out[v * 8 + u] = c;
+        }
+    }
+
+    out
+}
+
+/// Decodes the JPEG image read from ```r``` to its coefficients, applies
+/// ```transform``` and writes the result to ```w```, without any loss.
+/// The EXIF data and the ICC profile are kept, the EXIF orientation is
+/// not changed. Corrupt files with coefficients beyond the range of 8 bit
+/// samples are rejected with a `FormatError`.
+pub fn transform_lossless<R: Read, W: Write>(r: R, w: &mut W, transform: Transform) -> ImageResult<()> {
+    let mut decoder = JPEGDecoder::new(r);
+
+    let exif = try!(decoder.marker_segments()).iter()
+                   .find(|s| s.marker == APP1 && s.data.starts_with(b"Exif\0\0"))
+                   .map(|s| s.data.clone());
+    let icc_profile = try!(decoder.icc_profile());
+
+    let coefficients = try!(try!(decoder.read_coefficients()).transform(transform));
+    if !encoder::huffman_range(&coefficients) {
+        return Err(ImageError::FormatError("The coefficients exceed the range of 8 bit samples".to_string()))
+    }
+
+    let mut encoder = JPEGEncoder::new(w);
+    if let Some(exif) = exif {
+        encoder.set_exif_data(&exif);
+    }
+    if let Some(profile) = icc_profile {
+        encoder.set_icc_profile(&profile);
+    }
+
+    try!(encoder.encode_coefficients(&coefficients));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use color::ColorType;
+    use image::{DecodingResult, ImageDecoder, ImageError};
+    use math::Rect;
+
+    use super::super::{JPEGDecoder, JPEGEncoder, Subsampling};
+    use super::{transform_lossless, Transform};
+
+    // Encodes an RGB image with distinct pixels and returns it decoded
+    fn encode(width: u32, height: u32, subsampling: Subsampling) -> (Vec<u8>, Vec<u8>) {
+        let mut image = Vec::new();
+        for y in (0..height) {
+            for x in (0..width) {
+                image.extend([(x * 7) as u8, (y * 5) as u8, (x * y) as u8].iter().cloned());
+            }
+        }
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new(&mut encoded);
+            encoder.set_subsampling(subsampling);
+            encoder.encode(&image, width, height, ColorType::RGB(8)).unwrap();
+        }
+
+        let decoded = decode(&encoded).2;
+        (encoded, decoded)
+    }
+
+    fn decode(data: &[u8]) -> (u32, u32, Vec<u8>) {
+        let mut decoder = JPEGDecoder::new(data);
+        let (width, height) = decoder.dimensions().unwrap();
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(data) => (width, height, data),
+            _ => panic!("8 bit samples expected")
+        }
+    }
+
+    fn pixel(image: &[u8], width: u32, x: u32, y: u32) -> &[u8] {
+        let i = 3 * (y * width + x) as usize;
+        &image[i..i + 3]
+    }
+
+    fn max_difference(a: &[u8], b: &[u8]) -> i32 {
+        a.iter().zip(b.iter()).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap()
+    }
+
+    #[test]
+    fn test_rotate_and_flip() {
+        let (encoded, original) = encode(48, 32, Subsampling::Ratio444);
+
+        let mut results = Vec::new();
+        for &t in [Transform::Rotate90, Transform::Rotate180, Transform::Rotate270,
+                   Transform::FlipHorizontal, Transform::FlipVertical].iter() {
+            let mut out = Vec::new();
+            transform_lossless(&encoded[..], &mut out, t).unwrap();
+            results.push(decode(&out));
+        }
+
+        // Only the rounding of the inverse DCT, which differs between rows
+        // and columns, changes
+        for y in (0..32) {
+            for x in (0..48) {
+                let p = pixel(&original, 48, x, y);
+                assert!(max_difference(p, pixel(&results[0].2, 32, 31 - y, x)) <= 4);
+                assert!(max_difference(p, pixel(&results[1].2, 48, 47 - x, 31 - y)) <= 2);
+                assert!(max_difference(p, pixel(&results[2].2, 32, y, 47 - x)) <= 4);
+                assert!(max_difference(p, pixel(&results[3].2, 48, 47 - x, y)) <= 2);
+                assert!(max_difference(p, pixel(&results[4].2, 48, x, 31 - y)) <= 2);
+            }
+        }
+
+        // Four rotations restore the coefficients exactly
+        let mut data = encoded.clone();
+        for _ in (0..4) {
+            let mut out = Vec::new();
+            transform_lossless(&data[..], &mut out, Transform::Rotate90).unwrap();
+            data = out;
+        }
+        assert_eq!(decode(&data).2, original);
+    }
+
+    #[test]
+    fn test_subsampled_edges() {
+        // The partial MCU at the right edge is dropped by the flip
+        let (encoded, original) = encode(40, 24, Subsampling::Ratio420);
+
+        let mut out = Vec::new();
+        transform_lossless(&encoded[..], &mut out, Transform::FlipHorizontal).unwrap();
+        let (width, height, flipped) = decode(&out);
+        assert_eq!((width, height), (32, 24));
+
+        for y in (0..24) {
+            for x in (0..32) {
+                assert!(max_difference(pixel(&original, 40, x, y), pixel(&flipped, 32, 31 - x, y)) <= 2);
+            }
+        }
+
+        // The bottom edge after the transposition is dropped as well
+        let mut out = Vec::new();
+        transform_lossless(&encoded[..], &mut out, Transform::Rotate270).unwrap();
+        let (width, height, _) = decode(&out);
+        assert_eq!((width, height), (24, 32));
+    }
+
+    #[test]
+    fn test_crop() {
+        let (encoded, original) = encode(48, 32, Subsampling::Ratio422);
+
+        // Moved left to the MCU boundary at 16
+        let mut out = Vec::new();
+        transform_lossless(&encoded[..], &mut out, Transform::Crop(Rect::new(20, 8, 20, 17))).unwrap();
+        let (width, height, cropped) = decode(&out);
+        assert_eq!((width, height), (24, 17));
+
+        for y in (0..17) {
+            for x in (0..24) {
+                assert!(max_difference(pixel(&original, 48, x + 16, y + 8), pixel(&cropped, 24, x, y)) <= 2);
+            }
+        }
+
+        let mut out = Vec::new();
+        assert!(transform_lossless(&encoded[..], &mut out, Transform::Crop(Rect::new(40, 0, 10, 10))).is_err());
+    }
+
+    #[test]
+    fn test_coefficient_range() {
+        let (encoded, _) = encode(16, 16, Subsampling::Ratio444);
+
+        // Arithmetic coding takes coefficients beyond the range of 8 bit
+        // samples, as found in corrupt files, Huffman coding does not
+        for &(k, value) in [(0, 1024), (0, -1025), (1, 1024), (63, -1024)].iter() {
+            let mut coefficients = JPEGDecoder::new(&encoded[..]).read_coefficients().unwrap();
+            coefficients.components[1].blocks[2][k] = value;
+
+            let mut out = Vec::new();
+            assert!(JPEGEncoder::new(&mut out).encode_coefficients(&coefficients).is_err());
+
+            let mut arithmetic = Vec::new();
+            {
+                let mut encoder = JPEGEncoder::new(&mut arithmetic);
+                encoder.set_arithmetic_coding(true);
+                encoder.encode_coefficients(&coefficients).unwrap();
+            }
+
+            let mut out = Vec::new();
+            match transform_lossless(&arithmetic[..], &mut out, Transform::Rotate90) {
+                Err(ImageError::FormatError(..)) => (),
+                other => panic!("{:?}", other),
+            }
+        }
+
+        // The limits themselves are accepted
+        let mut coefficients = JPEGDecoder::new(&encoded[..]).read_coefficients().unwrap();
+        coefficients.components[0].blocks[0][0] = -1024;
+        coefficients.components[0].blocks[1][0] = 1023;
+        coefficients.components[0].blocks[1][5] = -1023;
+        let mut out = Vec::new();
+        JPEGEncoder::new(&mut out).encode_coefficients(&coefficients).unwrap();
+        let decoded = JPEGDecoder::new(&out[..]).read_coefficients().unwrap();
+        assert_eq!(decoded.components[0].blocks[..2], coefficients.components[0].blocks[..2]);
+    }
+}
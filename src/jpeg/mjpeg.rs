@@ -0,0 +1,361 @@
+//! Decoding of Motion JPEG streams
+//!
+//! A Motion JPEG stream, as sent by IP cameras or written by
+//! `ffmpeg -f mjpeg`, is a sequence of complete JPEG images without any
+//! container. Garbage between the images, like the multipart boundaries
+//! of an HTTP stream, is skipped by searching for the next SOI marker.
+
+use std::io::{self, Cursor, Read};
+use std::mem;
+
+use buffer::ImageBuffer;
+use color::ColorType;
+use dynimage::DynamicImage;
+use image::{DecodingResult, ImageDecoder, ImageError, ImageResult};
+
+use super::decoder::{JPEGDecoder, JpegDecodeOptions, EOI, SOI, SOS};
+
+// The markers without a length other than SOI and EOI, Table B.1
+const RST0: u8 = 0xD0;
+const RST7: u8 = 0xD7;
+const TEM: u8 = 0x01;
+
+// The default maximum size of a frame, far above that of the frames of
+// any camera
+const MAX_FRAME_SIZE: usize = 64 << 20;
+
+/// An iterator over the frames of a Motion JPEG stream
+///
+/// Each item is the next decoded frame, or the error that prevented its
+/// decoding. After an error the iterator resynchronizes on the next SOI
+/// marker, thus a damaged frame does not end the stream. The iteration
+/// ends with the data.
+///
+/// The stream is read one byte at a time, wrap unbuffered readers like
+/// sockets in a `BufReader`.
+///
+/// Frames are buffered before decoding, thus a frame larger than the
+/// maximum set with ```set_max_frame_size``` is skipped with a
+/// `LimitsExceeded` error, which keeps a stream without EOI markers from
+/// growing the buffer without bound.
+pub struct MjpegFrames<R> {
+    r: R,
+    decoder: JPEGDecoder<Cursor<Vec<u8>>>,
+    frame: Vec<u8>,
+    max_frame_size: usize,
+    // Whether the SOI of the next frame was already read
+    have_soi: bool,
+    done: bool,
+}
+
+impl<R: Read> MjpegFrames<R> {
+    /// Creates an iterator over the frames of the stream ```r```
+    pub fn new(r: R) -> MjpegFrames<R> {
+        MjpegFrames::new_with_options(r, Default::default())
+    }
+
+    /// Creates an iterator over the frames of the stream ```r```, which
+    /// are decoded using ```options```
+    ///
+    /// The decoder is reused for all frames, thus the quantization and
+    /// Huffman tables of a frame stay defined for the following ones.
+    pub fn new_with_options(r: R, options: JpegDecodeOptions) -> MjpegFrames<R> {
+        MjpegFrames {
+            r: r,
+            decoder: JPEGDecoder::new_with_options(Cursor::new(Vec::new()), options),
+            frame: Vec::new(),
+            max_frame_size: MAX_FRAME_SIZE,
+            have_soi: false,
+            done: false,
+        }
+    }
+
+    /// Sets the maximum size of a frame in bytes. Defaults to 64 MiB.
+    pub fn set_max_frame_size(&mut self, max: usize) {
+        self.max_frame_size = max;
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    // Returns an error if `additional` more bytes exceed the maximum frame size
+    fn check_frame_size(&self, additional: usize) -> ImageResult<()> {
+        if self.frame.len() + additional > self.max_frame_size {
+            return Err(ImageError::LimitsExceeded(format!(
+                "The frame is larger than the maximum of {} bytes", self.max_frame_size
+            )))
+        }
+
+        Ok(())
+    }
+
+    // Returns the next byte of the stream, or `None` at its end
+    fn next_byte(&mut self) -> ImageResult<Option<u8>> {
+        let mut byte = [0u8];
+
+        loop {
+            return match self.r.read(&mut byte) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(byte[0])),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(ImageError::IoError(e))
+            }
+        }
+    }
+
+    // Like `next_byte`, but the stream must not end within a frame
+    fn frame_byte(&mut self) -> ImageResult<u8> {
+        match try!(self.next_byte()) {
+            Some(byte) => Ok(byte),
+            None => Err(ImageError::NotEnoughData)
+        }
+    }
+
+    // Skips to the next SOI marker. Returns false if the stream ended first.
+    fn find_soi(&mut self) -> ImageResult<bool> {
+        if self.have_soi {
+            self.have_soi = false;
+            return Ok(true)
+        }
+
+        let mut previous = 0;
+
+        while let Some(byte) = try!(self.next_byte()) {
+            if previous == 0xFF && byte == SOI {
+                return Ok(true)
+            }
+
+            previous = byte;
+        }
+
+        Ok(false)
+    }
+
+    // Reads the marker following a 0xFF byte, skipping fill bytes
+    fn read_marker(&mut self) -> ImageResult<u8> {
+        loop {
+            match try!(self.frame_byte()) {
+                0xFF => continue,
+                marker => return Ok(marker)
+            }
+        }
+    }
+
+    // Reads the frame following an SOI marker into `frame`, up to and
+    // including its EOI marker
+    fn read_frame(&mut self) -> ImageResult<()> {
+        self.frame.clear();
+        self.frame.extend([0xFF, SOI].iter().cloned());
+
+        loop {
+            if try!(self.frame_byte()) != 0xFF {
+                return Err(ImageError::FormatError("Expected a marker".to_string()))
+            }
+
+            let mut marker = try!(self.read_marker());
+
+            // Markers followed by entropy-coded data, which ends at the
+            // next marker other than a restart marker
+            while marker == SOS {
+                try!(self.read_segment(marker));
+                marker = try!(self.skip_entropy_coded_data());
+            }
+
+            match marker {
+                EOI => {
+                    self.frame.extend([0xFF, EOI].iter().cloned());
+                    return Ok(())
+                }
+                SOI => {
+                    // The previous frame was cut off
+                    self.have_soi = true;
+                    return Err(ImageError::FormatError("Frame ended without EOI marker".to_string()))
+                }
+                TEM | RST0 ... RST7 => {
+                    try!(self.check_frame_size(2));
+                    self.frame.extend([0xFF, marker].iter().cloned());
+                }
+                _ => try!(self.read_segment(marker))
+            }
+        }
+    }
+
+    // Copies the marker segment starting with `marker` to `frame`
+    fn read_segment(&mut self, marker: u8) -> ImageResult<()> {
+        let (high, low) = (try!(self.frame_byte()), try!(self.frame_byte()));
+        let length = (high as usize) << 8 | low as usize;
+
+        if length < 2 {
+            return Err(ImageError::FormatError("Invalid segment length".to_string()))
+        }
+
+        try!(self.check_frame_size(2 + length));
+        self.frame.extend([0xFF, marker, high, low].iter().cloned());
+
+        let start = self.frame.len();
+        try!(self.r.by_ref().take(length as u64 - 2).read_to_end(&mut self.frame));
+
+        if self.frame.len() - start < length - 2 {
+            return Err(ImageError::NotEnoughData)
+        }
+
+        Ok(())
+    }
+
+    // Copies entropy-coded data to `frame` and returns the marker ending it
+    fn skip_entropy_coded_data(&mut self) -> ImageResult<u8> {
+        loop {
+            let byte = try!(self.frame_byte());
+            try!(self.check_frame_size(2));
+
+            if byte != 0xFF {
+                self.frame.push(byte);
+                continue
+            }
+
+            match try!(self.read_marker()) {
+                // A stuffed zero byte or a restart marker within the data
+                marker @ 0x00 | marker @ RST0 ... RST7 => self.frame.extend([0xFF, marker].iter().cloned()),
+                marker => return Ok(marker)
+            }
+        }
+    }
+
+    // Decodes the frame in `frame`, keeping the allocations of the decoder
+    fn decode_frame(&mut self) -> ImageResult<DynamicImage> {
+        let frame = mem::replace(&mut self.frame, Vec::new());
+        let previous = self.decoder.reset(Cursor::new(frame)).into_inner();
+        self.frame = previous;
+
+        let color = try!(self.decoder.colortype());
+        let (width, height) = try!(self.decoder.dimensions());
+        let buf = match try!(self.decoder.read_image()) {
+            DecodingResult::U8(buf) => buf,
+            DecodingResult::U16(_) => return Err(ImageError::UnsupportedColor(color))
+        };
+
+        let image = match color {
+            ColorType::Gray(8) => ImageBuffer::from_raw(width, height, buf.into()).map(DynamicImage::ImageLuma8),
+            ColorType::GrayA(8) => ImageBuffer::from_raw(width, height, buf.into()).map(DynamicImage::ImageLumaA8),
+            ColorType::RGB(8) => ImageBuffer::from_raw(width, height, buf.into()).map(DynamicImage::ImageRgb8),
+            ColorType::RGBA(8) => ImageBuffer::from_raw(width, height, buf.into()).map(DynamicImage::ImageRgba8),
+            _ => return Err(ImageError::UnsupportedColor(color))
+        };
+
+        image.ok_or(ImageError::DimensionError)
+    }
+}
+
+impl<R: Read> Iterator for MjpegFrames<R> {
+    type Item = ImageResult<DynamicImage>;
+
+    fn next(&mut self) -> Option<ImageResult<DynamicImage>> {
+        if self.done {
+            return None
+        }
+
+        match self.find_soi() {
+            Ok(true) => (),
+            Ok(false) => {
+                self.done = true;
+                return None
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e))
+            }
+        }
+
+        match self.read_frame() {
+            Ok(()) => Some(self.decode_frame()),
+            Err(e) => {
+                // A frame cut off by the end of the stream is the last one
+                if let ImageError::NotEnoughData = e {
+                    self.done = true;
+                }
+
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MjpegFrames;
+    use super::super::{JPEGDecoder, JPEGEncoder};
+    use color::ColorType;
+    use dynimage;
+    use image::{GenericImage, ImageError};
+
+    fn encode(width: u32, height: u32, seed: usize) -> Vec<u8> {
+        let image = (0..(width * height * 3) as usize).map(|i| (i * seed % 251) as u8).collect::<Vec<u8>>();
+        let mut encoded = Vec::new();
+        JPEGEncoder::new(&mut encoded).encode(&image, width, height, ColorType::RGB(8)).unwrap();
+        encoded
+    }
+
+    fn pixels(jpeg: &[u8]) -> Vec<u8> {
+        dynimage::decoder_to_image(JPEGDecoder::new(jpeg)).unwrap().raw_pixels()
+    }
+
+    #[test]
+    fn test_frames() {
+        let (a, b) = (encode(16, 8, 7), encode(24, 9, 13));
+
+        let mut stream = b"--boundary\r\nContent-Type: image/jpeg\r\n\r\n".to_vec();
+        stream.extend(a.iter().cloned());
+        stream.extend(b"\r\n--boundary\r\n\r\n".iter().cloned());
+        stream.extend(b.iter().cloned());
+        stream.extend(a.iter().cloned());
+        stream.extend(b"\r\n".iter().cloned());
+
+        let frames = MjpegFrames::new(&stream[..]).map(|f| f.unwrap()).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].dimensions(), (16, 8));
+        assert_eq!(frames[1].dimensions(), (24, 9));
+        assert_eq!(frames[0].raw_pixels(), pixels(&a));
+        assert_eq!(frames[1].raw_pixels(), pixels(&b));
+        assert_eq!(frames[2].raw_pixels(), pixels(&a));
+    }
+
+    #[test]
+    fn test_resync() {
+        let (a, b) = (encode(16, 8, 7), encode(24, 9, 13));
+
+        // The first frame is cut off within its scan, the last one too
+        let mut stream = a[..a.len() - 20].to_vec();
+        stream.extend(b.iter().cloned());
+        stream.extend(a[..a.len() / 2].iter().cloned());
+
+        let frames = MjpegFrames::new(&stream[..]).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].is_err());
+        assert_eq!(frames[1].as_ref().unwrap().raw_pixels(), pixels(&b));
+        assert!(frames[2].is_err());
+
+        assert_eq!(MjpegFrames::new(&b"no frames"[..]).count(), 0);
+    }
+
+    #[test]
+    fn test_max_frame_size() {
+        let (a, b) = (encode(16, 8, 7), encode(24, 9, 13));
+
+        // The scan of the first frame never ends
+        let mut stream = a[..a.len() - 2].to_vec();
+        stream.extend(vec![0x55; 5000]);
+        stream.extend(b.iter().cloned());
+
+        let mut frames = MjpegFrames::new(&stream[..]);
+        frames.set_max_frame_size(b.len() + 1000);
+        let frames = frames.collect::<Vec<_>>();
+        assert_eq!(frames.len(), 2);
+        match frames[0] {
+            Err(ImageError::LimitsExceeded(_)) => (),
+            ref other => panic!("{:?}", other.as_ref().map(|_| ())),
+        }
+        assert_eq!(frames[1].as_ref().unwrap().raw_pixels(), pixels(&b));
+    }
+}
@@ -8,10 +8,38 @@
 //!
 
 pub use self::decoder::JPEGDecoder;
-pub use self::encoder::JPEGEncoder;
+pub use self::encoder::{JPEGEncoder, ScanlineEncoder, Subsampling};
+pub use self::exif::{Exif, GpsPosition};
 pub use self::decoder::Component;
+pub use self::quality::estimate_quality;
+pub use self::thumbnail::read_thumbnail;
+pub use self::mjpeg::MjpegFrames;
+pub use self::mpo::{MpEntry, MpoDecoder};
+pub use self::lossless::{transform_lossless, Transform};
+pub use self::decoder::{
+    Coefficients,
+    ColorOrder,
+    ComponentCoefficients,
+    ComponentPlane,
+    JpegDecodeOptions,
+    Limits,
+    MarkerSegment,
+    PartialDecode,
+    ResourceUsage,
+    Tolerance,
+    UpsamplingMethod,
+};
 
 mod encoder;
 mod decoder;
 mod entropy;
 mod transform;
+mod simd;
+mod thumbnail;
+mod quality;
+mod mjpeg;
+mod mpo;
+mod exif;
+mod c2pa;
+mod arithmetic;
+mod lossless;
@@ -0,0 +1,269 @@
+//! Multi-Picture Object (MPO) files
+//!
+//! Stereo cameras store the pictures of both eyes, and often a preview,
+//! as JPEG images one after another in a single file. An APP2 segment of
+//! the first image holds the MP index, which locates all of them.
+//!
+//! See CIPA DC-007, "Multi-Picture Format"
+
+use byteorder::{ReadBytesExt, BigEndian};
+
+use dynimage::{self, DynamicImage};
+use image::{ImageError, ImageResult};
+
+use super::decoder::{JPEGDecoder, EOI, SOI, SOS};
+use super::thumbnail::Tiff;
+
+const APP2: u8 = 0xE2;
+
+// Tags of the MP index IFD
+const TAG_NUMBER_OF_IMAGES: u16 = 0xB001;
+const TAG_MP_ENTRY: u16 = 0xB002;
+
+/// A picture of a multi-picture file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MpEntry {
+    /// The attributes of the picture. The lowest 24 bits hold its type,
+    /// e.g. ```0x020002``` for a picture of a stereo pair and ```0x010001```
+    /// for a preview.
+    pub attributes: u32,
+
+    /// The offset of the picture in the file
+    pub offset: usize,
+
+    /// The length of the picture in bytes
+    pub length: usize,
+}
+
+impl MpEntry {
+    /// The type of the picture, without the flags of its attributes
+    pub fn picture_type(&self) -> u32 {
+        self.attributes & 0xFFFFFF
+    }
+}
+
+/// Decodes the pictures of a multi-picture file
+pub struct MpoDecoder<'a> {
+    data: &'a [u8],
+    entries: Vec<MpEntry>,
+    multi_picture: bool,
+}
+
+impl<'a> MpoDecoder<'a> {
+    /// Reads the MP index of the file ```data```. A JPEG file without one
+    /// is treated as a single picture.
+    pub fn new(data: &'a [u8]) -> ImageResult<MpoDecoder<'a>> {
+        let entries = try!(read_mp_index(data));
+        let multi_picture = entries.is_some();
+
+        let entries = entries.unwrap_or_else(|| vec![MpEntry {
+            attributes: 0,
+            offset: 0,
+            length: data.len(),
+        }]);
+
+        Ok(MpoDecoder {
+            data: data,
+            entries: entries,
+            multi_picture: multi_picture,
+        })
+    }
+
+    /// Returns true if the file has an MP index
+    pub fn is_multi_picture(&self) -> bool {
+        self.multi_picture
+    }
+
+    /// The pictures of the file, in the order of the MP index
+    pub fn entries(&self) -> &[MpEntry] {
+        &self.entries
+    }
+
+    /// Returns a decoder for picture ```index```
+    pub fn decoder(&self, index: usize) -> ImageResult<JPEGDecoder<&'a [u8]>> {
+        let entry = match self.entries.get(index) {
+            Some(entry) => entry,
+            None => return Err(ImageError::FormatError(format!("No picture {}", index)))
+        };
+
+        match entry.offset.checked_add(entry.length) {
+            Some(end) if end <= self.data.len() => {
+                Ok(JPEGDecoder::new(&self.data[entry.offset..end]))
+            }
+            _ => Err(ImageError::NotEnoughData)
+        }
+    }
+
+    /// Decodes picture ```index```
+    pub fn decode(&self, index: usize) -> ImageResult<DynamicImage> {
+        dynimage::decoder_to_image(try!(self.decoder(index)))
+    }
+}
+
+// Finds the MP index in the segments of the first image and returns its
+// entries, with offsets relative to the start of the file
+fn read_mp_index(data: &[u8]) -> ImageResult<Option<Vec<MpEntry>>> {
+    let mut r = data;
+
+    if try!(r.read_u8()) != 0xFF || try!(r.read_u8()) != SOI {
+        return Err(ImageError::FormatError("Missing SOI marker".to_string()))
+    }
+
+    loop {
+        if try!(r.read_u8()) != 0xFF {
+            continue
+        }
+
+        let marker = try!(r.read_u8());
+
+        match marker {
+            SOS | EOI => return Ok(None),
+            // Fill bytes and standalone markers
+            0xFF => continue,
+            0x01 | 0xD0 ... 0xD7 => continue,
+            _ => ()
+        }
+
+        let length = try!(r.read_u16::<BigEndian>()).saturating_sub(2) as usize;
+        if length > r.len() {
+            return Err(ImageError::NotEnoughData)
+        }
+
+        let (segment, rest) = r.split_at(length);
+        r = rest;
+
+        if marker == APP2 && segment.starts_with(b"MPF\0") {
+            // Offsets are relative to the TIFF header following the identifier
+            let base = data.len() - rest.len() - length + 4;
+            return parse_mp_index(&Tiff::new(&segment[4..]), base).map(Some)
+        }
+    }
+}
+
+fn parse_mp_index(tiff: &Tiff, base: usize) -> ImageResult<Vec<MpEntry>> {
+    let ifd = try!(tiff.u32(4)) as usize;
+    let mut count = None;
+    let mut entries = None;
+
+    for i in (0..try!(tiff.u16(ifd)) as usize) {
+        let entry = ifd + 2 + 12 * i;
+
+        match try!(tiff.u16(entry)) {
+            TAG_NUMBER_OF_IMAGES => count = Some(try!(tiff.u32(entry + 8)) as usize),
+            TAG_MP_ENTRY => entries = Some(try!(tiff.u32(entry + 8)) as usize),
+            _ => ()
+        }
+    }
+
+    let (count, offset) = match (count, entries) {
+        (Some(count), Some(offset)) => (count, offset),
+        _ => return Err(ImageError::FormatError("Incomplete MP index".to_string()))
+    };
+
+    // The count comes from the file, reserving it could allocate anything
+    let mut pictures = Vec::new();
+
+    for i in (0..count) {
+        let entry = offset + 16 * i;
+        let relative = try!(tiff.u32(entry + 8)) as usize;
+
+        pictures.push(MpEntry {
+            attributes: try!(tiff.u32(entry)),
+            // The first picture starts the file, its offset is 0
+            offset: if i == 0 { 0 } else { base + relative },
+            length: try!(tiff.u32(entry + 4)) as usize,
+        });
+    }
+
+    Ok(pictures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MpoDecoder;
+    use super::super::JPEGEncoder;
+    use color::ColorType;
+    use image::GenericImage;
+
+    fn encode(width: u32, height: u32, value: u8) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        let image = vec![value; (width * height * 3) as usize];
+        JPEGEncoder::new(&mut encoded).encode(&image, width, height, ColorType::RGB(8)).unwrap();
+        encoded
+    }
+
+    fn push_u32(v: &mut Vec<u8>, n: u32) {
+        v.extend([(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8].iter().cloned());
+    }
+
+    // Two pictures of a stereo pair with the MP index in the first one
+    fn mpo(left: &[u8], right: &[u8]) -> Vec<u8> {
+        // Big endian TIFF header and an IFD with the number of images and the entries
+        let mut segment = b"MPF\0MM\0\x2A\0\0\0\x08\0\x02".to_vec();
+        segment.extend([0xB0, 0x01, 0, 4, 0, 0, 0, 1, 0, 0, 0, 2].iter().cloned());
+        segment.extend([0xB0, 0x02, 0, 7, 0, 0, 0, 32, 0, 0, 0, 38].iter().cloned());
+        segment.extend([0, 0, 0, 0].iter().cloned());
+
+        let first_len = left.len() + 4 + segment.len() + 32;
+        let second_offset = first_len - 10;
+
+        push_u32(&mut segment, 0x20020002);
+        push_u32(&mut segment, first_len as u32);
+        push_u32(&mut segment, 0);
+        segment.extend([0, 0, 0, 0].iter().cloned());
+        push_u32(&mut segment, 0x00020002);
+        push_u32(&mut segment, right.len() as u32);
+        push_u32(&mut segment, second_offset as u32);
+        segment.extend([0, 0, 0, 0].iter().cloned());
+
+        let mut file = vec![0xFF, 0xD8, 0xFF, 0xE2];
+        file.extend([((segment.len() + 2) >> 8) as u8, (segment.len() + 2) as u8].iter().cloned());
+        file.extend(segment.iter().cloned());
+        file.extend(left[2..].iter().cloned());
+        assert_eq!(file.len(), first_len);
+        file.extend(right.iter().cloned());
+        file
+    }
+
+    #[test]
+    fn test_mpo() {
+        let (left, right) = (encode(16, 8, 40), encode(24, 16, 200));
+        let file = mpo(&left, &right);
+
+        let decoder = MpoDecoder::new(&file).unwrap();
+        assert!(decoder.is_multi_picture());
+        assert_eq!(decoder.entries().len(), 2);
+        assert_eq!(decoder.entries()[1].picture_type(), 0x020002);
+        assert_eq!(decoder.entries()[1].offset, file.len() - right.len());
+
+        let (a, b) = (decoder.decode(0).unwrap(), decoder.decode(1).unwrap());
+        assert_eq!(a.dimensions(), (16, 8));
+        assert_eq!(b.dimensions(), (24, 16));
+        assert!(b.raw_pixels().iter().all(|&v| v > 190));
+        assert!(decoder.decode(2).is_err());
+    }
+
+    #[test]
+    fn test_untrusted_count() {
+        let (left, right) = (encode(16, 8, 40), encode(24, 16, 200));
+        let mut file = mpo(&left, &right);
+
+        let count = [0xB0, 0x01, 0, 4, 0, 0, 0, 1, 0, 0, 0, 2];
+        let i = (0..file.len()).find(|&i| file[i..].starts_with(&count)).unwrap();
+        for b in file[i + 8..i + 12].iter_mut() {
+            *b = 0xFF;
+        }
+
+        assert!(MpoDecoder::new(&file).is_err());
+    }
+
+    #[test]
+    fn test_plain_jpeg() {
+        let jpeg = encode(16, 8, 40);
+        let decoder = MpoDecoder::new(&jpeg).unwrap();
+
+        assert!(!decoder.is_multi_picture());
+        assert_eq!(decoder.entries().len(), 1);
+        assert_eq!(decoder.decode(0).unwrap().dimensions(), (16, 8));
+    }
+}
@@ -0,0 +1,118 @@
+//! Estimation of the encoding quality
+//!
+//! Most encoders derive their quantization tables by scaling the example
+//! tables of Annex K with the quality setting, as the IJG libjpeg does.
+//! Inverting that scaling yields the approximate quality an image was
+//! saved with.
+
+use std::cmp;
+
+// Table K.1 in natural order
+static STD_LUMA_QTABLE: [u16; 64] = [
+    16, 11, 10, 16,  24,  40,  51,  61,
+    12, 12, 14, 19,  26,  58,  60,  55,
+    14, 13, 16, 24,  40,  57,  69,  56,
+    14, 17, 22, 29,  51,  87,  80,  62,
+    18, 22, 37, 56,  68, 109, 103,  77,
+    24, 35, 55, 64,  81, 104, 113,  92,
+    49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103,  99
+];
+
+// Table K.2 in natural order
+static STD_CHROMA_QTABLE: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99,
+    18, 21, 26, 66, 99, 99, 99, 99,
+    24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99
+];
+
+/// Estimates the quality between 1 and 100 an image was encoded with,
+/// using the scale of the IJG libjpeg quality setting.
+///
+/// `tables` are the quantization tables in natural order as returned by
+/// `JPEGDecoder::quantization_tables`. Table 0 is taken as the luma table
+/// and table 1, if present, as the chroma table. The estimate is the quality
+/// for which libjpeg would produce the most similar tables. Returns `None`
+/// if table 0 is not defined.
+pub fn estimate_quality(tables: &[Option<[u16; 64]>; 4]) -> Option<u8> {
+    let luma = match tables[0] {
+        Some(ref table) => table,
+        None => return None
+    };
+
+    (1..101u32).min_by_key(|&quality| {
+        let mut error = distance(luma, &STD_LUMA_QTABLE, quality);
+
+        if let Some(ref chroma) = tables[1] {
+            error += distance(chroma, &STD_CHROMA_QTABLE, quality);
+        }
+
+        error
+    }).map(|quality| quality as u8)
+}
+
+// The sum of the differences between `table` and `reference` scaled to `quality`
+fn distance(table: &[u16; 64], reference: &[u16; 64], quality: u32) -> u32 {
+    table.iter().zip(reference.iter()).fold(0, |sum, (&q, &r)| {
+        let s = scaled(r, quality) as i32;
+        sum + (q as i32 - s).abs() as u32
+    })
+}
+
+// The luma and chroma tables in natural order that libjpeg uses for
+// `quality`, which is between 1 and 100
+pub fn quality_tables(quality: u8) -> Vec<u8> {
+    STD_LUMA_QTABLE.iter()
+                   .chain(STD_CHROMA_QTABLE.iter())
+                   .map(|&q| scaled(q, quality as u32) as u8)
+                   .collect()
+}
+
+// Scales an entry of a reference table like `jpeg_quality_scaling` and
+// `jpeg_add_quant_table` of libjpeg with `force_baseline`
+fn scaled(q: u16, quality: u32) -> u16 {
+    let scale = if quality < 50 { 5000 / quality } else { 200 - 2 * quality };
+
+    cmp::max(1, cmp::min(255, (q as u32 * scale + 50) / 100)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_quality, scaled, STD_CHROMA_QTABLE, STD_LUMA_QTABLE};
+
+    fn scaled_table(table: &[u16; 64], quality: u32) -> [u16; 64] {
+        let mut out = [0u16; 64];
+
+        for (o, &q) in out.iter_mut().zip(table.iter()) {
+            *o = scaled(q, quality);
+        }
+
+        out
+    }
+
+    #[test]
+    fn test_estimate_quality() {
+        for &quality in [5u32, 10, 50, 75, 90, 95].iter() {
+            let tables = [
+                Some(scaled_table(&STD_LUMA_QTABLE, quality)),
+                Some(scaled_table(&STD_CHROMA_QTABLE, quality)),
+                None,
+                None
+            ];
+
+            assert_eq!(estimate_quality(&tables), Some(quality as u8));
+        }
+
+        // Tables of other encoders give the closest libjpeg quality
+        let mut luma = scaled_table(&STD_LUMA_QTABLE, 80);
+        luma[0] += 3;
+        assert_eq!(estimate_quality(&[Some(luma), None, None, None]), Some(80));
+
+        assert_eq!(estimate_quality(&[None; 4]), None);
+    }
+}
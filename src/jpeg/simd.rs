@@ -0,0 +1,1063 @@
+//! Vectorized kernels of the encoder and the decoder
+//!
+//! The kernels give exactly the same results as their scalar counterparts,
+//! thus the output of the codecs does not depend on the SIMD level. The
+//! encoder uses the SSE2 kernels at the SSE2 and AVX2 levels, the decoder
+//! has an AVX2 inverse DCT. On aarch64 the decoder has NEON kernels for the
+//! inverse DCT and the conversion to RGB, which works in 16 bit fixed point
+//! like the scalar code, and so has the SIMD128 level on WebAssembly. The
+//! other levels have no kernels and fall back to scalar code.
+//!
+//! The kernels are only compiled with the ```simd``` cargo feature, without
+//! it every function here forwards to the scalar code.
+//!
+//! The forward DCT works on 8 rows or columns at once with 16 bit lanes,
+//! where each multiply-add of ```pmaddwd``` computes two of the products of
+//! the scalar code. The rounding and shifts are those of ```transform::fdct```.
+//!
+//! The inverse DCT keeps the 32 bit lanes of ```transform::idct```, as the
+//! dequantized coefficients do not fit 16 bits. SSE2 transforms one block
+//! with two vectors per row, AVX2 two blocks at once with one vector per row.
+//! NEON and SIMD128 work like SSE2.
+
+// The constants and macros shared by the kernels are unused without them
+#![cfg_attr(not(feature = "simd"), allow(dead_code, unused_macros))]
+
+use config::SimdLevel;
+use super::transform;
+
+#[cfg(all(feature = "simd", target_arch = "x86"))]
+use std::arch::x86::*;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+use std::arch::x86_64::*;
+
+/// Computes the forward DCT of `samples` like `transform::fdct` if `level`
+/// has a vectorized kernel, returns false otherwise.
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn fdct(level: SimdLevel, samples: &[u8; 64], coeffs: &mut [i32; 64]) -> bool {
+    match level {
+        SimdLevel::Sse2 | SimdLevel::Avx2 if level.is_supported() => {
+            unsafe { fdct_sse2(samples, coeffs) };
+            true
+        }
+        _ => false
+    }
+}
+
+#[cfg(not(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64"))))]
+pub fn fdct(_: SimdLevel, _: &[u8; 64], _: &mut [i32; 64]) -> bool {
+    false
+}
+
+/// Converts the RGB pixels of `rgb`, each `bpp` bytes, to the samples of
+/// `ys`, `cbs` and `crs` if `level` has a vectorized kernel. Returns the
+/// number of pixels converted, the caller converts the rest.
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn rgb_to_ycbcr(level: SimdLevel, rgb: &[u8], bpp: usize,
+                    ys: &mut [u8], cbs: &mut [u8], crs: &mut [u8]) -> usize {
+    let n = ys.len() / 4 * 4;
+    assert!(rgb.len() >= n * bpp && bpp >= 3 && cbs.len() >= n && crs.len() >= n);
+
+    match level {
+        SimdLevel::Sse2 | SimdLevel::Avx2 if level.is_supported() => {
+            unsafe { rgb_to_ycbcr_sse2(rgb, bpp, n, ys, cbs, crs) };
+            n
+        }
+        _ => 0
+    }
+}
+
+#[cfg(not(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64"))))]
+pub fn rgb_to_ycbcr(_: SimdLevel, _: &[u8], _: usize, _: &mut [u8], _: &mut [u8], _: &mut [u8]) -> usize {
+    0
+}
+
+/// Converts the YCbCr pixels of `pixels`, each `bpp` bytes, to RGB in place
+/// like the scalar code of the decoder if `level` has a vectorized kernel,
+/// or to BGR if `bgr` is set. The alpha of pixels of 4 bytes is set to 255.
+/// Returns the number of pixels converted, the caller converts the rest.
+#[cfg(all(feature = "simd", any(target_arch = "aarch64",
+                               all(target_arch = "wasm32", target_feature = "simd128"))))]
+pub fn ycbcr_to_rgb(level: SimdLevel, pixels: &mut [u8], bpp: usize, bgr: bool) -> usize {
+    assert!(bpp == 3 || bpp == 4);
+
+    match level {
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon if level.is_supported() => {
+            let n = pixels.len() / bpp / 16 * 16;
+            unsafe { neon::ycbcr_to_rgb(&mut pixels[..n * bpp], bpp, bgr) };
+            n
+        }
+        #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+        SimdLevel::Simd128 if level.is_supported() => {
+            let n = pixels.len() / bpp / 8 * 8;
+            unsafe { wasm::ycbcr_to_rgb(&mut pixels[..n * bpp], bpp, bgr) };
+            n
+        }
+        _ => 0
+    }
+}
+
+#[cfg(not(all(feature = "simd", any(target_arch = "aarch64",
+                                   all(target_arch = "wasm32", target_feature = "simd128")))))]
+pub fn ycbcr_to_rgb(_: SimdLevel, _: &mut [u8], _: usize, _: bool) -> usize {
+    0
+}
+
+/// The inverse DCT of the decoder, selected once for its SIMD level. Only
+/// `new` selects a kernel, which the running CPU then supports.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Idct(Kernel);
+
+// The kernels of `Idct`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kernel {
+    /// `transform::idct`
+    Scalar,
+    /// One block at a time with SSE2
+    Sse2,
+    /// Pairs of blocks with AVX2, a remaining block with SSE2
+    Avx2,
+    /// One block at a time with NEON
+    Neon,
+    /// One block at a time with SIMD128
+    Simd128,
+}
+
+impl Idct {
+    /// Selects the kernel of `level`, or of the best level below it that
+    /// the running CPU supports
+    pub fn new(level: SimdLevel) -> Idct {
+        Idct(match level.effective() {
+            SimdLevel::Avx2 => Kernel::Avx2,
+            SimdLevel::Sse2 => Kernel::Sse2,
+            SimdLevel::Neon => Kernel::Neon,
+            SimdLevel::Simd128 => Kernel::Simd128,
+            _ => Kernel::Scalar,
+        })
+    }
+
+    /// Transforms the dequantized coefficients of each of `blocks` to the 64
+    /// samples at the same index in `samples`, like `transform::idct`
+    pub fn transform(self, blocks: &[[i32; 64]], samples: &mut [u8]) {
+        assert!(samples.len() >= blocks.len() * 64);
+
+        let done = idct_simd(self, blocks, samples);
+
+        for (block, out) in blocks[done..].iter().zip(samples[done * 64..].chunks_mut(64)) {
+            transform::idct(block, out);
+        }
+    }
+}
+
+// Transforms the blocks the kernel of `idct` handles, returns their number
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64",
+                               all(target_arch = "wasm32", target_feature = "simd128"))))]
+fn idct_simd(idct: Idct, blocks: &[[i32; 64]], samples: &mut [u8]) -> usize {
+    match idct.0 {
+        #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+        Kernel::Sse2 => {
+            for (block, out) in blocks.iter().zip(samples.chunks_mut(64)) {
+                unsafe { idct_sse2(block, out) };
+            }
+            blocks.len()
+        }
+        #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+        Kernel::Avx2 => {
+            let pairs = blocks.len() / 2;
+            for (pair, out) in blocks.chunks(2).zip(samples.chunks_mut(128)).take(pairs) {
+                unsafe { idct_avx2(&pair[0], &pair[1], out) };
+            }
+            if blocks.len() % 2 == 1 {
+                unsafe { idct_sse2(&blocks[2 * pairs], &mut samples[128 * pairs..]) };
+            }
+            blocks.len()
+        }
+        #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+        Kernel::Neon => {
+            for (block, out) in blocks.iter().zip(samples.chunks_mut(64)) {
+                unsafe { neon::idct(block, out) };
+            }
+            blocks.len()
+        }
+        #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+        Kernel::Simd128 => {
+            for (block, out) in blocks.iter().zip(samples.chunks_mut(64)) {
+                unsafe { wasm::idct(block, out) };
+            }
+            blocks.len()
+        }
+        _ => 0
+    }
+}
+
+#[cfg(not(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64",
+                                   all(target_arch = "wasm32", target_feature = "simd128")))))]
+fn idct_simd(_: Idct, _: &[[i32; 64]], _: &mut [u8]) -> usize {
+    0
+}
+
+const CONST_BITS: i32 = 13;
+const PASS1_BITS: i32 = 2;
+
+const FIX_0_298631336: i16 = 2446;
+const FIX_0_390180644: i16 = 3196;
+const FIX_0_541196100: i16 = 4433;
+const FIX_0_765366865: i16 = 6270;
+const FIX_0_899976223: i16 = 7373;
+const FIX_1_175875602: i16 = 9633;
+const FIX_1_501321110: i16 = 12299;
+const FIX_1_847759065: i16 = 15137;
+const FIX_1_961570560: i16 = 16069;
+const FIX_2_053119869: i16 = 16819;
+const FIX_2_562915447: i16 = 20995;
+const FIX_3_072711026: i16 = 25172;
+
+// The low and high halves of 8 lanes widened to 32 bits
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+type Wide = (__m128i, __m128i);
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn fdct_sse2(samples: &[u8; 64], coeffs: &mut [i32; 64]) {
+    let zero = _mm_setzero_si128();
+    let mut rows = [zero; 8];
+
+    for (y, row) in rows.iter_mut().enumerate() {
+        let bytes = _mm_loadl_epi64(samples[y * 8..].as_ptr() as *const __m128i);
+        *row = _mm_unpacklo_epi8(bytes, zero);
+    }
+
+    // Pass 1 processes the rows, thus works on the columns of the samples
+    transpose(&mut rows);
+    let mut columns = pass1(&rows);
+
+    // Pass 2 processes the columns, thus works on the rows of pass 1
+    transpose(&mut columns);
+    pass2(&columns, coeffs);
+}
+
+// The rows of pass 1, scaled by sqrt(8) and 2^PASS1_BITS, from the samples
+// `v` at each column of 8 rows. The results fit 16 bits.
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn pass1(v: &[__m128i; 8]) -> [__m128i; 8] {
+    let shift = CONST_BITS - PASS1_BITS;
+    let round = 1 << (CONST_BITS - PASS1_BITS - 1);
+
+    // Even part
+    let t0 = _mm_add_epi16(v[0], v[7]);
+    let t1 = _mm_add_epi16(v[1], v[6]);
+    let t2 = _mm_add_epi16(v[2], v[5]);
+    let t3 = _mm_add_epi16(v[3], v[4]);
+
+    let t10 = _mm_add_epi16(t0, t3);
+    let t12 = _mm_sub_epi16(t0, t3);
+    let t11 = _mm_add_epi16(t1, t2);
+    let t13 = _mm_sub_epi16(t1, t2);
+
+    let mut out = [_mm_setzero_si128(); 8];
+
+    // Apply unsigned -> signed conversion
+    out[0] = _mm_slli_epi16(_mm_sub_epi16(_mm_add_epi16(t10, t11), _mm_set1_epi16(8 * 128)), 2);
+    out[4] = _mm_slli_epi16(_mm_sub_epi16(t10, t11), 2);
+
+    out[2] = narrow(descale(madd(t12, t13, FIX_0_541196100 + FIX_0_765366865, FIX_0_541196100), round, shift));
+    out[6] = narrow(descale(madd(t12, t13, FIX_0_541196100, FIX_0_541196100 - FIX_1_847759065), round, shift));
+
+    // Odd part
+    let (z12, z13) = odd_products(v, round);
+    let odd = odd_part(v, z12, z13);
+    for (i, o) in odd.iter().enumerate() {
+        out[2 * i + 1] = narrow(descale(*o, 0, shift));
+    }
+
+    out
+}
+
+// The coefficients of pass 2, scaled by 8, from the rows of pass 1 `v`
+// at each of 8 columns
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn pass2(v: &[__m128i; 8], coeffs: &mut [i32; 64]) {
+    let shift = CONST_BITS + PASS1_BITS;
+
+    // Even part
+    let t0 = _mm_add_epi16(v[0], v[7]);
+    let t1 = _mm_add_epi16(v[1], v[6]);
+    let t2 = _mm_add_epi16(v[2], v[5]);
+    let t3 = _mm_add_epi16(v[3], v[4]);
+
+    let t10 = _mm_add_epi16(t0, t3);
+    let t12 = _mm_sub_epi16(t0, t3);
+    let t11 = _mm_add_epi16(t1, t2);
+    let t13 = _mm_sub_epi16(t1, t2);
+
+    // The sum of the DC coefficients overflows 16 bits, thus it is widened
+    let mut out = [(_mm_setzero_si128(), _mm_setzero_si128()); 8];
+    out[0] = descale(madd(t10, t11, 1, 1), 1 << (PASS1_BITS - 1), PASS1_BITS);
+    out[4] = descale(madd(t10, t11, 1, -1), 1 << (PASS1_BITS - 1), PASS1_BITS);
+
+    let round = 1 << (CONST_BITS + PASS1_BITS - 1);
+    out[2] = descale(madd(t12, t13, FIX_0_541196100 + FIX_0_765366865, FIX_0_541196100), round, shift);
+    out[6] = descale(madd(t12, t13, FIX_0_541196100, FIX_0_541196100 - FIX_1_847759065), round, shift);
+
+    // Odd part, rounded like the scalar code
+    let (z12, z13) = odd_products(v, 1 << (CONST_BITS - PASS1_BITS - 1));
+    let odd = odd_part(v, z12, z13);
+    for (i, o) in odd.iter().enumerate() {
+        out[2 * i + 1] = descale(*o, 0, shift);
+    }
+
+    for (k, &(lo, hi)) in out.iter().enumerate() {
+        _mm_storeu_si128(coeffs[k * 8..].as_mut_ptr() as *mut __m128i, lo);
+        _mm_storeu_si128(coeffs[k * 8 + 4..].as_mut_ptr() as *mut __m128i, hi);
+    }
+}
+
+// The terms `t12` and `t13` of the odd part shared by two outputs each, with
+// the rounding of the final descale
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn odd_products(v: &[__m128i; 8], round: i32) -> (Wide, Wide) {
+    let t12 = _mm_add_epi16(_mm_sub_epi16(v[0], v[7]), _mm_sub_epi16(v[2], v[5]));
+    let t13 = _mm_add_epi16(_mm_sub_epi16(v[1], v[6]), _mm_sub_epi16(v[3], v[4]));
+
+    // z1 = (t12 + t13) * FIX_1_175875602 is distributed over both products
+    let z12 = madd(t12, t13, FIX_1_175875602 - FIX_0_390180644, FIX_1_175875602);
+    let z13 = madd(t12, t13, FIX_1_175875602, FIX_1_175875602 - FIX_1_961570560);
+
+    (add(z12, splat(round)), add(z13, splat(round)))
+}
+
+// The undescaled coefficients 1, 3, 5 and 7
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn odd_part(v: &[__m128i; 8], z12: Wide, z13: Wide) -> [Wide; 4] {
+    let t0 = _mm_sub_epi16(v[0], v[7]);
+    let t1 = _mm_sub_epi16(v[1], v[6]);
+    let t2 = _mm_sub_epi16(v[2], v[5]);
+    let t3 = _mm_sub_epi16(v[3], v[4]);
+
+    [
+        add(madd(t0, t3, FIX_1_501321110 - FIX_0_899976223, -FIX_0_899976223), z12),
+        add(madd(t1, t2, FIX_3_072711026 - FIX_2_562915447, -FIX_2_562915447), z13),
+        add(madd(t1, t2, -FIX_2_562915447, FIX_2_053119869 - FIX_2_562915447), z12),
+        add(madd(t0, t3, -FIX_0_899976223, FIX_0_298631336 - FIX_0_899976223), z13),
+    ]
+}
+
+// `a * c0 + b * c1` in 32 bits
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn madd(a: __m128i, b: __m128i, c0: i16, c1: i16) -> Wide {
+    let c = _mm_set_epi16(c1, c0, c1, c0, c1, c0, c1, c0);
+    (_mm_madd_epi16(_mm_unpacklo_epi16(a, b), c), _mm_madd_epi16(_mm_unpackhi_epi16(a, b), c))
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn add(a: Wide, b: Wide) -> Wide {
+    (_mm_add_epi32(a.0, b.0), _mm_add_epi32(a.1, b.1))
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn splat(v: i32) -> Wide {
+    (_mm_set1_epi32(v), _mm_set1_epi32(v))
+}
+
+// Adds `round` and shifts right by `shift` bits
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn descale(a: Wide, round: i32, shift: i32) -> Wide {
+    let (a, count) = (add(a, splat(round)), _mm_cvtsi32_si128(shift));
+    (_mm_sra_epi32(a.0, count), _mm_sra_epi32(a.1, count))
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn narrow(a: Wide) -> __m128i {
+    _mm_packs_epi32(a.0, a.1)
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn transpose(v: &mut [__m128i; 8]) {
+    let a0 = _mm_unpacklo_epi16(v[0], v[1]);
+    let a1 = _mm_unpackhi_epi16(v[0], v[1]);
+    let a2 = _mm_unpacklo_epi16(v[2], v[3]);
+    let a3 = _mm_unpackhi_epi16(v[2], v[3]);
+    let a4 = _mm_unpacklo_epi16(v[4], v[5]);
+    let a5 = _mm_unpackhi_epi16(v[4], v[5]);
+    let a6 = _mm_unpacklo_epi16(v[6], v[7]);
+    let a7 = _mm_unpackhi_epi16(v[6], v[7]);
+
+    let b0 = _mm_unpacklo_epi32(a0, a2);
+    let b1 = _mm_unpackhi_epi32(a0, a2);
+    let b2 = _mm_unpacklo_epi32(a1, a3);
+    let b3 = _mm_unpackhi_epi32(a1, a3);
+    let b4 = _mm_unpacklo_epi32(a4, a6);
+    let b5 = _mm_unpackhi_epi32(a4, a6);
+    let b6 = _mm_unpacklo_epi32(a5, a7);
+    let b7 = _mm_unpackhi_epi32(a5, a7);
+
+    v[0] = _mm_unpacklo_epi64(b0, b4);
+    v[1] = _mm_unpackhi_epi64(b0, b4);
+    v[2] = _mm_unpacklo_epi64(b1, b5);
+    v[3] = _mm_unpackhi_epi64(b1, b5);
+    v[4] = _mm_unpacklo_epi64(b2, b6);
+    v[5] = _mm_unpackhi_epi64(b2, b6);
+    v[6] = _mm_unpacklo_epi64(b3, b7);
+    v[7] = _mm_unpackhi_epi64(b3, b7);
+}
+
+// One pass of the inverse DCT over the 8 rows `$v`, each lane of which
+// holds a column, with the operations of `transform::idct` on the vectors
+// of the kernel. The rounding of the final shift is added to the DC term.
+macro_rules! idct_pass {
+    ($v:expr, $round:expr, $shift:expr, $add:ident, $sub:ident, $mul:ident, $shl:ident, $sra:ident, $splat:ident) => {{
+        let v = &$v;
+
+        // Even part
+        let z1 = $mul($add(v[2], v[6]), FIX_0_541196100);
+        let t2 = $add(z1, $mul(v[2], FIX_0_765366865));
+        let t3 = $sub(z1, $mul(v[6], FIX_1_847759065));
+
+        let t0 = $add($shl($add(v[0], v[4])), $splat($round));
+        let t1 = $add($shl($sub(v[0], v[4])), $splat($round));
+
+        let t10 = $add(t0, t2);
+        let t13 = $sub(t0, t2);
+        let t11 = $add(t1, t3);
+        let t12 = $sub(t1, t3);
+
+        // Odd part
+        let z2 = $add(v[7], v[3]);
+        let z3 = $add(v[5], v[1]);
+
+        let z1 = $mul($add(z2, z3), FIX_1_175875602);
+        let z2 = $add($mul(z2, -FIX_1_961570560), z1);
+        let z3 = $add($mul(z3, -FIX_0_390180644), z1);
+
+        let z1 = $mul($add(v[7], v[1]), -FIX_0_899976223);
+        let o0 = $add($add($mul(v[7], FIX_0_298631336), z1), z2);
+        let o3 = $add($add($mul(v[1], FIX_1_501321110), z1), z3);
+
+        let z1 = $mul($add(v[5], v[3]), -FIX_2_562915447);
+        let o1 = $add($add($mul(v[5], FIX_2_053119869), z1), z3);
+        let o2 = $add($add($mul(v[3], FIX_3_072711026), z1), z2);
+
+        [
+            $sra($add(t10, o3), $shift), $sra($add(t11, o2), $shift),
+            $sra($add(t12, o1), $shift), $sra($add(t13, o0), $shift),
+            $sra($sub(t13, o0), $shift), $sra($sub(t12, o1), $shift),
+            $sra($sub(t11, o2), $shift), $sra($sub(t10, o3), $shift),
+        ]
+    }}
+}
+
+// The rounding and shift of the first and second pass of the inverse DCT
+const IDCT_ROUND1: i32 = 1 << (CONST_BITS - PASS1_BITS - 1);
+const IDCT_SHIFT1: i32 = CONST_BITS - PASS1_BITS;
+const IDCT_ROUND2: i32 = 1 << (CONST_BITS + PASS1_BITS + 2);
+const IDCT_SHIFT2: i32 = CONST_BITS + PASS1_BITS + 3;
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn idct_sse2(coeffs: &[i32; 64], samples: &mut [u8]) {
+    let zero = _mm_setzero_si128();
+    let mut rows = [(zero, zero); 8];
+
+    for (y, row) in rows.iter_mut().enumerate() {
+        *row = (_mm_loadu_si128(coeffs[y * 8..].as_ptr() as *const __m128i),
+                _mm_loadu_si128(coeffs[y * 8 + 4..].as_ptr() as *const __m128i));
+    }
+
+    // Pass 1 processes the columns, pass 2 the rows
+    let mut columns = idct_pass!(rows, IDCT_ROUND1, IDCT_SHIFT1, add, sub, mul, shl, sra, splat);
+    transpose_wide(&mut columns);
+    let mut rows = idct_pass!(columns, IDCT_ROUND2, IDCT_SHIFT2, add, sub, mul, shl, sra, splat);
+    transpose_wide(&mut rows);
+
+    for (y, &row) in rows.iter().enumerate() {
+        let (lo, hi) = add(row, splat(128));
+        let bytes = _mm_packus_epi16(_mm_packs_epi32(lo, hi), zero);
+        _mm_storel_epi64(samples[y * 8..y * 8 + 8].as_mut_ptr() as *mut __m128i, bytes);
+    }
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn idct_avx2(a: &[i32; 64], b: &[i32; 64], samples: &mut [u8]) {
+    let zero = _mm256_setzero_si256();
+    let (mut rows_a, mut rows_b) = ([zero; 8], [zero; 8]);
+
+    for y in (0..8) {
+        rows_a[y] = _mm256_loadu_si256(a[y * 8..].as_ptr() as *const __m256i);
+        rows_b[y] = _mm256_loadu_si256(b[y * 8..].as_ptr() as *const __m256i);
+    }
+
+    // The passes of both blocks are independent, thus they overlap
+    let mut columns_a = idct_pass!(rows_a, IDCT_ROUND1, IDCT_SHIFT1, add8, sub8, mul8, shl8, sra8, splat8);
+    let mut columns_b = idct_pass!(rows_b, IDCT_ROUND1, IDCT_SHIFT1, add8, sub8, mul8, shl8, sra8, splat8);
+    transpose8(&mut columns_a);
+    transpose8(&mut columns_b);
+
+    let mut rows_a = idct_pass!(columns_a, IDCT_ROUND2, IDCT_SHIFT2, add8, sub8, mul8, shl8, sra8, splat8);
+    let mut rows_b = idct_pass!(columns_b, IDCT_ROUND2, IDCT_SHIFT2, add8, sub8, mul8, shl8, sra8, splat8);
+    transpose8(&mut rows_a);
+    transpose8(&mut rows_b);
+
+    store8(&rows_a, &mut samples[..64]);
+    store8(&rows_b, &mut samples[64..128]);
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn sub(a: Wide, b: Wide) -> Wide {
+    (_mm_sub_epi32(a.0, b.0), _mm_sub_epi32(a.1, b.1))
+}
+
+// The low 32 bits of the products of `a` and `c`, which SSE2 has no single
+// instruction for
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn mul(a: Wide, c: i16) -> Wide {
+    let c = _mm_set1_epi32(c as i32);
+    let mullo = |v: __m128i| {
+        let even = _mm_mul_epu32(v, c);
+        let odd = _mm_mul_epu32(_mm_srli_epi64(v, 32), c);
+        _mm_unpacklo_epi32(_mm_shuffle_epi32(even, 0b00_00_10_00), _mm_shuffle_epi32(odd, 0b00_00_10_00))
+    };
+
+    (mullo(a.0), mullo(a.1))
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn shl(a: Wide) -> Wide {
+    (_mm_slli_epi32(a.0, CONST_BITS), _mm_slli_epi32(a.1, CONST_BITS))
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn sra(a: Wide, shift: i32) -> Wide {
+    let count = _mm_cvtsi32_si128(shift);
+    (_mm_sra_epi32(a.0, count), _mm_sra_epi32(a.1, count))
+}
+
+// Transposes the 8x8 32 bit lanes of `v` as four 4x4 quarters
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn transpose_wide(v: &mut [Wide; 8]) {
+    let quarter = |a: __m128i, b: __m128i, c: __m128i, d: __m128i| {
+        let (t0, t1) = (_mm_unpacklo_epi32(a, b), _mm_unpackhi_epi32(a, b));
+        let (t2, t3) = (_mm_unpacklo_epi32(c, d), _mm_unpackhi_epi32(c, d));
+        [_mm_unpacklo_epi64(t0, t2), _mm_unpackhi_epi64(t0, t2),
+         _mm_unpacklo_epi64(t1, t3), _mm_unpackhi_epi64(t1, t3)]
+    };
+
+    let top_left = quarter(v[0].0, v[1].0, v[2].0, v[3].0);
+    let top_right = quarter(v[0].1, v[1].1, v[2].1, v[3].1);
+    let bottom_left = quarter(v[4].0, v[5].0, v[6].0, v[7].0);
+    let bottom_right = quarter(v[4].1, v[5].1, v[6].1, v[7].1);
+
+    for i in (0..4) {
+        v[i] = (top_left[i], bottom_left[i]);
+        v[i + 4] = (top_right[i], bottom_right[i]);
+    }
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn add8(a: __m256i, b: __m256i) -> __m256i {
+    _mm256_add_epi32(a, b)
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn sub8(a: __m256i, b: __m256i) -> __m256i {
+    _mm256_sub_epi32(a, b)
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn mul8(a: __m256i, c: i16) -> __m256i {
+    _mm256_mullo_epi32(a, _mm256_set1_epi32(c as i32))
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn shl8(a: __m256i) -> __m256i {
+    _mm256_slli_epi32(a, CONST_BITS)
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn sra8(a: __m256i, shift: i32) -> __m256i {
+    _mm256_sra_epi32(a, _mm_cvtsi32_si128(shift))
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn splat8(v: i32) -> __m256i {
+    _mm256_set1_epi32(v)
+}
+
+// Transposes 8x8 32 bit lanes, the 128 bit halves of the vectors last
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn transpose8(v: &mut [__m256i; 8]) {
+    let mut t = [_mm256_setzero_si256(); 8];
+    for i in (0..4) {
+        t[2 * i] = _mm256_unpacklo_epi32(v[2 * i], v[2 * i + 1]);
+        t[2 * i + 1] = _mm256_unpackhi_epi32(v[2 * i], v[2 * i + 1]);
+    }
+
+    let mut u = [_mm256_setzero_si256(); 8];
+    for i in (0..2) {
+        let (a, b, c, d) = (t[4 * i], t[4 * i + 1], t[4 * i + 2], t[4 * i + 3]);
+        u[4 * i] = _mm256_unpacklo_epi64(a, c);
+        u[4 * i + 1] = _mm256_unpackhi_epi64(a, c);
+        u[4 * i + 2] = _mm256_unpacklo_epi64(b, d);
+        u[4 * i + 3] = _mm256_unpackhi_epi64(b, d);
+    }
+
+    for i in (0..4) {
+        v[i] = _mm256_permute2x128_si256(u[i], u[i + 4], 0x20);
+        v[i + 4] = _mm256_permute2x128_si256(u[i], u[i + 4], 0x31);
+    }
+}
+
+// Level shifts and saturates the 8 rows of `v` to the bytes of `samples`
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "avx2")]
+unsafe fn store8(v: &[__m256i; 8], samples: &mut [u8]) {
+    let shift = _mm256_set1_epi32(128);
+    let order = _mm256_setr_epi32(0, 4, 1, 5, 2, 6, 3, 7);
+
+    for (i, out) in samples.chunks_mut(32).enumerate() {
+        let r = &v[4 * i..4 * i + 4];
+        let words01 = _mm256_packs_epi32(_mm256_add_epi32(r[0], shift), _mm256_add_epi32(r[1], shift));
+        let words23 = _mm256_packs_epi32(_mm256_add_epi32(r[2], shift), _mm256_add_epi32(r[3], shift));
+
+        // The packs interleave the halves of the rows within each 128 bit lane
+        let bytes = _mm256_permutevar8x32_epi32(_mm256_packus_epi16(words01, words23), order);
+        _mm256_storeu_si256(out.as_mut_ptr() as *mut __m256i, bytes);
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::{CONST_BITS, IDCT_ROUND1, IDCT_ROUND2, IDCT_SHIFT1, IDCT_SHIFT2};
+    use super::{FIX_0_298631336, FIX_0_390180644, FIX_0_541196100, FIX_0_765366865, FIX_0_899976223,
+                FIX_1_175875602, FIX_1_501321110, FIX_1_847759065, FIX_1_961570560, FIX_2_053119869,
+                FIX_2_562915447, FIX_3_072711026};
+    use super::super::decoder::{F_0_344, F_0_402, F_0_714, F_0_772};
+
+    // The left and right halves of a row of 8 lanes of 32 bits
+    type Pair = (int32x4_t, int32x4_t);
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn idct(coeffs: &[i32; 64], samples: &mut [u8]) {
+        let zero = vdupq_n_s32(0);
+        let mut rows = [(zero, zero); 8];
+
+        for (y, row) in rows.iter_mut().enumerate() {
+            *row = (vld1q_s32(coeffs[y * 8..].as_ptr()), vld1q_s32(coeffs[y * 8 + 4..].as_ptr()));
+        }
+
+        // Pass 1 processes the columns, pass 2 the rows
+        let mut columns = idct_pass!(rows, IDCT_ROUND1, IDCT_SHIFT1, add, sub, mul, shl, sra, splat);
+        transpose(&mut columns);
+        let mut rows = idct_pass!(columns, IDCT_ROUND2, IDCT_SHIFT2, add, sub, mul, shl, sra, splat);
+        transpose(&mut rows);
+
+        for (y, &row) in rows.iter().enumerate() {
+            let (left, right) = add(row, splat(128));
+            let words = vcombine_s16(vqmovn_s32(left), vqmovn_s32(right));
+            vst1_u8(samples[y * 8..y * 8 + 8].as_mut_ptr(), vqmovun_s16(words));
+        }
+    }
+
+    // Converts 16 pixels at a time in two halves of 8 lanes of 16 bits.
+    // The rounding multiply gives the fractions of red and blue like the
+    // scalar code, green needs the sum of two products in 32 bits.
+    #[target_feature(enable = "neon")]
+    pub unsafe fn ycbcr_to_rgb(pixels: &mut [u8], bpp: usize, bgr: bool) {
+        for chunk in pixels.chunks_mut(16 * bpp) {
+            let (y, cb, cr) = if bpp == 4 {
+                let v = vld4q_u8(chunk.as_ptr());
+                (v.0, v.1, v.2)
+            } else {
+                let v = vld3q_u8(chunk.as_ptr());
+                (v.0, v.1, v.2)
+            };
+
+            let (r0, g0, b0) = convert(vget_low_u8(y), vget_low_u8(cb), vget_low_u8(cr));
+            let (r1, g1, b1) = convert(vget_high_u8(y), vget_high_u8(cb), vget_high_u8(cr));
+            let (r, g, b) = (vcombine_u8(r0, r1), vcombine_u8(g0, g1), vcombine_u8(b0, b1));
+            let (first, third) = if bgr { (b, r) } else { (r, b) };
+
+            if bpp == 4 {
+                vst4q_u8(chunk.as_mut_ptr(), uint8x16x4_t(first, g, third, vdupq_n_u8(255)));
+            } else {
+                vst3q_u8(chunk.as_mut_ptr(), uint8x16x3_t(first, g, third));
+            }
+        }
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn convert(y: uint8x8_t, cb: uint8x8_t, cr: uint8x8_t) -> (uint8x8_t, uint8x8_t, uint8x8_t) {
+        let widen = |v: uint8x8_t| vreinterpretq_s16_u16(vmovl_u8(v));
+        let y = widen(y);
+        let cb = vsubq_s16(widen(cb), vdupq_n_s16(128));
+        let cr = vsubq_s16(widen(cr), vdupq_n_s16(128));
+
+        let r = vaddq_s16(vaddq_s16(y, cr), vqrdmulhq_n_s16(cr, F_0_402 as i16));
+        let b = vaddq_s16(vaddq_s16(y, cb), vqrdmulhq_n_s16(cb, F_0_772 as i16));
+
+        let green = |cb: int16x4_t, cr: int16x4_t| {
+            vrshrn_n_s32(vmlal_n_s16(vmull_n_s16(cb, F_0_344 as i16), cr, F_0_714 as i16), 15)
+        };
+        let g = vsubq_s16(y, vcombine_s16(green(vget_low_s16(cb), vget_low_s16(cr)),
+                                          green(vget_high_s16(cb), vget_high_s16(cr))));
+
+        (vqmovun_s16(r), vqmovun_s16(g), vqmovun_s16(b))
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn add(a: Pair, b: Pair) -> Pair {
+        (vaddq_s32(a.0, b.0), vaddq_s32(a.1, b.1))
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn sub(a: Pair, b: Pair) -> Pair {
+        (vsubq_s32(a.0, b.0), vsubq_s32(a.1, b.1))
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn mul(a: Pair, c: i16) -> Pair {
+        (vmulq_n_s32(a.0, c as i32), vmulq_n_s32(a.1, c as i32))
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn shl(a: Pair) -> Pair {
+        let count = vdupq_n_s32(CONST_BITS);
+        (vshlq_s32(a.0, count), vshlq_s32(a.1, count))
+    }
+
+    // Shifts right arithmetically, as a left shift by a negative count
+    #[target_feature(enable = "neon")]
+    unsafe fn sra(a: Pair, shift: i32) -> Pair {
+        let count = vdupq_n_s32(-shift);
+        (vshlq_s32(a.0, count), vshlq_s32(a.1, count))
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn splat(v: i32) -> Pair {
+        (vdupq_n_s32(v), vdupq_n_s32(v))
+    }
+
+    // Transposes the 8x8 lanes of `v` as four 4x4 quarters
+    #[target_feature(enable = "neon")]
+    unsafe fn transpose(v: &mut [Pair; 8]) {
+        let quarter = |a: int32x4_t, b: int32x4_t, c: int32x4_t, d: int32x4_t| {
+            let (t0, t1) = (vreinterpretq_s64_s32(vtrn1q_s32(a, b)), vreinterpretq_s64_s32(vtrn2q_s32(a, b)));
+            let (t2, t3) = (vreinterpretq_s64_s32(vtrn1q_s32(c, d)), vreinterpretq_s64_s32(vtrn2q_s32(c, d)));
+
+            [vreinterpretq_s32_s64(vtrn1q_s64(t0, t2)), vreinterpretq_s32_s64(vtrn1q_s64(t1, t3)),
+             vreinterpretq_s32_s64(vtrn2q_s64(t0, t2)), vreinterpretq_s32_s64(vtrn2q_s64(t1, t3))]
+        };
+
+        let top_left = quarter(v[0].0, v[1].0, v[2].0, v[3].0);
+        let top_right = quarter(v[0].1, v[1].1, v[2].1, v[3].1);
+        let bottom_left = quarter(v[4].0, v[5].0, v[6].0, v[7].0);
+        let bottom_right = quarter(v[4].1, v[5].1, v[6].1, v[7].1);
+
+        for i in (0..4) {
+            v[i] = (top_left[i], bottom_left[i]);
+            v[i + 4] = (top_right[i], bottom_right[i]);
+        }
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+mod wasm {
+    use std::arch::wasm32::*;
+
+    use super::{CONST_BITS, IDCT_ROUND1, IDCT_ROUND2, IDCT_SHIFT1, IDCT_SHIFT2};
+    use super::{FIX_0_298631336, FIX_0_390180644, FIX_0_541196100, FIX_0_765366865, FIX_0_899976223,
+                FIX_1_175875602, FIX_1_501321110, FIX_1_847759065, FIX_1_961570560, FIX_2_053119869,
+                FIX_2_562915447, FIX_3_072711026};
+    use super::super::decoder::{F_0_344, F_0_402, F_0_714, F_0_772};
+
+    // The left and right halves of a row of 8 lanes of 32 bits
+    type Pair = (v128, v128);
+
+    #[target_feature(enable = "simd128")]
+    pub unsafe fn idct(coeffs: &[i32; 64], samples: &mut [u8]) {
+        let zero = i32x4_splat(0);
+        let mut rows = [(zero, zero); 8];
+
+        for (y, row) in rows.iter_mut().enumerate() {
+            *row = (v128_load(coeffs[y * 8..].as_ptr() as *const v128),
+                    v128_load(coeffs[y * 8 + 4..].as_ptr() as *const v128));
+        }
+
+        // Pass 1 processes the columns, pass 2 the rows
+        let mut columns = idct_pass!(rows, IDCT_ROUND1, IDCT_SHIFT1, add, sub, mul, shl, sra, splat);
+        transpose(&mut columns);
+        let mut rows = idct_pass!(columns, IDCT_ROUND2, IDCT_SHIFT2, add, sub, mul, shl, sra, splat);
+        transpose(&mut rows);
+
+        for (y, &row) in rows.iter().enumerate() {
+            let (left, right) = add(row, splat(128));
+            let words = i16x8_narrow_i32x4(left, right);
+            v128_store64_lane::<0>(u8x16_narrow_i16x8(words, words), samples[y * 8..y * 8 + 8].as_mut_ptr() as *mut u64);
+        }
+    }
+
+    // Converts 8 pixels at a time, which are gathered from and scattered
+    // back to their interleaved bytes with shuffles. The rounding multiply
+    // gives the fractions of red and blue like the scalar code, green needs
+    // the sum of two products in 32 bits.
+    #[target_feature(enable = "simd128")]
+    pub unsafe fn ycbcr_to_rgb(pixels: &mut [u8], bpp: usize, bgr: bool) {
+        for chunk in pixels.chunks_mut(8 * bpp) {
+            let p = chunk.as_mut_ptr();
+
+            let (y, cb, cr) = if bpp == 4 {
+                let (a, b) = (v128_load(p as *const v128), v128_load(p.offset(16) as *const v128));
+                (u8x16_shuffle::<0, 4, 8, 12, 16, 20, 24, 28, 0, 0, 0, 0, 0, 0, 0, 0>(a, b),
+                 u8x16_shuffle::<1, 5, 9, 13, 17, 21, 25, 29, 0, 0, 0, 0, 0, 0, 0, 0>(a, b),
+                 u8x16_shuffle::<2, 6, 10, 14, 18, 22, 26, 30, 0, 0, 0, 0, 0, 0, 0, 0>(a, b))
+            } else {
+                let (a, b) = (v128_load(p as *const v128), v128_load64_zero(p.offset(16) as *const u64));
+                (u8x16_shuffle::<0, 3, 6, 9, 12, 15, 18, 21, 0, 0, 0, 0, 0, 0, 0, 0>(a, b),
+                 u8x16_shuffle::<1, 4, 7, 10, 13, 16, 19, 22, 0, 0, 0, 0, 0, 0, 0, 0>(a, b),
+                 u8x16_shuffle::<2, 5, 8, 11, 14, 17, 20, 23, 0, 0, 0, 0, 0, 0, 0, 0>(a, b))
+            };
+
+            let (r, g, b) = convert(y, cb, cr);
+            let (first, third) = if bgr { (b, r) } else { (r, b) };
+
+            if bpp == 4 {
+                let low = u8x16_shuffle::<0, 16, 1, 17, 2, 18, 3, 19, 4, 20, 5, 21, 6, 22, 7, 23>(first, g);
+                let high = u8x16_shuffle::<0, 16, 1, 17, 2, 18, 3, 19, 4, 20, 5, 21, 6, 22, 7, 23>(third, u8x16_splat(255));
+                v128_store(p as *mut v128, i16x8_shuffle::<0, 8, 1, 9, 2, 10, 3, 11>(low, high));
+                v128_store(p.offset(16) as *mut v128, i16x8_shuffle::<4, 12, 5, 13, 6, 14, 7, 15>(low, high));
+            } else {
+                // The first two channels in one vector, the third in the other
+                let pairs = u8x16_shuffle::<0, 1, 2, 3, 4, 5, 6, 7, 16, 17, 18, 19, 20, 21, 22, 23>(first, g);
+                v128_store(p as *mut v128,
+                           u8x16_shuffle::<0, 8, 16, 1, 9, 17, 2, 10, 18, 3, 11, 19, 4, 12, 20, 5>(pairs, third));
+                v128_store64_lane::<0>(u8x16_shuffle::<13, 21, 6, 14, 22, 7, 15, 23, 0, 0, 0, 0, 0, 0, 0, 0>(pairs, third),
+                                       p.offset(16) as *mut u64);
+            }
+        }
+    }
+
+    // Converts the 8 pixels in the low bytes of `y`, `cb` and `cr` to the
+    // low bytes of red, green and blue
+    #[target_feature(enable = "simd128")]
+    unsafe fn convert(y: v128, cb: v128, cr: v128) -> (v128, v128, v128) {
+        let y = u16x8_extend_low_u8x16(y);
+        let cb = i16x8_sub(u16x8_extend_low_u8x16(cb), i16x8_splat(128));
+        let cr = i16x8_sub(u16x8_extend_low_u8x16(cr), i16x8_splat(128));
+
+        let r = i16x8_add(i16x8_add(y, cr), i16x8_q15mulr_sat(cr, i16x8_splat(F_0_402 as i16)));
+        let b = i16x8_add(i16x8_add(y, cb), i16x8_q15mulr_sat(cb, i16x8_splat(F_0_772 as i16)));
+
+        let (k_cb, k_cr, round) = (i16x8_splat(F_0_344 as i16), i16x8_splat(F_0_714 as i16), i32x4_splat(1 << 14));
+        let low = i32x4_add(i32x4_extmul_low_i16x8(cb, k_cb), i32x4_extmul_low_i16x8(cr, k_cr));
+        let high = i32x4_add(i32x4_extmul_high_i16x8(cb, k_cb), i32x4_extmul_high_i16x8(cr, k_cr));
+        let green = i16x8_narrow_i32x4(i32x4_shr(i32x4_add(low, round), 15), i32x4_shr(i32x4_add(high, round), 15));
+        let g = i16x8_sub(y, green);
+
+        (u8x16_narrow_i16x8(r, r), u8x16_narrow_i16x8(g, g), u8x16_narrow_i16x8(b, b))
+    }
+
+    #[target_feature(enable = "simd128")]
+    unsafe fn add(a: Pair, b: Pair) -> Pair {
+        (i32x4_add(a.0, b.0), i32x4_add(a.1, b.1))
+    }
+
+    #[target_feature(enable = "simd128")]
+    unsafe fn sub(a: Pair, b: Pair) -> Pair {
+        (i32x4_sub(a.0, b.0), i32x4_sub(a.1, b.1))
+    }
+
+    #[target_feature(enable = "simd128")]
+    unsafe fn mul(a: Pair, c: i16) -> Pair {
+        let c = i32x4_splat(c as i32);
+        (i32x4_mul(a.0, c), i32x4_mul(a.1, c))
+    }
+
+    #[target_feature(enable = "simd128")]
+    unsafe fn shl(a: Pair) -> Pair {
+        (i32x4_shl(a.0, CONST_BITS as u32), i32x4_shl(a.1, CONST_BITS as u32))
+    }
+
+    // `i32x4_shr` shifts arithmetically, `u32x4_shr` logically
+    #[target_feature(enable = "simd128")]
+    unsafe fn sra(a: Pair, shift: i32) -> Pair {
+        (i32x4_shr(a.0, shift as u32), i32x4_shr(a.1, shift as u32))
+    }
+
+    #[target_feature(enable = "simd128")]
+    unsafe fn splat(v: i32) -> Pair {
+        (i32x4_splat(v), i32x4_splat(v))
+    }
+
+    // Transposes the 8x8 lanes of `v` as four 4x4 quarters
+    #[target_feature(enable = "simd128")]
+    unsafe fn transpose(v: &mut [Pair; 8]) {
+        let quarter = |a: v128, b: v128, c: v128, d: v128| {
+            let (t0, t1) = (i32x4_shuffle::<0, 4, 1, 5>(a, b), i32x4_shuffle::<2, 6, 3, 7>(a, b));
+            let (t2, t3) = (i32x4_shuffle::<0, 4, 1, 5>(c, d), i32x4_shuffle::<2, 6, 3, 7>(c, d));
+
+            [i64x2_shuffle::<0, 2>(t0, t2), i64x2_shuffle::<1, 3>(t0, t2),
+             i64x2_shuffle::<0, 2>(t1, t3), i64x2_shuffle::<1, 3>(t1, t3)]
+        };
+
+        let top_left = quarter(v[0].0, v[1].0, v[2].0, v[3].0);
+        let top_right = quarter(v[0].1, v[1].1, v[2].1, v[3].1);
+        let bottom_left = quarter(v[4].0, v[5].0, v[6].0, v[7].0);
+        let bottom_right = quarter(v[4].1, v[5].1, v[6].1, v[7].1);
+
+        for i in (0..4) {
+            v[i] = (top_left[i], bottom_left[i]);
+            v[i + 4] = (top_right[i], bottom_right[i]);
+        }
+    }
+}
+
+// Converts 4 pixels at a time in single precision, with the operations in
+// the order of the scalar code to round alike
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn rgb_to_ycbcr_sse2(rgb: &[u8], bpp: usize, n: usize,
+                            ys: &mut [u8], cbs: &mut [u8], crs: &mut [u8]) {
+    let channel = |i: usize, c: usize| _mm_set_ps(rgb[(i + 3) * bpp + c] as f32, rgb[(i + 2) * bpp + c] as f32,
+                                                  rgb[(i + 1) * bpp + c] as f32, rgb[i * bpp + c] as f32);
+    let k = |v: f32| _mm_set1_ps(v);
+
+    for i in (0..n / 4).map(|i| i * 4) {
+        let (r, g, b) = (channel(i, 0), channel(i, 1), channel(i, 2));
+
+        let y = _mm_add_ps(_mm_add_ps(_mm_mul_ps(k(0.299), r), _mm_mul_ps(k(0.587), g)),
+                           _mm_mul_ps(k(0.114), b));
+        let cb = _mm_add_ps(_mm_add_ps(_mm_sub_ps(_mm_mul_ps(k(-0.1687), r), _mm_mul_ps(k(0.3313), g)),
+                                       _mm_mul_ps(k(0.5), b)), k(128.0));
+        let cr = _mm_add_ps(_mm_sub_ps(_mm_sub_ps(_mm_mul_ps(k(0.5), r), _mm_mul_ps(k(0.4187), g)),
+                                       _mm_mul_ps(k(0.0813), b)), k(128.0));
+
+        store4(y, &mut ys[i..i + 4]);
+        store4(cb, &mut cbs[i..i + 4]);
+        store4(cr, &mut crs[i..i + 4]);
+    }
+}
+
+// Truncates and saturates the 4 floats of `v` to bytes, like `as u8`
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn store4(v: __m128, out: &mut [u8]) {
+    let words = _mm_packs_epi32(_mm_cvttps_epi32(v), _mm_setzero_si128());
+    let bytes = _mm_cvtsi128_si32(_mm_packus_epi16(words, _mm_setzero_si128())) as u32;
+
+    for (i, o) in out.iter_mut().enumerate() {
+        *o = (bytes >> (8 * i)) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::SimdLevel;
+    use super::super::transform;
+    use super::{Idct, Kernel};
+
+    #[test]
+    fn test_fdct() {
+        if !SimdLevel::Sse2.is_supported() {
+            return
+        }
+
+        let mut state = 12345u32;
+        let mut blocks = vec![[0u8; 64], [255u8; 64]];
+        let mut checkerboard = [0u8; 64];
+        for (i, s) in checkerboard.iter_mut().enumerate() {
+            *s = if (i / 8 + i % 8) % 2 == 0 { 255 } else { 0 };
+        }
+        blocks.push(checkerboard);
+
+        for _ in (0..1000) {
+            let mut block = [0u8; 64];
+            for s in block.iter_mut() {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                *s = (state >> 16) as u8;
+            }
+            blocks.push(block);
+        }
+
+        for block in blocks.iter() {
+            let (mut scalar, mut simd) = ([0i32; 64], [0i32; 64]);
+            transform::fdct(block, &mut scalar);
+            assert!(super::fdct(SimdLevel::Sse2, block, &mut simd));
+            assert_eq!(&scalar[..], &simd[..]);
+        }
+
+        assert!(!super::fdct(SimdLevel::Scalar, &blocks[0], &mut [0i32; 64]));
+    }
+
+    #[test]
+    fn test_idct() {
+        let mut state = 54321u32;
+        let mut blocks = vec![[0i32; 64], [-100; 64]];
+        let mut dc = [0i32; 64];
+        dc[0] = 1016 * 8;
+        blocks.push(dc);
+
+        // Dequantized coefficients of either sign in the ranges of real
+        // images, an odd number of blocks to leave one for the SSE2 kernel
+        // at the AVX2 level
+        for _ in (0..999) {
+            let mut block = [0i32; 64];
+            for (i, c) in block.iter_mut().enumerate() {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                let range = if i == 0 { 8192 } else { 256 >> (i / 16) };
+                *c = (state >> 16) as i32 % (2 * range) - range;
+            }
+            blocks.push(block);
+        }
+
+        let mut expected = vec![0u8; blocks.len() * 64];
+        for (block, out) in blocks.iter().zip(expected.chunks_mut(64)) {
+            transform::idct(block, out);
+        }
+
+        for &level in [SimdLevel::Scalar, SimdLevel::Sse2, SimdLevel::Avx2, SimdLevel::Neon, SimdLevel::Simd128].iter() {
+            let idct = Idct::new(level);
+            let mut samples = vec![0u8; blocks.len() * 64];
+            idct.transform(&blocks, &mut samples);
+            assert!(samples == expected, "{:?}", idct);
+        }
+    }
+
+    #[test]
+    fn test_idct_selection() {
+        assert_eq!(Idct::new(SimdLevel::Scalar), Idct(Kernel::Scalar));
+        assert_eq!(Idct::new(SimdLevel::Neon) == Idct(Kernel::Neon), SimdLevel::Neon.is_supported());
+        assert_eq!(Idct::new(SimdLevel::Simd128) == Idct(Kernel::Simd128), SimdLevel::Simd128.is_supported());
+
+        let expected = Idct(match SimdLevel::Avx2.effective() {
+            SimdLevel::Avx2 => Kernel::Avx2,
+            SimdLevel::Sse2 => Kernel::Sse2,
+            _ => Kernel::Scalar,
+        });
+        assert_eq!(Idct::new(SimdLevel::Avx2), expected);
+    }
+}
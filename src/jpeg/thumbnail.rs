@@ -0,0 +1,239 @@
+//! Extraction of embedded thumbnails
+//!
+//! Cameras and many editors store a small preview of the image in the
+//! EXIF (APP1) or JFIF (APP0) segments at the start of the file. Reading
+//! it is much cheaper than decoding the main image.
+
+use std::io::Read;
+use byteorder::{ReadBytesExt, BigEndian};
+
+use buffer::ImageBuffer;
+use dynimage::{self, DynamicImage};
+use image::{ImageError, ImageResult};
+
+use super::decoder::{JPEGDecoder, APP0, EOI, SOI, SOS};
+
+// EXIF data
+const APP1: u8 = 0xE1;
+
+// Extension codes of JFXX thumbnails
+const JFXX_JPEG: u8 = 0x10;
+const JFXX_PALETTE: u8 = 0x11;
+const JFXX_RGB: u8 = 0x13;
+
+// Tags of the EXIF IFD1 that locate a JPEG thumbnail
+const TAG_THUMBNAIL_OFFSET: u16 = 0x0201;
+const TAG_THUMBNAIL_LENGTH: u16 = 0x0202;
+
+/// Reads the thumbnail embedded in the EXIF or JFIF segments of a JPEG
+/// image without decoding the main image.
+///
+/// Returns the first thumbnail found before the scan data, or `None` if
+/// the image has none.
+pub fn read_thumbnail<R: Read>(mut r: R) -> ImageResult<Option<DynamicImage>> {
+    if try!(r.read_u8()) != 0xFF || try!(r.read_u8()) != SOI {
+        return Err(ImageError::FormatError("Missing SOI marker".to_string()))
+    }
+
+    loop {
+        if try!(r.read_u8()) != 0xFF {
+            continue
+        }
+
+        let marker = try!(r.read_u8());
+
+        match marker {
+            SOS | EOI => return Ok(None),
+            // Fill bytes and standalone markers
+            0xFF => continue,
+            0x01 | 0xD0 ... 0xD7 => continue,
+            _ => ()
+        }
+
+        let length = try!(r.read_u16::<BigEndian>());
+        let mut segment = Vec::with_capacity(length.saturating_sub(2) as usize);
+        try!(r.by_ref().take(length.saturating_sub(2) as u64).read_to_end(&mut segment));
+
+        let thumbnail = match marker {
+            APP0 => try!(jfif_thumbnail(&segment)),
+            APP1 => try!(exif_thumbnail(&segment)),
+            _ => None
+        };
+
+        if thumbnail.is_some() {
+            return Ok(thumbnail)
+        }
+    }
+}
+
+// Parses the thumbnail of a JFIF or JFXX APP0 segment
+fn jfif_thumbnail(segment: &[u8]) -> ImageResult<Option<DynamicImage>> {
+    if segment.starts_with(b"JFIF\0") && segment.len() >= 14 {
+        let (width, height) = (segment[12] as u32, segment[13] as u32);
+        return rgb_thumbnail(width, height, &segment[14..])
+    }
+
+    if !segment.starts_with(b"JFXX\0") || segment.len() < 6 {
+        return Ok(None)
+    }
+
+    let data = &segment[6..];
+
+    match segment[5] {
+        JFXX_JPEG => decode_jpeg(data).map(Some),
+        JFXX_RGB if data.len() >= 2 => {
+            rgb_thumbnail(data[0] as u32, data[1] as u32, &data[2..])
+        }
+        JFXX_PALETTE if data.len() >= 2 + 768 => {
+            let (width, height) = (data[0] as usize, data[1] as usize);
+            let (palette, indices) = data[2..].split_at(768);
+
+            if indices.len() < width * height {
+                return Err(ImageError::NotEnoughData)
+            }
+
+            let rgb = indices[..width * height].iter().flat_map(|&i| {
+                palette[3 * i as usize..3 * i as usize + 3].iter().cloned()
+            }).collect::<Vec<u8>>();
+
+            rgb_thumbnail(width as u32, height as u32, &rgb)
+        }
+        _ => Ok(None)
+    }
+}
+
+fn rgb_thumbnail(width: u32, height: u32, data: &[u8]) -> ImageResult<Option<DynamicImage>> {
+    let len = width as usize * height as usize * 3;
+
+    if len == 0 {
+        return Ok(None)
+    } else if data.len() < len {
+        return Err(ImageError::NotEnoughData)
+    }
+
+    let image = ImageBuffer::from_raw(width, height, data[..len].to_vec().into());
+    Ok(image.map(|v| DynamicImage::ImageRgb8(v)))
+}
+
+// Parses the JPEG thumbnail referenced by IFD1 of an EXIF APP1 segment
+fn exif_thumbnail(segment: &[u8]) -> ImageResult<Option<DynamicImage>> {
+    if !segment.starts_with(b"Exif\0\0") {
+        return Ok(None)
+    }
+
+    let tiff = Tiff::new(&segment[6..]);
+    let ifd0 = try!(tiff.u32(4)) as usize;
+    let count = try!(tiff.u16(ifd0)) as usize;
+    let ifd1 = try!(tiff.u32(ifd0 + 2 + 12 * count)) as usize;
+
+    if ifd1 == 0 {
+        return Ok(None)
+    }
+
+    let mut offset = None;
+    let mut length = None;
+
+    for i in (0..try!(tiff.u16(ifd1)) as usize) {
+        let entry = ifd1 + 2 + 12 * i;
+
+        match try!(tiff.u16(entry)) {
+            TAG_THUMBNAIL_OFFSET => offset = Some(try!(tiff.u32(entry + 8)) as usize),
+            TAG_THUMBNAIL_LENGTH => length = Some(try!(tiff.u32(entry + 8)) as usize),
+            _ => ()
+        }
+    }
+
+    match (offset, length) {
+        (Some(offset), Some(length)) => {
+            let data = try!(tiff.slice(offset, length));
+            decode_jpeg(data).map(Some)
+        }
+        _ => Ok(None)
+    }
+}
+
+fn decode_jpeg(data: &[u8]) -> ImageResult<DynamicImage> {
+    dynimage::decoder_to_image(JPEGDecoder::new(data))
+}
+
+// The TIFF structure of EXIF and MPF data, offsets are relative to its header
+pub struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    pub fn new(data: &'a [u8]) -> Tiff<'a> {
+        Tiff { data: data, little_endian: data.starts_with(b"II") }
+    }
+
+    pub fn slice(&self, offset: usize, len: usize) -> ImageResult<&'a [u8]> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.data.len() => Ok(&self.data[offset..end]),
+            _ => Err(ImageError::FormatError("Invalid offset in EXIF data".to_string()))
+        }
+    }
+
+    pub fn u16(&self, offset: usize) -> ImageResult<u16> {
+        let b = try!(self.slice(offset, 2));
+
+        Ok(if self.little_endian {
+            b[0] as u16 | (b[1] as u16) << 8
+        } else {
+            (b[0] as u16) << 8 | b[1] as u16
+        })
+    }
+
+    pub fn u32(&self, offset: usize) -> ImageResult<u32> {
+        let (a, b) = (try!(self.u16(offset)) as u32, try!(self.u16(offset + 2)) as u32);
+
+        Ok(if self.little_endian { b << 16 | a } else { a << 16 | b })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_thumbnail;
+    use super::super::JPEGEncoder;
+    use color::ColorType;
+    use image::GenericImage;
+
+    // A JPEG file consisting of `segment` and an empty scan
+    fn jpeg_with(marker: u8, segment: &[u8]) -> Vec<u8> {
+        let mut file = vec![0xFF, 0xD8, 0xFF, marker];
+        file.push(((segment.len() + 2) >> 8) as u8);
+        file.push((segment.len() + 2) as u8);
+        file.extend(segment.iter().cloned());
+        file.extend([0xFF, 0xDA].iter().cloned());
+        file
+    }
+
+    #[test]
+    fn test_jfif_thumbnail() {
+        let mut segment = b"JFIF\0\x01\x01\0\0\x01\0\x01".to_vec();
+        segment.extend([2, 1, 10, 20, 30, 40, 50, 60].iter().cloned());
+
+        let thumbnail = read_thumbnail(&jpeg_with(0xE0, &segment)[..]).unwrap().unwrap();
+        assert_eq!(thumbnail.dimensions(), (2, 1));
+        assert_eq!(thumbnail.raw_pixels(), vec![10, 20, 30, 40, 50, 60]);
+
+        assert!(read_thumbnail(&jpeg_with(0xFE, b"comment")[..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_exif_thumbnail() {
+        let mut jpeg = Vec::new();
+        JPEGEncoder::new(&mut jpeg).encode(&[128; 16 * 8 * 3], 16, 8, ColorType::RGB(8)).unwrap();
+
+        // Big endian TIFF header, an empty IFD0 and an IFD1 with two entries
+        let mut segment = b"Exif\0\0MM\0\x2A\0\0\0\x08\0\0\0\0\0\x0E\0\x02".to_vec();
+        segment.extend([0x02, 0x01, 0, 4, 0, 0, 0, 1, 0, 0, 0, 44].iter().cloned());
+        segment.extend([0x02, 0x02, 0, 4, 0, 0, 0, 1, 0, 0].iter().cloned());
+        segment.extend([(jpeg.len() >> 8) as u8, jpeg.len() as u8].iter().cloned());
+        segment.extend([0, 0, 0, 0].iter().cloned());
+        segment.extend(jpeg.iter().cloned());
+
+        let thumbnail = read_thumbnail(&jpeg_with(0xE1, &segment)[..]).unwrap().unwrap();
+        assert_eq!(thumbnail.dimensions(), (16, 8));
+    }
+}
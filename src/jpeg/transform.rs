@@ -77,38 +77,51 @@ static FIX_2_562915447: i32 = 20995;
 static FIX_3_072711026: i32 = 25172;
 
 pub fn fdct(samples: &[u8], coeffs: &mut [i32]) {
+    fdct_generic(|i| samples[i] as i32, 128, PASS1_BITS, coeffs)
+}
+
+// The forward DCT of 12 bit samples, which keeps one bit less in the first
+// pass as libjpeg does, so that the second pass does not overflow
+pub fn fdct_12bit(samples: &[u16], coeffs: &mut [i32]) {
+    fdct_generic(|i| samples[i] as i32, 2048, 1, coeffs)
+}
+
+// The forward DCT of the samples returned by `sample` for each index, which
+// are centered on `center`, keeping `pass1_bits` more bits between the passes
+#[inline(always)]
+fn fdct_generic<F: Fn(usize) -> i32>(sample: F, center: i32, pass1_bits: i32, coeffs: &mut [i32]) {
     // Pass 1: process rows.
     // Results are scaled by sqrt(8) compared to a true DCT
-    // furthermore we scale the results by 2**PASS1_BITS
+    // furthermore we scale the results by 2**pass1_bits
     for y in (0usize..8) {
         let y0 = y * 8;
 
         // Even part
-        let t0 = samples[y0 + 0] as i32 + samples[y0 + 7] as i32;
-        let t1 = samples[y0 + 1] as i32 + samples[y0 + 6] as i32;
-        let t2 = samples[y0 + 2] as i32 + samples[y0 + 5] as i32;
-        let t3 = samples[y0 + 3] as i32 + samples[y0 + 4] as i32;
+        let t0 = sample(y0 + 0) + sample(y0 + 7);
+        let t1 = sample(y0 + 1) + sample(y0 + 6);
+        let t2 = sample(y0 + 2) + sample(y0 + 5);
+        let t3 = sample(y0 + 3) + sample(y0 + 4);
 
         let t10 = t0 + t3;
         let t12 = t0 - t3;
         let t11 = t1 + t2;
         let t13 = t1 - t2;
 
-        let t0 = samples[y0 + 0] as i32 - samples[y0 + 7] as i32;
-        let t1 = samples[y0 + 1] as i32 - samples[y0 + 6] as i32;
-        let t2 = samples[y0 + 2] as i32 - samples[y0 + 5] as i32;
-        let t3 = samples[y0 + 3] as i32 - samples[y0 + 4] as i32;
+        let t0 = sample(y0 + 0) - sample(y0 + 7);
+        let t1 = sample(y0 + 1) - sample(y0 + 6);
+        let t2 = sample(y0 + 2) - sample(y0 + 5);
+        let t3 = sample(y0 + 3) - sample(y0 + 4);
 
         // Apply unsigned -> signed conversion
-        coeffs[y0 + 0] = (t10 + t11 - 8 * 128) << PASS1_BITS as usize;
-        coeffs[y0 + 4] = (t10 - t11) << PASS1_BITS as usize;
+        coeffs[y0 + 0] = (t10 + t11 - 8 * center) << pass1_bits as usize;
+        coeffs[y0 + 4] = (t10 - t11) << pass1_bits as usize;
 
         let mut z1 = (t12 + t13) * FIX_0_541196100;
         // Add fudge factor here for final descale
-        z1 += 1 << (CONST_BITS - PASS1_BITS - 1) as usize;
+        z1 += 1 << (CONST_BITS - pass1_bits - 1) as usize;
 
-        coeffs[y0 + 2] = (z1 + t12 * FIX_0_765366865) >> (CONST_BITS - PASS1_BITS) as usize;
-        coeffs[y0 + 6] = (z1 - t13 * FIX_1_847759065) >> (CONST_BITS - PASS1_BITS) as usize;
+        coeffs[y0 + 2] = (z1 + t12 * FIX_0_765366865) >> (CONST_BITS - pass1_bits) as usize;
+        coeffs[y0 + 6] = (z1 - t13 * FIX_1_847759065) >> (CONST_BITS - pass1_bits) as usize;
 
         // Odd part
         let t12 = t0 + t2;
@@ -116,7 +129,7 @@ pub fn fdct(samples: &[u8], coeffs: &mut [i32]) {
 
         let mut z1 = (t12 + t13) * FIX_1_175875602;
         // Add fudge factor here for final descale
-        z1 += 1 << (CONST_BITS - PASS1_BITS - 1) as usize;
+        z1 += 1 << (CONST_BITS - pass1_bits - 1) as usize;
 
         let mut t12 = t12 * (-FIX_0_390180644);
         let mut t13 = t13 * (-FIX_1_961570560);
@@ -135,14 +148,14 @@ pub fn fdct(samples: &[u8], coeffs: &mut [i32]) {
         t1 += z1 + t13;
         t2 += z1 + t12;
 
-        coeffs[y0 + 1] = t0 >> (CONST_BITS - PASS1_BITS) as usize;
-        coeffs[y0 + 3] = t1 >> (CONST_BITS - PASS1_BITS) as usize;
-        coeffs[y0 + 5] = t2 >> (CONST_BITS - PASS1_BITS) as usize;
-        coeffs[y0 + 7] = t3 >> (CONST_BITS - PASS1_BITS) as usize;
+        coeffs[y0 + 1] = t0 >> (CONST_BITS - pass1_bits) as usize;
+        coeffs[y0 + 3] = t1 >> (CONST_BITS - pass1_bits) as usize;
+        coeffs[y0 + 5] = t2 >> (CONST_BITS - pass1_bits) as usize;
+        coeffs[y0 + 7] = t3 >> (CONST_BITS - pass1_bits) as usize;
     }
 
     // Pass 2: process columns
-    // We remove the PASS1_BITS scaling but leave the results scaled up an
+    // We remove the pass1_bits scaling but leave the results scaled up an
     // overall factor of 8
     for x in (0usize..8).rev() {
         // Even part
@@ -152,7 +165,7 @@ pub fn fdct(samples: &[u8], coeffs: &mut [i32]) {
         let t3 = coeffs[x + 8 * 3] + coeffs[x + 8 * 4];
 
         // Add fudge factor here for final descale
-        let t10 = t0 + t3 + (1 << (PASS1_BITS - 1) as usize);
+        let t10 = t0 + t3 + (1 << (pass1_bits - 1) as usize);
         let t12 = t0 - t3;
         let t11 = t1 + t2;
         let t13 = t1 - t2;
@@ -162,15 +175,15 @@ pub fn fdct(samples: &[u8], coeffs: &mut [i32]) {
         let t2 = coeffs[x + 8 * 2] - coeffs[x + 8 * 5];
         let t3 = coeffs[x + 8 * 3] - coeffs[x + 8 * 4];
 
-        coeffs[x + 8 * 0] = (t10 + t11) >> PASS1_BITS as usize;
-        coeffs[x + 8 * 4] = (t10 - t11) >> PASS1_BITS as usize;
+        coeffs[x + 8 * 0] = (t10 + t11) >> pass1_bits as usize;
+        coeffs[x + 8 * 4] = (t10 - t11) >> pass1_bits as usize;
 
         let mut z1 = (t12 + t13) * FIX_0_541196100;
         // Add fudge factor here for final descale
-        z1 += 1 << (CONST_BITS + PASS1_BITS - 1) as usize;
+        z1 += 1 << (CONST_BITS + pass1_bits - 1) as usize;
 
-        coeffs[x + 8 * 2] = (z1 + t12 * FIX_0_765366865) >> (CONST_BITS + PASS1_BITS) as usize;
-        coeffs[x + 8 * 6] = (z1 - t13 * FIX_1_847759065) >> (CONST_BITS + PASS1_BITS) as usize;
+        coeffs[x + 8 * 2] = (z1 + t12 * FIX_0_765366865) >> (CONST_BITS + pass1_bits) as usize;
+        coeffs[x + 8 * 6] = (z1 - t13 * FIX_1_847759065) >> (CONST_BITS + pass1_bits) as usize;
 
         // Odd part
         let t12 = t0 + t2;
@@ -178,7 +191,7 @@ pub fn fdct(samples: &[u8], coeffs: &mut [i32]) {
 
         let mut z1 = (t12 + t13) * FIX_1_175875602;
         // Add fudge factor here for final descale
-        z1 += 1 << (CONST_BITS - PASS1_BITS - 1) as usize;
+        z1 += 1 << (CONST_BITS - pass1_bits - 1) as usize;
 
         let mut t12 = t12 * (-FIX_0_390180644);
         let mut t13 = t13 * (-FIX_1_961570560);
@@ -197,10 +210,10 @@ pub fn fdct(samples: &[u8], coeffs: &mut [i32]) {
         t1 += z1 + t13;
         t2 += z1 + t12;
 
-        coeffs[x + 8 * 1] = t0 >> (CONST_BITS + PASS1_BITS) as usize;
-        coeffs[x + 8 * 3] = t1 >> (CONST_BITS + PASS1_BITS) as usize;
-        coeffs[x + 8 * 5] = t2 >> (CONST_BITS + PASS1_BITS) as usize;
-        coeffs[x + 8 * 7] = t3 >> (CONST_BITS + PASS1_BITS) as usize;
+        coeffs[x + 8 * 1] = t0 >> (CONST_BITS + pass1_bits) as usize;
+        coeffs[x + 8 * 3] = t1 >> (CONST_BITS + pass1_bits) as usize;
+        coeffs[x + 8 * 5] = t2 >> (CONST_BITS + pass1_bits) as usize;
+        coeffs[x + 8 * 7] = t3 >> (CONST_BITS + pass1_bits) as usize;
     }
 }
 
@@ -356,3 +369,24 @@ pub fn idct(coeffs: &[i32], samples: &mut [u8]) {
         samples[y0 + 4] = level_shift_up(a);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{fdct, fdct_12bit};
+
+    #[test]
+    fn test_fdct_12bit() {
+        let samples = (0..64).map(|i| (i * 37 % 256) as u8).collect::<Vec<u8>>();
+        let wide = samples.iter().map(|&s| (s as u16) << 4).collect::<Vec<u16>>();
+
+        let (mut narrow_coeffs, mut wide_coeffs) = ([0i32; 64], [0i32; 64]);
+        fdct(&samples, &mut narrow_coeffs);
+        fdct_12bit(&wide, &mut wide_coeffs);
+
+        // The same transform, only 16 times larger. Both round the odd
+        // coefficients down, the 8 bit one by up to 16 of the 12 bit units
+        for (&a, &b) in narrow_coeffs.iter().zip(wide_coeffs.iter()) {
+            assert!((a * 16 - b).abs() <= 32, "{} {}", a, b);
+        }
+    }
+}
@@ -11,6 +11,13 @@ extern crate byteorder;
 extern crate num;
 #[macro_use]
 extern crate enum_primitive;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "python")]
+extern crate pyo3;
+// The code generated by the pyo3 macros refers to `::core`
+#[cfg(feature = "python")]
+extern crate core;
 #[cfg(test)]
 extern crate test;
 
@@ -24,16 +31,20 @@ pub use color::ColorType::{
 };
 
 pub use color::{
+    ColorSpace,
     Luma,
     LumaA,
     Rgb,
     Rgba
 };
 
+pub use icc::{IccProfile, Intent};
+
 pub use image::{
     ImageDecoder,
     ImageError,
     ImageResult,
+    RowOrder,
     SubImage,
     GenericImage,
     // Iterators
@@ -47,7 +58,8 @@ pub use imageops::FilterType::{
     Nearest,
     CatmullRom,
     Gaussian,
-    Lanczos3
+    Lanczos3,
+    DetailPreserving
 };
 
 pub use image::ImageFormat::{
@@ -67,9 +79,29 @@ pub use buffer::{
     RgbImage,
     RgbaImage,
     GrayImage,
-    GrayAlphaImage
+    GrayAlphaImage,
+    Gray16Image,
+    GrayAlpha16Image,
+    // Copy-on-write image types
+    SharedBuffer,
+    SharedRgbImage,
+    SharedRgbaImage,
+    SharedGrayImage,
+    SharedGrayAlphaImage,
+    // Undo support
+    RegionPatch
 };
 
+pub use math::Rect;
+
+pub use tracked::TrackedImage;
+
+pub use viewport::Viewport;
+
+pub use background::{spawn_decode, DecodeHandle, DecodeOptions};
+
+pub use tensor::{Normalization, TensorLayout};
+
 // Traits
 pub use traits::Primitive;
 
@@ -96,8 +128,31 @@ pub use animation::{
 };
 
 // Math utils
+pub mod config;
+
 pub mod math;
 
+pub mod delta;
+
+pub mod icc;
+
+pub mod executor;
+
+pub mod ops;
+
+pub mod draw;
+
+pub mod sequence;
+
+pub mod y4m;
+
+pub mod montage;
+
+#[cfg(feature = "mmap")]
+pub mod mmap;
+
+pub mod texture;
+
 // Image processing functions
 pub mod imageops;
 
@@ -118,6 +173,10 @@ pub mod tiff;
 pub mod tga;
 #[cfg(feature = "bmp")]
 pub mod bmp;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "python")]
+mod python;
 
 mod image;
 mod utils;
@@ -126,6 +185,10 @@ mod color;
 mod buffer;
 mod traits;
 mod animation;
+mod tracked;
+mod viewport;
+mod background;
+mod tensor;
 
 // Copies data from `src` to `dst`
 //
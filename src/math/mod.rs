@@ -1,3 +1,6 @@
 //! Mathematical helper functions and types.
 pub mod utils;
 pub mod nq;
+pub mod rect;
+
+pub use self::rect::Rect;
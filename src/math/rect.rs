@@ -0,0 +1,32 @@
+//! A rectangle type used to describe regions of an image.
+
+/// An axis aligned rectangle, described by its top left corner and its size
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rect {
+    /// The x coordinate of the left edge
+    pub x: u32,
+    /// The y coordinate of the top edge
+    pub y: u32,
+    /// The width of the rectangle
+    pub width: u32,
+    /// The height of the rectangle
+    pub height: u32,
+}
+
+impl Rect {
+    /// Creates a rectangle with top left corner `(x, y)`
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height
+        }
+    }
+
+    /// Returns true if the rectangle lies within an image of the given dimensions
+    pub fn fits_within(&self, width: u32, height: u32) -> bool {
+        self.x as u64 + self.width as u64 <= width as u64 &&
+        self.y as u64 + self.height as u64 <= height as u64
+    }
+}
@@ -0,0 +1,79 @@
+//! Memory-mapped image files
+//!
+//! Decoders that read from a ```MappedFile``` read straight from the page
+//! cache of the file, without the system calls of a ```File``` or the
+//! extra copy of a ```BufReader```. This pays off for large images. The
+//! ```from_path``` constructors of the decoders map their file this way.
+//!
+//! ```no_run
+//! use image::ImageDecoder;
+//! use image::jpeg::JPEGDecoder;
+//!
+//! // The file is not changed while it is decoded
+//! let mut decoder = unsafe { JPEGDecoder::from_path("large.jpg") }.unwrap();
+//! let pixels = decoder.read_image().unwrap();
+//! ```
+//!
+//! Mapping a file is unsafe, as the mapped bytes are not owned by the
+//! process: another process that writes the file changes bytes the decoder
+//! holds as an immutable slice, and one that truncates it makes reads past
+//! the new end crash the process. Only map files that nothing changes
+//! while they are mapped.
+
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use image::{ImageError, ImageResult};
+
+/// The contents of a memory-mapped file, read from the start
+pub type MappedFile = Cursor<Mmap>;
+
+/// Maps the file at ```path``` into memory for reading
+///
+/// # Safety
+///
+/// The file must not be written to or truncated, by this or any other
+/// process, while the returned ```MappedFile``` exists.
+pub unsafe fn map_file<P: AsRef<Path>>(path: P) -> ImageResult<MappedFile> {
+    let file = try!(File::open(path));
+
+    // Empty files can not be mapped, and hold no image either
+    if try!(file.metadata()).len() == 0 {
+        return Err(ImageError::NotEnoughData)
+    }
+
+    let map = try!(Mmap::map(&file));
+    Ok(Cursor::new(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    use image::ImageError;
+    use super::map_file;
+
+    #[test]
+    fn test_map_file() {
+        let path = env::temp_dir().join(format!("image-mmap-{}", ::std::process::id()));
+
+        File::create(&path).unwrap().write_all(b"mapped").unwrap();
+        let mut contents = Vec::new();
+        unsafe { map_file(&path) }.unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"mapped");
+
+        File::create(&path).unwrap();
+        match unsafe { map_file(&path) } {
+            Err(ImageError::NotEnoughData) => (),
+            _ => panic!("an empty file was mapped")
+        }
+
+        fs::remove_file(&path).unwrap();
+        assert!(unsafe { map_file(&path) }.is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Contact sheets
+//!
+//! A contact sheet shows a folder of pictures at a glance: each picture is
+//! turned upright as its EXIF orientation says, scaled to fit a cell and
+//! captioned with the time it was taken or its file name.
+//!
+//! ```no_run
+//! use image::montage::{self, CaptionSource};
+//!
+//! let sheet = montage::contact_sheet(&["a.jpg", "b.jpg", "c.jpg"], 2, (160, 120),
+//!                                    CaptionSource::Exif).unwrap();
+//! sheet.save("sheet.png").unwrap();
+//! ```
+
+use std::cmp;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use buffer::{ImageBuffer, RgbImage};
+use color::Rgb;
+use draw;
+use dynimage::{self, DynamicImage};
+use image::{GenericImage, ImageError, ImageResult};
+use imageops;
+
+#[cfg(feature = "jpeg")]
+use image::ImageFormat;
+#[cfg(feature = "jpeg")]
+use jpeg::JPEGDecoder;
+
+// The space around and between the cells
+const MARGIN: u32 = 8;
+
+// The scale of the font and the space between a picture and its caption
+const CAPTION_SCALE: u32 = 2;
+const CAPTION_GAP: u32 = 4;
+
+const BACKGROUND: [u8; 3] = [32, 32, 32];
+const TEXT: [u8; 3] = [255, 255, 255];
+
+/// What the pictures of a contact sheet are captioned with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaptionSource {
+    /// The time the picture was taken as recorded in its EXIF metadata, or
+    /// the file name if there is none
+    Exif,
+    /// The file name
+    Filename,
+}
+
+// The metadata of a picture that the sheet uses
+#[derive(Default)]
+struct Metadata {
+    orientation: Option<u16>,
+    date_time: Option<String>,
+}
+
+/// Lays out the pictures in the files ```images``` in a grid of
+/// ```columns``` columns, in order row by row. Each picture is scaled to
+/// fit a cell of ```cell``` width and height and centered in it, with its
+/// caption below. Captions that are too wide for the cell are cut short.
+///
+/// The format of the files is guessed from their content, or from their
+/// extension for formats without a signature.
+pub fn contact_sheet<P: AsRef<Path>>(images: &[P], columns: u32, cell: (u32, u32), caption: CaptionSource)
+    -> ImageResult<RgbImage> {
+
+    if images.is_empty() || columns == 0 || cell.0 == 0 || cell.1 == 0 {
+        return Err(ImageError::DimensionError)
+    }
+
+    let columns = cmp::min(columns, images.len() as u32);
+    let rows = (images.len() as u32 + columns - 1) / columns;
+    let caption_height = CAPTION_GAP + draw::text_size("", CAPTION_SCALE).1;
+    let (stride_x, stride_y) = (cell.0 + MARGIN, cell.1 + caption_height + MARGIN);
+
+    let mut sheet = ImageBuffer::from_pixel(MARGIN + columns * stride_x, MARGIN + rows * stride_y,
+                                            Rgb(BACKGROUND));
+
+    for (i, path) in images.iter().enumerate() {
+        let path = path.as_ref();
+        let (x, y) = (MARGIN + i as u32 % columns * stride_x, MARGIN + i as u32 / columns * stride_y);
+
+        let mut data = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut data));
+
+        let image = match dynimage::guess_format(&data) {
+            Ok(format) => try!(dynimage::load_from_memory_with_format(&data, format)),
+            Err(_) => try!(dynimage::open(path)),
+        };
+        let metadata = read_metadata(&data);
+        let image = orient(image, metadata.orientation.unwrap_or(1));
+
+        let (thumbnail, _) = imageops::letterbox(&image.to_rgb(), cell.0, cell.1, Rgb(BACKGROUND));
+        sheet.copy_from(&thumbnail, x, y);
+
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let text = match (caption, metadata.date_time) {
+            (CaptionSource::Exif, Some(date_time)) => format_date_time(&date_time),
+            _ => name,
+        };
+        let text = fit(&text, cell.0);
+        let offset = (cell.0 - draw::text_size(&text, CAPTION_SCALE).0) / 2;
+
+        draw::draw_text(&mut sheet, (x + offset) as i64, (y + cell.1 + CAPTION_GAP) as i64, &text,
+                        Rgb(TEXT), CAPTION_SCALE);
+    }
+
+    Ok(sheet)
+}
+
+#[cfg(feature = "jpeg")]
+fn read_metadata(data: &[u8]) -> Metadata {
+    if dynimage::guess_format(data).ok() != Some(ImageFormat::JPEG) {
+        return Metadata::default()
+    }
+
+    // Pictures with unreadable metadata are shown as they are stored
+    match JPEGDecoder::new(data).exif() {
+        Ok(Some(exif)) => Metadata {
+            orientation: exif.orientation,
+            date_time: exif.date_time_original.or(exif.date_time),
+        },
+        _ => Metadata::default()
+    }
+}
+
+#[cfg(not(feature = "jpeg"))]
+fn read_metadata(_: &[u8]) -> Metadata {
+    Metadata::default()
+}
+
+// Turns `image` upright according to the EXIF orientation `orientation`
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image
+    }
+}
+
+// `YYYY:MM:DD HH:MM:SS` as `YYYY-MM-DD HH:MM`, other text as it is
+fn format_date_time(date_time: &str) -> String {
+    let b = date_time.as_bytes();
+
+    if b.len() >= 16 && b[4] == b':' && b[7] == b':' && b[10] == b' ' && date_time.is_char_boundary(16) {
+        format!("{}-{}-{} {}", &date_time[..4], &date_time[5..7], &date_time[8..10], &date_time[11..16])
+    } else {
+        date_time.to_string()
+    }
+}
+
+// `text`, cut short and ended with `..` if it is wider than `width`
+fn fit(text: &str, width: u32) -> String {
+    let max = ((width + CAPTION_SCALE) / (4 * CAPTION_SCALE)) as usize;
+
+    if text.chars().count() <= max {
+        text.to_string()
+    } else if max > 2 {
+        text.chars().take(max - 2).chain("..".chars()).collect()
+    } else {
+        text.chars().take(max).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fit, format_date_time};
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_contact_sheet() {
+        use std::env;
+        use std::fs::{self, File};
+
+        use buffer::{ImageBuffer, RgbImage};
+        use color::{ColorType, Rgb};
+        use jpeg::{Exif, JPEGEncoder};
+        use super::{contact_sheet, CaptionSource, BACKGROUND, TEXT};
+
+        let dir = env::temp_dir().join(format!("image-montage-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // A wide red picture stored sideways, to be rotated upright
+        let red: RgbImage = ImageBuffer::from_pixel(40, 20, Rgb([255, 0, 0]));
+        let mut file = File::create(dir.join("a.jpg")).unwrap();
+        let mut encoder = JPEGEncoder::new(&mut file);
+        encoder.set_exif(&Exif {
+            orientation: Some(6),
+            date_time_original: Some("2015:06:01 12:30:00".to_string()),
+            ..Exif::default()
+        });
+        encoder.encode(&red, 40, 20, ColorType::RGB(8)).unwrap();
+
+        let blue: RgbImage = ImageBuffer::from_pixel(40, 20, Rgb([0, 0, 255]));
+        let mut file = File::create(dir.join("b.jpg")).unwrap();
+        JPEGEncoder::new(&mut file).encode(&blue, 40, 20, ColorType::RGB(8)).unwrap();
+
+        let paths = [dir.join("a.jpg"), dir.join("b.jpg"), dir.join("a.jpg")];
+        let sheet = contact_sheet(&paths, 2, (40, 40), CaptionSource::Exif).unwrap();
+
+        // Two rows of two cells, each with a 14 pixel caption
+        assert_eq!(sheet.dimensions(), (8 + 2 * 48, 8 + 2 * 62));
+
+        // The first picture is upright and fills the middle of its cell,
+        // the second fills its width
+        let near = |p: &Rgb<u8>, q: [u8; 3]| p.data.iter().zip(q.iter()).all(|(a, b)| (*a as i32 - *b as i32).abs() < 8);
+        assert!(near(&sheet[(8 + 20, 8 + 20)], [255, 0, 0]));
+        assert!(near(&sheet[(8 + 2, 8 + 20)], BACKGROUND));
+        assert!(near(&sheet[(56 + 2, 8 + 20)], [0, 0, 255]));
+        assert!(near(&sheet[(56 + 20, 8 + 2)], BACKGROUND));
+        assert!(near(&sheet[(100, 8)], BACKGROUND));
+
+        // Captions are drawn below the cells, the last cell stays empty
+        let text = |x0: u32, y0: u32| (x0..x0 + 40).any(|x| (y0..y0 + 10).any(|y| sheet[(x, y)] == Rgb(TEXT)));
+        assert!(text(8, 52) && text(56, 52) && text(8, 114));
+        assert!(!text(56, 114));
+
+        assert!(contact_sheet(&paths, 0, (40, 40), CaptionSource::Filename).is_err());
+        assert!(contact_sheet::<&str>(&[], 2, (40, 40), CaptionSource::Filename).is_err());
+        assert!(contact_sheet(&[dir.join("missing.jpg")], 2, (40, 40), CaptionSource::Filename).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_captions() {
+        assert_eq!(format_date_time("2015:06:01 12:30:00"), "2015-06-01 12:30");
+        assert_eq!(format_date_time("June 2015"), "June 2015");
+
+        // 40 pixels fit 5 characters of 8
+        assert_eq!(fit("a.jpg", 40), "a.jpg");
+        assert_eq!(fit("ab.jpg", 40), "ab...");
+        assert_eq!(fit("ab.jpg", 12), "a");
+    }
+}
@@ -0,0 +1,616 @@
+//! Chains of image operations
+//!
+//! A `Chain` is a declarative list of operations that is parsed from a
+//! compact specification, as used in the URLs of thumbnail services, and
+//! applied to an image. Every operation consumes the result of the previous
+//! one, thus at most two images are kept in memory, and consecutive crops
+//! are merged into a single one.
+//!
+//! The operations are separated by `/`, their arguments by `,`:
+//!
+//! ```
+//! use image::DynamicImage;
+//! use image::ops::Chain;
+//!
+//! let chain = Chain::parse("crop:0,0,64,48/resize:32,32/sharpen:1.0,2/encode:jpeg").unwrap();
+//!
+//! let mut thumbnail = Vec::new();
+//! chain.execute(DynamicImage::new_rgb8(100, 100), &mut thumbnail).unwrap();
+//! ```
+//!
+//! | Operation                     | Effect                                                  |
+//! |-------------------------------|---------------------------------------------------------|
+//! | `crop:x,y,width,height`       | Cuts out a rectangle                                    |
+//! | `resize:width,height[,mode]`  | Resizes to fit within (`fit`, default) or to (`exact`)  |
+//! | `sharpen:sigma[,threshold]`   | Applies an unsharpen mask                               |
+//! | `blur:sigma`                  | Applies a Gaussian blur                                 |
+//! | `grayscale`                   | Converts to grayscale                                   |
+//! | `fliph`, `flipv`              | Flips horizontally or vertically                        |
+//! | `rotate:90`, `180` or `270`   | Rotates clockwise                                       |
+//! | `encode:format`               | Encodes the result, has to be the last operation        |
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use dynimage::{self, DynamicImage};
+use image::{GenericImage, ImageError, ImageFormat, ImageResult};
+use imageops::FilterType;
+use math::Rect;
+
+/// A single operation of a `Chain`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    /// Cuts out a rectangle
+    Crop(Rect),
+    /// Resizes the image, preserving its aspect ratio if `exact` is false
+    Resize {
+        /// The new width, or the maximum width if `exact` is false
+        width: u32,
+        /// The new height, or the maximum height if `exact` is false
+        height: u32,
+        /// Whether to ignore the aspect ratio
+        exact: bool,
+    },
+    /// Applies an unsharpen mask with the given sigma and threshold
+    Sharpen(f32, i32),
+    /// Applies a Gaussian blur with the given sigma
+    Blur(f32),
+    /// Converts the image to grayscale
+    Grayscale,
+    /// Flips the image horizontally
+    FlipH,
+    /// Flips the image vertically
+    FlipV,
+    /// Rotates the image clockwise by 90, 180 or 270 degrees
+    Rotate(u32),
+    /// Encodes the image in the given format
+    Encode(ImageFormat),
+}
+
+/// A sequence of operations applied to an image
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chain {
+    ops: Vec<Op>,
+}
+
+impl Chain {
+    /// Creates an empty chain
+    pub fn new() -> Chain {
+        Chain { ops: Vec::new() }
+    }
+
+    /// Parses a chain from its specification, see the module documentation
+    pub fn parse(spec: &str) -> ImageResult<Chain> {
+        let mut chain = Chain::new();
+
+        for op in spec.split('/').filter(|s| !s.is_empty()) {
+            chain = try!(chain.then(try!(parse_op(op))));
+        }
+
+        Ok(chain)
+    }
+
+    /// Appends `op` to the chain. Returns an error if the chain already
+    /// ends with an `Encode` operation.
+    pub fn then(mut self, op: Op) -> ImageResult<Chain> {
+        if let Some(&Op::Encode(..)) = self.ops.last() {
+            return Err(invalid("no operation may follow encode"))
+        }
+
+        self.ops.push(op);
+        Ok(self)
+    }
+
+    /// The operations of this chain
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// The format of the final `Encode` operation, if any
+    pub fn output_format(&self) -> Option<ImageFormat> {
+        match self.ops.last() {
+            Some(&Op::Encode(format)) => Some(format),
+            _ => None
+        }
+    }
+
+    /// Applies all operations except the final `Encode` to `image`.
+    ///
+    /// Returns a `DimensionError` if a crop exceeds the image.
+    pub fn apply(&self, image: DynamicImage) -> ImageResult<DynamicImage> {
+        let mut image = image;
+        let mut crop: Option<Rect> = None;
+
+        for op in self.ops.iter() {
+            // Crops are collected and applied together before the next operation
+            if let Op::Crop(rect) = *op {
+                crop = Some(match crop {
+                    Some(outer) => try!(nested(outer, rect)),
+                    None => rect
+                });
+                continue
+            }
+
+            if let Some(rect) = crop.take() {
+                image = try!(crop_image(image, rect));
+            }
+
+            image = match *op {
+                Op::Crop(..) | Op::Encode(..) => image,
+                Op::Resize { width, height, exact: true } => {
+                    image.resize_exact(width, height, FilterType::Lanczos3)
+                }
+                Op::Resize { width, height, exact: false } => {
+                    image.resize(width, height, FilterType::Lanczos3)
+                }
+                Op::Sharpen(sigma, threshold) => image.unsharpen(sigma, threshold),
+                Op::Blur(sigma) => image.blur(sigma),
+                Op::Grayscale => image.grayscale(),
+                Op::FlipH => image.fliph(),
+                Op::FlipV => image.flipv(),
+                Op::Rotate(90) => image.rotate90(),
+                Op::Rotate(180) => image.rotate180(),
+                Op::Rotate(270) => image.rotate270(),
+                Op::Rotate(..) => return Err(invalid("rotation must be 90, 180 or 270")),
+            };
+        }
+
+        match crop {
+            Some(rect) => crop_image(image, rect),
+            None => Ok(image)
+        }
+    }
+
+    /// Applies the chain to `image` and writes the result to `w` in the
+    /// format of the final `Encode` operation.
+    pub fn execute<W: Write>(&self, image: DynamicImage, w: &mut W) -> ImageResult<()> {
+        let format = match self.output_format() {
+            Some(format) => format,
+            None => return Err(invalid("the chain does not end with encode"))
+        };
+
+        try!(self.apply(image)).save(w, format)
+    }
+}
+
+/// Applies the crops, flips and rotations of `chain` to the image file
+/// `data` and writes the result in the same format to `w`, keeping the
+/// metadata of the file.
+///
+/// The pixels are rearranged and re-encoded without loss, which PNG files
+/// allow, see `png::transform`. Other formats, including WebP, which can
+/// only be decoded, and chains with other operations are rejected with an
+/// `UnsupportedError`.
+pub fn transform<W: Write>(data: &[u8], w: W, chain: &Chain) -> ImageResult<()> {
+    let mut transposed = false;
+
+    for op in chain.ops().iter() {
+        match *op {
+            Op::Crop(..) | Op::FlipH | Op::FlipV | Op::Rotate(180) => (),
+            Op::Rotate(90) | Op::Rotate(270) => transposed = !transposed,
+            _ => return Err(ImageError::UnsupportedError(
+                "Only crops, flips and rotations can be applied to a file".to_string()
+            ))
+        }
+    }
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return transform_png(data, w, transposed, chain)
+    }
+
+    let msg = if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        "WebP files can not be encoded"
+    } else {
+        "The format of the file is not supported"
+    };
+
+    Err(ImageError::UnsupportedError(msg.to_string()))
+}
+
+/// Applies the crops, flips and rotations of `chain` to the image file at
+/// `input` and writes the result to `output`, see `transform`
+pub fn transform_file<P, Q>(input: P, output: Q, chain: &Chain) -> ImageResult<()>
+    where P: AsRef<Path>, Q: AsRef<Path> {
+
+    let mut data = Vec::new();
+    try!(try!(File::open(input)).read_to_end(&mut data));
+
+    // Transform before creating the output, which may be the input
+    let mut out = Vec::new();
+    try!(transform(&data, &mut out, chain));
+
+    try!(try!(File::create(output)).write_all(&out));
+    Ok(())
+}
+
+#[cfg(feature = "png_codec")]
+fn transform_png<W: Write>(data: &[u8], w: W, transposed: bool, chain: &Chain) -> ImageResult<()> {
+    ::png::transform(data, w, transposed, |image| chain.apply(image))
+}
+
+#[cfg(not(feature = "png_codec"))]
+fn transform_png<W: Write>(_: &[u8], _: W, _: bool, _: &Chain) -> ImageResult<()> {
+    Err(ImageError::UnsupportedError("PNG support is not enabled".to_string()))
+}
+
+/// The metadata `sanitize` keeps
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Whitelist {
+    /// Keep the ICC profile and the color space chunks of PNG files,
+    /// without which the colors may be displayed wrongly
+    pub color_space: bool,
+    /// Keep the EXIF orientation of JPEG files
+    pub orientation: bool,
+}
+
+impl Default for Whitelist {
+    fn default() -> Whitelist {
+        Whitelist { color_space: true, orientation: true }
+    }
+}
+
+/// Decodes the image file `data` and encodes its pixels again to `w` in
+/// the same format, keeping only the metadata in `whitelist`.
+///
+/// Everything else that could carry a hidden payload is dropped: trailing
+/// bytes after the image, comments, private APPn segments of JPEG files
+/// and the other ancillary chunks of PNG files. JPEG files are re-encoded
+/// at their estimated quality, thus lossily. Other formats are rejected
+/// with an `UnsupportedError`.
+pub fn sanitize<W: Write>(data: &[u8], w: W, whitelist: Whitelist) -> ImageResult<()> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        sanitize_png(data, w, whitelist)
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        sanitize_jpeg(data, w, whitelist)
+    } else {
+        Err(ImageError::UnsupportedError("The format of the file is not supported".to_string()))
+    }
+}
+
+#[cfg(feature = "png_codec")]
+fn sanitize_png<W: Write>(data: &[u8], w: W, whitelist: Whitelist) -> ImageResult<()> {
+    ::png::sanitize(data, w, whitelist.color_space)
+}
+
+#[cfg(not(feature = "png_codec"))]
+fn sanitize_png<W: Write>(_: &[u8], _: W, _: Whitelist) -> ImageResult<()> {
+    Err(ImageError::UnsupportedError("PNG support is not enabled".to_string()))
+}
+
+#[cfg(feature = "jpeg")]
+fn sanitize_jpeg<W: Write>(data: &[u8], mut w: W, whitelist: Whitelist) -> ImageResult<()> {
+    use jpeg::{estimate_quality, Exif, JPEGDecoder, JPEGEncoder};
+
+    let mut decoder = JPEGDecoder::new(data);
+    let quality = estimate_quality(&try!(decoder.quantization_tables())).unwrap_or(90);
+    let icc_profile = try!(decoder.icc_profile());
+    let orientation = try!(decoder.exif_orientation());
+    let image = try!(dynimage::decoder_to_image(decoder));
+
+    let mut encoder = JPEGEncoder::new_with_quality(&mut w, quality);
+    if let (true, Some(profile)) = (whitelist.color_space, icc_profile) {
+        encoder.set_icc_profile(&profile);
+    }
+    if let (true, Some(orientation)) = (whitelist.orientation, orientation) {
+        encoder.set_exif(&Exif { orientation: Some(orientation), ..Exif::default() });
+    }
+
+    let (width, height) = image.dimensions();
+    try!(encoder.encode(&image.raw_pixels(), width, height, image.color()));
+    Ok(())
+}
+
+#[cfg(not(feature = "jpeg"))]
+fn sanitize_jpeg<W: Write>(_: &[u8], _: W, _: Whitelist) -> ImageResult<()> {
+    Err(ImageError::UnsupportedError("JPEG support is not enabled".to_string()))
+}
+
+impl FromStr for Chain {
+    type Err = ImageError;
+
+    fn from_str(spec: &str) -> ImageResult<Chain> {
+        Chain::parse(spec)
+    }
+}
+
+fn invalid(msg: &str) -> ImageError {
+    ImageError::FormatError(format!("Invalid operation chain: {}", msg))
+}
+
+// Returns `inner`, relative to `outer`, in the coordinates of the image
+fn nested(outer: Rect, inner: Rect) -> ImageResult<Rect> {
+    if !inner.fits_within(outer.width, outer.height) {
+        return Err(ImageError::DimensionError)
+    }
+
+    Ok(Rect::new(outer.x + inner.x, outer.y + inner.y, inner.width, inner.height))
+}
+
+fn crop_image(mut image: DynamicImage, rect: Rect) -> ImageResult<DynamicImage> {
+    let (width, height) = image.dimensions();
+
+    if !rect.fits_within(width, height) {
+        return Err(ImageError::DimensionError)
+    }
+
+    if rect == Rect::new(0, 0, width, height) {
+        return Ok(image)
+    }
+
+    Ok(image.crop(rect.x, rect.y, rect.width, rect.height))
+}
+
+fn parse_op(op: &str) -> ImageResult<Op> {
+    let mut parts = op.splitn(2, ':');
+    let name = parts.next().unwrap_or("");
+    let args = match parts.next() {
+        Some(args) => args.split(',').collect::<Vec<&str>>(),
+        None => Vec::new()
+    };
+
+    let arg = |i: usize| -> ImageResult<&str> {
+        args.get(i).cloned().ok_or_else(|| invalid(&format!("missing argument for {}", name)))
+    };
+
+    let expect_args = |min: usize, max: usize| -> ImageResult<()> {
+        if args.len() < min || args.len() > max {
+            return Err(invalid(&format!("wrong number of arguments for {}", name)))
+        }
+
+        Ok(())
+    };
+
+    match name {
+        "crop" => {
+            try!(expect_args(4, 4));
+            Ok(Op::Crop(Rect::new(
+                try!(number(try!(arg(0)))), try!(number(try!(arg(1)))),
+                try!(number(try!(arg(2)))), try!(number(try!(arg(3))))
+            )))
+        }
+        "resize" => {
+            try!(expect_args(2, 3));
+            let exact = match args.get(2).cloned() {
+                None | Some("fit") => false,
+                Some("exact") => true,
+                Some(mode) => return Err(invalid(&format!("unknown resize mode {}", mode)))
+            };
+
+            Ok(Op::Resize {
+                width: try!(number(try!(arg(0)))),
+                height: try!(number(try!(arg(1)))),
+                exact: exact
+            })
+        }
+        "sharpen" => {
+            try!(expect_args(1, 2));
+            let threshold = match args.get(1) {
+                Some(t) => try!(number(t)),
+                None => 0
+            };
+
+            Ok(Op::Sharpen(try!(number(try!(arg(0)))), threshold))
+        }
+        "blur" => {
+            try!(expect_args(1, 1));
+            Ok(Op::Blur(try!(number(try!(arg(0))))))
+        }
+        "grayscale" | "fliph" | "flipv" => {
+            try!(expect_args(0, 0));
+            Ok(match name {
+                "grayscale" => Op::Grayscale,
+                "fliph" => Op::FlipH,
+                _ => Op::FlipV
+            })
+        }
+        "rotate" => {
+            try!(expect_args(1, 1));
+            match try!(number(try!(arg(0)))) {
+                degrees @ 90 | degrees @ 180 | degrees @ 270 => Ok(Op::Rotate(degrees)),
+                _ => Err(invalid("rotation must be 90, 180 or 270"))
+            }
+        }
+        "encode" => {
+            try!(expect_args(1, 1));
+            let format = match &*try!(arg(0)).to_lowercase() {
+                "png" => ImageFormat::PNG,
+                "jpg" | "jpeg" => ImageFormat::JPEG,
+                "gif" => ImageFormat::GIF,
+                "webp" => ImageFormat::WEBP,
+                "ppm" => ImageFormat::PPM,
+                "tif" | "tiff" => ImageFormat::TIFF,
+                "tga" => ImageFormat::TGA,
+                "bmp" => ImageFormat::BMP,
+                format => return Err(invalid(&format!("unknown format {}", format)))
+            };
+
+            Ok(Op::Encode(format))
+        }
+        _ => Err(invalid(&format!("unknown operation {}", name)))
+    }
+}
+
+fn number<T: FromStr>(s: &str) -> ImageResult<T> {
+    s.trim().parse().map_err(|_| invalid(&format!("invalid number {}", s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chain, Op, Whitelist, sanitize, transform};
+    use dynimage::DynamicImage;
+    use image::{GenericImage, ImageError, ImageFormat};
+    use math::Rect;
+
+    #[test]
+    fn test_parse() {
+        let chain = Chain::parse("crop:1,2,30,40/resize:16,16,exact/rotate:90/encode:JPEG").unwrap();
+
+        assert_eq!(chain.ops(), &[
+            Op::Crop(Rect::new(1, 2, 30, 40)),
+            Op::Resize { width: 16, height: 16, exact: true },
+            Op::Rotate(90),
+            Op::Encode(ImageFormat::JPEG),
+        ]);
+
+        assert!(Chain::parse("encode:jpeg/blur:1").is_err());
+        assert!(Chain::parse("resize:16").is_err());
+        assert!(Chain::parse("rotate:45").is_err());
+        assert!(Chain::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let image = DynamicImage::new_rgb8(100, 80);
+
+        let chain = Chain::parse("crop:10,10,60,40/crop:5,5,40,20/resize:20,20/grayscale").unwrap();
+        let result = chain.apply(image.clone()).unwrap();
+        assert_eq!(result.dimensions(), (20, 10));
+        assert!(result.as_luma8().is_some());
+
+        assert!(Chain::parse("crop:10,10,60,40/crop:30,0,40,20").unwrap().apply(image.clone()).is_err());
+
+        let mut encoded = Vec::new();
+        let result = Chain::parse("fliph/encode:jpeg").unwrap().execute(image, &mut encoded);
+        if cfg!(feature = "jpeg") {
+            result.unwrap();
+            assert_eq!(&encoded[..2], &[0xFF, 0xD8]);
+        } else {
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_transform() {
+        let chain = Chain::parse("crop:0,0,2,2/rotate:90").unwrap();
+        let webp = b"RIFF\x00\x00\x00\x00WEBPVP8 ";
+
+        match transform(webp, Vec::new(), &chain) {
+            Err(ImageError::UnsupportedError(..)) => (),
+            _ => panic!("WebP files can not be transformed")
+        }
+
+        match transform(b"\x89PNG\r\n\x1a\n", Vec::new(), &Chain::parse("blur:1").unwrap()) {
+            Err(ImageError::UnsupportedError(..)) => (),
+            _ => panic!("blurs are not lossless")
+        }
+    }
+
+    // The CRC of PNG chunks
+    #[cfg(feature = "png_codec")]
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data.iter() {
+            crc ^= byte as u32;
+            for _ in (0..8) {
+                crc = if crc & 1 == 1 { crc >> 1 ^ 0xEDB88320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    #[cfg(feature = "png_codec")]
+    #[test]
+    fn test_transform_png() {
+        use buffer::ImageBuffer;
+        use color::{ColorSpace, ColorType, Rgb};
+        use dynimage::load_from_memory;
+        use png::PNGEncoder;
+
+        let image = ImageBuffer::from_fn(4, 3, |x, y| Rgb([x as u8, y as u8, 0]));
+        let mut file = Vec::new();
+        {
+            let mut encoder = PNGEncoder::new(&mut file);
+            encoder.set_color_space(ColorSpace::srgb());
+            encoder.encode(&image, 4, 3, ColorType::RGB(8)).unwrap();
+        }
+
+        // A background color and significant bits after the header
+        let chunk = |name: &[u8], data: &[u8]| {
+            let mut chunk = vec![0, 0, 0, data.len() as u8];
+            chunk.extend(name.iter().chain(data.iter()).cloned());
+            let crc = crc32(&chunk[4..]);
+            chunk.extend([(crc >> 24) as u8, (crc >> 16) as u8, (crc >> 8) as u8, crc as u8].iter());
+            chunk
+        };
+        let extra = [chunk(b"sBIT", &[5, 6, 5]), chunk(b"bKGD", &[0, 1, 0, 2, 0, 3])].concat();
+        let file = [&file[..33], &extra[..], &file[33..]].concat();
+
+        let mut out = Vec::new();
+        transform(&file, &mut out, &Chain::parse("crop:1,0,3,3/rotate:90").unwrap()).unwrap();
+
+        // The color space chunks are kept, and so are those of the color type
+        // as it does not change
+        assert!(out.windows(4).any(|w| w == b"sRGB") && out.windows(4).any(|w| w == b"gAMA"));
+        assert!(out.windows(extra.len()).any(|w| w == &extra[..]));
+
+        let expected = DynamicImage::ImageRgb8(image).crop(1, 0, 3, 3).rotate90();
+        assert_eq!(load_from_memory(&out).unwrap().raw_pixels(), expected.raw_pixels());
+    }
+
+    #[cfg(feature = "png_codec")]
+    #[test]
+    fn test_sanitize_png() {
+        use buffer::ImageBuffer;
+        use color::{ColorSpace, ColorType, Rgb};
+        use dynimage::load_from_memory;
+        use png::PNGEncoder;
+
+        let image = ImageBuffer::from_fn(4, 3, |x, y| Rgb([x as u8, y as u8, 0]));
+        let mut file = Vec::new();
+        {
+            let mut encoder = PNGEncoder::new(&mut file);
+            encoder.set_color_space(ColorSpace::srgb());
+            encoder.set_c2pa_manifest(b"\0\0\0\x08jumb");
+            encoder.encode(&image, 4, 3, ColorType::RGB(8)).unwrap();
+        }
+        file.extend(b"trailing payload".iter().cloned());
+
+        let mut out = Vec::new();
+        sanitize(&file, &mut out, Whitelist::default()).unwrap();
+        assert!(out.windows(4).any(|w| w == b"sRGB") && !out.windows(4).any(|w| w == b"caBX"));
+        assert!(out.ends_with(b"IEND\xAE\x42\x60\x82"));
+        assert_eq!(load_from_memory(&out).unwrap().raw_pixels(), DynamicImage::ImageRgb8(image).raw_pixels());
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_sanitize_jpeg() {
+        use color::ColorType;
+        use jpeg::{Exif, JPEGDecoder, JPEGEncoder};
+
+        let image = (0..16 * 8 * 3).map(|i| (i * 5 % 256) as u8).collect::<Vec<u8>>();
+        let mut file = Vec::new();
+        {
+            let mut encoder = JPEGEncoder::new_with_quality(&mut file, 60);
+            encoder.set_exif(&Exif { orientation: Some(8), ..Exif::default() });
+            encoder.set_icc_profile(b"profile");
+            encoder.encode(&image, 16, 8, ColorType::RGB(8)).unwrap();
+        }
+
+        // A comment, an APP9 segment and a payload after the image
+        file.splice(2..2, b"\xFF\xFE\x00\x07hello\xFF\xE9\x00\x06data".iter().cloned());
+        file.extend(b"PK\x03\x04 hidden archive".iter().cloned());
+
+        let mut out = Vec::new();
+        sanitize(&file, &mut out, Whitelist::default()).unwrap();
+        assert!(out.ends_with(&[0xFF, 0xD9]));
+
+        let mut decoder = JPEGDecoder::new(&out[..]);
+        assert_eq!(decoder.exif_orientation().unwrap(), Some(8));
+        assert_eq!(decoder.icc_profile().unwrap(), Some(b"profile".to_vec()));
+        let markers = decoder.marker_segments().unwrap().iter().map(|s| s.marker).collect::<Vec<u8>>();
+        assert_eq!(markers, vec![0xE0, 0xE1, 0xE2]);
+
+        let mut out = Vec::new();
+        sanitize(&file, &mut out, Whitelist { color_space: false, orientation: false }).unwrap();
+        let mut decoder = JPEGDecoder::new(&out[..]);
+        assert_eq!(decoder.marker_segments().unwrap().len(), 1);
+
+        match sanitize(b"GIF89a", Vec::new(), Whitelist::default()) {
+            Err(ImageError::UnsupportedError(..)) => (),
+            _ => panic!("GIF files can not be sanitized")
+        }
+    }
+}
@@ -11,10 +11,24 @@ extern crate png;
 
 use self::png::HasParameters;
 
+use std::cmp;
 use std::io::{self, Read, Write};
 
-use image::{ImageError, ImageResult, DecodingResult, ImageDecoder};
-use color::ColorType;
+use image::{ImageError, ImageResult, DecodingResult, ImageDecoder, GenericImage};
+use color::{ColorSpace, ColorType};
+use dynimage::{self, DynamicImage};
+
+// The signature every PNG file starts with
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Ancillary chunks that are not safe to copy by their name, but stay valid
+// when the pixels are rearranged or can be converted to the new color type,
+// or that record the provenance of the image
+const COPIED_CHUNKS: [&'static [u8; 4]; 8] = [b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"tIME", b"caBX",
+                                              b"bKGD", b"sBIT"];
+
+// The chunks that describe the color space
+const COLOR_SPACE_CHUNKS: [&'static [u8; 4]; 4] = [b"gAMA", b"cHRM", b"sRGB", b"iCCP"];
 
 enum Either<T, U> {
     Left(T),
@@ -88,17 +102,35 @@ impl<R: Read> ImageDecoder for PNGDecoder<R> {
 
 /// PNG encoder
 pub struct PNGEncoder<W: Write> {
-    w: W
+    w: W,
+    color_space: Option<ColorSpace>,
+    c2pa_manifest: Option<Vec<u8>>,
 }
 
 impl<W: Write> PNGEncoder<W> {
     /// Create a new encoder that writes its output to ```w```
     pub fn new(w: W) -> PNGEncoder<W> {
         PNGEncoder {
-            w: w
+            w: w,
+            color_space: None,
+            c2pa_manifest: None,
         }
     }
 
+    /// Records the color space of the image in ```sRGB```, ```gAMA``` and
+    /// ```cHRM``` chunks. PNG has a single gamma for all channels, thus
+    /// ```gAMA``` is left out if the gammas of the channels differ, and
+    /// the black and white points are not recorded.
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = Some(color_space);
+    }
+
+    /// Embeds the C2PA manifest store ```manifest```, a JUMBF box as
+    /// returned by ```read_c2pa_manifest```, in a ```caBX``` chunk
+    pub fn set_c2pa_manifest(&mut self, manifest: &[u8]) {
+        self.c2pa_manifest = Some(manifest.to_vec());
+    }
+
     /// Encodes the image ```image```
     /// that has dimensions ```width``` and ```height```
     /// and ```ColorType``` ```c```
@@ -107,10 +139,277 @@ impl<W: Write> PNGEncoder<W> {
         let mut encoder = png::Encoder::new(self.w, width, height);
         encoder.set(ct).set(bits);
         let mut writer = try!(encoder.write_header());
+
+        if let Some(space) = self.color_space {
+            for &(name, ref chunk) in color_space_chunks(&space, color).iter() {
+                try!(writer.write_chunk(name, chunk));
+            }
+        }
+
+        if let Some(ref manifest) = self.c2pa_manifest {
+            try!(writer.write_chunk(*b"caBX", manifest));
+        }
+
         writer.write_image_data(data).map_err(|e| e.into())
     }
 }
 
+/// Decodes the PNG file ```data```, rearranges its pixels with ```f``` and
+/// writes the result to ```w```, keeping the metadata of the file.
+///
+/// The image data is decoded and compressed again as a whole, without
+/// loss, rather than rewritten in place. The ancillary chunks that do not
+/// depend on the encoding of the pixels, like text, the modification time,
+/// the color space and the physical pixel size, and unknown chunks that are
+/// marked as safe to copy are written before the image data. The horizontal
+/// and vertical pixel sizes are swapped if ```transposed``` is set, as it is
+/// by rotations by 90 or 270 degrees. Palette images are written as
+/// RGB(A), with the background color of ```bKGD``` and the significant bits
+/// of ```sBIT``` converted to match.
+pub fn transform<W, F>(data: &[u8], w: W, transposed: bool, f: F) -> ImageResult<()>
+    where W: Write, F: FnOnce(DynamicImage) -> ImageResult<DynamicImage> {
+
+    let mut chunks = try!(copied_chunks(data));
+
+    for chunk in chunks.iter_mut() {
+        if transposed && &chunk.0 == b"pHYs" && chunk.1.len() >= 8 {
+            for i in (0..4) {
+                chunk.1.swap(i, i + 4);
+            }
+        }
+    }
+
+    rewrite(data, w, &chunks, f)
+}
+
+/// Decodes the PNG file ```data``` and writes its pixels to ```w```,
+/// dropping all chunks that could hide data: text, unknown chunks and
+/// anything after the image end. The chunks of the color space, ```gAMA```,
+/// ```cHRM```, ```sRGB``` and ```iCCP```, are kept if ```color_space``` is
+/// set.
+pub fn sanitize<W: Write>(data: &[u8], w: W, color_space: bool) -> ImageResult<()> {
+    let mut chunks = try!(copied_chunks(data));
+    chunks.retain(|c| color_space && COLOR_SPACE_CHUNKS.iter().any(|&name| *name == c.0));
+
+    rewrite(data, w, &chunks, Ok)
+}
+
+// Decodes `data`, applies `f` and writes the result with `chunks` before
+// the image data. Chunks that depend on the color type are converted to the
+// one of the result, or dropped if they can not be.
+fn rewrite<W, F>(data: &[u8], w: W, chunks: &[([u8; 4], Vec<u8>)], f: F) -> ImageResult<()>
+    where W: Write, F: FnOnce(DynamicImage) -> ImageResult<DynamicImage> {
+
+    let image = try!(f(try!(dynimage::decoder_to_image(PNGDecoder::new(data)))));
+
+    let (width, height) = image.dimensions();
+    let (ct, bits) = image.color().into();
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set(ct).set(bits);
+    let mut writer = try!(encoder.write_header().map_err(io::Error::from));
+
+    let source = try!(Source::new(data));
+
+    for &(name, ref chunk) in chunks.iter() {
+        let converted = match &name {
+            b"bKGD" => source.background(chunk, image.color()),
+            b"sBIT" => source.significant_bits(chunk, image.color()),
+            _ => Some(chunk.clone())
+        };
+
+        if let Some(chunk) = converted {
+            try!(writer.write_chunk(name, &chunk).map_err(io::Error::from));
+        }
+    }
+
+    try!(writer.write_image_data(&image.raw_pixels()).map_err(io::Error::from));
+    Ok(())
+}
+
+/// Returns the C2PA manifest store of the PNG file ```data```, the JUMBF
+/// box in its ```caBX``` chunk, without verifying it. ```transform```
+/// keeps the chunk.
+pub fn read_c2pa_manifest(data: &[u8]) -> ImageResult<Option<Vec<u8>>> {
+    let chunks = try!(copied_chunks(data));
+    Ok(chunks.into_iter().find(|c| &c.0 == b"caBX").map(|c| c.1))
+}
+
+// The ancillary chunks of the PNG file `data` that `transform` copies
+fn copied_chunks(data: &[u8]) -> ImageResult<Vec<([u8; 4], Vec<u8>)>> {
+    let chunks = try!(read_chunks(data));
+
+    Ok(chunks.into_iter().filter(|&(name, _)| {
+        // Lowercase first and last letters mark ancillary, safe to copy chunks
+        let ancillary = name[0] & 0x20 != 0;
+        let safe = name[3] & 0x20 != 0;
+
+        ancillary && (safe || COPIED_CHUNKS.iter().any(|&c| *c == name))
+    }).map(|(name, chunk)| (name, chunk.to_vec())).collect())
+}
+
+// The chunks of the PNG file `data` before its end
+fn read_chunks(data: &[u8]) -> ImageResult<Vec<([u8; 4], &[u8])>> {
+    if data.len() < 8 || data[..8] != SIGNATURE {
+        return Err(ImageError::FormatError("invalid signature".into()))
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+
+    while pos + 12 <= data.len() {
+        let len = (data[pos] as usize) << 24 | (data[pos + 1] as usize) << 16
+                | (data[pos + 2] as usize) << 8 | data[pos + 3] as usize;
+        let name = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+
+        if len > data.len() - pos - 12 {
+            return Err(ImageError::ImageEnd)
+        }
+
+        if &name == b"IEND" {
+            break
+        }
+
+        chunks.push((name, &data[pos + 8..pos + 8 + len]));
+        pos += 12 + len;
+    }
+
+    Ok(chunks)
+}
+
+// The header and palette of the file being transformed, which the chunks
+// that depend on the color type refer to
+struct Source<'a> {
+    color: u8,
+    depth: u8,
+    palette: &'a [u8],
+}
+
+impl<'a> Source<'a> {
+    fn new(data: &'a [u8]) -> ImageResult<Source<'a>> {
+        let chunks = try!(read_chunks(data));
+        let header = match chunks.iter().find(|c| &c.0 == b"IHDR") {
+            Some(&(_, header)) if header.len() >= 13 => header,
+            _ => return Err(ImageError::FormatError("missing image header".into()))
+        };
+
+        Ok(Source {
+            depth: header[8],
+            color: header[9],
+            palette: chunks.iter().find(|c| &c.0 == b"PLTE").map_or(&[][..], |c| c.1),
+        })
+    }
+
+    // The background color of `chunk` for an image of color type `out`. The
+    // decoder expands samples to at least 8 bits and palette indices to RGB.
+    fn background(&self, chunk: &[u8], out: ColorType) -> Option<Vec<u8>> {
+        let scale = |v: u16| -> u16 {
+            if self.depth < 8 {
+                let max = (1 << self.depth) - 1;
+                cmp::min(v, max) * 255 / max
+            } else {
+                v
+            }
+        };
+        let sample = |i: usize| chunk.get(i..i + 2).map(|b| scale((b[0] as u16) << 8 | b[1] as u16));
+
+        let samples = match self.color {
+            0 | 4 => vec![sample(0)],
+            2 | 6 => vec![sample(0), sample(2), sample(4)],
+            3 => {
+                let index = match chunk.first() {
+                    Some(&index) => index as usize,
+                    None => return None
+                };
+                match self.palette.get(3 * index..3 * index + 3) {
+                    Some(rgb) => rgb.iter().map(|&v| Some(v as u16)).collect(),
+                    None => return None
+                }
+            }
+            _ => return None
+        };
+
+        let samples = match samples.into_iter().collect::<Option<Vec<u16>>>() {
+            Some(samples) => samples,
+            None => return None
+        };
+
+        match (out, samples.len()) {
+            (ColorType::Gray(_), 1) | (ColorType::GrayA(_), 1) |
+            (ColorType::RGB(_), 3) | (ColorType::RGBA(_), 3) => {
+                Some(samples.iter().flat_map(|&v| vec![(v >> 8) as u8, v as u8].into_iter()).collect())
+            }
+            _ => None
+        }
+    }
+
+    // The significant bits of `chunk` for an image of color type `out`, with
+    // all bits of an alpha channel that the decoder added being significant
+    fn significant_bits(&self, chunk: &[u8], out: ColorType) -> Option<Vec<u8>> {
+        let channels = match self.color {
+            0 => 1,
+            4 => 2,
+            2 | 3 => 3,
+            6 => 4,
+            _ => return None
+        };
+        if chunk.len() < channels {
+            return None
+        }
+
+        let mut bits = chunk[..channels].to_vec();
+        let alpha = match out {
+            ColorType::GrayA(n) | ColorType::RGBA(n) => Some(n),
+            _ => None
+        };
+        match (alpha, channels) {
+            (Some(n), 1) | (Some(n), 3) => bits.push(n),
+            _ => ()
+        }
+
+        match (out, bits.len()) {
+            (ColorType::Gray(_), 1) | (ColorType::GrayA(_), 2) |
+            (ColorType::RGB(_), 3) | (ColorType::RGBA(_), 4) => Some(bits),
+            _ => None
+        }
+    }
+}
+
+// The chunks that record `space`, which precede the image data
+fn color_space_chunks(space: &ColorSpace, color: ColorType) -> Vec<([u8; 4], Vec<u8>)> {
+    let mut chunks = Vec::new();
+
+    // Perceptual rendering intent
+    if space.srgb {
+        chunks.push((*b"sRGB", vec![0]));
+    }
+
+    let gray = match color {
+        ColorType::Gray(_) | ColorType::GrayA(_) => true,
+        _ => false
+    };
+
+    let gamma = space.gamma[0];
+    if gray || space.gamma.iter().all(|&g| g == gamma) {
+        chunks.push((*b"gAMA", be_u32s(&[(100000.0 / gamma).round() as u32])));
+    }
+
+    let mut chromaticities = vec![space.white_point];
+    chromaticities.extend(space.primaries.iter().cloned());
+    let values = chromaticities.iter()
+                               .flat_map(|&(x, y)| vec![x, y].into_iter())
+                               .map(|v| (v * 100000.0).round() as u32)
+                               .collect::<Vec<u32>>();
+    chunks.push((*b"cHRM", be_u32s(&values)));
+
+    chunks
+}
+
+fn be_u32s(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|&v| {
+        vec![(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8].into_iter()
+    }).collect()
+}
+
 impl From<(png::ColorType, png::BitDepth)> for ColorType {
     fn from((ct, bits): (png::ColorType, png::BitDepth)) -> ColorType {
         use self::png::ColorType::*;
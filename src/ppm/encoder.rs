@@ -27,7 +27,12 @@ impl<'a, W: Write> PPMEncoder<'a, W> {
     /// Encode the buffer ```im``` as a PPM image.
     /// ```width``` and ```height``` are the dimensions of the buffer.
     /// ```color``` is the buffers ColorType.
+    /// Images without pixels are rejected with an ```InvalidInput``` error.
     pub fn encode(&mut self, im: &[u8], width: u32, height: u32, color: color::ColorType) -> io::Result<()> {
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Image has no pixels"))
+        }
+
         let _ = try!(self.write_magic_number());
         let _ = try!(self.write_metadata(width, height, color));
 
@@ -0,0 +1,247 @@
+//! Python bindings
+//!
+//! Enabled by the `python` feature. The extension module is named `image`
+//! and can be built with maturin: `maturin build --release --features
+//! python,pyo3/extension-module`. Pixels are exchanged as numpy arrays of
+//! `uint8` with the shape `(height, width)` for gray images and
+//! `(height, width, channels)` otherwise.
+//!
+//! ```python
+//! import image
+//!
+//! pixels = image.decode(open("photo.jpg", "rb").read())
+//! thumbnail = image.encode(pixels[::4, ::4], "jpeg", threads=4)
+//! ```
+
+use std::convert::TryFrom;
+
+use pyo3::prelude::*;
+use pyo3::exceptions::{PyIOError, PyTypeError, PyValueError};
+use pyo3::types::{PyBytes, PyDict};
+use pyo3::wrap_pyfunction;
+
+use buffer::ImageBuffer;
+use color::ColorType;
+use dynimage::{self, DynamicImage};
+use image::{GenericImage, ImageError, ImageFormat};
+use jpeg::JPEGEncoder;
+
+fn to_py_err(error: ImageError) -> PyErr {
+    match error {
+        ImageError::IoError(e) => PyIOError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string())
+    }
+}
+
+fn image_format(name: &str) -> PyResult<ImageFormat> {
+    match &*name.to_lowercase() {
+        "png" => Ok(ImageFormat::PNG),
+        "jpg" | "jpeg" => Ok(ImageFormat::JPEG),
+        "gif" => Ok(ImageFormat::GIF),
+        "webp" => Ok(ImageFormat::WEBP),
+        "ppm" => Ok(ImageFormat::PPM),
+        "tif" | "tiff" => Ok(ImageFormat::TIFF),
+        "tga" => Ok(ImageFormat::TGA),
+        "bmp" => Ok(ImageFormat::BMP),
+        _ => Err(PyValueError::new_err(format!("Unknown image format {:?}", name)))
+    }
+}
+
+/// Decodes an image file, detecting its format, into a numpy array
+#[pyfunction]
+fn decode<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyAny>> {
+    let image = try!(dynimage::load_from_memory(data).map_err(to_py_err));
+    let (width, height) = image.dimensions();
+
+    let (pixels, channels) = match image {
+        DynamicImage::ImageLuma8(ref i) => (i.to_vec(), 1),
+        DynamicImage::ImageLumaA8(ref i) => (i.to_vec(), 2),
+        DynamicImage::ImageRgb8(ref i) => (i.to_vec(), 3),
+        DynamicImage::ImageRgba8(ref i) => (i.to_vec(), 4),
+    };
+
+    let numpy = try!(py.import_bound("numpy"));
+    let array = try!(numpy.call_method1("frombuffer", (PyBytes::new_bound(py, &pixels), "uint8")));
+
+    if channels == 1 {
+        array.call_method1("reshape", ((height, width),))
+    } else {
+        array.call_method1("reshape", ((height, width, channels),))
+    }
+}
+
+/// Encodes a numpy array of `uint8` into an image file of the given format
+///
+/// Arrays of other types raise a `TypeError` instead of being cast. The
+/// JPEG encoder accepts the option `threads`.
+#[pyfunction]
+#[pyo3(signature = (array, format, **options))]
+fn encode<'py>(py: Python<'py>,
+               array: &Bound<'py, PyAny>,
+               format: &str,
+               options: Option<&Bound<'py, PyDict>>) -> PyResult<Bound<'py, PyBytes>> {
+    let format = try!(image_format(format));
+
+    let numpy = try!(py.import_bound("numpy"));
+    let dtype = try!(array.getattr("dtype"));
+    if !try!(dtype.eq(try!(numpy.getattr("uint8")))) {
+        return Err(PyTypeError::new_err(format!("Unsupported array type {}, expected uint8", dtype)))
+    }
+
+    let array = try!(numpy.call_method1("ascontiguousarray", (array,)));
+    let shape: Vec<usize> = try!(try!(array.getattr("shape")).extract());
+
+    let dimension = |n: usize| u32::try_from(n).map_err(|_| {
+        PyValueError::new_err(format!("Unsupported array shape {:?}", shape))
+    });
+
+    let (height, width, channels) = match shape[..] {
+        [h, w] => (try!(dimension(h)), try!(dimension(w)), 1),
+        [h, w, c] if c >= 1 && c <= 4 => (try!(dimension(h)), try!(dimension(w)), c),
+        _ => return Err(PyValueError::new_err(format!("Unsupported array shape {:?}", shape)))
+    };
+
+    let bytes = try!(array.call_method0("tobytes"));
+    let data = try!(bytes.downcast::<PyBytes>()).as_bytes().to_vec();
+
+    let mut threads = 1;
+    if let Some(options) = options {
+        for (key, value) in options.iter() {
+            let key: String = try!(key.extract());
+
+            match (&*key, format) {
+                ("threads", ImageFormat::JPEG) => threads = try!(value.extract()),
+                _ => return Err(PyValueError::new_err(format!("Unknown option {:?}", key)))
+            }
+        }
+    }
+
+    let mut encoded = Vec::new();
+
+    let result = if format == ImageFormat::JPEG {
+        let color = match channels {
+            1 => ColorType::Gray(8),
+            2 => ColorType::GrayA(8),
+            3 => ColorType::RGB(8),
+            _ => ColorType::RGBA(8),
+        };
+        let mut encoder = JPEGEncoder::new(&mut encoded);
+        encoder.set_threads(threads);
+        encoder.encode(&data, width, height, color).map_err(ImageError::from)
+    } else {
+        let image = match channels {
+            1 => ImageBuffer::from_raw(width, height, data).map(|i| DynamicImage::ImageLuma8(i.into())),
+            2 => ImageBuffer::from_raw(width, height, data).map(|i| DynamicImage::ImageLumaA8(i.into())),
+            3 => ImageBuffer::from_raw(width, height, data).map(|i| DynamicImage::ImageRgb8(i.into())),
+            _ => ImageBuffer::from_raw(width, height, data).map(|i| DynamicImage::ImageRgba8(i.into())),
+        };
+
+        match image {
+            Some(image) => image.save(&mut encoded, format),
+            None => Err(ImageError::DimensionError)
+        }
+    };
+
+    try!(result.map_err(to_py_err));
+    Ok(PyBytes::new_bound(py, &encoded))
+}
+
+/// The `image` Python module
+#[pymodule]
+#[pyo3(name = "image")]
+fn python_module(m: &Bound<PyModule>) -> PyResult<()> {
+    try!(m.add_function(try!(wrap_pyfunction!(python::decode, m))));
+    try!(m.add_function(try!(wrap_pyfunction!(python::encode, m))));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::prelude::*;
+    use pyo3::exceptions::{PyTypeError, PyValueError};
+    use pyo3::types::{PyDict, PyModule};
+
+    use super::python_module;
+
+    // Stands in for numpy where it is not installed, with just the parts
+    // used by the bindings
+    const NUMPY: &'static str = "
+import sys
+try:
+    import numpy
+except ImportError:
+    class Array(object):
+        def __init__(self, data, shape, dtype):
+            self.data, self.shape, self.dtype = bytes(data), tuple(shape), dtype
+        def reshape(self, shape):
+            return Array(self.data, shape, self.dtype)
+        def tobytes(self):
+            return self.data
+
+    class Numpy(object):
+        uint8 = 'uint8'
+        def frombuffer(self, data, dtype):
+            return Array(data, (len(data),), dtype)
+        def ascontiguousarray(self, array):
+            return array
+
+    sys.modules['numpy'] = Numpy()
+";
+
+    fn with_module<F: FnOnce(Python, &Bound<PyModule>)>(f: F) {
+        pyo3::prepare_freethreaded_python();
+
+        Python::with_gil(|py| {
+            py.run_bound(NUMPY, None, None).unwrap();
+            let module = PyModule::new_bound(py, "image").unwrap();
+            python_module(&module).unwrap();
+            f(py, &module);
+        });
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        with_module(|py, module| {
+            let numpy = py.import_bound("numpy").unwrap();
+            let pixels = numpy.call_method1("frombuffer", (vec![128u8; 16 * 8].as_slice(), "uint8")).unwrap()
+                              .call_method1("reshape", ((8, 16),)).unwrap();
+
+            let options = PyDict::new_bound(py);
+            options.set_item("threads", 2).unwrap();
+            let encoded = module.getattr("encode").unwrap()
+                                .call((&pixels, "jpeg"), Some(&options)).unwrap();
+
+            let decoded = module.getattr("decode").unwrap().call1((encoded,)).unwrap();
+            let shape: Vec<usize> = decoded.getattr("shape").unwrap().extract().unwrap();
+            let data: Vec<u8> = decoded.call_method0("tobytes").unwrap().extract().unwrap();
+
+            assert_eq!(shape, vec![8, 16]);
+            assert_eq!(data, vec![128u8; 16 * 8]);
+        });
+    }
+
+    #[test]
+    fn test_errors() {
+        with_module(|py, module| {
+            let decoded = module.getattr("decode").unwrap().call1((&b"not an image"[..],));
+            assert!(decoded.unwrap_err().is_instance_of::<PyValueError>(py));
+
+            let options = PyDict::new_bound(py);
+            options.set_item("quality", 90).unwrap();
+            let numpy = py.import_bound("numpy").unwrap();
+            let pixels = numpy.call_method1("frombuffer", (&[0u8; 4][..], "uint8")).unwrap()
+                              .call_method1("reshape", ((2, 2),)).unwrap();
+
+            for &(format, options) in [("jpeg", Some(&options)), ("xyz", None)].iter() {
+                let encoded = module.getattr("encode").unwrap().call((&pixels, format), options);
+                assert!(encoded.unwrap_err().is_instance_of::<PyValueError>(py));
+            }
+
+            // Floats are not cast to uint8
+            let floats = numpy.call_method1("frombuffer", (&[0u8; 16][..], "float32")).unwrap()
+                              .call_method1("reshape", ((2, 2),)).unwrap();
+            let encoded = module.getattr("encode").unwrap().call1((&floats, "jpeg"));
+            assert!(encoded.unwrap_err().is_instance_of::<PyTypeError>(py));
+        });
+    }
+}
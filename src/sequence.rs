@@ -0,0 +1,375 @@
+//! Numbered image sequences
+//!
+//! Video tools exchange frames as one file per frame, numbered by a
+//! printf-like pattern such as `frame_%05d.png`. A `Writer` encodes each
+//! frame into a buffer that is kept for the next one and writes the file at
+//! once, a `Reader` finds the frames of a pattern in a directory and loads
+//! them in order.
+//!
+//! ```no_run
+//! use image::{ImageFormat, RgbImage, ImageRgb8};
+//! use image::sequence::{Reader, WriteOptions, Writer};
+//!
+//! let mut writer = Writer::new("out", "frame_%05d.jpg", ImageFormat::JPEG,
+//!                              WriteOptions::default()).unwrap();
+//! for _ in 0..10 {
+//!     writer.write_frame(&ImageRgb8(RgbImage::new(64, 48).into())).unwrap();
+//! }
+//!
+//! let frames = Reader::new("out", "frame_%05d.jpg", ImageFormat::JPEG).unwrap().frames().unwrap();
+//! ```
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use animation::{Frame, Frames};
+use color::ColorType;
+use dynimage::{self, DynamicImage};
+use image::{GenericImage, ImageError, ImageFormat, ImageResult};
+
+#[cfg(feature = "jpeg")]
+use jpeg;
+#[cfg(feature = "png_codec")]
+use png;
+#[cfg(feature = "ppm")]
+use ppm;
+
+/// The options of a `Writer`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// The number of the first frame. Defaults to 0.
+    pub first: u32,
+
+    /// The quality of JPEG frames from 1 to 100, the default of the encoder
+    /// if `None`
+    pub quality: Option<u8>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions {
+            first: 0,
+            quality: None,
+        }
+    }
+}
+
+// A file name pattern with one number, padded with zeros to `width` digits
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Pattern {
+    prefix: String,
+    width: usize,
+    suffix: String,
+}
+
+impl Pattern {
+    // Parses a pattern with exactly one `%d` or `%0<width>d`, where `%%`
+    // stands for a literal `%`
+    fn parse(pattern: &str) -> ImageResult<Pattern> {
+        let invalid = || ImageError::IoError(io::Error::new(
+            io::ErrorKind::InvalidInput, format!("Invalid frame pattern {:?}", pattern)));
+
+        let mut parts = (String::new(), String::new());
+        let mut width = None;
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let part = if width.is_none() { &mut parts.0 } else { &mut parts.1 };
+
+            if c != '%' {
+                part.push(c);
+                continue
+            }
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                part.push('%');
+                continue
+            }
+            if width.is_some() {
+                return Err(invalid())
+            }
+
+            let mut digits = String::new();
+            while let Some(&d) = chars.peek() {
+                if !d.is_digit(10) {
+                    break
+                }
+                digits.push(d);
+                chars.next();
+            }
+            if chars.next() != Some('d') || (!digits.is_empty() && !digits.starts_with('0')) {
+                return Err(invalid())
+            }
+            width = Some(if digits.is_empty() { 0 } else { try!(digits.parse().map_err(|_| invalid())) });
+        }
+
+        match width {
+            Some(width) => Ok(Pattern { prefix: parts.0, width: width, suffix: parts.1 }),
+            None => Err(invalid())
+        }
+    }
+
+    fn name(&self, number: u32) -> String {
+        format!("{}{:0width$}{}", self.prefix, number, self.suffix, width = self.width)
+    }
+
+    // The number of the file `name` if it matches the pattern
+    fn number(&self, name: &str) -> Option<u32> {
+        if !name.starts_with(&self.prefix) || !name.ends_with(&self.suffix)
+            || name.len() < self.prefix.len() + self.suffix.len() {
+            return None
+        }
+
+        let digits = &name[self.prefix.len()..name.len() - self.suffix.len()];
+        let padded = digits.len() == self.width || digits.len() > self.width && !digits.starts_with('0');
+
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(10)) || !padded && self.width > 0 {
+            return None
+        }
+
+        digits.parse().ok()
+    }
+}
+
+/// Writes the frames of a sequence as numbered files
+pub struct Writer {
+    dir: PathBuf,
+    pattern: Pattern,
+    format: ImageFormat,
+    options: WriteOptions,
+    next: u32,
+    // The encoded frame, kept for the next one
+    encoded: Vec<u8>,
+}
+
+impl Writer {
+    /// Creates a writer of frames in ```format``` to the directory ```dir```,
+    /// which is created if it does not exist. The frames are named by
+    /// ```pattern```, which contains one ```%d``` or ```%0<width>d``` for the
+    /// number of the frame, e.g. ```frame_%05d.png```. A literal ```%``` is
+    /// written as ```%%```.
+    ///
+    /// Frames can be written as JPEG, PNG and PPM, as far as these codecs
+    /// are enabled.
+    pub fn new<P: AsRef<Path>>(dir: P, pattern: &str, format: ImageFormat, options: WriteOptions)
+        -> ImageResult<Writer> {
+
+        let pattern = try!(Pattern::parse(pattern));
+
+        match format {
+            #[cfg(feature = "jpeg")]
+            ImageFormat::JPEG => (),
+            #[cfg(feature = "png_codec")]
+            ImageFormat::PNG => (),
+            #[cfg(feature = "ppm")]
+            ImageFormat::PPM => (),
+            _ => return Err(ImageError::UnsupportedError(
+                format!("Sequences of {:?} frames can not be written.", format)))
+        }
+
+        try!(fs::create_dir_all(dir.as_ref()));
+
+        Ok(Writer {
+            dir: dir.as_ref().to_path_buf(),
+            pattern: pattern,
+            format: format,
+            options: options,
+            next: options.first,
+            encoded: Vec::new(),
+        })
+    }
+
+    /// Writes ```image``` as the next frame and returns the path of its file
+    pub fn write_frame(&mut self, image: &DynamicImage) -> ImageResult<PathBuf> {
+        let (width, height) = image.dimensions();
+        let bytes: &[u8] = match *image {
+            DynamicImage::ImageLuma8(ref p) => p,
+            DynamicImage::ImageLumaA8(ref p) => p,
+            DynamicImage::ImageRgb8(ref p) => p,
+            DynamicImage::ImageRgba8(ref p) => p,
+        };
+
+        self.write_buffer(bytes, width, height, image.color())
+    }
+
+    /// Writes the pixels of ```buf``` with the dimensions ```width``` and
+    /// ```height``` and the color type ```color``` as the next frame and
+    /// returns the path of its file
+    pub fn write_buffer(&mut self, buf: &[u8], width: u32, height: u32, color: ColorType)
+        -> ImageResult<PathBuf> {
+
+        self.encoded.clear();
+        try!(encode(&mut self.encoded, buf, width, height, color, self.format, self.options.quality));
+
+        let path = self.dir.join(self.pattern.name(self.next));
+        let mut file = try!(File::create(&path));
+        try!(file.write_all(&self.encoded));
+
+        self.next += 1;
+        Ok(path)
+    }
+
+    /// The number of the next frame
+    pub fn next_number(&self) -> u32 {
+        self.next
+    }
+}
+
+fn encode(out: &mut Vec<u8>, buf: &[u8], width: u32, height: u32, color: ColorType,
+          format: ImageFormat, quality: Option<u8>) -> io::Result<()> {
+    match format {
+        #[cfg(feature = "jpeg")]
+        ImageFormat::JPEG => {
+            let mut encoder = match quality {
+                Some(quality) => jpeg::JPEGEncoder::new_with_quality(out, quality),
+                None => jpeg::JPEGEncoder::new(out),
+            };
+            encoder.encode(buf, width, height, color)
+        }
+        #[cfg(feature = "png_codec")]
+        ImageFormat::PNG => png::PNGEncoder::new(out).encode(buf, width, height, color),
+        #[cfg(feature = "ppm")]
+        ImageFormat::PPM => ppm::PPMEncoder::new(out).encode(buf, width, height, color),
+        _ => unreachable!()
+    }
+}
+
+/// Reads the frames of a sequence written as numbered files
+pub struct Reader {
+    format: ImageFormat,
+    // The numbers and paths of the frames in order
+    files: Vec<(u32, PathBuf)>,
+}
+
+impl Reader {
+    /// Finds the frames in ```format``` in the directory ```dir``` whose
+    /// names match ```pattern```, as taken by ```Writer::new```. Frames are
+    /// ordered by their number, which need not be consecutive. With a width
+    /// in the pattern only numbers padded to that width match.
+    pub fn new<P: AsRef<Path>>(dir: P, pattern: &str, format: ImageFormat) -> ImageResult<Reader> {
+        let pattern = try!(Pattern::parse(pattern));
+        let mut files = Vec::new();
+
+        for entry in try!(fs::read_dir(dir)) {
+            let entry = try!(entry);
+            let number = entry.file_name().to_str().and_then(|name| pattern.number(name));
+
+            if let Some(number) = number {
+                files.push((number, entry.path()));
+            }
+        }
+
+        files.sort();
+
+        Ok(Reader {
+            format: format,
+            files: files,
+        })
+    }
+
+    /// The number of frames
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns true if no frames were found
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The numbers of the frames in order
+    pub fn numbers(&self) -> Vec<u32> {
+        self.files.iter().map(|&(number, _)| number).collect()
+    }
+
+    /// Loads frame ```index```, counted from 0 in the order of the numbers
+    ///
+    /// # Panics
+    ///
+    /// Panics if ```index``` is not less than the number of frames.
+    pub fn read_frame(&self, index: usize) -> ImageResult<DynamicImage> {
+        let file = try!(File::open(&self.files[index].1));
+        dynimage::load(BufReader::new(file), self.format)
+    }
+
+    /// Loads all frames as RGBA, without delays between them
+    pub fn frames(&self) -> ImageResult<Frames> {
+        let mut frames = Vec::with_capacity(self.files.len());
+
+        for index in (0..self.files.len()) {
+            frames.push(Frame::new(try!(self.read_frame(index)).to_rgba()));
+        }
+
+        Ok(Frames::new(frames))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+
+    #[test]
+    fn test_pattern() {
+        let pattern = Pattern::parse("frame_%05d.png").unwrap();
+        assert_eq!(pattern.name(42), "frame_00042.png");
+        assert_eq!(pattern.name(1234567), "frame_1234567.png");
+        assert_eq!(pattern.number("frame_00042.png"), Some(42));
+        assert_eq!(pattern.number("frame_1234567.png"), Some(1234567));
+        assert_eq!(pattern.number("frame_42.png"), None);
+        assert_eq!(pattern.number("frame_0000x.png"), None);
+        assert_eq!(pattern.number("frame_00042.jpg"), None);
+
+        let pattern = Pattern::parse("%d%%.ppm").unwrap();
+        assert_eq!(pattern.name(7), "7%.ppm");
+        assert_eq!(pattern.number("7%.ppm"), Some(7));
+        assert_eq!(pattern.number("%.ppm"), None);
+
+        for invalid in ["frame.png", "%d_%d.png", "%5d.png", "%05x.png", "100%"].iter() {
+            assert!(Pattern::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_round_trip() {
+        use std::env;
+        use std::fs;
+
+        use buffer::{ImageBuffer, RgbImage};
+        use color::Rgb;
+        use dynimage::DynamicImage;
+        use image::{GenericImage, ImageFormat};
+        use super::{Reader, WriteOptions, Writer};
+
+        let dir = env::temp_dir().join(format!("image-sequence-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let options = WriteOptions { first: 8, quality: Some(95) };
+        let mut writer = Writer::new(&dir, "frame_%03d.jpg", ImageFormat::JPEG, options).unwrap();
+        for i in (0..3) {
+            let frame: RgbImage = ImageBuffer::from_pixel(16, 8, Rgb([i * 100, 50, 0]));
+            let path = writer.write_frame(&DynamicImage::ImageRgb8(frame.into())).unwrap();
+            assert_eq!(path, dir.join(format!("frame_{:03}.jpg", 8 + i)));
+        }
+        assert_eq!(writer.next_number(), 11);
+
+        // Other files in the directory are ignored
+        fs::File::create(dir.join("frame_9.jpg")).unwrap();
+        fs::File::create(dir.join("notes.txt")).unwrap();
+
+        let reader = Reader::new(&dir, "frame_%03d.jpg", ImageFormat::JPEG).unwrap();
+        assert_eq!(reader.numbers(), vec![8, 9, 10]);
+        assert_eq!(reader.read_frame(1).unwrap().dimensions(), (16, 8));
+
+        let frames = reader.frames().unwrap().collect::<Vec<_>>();
+        assert_eq!(frames.len(), 3);
+        for (i, frame) in frames.iter().enumerate() {
+            let red = frame.buffer().get_pixel(8, 4)[0] as i32;
+            assert!((red - i as i32 * 100).abs() <= 2, "{} {}", i, red);
+        }
+
+        assert!(Writer::new(&dir, "frame_%03d.tga", ImageFormat::TGA, options).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
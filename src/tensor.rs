@@ -0,0 +1,185 @@
+//! Normalized tensors as the input of neural networks
+//!
+//! Models expect samples scaled to 0 to 1, shifted by the mean and divided
+//! by the standard deviation of each channel in their training data, either
+//! interleaved per pixel or as one plane per channel. Each sample is mapped
+//! with one multiply and one add, vectorized at the SIMD level of
+//! ```config::simd```, with the same results at every level.
+
+use config::{self, SimdLevel};
+
+#[cfg(all(feature = "simd", target_arch = "x86"))]
+use std::arch::x86::*;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+use std::arch::x86_64::*;
+
+/// The order of the samples of a tensor
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TensorLayout {
+    /// One plane per channel, as taken by PyTorch and ONNX models
+    Chw,
+    /// The channels of each pixel interleaved, as taken by TensorFlow models
+    Hwc,
+}
+
+/// The mean and standard deviation of each channel a tensor is normalized
+/// with, and its layout.
+///
+/// The number of channels selects the color type of the tensor: 1 for
+/// luma, 3 for RGB and 4 for RGBA.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Normalization {
+    /// The mean of each channel, from 0 to 1
+    pub mean: Vec<f32>,
+    /// The standard deviation of each channel, from 0 to 1
+    pub std: Vec<f32>,
+    /// The order of the samples
+    pub layout: TensorLayout,
+}
+
+impl Normalization {
+    /// The RGB mean and standard deviation of ImageNet, which most vision
+    /// models are trained with, in ```layout```
+    pub fn imagenet(layout: TensorLayout) -> Normalization {
+        Normalization {
+            mean: vec![0.485, 0.456, 0.406],
+            std: vec![0.229, 0.224, 0.225],
+            layout: layout,
+        }
+    }
+}
+
+// Normalizes the interleaved `samples` of `channels` per pixel, which has to
+// be the number of channels of `normalization`
+pub fn normalize(samples: &[u8], channels: usize, normalization: &Normalization) -> Vec<f32> {
+    assert!(normalization.mean.len() == channels && normalization.std.len() == channels);
+
+    if samples.is_empty() {
+        return Vec::new()
+    }
+
+    // (v / 255 - mean) / std as one multiply and add
+    let scale = normalization.std.iter().map(|&s| 1.0 / (255.0 * s)).collect::<Vec<f32>>();
+    let offset = normalization.mean.iter().zip(normalization.std.iter())
+                                   .map(|(&m, &s)| -m / s).collect::<Vec<f32>>();
+
+    let level = config::simd();
+    let mut out = vec![0f32; samples.len()];
+
+    match normalization.layout {
+        TensorLayout::Hwc => scale_samples(level, samples, &scale, &offset, &mut out),
+        TensorLayout::Chw => {
+            let pixels = samples.len() / channels;
+            let mut plane = vec![0u8; pixels];
+
+            for (c, out) in out.chunks_mut(pixels).enumerate() {
+                for (p, s) in plane.iter_mut().zip(samples[c..].iter().step_by(channels)) {
+                    *p = *s;
+                }
+
+                scale_samples(level, &plane, &scale[c..c + 1], &offset[c..c + 1], out);
+            }
+        }
+    }
+
+    out
+}
+
+// Maps each of `samples` to `v * scale + offset` of its channel, where the
+// channels repeat with the period of `scale`
+fn scale_samples(level: SimdLevel, samples: &[u8], scale: &[f32], offset: &[f32], out: &mut [f32]) {
+    let done = scale_samples_simd(level, samples, scale, offset, out);
+
+    for (i, (&v, o)) in samples.iter().zip(out.iter_mut()).enumerate().skip(done) {
+        let c = i % scale.len();
+        *o = v as f32 * scale[c] + offset[c];
+    }
+}
+
+// Scales whole periods of 12 samples, which hold whole pixels of 1, 3 or 4
+// channels, if `level` has a vectorized kernel. Returns the number of
+// samples scaled.
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+fn scale_samples_simd(level: SimdLevel, samples: &[u8], scale: &[f32], offset: &[f32], out: &mut [f32]) -> usize {
+    if 12 % scale.len() != 0 {
+        return 0
+    }
+
+    match level {
+        SimdLevel::Sse2 | SimdLevel::Avx2 if level.is_supported() => {
+            let n = samples.len() / 12 * 12;
+            unsafe { scale_samples_sse2(&samples[..n], scale, offset, &mut out[..n]) };
+            n
+        }
+        _ => 0
+    }
+}
+
+#[cfg(not(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64"))))]
+fn scale_samples_simd(_: SimdLevel, _: &[u8], _: &[f32], _: &[f32], _: &mut [f32]) -> usize {
+    0
+}
+
+#[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+#[target_feature(enable = "sse2")]
+unsafe fn scale_samples_sse2(samples: &[u8], scale: &[f32], offset: &[f32], out: &mut [f32]) {
+    // The scales and offsets of the 12 lanes of the 3 vectors of a period
+    let lanes = |v: &[f32], i: usize| {
+        _mm_set_ps(v[(i + 3) % v.len()], v[(i + 2) % v.len()], v[(i + 1) % v.len()], v[i % v.len()])
+    };
+    let scales = [lanes(scale, 0), lanes(scale, 4), lanes(scale, 8)];
+    let offsets = [lanes(offset, 0), lanes(offset, 4), lanes(offset, 8)];
+
+    for (s, o) in samples.chunks(12).zip(out.chunks_mut(12)) {
+        for j in (0..3) {
+            let i = j * 4;
+            let v = _mm_set_ps(s[i + 3] as f32, s[i + 2] as f32, s[i + 1] as f32, s[i] as f32);
+            let v = _mm_add_ps(_mm_mul_ps(v, scales[j]), offsets[j]);
+            _mm_storeu_ps(o[i..i + 4].as_mut_ptr(), v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::SimdLevel;
+    use super::{normalize, scale_samples, Normalization, TensorLayout};
+
+    #[test]
+    fn test_layouts() {
+        // Two pixels, red and blue
+        let samples = [255u8, 0, 0, 0, 0, 255];
+        let normalization = Normalization {
+            mean: vec![0.5, 0.5, 0.5],
+            std: vec![0.5, 0.25, 0.5],
+            layout: TensorLayout::Hwc,
+        };
+
+        assert_eq!(normalize(&samples, 3, &normalization), vec![1.0, -2.0, -1.0, -1.0, -2.0, 1.0]);
+
+        let chw = Normalization { layout: TensorLayout::Chw, ..normalization };
+        assert_eq!(normalize(&samples, 3, &chw), vec![1.0, -1.0, -2.0, -2.0, -1.0, 1.0]);
+
+        // Images without pixels
+        assert!(normalize(&[], 3, &Normalization { layout: TensorLayout::Hwc, ..chw.clone() }).is_empty());
+        assert!(normalize(&[], 3, &chw).is_empty());
+    }
+
+    #[test]
+    fn test_simd_levels() {
+        let samples = (0..12 * 7 + 5).map(|i| (i * 37 % 256) as u8).collect::<Vec<u8>>();
+        let normalization = Normalization::imagenet(TensorLayout::Hwc);
+        let scale = normalization.std.iter().map(|&s| 1.0 / (255.0 * s)).collect::<Vec<f32>>();
+        let offset = normalization.mean.iter().zip(normalization.std.iter())
+                                       .map(|(&m, &s)| -m / s).collect::<Vec<f32>>();
+
+        let mut expected = vec![0f32; samples.len()];
+        scale_samples(SimdLevel::Scalar, &samples, &scale, &offset, &mut expected);
+
+        for &level in [SimdLevel::Sse2, SimdLevel::Avx2, SimdLevel::Neon].iter() {
+            let mut out = vec![0f32; samples.len()];
+            scale_samples(level.effective(), &samples, &scale, &offset, &mut out);
+            assert!(out == expected, "{:?}", level);
+        }
+    }
+}
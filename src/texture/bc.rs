@@ -0,0 +1,559 @@
+//! BC1, BC3 and BC7 blocks
+//!
+//! BC1 stores two RGB565 endpoints and a 2 bit index per pixel, which
+//! selects one of four colors on the line between the endpoints, or one of
+//! three and transparent black. The alpha of BC3 is stored like a BC4 block,
+//! with two endpoints and 3 bit indices. BC7 has eight modes, of which the
+//! encoder uses mode 6: two RGBA endpoints of 7 bits and a shared low bit per
+//! endpoint, with 4 bit indices.
+
+use std::f32;
+
+use super::{Block, Quality};
+
+// The weights of the 4 bit indices of BC7 in 64ths
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+// The number of refinements of the endpoints at the best quality
+const REFINEMENTS: usize = 4;
+
+type Color = [f32; 4];
+
+/// Encodes the RGB of ```block``` as a BC1 block. If ```punchthrough``` is
+/// true, pixels with an alpha below 128 are encoded as transparent.
+pub fn bc1(block: &Block, quality: Quality, punchthrough: bool) -> [u8; 8] {
+    let transparent = punchthrough && block.iter().any(|p| p[3] < 128);
+    let opaque = block.iter().filter(|p| !transparent || p[3] >= 128).map(color).collect::<Vec<_>>();
+
+    if opaque.is_empty() {
+        return [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]
+    }
+
+    let (a, b) = fit_line(&opaque, 3, quality);
+    let mut best = bc1_encode(block, rgb565(&a), rgb565(&b), transparent);
+
+    for _ in (0..refinements(quality)) {
+        let weights = if transparent { [0.0, 1.0, 0.5, 0.0] } else { [0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0] };
+        let w = block.iter().zip(best.2.iter())
+                     .filter(|&(p, _)| !transparent || p[3] >= 128)
+                     .map(|(_, &i)| weights[i as usize])
+                     .collect::<Vec<_>>();
+
+        let candidate = match least_squares(&opaque, &w, 3) {
+            Some((a, b)) => bc1_encode(block, rgb565(&a), rgb565(&b), transparent),
+            None => break
+        };
+
+        if candidate.1 >= best.1 {
+            break
+        }
+        best = candidate;
+    }
+
+    best.0
+}
+
+// Encodes `block` with the endpoints `e0` and `e1`, returns the block, its
+// error and its indices
+fn bc1_encode(block: &Block, e0: u16, e1: u16, transparent: bool) -> ([u8; 8], f32, [u8; 16]) {
+    // The three color mode is selected by the first endpoint not being greater
+    let (c0, c1) = if (transparent && e0 > e1) || (!transparent && e0 < e1) { (e1, e0) } else { (e0, e1) };
+    let (p0, p1) = (unpack565(c0), unpack565(c1));
+
+    let (palette, count) = if transparent {
+        ([p0, p1, mix(&p0, &p1, 0.5), p0], 3)
+    } else if c0 == c1 {
+        ([p0, p1, p0, p1], 1)
+    } else {
+        ([p0, p1, mix(&p0, &p1, 1.0 / 3.0), mix(&p0, &p1, 2.0 / 3.0)], 4)
+    };
+
+    let mut indices = [0u8; 16];
+    let mut error = 0.0;
+
+    for (i, p) in block.iter().enumerate() {
+        if transparent && p[3] < 128 {
+            indices[i] = 3;
+            continue
+        }
+
+        let (index, e) = nearest(&palette[..count], &color(p), 3);
+        indices[i] = index as u8;
+        error += e;
+    }
+
+    let mut bytes = [c0 as u8, (c0 >> 8) as u8, c1 as u8, (c1 >> 8) as u8, 0, 0, 0, 0];
+    for (i, &index) in indices.iter().enumerate() {
+        bytes[4 + i / 4] |= index << (2 * (i % 4));
+    }
+
+    (bytes, error, indices)
+}
+
+/// Encodes the samples of ```channel``` of ```block``` as a BC4 block, as
+/// the alpha of BC3 is stored.
+pub fn bc4(block: &Block, channel: usize, quality: Quality) -> [u8; 8] {
+    let values = block.iter().map(|p| p[channel]).collect::<Vec<_>>();
+    let min = values.iter().cloned().min().unwrap();
+    let max = values.iter().cloned().max().unwrap();
+
+    let mut best = bc4_encode(&values, max, min);
+
+    // Six interpolated values and the extremes 0 and 255
+    if quality != Quality::Fast {
+        let inner = values.iter().cloned().filter(|&v| v != 0 && v != 255).collect::<Vec<_>>();
+        let lo = inner.iter().cloned().min().unwrap_or(0);
+        let hi = inner.iter().cloned().max().unwrap_or(0);
+
+        let candidate = bc4_encode(&values, lo, hi);
+        if candidate.1 < best.1 {
+            best = candidate;
+        }
+    }
+
+    best.0
+}
+
+fn bc4_encode(values: &[u8], a0: u8, a1: u8) -> ([u8; 8], f32) {
+    let (e0, e1) = (a0 as f32, a1 as f32);
+    let mut palette = [e0, e1, 0.0, 0.0, 0.0, 0.0, 0.0, 255.0];
+
+    if a0 > a1 {
+        for i in (1..7) {
+            palette[i + 1] = ((7 - i) as f32 * e0 + i as f32 * e1) / 7.0;
+        }
+    } else {
+        for i in (1..5) {
+            palette[i + 1] = ((5 - i) as f32 * e0 + i as f32 * e1) / 5.0;
+        }
+    }
+
+    let mut bits = 0u64;
+    let mut error = 0.0;
+
+    for (i, &v) in values.iter().enumerate() {
+        let (index, e) = palette.iter().enumerate().fold((0, f32::MAX), |best, (j, &p)| {
+            let e = (p - v as f32) * (p - v as f32);
+            if e < best.1 { (j, e) } else { best }
+        });
+
+        bits |= (index as u64) << (3 * i);
+        error += e;
+    }
+
+    let mut bytes = [a0, a1, 0, 0, 0, 0, 0, 0];
+    for i in (0..6) {
+        bytes[2 + i] = (bits >> (8 * i)) as u8;
+    }
+
+    (bytes, error)
+}
+
+/// Encodes ```block``` as a BC7 block of mode 6
+pub fn bc7(block: &Block, quality: Quality) -> [u8; 16] {
+    let pixels = block.iter().map(color).collect::<Vec<_>>();
+    let opaque = block.iter().all(|p| p[3] == 255);
+
+    let (a, b) = fit_line(&pixels, 4, quality);
+    let mut best = bc7_encode(block, &a, &b, opaque);
+
+    for _ in (0..refinements(quality)) {
+        let w = best.2.iter().map(|&i| BC7_WEIGHTS[i as usize] as f32 / 64.0).collect::<Vec<_>>();
+
+        let candidate = match least_squares(&pixels, &w, 4) {
+            Some((a, b)) => bc7_encode(block, &a, &b, opaque),
+            None => break
+        };
+
+        if candidate.1 >= best.1 {
+            break
+        }
+        best = candidate;
+    }
+
+    best.0
+}
+
+// Encodes `block` with the endpoints `a` and `b`, returns the block, its
+// error and its indices
+fn bc7_encode(block: &Block, a: &Color, b: &Color, opaque: bool) -> ([u8; 16], f32, [u8; 16]) {
+    // Opaque blocks need the low bits to reach an alpha of 255
+    let pbits: &[(u8, u8)] = if opaque { &[(1, 1)] } else { &[(0, 0), (0, 1), (1, 0), (1, 1)] };
+
+    let mut best = ([0u8; 4], [0u8; 4], (0, 0), [0u8; 16], f32::MAX);
+
+    for &(p0, p1) in pbits {
+        let (q0, q1) = (quantize7(a, p0), quantize7(b, p1));
+
+        let mut palette = [[0f32; 4]; 16];
+        for (entry, &w) in palette.iter_mut().zip(BC7_WEIGHTS.iter()) {
+            for c in (0..4) {
+                let (e0, e1) = ((q0[c] << 1 | p0) as u32, (q1[c] << 1 | p1) as u32);
+                entry[c] = (((64 - w) * e0 + w * e1 + 32) >> 6) as f32;
+            }
+        }
+
+        let mut indices = [0u8; 16];
+        let mut error = 0.0;
+        for (i, p) in block.iter().enumerate() {
+            let (index, e) = nearest(&palette, &color(p), 4);
+            indices[i] = index as u8;
+            error += e;
+        }
+
+        if error < best.4 {
+            best = (q0, q1, (p0, p1), indices, error);
+        }
+    }
+
+    let (mut q0, mut q1, (mut p0, mut p1), mut indices, error) = best;
+
+    // The most significant bit of the index of the first pixel is implied 0
+    if indices[0] >= 8 {
+        ::std::mem::swap(&mut q0, &mut q1);
+        ::std::mem::swap(&mut p0, &mut p1);
+        for i in indices.iter_mut() {
+            *i = 15 - *i;
+        }
+    }
+
+    let mut bytes = [0u8; 16];
+    let mut pos = 0;
+
+    put_bits(&mut bytes, &mut pos, 1 << 6, 7);
+    for c in (0..4) {
+        put_bits(&mut bytes, &mut pos, q0[c] as u32, 7);
+        put_bits(&mut bytes, &mut pos, q1[c] as u32, 7);
+    }
+    put_bits(&mut bytes, &mut pos, p0 as u32, 1);
+    put_bits(&mut bytes, &mut pos, p1 as u32, 1);
+
+    put_bits(&mut bytes, &mut pos, indices[0] as u32, 3);
+    for &index in indices[1..].iter() {
+        put_bits(&mut bytes, &mut pos, index as u32, 4);
+    }
+
+    (bytes, error, indices)
+}
+
+// Writes the `n` low bits of `value` at bit `pos` of `bytes`, least
+// significant bit first
+fn put_bits(bytes: &mut [u8], pos: &mut usize, value: u32, n: usize) {
+    for i in (0..n) {
+        if value >> i & 1 != 0 {
+            bytes[*pos / 8] |= 1 << (*pos % 8);
+        }
+        *pos += 1;
+    }
+}
+
+// The 7 bit samples that give `e` best with the low bit `p`
+fn quantize7(e: &Color, p: u8) -> [u8; 4] {
+    let mut q = [0u8; 4];
+    for c in (0..4) {
+        q[c] = ((e[c] - p as f32) / 2.0).round().max(0.0).min(127.0) as u8;
+    }
+    q
+}
+
+fn refinements(quality: Quality) -> usize {
+    match quality {
+        Quality::Fast => 0,
+        Quality::Normal => 1,
+        Quality::Best => REFINEMENTS,
+    }
+}
+
+// The endpoints of a line through `pixels` in their first
+// `channels` channels
+fn fit_line(pixels: &[Color], channels: usize, quality: Quality) -> (Color, Color) {
+    let n = pixels.len() as f32;
+    let mut mean = [0f32; 4];
+    let mut lo = [0f32; 4];
+    let mut hi = [0f32; 4];
+
+    for c in (0..channels) {
+        mean[c] = pixels.iter().fold(0.0, |s, p| s + p[c]) / n;
+        lo[c] = pixels.iter().fold(f32::MAX, |m, p| m.min(p[c]));
+        hi[c] = pixels.iter().fold(f32::MIN, |m, p| m.max(p[c]));
+    }
+
+    let mut covariance = [[0f32; 4]; 4];
+    for p in pixels {
+        for i in (0..channels) {
+            for j in (0..channels) {
+                covariance[i][j] += (p[i] - mean[i]) * (p[j] - mean[j]);
+            }
+        }
+    }
+
+    if quality == Quality::Fast {
+        // The diagonal of the bounding box that follows the correlation of
+        // the channel with the largest range
+        let k = (0..channels).fold(0, |k, c| if hi[c] - lo[c] > hi[k] - lo[k] { c } else { k });
+        for c in (0..channels) {
+            if covariance[c][k] < 0.0 {
+                ::std::mem::swap(&mut lo[c], &mut hi[c]);
+            }
+        }
+        return (lo, hi)
+    }
+
+    // The principal axis by power iteration
+    let mut axis = [0f32; 4];
+    for c in (0..channels) {
+        axis[c] = hi[c] - lo[c];
+    }
+
+    for _ in (0..8) {
+        let mut next = [0f32; 4];
+        for i in (0..channels) {
+            for j in (0..channels) {
+                next[i] += covariance[i][j] * axis[j];
+            }
+        }
+
+        let norm = next.iter().fold(0.0, |s, v| s + v * v).sqrt();
+        if norm < 1e-6 {
+            break
+        }
+        for c in (0..channels) {
+            axis[c] = next[c] / norm;
+        }
+    }
+
+    let norm = axis.iter().fold(0.0, |s, v| s + v * v).sqrt();
+    if norm < 1e-6 {
+        return (mean, mean)
+    }
+    for c in (0..channels) {
+        axis[c] /= norm;
+    }
+
+    let (tmin, tmax) = pixels.iter().fold((f32::MAX, f32::MIN), |(tmin, tmax), p| {
+        let t = (0..channels).fold(0.0, |t, c| t + (p[c] - mean[c]) * axis[c]);
+        (tmin.min(t), tmax.max(t))
+    });
+
+    let mut a = [0f32; 4];
+    let mut b = [0f32; 4];
+    for c in (0..channels) {
+        a[c] = (mean[c] + tmin * axis[c]).max(0.0).min(255.0);
+        b[c] = (mean[c] + tmax * axis[c]).max(0.0).min(255.0);
+    }
+
+    (a, b)
+}
+
+// The endpoints that minimize the squared error of `pixels` when they are
+// interpolated with `weights`, or `None` if all weights are equal
+fn least_squares(pixels: &[Color], weights: &[f32], channels: usize) -> Option<(Color, Color)> {
+    let (mut aa, mut ab, mut bb) = (0.0, 0.0, 0.0);
+    let mut pa = [0f32; 4];
+    let mut pb = [0f32; 4];
+
+    for (p, &w) in pixels.iter().zip(weights.iter()) {
+        let v = 1.0 - w;
+        aa += v * v;
+        ab += v * w;
+        bb += w * w;
+        for c in (0..channels) {
+            pa[c] += v * p[c];
+            pb[c] += w * p[c];
+        }
+    }
+
+    let det = aa * bb - ab * ab;
+    if det.abs() < 1e-6 {
+        return None
+    }
+
+    let mut a = [0f32; 4];
+    let mut b = [0f32; 4];
+    for c in (0..channels) {
+        a[c] = ((bb * pa[c] - ab * pb[c]) / det).max(0.0).min(255.0);
+        b[c] = ((aa * pb[c] - ab * pa[c]) / det).max(0.0).min(255.0);
+    }
+
+    Some((a, b))
+}
+
+// The index of the entry of `palette` closest to `p` and the squared
+// distance
+fn nearest(palette: &[Color], p: &Color, channels: usize) -> (usize, f32) {
+    palette.iter().enumerate().fold((0, f32::MAX), |best, (i, q)| {
+        let e = (0..channels).fold(0.0, |e, c| e + (p[c] - q[c]) * (p[c] - q[c]));
+        if e < best.1 { (i, e) } else { best }
+    })
+}
+
+fn color(p: &[u8; 4]) -> Color {
+    [p[0] as f32, p[1] as f32, p[2] as f32, p[3] as f32]
+}
+
+fn mix(a: &Color, b: &Color, t: f32) -> Color {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t, a[3] + (b[3] - a[3]) * t]
+}
+
+fn rgb565(c: &Color) -> u16 {
+    let r = (c[0] * 31.0 / 255.0).round() as u16;
+    let g = (c[1] * 63.0 / 255.0).round() as u16;
+    let b = (c[2] * 31.0 / 255.0).round() as u16;
+    r << 11 | g << 5 | b
+}
+
+fn unpack565(c: u16) -> Color {
+    let (r, g, b) = (c >> 11 & 31, c >> 5 & 63, c & 31);
+    [(r << 3 | r >> 2) as f32, (g << 2 | g >> 4) as f32, (b << 3 | b >> 2) as f32, 255.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Block, Quality};
+    use super::{bc1, bc4, bc7, unpack565, BC7_WEIGHTS};
+
+    // A block with a gradient in every channel and some noise in blue
+    fn gradient(alpha: bool) -> Block {
+        let mut block = [[0u8; 4]; 16];
+        for (i, p) in block.iter_mut().enumerate() {
+            let t = i as u8;
+            *p = [40 + 8 * t, 200 - 5 * t, 10 + 6 * t + t % 3 * 4, if alpha { 15 * t } else { 255 }];
+        }
+        block
+    }
+
+    fn decode_bc1(b: &[u8], four_colors: bool) -> Block {
+        let c0 = b[0] as u16 | (b[1] as u16) << 8;
+        let c1 = b[2] as u16 | (b[3] as u16) << 8;
+        let (p0, p1) = (unpack565(c0), unpack565(c1));
+
+        let mut palette = [[0u8; 4]; 4];
+        for c in (0..3) {
+            let (e0, e1) = (p0[c] as u32, p1[c] as u32);
+            palette[0][c] = e0 as u8;
+            palette[1][c] = e1 as u8;
+            if c0 > c1 || four_colors {
+                palette[2][c] = ((2 * e0 + e1) / 3) as u8;
+                palette[3][c] = ((e0 + 2 * e1) / 3) as u8;
+            } else {
+                palette[2][c] = ((e0 + e1) / 2) as u8;
+            }
+        }
+        for p in palette.iter_mut().take(3) {
+            p[3] = 255;
+        }
+        if c0 > c1 || four_colors {
+            palette[3][3] = 255;
+        }
+
+        let mut block = [[0u8; 4]; 16];
+        for (i, p) in block.iter_mut().enumerate() {
+            *p = palette[(b[4 + i / 4] >> (2 * (i % 4)) & 3) as usize];
+        }
+        block
+    }
+
+    fn decode_bc4(b: &[u8]) -> [u8; 16] {
+        let (a0, a1) = (b[0] as u32, b[1] as u32);
+        let mut palette = [a0, a1, 0, 0, 0, 0, 0, 255];
+        if a0 > a1 {
+            for i in (1..7) {
+                palette[i as usize + 1] = ((7 - i) * a0 + i * a1 + 3) / 7;
+            }
+        } else {
+            for i in (1..5) {
+                palette[i as usize + 1] = ((5 - i) * a0 + i * a1 + 2) / 5;
+            }
+        }
+
+        let bits = (0..6).fold(0u64, |bits, i| bits | (b[2 + i] as u64) << (8 * i));
+        let mut values = [0u8; 16];
+        for (i, v) in values.iter_mut().enumerate() {
+            *v = palette[(bits >> (3 * i) & 7) as usize] as u8;
+        }
+        values
+    }
+
+    fn decode_bc7(b: &[u8]) -> Block {
+        let bit = |i: usize| (b[i / 8] >> (i % 8) & 1) as u32;
+        let bits = |pos: usize, n: usize| (0..n).fold(0, |v, i| v | bit(pos + i) << i);
+
+        assert_eq!(bits(0, 7), 1 << 6);
+        let (p0, p1) = (bits(63, 1), bits(64, 1));
+
+        let mut block = [[0u8; 4]; 16];
+        for (i, p) in block.iter_mut().enumerate() {
+            let index = if i == 0 { bits(65, 3) } else { bits(68 + 4 * (i - 1), 4) };
+            let w = BC7_WEIGHTS[index as usize];
+            for c in (0..4) {
+                let e0 = bits(7 + 14 * c, 7) << 1 | p0;
+                let e1 = bits(14 + 14 * c, 7) << 1 | p1;
+                p[c] = (((64 - w) * e0 + w * e1 + 32) >> 6) as u8;
+            }
+        }
+        block
+    }
+
+    fn max_error(a: &Block, b: &Block, channels: usize) -> i32 {
+        a.iter().zip(b.iter()).fold(0, |m, (p, q)| {
+            (0..channels).fold(m, |m, c| ::std::cmp::max(m, (p[c] as i32 - q[c] as i32).abs()))
+        })
+    }
+
+    #[test]
+    fn test_bc1() {
+        let block = gradient(false);
+        let mut previous = i32::max_value();
+
+        for &quality in [Quality::Fast, Quality::Normal, Quality::Best].iter() {
+            let error = max_error(&block, &decode_bc1(&bc1(&block, quality, true), false), 3);
+            assert!(error <= 24 && error <= previous, "{:?}: {}", quality, error);
+            previous = error;
+        }
+
+        // Transparent pixels select the three color mode
+        let mut block = gradient(false);
+        block[5][3] = 0;
+        let decoded = decode_bc1(&bc1(&block, Quality::Normal, true), false);
+        assert_eq!(decoded[5][3], 0);
+        assert!(decoded.iter().enumerate().all(|(i, p)| i == 5 || p[3] == 255));
+
+        // But not without punchthrough, as for BC3
+        let decoded = decode_bc1(&bc1(&block, Quality::Normal, false), true);
+        assert!(decoded.iter().all(|p| p[3] == 255));
+
+        assert_eq!(decode_bc1(&bc1(&[[9, 9, 9, 0]; 16], Quality::Best, true), false), [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn test_bc4() {
+        let block = gradient(true);
+        let decoded = decode_bc4(&bc4(&block, 3, Quality::Normal));
+        for (p, &v) in block.iter().zip(decoded.iter()) {
+            assert!((p[3] as i32 - v as i32).abs() <= 17);
+        }
+
+        // The extremes are exact in the six value mode
+        let mut block = [[0, 0, 0, 100]; 16];
+        block[0][3] = 0;
+        block[1][3] = 255;
+        block[2][3] = 110;
+        let decoded = decode_bc4(&bc4(&block, 3, Quality::Normal));
+        assert_eq!(&decoded[..4], &[0, 255, 110, 100]);
+    }
+
+    #[test]
+    fn test_bc7() {
+        for &alpha in [false, true].iter() {
+            let block = gradient(alpha);
+            for &quality in [Quality::Fast, Quality::Normal, Quality::Best].iter() {
+                let decoded = decode_bc7(&bc7(&block, quality));
+                assert!(max_error(&block, &decoded, 4) <= 8);
+                assert!(alpha || decoded.iter().all(|p| p[3] == 255));
+            }
+        }
+
+        let decoded = decode_bc7(&bc7(&[[12, 34, 56, 78]; 16], Quality::Normal));
+        assert!(max_error(&[[12, 34, 56, 78]; 16], &decoded, 4) <= 1);
+    }
+}
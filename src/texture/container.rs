@@ -0,0 +1,276 @@
+//! DDS and KTX2 files
+//!
+//! Both hold a 2D texture with its mipmaps. DDS files of BC1 and BC3 use
+//! the legacy ```DXT1``` and ```DXT5``` codes, which all readers know,
+//! unless they are sRGB encoded. Those and BC7 need the DX10 extension of
+//! the header. DDS has no codes for ETC2.
+
+use std::io::Write;
+use byteorder::{WriteBytesExt, LittleEndian};
+
+use image::{ImageError, ImageResult};
+use super::Format;
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+
+// The channel of the alpha samples in data format descriptors, the channel
+// of BC1 blocks with alpha, which code color and alpha in one sample, and
+// the qualifier of linear samples in formats with another transfer function
+const DFD_CHANNEL_ALPHA: u8 = 15;
+const DFD_CHANNEL_BC1A_ALPHA: u8 = 1;
+const DFD_LINEAR: u8 = 0x10;
+
+/// A compressed texture, as returned by ```compress```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressedTexture {
+    /// The format of the blocks
+    pub format: Format,
+    /// The width of the image in pixels
+    pub width: u32,
+    /// The height of the image in pixels
+    pub height: u32,
+    /// The blocks in row-major order, ready to be uploaded to a GPU
+    pub data: Vec<u8>,
+    /// The blocks of the mipmaps after the first level, each half as wide
+    /// and high as the one before it
+    pub mipmaps: Vec<Vec<u8>>,
+    /// Whether the RGB samples are sRGB encoded, which selects the sRGB
+    /// variant of the format. Defaults to false.
+    pub srgb: bool,
+}
+
+impl CompressedTexture {
+    /// Writes the texture as a DDS file.
+    ///
+    /// Returns an ```UnsupportedError``` for the ETC2 formats.
+    pub fn write_dds<W: Write>(&self, w: &mut W) -> ImageResult<()> {
+        let (fourcc, dxgi_format) = match (self.format, self.srgb) {
+            (Format::BC1, false) => (b"DXT1", None),
+            (Format::BC3, false) => (b"DXT5", None),
+            // DXGI_FORMAT_BC1_UNORM_SRGB, BC3_UNORM_SRGB, BC7_UNORM and BC7_UNORM_SRGB
+            (Format::BC1, true) => (b"DX10", Some(72)),
+            (Format::BC3, true) => (b"DX10", Some(78)),
+            (Format::BC7, false) => (b"DX10", Some(98)),
+            (Format::BC7, true) => (b"DX10", Some(99)),
+            (Format::ETC2RGB, _) | (Format::ETC2RGBA, _) => return Err(ImageError::UnsupportedError(
+                format!("DDS files can not hold {:?} textures", self.format)
+            ))
+        };
+
+        try!(w.write_all(b"DDS "));
+
+        let levels = 1 + self.mipmaps.len() as u32;
+        let mipmapped = levels > 1;
+
+        // The header, with caps, height, width, pixel format, linear size
+        // and the mipmap count
+        try!(w.write_u32::<LittleEndian>(124));
+        try!(w.write_u32::<LittleEndian>(0x1 | 0x2 | 0x4 | 0x1000 | 0x80000 | if mipmapped { 0x20000 } else { 0 }));
+        try!(w.write_u32::<LittleEndian>(self.height));
+        try!(w.write_u32::<LittleEndian>(self.width));
+        try!(w.write_u32::<LittleEndian>(self.data.len() as u32));
+        try!(w.write_u32::<LittleEndian>(0));
+        try!(w.write_u32::<LittleEndian>(if mipmapped { levels } else { 0 }));
+        // Reserved words
+        for _ in (0..11) {
+            try!(w.write_u32::<LittleEndian>(0));
+        }
+
+        // The pixel format, given by its four character code
+        try!(w.write_u32::<LittleEndian>(32));
+        try!(w.write_u32::<LittleEndian>(0x4));
+        try!(w.write_all(fourcc));
+        for _ in (0..5) {
+            try!(w.write_u32::<LittleEndian>(0));
+        }
+
+        // A texture, with mipmaps if complex, and unused caps
+        try!(w.write_u32::<LittleEndian>(0x1000 | if mipmapped { 0x8 | 0x400000 } else { 0 }));
+        for _ in (0..4) {
+            try!(w.write_u32::<LittleEndian>(0));
+        }
+
+        if let Some(dxgi_format) = dxgi_format {
+            try!(w.write_u32::<LittleEndian>(dxgi_format));
+            // A 2D texture, not a cube map, an array of one texture and
+            // straight alpha
+            try!(w.write_u32::<LittleEndian>(3));
+            try!(w.write_u32::<LittleEndian>(0));
+            try!(w.write_u32::<LittleEndian>(1));
+            try!(w.write_u32::<LittleEndian>(1));
+        }
+
+        try!(w.write_all(&self.data));
+        for mipmap in self.mipmaps.iter() {
+            try!(w.write_all(mipmap));
+        }
+        Ok(())
+    }
+
+    /// Writes the texture as a KTX2 file
+    pub fn write_ktx2<W: Write>(&self, w: &mut W) -> ImageResult<()> {
+        // The Vulkan format, the color model of the descriptor and its
+        // samples as channel and bit offset. The sRGB formats follow the
+        // UNORM ones.
+        let (vk_format, model, samples): (u32, u8, &[(u8, u16)]) = match self.format {
+            Format::BC1 => (133, 128, &[(DFD_CHANNEL_BC1A_ALPHA, 0)]),
+            Format::BC3 => (137, 130, &[(DFD_CHANNEL_ALPHA, 0), (0, 64)]),
+            Format::BC7 => (145, 134, &[(0, 0)]),
+            Format::ETC2RGB => (147, 161, &[(2, 0)]),
+            Format::ETC2RGBA => (151, 161, &[(DFD_CHANNEL_ALPHA, 0), (2, 64)]),
+        };
+        let vk_format = if self.srgb { vk_format + 1 } else { vk_format };
+
+        let mut levels = vec![&self.data];
+        levels.extend(self.mipmaps.iter());
+
+        let block_len = self.format.block_len();
+        let index_len = 80 + 24 * levels.len();
+        let dfd_len = 4 + 24 + 16 * samples.len();
+        // The data is aligned to the block size
+        let data_offset = (index_len + dfd_len + block_len - 1) / block_len * block_len;
+
+        try!(w.write_all(&KTX2_IDENTIFIER));
+        try!(w.write_u32::<LittleEndian>(vk_format));
+        // The type size, 1 for block compressed formats
+        try!(w.write_u32::<LittleEndian>(1));
+        try!(w.write_u32::<LittleEndian>(self.width));
+        try!(w.write_u32::<LittleEndian>(self.height));
+        // Depth, layers, faces, levels and supercompression
+        for &v in [0, 0, 1, levels.len() as u32, 0].iter() {
+            try!(w.write_u32::<LittleEndian>(v));
+        }
+
+        // The index of the descriptor, without key/value or supercompression data
+        try!(w.write_u32::<LittleEndian>(index_len as u32));
+        try!(w.write_u32::<LittleEndian>(dfd_len as u32));
+        try!(w.write_u32::<LittleEndian>(0));
+        try!(w.write_u32::<LittleEndian>(0));
+        try!(w.write_u64::<LittleEndian>(0));
+        try!(w.write_u64::<LittleEndian>(0));
+
+        // The level index, the smallest level comes first in the file
+        for (i, level) in levels.iter().enumerate() {
+            let offset = data_offset + levels[i + 1..].iter().fold(0, |n, l| n + l.len());
+            try!(w.write_u64::<LittleEndian>(offset as u64));
+            try!(w.write_u64::<LittleEndian>(level.len() as u64));
+            try!(w.write_u64::<LittleEndian>(level.len() as u64));
+        }
+
+        // The basic data format descriptor block, of version 2, with BT.709
+        // primaries and a linear or sRGB transfer function
+        try!(w.write_u32::<LittleEndian>(dfd_len as u32));
+        try!(w.write_u32::<LittleEndian>(0));
+        try!(w.write_u32::<LittleEndian>(2 | ((dfd_len - 4) as u32) << 16));
+        try!(w.write_all(&[model, 1, if self.srgb { 2 } else { 1 }, 0]));
+        try!(w.write_all(&[3, 3, 0, 0]));
+        try!(w.write_all(&[block_len as u8, 0, 0, 0, 0, 0, 0, 0]));
+
+        for &(channel, offset) in samples {
+            let len = block_len / samples.len() * 8;
+            // Alpha stays linear in sRGB formats
+            let linear = if self.srgb && channel == DFD_CHANNEL_ALPHA { DFD_LINEAR } else { 0 };
+            try!(w.write_u16::<LittleEndian>(offset));
+            try!(w.write_all(&[(len - 1) as u8, channel | linear]));
+            try!(w.write_u32::<LittleEndian>(0));
+            try!(w.write_u32::<LittleEndian>(0));
+            try!(w.write_u32::<LittleEndian>(0xFFFFFFFF));
+        }
+
+        try!(w.write_all(&vec![0; data_offset - index_len - dfd_len]));
+        for level in levels.iter().rev() {
+            try!(w.write_all(level));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::RgbaImage;
+    use super::super::{compress, compress_mips, generate_mips, Format, MipOptions, Quality};
+
+    fn u32_at(b: &[u8], i: usize) -> u32 {
+        b[i] as u32 | (b[i + 1] as u32) << 8 | (b[i + 2] as u32) << 16 | (b[i + 3] as u32) << 24
+    }
+
+    #[test]
+    fn test_dds() {
+        let texture = compress(&RgbaImage::new(8, 12), Format::BC1, Quality::Fast);
+        let mut dds = Vec::new();
+        texture.write_dds(&mut dds).unwrap();
+
+        assert_eq!(dds.len(), 128 + 6 * 8);
+        assert_eq!(&dds[..4], b"DDS ");
+        assert_eq!((u32_at(&dds, 12), u32_at(&dds, 16)), (12, 8));
+        assert_eq!(&dds[84..88], b"DXT1");
+
+        let texture = compress(&RgbaImage::new(4, 4), Format::BC7, Quality::Fast);
+        let mut dds = Vec::new();
+        texture.write_dds(&mut dds).unwrap();
+        assert_eq!(dds.len(), 128 + 20 + 16);
+        assert_eq!(u32_at(&dds, 128), 98);
+
+        // The sRGB variants of BC1 and BC3 need the DX10 header
+        let mut texture = compress(&RgbaImage::new(4, 4), Format::BC1, Quality::Fast);
+        texture.srgb = true;
+        let mut dds = Vec::new();
+        texture.write_dds(&mut dds).unwrap();
+        assert_eq!(&dds[84..88], b"DX10");
+        assert_eq!(u32_at(&dds, 128), 72);
+
+        let texture = compress(&RgbaImage::new(4, 4), Format::ETC2RGB, Quality::Fast);
+        assert!(texture.write_dds(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_ktx2() {
+        for &(format, vk_format, offset, channel) in [(Format::BC1, 133, 152, 1), (Format::BC3, 137, 176, 15),
+                                                      (Format::ETC2RGBA, 151, 176, 15)].iter() {
+            let texture = compress(&RgbaImage::new(5, 4), format, Quality::Fast);
+            let mut ktx2 = Vec::new();
+            texture.write_ktx2(&mut ktx2).unwrap();
+
+            assert_eq!(&ktx2[..12], b"\xABKTX 20\xBB\r\n\x1A\n");
+            assert_eq!(u32_at(&ktx2, 12), vk_format);
+            assert_eq!((u32_at(&ktx2, 20), u32_at(&ktx2, 24)), (5, 4));
+
+            // The descriptor ends before the data
+            let dfd_len = u32_at(&ktx2, 52) as usize;
+            assert_eq!(u32_at(&ktx2, 104) as usize, dfd_len);
+            assert!(104 + dfd_len <= offset);
+
+            // The channel of the first sample
+            assert_eq!(ktx2[104 + 28 + 3], channel);
+
+            assert_eq!(u32_at(&ktx2, 80) as usize, offset);
+            assert_eq!(u32_at(&ktx2, 88) as usize, texture.data.len());
+            assert_eq!(&ktx2[offset..], &texture.data[..]);
+        }
+    }
+
+    #[test]
+    fn test_mipmaps() {
+        let levels = generate_mips(&RgbaImage::new(8, 4), &MipOptions::default());
+        let mut texture = compress_mips(&levels, Format::BC1, Quality::Fast);
+        texture.srgb = true;
+        assert_eq!(texture.mipmaps.iter().map(|l| l.len()).collect::<Vec<_>>(), vec![8, 8, 8]);
+
+        let mut dds = Vec::new();
+        texture.write_dds(&mut dds).unwrap();
+        assert_eq!(dds.len(), 148 + 16 + 3 * 8);
+        assert_eq!(u32_at(&dds, 28), 4);
+
+        let mut ktx2 = Vec::new();
+        texture.write_ktx2(&mut ktx2).unwrap();
+        assert_eq!(u32_at(&ktx2, 12), 134);
+        assert_eq!(u32_at(&ktx2, 40), 4);
+
+        // The levels are stored from the smallest to the largest
+        let offsets = (0..4).map(|i| u32_at(&ktx2, 80 + 24 * i) as usize).collect::<Vec<_>>();
+        assert_eq!(offsets[3] + 8, offsets[2]);
+        assert_eq!(offsets[1] + 8, offsets[0]);
+        assert_eq!(&ktx2[offsets[0]..], &texture.data[..]);
+        assert_eq!(ktx2.len(), offsets[3] + 40);
+    }
+}
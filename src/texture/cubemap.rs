@@ -0,0 +1,243 @@
+//! Cube maps
+//!
+//! The faces follow the order and orientation of OpenGL, DDS and KTX2:
+//! +X, -X, +Y, -Y, +Z, -Z, with +Y up. Equirectangular images, as taken by
+//! 360° cameras, span the longitudes from left to right and the latitudes
+//! from the top to the bottom, with -Z in their center.
+//!
+//! Bilinear filtering near the edge of a face continues on the neighbouring
+//! face, and near the left and right edges of an equirectangular image on
+//! the opposite edge, thus neither conversion leaves visible seams.
+
+use std::cmp;
+use std::f32;
+
+use buffer::{ImageBuffer, RgbaImage};
+use color::Rgba;
+use image::{ImageError, ImageResult};
+
+/// The six square faces of a cube map
+#[derive(Clone)]
+pub struct Cubemap {
+    /// The faces in the order +X, -X, +Y, -Y, +Z, -Z
+    pub faces: [RgbaImage; 6],
+}
+
+impl Cubemap {
+    /// The width and height of the faces
+    pub fn size(&self) -> u32 {
+        self.faces[0].width()
+    }
+
+    /// Samples the cube map in the direction ```x```, ```y```, ```z```,
+    /// which need not be normalized.
+    pub fn sample(&self, x: f32, y: f32, z: f32) -> Rgba<u8> {
+        let size = self.size() as f32;
+        let (face, s, t) = project(x, y, z);
+        let (fx, fy) = (s * size - 0.5, t * size - 0.5);
+        let (x0, y0) = (fx.floor(), fy.floor());
+        let (dx, dy) = (fx - x0, fy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        bilinear(
+            [self.texel(face, x0, y0), self.texel(face, x0 + 1, y0),
+             self.texel(face, x0, y0 + 1), self.texel(face, x0 + 1, y0 + 1)],
+            dx, dy
+        )
+    }
+
+    // The texel at `x`, `y` of `face`. Texels past the edges of the face are
+    // taken from the face their direction points at.
+    fn texel(&self, face: usize, x: i64, y: i64) -> Rgba<u8> {
+        let size = self.size() as i64;
+        if x >= 0 && y >= 0 && x < size && y < size {
+            return *self.faces[face].get_pixel(x as u32, y as u32)
+        }
+
+        let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+        let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+        let (dx, dy, dz) = direction(face, u, v);
+        let (face, s, t) = project(dx, dy, dz);
+        let clamp = |c: f32| cmp::max(0, cmp::min(size - 1, (c * size as f32) as i64)) as u32;
+
+        *self.faces[face].get_pixel(clamp(s), clamp(t))
+    }
+}
+
+/// Assembles a cube map from ```faces``` in the order +X, -X, +Y, -Y, +Z, -Z.
+///
+/// Returns a ```DimensionError``` unless the faces are square and of the
+/// same size.
+pub fn cubemap_from_faces(faces: [RgbaImage; 6]) -> ImageResult<Cubemap> {
+    let size = faces[0].width();
+    if size == 0 || faces.iter().any(|f| f.dimensions() != (size, size)) {
+        return Err(ImageError::DimensionError)
+    }
+
+    Ok(Cubemap { faces: faces })
+}
+
+/// Projects the equirectangular ```image``` to a cube map with faces of
+/// ```size``` by ```size``` pixels.
+///
+/// # Panics
+///
+/// Panics if ```image``` or ```size``` is empty.
+pub fn equirect_to_cubemap(image: &RgbaImage, size: u32) -> Cubemap {
+    assert!(size > 0 && image.width() > 0 && image.height() > 0);
+
+    let face = |face: usize| ImageBuffer::from_fn(size, size, |x, y| {
+        let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+        let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+        let (dx, dy, dz) = direction(face, u, v);
+        sample_equirect(image, dx, dy, dz)
+    });
+
+    Cubemap { faces: [face(0), face(1), face(2), face(3), face(4), face(5)] }
+}
+
+/// Projects ```cubemap``` to an equirectangular image of ```width``` by
+/// ```height``` pixels, usually twice as wide as high.
+pub fn cubemap_to_equirect(cubemap: &Cubemap, width: u32, height: u32) -> RgbaImage {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let longitude = ((x as f32 + 0.5) / width as f32 - 0.5) * 2.0 * f32::consts::PI;
+        let latitude = (0.5 - (y as f32 + 0.5) / height as f32) * f32::consts::PI;
+        cubemap.sample(latitude.cos() * longitude.sin(), latitude.sin(), -latitude.cos() * longitude.cos())
+    })
+}
+
+// Samples the equirectangular `image` in the direction `x`, `y`, `z`,
+// wrapping around horizontally
+fn sample_equirect(image: &RgbaImage, x: f32, y: f32, z: f32) -> Rgba<u8> {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let longitude = x.atan2(-z);
+    let latitude = (y / (x * x + y * y + z * z).sqrt()).max(-1.0).min(1.0).asin();
+
+    let fx = (longitude / (2.0 * f32::consts::PI) + 0.5) * width as f32 - 0.5;
+    let fy = (0.5 - latitude / f32::consts::PI) * height as f32 - 0.5;
+    let (x0, y0) = (fx.floor(), fy.floor());
+    let (dx, dy) = (fx - x0, fy - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let texel = |x: i64, y: i64| {
+        let x = ((x % width) + width) % width;
+        let y = cmp::max(0, cmp::min(height - 1, y));
+        *image.get_pixel(x as u32, y as u32)
+    };
+
+    bilinear([texel(x0, y0), texel(x0 + 1, y0), texel(x0, y0 + 1), texel(x0 + 1, y0 + 1)], dx, dy)
+}
+
+// Interpolates the top left, top right, bottom left and bottom right pixels
+fn bilinear(pixels: [Rgba<u8>; 4], dx: f32, dy: f32) -> Rgba<u8> {
+    let mut out = [0u8; 4];
+    for c in (0..4) {
+        let top = pixels[0][c] as f32 * (1.0 - dx) + pixels[1][c] as f32 * dx;
+        let bottom = pixels[2][c] as f32 * (1.0 - dx) + pixels[3][c] as f32 * dx;
+        out[c] = (top * (1.0 - dy) + bottom * dy + 0.5) as u8;
+    }
+    Rgba(out)
+}
+
+// The direction of the point `u`, `v` of `face`, both from -1 to 1, from
+// left to right and top to bottom
+fn direction(face: usize, u: f32, v: f32) -> (f32, f32, f32) {
+    match face {
+        0 => (1.0, -v, -u),
+        1 => (-1.0, -v, u),
+        2 => (u, 1.0, v),
+        3 => (u, -1.0, -v),
+        4 => (u, -v, 1.0),
+        _ => (-u, -v, -1.0),
+    }
+}
+
+// The face in the direction `x`, `y`, `z` and the point on it, both
+// coordinates from 0 to 1
+fn project(x: f32, y: f32, z: f32) -> (usize, f32, f32) {
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if x > 0.0 { (0, -z, -y, ax) } else { (1, z, -y, ax) }
+    } else if ay >= az {
+        if y > 0.0 { (2, x, z, ay) } else { (3, x, -z, ay) }
+    } else {
+        if z > 0.0 { (4, x, -y, az) } else { (5, -x, -y, az) }
+    };
+
+    (face, (sc / ma + 1.0) / 2.0, (tc / ma + 1.0) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{ImageBuffer, RgbaImage};
+    use color::Rgba;
+    use super::{cubemap_from_faces, cubemap_to_equirect, direction, equirect_to_cubemap, project};
+
+    fn solid(size: u32, v: u8) -> RgbaImage {
+        ImageBuffer::from_pixel(size, size, Rgba([v, v, v, 255]))
+    }
+
+    #[test]
+    fn test_project() {
+        for face in (0..6) {
+            for &(u, v) in [(0.0, 0.0), (-0.5, 0.25), (0.9, -0.75)].iter() {
+                let (x, y, z) = direction(face, u, v);
+                let (f, s, t) = project(x, y, z);
+                assert_eq!(f, face);
+                assert!((s - (u + 1.0) / 2.0).abs() < 1e-6 && (t - (v + 1.0) / 2.0).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_faces() {
+        let faces = [solid(4, 0), solid(4, 1), solid(4, 2), solid(4, 3), solid(4, 4), solid(4, 5)];
+        assert_eq!(cubemap_from_faces(faces).unwrap().size(), 4);
+
+        let faces = [solid(4, 0), solid(4, 1), solid(4, 2), solid(4, 3), solid(4, 4), solid(3, 5)];
+        assert!(cubemap_from_faces(faces).is_err());
+    }
+
+    #[test]
+    fn test_faces_and_seams() {
+        let faces = [solid(8, 0), solid(8, 40), solid(8, 80), solid(8, 120), solid(8, 160), solid(8, 200)];
+        let cubemap = cubemap_from_faces(faces).unwrap();
+
+        // The center of every face
+        assert_eq!(cubemap.sample(1.0, 0.0, 0.0)[0], 0);
+        assert_eq!(cubemap.sample(0.0, -1.0, 0.0)[0], 120);
+        assert_eq!(cubemap.sample(0.0, 0.0, -1.0)[0], 200);
+
+        // Halfway between +X and +Z, both faces contribute equally
+        assert_eq!(cubemap.sample(1.0, 0.0, 1.0)[0], 80);
+
+        // Equirectangular images look at -Z in their center and at +Y at the top
+        let equirect = cubemap_to_equirect(&cubemap, 64, 32);
+        assert_eq!(equirect[(32, 16)][0], 200);
+        assert_eq!(equirect[(0, 16)][0], 160);
+        assert_eq!(equirect[(48, 16)][0], 0);
+        assert_eq!(equirect[(20, 0)][0], 80);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // A smooth gradient over the longitudes, which wraps around
+        let image: RgbaImage = ImageBuffer::from_fn(128, 64, |x, y| {
+            let angle = x as f32 / 128.0 * 2.0 * ::std::f32::consts::PI;
+            Rgba([(128.0 + 100.0 * angle.cos()) as u8, (y * 4) as u8, 0, 255])
+        });
+
+        let cubemap = equirect_to_cubemap(&image, 32);
+        let equirect = cubemap_to_equirect(&cubemap, 128, 64);
+
+        // Away from the poles, where the cube faces are coarser
+        for y in (12..52) {
+            for x in (0..128) {
+                let (a, b) = (image[(x, y)], equirect[(x, y)]);
+                assert!((a[0] as i32 - b[0] as i32).abs() <= 6, "{} {}: {:?} {:?}", x, y, a, b);
+                assert!((a[1] as i32 - b[1] as i32).abs() <= 8, "{} {}: {:?} {:?}", x, y, a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! ETC2 blocks and EAC alpha
+//!
+//! The encoder uses the individual and differential modes, which ETC2 has
+//! inherited from ETC1: each half of a block has a base color and one of
+//! eight tables of luminance modifiers, selected per pixel by a 2 bit index.
+//! The halves are side by side, or above each other if the flip bit is set.
+//! Alpha is stored as in EAC, with a base value, a multiplier and one of
+//! sixteen tables of modifiers, selected per pixel by a 3 bit index.
+
+use std::cmp;
+
+use super::{Block, Quality};
+
+const MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8], [5, 17], [9, 29], [13, 42], [18, 60], [24, 80], [33, 106], [47, 183]
+];
+
+const ALPHA_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// Encodes the RGB of ```block``` as an ETC2 block
+pub fn etc2(block: &Block, quality: Quality) -> [u8; 8] {
+    let offsets: &[i32] = if quality == Quality::Best { &[-1, 0, 1] } else { &[0] };
+    let mut best = (0u64, u32::max_value());
+
+    for &flip in [false, true].iter() {
+        let halves = [half(block, flip, 0), half(block, flip, 1)];
+        let averages = [average(block, &halves[0]), average(block, &halves[1])];
+
+        for &d0 in offsets {
+            for &d1 in offsets {
+                // Individual mode with 4 bit base colors
+                let b0 = quantize(&averages[0], 15, d0);
+                let b1 = quantize(&averages[1], 15, d1);
+
+                let mut bits = (flip as u64) << 32;
+                for c in (0..3) {
+                    bits |= (b0[c] as u64) << (60 - 8 * c) | (b1[c] as u64) << (56 - 8 * c);
+                }
+                candidate(block, &halves, [expand(b0, 4), expand(b1, 4)], bits, &mut best);
+
+                // Differential mode with a 5 bit base color and a 3 bit
+                // difference of the second one
+                let b0 = quantize(&averages[0], 31, d0);
+                let b1 = quantize(&averages[1], 31, d1);
+                if (0..3).any(|c| b1[c] - b0[c] < -4 || b1[c] - b0[c] > 3) {
+                    continue
+                }
+
+                let mut bits = 1 << 33 | (flip as u64) << 32;
+                for c in (0..3) {
+                    bits |= (b0[c] as u64) << (59 - 8 * c) | (((b1[c] - b0[c]) & 7) as u64) << (56 - 8 * c);
+                }
+                candidate(block, &halves, [expand(b0, 5), expand(b1, 5)], bits, &mut best);
+            }
+        }
+    }
+
+    to_bytes(best.0)
+}
+
+// Completes the block `bits` with the tables and indices for the base
+// colors `bases` and keeps it in `best` if its error is lower
+fn candidate(block: &Block, halves: &[Vec<usize>; 2], bases: [[i32; 3]; 2], mut bits: u64,
+             best: &mut (u64, u32)) {
+    let mut error = 0;
+
+    for (h, pixels) in halves.iter().enumerate() {
+        let (table, indices, e) = fit_half(block, pixels, &bases[h]);
+        error += e;
+
+        bits |= (table as u64) << (37 - 3 * h);
+        for (&i, &index) in pixels.iter().zip(indices.iter()) {
+            let j = (i % 4) * 4 + i / 4;
+            bits |= (index as u64 >> 1) << (16 + j) | (index as u64 & 1) << j;
+        }
+    }
+
+    if error < best.1 {
+        *best = (bits, error);
+    }
+}
+
+// The table, indices and error that encode `pixels` of `block` best with
+// the base color `base`
+fn fit_half(block: &Block, pixels: &[usize], base: &[i32; 3]) -> (usize, Vec<u8>, u32) {
+    let mut best = (0, Vec::new(), u32::max_value());
+
+    for (t, table) in MODIFIERS.iter().enumerate() {
+        let modifiers = [table[0], table[1], -table[0], -table[1]];
+        let mut indices = Vec::with_capacity(pixels.len());
+        let mut error = 0;
+
+        for &i in pixels {
+            let (index, e) = (0..4).fold((0, u32::max_value()), |best, m| {
+                let e = (0..3).fold(0, |e, c| {
+                    let d = clamp(base[c] + modifiers[m]) - block[i][c] as i32;
+                    e + (d * d) as u32
+                });
+                if e < best.1 { (m as u8, e) } else { best }
+            });
+            indices.push(index);
+            error += e;
+        }
+
+        if error < best.2 {
+            best = (t, indices, error);
+        }
+    }
+
+    best
+}
+
+// The pixels of half `h` of a block
+fn half(block: &Block, flip: bool, h: usize) -> Vec<usize> {
+    (0..block.len()).filter(|&i| {
+        let k = if flip { i / 4 } else { i % 4 };
+        (k >= 2) == (h == 1)
+    }).collect()
+}
+
+fn average(block: &Block, pixels: &[usize]) -> [f32; 3] {
+    let mut sum = [0f32; 3];
+    for &i in pixels {
+        for c in (0..3) {
+            sum[c] += block[i][c] as f32;
+        }
+    }
+    [sum[0] / pixels.len() as f32, sum[1] / pixels.len() as f32, sum[2] / pixels.len() as f32]
+}
+
+fn quantize(color: &[f32; 3], max: i32, offset: i32) -> [i32; 3] {
+    let mut q = [0i32; 3];
+    for c in (0..3) {
+        q[c] = cmp::max(0, cmp::min(max, (color[c] * max as f32 / 255.0).round() as i32 + offset));
+    }
+    q
+}
+
+// Expands the `bits` bit samples of `q` to 8 bits
+fn expand(q: [i32; 3], bits: usize) -> [i32; 3] {
+    let mut e = [0i32; 3];
+    for c in (0..3) {
+        e[c] = q[c] << (8 - bits) | q[c] >> (2 * bits - 8);
+    }
+    e
+}
+
+/// Encodes the samples of ```channel``` of ```block``` as an EAC block, as
+/// the alpha of ETC2 is stored.
+pub fn eac(block: &Block, channel: usize, quality: Quality) -> [u8; 8] {
+    let values = block.iter().map(|p| p[channel] as i32).collect::<Vec<_>>();
+    let min = values.iter().cloned().min().unwrap();
+    let max = values.iter().cloned().max().unwrap();
+
+    let mut best = (0u64, u32::max_value());
+
+    for (t, table) in ALPHA_MODIFIERS.iter().enumerate() {
+        // The multiplier that spans the range of the values with the table
+        let m = cmp::max(1, ((max - min) as f32 / (table[7] - table[3]) as f32).round() as i32);
+        let multipliers = match quality {
+            Quality::Fast => (m, m),
+            Quality::Normal => (m - 1, m + 1),
+            Quality::Best => (1, 15),
+        };
+
+        for m in (cmp::max(1, multipliers.0)..cmp::min(15, multipliers.1) + 1) {
+            let center = (min - table[3] * m + max - table[7] * m) / 2;
+
+            for base in (cmp::max(0, center - 1)..cmp::min(255, center + 1) + 1) {
+                let mut bits = (base as u64) << 56 | (m as u64) << 52 | (t as u64) << 48;
+                let mut error = 0;
+
+                for (i, &v) in values.iter().enumerate() {
+                    let (index, e) = table.iter().enumerate().fold((0, u32::max_value()), |best, (k, &d)| {
+                        let e = (clamp(base + d * m) - v) * (clamp(base + d * m) - v);
+                        if (e as u32) < best.1 { (k, e as u32) } else { best }
+                    });
+
+                    let j = (i % 4) * 4 + i / 4;
+                    bits |= (index as u64) << (45 - 3 * j);
+                    error += e;
+                }
+
+                if error < best.1 {
+                    best = (bits, error);
+                }
+            }
+        }
+    }
+
+    to_bytes(best.0)
+}
+
+fn clamp(v: i32) -> i32 {
+    cmp::max(0, cmp::min(255, v))
+}
+
+fn to_bytes(bits: u64) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = (bits >> (56 - 8 * i)) as u8;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Block, Quality};
+    use super::{clamp, eac, etc2, expand, ALPHA_MODIFIERS, MODIFIERS};
+
+    fn decode_etc2(b: &[u8]) -> Block {
+        let bits = b.iter().fold(0u64, |bits, &b| bits << 8 | b as u64);
+        let flip = bits >> 32 & 1 == 1;
+
+        let mut bases = [[0i32; 3]; 2];
+        for c in (0..3) {
+            if bits >> 33 & 1 == 0 {
+                bases[0][c] = (bits >> (60 - 8 * c) & 15) as i32;
+                bases[1][c] = (bits >> (56 - 8 * c) & 15) as i32;
+            } else {
+                let base = (bits >> (59 - 8 * c) & 31) as i32;
+                let diff = ((bits >> (56 - 8 * c) & 7) as i32 ^ 4) - 4;
+                assert!(base + diff >= 0 && base + diff <= 31);
+                bases[0][c] = base;
+                bases[1][c] = base + diff;
+            }
+        }
+        let bits_per_sample = if bits >> 33 & 1 == 0 { 4 } else { 5 };
+        let bases = [expand(bases[0], bits_per_sample), expand(bases[1], bits_per_sample)];
+
+        let mut block = [[0u8; 4]; 16];
+        for (i, p) in block.iter_mut().enumerate() {
+            let (x, y) = (i % 4, i / 4);
+            let h = if flip { y / 2 } else { x / 2 };
+            let table = MODIFIERS[(bits >> (37 - 3 * h) & 7) as usize];
+
+            let j = x * 4 + y;
+            let modifier = match (bits >> (16 + j) & 1, bits >> j & 1) {
+                (0, 0) => table[0],
+                (0, _) => table[1],
+                (_, 0) => -table[0],
+                _ => -table[1],
+            };
+
+            for c in (0..3) {
+                p[c] = clamp(bases[h][c] + modifier) as u8;
+            }
+            p[3] = 255;
+        }
+        block
+    }
+
+    fn decode_eac(b: &[u8]) -> [u8; 16] {
+        let bits = b.iter().fold(0u64, |bits, &b| bits << 8 | b as u64);
+        let base = (bits >> 56) as i32;
+        let m = (bits >> 52 & 15) as i32;
+        let table = ALPHA_MODIFIERS[(bits >> 48 & 15) as usize];
+
+        let mut values = [0u8; 16];
+        for (i, v) in values.iter_mut().enumerate() {
+            let j = (i % 4) * 4 + i / 4;
+            *v = clamp(base + table[(bits >> (45 - 3 * j) & 7) as usize] * m) as u8;
+        }
+        values
+    }
+
+    fn max_error(a: &Block, b: &Block) -> i32 {
+        a.iter().zip(b.iter()).fold(0, |m, (p, q)| {
+            (0..3).fold(m, |m, c| ::std::cmp::max(m, (p[c] as i32 - q[c] as i32).abs()))
+        })
+    }
+
+    #[test]
+    fn test_etc2() {
+        // Two colors, split horizontally
+        let mut block = [[200, 40, 40, 255]; 16];
+        for p in block[8..].iter_mut() {
+            *p = [30, 30, 220, 255];
+        }
+
+        let encoded = etc2(&block, Quality::Normal);
+        assert_eq!(encoded[3] & 1, 1);
+        assert!(max_error(&block, &decode_etc2(&encoded)) <= 8);
+
+        // A luminance gradient
+        let mut block = [[0u8; 4]; 16];
+        for (i, p) in block.iter_mut().enumerate() {
+            let v = 60 + 8 * (i % 4) as u8 + 4 * (i / 4) as u8;
+            *p = [v, v + 10, v + 20, 255];
+        }
+
+        for &quality in [Quality::Fast, Quality::Best].iter() {
+            assert!(max_error(&block, &decode_etc2(&etc2(&block, quality))) <= 8);
+        }
+    }
+
+    #[test]
+    fn test_eac() {
+        let mut block = [[0u8; 4]; 16];
+        for (i, p) in block.iter_mut().enumerate() {
+            p[3] = (i * 16) as u8;
+        }
+
+        for &quality in [Quality::Fast, Quality::Normal, Quality::Best].iter() {
+            let decoded = decode_eac(&eac(&block, 3, quality));
+            let error = block.iter().zip(decoded.iter()).fold(0, |m, (p, &v)| {
+                ::std::cmp::max(m, (p[3] as i32 - v as i32).abs())
+            });
+            assert!(error <= if quality == Quality::Fast { 20 } else { 14 }, "{:?}: {}", quality, error);
+        }
+
+        assert_eq!(decode_eac(&eac(&[[0, 0, 0, 255]; 16], 3, Quality::Fast)), [255; 16]);
+        assert_eq!(decode_eac(&eac(&[[0, 0, 0, 77]; 16], 3, Quality::Fast)), [77; 16]);
+    }
+}
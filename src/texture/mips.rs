@@ -0,0 +1,278 @@
+//! Mipmap chains
+//!
+//! Every level is filtered from the one before it, with the samples kept as
+//! floats between the levels. Averaging sRGB samples darkens fine detail
+//! and high contrast edges, thus sRGB colors are filtered in linear light.
+//! Filtering normals shortens them, which makes lighting dull in the
+//! smaller levels unless they are renormalized.
+
+use std::cmp;
+use std::f32;
+
+use buffer::{ImageBuffer, RgbaImage};
+use color::Rgba;
+
+// The radius of the Kaiser windowed sinc in pixels of the smaller level and
+// the shape of the window, larger values narrow it
+const KAISER_RADIUS: f32 = 3.0;
+const KAISER_ALPHA: f32 = 4.0;
+
+/// The options of ```generate_mips```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MipOptions {
+    /// Whether the RGB samples are sRGB encoded, thus filtered in linear
+    /// light. Defaults to true.
+    pub srgb: bool,
+
+    /// Whether the RGB samples are the components of unit normals mapped
+    /// from -1 to 1 to 0 to 255, as in tangent space normal maps, which are
+    /// renormalized after filtering. Such samples are linear, ```srgb```
+    /// does not apply to them. Defaults to false.
+    pub renormalize_normals: bool,
+
+    /// Whether the levels are filtered with a Kaiser windowed sinc, which
+    /// keeps them sharper than the average of 2x2 pixels. Defaults to false.
+    pub kaiser_filter: bool,
+}
+
+impl Default for MipOptions {
+    fn default() -> MipOptions {
+        MipOptions {
+            srgb: true,
+            renormalize_normals: false,
+            kaiser_filter: false,
+        }
+    }
+}
+
+/// Generates the mipmap chain of ```image```, from the image itself down to
+/// 1x1 pixels. Each level is half as wide and high as the one before it,
+/// rounded down, but at least 1 pixel.
+pub fn generate_mips(image: &RgbaImage, options: &MipOptions) -> Vec<RgbaImage> {
+    let srgb = options.srgb && !options.renormalize_normals;
+    let (mut width, mut height) = image.dimensions();
+
+    let mut samples = image.pixels().map(|p| {
+        let mut s = [0f32; 4];
+        for c in (0..4) {
+            s[c] = p[c] as f32 / 255.0;
+            if srgb && c < 3 {
+                s[c] = srgb_to_linear(s[c]);
+            }
+        }
+        s
+    }).collect::<Vec<_>>();
+
+    let mut levels = vec![image.clone()];
+
+    while width > 1 || height > 1 {
+        let (w, h) = (cmp::max(1, width / 2), cmp::max(1, height / 2));
+        samples = resample(&samples, width, height, w, h, options.kaiser_filter);
+
+        if options.renormalize_normals {
+            for s in samples.iter_mut() {
+                renormalize(s);
+            }
+        }
+
+        levels.push(ImageBuffer::from_fn(w, h, |x, y| {
+            let s = &samples[(y * w + x) as usize];
+            let mut p = [0u8; 4];
+            for c in (0..4) {
+                let v = if srgb && c < 3 { linear_to_srgb(s[c]) } else { s[c] };
+                p[c] = (v * 255.0 + 0.5) as u8;
+            }
+            Rgba(p)
+        }));
+
+        width = w;
+        height = h;
+    }
+
+    levels
+}
+
+// Resamples `samples` of `width` by `height` pixels to `w` by `h` pixels,
+// first the rows, then the columns
+fn resample(samples: &[[f32; 4]], width: u32, height: u32, w: u32, h: u32, kaiser: bool) -> Vec<[f32; 4]> {
+    let (width, height, w, h) = (width as usize, height as usize, w as usize, h as usize);
+    let columns = taps(width, w, kaiser);
+    let rows = taps(height, h, kaiser);
+
+    let mut tmp = vec![[0f32; 4]; w * height];
+    for y in (0..height) {
+        for (x, taps) in columns.iter().enumerate() {
+            tmp[y * w + x] = filter(taps.iter().map(|&(i, weight)| (&samples[y * width + i], weight)));
+        }
+    }
+
+    let mut out = vec![[0f32; 4]; w * h];
+    for (y, taps) in rows.iter().enumerate() {
+        for x in (0..w) {
+            out[y * w + x] = filter(taps.iter().map(|&(i, weight)| (&tmp[i * w + x], weight)));
+        }
+    }
+
+    out
+}
+
+// Sums the weighted samples, clamped to 0 to 1 as the negative lobes of
+// the sinc may overshoot
+fn filter<'a, I: Iterator<Item=(&'a [f32; 4], f32)>>(taps: I) -> [f32; 4] {
+    let mut sum = [0f32; 4];
+    for (s, weight) in taps {
+        for c in (0..4) {
+            sum[c] += s[c] * weight;
+        }
+    }
+
+    for v in sum.iter_mut() {
+        *v = v.max(0.0).min(1.0);
+    }
+    sum
+}
+
+// The source pixels and their normalized weights of each of `m` pixels
+// resampled from `n` pixels
+fn taps(n: usize, m: usize, kaiser: bool) -> Vec<Vec<(usize, f32)>> {
+    let scale = n as f32 / m as f32;
+
+    (0..m).map(|x| {
+        let mut taps = Vec::new();
+
+        if kaiser {
+            let center = (x as f32 + 0.5) * scale;
+            let first = (center - KAISER_RADIUS * scale).floor() as isize;
+            let last = (center + KAISER_RADIUS * scale).ceil() as isize;
+
+            // Pixels past the edges repeat the edge pixels
+            for i in (first..last + 1) {
+                let t = (i as f32 + 0.5 - center) / scale;
+                if t.abs() < KAISER_RADIUS {
+                    let index = cmp::max(0, cmp::min(n as isize - 1, i)) as usize;
+                    taps.push((index, sinc(t) * kaiser_window(t / KAISER_RADIUS)));
+                }
+            }
+        } else {
+            // The area of each pixel covered by the larger pixel
+            let (left, right) = (x as f32 * scale, (x + 1) as f32 * scale);
+            for i in (left.floor() as usize..cmp::min(n, right.ceil() as usize)) {
+                let covered = right.min(i as f32 + 1.0) - left.max(i as f32);
+                if covered > 0.0 {
+                    taps.push((i, covered));
+                }
+            }
+        }
+
+        let sum = taps.iter().fold(0.0, |s, &(_, w)| s + w);
+        for tap in taps.iter_mut() {
+            tap.1 /= sum;
+        }
+        taps
+    }).collect()
+}
+
+fn sinc(t: f32) -> f32 {
+    if t == 0.0 {
+        1.0
+    } else {
+        (t * f32::consts::PI).sin() / (t * f32::consts::PI)
+    }
+}
+
+// The Kaiser window for `x` from -1 to 1
+fn kaiser_window(x: f32) -> f32 {
+    bessel_i0(KAISER_ALPHA * (1.0 - x * x).max(0.0).sqrt()) / bessel_i0(KAISER_ALPHA)
+}
+
+// The modified Bessel function of the first kind of order 0
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+
+    for k in (1..20) {
+        term *= (x / (2.0 * k as f32)) * (x / (2.0 * k as f32));
+        sum += term;
+    }
+
+    sum
+}
+
+// Scales the normal in the RGB samples back to unit length
+fn renormalize(s: &mut [f32; 4]) {
+    let n = [s[0] * 2.0 - 1.0, s[1] * 2.0 - 1.0, s[2] * 2.0 - 1.0];
+    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+
+    // Opposite normals may cancel out, which leaves no direction
+    if length < 1e-6 {
+        return
+    }
+
+    for c in (0..3) {
+        s[c] = (n[c] / length + 1.0) / 2.0;
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{ImageBuffer, RgbaImage};
+    use color::Rgba;
+    use super::{generate_mips, MipOptions};
+
+    #[test]
+    fn test_chain() {
+        let image = RgbaImage::new(13, 4);
+        let levels = generate_mips(&image, &MipOptions::default());
+
+        let sizes = levels.iter().map(|l| l.dimensions()).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(13, 4), (6, 2), (3, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn test_srgb() {
+        // A checkerboard of black and white, which has half the light of white
+        let image: RgbaImage = ImageBuffer::from_fn(8, 8, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            Rgba([v, v, v, 255])
+        });
+
+        let linear = MipOptions { srgb: false, ..MipOptions::default() };
+        assert_eq!(generate_mips(&image, &linear)[1][(0, 0)], Rgba([128, 128, 128, 255]));
+        assert_eq!(generate_mips(&image, &MipOptions::default())[1][(0, 0)], Rgba([188, 188, 188, 255]));
+
+        // The sinc leaves little of the checkerboard
+        let kaiser = MipOptions { kaiser_filter: true, ..MipOptions::default() };
+        let level = &generate_mips(&image, &kaiser)[1];
+        assert!(level.pixels().all(|p| (p[0] as i32 - 188).abs() <= 4));
+    }
+
+    #[test]
+    fn test_renormalize_normals() {
+        // Normals tilted to the left and to the right
+        let image: RgbaImage = ImageBuffer::from_fn(2, 2, |x, _| {
+            if x == 0 { Rgba([37, 128, 218, 255]) } else { Rgba([218, 128, 218, 255]) }
+        });
+
+        let plain = MipOptions { srgb: false, ..MipOptions::default() };
+        assert_eq!(generate_mips(&image, &plain)[1][(0, 0)], Rgba([128, 128, 218, 255]));
+
+        let normals = MipOptions { renormalize_normals: true, ..MipOptions::default() };
+        assert_eq!(generate_mips(&image, &normals)[1][(0, 0)], Rgba([128, 128, 255, 255]));
+    }
+}
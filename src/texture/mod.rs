@@ -0,0 +1,194 @@
+//! Block compression of textures for GPUs
+//!
+//! GPUs sample block compressed textures directly, thus they take a fraction
+//! of the memory and bandwidth of uncompressed ones. Every format divides the
+//! image into blocks of 4x4 pixels, which are compressed to 8 or 16 bytes.
+//! Images whose dimensions are not multiples of 4 are padded by repeating
+//! their last row and column.
+//!
+//! ```
+//! use image::RgbaImage;
+//! use image::texture::{self, Format, Quality};
+//!
+//! let image = RgbaImage::new(64, 64);
+//! let compressed = texture::compress(&image, Format::BC7, Quality::Normal);
+//!
+//! let mut ktx2 = Vec::new();
+//! compressed.write_ktx2(&mut ktx2).unwrap();
+//! ```
+//!
+//! Textures sampled at a distance need mipmaps, which ```generate_mips```
+//! filters from the image:
+//!
+//! ```
+//! use image::RgbaImage;
+//! use image::texture::{self, Format, MipOptions, Quality};
+//!
+//! let image = RgbaImage::new(64, 64);
+//! let options = MipOptions::default();
+//! let levels = texture::generate_mips(&image, &options);
+//!
+//! let mut compressed = texture::compress_mips(&levels, Format::BC1, Quality::Normal);
+//! compressed.srgb = options.srgb;
+//!
+//! let mut dds = Vec::new();
+//! compressed.write_dds(&mut dds).unwrap();
+//! ```
+//!
+//! Skyboxes and environment maps are cube maps, which ```equirect_to_cubemap```
+//! projects from 360° photos.
+//!
+//! #Related Links
+//! * https://learn.microsoft.com/en-us/windows/win32/direct3d11/texture-block-compression-in-direct3d-11
+//! * https://registry.khronos.org/DataFormat/specs/1.3/dataformat.1.3.html - ETC2 and the data format descriptors
+//! * https://registry.khronos.org/KTX/specs/2.0/ktxspec.v2.html - The KTX2 specification
+
+use buffer::RgbaImage;
+
+pub use self::container::CompressedTexture;
+pub use self::cubemap::{cubemap_from_faces, cubemap_to_equirect, equirect_to_cubemap, Cubemap};
+pub use self::mips::{generate_mips, MipOptions};
+
+mod bc;
+mod etc2;
+mod container;
+mod cubemap;
+mod mips;
+
+/// The block compression formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// 8 bytes per block, RGB with 1 bit alpha
+    BC1,
+    /// 16 bytes per block, RGB as in BC1 with interpolated alpha
+    BC3,
+    /// 16 bytes per block, RGBA of high quality
+    BC7,
+    /// 8 bytes per block, RGB, the mandatory format of OpenGL ES 3.0
+    ETC2RGB,
+    /// 16 bytes per block, RGB as in ETC2RGB with interpolated alpha
+    ETC2RGBA,
+}
+
+impl Format {
+    /// The number of bytes of a compressed block of 4x4 pixels
+    pub fn block_len(&self) -> usize {
+        match *self {
+            Format::BC1 | Format::ETC2RGB => 8,
+            Format::BC3 | Format::BC7 | Format::ETC2RGBA => 16,
+        }
+    }
+}
+
+/// The effort of searching for the best encoding of a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quality {
+    /// Derives the colors of a block from its bounding box
+    Fast,
+    /// Derives the colors of a block from its principal axis
+    Normal,
+    /// Refines the colors of a block until the error stops decreasing
+    Best,
+}
+
+// The RGBA samples of a block of 4x4 pixels in row-major order
+type Block = [[u8; 4]; 16];
+
+/// Compresses ```image``` to ```format```.
+pub fn compress(image: &RgbaImage, format: Format, quality: Quality) -> CompressedTexture {
+    let (width, height) = image.dimensions();
+
+    CompressedTexture {
+        format: format,
+        width: width,
+        height: height,
+        data: compress_level(image, format, quality),
+        mipmaps: Vec::new(),
+        srgb: false,
+    }
+}
+
+/// Compresses the mipmap chain ```levels```, as returned by ```generate_mips```,
+/// to ```format```. The first level is the image itself.
+///
+/// # Panics
+///
+/// Panics if ```levels``` is empty.
+pub fn compress_mips(levels: &[RgbaImage], format: Format, quality: Quality) -> CompressedTexture {
+    let mut texture = compress(&levels[0], format, quality);
+    texture.mipmaps = levels[1..].iter().map(|level| compress_level(level, format, quality)).collect();
+    texture
+}
+
+// Compresses the blocks of a single level
+fn compress_level(image: &RgbaImage, format: Format, quality: Quality) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mut data = Vec::with_capacity(blocks(width) * blocks(height) * format.block_len());
+
+    for by in (0..blocks(height) as u32) {
+        for bx in (0..blocks(width) as u32) {
+            let block = read_block(image, bx * 4, by * 4);
+
+            match format {
+                Format::BC1 => data.extend(bc::bc1(&block, quality, true).iter().cloned()),
+                Format::BC3 => {
+                    data.extend(bc::bc4(&block, 3, quality).iter().cloned());
+                    data.extend(bc::bc1(&block, quality, false).iter().cloned());
+                }
+                Format::BC7 => data.extend(bc::bc7(&block, quality).iter().cloned()),
+                Format::ETC2RGB => data.extend(etc2::etc2(&block, quality).iter().cloned()),
+                Format::ETC2RGBA => {
+                    data.extend(etc2::eac(&block, 3, quality).iter().cloned());
+                    data.extend(etc2::etc2(&block, quality).iter().cloned());
+                }
+            }
+        }
+    }
+
+    data
+}
+
+// The number of blocks covering `n` pixels
+fn blocks(n: u32) -> usize {
+    (n as usize + 3) / 4
+}
+
+// Reads the block at `x`, `y`, repeating the last row and column of the image
+fn read_block(image: &RgbaImage, x: u32, y: u32) -> Block {
+    let (width, height) = image.dimensions();
+    let mut block = [[0u8; 4]; 16];
+
+    if width == 0 || height == 0 {
+        return block
+    }
+
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let px = ::std::cmp::min(x + i as u32 % 4, width - 1);
+        let py = ::std::cmp::min(y + i as u32 / 4, height - 1);
+        *pixel = image.get_pixel(px, py).data;
+    }
+
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::{ImageBuffer, RgbaImage};
+    use color::Rgba;
+    use super::{compress, read_block, Format, Quality};
+
+    #[test]
+    fn test_block_padding() {
+        let image: RgbaImage = ImageBuffer::from_fn(5, 3, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        let block = read_block(&image, 4, 0);
+        assert_eq!(block[0], [4, 0, 0, 255]);
+        assert_eq!(block[3], [4, 0, 0, 255]);
+        assert_eq!(block[15], [4, 2, 0, 255]);
+
+        for &format in [Format::BC1, Format::BC3, Format::BC7, Format::ETC2RGB, Format::ETC2RGBA].iter() {
+            let compressed = compress(&image, format, Quality::Fast);
+            assert_eq!(compressed.data.len(), 2 * format.block_len());
+        }
+    }
+}
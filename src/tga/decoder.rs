@@ -7,6 +7,10 @@ use image::ImageResult;
 use image::ImageDecoder;
 use image::DecodingResult;
 use color::ColorType;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+#[cfg(feature = "mmap")]
+use mmap::{self, MappedFile};
 
 enum ImageType {
     NoImageData = 0,
@@ -378,6 +382,20 @@ impl<R: Read + Seek> TGADecoder<R> {
     }
 }
 
+#[cfg(feature = "mmap")]
+impl TGADecoder<MappedFile> {
+    /// Create a new decoder that decodes the file at ```path```, mapped
+    /// into memory
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to or truncated, by this or any other
+    /// process, while the decoder exists. See the ```mmap``` module.
+    pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> ImageResult<TGADecoder<MappedFile>> {
+        Ok(TGADecoder::new(try!(mmap::map_file(path))))
+    }
+}
+
 impl<R: Read + Seek> ImageDecoder for TGADecoder<R> {
     fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
         try!(self.read_metadata());
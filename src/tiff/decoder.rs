@@ -1,4 +1,6 @@
+use std::cmp;
 use std::io::{self, Read, Seek};
+use std::u32;
 use std::mem;
 use num::FromPrimitive;
 use std::collections::HashMap;
@@ -15,7 +17,12 @@ use image::{
 };
 
 use color::{ColorType};
+#[cfg(feature = "mmap")]
+use std::path::Path;
+#[cfg(feature = "mmap")]
+use mmap::{self, MappedFile};
 
+use super::geo::GeoTags;
 use super::ifd;
 use super::ifd::Directory;
 
@@ -251,6 +258,22 @@ impl<R: Read + Seek> TIFFDecoder<R> {
         self.reader.read_u32()
     }
 
+    /// Reads a TIFF byte value
+    #[inline]
+    pub fn read_byte(&mut self) -> Result<u8, byteorder::Error> {
+        let mut val = [0; 1];
+        if try!(self.reader.read(&mut val)) != 1 {
+            return Err(byteorder::Error::UnexpectedEOF);
+        }
+        Ok(val[0])
+    }
+
+    /// Reads a TIFF double value
+    #[inline]
+    pub fn read_double(&mut self) -> Result<f64, byteorder::Error> {
+        self.reader.read_f64()
+    }
+
     /// Reads a TIFF IFA offset/value field
     #[inline]
     pub fn read_offset(&mut self) -> Result<[u8; 4], byteorder::Error> {
@@ -267,6 +290,15 @@ impl<R: Read + Seek> TIFFDecoder<R> {
         self.reader.seek(io::SeekFrom::Start(offset as u64)).map(|_| ())
     }
 
+    /// Returns how many of ```n``` values of ```size``` bytes fit in the
+    /// rest of the file, to reserve memory for a list of untrusted length
+    pub fn list_capacity(&mut self, n: u32, size: u64) -> io::Result<usize> {
+        let position = try!(self.reader.seek(io::SeekFrom::Current(0)));
+        let end = try!(self.reader.seek(io::SeekFrom::End(0)));
+        try!(self.reader.seek(io::SeekFrom::Start(position)));
+        Ok(cmp::min(n as u64, end.saturating_sub(position) / size) as usize)
+    }
+
     /// Reads a IFD entry.
     ///
     /// And IFD entry has four fields
@@ -348,6 +380,35 @@ impl<R: Read + Seek> TIFFDecoder<R> {
         }
     }
 
+    /// Tries to retrieve a tag an convert it to the desired type.
+    fn find_tag_f64_vec(&mut self, tag: ifd::Tag) -> ImageResult<Option<Vec<f64>>> {
+        match try!(self.find_tag(tag)) {
+            Some(val) => Ok(Some(try!(val.as_f64_vec()))),
+            None => Ok(None)
+        }
+    }
+
+    /// Returns the GeoTIFF georeferencing tags of the current image
+    pub fn geo_tags(&mut self) -> ImageResult<GeoTags> {
+        let key_directory = match try!(self.find_tag_u32_vec(ifd::Tag::GeoKeyDirectory)) {
+            Some(keys) => Some(keys.iter().map(|&k| k as u16).collect()),
+            None => None
+        };
+        let ascii_params = match try!(self.find_tag(ifd::Tag::GeoAsciiParams)) {
+            Some(val) => Some(try!(val.as_string())),
+            None => None
+        };
+
+        Ok(GeoTags {
+            model_pixel_scale: try!(self.find_tag_f64_vec(ifd::Tag::ModelPixelScale)),
+            model_tiepoints: try!(self.find_tag_f64_vec(ifd::Tag::ModelTiepoint)),
+            model_transformation: try!(self.find_tag_f64_vec(ifd::Tag::ModelTransformation)),
+            key_directory: key_directory,
+            double_params: try!(self.find_tag_f64_vec(ifd::Tag::GeoDoubleParams)),
+            ascii_params: ascii_params,
+        })
+    }
+
     /// Tries to retrieve a tag.
     /// Returns an error if the tag is not present
     fn get_tag(&mut self, tag: ifd::Tag) -> ImageResult<ifd::Value> {
@@ -424,6 +485,20 @@ impl<R: Read + Seek> TIFFDecoder<R> {
     }
 }
 
+#[cfg(feature = "mmap")]
+impl TIFFDecoder<MappedFile> {
+    /// Create a new decoder that decodes the file at ```path```, mapped
+    /// into memory
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to or truncated, by this or any other
+    /// process, while the decoder exists. See the ```mmap``` module.
+    pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> ImageResult<TIFFDecoder<MappedFile>> {
+        TIFFDecoder::new(try!(mmap::map_file(path)))
+    }
+}
+
 impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
     fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
         Ok((self.width, self.height))
@@ -446,6 +521,31 @@ impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
         }
     }
 
+    // The ratio of the vertical to the horizontal resolution. Resolutions
+    // of another type than rational are ignored like missing ones.
+    fn pixel_aspect_ratio(&mut self) -> ImageResult<(u32, u32)> {
+        let x = try!(self.find_tag(ifd::Tag::XResolution)).and_then(|v| v.as_rational().ok());
+        let y = try!(self.find_tag(ifd::Tag::YResolution)).and_then(|v| v.as_rational().ok());
+
+        Ok(match (x, y) {
+            (Some((xn, xd)), Some((yn, yd))) if xn > 0 && xd > 0 && yn > 0 && yd > 0 => {
+                let (mut w, mut h) = (yn as u64 * xd as u64, yd as u64 * xn as u64);
+                let d = num::integer::gcd(w, h);
+                w /= d;
+                h /= d;
+
+                // Ratios of large terms are approximated
+                while w > u32::MAX as u64 || h > u32::MAX as u64 {
+                    w = cmp::max(w >> 1, 1);
+                    h = cmp::max(h >> 1, 1);
+                }
+
+                image::reduce_aspect_ratio(w as u32, h as u32)
+            }
+            _ => (1, 1)
+        })
+    }
+
     fn row_len(&mut self) -> ImageResult<usize> {
         unimplemented!()
     }
@@ -534,3 +634,71 @@ impl<R: Read + Seek> ImageDecoder for TIFFDecoder<R> {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::ImageDecoder;
+    use super::TIFFDecoder;
+
+    // A little endian TIFF file with one directory of `entries`, each a tag,
+    // type, count and value, followed by `data`
+    fn tiff(entries: &[(u16, u16, u32, u32)], data: &[u8]) -> Vec<u8> {
+        let mut file = b"II*\0\x08\0\0\0".to_vec();
+        let put = |file: &mut Vec<u8>, v: u32, n: usize| {
+            file.extend((0..n).map(|i| (v >> (8 * i)) as u8));
+        };
+
+        put(&mut file, entries.len() as u32, 2);
+        for &(tag, ty, count, value) in entries.iter() {
+            put(&mut file, tag as u32, 2);
+            put(&mut file, ty as u32, 2);
+            put(&mut file, count, 4);
+            put(&mut file, value, 4);
+        }
+        put(&mut file, 0, 4);
+
+        file.extend(data.iter().cloned());
+        file
+    }
+
+    // The offset of the data after a directory of `n` entries
+    fn data_offset(n: usize) -> u32 {
+        (8 + 2 + 12 * n + 4) as u32
+    }
+
+    // A 1x1 gray image with `extra` entries
+    fn gray(extra: &[(u16, u16, u32, u32)], data: &[u8]) -> Vec<u8> {
+        let mut entries = vec![(256, 3, 1, 1), (257, 3, 1, 1), (262, 3, 1, 1)];
+        entries.extend(extra.iter().cloned());
+        tiff(&entries, data)
+    }
+
+    #[test]
+    fn test_untrusted_counts() {
+        // A list of doubles claiming far more values than the file holds
+        let file = gray(&[(33922, 12, 0x1000_0000, data_offset(4))], &[0; 16]);
+        let mut decoder = TIFFDecoder::new(Cursor::new(file)).unwrap();
+        assert!(decoder.geo_tags().is_err());
+    }
+
+    #[test]
+    fn test_pixel_aspect_ratio() {
+        let aspect = |entries: &[(u16, u16, u32, u32)], data: &[u8]| {
+            TIFFDecoder::new(Cursor::new(gray(entries, data))).unwrap().pixel_aspect_ratio().unwrap()
+        };
+        let offset = data_offset(5);
+        let rationals = [72, 0, 0, 0, 1, 0, 0, 0, 144, 0, 0, 0, 1, 0, 0, 0];
+
+        // Twice the vertical resolution makes pixels twice as wide as high
+        assert_eq!(aspect(&[(282, 5, 1, offset), (283, 5, 1, offset + 8)], &rationals), (2, 1));
+        assert_eq!(aspect(&[(282, 5, 1, offset + 8), (283, 5, 1, offset)], &rationals), (1, 2));
+        assert_eq!(aspect(&[(282, 5, 1, offset), (283, 5, 1, offset)], &rationals), (1, 1));
+
+        // Missing, zero and mistyped resolutions give square pixels
+        assert_eq!(aspect(&[], &[]), (1, 1));
+        assert_eq!(aspect(&[(282, 5, 1, offset), (283, 5, 1, offset + 8)], &[0; 16]), (1, 1));
+        assert_eq!(aspect(&[(282, 3, 1, 72), (283, 5, 1, offset + 8)], &rationals), (1, 1));
+    }
+}
@@ -0,0 +1,616 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::io::{self, Write};
+use std::u32;
+
+use byteorder::{WriteBytesExt, LittleEndian};
+
+use color::{ColorSpace, ColorType};
+use super::geo::GeoTags;
+
+// The tags written, in ascending order as required for an IFD
+const NEW_SUBFILE_TYPE: u16 = 254;
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const PLANAR_CONFIGURATION: u16 = 284;
+const TRANSFER_FUNCTION: u16 = 301;
+const WHITE_POINT: u16 = 318;
+const PRIMARY_CHROMATICITIES: u16 = 319;
+const TILE_WIDTH: u16 = 322;
+const TILE_LENGTH: u16 = 323;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+const EXTRA_SAMPLES: u16 = 338;
+const REFERENCE_BLACK_WHITE: u16 = 532;
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const MODEL_TRANSFORMATION: u16 = 34264;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+const GEO_DOUBLE_PARAMS: u16 = 34736;
+const GEO_ASCII_PARAMS: u16 = 34737;
+
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const DOUBLE: u16 = 12;
+
+// An entry of an IFD whose values are either stored in the entry itself
+// or, if they take more than 4 bytes, after the entries
+struct Entry {
+    tag: u16,
+    type_: u16,
+    count: u32,
+    data: Vec<u8>,
+}
+
+impl Entry {
+    fn short(tag: u16, values: Vec<u32>) -> Entry {
+        let mut data = Vec::with_capacity(2 * values.len());
+        for &v in values.iter() {
+            data.write_u16::<LittleEndian>(v as u16).unwrap();
+        }
+
+        Entry { tag: tag, type_: SHORT, count: values.len() as u32, data: data }
+    }
+
+    fn long(tag: u16, values: Vec<u32>) -> Entry {
+        let mut data = Vec::with_capacity(4 * values.len());
+        for &v in values.iter() {
+            data.write_u32::<LittleEndian>(v).unwrap();
+        }
+
+        Entry { tag: tag, type_: LONG, count: values.len() as u32, data: data }
+    }
+
+    // Non-negative values with a precision of a millionth
+    fn rational(tag: u16, values: &[f64]) -> Entry {
+        let mut data = Vec::with_capacity(8 * values.len());
+        for &v in values.iter() {
+            data.write_u32::<LittleEndian>((v.max(0.0) * 1e6).round() as u32).unwrap();
+            data.write_u32::<LittleEndian>(1000000).unwrap();
+        }
+
+        Entry { tag: tag, type_: RATIONAL, count: values.len() as u32, data: data }
+    }
+
+    fn double(tag: u16, values: &[f64]) -> Entry {
+        let mut data = Vec::with_capacity(8 * values.len());
+        for &v in values.iter() {
+            data.write_f64::<LittleEndian>(v).unwrap();
+        }
+
+        Entry { tag: tag, type_: DOUBLE, count: values.len() as u32, data: data }
+    }
+
+    fn ascii(tag: u16, value: &str) -> Entry {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+
+        Entry { tag: tag, type_: ASCII, count: data.len() as u32, data: data }
+    }
+
+    // The space the values take after the entries, padded to a word boundary
+    fn values_len(&self) -> u32 {
+        if self.data.len() > 4 {
+            (self.data.len() as u32 + 1) / 2 * 2
+        } else {
+            0
+        }
+    }
+}
+
+// One resolution level of the pyramid
+struct Level<'a> {
+    data: Cow<'a, [u8]>,
+    width: u32,
+    height: u32,
+}
+
+/// A tiled TIFF encoder that writes an image and a pyramid of reduced
+/// resolution versions of it
+///
+/// Every level halves the size of the previous one until it fits into a
+/// single tile. The levels are stored as consecutive IFDs, the reduced ones
+/// marked as such by ```NewSubfileType```, which is the layout viewers of
+/// whole-slide images and GIS rasters expect. The tiles are uncompressed
+/// and, as the offsets are 32 bit, the file is limited to 4 GiB.
+pub struct TiledTIFFEncoder<W: Write> {
+    w: W,
+    tile_size: u32,
+    geo_tags: GeoTags,
+    color_space: Option<ColorSpace>,
+}
+
+impl<W: Write> TiledTIFFEncoder<W> {
+    /// Create a new encoder that writes its output to ```w```
+    pub fn new(w: W) -> TiledTIFFEncoder<W> {
+        TiledTIFFEncoder {
+            w: w,
+            tile_size: 256,
+            geo_tags: GeoTags::default(),
+            color_space: None,
+        }
+    }
+
+    /// Sets the width and height of the tiles. Defaults to 256.
+    ///
+    /// # Panics
+    ///
+    /// Panics if ```size``` is not a positive multiple of 16, as required
+    /// by the TIFF specification.
+    pub fn set_tile_size(&mut self, size: u32) {
+        assert!(size > 0 && size % 16 == 0, "TIFF tile size must be a multiple of 16");
+        self.tile_size = size;
+    }
+
+    /// Sets the GeoTIFF tags of the image, as read by
+    /// ```TIFFDecoder::geo_tags```. They are written for the full
+    /// resolution only, the reduced levels inherit its georeferencing.
+    pub fn set_geo_tags(&mut self, tags: GeoTags) {
+        self.geo_tags = tags;
+    }
+
+    /// Records the color space of the image in the ```TransferFunction```,
+    /// ```WhitePoint```, ```PrimaryChromaticities``` and, for RGB images,
+    /// ```ReferenceBlackWhite``` tags of every level.
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = Some(color_space);
+    }
+
+    /// Encodes the image ```image``` that has dimensions ```width``` and
+    /// ```height``` and ```ColorType``` ```c```, together with its reduced
+    /// resolution levels.
+    pub fn encode(&mut self, image: &[u8], width: u32, height: u32, c: ColorType) -> io::Result<()> {
+        let samples = match c {
+            ColorType::Gray(8) => 1,
+            ColorType::GrayA(8) => 2,
+            ColorType::RGB(8) => 3,
+            ColorType::RGBA(8) => 4,
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("Unsupported color type {:?}. Use 8 bit per channel RGB(A) or Gray(A) instead.", c)[..],
+            ))
+        };
+
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Image has no pixels"))
+        }
+
+        if image.len() < width as usize * height as usize * samples {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Image buffer is too small"))
+        }
+
+        let mut levels = vec![Level {
+            data: Cow::Borrowed(&image[..width as usize * height as usize * samples]),
+            width: width,
+            height: height,
+        }];
+
+        loop {
+            let next = {
+                let last = &levels[levels.len() - 1];
+                if last.width <= self.tile_size && last.height <= self.tile_size {
+                    break
+                }
+
+                downsample(last, samples)
+            };
+
+            levels.push(next);
+        }
+
+        let tile = self.tile_size;
+        let tile_bytes = (tile * tile) as u64 * samples as u64;
+        let tiles = |l: &Level| (((l.width + tile - 1) / tile) * ((l.height + tile - 1) / tile)) as u64;
+
+        let geo_len = geo_entries(&self.geo_tags).iter().fold(0, |sum, e| sum + 12 + e.values_len() as u64);
+        let color_len = match self.color_space {
+            Some(ref space) => color_entries(space, samples).iter().fold(0, |sum, e| sum + 12 + e.values_len() as u64),
+            None => 0
+        };
+        let total = levels.iter().fold(8 + geo_len, |sum, l| sum + tiles(l) * (tile_bytes + 8) + 256 + color_len);
+        if total > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput, "Image exceeds the 4 GiB limit of TIFF"
+            ))
+        }
+
+        // Each level is written as its tiles followed by its IFD
+        let mut pos = 8 + (tiles(&levels[0]) * tile_bytes) as u32;
+
+        try!(self.w.write_all(b"II"));
+        try!(self.w.write_u16::<LittleEndian>(42));
+        try!(self.w.write_u32::<LittleEndian>(pos));
+        pos = 8;
+
+        for (i, level) in levels.iter().enumerate() {
+            let count = tiles(level) as usize;
+            let offsets = (0..count).map(|t| pos + t as u32 * tile_bytes as u32).collect::<Vec<u32>>();
+
+            try!(self.write_tiles(level, samples));
+            pos += count as u32 * tile_bytes as u32;
+
+            let mut entries = vec![
+                Entry::long(NEW_SUBFILE_TYPE, vec![if i == 0 { 0 } else { 1 }]),
+                Entry::long(IMAGE_WIDTH, vec![level.width]),
+                Entry::long(IMAGE_LENGTH, vec![level.height]),
+                Entry::short(BITS_PER_SAMPLE, vec![8; samples]),
+                Entry::short(COMPRESSION, vec![1]),
+                Entry::short(PHOTOMETRIC_INTERPRETATION, vec![if samples < 3 { 1 } else { 2 }]),
+                Entry::short(SAMPLES_PER_PIXEL, vec![samples as u32]),
+                Entry::short(PLANAR_CONFIGURATION, vec![1]),
+            ];
+
+            let color = match self.color_space {
+                Some(ref space) => color_entries(space, samples),
+                None => Vec::new()
+            };
+
+            // The color entries go around the tile and alpha tags
+            let split = color.iter().position(|e| e.tag > TILE_BYTE_COUNTS).unwrap_or(color.len());
+            let mut color = color.into_iter();
+            entries.extend(color.by_ref().take(split));
+
+            entries.push(Entry::long(TILE_WIDTH, vec![tile]));
+            entries.push(Entry::long(TILE_LENGTH, vec![tile]));
+            entries.push(Entry::long(TILE_OFFSETS, offsets));
+            entries.push(Entry::long(TILE_BYTE_COUNTS, vec![tile_bytes as u32; count]));
+
+            if samples % 2 == 0 {
+                // Unassociated alpha
+                entries.push(Entry::short(EXTRA_SAMPLES, vec![2]));
+            }
+
+            entries.extend(color);
+
+            if i == 0 {
+                entries.extend(geo_entries(&self.geo_tags));
+            }
+
+            let ifd_len = 2 + 12 * entries.len() as u32 + 4;
+            let values_len = entries.iter().fold(0, |sum, e| sum + e.values_len());
+
+            let next = match levels.get(i + 1) {
+                Some(next) => pos + ifd_len + values_len + (tiles(next) * tile_bytes) as u32,
+                None => 0
+            };
+
+            try!(self.write_ifd(&entries, pos + ifd_len, next));
+            pos += ifd_len + values_len;
+        }
+
+        Ok(())
+    }
+
+    // Writes the tiles of `level` row by row, padding the tiles at the
+    // right and bottom edges with zeros
+    fn write_tiles(&mut self, level: &Level, samples: usize) -> io::Result<()> {
+        let tile = self.tile_size as usize;
+        let (width, height) = (level.width as usize, level.height as usize);
+        let stride = width * samples;
+
+        let mut buf = vec![0u8; tile * tile * samples];
+
+        for ty in (0..(height + tile - 1) / tile) {
+            for tx in (0..(width + tile - 1) / tile) {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+
+                let columns = cmp::min(tile, width - tx * tile) * samples;
+
+                for y in (0..cmp::min(tile, height - ty * tile)) {
+                    let start = (ty * tile + y) * stride + tx * tile * samples;
+                    let row = &level.data[start..start + columns];
+                    ::copy_memory(row, &mut buf[y * tile * samples..y * tile * samples + columns]);
+                }
+
+                try!(self.w.write_all(&buf));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes an IFD whose values that do not fit into their entry are
+    // stored at `values`, followed by them
+    fn write_ifd(&mut self, entries: &[Entry], values: u32, next: u32) -> io::Result<()> {
+        try!(self.w.write_u16::<LittleEndian>(entries.len() as u16));
+
+        let mut offset = values;
+
+        for e in entries.iter() {
+            try!(self.w.write_u16::<LittleEndian>(e.tag));
+            try!(self.w.write_u16::<LittleEndian>(e.type_));
+            try!(self.w.write_u32::<LittleEndian>(e.count));
+
+            if e.data.len() > 4 {
+                try!(self.w.write_u32::<LittleEndian>(offset));
+                offset += e.values_len();
+            } else {
+                // Values stored in the entry are left-justified
+                let mut field = [0u8; 4];
+                ::copy_memory(&e.data, &mut field);
+                try!(self.w.write_all(&field));
+            }
+        }
+
+        try!(self.w.write_u32::<LittleEndian>(next));
+
+        for e in entries.iter().filter(|e| e.data.len() > 4) {
+            try!(self.w.write_all(&e.data));
+            if e.data.len() % 2 == 1 {
+                try!(self.w.write_all(&[0]));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The entries of the tags that record `space` for an image with `samples`
+// samples per pixel, in ascending order
+fn color_entries(space: &ColorSpace, samples: usize) -> Vec<Entry> {
+    let channels = if samples < 3 { 1 } else { 3 };
+
+    // A table per channel, or one if they are all the same
+    let tables = (0..channels).map(|c| {
+        (0..256).map(|i| (space.to_linear(c, i as f32 / 255.0) * 65535.0).round() as u32).collect::<Vec<u32>>()
+    }).collect::<Vec<_>>();
+    let distinct = if tables.iter().all(|t| *t == tables[0]) { 1 } else { channels };
+    let transfer = tables.into_iter().take(distinct).flat_map(|t| t.into_iter()).collect();
+
+    let (x, y) = space.white_point;
+    let mut entries = vec![
+        Entry::short(TRANSFER_FUNCTION, transfer),
+        Entry::rational(WHITE_POINT, &[x as f64, y as f64]),
+    ];
+
+    if channels == 3 {
+        let primaries = space.primaries.iter()
+                                       .flat_map(|&(x, y)| vec![x as f64, y as f64].into_iter())
+                                       .collect::<Vec<f64>>();
+        entries.push(Entry::rational(PRIMARY_CHROMATICITIES, &primaries));
+
+        let black_white = space.black_white.iter()
+                                           .flat_map(|&(b, w)| vec![b as f64, w as f64].into_iter())
+                                           .collect::<Vec<f64>>();
+        entries.push(Entry::rational(REFERENCE_BLACK_WHITE, &black_white));
+    }
+
+    entries
+}
+
+// The entries of the GeoTIFF tags that are present, which all follow
+// the baseline tags
+fn geo_entries(tags: &GeoTags) -> Vec<Entry> {
+    let mut entries = Vec::new();
+
+    if let Some(ref v) = tags.model_pixel_scale {
+        entries.push(Entry::double(MODEL_PIXEL_SCALE, v));
+    }
+    if let Some(ref v) = tags.model_tiepoints {
+        entries.push(Entry::double(MODEL_TIEPOINT, v));
+    }
+    if let Some(ref v) = tags.model_transformation {
+        entries.push(Entry::double(MODEL_TRANSFORMATION, v));
+    }
+    if let Some(ref v) = tags.key_directory {
+        entries.push(Entry::short(GEO_KEY_DIRECTORY, v.iter().map(|&k| k as u32).collect()));
+    }
+    if let Some(ref v) = tags.double_params {
+        entries.push(Entry::double(GEO_DOUBLE_PARAMS, v));
+    }
+    if let Some(ref v) = tags.ascii_params {
+        entries.push(Entry::ascii(GEO_ASCII_PARAMS, v));
+    }
+
+    entries
+}
+
+// Halves the size of `level` by averaging blocks of 2x2 pixels. A last
+// odd column or row is averaged with itself.
+fn downsample(level: &Level, samples: usize) -> Level<'static> {
+    let (width, height) = (level.width as usize, level.height as usize);
+    let (w2, h2) = ((width + 1) / 2, (height + 1) / 2);
+    let stride = width * samples;
+
+    let mut data = vec![0u8; w2 * h2 * samples];
+
+    for y in (0..h2) {
+        let (y0, y1) = (2 * y, cmp::min(2 * y + 1, height - 1));
+
+        for x in (0..w2) {
+            let (x0, x1) = (2 * x, cmp::min(2 * x + 1, width - 1));
+
+            for c in (0..samples) {
+                let sum = level.data[y0 * stride + x0 * samples + c] as u32
+                        + level.data[y0 * stride + x1 * samples + c] as u32
+                        + level.data[y1 * stride + x0 * samples + c] as u32
+                        + level.data[y1 * stride + x1 * samples + c] as u32;
+
+                data[(y * w2 + x) * samples + c] = ((sum + 2) / 4) as u8;
+            }
+        }
+    }
+
+    Level {
+        data: Cow::Owned(data),
+        width: w2 as u32,
+        height: h2 as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use byteorder::{ReadBytesExt, LittleEndian};
+
+    use color::{ColorSpace, ColorType};
+    use image::ImageDecoder;
+    use super::TiledTIFFEncoder;
+    use super::super::{GeoTags, TIFFDecoder};
+
+    // Reads the entries of the IFD at `offset` as (tag, values) and the next offset
+    fn read_ifd(file: &[u8], offset: u32) -> (Vec<(u16, Vec<u32>)>, u32) {
+        let mut r = Cursor::new(file);
+        r.set_position(offset as u64);
+
+        let count = r.read_u16::<LittleEndian>().unwrap();
+        let mut entries = Vec::new();
+
+        for _ in (0..count) {
+            let tag = r.read_u16::<LittleEndian>().unwrap();
+            let type_ = r.read_u16::<LittleEndian>().unwrap();
+            let n = r.read_u32::<LittleEndian>().unwrap();
+            let size = if type_ == 3 { 2 } else { 4 };
+
+            // Rationals are read as numerator and denominator
+            let n = if type_ == 5 { 2 * n } else { n };
+
+            let pos = r.position();
+            if n * size > 4 {
+                let at = r.read_u32::<LittleEndian>().unwrap();
+                r.set_position(at as u64);
+            }
+
+            let values = (0..n).map(|_| if type_ == 3 {
+                r.read_u16::<LittleEndian>().unwrap() as u32
+            } else {
+                r.read_u32::<LittleEndian>().unwrap()
+            }).collect();
+
+            r.set_position(pos + 4);
+            entries.push((tag, values));
+        }
+
+        (entries, r.read_u32::<LittleEndian>().unwrap())
+    }
+
+    fn value(entries: &[(u16, Vec<u32>)], tag: u16) -> Vec<u32> {
+        entries.iter().find(|e| e.0 == tag).unwrap().1.clone()
+    }
+
+    #[test]
+    fn test_pyramid() {
+        let (width, height) = (40, 24);
+        let image = (0..width * height * 3).map(|i| (i % 3 * 100 + i / 3 % width) as u8).collect::<Vec<u8>>();
+
+        let mut file = Vec::new();
+        {
+            let mut encoder = TiledTIFFEncoder::new(&mut file);
+            encoder.set_tile_size(16);
+            encoder.encode(&image, width, height, ColorType::RGB(8)).unwrap();
+        }
+
+        assert_eq!(&file[..4], b"II*\0");
+        let mut offset = Cursor::new(&file[4..8]).read_u32::<LittleEndian>().unwrap();
+
+        let mut levels = Vec::new();
+        while offset != 0 {
+            assert_eq!(offset % 2, 0);
+            let (entries, next) = read_ifd(&file, offset);
+            let tags = entries.iter().map(|e| e.0).collect::<Vec<u16>>();
+            let mut sorted = tags.clone();
+            sorted.sort();
+            assert_eq!(tags, sorted);
+
+            levels.push(entries);
+            offset = next;
+        }
+
+        let sizes = levels.iter().map(|e| (value(e, 256)[0], value(e, 257)[0], value(e, 254)[0]))
+                          .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(40, 24, 0), (20, 12, 1), (10, 6, 1)]);
+
+        // The pixel (33, 17) lies in the last tile of the full resolution
+        let offsets = value(&levels[0], 324);
+        assert_eq!(offsets.len(), 3 * 2);
+        let tile = offsets[5] as usize;
+        let pixel = tile + (1 * 16 + 1) * 3;
+        assert_eq!(&file[pixel..pixel + 3], &image[(17 * 40 + 33) * 3..(17 * 40 + 33) * 3 + 3]);
+
+        // The padding of the edge tiles is zero
+        assert_eq!(file[tile + (8 * 16) * 3], 0);
+
+        // The reduced levels average 2x2 pixels
+        let tile = value(&levels[1], 324)[0] as usize;
+        assert_eq!(file[tile + 3], ((2 + 3 + 2 + 3) + 2) / 4);
+    }
+
+    #[test]
+    fn test_geo_tags() {
+        let tags = GeoTags {
+            model_pixel_scale: Some(vec![30.0, 30.0, 0.0]),
+            model_tiepoints: Some(vec![0.0, 0.0, 0.0, 500000.0, 4000000.0, 0.0]),
+            model_transformation: None,
+            key_directory: Some(vec![1, 1, 0, 2, 1024, 0, 1, 1, 3072, 0, 1, 32617]),
+            double_params: None,
+            ascii_params: Some("WGS 84 / UTM 17N|".to_string()),
+        };
+
+        let image = vec![7u8; 20 * 10 * 3];
+        let mut file = Vec::new();
+        {
+            let mut encoder = TiledTIFFEncoder::new(&mut file);
+            encoder.set_tile_size(16);
+            encoder.set_geo_tags(tags.clone().crop(4, 2));
+            encoder.encode(&image, 20, 10, ColorType::RGB(8)).unwrap();
+        }
+
+        let mut decoder = TIFFDecoder::new(Cursor::new(&file[..])).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (20, 10));
+
+        let read = decoder.geo_tags().unwrap();
+        assert_eq!(read, tags.crop(4, 2));
+        assert_eq!(read.model_tiepoints.unwrap()[..2], [-4.0, -2.0]);
+    }
+
+    #[test]
+    fn test_color_space() {
+        let mut space = ColorSpace::with_gamma(2.2);
+        space.gamma[1] = 1.8;
+        space.black_white[2] = (16.0, 235.0);
+
+        for &(color, samples) in [(ColorType::RGB(8), 3), (ColorType::GrayA(8), 2)].iter() {
+            let image = vec![128u8; 20 * 10 * samples];
+            let mut file = Vec::new();
+            {
+                let mut encoder = TiledTIFFEncoder::new(&mut file);
+                encoder.set_tile_size(16);
+                encoder.set_color_space(space);
+                encoder.encode(&image, 20, 10, color).unwrap();
+            }
+
+            let offset = Cursor::new(&file[4..8]).read_u32::<LittleEndian>().unwrap();
+            let (entries, _) = read_ifd(&file, offset);
+            let tags = entries.iter().map(|e| e.0).collect::<Vec<u16>>();
+            let mut sorted = tags.clone();
+            sorted.sort();
+            assert_eq!(tags, sorted);
+
+            // The transfer functions map the samples to linear intensity
+            let transfer = value(&entries, 301);
+            assert_eq!(transfer.len(), if samples == 3 { 3 * 256 } else { 256 });
+            assert_eq!((transfer[0], transfer[255]), (0, 65535));
+            assert_eq!(transfer[128], (0.5019608f32.powf(2.2) * 65535.0).round() as u32);
+            if samples == 3 {
+                assert_eq!(transfer[256 + 128], (0.5019608f32.powf(1.8) * 65535.0).round() as u32);
+            }
+
+            assert_eq!(value(&entries, 318), vec![312700, 1000000, 329000, 1000000]);
+
+            if samples == 3 {
+                assert_eq!(value(&entries, 319)[0..2].to_vec(), vec![640000, 1000000]);
+                assert_eq!(value(&entries, 532)[8..12].to_vec(), vec![16000000, 1000000, 235000000, 1000000]);
+            } else {
+                assert!(!tags.contains(&319) && !tags.contains(&532));
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! GeoTIFF georeferencing tags
+//!
+//! See http://geotiff.maptools.org/spec/geotiff2.6.html
+
+/// The GeoTIFF tags of an image, which relate its raster to a model space
+/// like the coordinates of a map projection
+///
+/// The tags are kept as stored in the file. The geo keys in
+/// ```key_directory``` refer to ```double_params``` and ```ascii_params```
+/// by index, thus the three are only meaningful together.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GeoTags {
+    /// ModelPixelScaleTag: the size of a pixel in model space as (x, y, z)
+    pub model_pixel_scale: Option<Vec<f64>>,
+
+    /// ModelTiepointTag: tie points as groups of six values (i, j, k, x, y, z),
+    /// which map the raster point (i, j, k) to the model point (x, y, z)
+    pub model_tiepoints: Option<Vec<f64>>,
+
+    /// ModelTransformationTag: a 4x4 matrix in row major order that maps
+    /// raster to model coordinates
+    pub model_transformation: Option<Vec<f64>>,
+
+    /// GeoKeyDirectoryTag
+    pub key_directory: Option<Vec<u16>>,
+
+    /// GeoDoubleParamsTag
+    pub double_params: Option<Vec<f64>>,
+
+    /// GeoAsciiParamsTag, with its ```|``` separators
+    pub ascii_params: Option<String>,
+}
+
+impl GeoTags {
+    /// Returns true if no tag is present
+    pub fn is_empty(&self) -> bool {
+        *self == GeoTags::default()
+    }
+
+    /// Returns the tags of the part of the image that starts at the pixel
+    /// (```x```, ```y```), as cropping it moves the raster relative to the
+    /// model space.
+    pub fn crop(&self, x: u32, y: u32) -> GeoTags {
+        let (x, y) = (x as f64, y as f64);
+        let mut tags = self.clone();
+
+        if let Some(ref mut tiepoints) = tags.model_tiepoints {
+            for point in tiepoints.chunks_mut(6) {
+                if point.len() == 6 {
+                    point[0] -= x;
+                    point[1] -= y;
+                }
+            }
+        }
+
+        // The translation column absorbs the offset of the origin
+        if let Some(ref mut m) = tags.model_transformation {
+            if m.len() == 16 {
+                for row in (0..3) {
+                    m[row * 4 + 3] += m[row * 4] * x + m[row * 4 + 1] * y;
+                }
+            }
+        }
+
+        tags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GeoTags;
+
+    #[test]
+    fn test_crop() {
+        let mut tags = GeoTags::default();
+        assert!(tags.is_empty());
+
+        tags.model_tiepoints = Some(vec![0.0, 0.0, 0.0, 500000.0, 4000000.0, 0.0]);
+        tags.model_transformation = Some(vec![
+            30.0, 0.0, 0.0, 500000.0,
+            0.0, -30.0, 0.0, 4000000.0,
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0
+        ]);
+
+        let cropped = tags.crop(10, 20);
+        assert_eq!(cropped.model_tiepoints.unwrap()[..2], [-10.0, -20.0]);
+
+        let m = cropped.model_transformation.unwrap();
+        assert_eq!((m[3], m[7]), (500300.0, 3999400.0));
+    }
+}
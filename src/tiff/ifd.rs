@@ -5,7 +5,7 @@ use std::collections::{HashMap};
 
 use super::stream::{ByteOrder, SmartReader, EndianReader};
 
-use self::Value::{Unsigned, List};
+use self::Value::{Unsigned, Rational, Double, Ascii, List};
 
 macro_rules! tags {
     {$(
@@ -72,6 +72,13 @@ tags!{
     YResolution 283;
     // Advanced tags
     Predictor 317;
+    // GeoTIFF tags
+    ModelPixelScale 33550;
+    ModelTiepoint 33922;
+    ModelTransformation 34264;
+    GeoKeyDirectory 34735;
+    GeoDoubleParams 34736;
+    GeoAsciiParams 34737;
 }
 
 enum_from_primitive! {
@@ -82,6 +89,7 @@ pub enum Type {
     SHORT = 3,
     LONG = 4,
     RATIONAL = 5,
+    DOUBLE = 12,
 }
 }
 
@@ -91,6 +99,9 @@ pub enum Type {
 pub enum Value {
     //Signed(i32),
     Unsigned(u32),
+    Rational(u32, u32),
+    Double(f64),
+    Ascii(String),
     List(Vec<Value>)
 }
 
@@ -113,7 +124,45 @@ impl Value {
                 Ok(new_vec)
             },
             Unsigned(val) => Ok(vec![val]),
-            //_ => Err(::image::FormatError("Tag data malformed.".to_string()))
+            val => Err(::image::ImageError::FormatError(format!(
+                "Expected unsigned integers, {:?} found.", val
+            )))
+        }
+    }
+    pub fn as_f64_vec(self) -> ::image::ImageResult<Vec<f64>> {
+        match self {
+            List(vec) => {
+                let mut new_vec = Vec::with_capacity(vec.len());
+                for v in vec.into_iter() {
+                    match v {
+                        Double(val) => new_vec.push(val),
+                        val => return Err(::image::ImageError::FormatError(format!(
+                            "Expected double, {:?} found.", val
+                        )))
+                    }
+                }
+                Ok(new_vec)
+            },
+            Double(val) => Ok(vec![val]),
+            val => Err(::image::ImageError::FormatError(format!(
+                "Expected doubles, {:?} found.", val
+            )))
+        }
+    }
+    pub fn as_rational(self) -> ::image::ImageResult<(u32, u32)> {
+        match self {
+            Rational(n, d) => Ok((n, d)),
+            val => Err(::image::ImageError::FormatError(format!(
+                "Expected rational, {:?} found.", val
+            )))
+        }
+    }
+    pub fn as_string(self) -> ::image::ImageResult<String> {
+        match self {
+            Ascii(val) => Ok(val),
+            val => Err(::image::ImageError::FormatError(format!(
+                "Expected ASCII string, {:?} found.", val
+            )))
         }
     }
 }
@@ -167,8 +216,8 @@ impl Entry {
                 ]))
             },
             (Type::SHORT, n) => {
-                let mut v = Vec::with_capacity(n as usize);
                 try!(decoder.goto_offset(try!(self.r(bo).read_u32())));
+                let mut v = Vec::with_capacity(try!(decoder.list_capacity(n, 2)));
                 for _ in 0 .. n {
                     v.push(Unsigned(try!(decoder.read_short()) as u32))
                 }
@@ -176,13 +225,50 @@ impl Entry {
             },
             (Type::LONG, 1) => Ok(Unsigned(try!(self.r(bo).read_u32()))),
             (Type::LONG, n) => {
-                let mut v = Vec::with_capacity(n as usize);
                 try!(decoder.goto_offset(try!(self.r(bo).read_u32())));
+                let mut v = Vec::with_capacity(try!(decoder.list_capacity(n, 4)));
                 for _ in 0 .. n {
                     v.push(Unsigned(try!(decoder.read_long())))
                 }
                 Ok(List(v))
             }
+            (Type::RATIONAL, 1) => {
+                try!(decoder.goto_offset(try!(self.r(bo).read_u32())));
+                Ok(Rational(try!(decoder.read_long()), try!(decoder.read_long())))
+            }
+            (Type::RATIONAL, n) => {
+                try!(decoder.goto_offset(try!(self.r(bo).read_u32())));
+                let mut v = Vec::with_capacity(try!(decoder.list_capacity(n, 8)));
+                for _ in 0 .. n {
+                    v.push(Rational(try!(decoder.read_long()), try!(decoder.read_long())))
+                }
+                Ok(List(v))
+            }
+            (Type::DOUBLE, n) => {
+                try!(decoder.goto_offset(try!(self.r(bo).read_u32())));
+                let mut v = Vec::with_capacity(try!(decoder.list_capacity(n, 8)));
+                for _ in 0 .. n {
+                    v.push(Double(try!(decoder.read_double())))
+                }
+                Ok(List(v))
+            }
+            (Type::ASCII, n) => {
+                let mut bytes = Vec::new();
+                if n <= 4 {
+                    bytes.extend(self.offset[..n as usize].iter().cloned());
+                } else {
+                    try!(decoder.goto_offset(try!(self.r(bo).read_u32())));
+                    bytes.reserve(try!(decoder.list_capacity(n, 1)));
+                    for _ in 0 .. n {
+                        bytes.push(try!(decoder.read_byte()))
+                    }
+                }
+                // The string is terminated by a NUL
+                while bytes.last() == Some(&0) {
+                    bytes.pop();
+                }
+                Ok(Ascii(String::from_utf8_lossy(&bytes).into_owned()))
+            }
             _ => Err(::image::ImageError::UnsupportedError("Unsupported data type.".to_string()))
         }
     }
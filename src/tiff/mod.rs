@@ -8,8 +8,12 @@
 //!
 
 pub use self::decoder::TIFFDecoder;
+pub use self::encoder::TiledTIFFEncoder;
+pub use self::geo::GeoTags;
 pub use self::stream::ByteOrder;
 
 mod decoder;
+mod encoder;
+mod geo;
 mod ifd;
 mod stream;
\ No newline at end of file
@@ -0,0 +1,200 @@
+//! Tracking of modified image regions
+use std::cmp;
+use std::ops::{Deref, DerefMut};
+
+use buffer::{ImageBuffer, Pixel};
+use image::GenericImage;
+use math::Rect;
+
+/// An image wrapper that records which tiles were written to
+///
+/// The image is divided into square tiles of `tile_size` pixels. Every write
+/// through `put_pixel`, `get_pixel_mut`, `blend_pixel` or `row_mut` marks the
+/// affected tiles as dirty, such that incremental encoders or screen sharing
+/// tools only need to process the regions returned by `dirty_tiles`.
+pub struct TrackedImage<I> {
+    image: I,
+    tile_size: u32,
+    tiles_per_row: u32,
+    dirty: Vec<bool>,
+}
+
+impl<I: GenericImage> TrackedImage<I> {
+    /// Wraps `image`, tracking writes in tiles of `tile_size * tile_size` pixels.
+    /// Initially no tile is dirty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tile_size` is zero.
+    pub fn new(image: I, tile_size: u32) -> TrackedImage<I> {
+        assert!(tile_size > 0, "tile size must not be zero");
+
+        let (width, height) = image.dimensions();
+        let tiles_per_row = (width + tile_size - 1) / tile_size;
+        let tiles_per_column = (height + tile_size - 1) / tile_size;
+
+        TrackedImage {
+            image: image,
+            tile_size: tile_size,
+            tiles_per_row: tiles_per_row,
+            dirty: vec![false; (tiles_per_row * tiles_per_column) as usize],
+        }
+    }
+
+    /// The size of the tiles in pixels
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    /// Returns a reference to the wrapped image
+    pub fn inner(&self) -> &I {
+        &self.image
+    }
+
+    /// Returns the wrapped image
+    pub fn into_inner(self) -> I {
+        self.image
+    }
+
+    /// Returns true if any tile was written to since the last call to `clear_dirty`
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.iter().any(|&d| d)
+    }
+
+    /// Returns the regions of all dirty tiles in row-major order.
+    /// Tiles at the right and bottom edges are clipped to the image.
+    pub fn dirty_tiles(&self) -> Vec<Rect> {
+        let (width, height) = self.image.dimensions();
+        let size = self.tile_size;
+
+        self.dirty.iter().enumerate().filter(|&(_, &d)| d).map(|(i, _)| {
+            let x = (i as u32 % self.tiles_per_row) * size;
+            let y = (i as u32 / self.tiles_per_row) * size;
+
+            Rect::new(x, y, cmp::min(size, width - x), cmp::min(size, height - y))
+        }).collect()
+    }
+
+    /// Marks all tiles as clean
+    pub fn clear_dirty(&mut self) {
+        for d in self.dirty.iter_mut() {
+            *d = false;
+        }
+    }
+
+    /// Marks all tiles overlapping `rect` as dirty. Use this after modifying
+    /// the image by other means than the methods of this wrapper.
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        if rect.width == 0 || rect.height == 0 || self.dirty.is_empty() {
+            return
+        }
+
+        let size = self.tile_size;
+        let tiles_per_column = self.dirty.len() as u32 / self.tiles_per_row;
+        let x1 = cmp::min((rect.x + rect.width - 1) / size, self.tiles_per_row - 1);
+        let y1 = cmp::min((rect.y + rect.height - 1) / size, tiles_per_column - 1);
+
+        for ty in (rect.y / size..y1 + 1) {
+            for tx in (rect.x / size..x1 + 1) {
+                self.dirty[(ty * self.tiles_per_row + tx) as usize] = true;
+            }
+        }
+    }
+
+    fn mark_pixel(&mut self, x: u32, y: u32) {
+        let index = (y / self.tile_size) * self.tiles_per_row + x / self.tile_size;
+        self.dirty[index as usize] = true;
+    }
+}
+
+impl<P, Container> TrackedImage<ImageBuffer<P, Container>>
+where P: Pixel + 'static,
+      P::Subpixel: 'static,
+      Container: Deref<Target=[P::Subpixel]> + DerefMut {
+
+    /// Returns the subpixels of row `y` and marks the tiles it crosses as dirty
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y` is out of bounds.
+    pub fn row_mut(&mut self, y: u32) -> &mut [P::Subpixel] {
+        let width = self.image.width();
+        assert!(y < self.image.height(), "row {} is out of bounds", y);
+        self.mark_dirty(Rect::new(0, y, width, 1));
+
+        let len = width as usize * <P as Pixel>::channel_count() as usize;
+        let start = y as usize * len;
+        &mut self.image.deref_mut()[start..start + len]
+    }
+}
+
+#[allow(deprecated)]
+impl<I: GenericImage> GenericImage for TrackedImage<I> {
+    type Pixel = I::Pixel;
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    fn bounds(&self) -> (u32, u32, u32, u32) {
+        self.image.bounds()
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> I::Pixel {
+        self.image.get_pixel(x, y)
+    }
+
+    unsafe fn unsafe_get_pixel(&self, x: u32, y: u32) -> I::Pixel {
+        self.image.unsafe_get_pixel(x, y)
+    }
+
+    fn get_pixel_mut(&mut self, x: u32, y: u32) -> &mut I::Pixel {
+        self.mark_pixel(x, y);
+        self.image.get_pixel_mut(x, y)
+    }
+
+    fn put_pixel(&mut self, x: u32, y: u32, pixel: I::Pixel) {
+        self.image.put_pixel(x, y, pixel);
+        self.mark_pixel(x, y);
+    }
+
+    unsafe fn unsafe_put_pixel(&mut self, x: u32, y: u32, pixel: I::Pixel) {
+        self.image.unsafe_put_pixel(x, y, pixel);
+        self.mark_pixel(x, y);
+    }
+
+    /// DEPRECATED: This method will be removed. Blend the pixel directly instead.
+    fn blend_pixel(&mut self, x: u32, y: u32, pixel: I::Pixel) {
+        self.image.blend_pixel(x, y, pixel);
+        self.mark_pixel(x, y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrackedImage;
+    use buffer::{ImageBuffer, GrayImage};
+    use color::Luma;
+    use image::GenericImage;
+    use math::Rect;
+
+    #[test]
+    fn test_dirty_tiles() {
+        let image: GrayImage = ImageBuffer::new(10, 10);
+        let mut tracked = TrackedImage::new(image, 4);
+        assert!(!tracked.is_dirty());
+
+        tracked.put_pixel(9, 1, Luma([1]));
+        tracked.row_mut(5)[0] = 1;
+
+        assert_eq!(tracked.dirty_tiles(), vec![
+            Rect::new(8, 0, 2, 4),
+            Rect::new(0, 4, 4, 4),
+            Rect::new(4, 4, 4, 4),
+            Rect::new(8, 4, 2, 4),
+        ]);
+
+        tracked.clear_dirty();
+        assert!(tracked.dirty_tiles().is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! Zoomable views of large images
+use std::cmp;
+use std::collections::HashMap;
+
+use buffer::{ImageBuffer, Pixel, RgbaImage};
+use color::Rgba;
+use image::GenericImage;
+
+/// A display-sized window into an image at an arbitrary zoom and position,
+/// as shown by an image viewer
+///
+/// The source is resampled in square tiles of the zoomed image, which are
+/// cached, thus panning only resamples the tiles that come into view.
+/// Magnified sources are interpolated bilinearly, reduced ones averaged
+/// over the area each output pixel covers. The cache is cleared when the
+/// zoom changes.
+pub struct Viewport<I> {
+    source: I,
+    width: u32,
+    height: u32,
+    zoom: f32,
+    center: (f32, f32),
+    tile_size: u32,
+    capacity: usize,
+    // The cached tiles with the time of their last use
+    tiles: HashMap<(u32, u32), (RgbaImage, u64)>,
+    clock: u64,
+}
+
+impl<I> Viewport<I>
+where I: GenericImage, I::Pixel: Pixel<Subpixel=u8> {
+    /// Creates a view of ```width``` by ```height``` pixels, which shows
+    /// ```source``` at its original size centered
+    pub fn new(source: I, width: u32, height: u32) -> Viewport<I> {
+        let (w, h) = source.dimensions();
+
+        Viewport {
+            source: source,
+            width: width,
+            height: height,
+            zoom: 1.0,
+            center: (w as f32 / 2.0, h as f32 / 2.0),
+            tile_size: 256,
+            capacity: 64,
+            tiles: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Returns a reference to the source image
+    pub fn inner(&self) -> &I {
+        &self.source
+    }
+
+    /// Returns the source image
+    pub fn into_inner(self) -> I {
+        self.source
+    }
+
+    /// The size of the view in pixels
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Changes the size of the view, keeping its center
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    /// The number of view pixels per source pixel
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Sets the number of view pixels per source pixel, keeping the center
+    ///
+    /// # Panics
+    ///
+    /// Panics if `zoom` is not positive.
+    pub fn set_zoom(&mut self, zoom: f32) {
+        assert!(zoom > 0.0, "zoom must be positive");
+
+        if zoom != self.zoom {
+            self.zoom = zoom;
+            self.tiles.clear();
+        }
+    }
+
+    /// Multiplies the zoom by ```factor```, keeping the source point under
+    /// the view pixel (```x```, ```y```) in place, like zooming with the
+    /// mouse wheel does
+    pub fn zoom_at(&mut self, factor: f32, x: f32, y: f32) {
+        let (sx, sy) = self.view_to_source(x, y);
+        self.set_zoom(self.zoom * factor);
+
+        let (cx, cy) = (self.width as f32 / 2.0, self.height as f32 / 2.0);
+        self.center = (sx - (x - cx) / self.zoom, sy - (y - cy) / self.zoom);
+    }
+
+    /// The source coordinates shown at the center of the view
+    pub fn center(&self) -> (f32, f32) {
+        self.center
+    }
+
+    /// Shows the source coordinates (```x```, ```y```) at the center of the view
+    pub fn set_center(&mut self, x: f32, y: f32) {
+        self.center = (x, y);
+    }
+
+    /// Moves the view by ```dx``` and ```dy``` view pixels
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        self.center = (self.center.0 + dx / self.zoom, self.center.1 + dy / self.zoom);
+    }
+
+    /// Returns the source coordinates shown at the view coordinates
+    /// (```x```, ```y```)
+    pub fn view_to_source(&self, x: f32, y: f32) -> (f32, f32) {
+        let (ox, oy) = self.origin();
+        ((ox as f32 + x) / self.zoom, (oy as f32 + y) / self.zoom)
+    }
+
+    /// Sets the size of the cached tiles, which clears the cache
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tile_size` is zero.
+    pub fn set_tile_size(&mut self, tile_size: u32) {
+        assert!(tile_size > 0, "tile size must not be zero");
+        self.tile_size = tile_size;
+        self.tiles.clear();
+    }
+
+    /// Sets the maximum number of cached tiles. The least recently used
+    /// tiles are dropped first. Defaults to 64.
+    pub fn set_cache_capacity(&mut self, tiles: usize) {
+        self.capacity = tiles;
+        self.evict();
+    }
+
+    /// The number of tiles in the cache
+    pub fn cached_tiles(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// Renders the view. Pixels outside of the source are transparent.
+    pub fn render(&mut self) -> RgbaImage {
+        let (ox, oy) = self.origin();
+        let (zw, zh) = self.zoomed_dimensions();
+        let size = self.tile_size as i64;
+        let mut out = ImageBuffer::new(self.width, self.height);
+
+        // The part of the view covered by the zoomed source
+        let x0 = cmp::max(ox, 0);
+        let y0 = cmp::max(oy, 0);
+        let x1 = cmp::min(ox + self.width as i64, zw as i64);
+        let y1 = cmp::min(oy + self.height as i64, zh as i64);
+
+        if x0 >= x1 || y0 >= y1 {
+            return out
+        }
+
+        for ty in (y0 / size..(y1 - 1) / size + 1) {
+            for tx in (x0 / size..(x1 - 1) / size + 1) {
+                let key = (tx as u32, ty as u32);
+                self.clock += 1;
+
+                if !self.tiles.contains_key(&key) {
+                    let tile = self.resample_tile(key.0, key.1);
+                    self.tiles.insert(key, (tile, 0));
+                }
+
+                let entry = self.tiles.get_mut(&key).unwrap();
+                entry.1 = self.clock;
+                let tile = &entry.0;
+
+                // Copy the visible part of the tile
+                let (left, top) = (tx * size, ty * size);
+                for y in (cmp::max(top, y0)..cmp::min(top + tile.height() as i64, y1)) {
+                    for x in (cmp::max(left, x0)..cmp::min(left + tile.width() as i64, x1)) {
+                        let p = *tile.get_pixel((x - left) as u32, (y - top) as u32);
+                        out.put_pixel((x - ox) as u32, (y - oy) as u32, p);
+                    }
+                }
+            }
+        }
+
+        self.evict();
+        out
+    }
+
+    // The position of the top left view pixel in the zoomed source
+    fn origin(&self) -> (i64, i64) {
+        let x = self.center.0 * self.zoom - self.width as f32 / 2.0;
+        let y = self.center.1 * self.zoom - self.height as f32 / 2.0;
+        (x.floor() as i64, y.floor() as i64)
+    }
+
+    fn zoomed_dimensions(&self) -> (u32, u32) {
+        let (w, h) = self.source.dimensions();
+        ((w as f32 * self.zoom).ceil() as u32, (h as f32 * self.zoom).ceil() as u32)
+    }
+
+    // Drops the least recently used tiles beyond the capacity
+    fn evict(&mut self) {
+        while self.tiles.len() > self.capacity {
+            let oldest = *self.tiles.iter().min_by_key(|&(_, &(_, used))| used).unwrap().0;
+            self.tiles.remove(&oldest);
+        }
+    }
+
+    // Resamples tile (tx, ty) of the zoomed source
+    fn resample_tile(&self, tx: u32, ty: u32) -> RgbaImage {
+        let (zw, zh) = self.zoomed_dimensions();
+        let (left, top) = (tx * self.tile_size, ty * self.tile_size);
+        let width = cmp::min(self.tile_size, zw - left);
+        let height = cmp::min(self.tile_size, zh - top);
+        let (sw, sh) = self.source.dimensions();
+
+        let columns = (left..left + width).map(|x| taps(x, self.zoom, sw)).collect::<Vec<_>>();
+        let rows = (top..top + height).map(|y| taps(y, self.zoom, sh)).collect::<Vec<_>>();
+
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let mut sum = [0f32; 4];
+
+            for &(sy, wy) in rows[y as usize].iter() {
+                for &(sx, wx) in columns[x as usize].iter() {
+                    let p = self.source.get_pixel(sx, sy).to_rgba();
+                    let w = wx * wy;
+
+                    for c in (0..4) {
+                        sum[c] += p[c] as f32 * w;
+                    }
+                }
+            }
+
+            let mut pixel = [0u8; 4];
+            for c in (0..4) {
+                pixel[c] = sum[c].round().max(0.0).min(255.0) as u8;
+            }
+
+            Rgba(pixel)
+        })
+    }
+}
+
+// The source samples and their weights that make up zoomed sample `x`
+fn taps(x: u32, zoom: f32, len: u32) -> Vec<(u32, f32)> {
+    let last = len - 1;
+
+    if zoom >= 1.0 {
+        // Linear interpolation between the neighbouring sample centers
+        let u = ((x as f32 + 0.5) / zoom - 0.5).max(0.0).min(last as f32);
+        let i = u.floor() as u32;
+        let f = u - i as f32;
+
+        if f == 0.0 || i == last {
+            vec![(i, 1.0)]
+        } else {
+            vec![(i, 1.0 - f), (i + 1, f)]
+        }
+    } else {
+        // The average over the samples covered by the footprint of x
+        let x0 = x as f32 / zoom;
+        let x1 = ((x + 1) as f32 / zoom).min(len as f32);
+        let first = cmp::min(x0.floor() as u32, last);
+        let end = cmp::max(x1.ceil() as u32, first + 1);
+
+        (first..end).map(|i| {
+            let overlap = (x1.min((i + 1) as f32) - x0.max(i as f32)).max(0.0);
+            (i, overlap / (x1 - x0))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Viewport;
+    use buffer::{GrayImage, ImageBuffer};
+    use color::{Luma, Rgba};
+
+    fn gradient() -> GrayImage {
+        ImageBuffer::from_fn(40, 30, |x, y| Luma([(x * 4 + y) as u8]))
+    }
+
+    #[test]
+    fn test_original_size() {
+        let mut view = Viewport::new(gradient(), 20, 10);
+        view.set_tile_size(8);
+        view.set_center(10.0, 5.0);
+
+        let out = view.render();
+        for (x, y, p) in out.enumerate_pixels() {
+            let v = (x * 4 + y) as u8;
+            assert_eq!(p, &Rgba([v, v, v, 255]));
+        }
+        assert_eq!(view.cached_tiles(), 3 * 2);
+
+        // Panning within the cached tiles resamples nothing
+        view.pan(-3.0, -2.0);
+        let out = view.render();
+        assert_eq!(view.cached_tiles(), 3 * 2);
+        assert_eq!(out.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+        assert_eq!(out.get_pixel(3, 2), &Rgba([0, 0, 0, 255]));
+
+        view.set_cache_capacity(4);
+        assert_eq!(view.cached_tiles(), 4);
+    }
+
+    #[test]
+    fn test_zoom() {
+        let mut view = Viewport::new(gradient(), 16, 16);
+
+        // Reducing to a quarter averages 4x4 blocks
+        view.set_zoom(0.25);
+        view.set_center(8.0 * 4.0, 8.0 * 4.0);
+        let out = view.render();
+        let average = (0..4).map(|y| (0..4).map(|x| x * 4 + y).sum::<u32>()).sum::<u32>() as f32 / 16.0;
+        assert_eq!(out.get_pixel(0, 0)[0], average.round() as u8);
+        assert_eq!(out.get_pixel(10, 0)[3], 0);
+
+        // Magnifying interpolates between the samples
+        view.set_zoom(2.0);
+        view.set_center(4.0, 4.0);
+        assert_eq!(view.cached_tiles(), 0);
+        let out = view.render();
+        assert_eq!(out.get_pixel(0, 0)[0], 0);
+        assert_eq!(out.get_pixel(2, 0)[0], 3);
+        assert_eq!(out.get_pixel(3, 0)[0], 5);
+
+        // The point under the cursor stays in place
+        let before = view.view_to_source(5.0, 7.0);
+        view.zoom_at(1.5, 5.0, 7.0);
+        let after = view.view_to_source(5.0, 7.0);
+        assert!((before.0 - after.0).abs() < 1.0 && (before.1 - after.1).abs() < 1.0);
+    }
+}
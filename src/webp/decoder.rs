@@ -8,6 +8,10 @@ use image::ImageResult;
 use image::ImageDecoder;
 
 use color;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+#[cfg(feature = "mmap")]
+use mmap::{self, MappedFile};
 
 use super::vp8::Frame;
 use super::vp8::VP8Decoder;
@@ -93,6 +97,20 @@ impl<R: Read> WebpDecoder<R> {
     }
 }
 
+#[cfg(feature = "mmap")]
+impl WebpDecoder<MappedFile> {
+    /// Create a new decoder that decodes the file at ```path```, mapped
+    /// into memory
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to or truncated, by this or any other
+    /// process, while the decoder exists. See the ```mmap``` module.
+    pub unsafe fn from_path<P: AsRef<Path>>(path: P) -> ImageResult<WebpDecoder<MappedFile>> {
+        Ok(WebpDecoder::new(try!(mmap::map_file(path))))
+    }
+}
+
 impl<R: Read> ImageDecoder for WebpDecoder<R> {
     fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
         let _ = try!(self.read_metadata());
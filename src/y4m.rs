@@ -0,0 +1,835 @@
+//! YUV4MPEG2 streams
+//!
+//! Video encoders and their test harnesses exchange raw frames as Y4M: a
+//! text header with the dimensions, frame rate and chroma subsampling,
+//! followed by frames of 8 bit Y, Cb and Cr planes. A `Writer` converts
+//! images to such frames, a `Reader` converts them back.
+//!
+//! The header records the chroma siting through the subsampling, e.g.
+//! ```420jpeg``` or ```420mpeg2```, and the range through the common
+//! ```XCOLORRANGE``` extension. The stream has no tag for the matrix of the
+//! conversion, which is part of the `Header` nonetheless and is BT.601 for
+//! streams that are read, as most tools assume.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use image::{RgbImage, ImageRgb8};
+//! use image::y4m::{Header, Matrix, Reader, Writer};
+//!
+//! let mut header = Header::new(64, 48);
+//! header.matrix = Matrix::Bt709;
+//!
+//! let mut writer = Writer::new(File::create("clip.y4m").unwrap(), header).unwrap();
+//! for _ in 0..10 {
+//!     writer.write_image(&ImageRgb8(RgbImage::new(64, 48).into())).unwrap();
+//! }
+//!
+//! let frames = Reader::new(File::open("clip.y4m").unwrap()).unwrap().frames().unwrap();
+//! ```
+
+use std::io::{self, Read, Write};
+
+use num::rational::Ratio;
+
+use animation::{Frame, Frames};
+use buffer::{ImageBuffer, RgbImage};
+use dynimage::DynamicImage;
+use image::{GenericImage, ImageError, ImageResult};
+
+// The longest header line that is accepted
+const MAX_LINE: usize = 4096;
+
+// The default maximum size of a frame read, enough for 8K frames with 4:4:4
+// chroma
+const MAX_FRAME_SIZE: usize = 256 << 20;
+
+/// The subsampling and siting of the chroma planes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Subsampling {
+    /// 4:2:0 with the chroma centered between the luma samples, as in JPEG
+    Yuv420Jpeg,
+    /// 4:2:0 with the chroma horizontally at the left luma sample and
+    /// vertically centered, as in MPEG-2
+    Yuv420Mpeg2,
+    /// 4:2:0 with the chroma at the top left luma sample, as in PAL DV
+    Yuv420Paldv,
+    /// 4:2:2 with the chroma at the left luma sample
+    Yuv422,
+    /// Chroma at every luma sample
+    Yuv444,
+    /// Only the luma plane
+    Mono,
+}
+
+impl Subsampling {
+    // The value of the `C` tag
+    fn tag(self) -> &'static str {
+        match self {
+            Subsampling::Yuv420Jpeg => "420jpeg",
+            Subsampling::Yuv420Mpeg2 => "420mpeg2",
+            Subsampling::Yuv420Paldv => "420paldv",
+            Subsampling::Yuv422 => "422",
+            Subsampling::Yuv444 => "444",
+            Subsampling::Mono => "mono",
+        }
+    }
+
+    fn from_tag(tag: &str) -> ImageResult<Subsampling> {
+        Ok(match tag {
+            "420jpeg" | "420" => Subsampling::Yuv420Jpeg,
+            "420mpeg2" => Subsampling::Yuv420Mpeg2,
+            "420paldv" => Subsampling::Yuv420Paldv,
+            "422" => Subsampling::Yuv422,
+            "444" => Subsampling::Yuv444,
+            "mono" => Subsampling::Mono,
+            _ => return Err(ImageError::UnsupportedError(
+                format!("The YUV4MPEG2 chroma format {} is not supported.", tag)))
+        })
+    }
+
+    // The horizontal and vertical subsampling factors
+    fn factors(self) -> (usize, usize) {
+        match self {
+            Subsampling::Yuv420Jpeg | Subsampling::Yuv420Mpeg2 | Subsampling::Yuv420Paldv => (2, 2),
+            Subsampling::Yuv422 => (2, 1),
+            Subsampling::Yuv444 | Subsampling::Mono => (1, 1),
+        }
+    }
+
+    // The position of the first chroma sample in luma samples
+    fn siting(self) -> (f32, f32) {
+        match self {
+            Subsampling::Yuv420Jpeg => (0.5, 0.5),
+            Subsampling::Yuv420Mpeg2 => (0.0, 0.5),
+            _ => (0.0, 0.0),
+        }
+    }
+}
+
+/// The range of the samples
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Range {
+    /// Luma from 16 to 235 and chroma from 16 to 240, as in broadcast video
+    Limited,
+    /// Luma and chroma from 0 to 255, as in JPEG
+    Full,
+}
+
+/// The matrix of the conversion between RGB and YCbCr
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Matrix {
+    /// ITU-R BT.601, for standard definition video
+    Bt601,
+    /// ITU-R BT.709, for high definition video
+    Bt709,
+}
+
+impl Matrix {
+    // The weights of red and blue in luma
+    fn weights(self) -> (f32, f32) {
+        match self {
+            Matrix::Bt601 => (0.299, 0.114),
+            Matrix::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+/// The interlacing of the frames
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Interlace {
+    /// Progressive frames
+    Progressive,
+    /// Interlaced frames with the top field first
+    TopFirst,
+    /// Interlaced frames with the bottom field first
+    BottomFirst,
+    /// Interlacing that differs between frames
+    Mixed,
+}
+
+/// The properties of a stream
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The width of the frames
+    pub width: u32,
+    /// The height of the frames
+    pub height: u32,
+    /// The frames per second as numerator and denominator
+    pub frame_rate: (u32, u32),
+    /// The aspect ratio of the pixels as numerator and denominator, 0:0 if
+    /// unknown
+    pub pixel_aspect: (u32, u32),
+    /// The interlacing of the frames
+    pub interlace: Interlace,
+    /// The subsampling and siting of the chroma
+    pub subsampling: Subsampling,
+    /// The range of the samples
+    pub range: Range,
+    /// The matrix of the conversion from and to RGB, which is not stored in
+    /// the stream
+    pub matrix: Matrix,
+}
+
+impl Header {
+    /// The header of progressive frames with the dimensions ```width``` and
+    /// ```height``` at 25 frames per second with square pixels, 4:2:0 JPEG
+    /// subsampling, limited range and the BT.601 matrix
+    pub fn new(width: u32, height: u32) -> Header {
+        Header {
+            width: width,
+            height: height,
+            frame_rate: (25, 1),
+            pixel_aspect: (1, 1),
+            interlace: Interlace::Progressive,
+            subsampling: Subsampling::Yuv420Jpeg,
+            range: Range::Limited,
+            matrix: Matrix::Bt601,
+        }
+    }
+
+    /// The dimensions of the chroma planes, 0x0 for monochrome streams
+    pub fn chroma_dimensions(&self) -> (u32, u32) {
+        if self.subsampling == Subsampling::Mono {
+            return (0, 0)
+        }
+
+        let (fx, fy) = self.subsampling.factors();
+        (((self.width as u64 + fx as u64 - 1) / fx as u64) as u32,
+         ((self.height as u64 + fy as u64 - 1) / fy as u64) as u32)
+    }
+
+    // The number of samples of the luma plane and of each chroma plane, or
+    // `None` if they do not fit in a `usize`
+    fn plane_sizes(&self) -> Option<(usize, usize)> {
+        let (cw, ch) = self.chroma_dimensions();
+        let luma = (self.width as usize).checked_mul(self.height as usize);
+        let chroma = (cw as usize).checked_mul(ch as usize);
+
+        match (luma, chroma) {
+            (Some(luma), Some(chroma)) => Some((luma, chroma)),
+            _ => None
+        }
+    }
+
+    fn parse(line: &str) -> ImageResult<Header> {
+        let mut params = line.split(' ');
+        if params.next() != Some("YUV4MPEG2") {
+            return Err(ImageError::FormatError("Not a YUV4MPEG2 stream".to_string()))
+        }
+
+        let mut header = Header::new(0, 0);
+        let mut dimensions = (None, None);
+
+        for param in params.filter(|p| !p.is_empty()) {
+            let (tag, value) = param.split_at(param.chars().next().unwrap().len_utf8());
+            match tag {
+                "W" => dimensions.0 = Some(try!(parse_number(value))),
+                "H" => dimensions.1 = Some(try!(parse_number(value))),
+                "F" => header.frame_rate = try!(parse_ratio(value)),
+                "A" => header.pixel_aspect = try!(parse_ratio(value)),
+                "C" => header.subsampling = try!(Subsampling::from_tag(value)),
+                "I" => header.interlace = match value {
+                    "p" | "?" => Interlace::Progressive,
+                    "t" => Interlace::TopFirst,
+                    "b" => Interlace::BottomFirst,
+                    "m" => Interlace::Mixed,
+                    _ => return Err(invalid_param(param))
+                },
+                "X" if value.starts_with("COLORRANGE=") => header.range = match &value[11..] {
+                    "LIMITED" => Range::Limited,
+                    "FULL" => Range::Full,
+                    _ => return Err(invalid_param(param))
+                },
+                // Other extensions are to be ignored
+                "X" => (),
+                _ => return Err(invalid_param(param))
+            }
+        }
+
+        match dimensions {
+            (Some(width), Some(height)) if width > 0 && height > 0 => {
+                header.width = width;
+                header.height = height;
+            }
+            _ => return Err(ImageError::DimensionError)
+        }
+        if header.frame_rate.0 == 0 || header.frame_rate.1 == 0 {
+            return Err(invalid_param(&format!("F{}:{}", header.frame_rate.0, header.frame_rate.1)))
+        }
+
+        Ok(header)
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let interlace = match self.interlace {
+            Interlace::Progressive => 'p',
+            Interlace::TopFirst => 't',
+            Interlace::BottomFirst => 'b',
+            Interlace::Mixed => 'm',
+        };
+        let range = match self.range {
+            Range::Limited => "LIMITED",
+            Range::Full => "FULL",
+        };
+
+        write!(w, "YUV4MPEG2 W{} H{} F{}:{} I{} A{}:{} C{} XCOLORRANGE={}\n",
+               self.width, self.height, self.frame_rate.0, self.frame_rate.1, interlace,
+               self.pixel_aspect.0, self.pixel_aspect.1, self.subsampling.tag(), range)
+    }
+
+    // The duration of a frame in seconds
+    fn delay(&self) -> Ratio<u16> {
+        let (n, d) = self.frame_rate;
+        let delay = Ratio::new(d as u64, n as u64);
+        let max = u16::max_value() as u64;
+
+        if *delay.numer() <= max && *delay.denom() <= max {
+            Ratio::new(*delay.numer() as u16, *delay.denom() as u16)
+        } else {
+            // Rounded to milliseconds
+            let ms = (1000 * d as u64 + n as u64 / 2) / n as u64;
+            Ratio::new(ms.min(max) as u16, 1000)
+        }
+    }
+}
+
+fn parse_number(s: &str) -> ImageResult<u32> {
+    s.parse().map_err(|_| invalid_param(s))
+}
+
+fn parse_ratio(s: &str) -> ImageResult<(u32, u32)> {
+    let mut parts = s.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(n), Some(d)) => Ok((try!(parse_number(n)), try!(parse_number(d)))),
+        _ => Err(invalid_param(s))
+    }
+}
+
+fn invalid_param(param: &str) -> ImageError {
+    ImageError::FormatError(format!("Invalid YUV4MPEG2 parameter {:?}", param))
+}
+
+/// The planes of a frame, with the dimensions of the luma and chroma
+/// planes of its `Header`. The chroma planes of monochrome frames are empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Planes {
+    /// The luma plane
+    pub y: Vec<u8>,
+    /// The blue difference plane
+    pub cb: Vec<u8>,
+    /// The red difference plane
+    pub cr: Vec<u8>,
+}
+
+impl Planes {
+    /// Converts ```image``` to the planes of ```header```, which must have
+    /// the same dimensions. The chroma is filtered at the sites of the
+    /// subsampling.
+    pub fn from_rgb(header: &Header, image: &RgbImage) -> Planes {
+        assert_eq!(image.dimensions(), (header.width, header.height));
+
+        let (width, height) = (header.width as usize, header.height as usize);
+        let (kr, kb) = header.matrix.weights();
+        let mut planes = Planes { y: Vec::with_capacity(width * height), cb: Vec::new(), cr: Vec::new() };
+        let mut cb = Vec::with_capacity(width * height);
+        let mut cr = Vec::with_capacity(width * height);
+
+        for p in image.chunks(3) {
+            let (r, g, b) = (p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0);
+            let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+
+            planes.y.push(quantize_luma(y, header.range));
+            cb.push((b - y) / (2.0 * (1.0 - kb)));
+            cr.push((r - y) / (2.0 * (1.0 - kr)));
+        }
+
+        if header.subsampling != Subsampling::Mono {
+            let (fx, fy) = header.subsampling.factors();
+            let (cw, ch) = header.chroma_dimensions();
+            let (cw, ch) = (cw as usize, ch as usize);
+            let (sx, sy) = header.subsampling.siting();
+            let columns: Vec<_> = (0..cw).map(|x| taps(x, fx, sx, width)).collect();
+            let rows: Vec<_> = (0..ch).map(|y| taps(y, fy, sy, height)).collect();
+
+            planes.cb = downsample(&cb, width, &columns, &rows, header.range);
+            planes.cr = downsample(&cr, width, &columns, &rows, header.range);
+        }
+
+        planes
+    }
+
+    /// Converts the planes of ```header``` to RGB. The chroma is
+    /// interpolated linearly between its sites.
+    ///
+    /// Returns a `DimensionError` if the lengths of the planes differ from
+    /// the dimensions of ```header```.
+    pub fn to_rgb(&self, header: &Header) -> ImageResult<RgbImage> {
+        if !self.fit(header) {
+            return Err(ImageError::DimensionError)
+        }
+
+        let (width, height) = (header.width as usize, header.height as usize);
+        let (kr, kb) = header.matrix.weights();
+        let mut rgb = match self.y.len().checked_mul(3) {
+            Some(len) => Vec::with_capacity(len),
+            None => return Err(ImageError::DimensionError)
+        };
+
+        if header.subsampling == Subsampling::Mono {
+            for &y in &self.y {
+                let v = quantize(dequantize_luma(y, header.range));
+                rgb.extend_from_slice(&[v, v, v]);
+            }
+            return Ok(ImageBuffer::from_raw(header.width, header.height, rgb).unwrap())
+        }
+
+        let (fx, fy) = header.subsampling.factors();
+        let (cw, ch) = header.chroma_dimensions();
+        let (cw, ch) = (cw as usize, ch as usize);
+        let (sx, sy) = header.subsampling.siting();
+        let columns: Vec<_> = (0..width).map(|x| lerp_taps(x, fx, sx, cw)).collect();
+
+        for y in (0..height) {
+            let (r0, r1, t) = lerp_taps(y, fy, sy, ch);
+
+            for x in (0..width) {
+                let (c0, c1, s) = columns[x];
+                let chroma = |plane: &[u8]| {
+                    let top = plane[r0 * cw + c0] as f32 * (1.0 - s) + plane[r0 * cw + c1] as f32 * s;
+                    let bottom = plane[r1 * cw + c0] as f32 * (1.0 - s) + plane[r1 * cw + c1] as f32 * s;
+                    dequantize_chroma(top * (1.0 - t) + bottom * t, header.range)
+                };
+
+                let luma = dequantize_luma(self.y[y * width + x], header.range);
+                let r = luma + 2.0 * (1.0 - kr) * chroma(&self.cr);
+                let b = luma + 2.0 * (1.0 - kb) * chroma(&self.cb);
+                let g = (luma - kr * r - kb * b) / (1.0 - kr - kb);
+
+                rgb.extend_from_slice(&[quantize(r), quantize(g), quantize(b)]);
+            }
+        }
+
+        Ok(ImageBuffer::from_raw(header.width, header.height, rgb).unwrap())
+    }
+
+    // Whether the lengths of the planes match the dimensions of `header`
+    fn fit(&self, header: &Header) -> bool {
+        match header.plane_sizes() {
+            Some((luma, chroma)) => self.y.len() == luma && self.cb.len() == chroma && self.cr.len() == chroma,
+            None => false
+        }
+    }
+}
+
+// The luma samples and their weights that make up the chroma sample
+// `index` of a plane subsampled by `factor`, with a tent filter around its
+// site. Samples past the edges are replaced by the edge sample.
+fn taps(index: usize, factor: usize, siting: f32, len: usize) -> Vec<(usize, f32)> {
+    let center = (index * factor) as f32 + siting;
+    let first = (center - factor as f32).floor() as isize + 1;
+    let last = (center + factor as f32).ceil() as isize - 1;
+    let mut taps: Vec<(usize, f32)> = Vec::new();
+
+    for i in (first..last + 1) {
+        let weight = 1.0 - (i as f32 - center).abs() / factor as f32;
+        if weight > 0.0 {
+            taps.push((i.max(0).min(len as isize - 1) as usize, weight));
+        }
+    }
+
+    let sum: f32 = taps.iter().map(|&(_, w)| w).sum();
+    for tap in &mut taps {
+        tap.1 /= sum;
+    }
+
+    taps
+}
+
+// The chroma samples around the luma sample `index` and the weight of the
+// second one for linear interpolation
+fn lerp_taps(index: usize, factor: usize, siting: f32, len: usize) -> (usize, usize, f32) {
+    let u = ((index as f32 - siting) / factor as f32).max(0.0).min((len - 1) as f32);
+    let first = u.floor() as usize;
+
+    (first, (first + 1).min(len - 1), u - first as f32)
+}
+
+fn downsample(full: &[f32], width: usize, columns: &[Vec<(usize, f32)>], rows: &[Vec<(usize, f32)>],
+              range: Range) -> Vec<u8> {
+    let mut plane = Vec::with_capacity(columns.len() * rows.len());
+
+    for row in rows {
+        for column in columns {
+            let mut sum = 0.0;
+            for &(y, wy) in row {
+                for &(x, wx) in column {
+                    sum += wx * wy * full[y * width + x];
+                }
+            }
+            plane.push(quantize_chroma(sum, range));
+        }
+    }
+
+    plane
+}
+
+fn quantize(v: f32) -> u8 {
+    (v * 255.0).round().max(0.0).min(255.0) as u8
+}
+
+fn quantize_luma(y: f32, range: Range) -> u8 {
+    match range {
+        Range::Limited => (16.0 + 219.0 * y).round().max(0.0).min(255.0) as u8,
+        Range::Full => quantize(y),
+    }
+}
+
+fn quantize_chroma(c: f32, range: Range) -> u8 {
+    let scale = if range == Range::Limited { 224.0 } else { 255.0 };
+    (128.0 + scale * c).round().max(0.0).min(255.0) as u8
+}
+
+fn dequantize_luma(y: u8, range: Range) -> f32 {
+    match range {
+        Range::Limited => (y as f32 - 16.0) / 219.0,
+        Range::Full => y as f32 / 255.0,
+    }
+}
+
+fn dequantize_chroma(c: f32, range: Range) -> f32 {
+    let scale = if range == Range::Limited { 224.0 } else { 255.0 };
+    (c - 128.0) / scale
+}
+
+/// Writes a YUV4MPEG2 stream
+pub struct Writer<W: Write> {
+    w: W,
+    header: Header,
+}
+
+impl<W: Write> Writer<W> {
+    /// Creates a writer of frames with the properties of ```header``` to
+    /// ```w``` and writes the header
+    pub fn new(mut w: W, header: Header) -> ImageResult<Writer<W>> {
+        if header.width == 0 || header.height == 0 {
+            return Err(ImageError::DimensionError)
+        }
+        if header.frame_rate.0 == 0 || header.frame_rate.1 == 0 {
+            return Err(ImageError::FormatError(
+                format!("Invalid frame rate {}:{}", header.frame_rate.0, header.frame_rate.1)))
+        }
+
+        try!(header.write(&mut w));
+
+        Ok(Writer {
+            w: w,
+            header: header,
+        })
+    }
+
+    /// The header of the stream
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Writes ```planes``` as the next frame
+    pub fn write_planes(&mut self, planes: &Planes) -> ImageResult<()> {
+        if !planes.fit(&self.header) {
+            return Err(ImageError::DimensionError)
+        }
+
+        try!(self.w.write_all(b"FRAME\n"));
+        try!(self.w.write_all(&planes.y));
+        try!(self.w.write_all(&planes.cb));
+        try!(self.w.write_all(&planes.cr));
+        Ok(())
+    }
+
+    /// Converts ```image``` with the matrix, range and subsampling of the
+    /// header and writes it as the next frame. Alpha is dropped.
+    pub fn write_image(&mut self, image: &DynamicImage) -> ImageResult<()> {
+        if image.dimensions() != (self.header.width, self.header.height) {
+            return Err(ImageError::DimensionError)
+        }
+
+        let planes = Planes::from_rgb(&self.header, &image.to_rgb());
+        self.write_planes(&planes)
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+/// Reads a YUV4MPEG2 stream
+///
+/// The planes of a frame are allocated before they are read, thus a frame
+/// larger than the maximum set with ```set_max_frame_size``` is rejected
+/// with a `LimitsExceeded` error, which keeps the dimensions of a damaged
+/// or hostile header from exhausting the memory.
+pub struct Reader<R: Read> {
+    r: R,
+    header: Header,
+    max_frame_size: usize,
+}
+
+impl<R: Read> Reader<R> {
+    /// Creates a reader of the stream ```r``` and reads its header
+    pub fn new(mut r: R) -> ImageResult<Reader<R>> {
+        let line = match try!(read_line(&mut r)) {
+            Some(line) => line,
+            None => return Err(ImageError::NotEnoughData)
+        };
+        let header = try!(Header::parse(&line));
+
+        Ok(Reader {
+            r: r,
+            header: header,
+            max_frame_size: MAX_FRAME_SIZE,
+        })
+    }
+
+    /// The header of the stream
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Sets the matrix used by ```read_image``` and ```frames```, which the
+    /// stream does not record
+    pub fn set_matrix(&mut self, matrix: Matrix) {
+        self.header.matrix = matrix;
+    }
+
+    /// Sets the maximum size of a frame in bytes. Defaults to 256 MiB.
+    pub fn set_max_frame_size(&mut self, max: usize) {
+        self.max_frame_size = max;
+    }
+
+    /// Reads the planes of the next frame, or returns ```None``` at the end
+    /// of the stream
+    pub fn read_planes(&mut self) -> ImageResult<Option<Planes>> {
+        let line = match try!(read_line(&mut self.r)) {
+            Some(line) => line,
+            None => return Ok(None)
+        };
+        // Frame parameters are allowed but carry nothing we use
+        if line != "FRAME" && !line.starts_with("FRAME ") {
+            return Err(ImageError::FormatError("Expected a YUV4MPEG2 frame header".to_string()))
+        }
+
+        let (luma, chroma) = match self.header.plane_sizes() {
+            Some(sizes) => sizes,
+            None => return Err(ImageError::DimensionError)
+        };
+        if (luma as u64).saturating_add((chroma as u64).saturating_mul(2)) > self.max_frame_size as u64 {
+            return Err(ImageError::LimitsExceeded(format!(
+                "The frame is larger than the maximum of {} bytes", self.max_frame_size
+            )))
+        }
+
+        let mut planes = Planes {
+            y: vec![0; luma],
+            cb: vec![0; chroma],
+            cr: vec![0; chroma],
+        };
+
+        for plane in [&mut planes.y, &mut planes.cb, &mut planes.cr].iter_mut() {
+            try!(self.r.read_exact(plane).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => ImageError::NotEnoughData,
+                _ => ImageError::IoError(e)
+            }));
+        }
+
+        Ok(Some(planes))
+    }
+
+    /// Reads the next frame and converts it to RGB, or returns ```None``` at
+    /// the end of the stream
+    pub fn read_image(&mut self) -> ImageResult<Option<RgbImage>> {
+        match try!(self.read_planes()) {
+            Some(planes) => planes.to_rgb(&self.header).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    /// Reads all remaining frames as RGBA, with the delay of the frame rate
+    pub fn frames(mut self) -> ImageResult<Frames> {
+        let delay = self.header.delay();
+        let mut frames = Vec::new();
+
+        while let Some(image) = try!(self.read_image()) {
+            let rgba = DynamicImage::ImageRgb8(image.into()).to_rgba();
+            frames.push(Frame::from_parts(rgba, 0, 0, delay));
+        }
+
+        Ok(Frames::new(frames))
+    }
+}
+
+// Reads a line without its newline, or returns `None` at the end of the
+// stream. The stream is read byte by byte as the frame data follows.
+fn read_line<R: Read>(r: &mut R) -> ImageResult<Option<String>> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+
+    loop {
+        if try!(r.read(&mut byte)) == 0 {
+            if line.is_empty() {
+                return Ok(None)
+            }
+            return Err(ImageError::NotEnoughData)
+        }
+        if byte[0] == b'\n' {
+            break
+        }
+        if line.len() == MAX_LINE {
+            return Err(ImageError::FormatError("YUV4MPEG2 header line is too long".to_string()))
+        }
+        line.push(byte[0]);
+    }
+
+    match String::from_utf8(line) {
+        Ok(line) => Ok(Some(line)),
+        Err(_) => Err(ImageError::FormatError("YUV4MPEG2 header is not valid text".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use buffer::{ImageBuffer, RgbImage};
+    use color::Rgb;
+    use dynimage::DynamicImage;
+    use image::ImageError;
+    use super::{Header, Interlace, Matrix, Planes, Range, Reader, Subsampling, Writer};
+
+    #[test]
+    fn test_header() {
+        let line = "YUV4MPEG2 W720 H576 F25:1 It A16:15 C420mpeg2 XYSCSS=420MPEG2 XCOLORRANGE=FULL";
+        let header = Header::parse(line).unwrap();
+        assert_eq!((header.width, header.height), (720, 576));
+        assert_eq!(header.frame_rate, (25, 1));
+        assert_eq!(header.pixel_aspect, (16, 15));
+        assert_eq!(header.interlace, Interlace::TopFirst);
+        assert_eq!(header.subsampling, Subsampling::Yuv420Mpeg2);
+        assert_eq!(header.range, Range::Full);
+        assert_eq!(header.chroma_dimensions(), (360, 288));
+
+        let mut written = Vec::new();
+        header.write(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(Header::parse(&written[..written.len() - 1]).unwrap(), header);
+
+        // Defaults of the optional parameters
+        let header = Header::parse("YUV4MPEG2 W5 H3 F30000:1001").unwrap();
+        assert_eq!(header, Header { frame_rate: (30000, 1001), ..Header::new(5, 3) });
+        assert_eq!(header.chroma_dimensions(), (3, 2));
+        assert_eq!(header.delay(), ::num::rational::Ratio::new(1001, 30000));
+
+        for invalid in ["YUV4MPEG W5 H3", "YUV4MPEG2 W5", "YUV4MPEG2 W0 H3", "YUV4MPEG2 W5 H3 F25",
+                        "YUV4MPEG2 W5 H3 C420p10", "YUV4MPEG2 W5 H3 Q1", "YUV4MPEG2 W5 H3 F0:1",
+                        "YUV4MPEG2 W5 H3 \u{e9}"].iter() {
+            assert!(Header::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let image: RgbImage = ImageBuffer::from_fn(9, 7, |x, y| Rgb([x as u8 * 25, y as u8 * 30, 200]));
+        let subsamplings = [Subsampling::Yuv420Jpeg, Subsampling::Yuv420Mpeg2, Subsampling::Yuv420Paldv,
+                            Subsampling::Yuv422, Subsampling::Yuv444];
+
+        for &subsampling in subsamplings.iter() {
+            for &(range, matrix) in [(Range::Limited, Matrix::Bt601), (Range::Full, Matrix::Bt709)].iter() {
+                let header = Header { subsampling: subsampling, range: range, matrix: matrix, ..Header::new(9, 7) };
+                let mut writer = Writer::new(Vec::new(), header).unwrap();
+                writer.write_image(&DynamicImage::ImageRgb8(image.clone().into())).unwrap();
+                writer.write_image(&DynamicImage::ImageRgb8(image.clone().into())).unwrap();
+                let stream = writer.into_inner();
+
+                let mut reader = Reader::new(Cursor::new(stream)).unwrap();
+                assert_eq!(reader.header().subsampling, subsampling);
+                assert_eq!(reader.header().range, range);
+                reader.set_matrix(matrix);
+
+                // The linear gradients survive the subsampling, except at the
+                // edges where the chroma filters see replicated samples
+                let decoded = reader.read_image().unwrap().unwrap();
+                for (x, y, p) in decoded.enumerate_pixels() {
+                    let edge = x < 2 || x > 6 || y < 2 || y > 4;
+                    let tolerance = if subsampling == Subsampling::Yuv444 || !edge { 2 } else { 24 };
+                    for (a, b) in image.get_pixel(x, y).data.iter().zip(p.data.iter()) {
+                        assert!((*a as i32 - *b as i32).abs() <= tolerance, "{:?} {:?} {} {}", subsampling, range, x, y);
+                    }
+                }
+
+                assert_eq!(reader.frames().unwrap().count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_planes() {
+        // A uniform color converts to the same samples at every site
+        let header = Header::new(4, 2);
+        let image: RgbImage = ImageBuffer::from_pixel(4, 2, Rgb([255, 0, 0]));
+        let planes = Planes::from_rgb(&header, &image);
+        assert_eq!(planes.y, vec![81; 8]);
+        assert_eq!(planes.cb, vec![90; 2]);
+        assert_eq!(planes.cr, vec![240; 2]);
+        assert_eq!(planes.to_rgb(&header).unwrap().get_pixel(3, 1), &Rgb([254, 0, 0]));
+
+        let header = Header { subsampling: Subsampling::Mono, range: Range::Full, ..header };
+        let planes = Planes::from_rgb(&header, &image);
+        assert_eq!(planes.y, vec![76; 8]);
+        assert!(planes.cb.is_empty() && planes.cr.is_empty());
+        assert_eq!(planes.to_rgb(&header).unwrap().get_pixel(0, 0), &Rgb([76, 76, 76]));
+
+        let mut writer = Writer::new(Vec::new(), header).unwrap();
+        writer.write_planes(&planes).unwrap();
+        assert!(writer.write_planes(&Planes { y: vec![0; 7], ..planes.clone() }).is_err());
+        match (Planes { cb: vec![0; 1], ..planes.clone() }).to_rgb(&header) {
+            Err(ImageError::DimensionError) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        let color = Header { subsampling: Subsampling::Yuv422, ..header };
+        assert!(Planes { cb: vec![0; 3], cr: vec![0; 4], ..planes.clone() }.to_rgb(&color).is_err());
+        assert!(Planes { cb: vec![0; 4], cr: vec![0; 4], ..planes.clone() }.to_rgb(&color).is_ok());
+        let stream = writer.into_inner();
+        assert_eq!(&stream[stream.len() - 14..stream.len() - 8], b"FRAME\n");
+
+        // A truncated frame
+        let mut reader = Reader::new(Cursor::new(&stream[..stream.len() - 1])).unwrap();
+        match reader.read_planes() {
+            Err(ImageError::NotEnoughData) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        assert_eq!(Reader::new(Cursor::new(stream)).unwrap().read_planes().unwrap(), Some(planes));
+    }
+
+    #[test]
+    fn test_frame_size() {
+        // The dimensions of the header are not allocated before a frame
+        let stream = b"YUV4MPEG2 W4294967295 H4294967295 F25:1 C444\nFRAME\n".to_vec();
+        let mut reader = Reader::new(Cursor::new(stream)).unwrap();
+        assert_eq!(reader.header().chroma_dimensions(), (4294967295, 4294967295));
+        assert!(reader.read_planes().is_err());
+
+        let mut writer = Writer::new(Vec::new(), Header::new(64, 48)).unwrap();
+        writer.write_image(&DynamicImage::ImageRgb8(RgbImage::new(64, 48).into())).unwrap();
+        let stream = writer.into_inner();
+
+        let mut reader = Reader::new(Cursor::new(&stream[..])).unwrap();
+        reader.set_max_frame_size(64 * 48 + 2 * 32 * 24 - 1);
+        match reader.read_planes() {
+            Err(ImageError::LimitsExceeded(_)) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        let mut reader = Reader::new(Cursor::new(&stream[..])).unwrap();
+        reader.set_max_frame_size(64 * 48 + 2 * 32 * 24);
+        assert!(reader.read_image().unwrap().is_some());
+    }
+}
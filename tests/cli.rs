@@ -0,0 +1,94 @@
+//! Runs the `img` command line tool on generated images.
+#![cfg(feature = "cli")]
+
+extern crate image;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn img(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_img")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn convert_resize_info_diff() {
+    let dir: PathBuf = env::temp_dir().join(format!("image-cli-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let (a, b) = (path("a.jpg"), path("b.jpg"));
+
+    let pixels = (0..16 * 8 * 3).map(|i| (i * 5 % 256) as u8).collect::<Vec<u8>>();
+    image::save_buffer(&a, &pixels, 16, 8, image::RGB(8)).unwrap();
+
+    let output = img(&["resize", &a, &b, "8x4", "--exact", "--filter", "triangle"]);
+    assert!(output.status.success());
+
+    let output = img(&["info", &a, &b]);
+    assert_eq!(stdout(&output), format!("{}: 16x8 RGB(8)\n{}: 8x4 RGB(8)\n", a, b));
+
+    let output = img(&["diff", &a, &a]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "identical\n");
+
+    let output = img(&["diff", &a, &b]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = img(&["frobnicate"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn strip_metadata() {
+    use std::io::Write;
+    use image::jpeg::{Exif, JPEGDecoder, JPEGEncoder};
+
+    let dir: PathBuf = env::temp_dir().join(format!("image-cli-strip-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let (a, b, c) = (path("a.jpg"), path("b.jpg"), path("c.txt"));
+
+    let pixels = (0..16 * 8 * 3).map(|i| (i * 5 % 256) as u8).collect::<Vec<u8>>();
+    let mut encoded = Vec::new();
+    {
+        let mut encoder = JPEGEncoder::new(&mut encoded);
+        encoder.set_exif(&Exif { orientation: Some(6), ..Exif::default() });
+        encoder.add_comment("secret");
+        encoder.encode(&pixels, 16, 8, image::RGB(8)).unwrap();
+    }
+    let mut file = fs::File::create(&a).unwrap();
+    file.write_all(&encoded).unwrap();
+    file.write_all(b"trailing data").unwrap();
+    drop(file);
+
+    assert!(img(&["strip-metadata", &a, &b]).status.success());
+
+    let stripped = fs::read(&b).unwrap();
+    assert!(stripped.len() < encoded.len());
+    let mut decoder = JPEGDecoder::new(&stripped[..]);
+    assert!(decoder.comments().unwrap().is_empty());
+    assert_eq!(decoder.exif_orientation().unwrap(), None);
+
+    let output = img(&["diff", &a, &b]);
+    assert_eq!(stdout(&output), "identical\n");
+
+    fs::write(&c, b"not an image").unwrap();
+    assert_eq!(img(&["strip-metadata", &c, &b]).status.code(), Some(2));
+
+    // A segment too short to hold its own length
+    fs::write(&c, b"\xff\xd8\xff\xfe\x00\x00\xff\xd9").unwrap();
+    let output = img(&["strip-metadata", &c, &b]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("segment length"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,211 @@
+//! Reads the manifests of the suites and checks their images
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use image::{self, ImageError, RgbaImage};
+use image::config::{self, SimdLevel};
+
+const LEVELS: [SimdLevel; 5] = [
+    SimdLevel::Scalar,
+    SimdLevel::Sse2,
+    SimdLevel::Avx2,
+    SimdLevel::Neon,
+    SimdLevel::Simd128,
+];
+
+enum Reference {
+    Crc(u32),
+    Pixels(PathBuf),
+    Error,
+}
+
+// The tolerance against a PAM reference
+struct Rule {
+    max: u8,
+    mean: Option<f64>,
+}
+
+struct Entry {
+    image: PathBuf,
+    reference: Reference,
+    rule: Rule,
+}
+
+/// Checks the images of the suite `name` at every supported SIMD level and
+/// returns a description of every failure
+pub fn run_suite(name: &str) -> Vec<String> {
+    let dir: PathBuf = [".", "tests", "conformance", name].iter().collect();
+
+    let entries = match read_manifest(&dir) {
+        Ok(entries) => entries,
+        Err(msg) => return vec![format!("{}: {}", name, msg)]
+    };
+
+    // A missing image fails instead of passing untested
+    let mut failures = entries.iter().filter(|e| !e.image.exists()).map(|e| {
+        format!("{}: the image is missing", e.image.display())
+    }).collect::<Vec<String>>();
+
+    for &level in LEVELS.iter().filter(|l| l.is_supported()) {
+        config::set_simd(level);
+
+        for entry in entries.iter().filter(|e| e.image.exists()) {
+            if let Err(msg) = check(entry) {
+                failures.push(format!("{} ({:?}): {}", entry.image.display(), level, msg));
+            }
+        }
+    }
+
+    config::reset_simd();
+    failures
+}
+
+fn read_manifest(dir: &Path) -> Result<Vec<Entry>, String> {
+    let file = try!(File::open(dir.join("manifest.txt")).map_err(|e| e.to_string()));
+    let mut entries = Vec::new();
+
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = try!(line.map_err(|e| e.to_string()));
+        let fields = line.split('#').next().unwrap().split_whitespace().collect::<Vec<&str>>();
+
+        let (image, reference, rule) = match fields.len() {
+            0 => continue,
+            2 => (fields[0], fields[1], "exact"),
+            3 => (fields[0], fields[1], fields[2]),
+            _ => return Err(format!("line {}: expected an image, a reference and a rule", i + 1))
+        };
+
+        let reference = if reference == "error" {
+            Reference::Error
+        } else if reference.starts_with("crc:") {
+            match u32::from_str_radix(&reference[4..], 16) {
+                Ok(crc) => Reference::Crc(crc),
+                Err(_) => return Err(format!("line {}: invalid CRC {}", i + 1, reference))
+            }
+        } else {
+            Reference::Pixels(dir.join(reference))
+        };
+
+        entries.push(Entry {
+            image: dir.join(image),
+            reference: reference,
+            rule: try!(parse_rule(rule).map_err(|msg| format!("line {}: {}", i + 1, msg))),
+        });
+    }
+
+    Ok(entries)
+}
+
+fn parse_rule(rule: &str) -> Result<Rule, String> {
+    let mut result = Rule { max: 0, mean: None };
+
+    for part in rule.split(',') {
+        let mut kv = part.splitn(2, ':');
+
+        match (kv.next().unwrap(), kv.next()) {
+            ("exact", None) => (),
+            ("max", Some(v)) => result.max = try!(v.parse().map_err(|_| format!("invalid rule {}", part))),
+            ("mean", Some(v)) => result.mean = Some(try!(v.parse().map_err(|_| format!("invalid rule {}", part)))),
+            _ => return Err(format!("unknown rule {}", part))
+        }
+    }
+
+    Ok(result)
+}
+
+fn check(entry: &Entry) -> Result<(), String> {
+    let decoded = match image::open(&entry.image) {
+        Ok(image) => Ok(image.to_rgba()),
+        // The format is not enabled
+        Err(ImageError::UnsupportedError(_)) => return Ok(()),
+        Err(err) => Err(err)
+    };
+
+    match (&entry.reference, decoded) {
+        (&Reference::Error, Err(_)) => Ok(()),
+        (&Reference::Error, Ok(_)) => Err("the invalid image was decoded".to_string()),
+        (_, Err(err)) => Err(format!("decoding failed: {}", err)),
+        (&Reference::Crc(crc), Ok(image)) => {
+            let actual = crc32(&image);
+            if actual == crc {
+                Ok(())
+            } else {
+                Err(format!("CRC {:08x} instead of {:08x}", actual, crc))
+            }
+        }
+        (&Reference::Pixels(ref path), Ok(image)) => {
+            let reference = try!(read_pam(path));
+            compare(&image, &reference, &entry.rule)
+        }
+    }
+}
+
+fn compare(image: &RgbaImage, reference: &RgbaImage, rule: &Rule) -> Result<(), String> {
+    if image.dimensions() != reference.dimensions() {
+        return Err(format!("{:?} pixels instead of {:?}", image.dimensions(), reference.dimensions()))
+    }
+
+    let mut max = 0u8;
+    let mut sum = 0u64;
+
+    for (&a, &b) in image.iter().zip(reference.iter()) {
+        let d = if a > b { a - b } else { b - a };
+        max = ::std::cmp::max(max, d);
+        sum += d as u64;
+    }
+
+    let mean = sum as f64 / image.len() as f64;
+
+    if max > rule.max {
+        Err(format!("a sample differs by {}, {} are allowed", max, rule.max))
+    } else if rule.mean.map_or(false, |m| mean > m) {
+        Err(format!("the samples differ by {:.3} on average, {} are allowed", mean, rule.mean.unwrap()))
+    } else {
+        Ok(())
+    }
+}
+
+// Reads a PAM file with 8 bit RGB_ALPHA tuples
+fn read_pam(path: &Path) -> Result<RgbaImage, String> {
+    let mut data = Vec::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_end(&mut data))
+                         .map_err(|e| format!("{}: {}", path.display(), e)));
+
+    let end = match data.windows(7).position(|w| w == b"ENDHDR\n") {
+        Some(end) => end,
+        None => return Err(format!("{}: missing ENDHDR", path.display()))
+    };
+
+    let header = String::from_utf8_lossy(&data[..end]).into_owned();
+    let (mut width, mut height) = (0, 0);
+
+    for line in header.lines().skip(1) {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+
+        match (fields.get(0).cloned(), fields.get(1).cloned()) {
+            (Some("WIDTH"), Some(v)) => width = v.parse().unwrap_or(0),
+            (Some("HEIGHT"), Some(v)) => height = v.parse().unwrap_or(0),
+            (Some("DEPTH"), Some("4")) | (Some("MAXVAL"), Some("255")) | (Some("TUPLTYPE"), Some("RGB_ALPHA")) => (),
+            (Some(field), _) => return Err(format!("{}: unsupported header field {}", path.display(), field)),
+            (None, _) => ()
+        }
+    }
+
+    RgbaImage::from_raw(width, height, data[end + 7..].to_vec())
+        .ok_or_else(|| format!("{}: truncated pixels", path.display()))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in (0..8) {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    crc ^ 0xFFFFFFFF
+}
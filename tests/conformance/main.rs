@@ -0,0 +1,43 @@
+//! Checks the decoders against conformance suites.
+//!
+//! Every suite is a directory next to this file with a `manifest.txt`,
+//! which lists an image per line, followed by its reference and optionally
+//! the rule the decoded pixels have to satisfy:
+//!
+//! ```text
+//! # Comment
+//! basn0g01.png   crc:a1b2c3d4
+//! testorig.jpg   testorig.pam   max:1,mean:0.1
+//! xs1n0g01.png   error
+//! ```
+//!
+//! A reference is either the CRC-32 of the decoded RGBA8 pixels, a PAM
+//! file with the expected RGB_ALPHA pixels, or `error` for images that have
+//! to be rejected. The rules against PAM references are `exact`, the
+//! default, `max:n` for the largest difference of a sample and `mean:x` for
+//! the mean difference of the samples, which may be combined with commas.
+//!
+//! The images are decoded once for every SIMD level the CPU supports, so
+//! that every backend is held to the same references. Every listed image
+//! has to exist, thus images of published suites that are not part of the
+//! repository are only listed once they are added. Formats that are not
+//! enabled are skipped.
+
+extern crate image;
+
+mod harness;
+
+use harness::run_suite;
+
+#[test]
+fn conformance() {
+    let mut failures = Vec::new();
+
+    for suite in ["pngsuite", "jpeg", "gif", "local"].iter() {
+        failures.extend(run_suite(suite));
+    }
+
+    if !failures.is_empty() {
+        panic!("{} conformance failures:\n{}", failures.len(), failures.join("\n"));
+    }
+}